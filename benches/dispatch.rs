@@ -0,0 +1,98 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use vt6::common::core::ClientID;
+use vt6::server::testing::MockApplication;
+use vt6::server::tokio::Dispatch;
+use vt6::server::{Application, ClientIdentity};
+
+const WANT: &[u8] = b"{2|4:want,5:core1,}";
+//the reply is always `{2|4:have,7:core1.0,}`
+const HAVE_LEN: usize = 21;
+const MESSAGES_PER_ROUND: usize = 100;
+
+async fn connect(path: &std::path::Path, app: &MockApplication, idx: usize) -> UnixStream {
+    let id = format!("client{}", idx);
+    let creds = app.register_client(ClientIdentity::new(&ClientID::parse(&id).unwrap()));
+    let mut stream = UnixStream::connect(path).await.unwrap();
+    let hello = format!(
+        "{{2|19:posix1.client-hello,{}:{},}}",
+        creds.secret().len(),
+        creds.secret()
+    );
+    stream.write_all(hello.as_bytes()).await.unwrap();
+    let server_hello = format!("{{5|19:posix1.server-hello,{}:{},0:,0:,0:,}}", id.len(), id);
+    let mut buf = vec![0u8; server_hello.len()];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, server_hello.as_bytes());
+    stream
+}
+
+//Sends a batch of requests on the given connection, and waits for all replies.
+async fn round(mut stream: UnixStream) -> UnixStream {
+    stream
+        .write_all(&WANT.repeat(MESSAGES_PER_ROUND))
+        .await
+        .unwrap();
+    let mut buf = vec![0u8; HAVE_LEN * MESSAGES_PER_ROUND];
+    stream.read_exact(&mut buf).await.unwrap();
+    stream
+}
+
+//Many clients talking to the Dispatch at the same time. Since every connection is worked on
+//independently, the throughput should scale with the number of worker threads instead of being
+//limited by a single lock on the connection pool.
+fn bench_concurrent_clients(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let path = std::env::temp_dir().join(format!("vt6-bench-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let app = MockApplication::new();
+    let dispatch = runtime.block_on(async { Dispatch::new(&path, app.clone()).unwrap() });
+    let d = dispatch.clone();
+    let listener = runtime.spawn(async move { d.run_listener().await });
+
+    let mut group = c.benchmark_group("concurrent_clients");
+    let mut next_idx = 0;
+    for &count in &[1usize, 4, 16, 64] {
+        let mut streams = runtime.block_on(async {
+            let mut streams = Vec::with_capacity(count);
+            for _ in 0..count {
+                streams.push(connect(&path, &app, next_idx).await);
+                next_idx += 1;
+            }
+            streams
+        });
+        group.throughput(Throughput::Elements((count * MESSAGES_PER_ROUND) as u64));
+        group.bench_function(BenchmarkId::from_parameter(count), |b| {
+            b.iter(|| {
+                streams = runtime.block_on(async {
+                    let jobs: Vec<_> = std::mem::take(&mut streams)
+                        .into_iter()
+                        .map(|stream| tokio::spawn(round(stream)))
+                        .collect();
+                    let mut result = Vec::with_capacity(jobs.len());
+                    for job in jobs {
+                        result.push(job.await.unwrap());
+                    }
+                    result
+                });
+            })
+        });
+    }
+    group.finish();
+
+    dispatch.shutdown();
+    runtime.block_on(listener).unwrap().unwrap();
+}
+
+criterion_group!(benches, bench_concurrent_clients);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::collections::HashSet;
+use vt6::common::core::ScopedIdentifier;
+use vt6::server::Subscriptions;
+
+const PROPERTY: &str = "term1.title";
+const OTHER_PROPERTY: &str = "term1.icon-title";
+
+//Every 100th connection subscribes to PROPERTY, all others to OTHER_PROPERTY.
+fn property_for(id: u64) -> &'static str {
+    if id.is_multiple_of(100) {
+        PROPERTY
+    } else {
+        OTHER_PROPERTY
+    }
+}
+
+//Compares finding the subscribers of a property through the Subscriptions index with the naive
+//approach of visiting every connection and checking its own set of subscriptions (which is what a
+//publish through a plain Dispatch::enqueue_broadcast() amounts to).
+fn bench_find_subscribers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("find_subscribers");
+    for &count in &[100u64, 1000, 10000] {
+        let connections: Vec<(u64, HashSet<String>)> = (0..count)
+            .map(|id| (id, std::iter::once(property_for(id).to_owned()).collect()))
+            .collect();
+        group.bench_with_input(
+            BenchmarkId::new("naive", count),
+            &connections,
+            |b, conns| {
+                b.iter(|| {
+                    conns
+                        .iter()
+                        .filter(|(_, subs)| subs.contains(black_box(PROPERTY)))
+                        .map(|(id, _)| *id)
+                        .collect::<Vec<u64>>()
+                })
+            },
+        );
+
+        let subscriptions = Subscriptions::new();
+        for id in 0..count {
+            let name = ScopedIdentifier::parse(property_for(id)).unwrap();
+            subscriptions.subscribe(&name, id);
+        }
+        group.bench_with_input(
+            BenchmarkId::new("index", count),
+            &subscriptions,
+            |b, subs| b.iter(|| subs.subscribers(black_box(PROPERTY))),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_find_subscribers);
+criterion_main!(benches);
@@ -4,11 +4,17 @@
 * Refer to the file "LICENSE" for details.
 *******************************************************************************/
 
-use std::sync::{Arc, Mutex};
-use vt6::common::core::{msg, ClientID};
+mod screen;
+
+use screen::{ScreenEvent, ScreenModel};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use vt6::common::core::{msg, ClientID, ScreenID};
+use vt6::server::term::TitleKind;
 use vt6::server::{
-    Application, ClientCredentials, ClientIdentity, ClientSelector, Connection, Dispatch, Handler,
-    HandshakeHandler, MessageHandler, Notification, ScreenCredentials, ScreenIdentity,
+    Application, ClientCredentials, ClientIdentity, ClientSelector, Connection, ConnectionState,
+    Dispatch, Handler, HandshakeHandler, LineDisciplineOptions, MessageHandler, Notification,
+    PeerCredentials, ScreenCredentials, ScreenIdentity,
 };
 
 #[tokio::main]
@@ -18,7 +24,7 @@ async fn main() -> std::io::Result<()> {
     //log the handshake messages that users can use to connect
     let client_identity = ClientIdentity::new(&ClientID::parse("a").unwrap());
     let client_credentials = ClientCredentials::generate();
-    let screen_identity = ScreenIdentity::new("screen1");
+    let screen_identity = ScreenIdentity::new(&ScreenID::parse("screen1").unwrap());
     let screen_credentials = ScreenCredentials::generate();
     let msg1 = vt6::msg::posix::StdinHello {
         secret: screen_credentials.stdin_secret(),
@@ -33,6 +39,9 @@ async fn main() -> std::io::Result<()> {
     };
     log::info!("{}", encode_to_string(msg3));
 
+    //the stdout of the screen is rendered into this model, which the GUI thread displays
+    let (model, events) = ScreenModel::register(screen_identity.clone(), 80, 24);
+
     //create an Application instance
     let app = MyApplicationImpl {
         clients: vec![(client_identity, client_credentials, false)],
@@ -40,6 +49,9 @@ async fn main() -> std::io::Result<()> {
         screen_credentials,
         stdin_authorized: false,
         stdout_authorized: false,
+        line_discipline_options: LineDisciplineOptions::default(),
+        title: String::new(),
+        icon_title: String::new(),
     };
     let app = MyApplication(Arc::new(Mutex::new(app)));
 
@@ -48,6 +60,12 @@ async fn main() -> std::io::Result<()> {
     log::info!("server socket is at {}", socket_path.to_str().unwrap());
     let dispatch = vt6::server::tokio::Dispatch::new(socket_path, app.clone())?;
 
+    //run the GUI in its own thread, like a real terminal would
+    {
+        let dispatch = dispatch.clone();
+        std::thread::spawn(move || run_gui(model, events, dispatch));
+    }
+
     //shutdown server on Ctrl-C
     {
         let dispatch = dispatch.clone();
@@ -93,6 +111,53 @@ fn encode_to_string<M: vt6::common::core::msg::EncodeMessage>(msg: M) -> String
     String::from_utf8_lossy(&buf[0..len]).into()
 }
 
+////////////////////////////////////////////////////////////////////////////////
+// GUI thread
+
+///Stands in for the GUI of a real terminal. It repaints whenever the screen model reports damage
+///(by logging the changed rows), and pretends that the user resizes the window every 10 seconds.
+fn run_gui(
+    model: ScreenModel,
+    events: mpsc::Receiver<ScreenEvent>,
+    dispatch: vt6::server::tokio::Dispatch<MyApplication>,
+) {
+    let sizes = [(80, 24), (100, 30)];
+    let mut next_size = 1;
+    let resize_interval = Duration::from_secs(10);
+    let mut next_resize = Instant::now() + resize_interval;
+
+    loop {
+        let timeout = next_resize.saturating_duration_since(Instant::now());
+        match events.recv_timeout(timeout) {
+            Ok(ScreenEvent::Damaged) => {}
+            Ok(ScreenEvent::StdoutDetached) => log::info!(
+                "stdout of screen {} was detached",
+                model.identity().screen_id()
+            ),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let (width, height) = sizes[next_size];
+                next_size = 1 - next_size;
+                next_resize += resize_interval;
+                log::info!("resizing screen to {}x{}", width, height);
+                model.resize(width, height, &dispatch);
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+
+        //copy the damaged rows out of the grid first, so that stdout processing is not blocked
+        //while we paint
+        let rows: Vec<(u16, String)> = {
+            let mut grid = model.grid();
+            let damage = grid.take_damage();
+            damage.into_iter().map(|y| (y, grid.row_text(y))).collect()
+        };
+        //a real GUI would repaint blank rows too, but they are not worth logging
+        for (y, text) in rows.into_iter().filter(|(_, text)| !text.is_empty()) {
+            log::info!("row {:>2} | {}", y, text);
+        }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Application object
 
@@ -103,6 +168,9 @@ struct MyApplicationImpl {
     screen_credentials: ScreenCredentials,
     stdin_authorized: bool,
     stdout_authorized: bool,
+    line_discipline_options: LineDisciplineOptions,
+    title: String,
+    icon_title: String,
 }
 
 #[derive(Clone)]
@@ -116,9 +184,18 @@ impl std::fmt::Debug for MyApplication {
 
 impl vt6::server::Application for MyApplication {
     type MessageConnector = MyMessageConnector;
-    type StdoutConnector = MyStdoutConnector;
-    type MessageHandler =
-        LoggingHandler<vt6::server::core::MessageHandler<vt6::server::RejectHandler>>;
+    type StdoutConnector = vt6::server::Utf8StdoutConnector<screen::ScreenStdoutConnector>;
+    type MessageHandler = LoggingHandler<
+        vt6::server::core::MessageHandler<
+            vt6::server::sig::MessageHandler<
+                vt6::server::term::MessageHandler<
+                    vt6::server::term::TitleHandler<
+                        screen::SizeHandler<vt6::server::RejectHandler>,
+                    >,
+                >,
+            >,
+        >,
+    >;
     type HandshakeHandler =
         LoggingHandler<vt6::server::core::HandshakeHandler<vt6::server::RejectHandler>>;
 
@@ -153,12 +230,16 @@ impl vt6::server::Application for MyApplication {
             .any(|(ident, _, _)| s.contains(ident.client_id()))
     }
 
-    fn authorize_client(&self, secret: &str) -> Option<ClientIdentity> {
+    fn authorize_client(
+        &self,
+        secret: &str,
+        _peer: Option<&PeerCredentials>,
+    ) -> Option<ClientIdentity> {
         let mut app = self.0.lock().unwrap();
         let (id, _, ref mut is_authorized) = app
             .clients
             .iter_mut()
-            .find(|(_, creds, _)| creds.secret() == secret)?;
+            .find(|(_, creds, _)| creds.verify(secret))?;
         if *is_authorized {
             None
         } else {
@@ -177,7 +258,7 @@ impl vt6::server::Application for MyApplication {
 
     fn authorize_stdin(&self, secret: &str) -> Option<ScreenIdentity> {
         let mut app = self.0.lock().unwrap();
-        if !app.stdin_authorized && app.screen_credentials.stdin_secret() == secret {
+        if !app.stdin_authorized && app.screen_credentials.verify_stdin(secret) {
             app.stdin_authorized = true;
             Some(app.screen_identity.clone())
         } else {
@@ -187,13 +268,50 @@ impl vt6::server::Application for MyApplication {
 
     fn authorize_stdout(&self, secret: &str) -> Option<ScreenIdentity> {
         let mut app = self.0.lock().unwrap();
-        if !app.stdout_authorized && app.screen_credentials.stdout_secret() == secret {
+        if !app.stdout_authorized && app.screen_credentials.verify_stdout(secret) {
             app.stdout_authorized = true;
             Some(app.screen_identity.clone())
         } else {
             None
         }
     }
+
+    fn detach_stdin(&self, _screen: &ScreenIdentity) {
+        self.0.lock().unwrap().stdin_authorized = false;
+    }
+
+    fn detach_stdout(&self, _screen: &ScreenIdentity) {
+        self.0.lock().unwrap().stdout_authorized = false;
+    }
+
+    fn line_discipline_options(&self, _screen: &ScreenIdentity) -> Option<LineDisciplineOptions> {
+        Some(self.0.lock().unwrap().line_discipline_options)
+    }
+
+    fn set_line_discipline_options(
+        &self,
+        _screen: &ScreenIdentity,
+        options: LineDisciplineOptions,
+    ) {
+        self.0.lock().unwrap().line_discipline_options = options;
+    }
+
+    fn screen_title(&self, _screen: &ScreenIdentity, kind: TitleKind) -> Option<String> {
+        let app = self.0.lock().unwrap();
+        Some(match kind {
+            TitleKind::Window => app.title.clone(),
+            TitleKind::Icon => app.icon_title.clone(),
+        })
+    }
+
+    fn set_screen_title(&self, _screen: &ScreenIdentity, kind: TitleKind, title: &str) {
+        log::info!("{:?} title changed to {:?}", kind, title);
+        let mut app = self.0.lock().unwrap();
+        match kind {
+            TitleKind::Window => app.title = title.into(),
+            TitleKind::Icon => app.icon_title = title.into(),
+        }
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -214,25 +332,6 @@ impl vt6::server::MessageConnector for MyMessageConnector {
     }
 }
 
-#[derive(Clone, Debug)]
-struct MyStdoutConnector {
-    id: vt6::server::ScreenIdentity,
-}
-
-impl vt6::server::StdoutConnector for MyStdoutConnector {
-    fn new(id: vt6::server::ScreenIdentity) -> Self {
-        Self { id }
-    }
-
-    fn receive(&mut self, data: &[u8]) {
-        log::info!(
-            "stdout received for screen {}: {:?}",
-            self.id.screen_id(),
-            String::from_utf8_lossy(data)
-        );
-    }
-}
-
 ////////////////////////////////////////////////////////////////////////////////
 // custom handlers
 
@@ -251,7 +350,7 @@ impl<A: Application, H: Handler<A>> Handler<A> for LoggingHandler<H> {
     ) -> Result<(), vt6::server::HandlerError> {
         log::info!(
             "received message {} in connection state {}",
-            msg,
+            msg.display_redacted(),
             conn.state().type_name()
         );
         self.next.handle(msg, conn)
@@ -261,6 +360,22 @@ impl<A: Application, H: Handler<A>> Handler<A> for LoggingHandler<H> {
         log::error!("parse error: {} at offset {}", e.kind, e.offset);
         self.next.handle_error(e, conn)
     }
+
+    fn on_connect<D: Dispatch<A>>(&self, conn: &mut Connection<A, D>) {
+        self.next.on_connect(conn)
+    }
+
+    fn on_state_change<D: Dispatch<A>>(
+        &self,
+        old_state: &ConnectionState<A>,
+        conn: &mut Connection<A, D>,
+    ) {
+        self.next.on_state_change(old_state, conn)
+    }
+
+    fn on_disconnect<D: Dispatch<A>>(&self, conn: &mut Connection<A, D>) {
+        self.next.on_disconnect(conn)
+    }
 }
 
 impl<A: Application, H: MessageHandler<A>> MessageHandler<A> for LoggingHandler<H> {
@@ -270,6 +385,13 @@ impl<A: Application, H: MessageHandler<A>> MessageHandler<A> for LoggingHandler<
     ) -> Option<u16> {
         self.next.get_supported_module_version(module)
     }
+
+    fn get_module_deprecation(
+        &self,
+        module: &vt6::common::core::ModuleIdentifier<'_>,
+    ) -> Option<vt6::server::ModuleDeprecation> {
+        self.next.get_module_deprecation(module)
+    }
 }
 
 impl<A: Application, H: HandshakeHandler<A>> HandshakeHandler<A> for LoggingHandler<H> {}
@@ -0,0 +1,440 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use vt6::common::core::{msg, EncodeArgument, ModuleIdentifier, ScopedIdentifier, ScreenID};
+use vt6::server::{self, MessageConnector, ScreenIdentity, StdoutError};
+
+pub const WIDTH_PROPERTY: &str = "example1.width";
+pub const HEIGHT_PROPERTY: &str = "example1.height";
+
+const TAB_WIDTH: usize = 8;
+
+////////////////////////////////////////////////////////////////////////////////
+// Grid
+
+///A single character cell on the screen. A real terminal would also store colors, attributes and
+///the width of the character here.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self { ch: ' ' }
+    }
+}
+
+///The contents of a screen as a grid of cells, plus the cursor position.
+///
+///Text written to the grid is placed at the cursor. Lines wrap at the right edge, and the grid
+///scrolls up when the cursor moves past the bottom row. Of the control characters, only CR, LF,
+///TAB and BS are interpreted; LF also returns the cursor to the first column, since there is no
+///line discipline in between that would translate it.
+///
+///Every change marks the affected rows as damaged. The GUI collects the damaged rows with
+///[take_damage()](#method.take_damage) and only repaints those.
+#[derive(Clone, Debug)]
+pub struct Grid {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    cursor_x: usize,
+    cursor_y: usize,
+    damage: Vec<bool>,
+}
+
+impl Grid {
+    ///Creates an empty grid. Zero dimensions are treated as 1.
+    pub fn new(width: u16, height: u16) -> Self {
+        let width = usize::from(width.max(1));
+        let height = usize::from(height.max(1));
+        Self {
+            width,
+            height,
+            cells: vec![Cell::default(); width * height],
+            cursor_x: 0,
+            cursor_y: 0,
+            damage: vec![true; height],
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width as u16
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height as u16
+    }
+
+    pub fn row(&self, y: u16) -> &[Cell] {
+        let start = usize::from(y) * self.width;
+        &self.cells[start..start + self.width]
+    }
+
+    ///Returns the text in the given row, without trailing blanks.
+    pub fn row_text(&self, y: u16) -> String {
+        let text: String = self.row(y).iter().map(|c| c.ch).collect();
+        text.trim_end_matches(' ').into()
+    }
+
+    pub fn has_damage(&self) -> bool {
+        self.damage.iter().any(|d| *d)
+    }
+
+    ///Returns the indexes of all rows that changed since the last call, in ascending order.
+    pub fn take_damage(&mut self) -> Vec<u16> {
+        let rows = (0..self.height)
+            .filter(|y| self.damage[*y])
+            .map(|y| y as u16)
+            .collect();
+        self.damage.iter_mut().for_each(|d| *d = false);
+        rows
+    }
+
+    pub fn write_text(&mut self, text: &str) {
+        for ch in text.chars() {
+            match ch {
+                '\r' => self.cursor_x = 0,
+                '\n' => {
+                    self.cursor_x = 0;
+                    self.line_feed();
+                }
+                '\t' => {
+                    let next_stop = (self.cursor_x / TAB_WIDTH + 1) * TAB_WIDTH;
+                    self.cursor_x = next_stop.min(self.width - 1);
+                }
+                '\x08' => self.cursor_x = self.cursor_x.saturating_sub(1),
+                ch if ch.is_control() => {}
+                ch => self.put(ch),
+            }
+        }
+    }
+
+    ///Changes the size of the grid. Content is anchored at the top-left corner, except when the
+    ///cursor would end up below the last row: then the topmost rows are discarded until the
+    ///cursor row is visible again, like when scrolling.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        let width = usize::from(width.max(1));
+        let height = usize::from(height.max(1));
+        let shift = (self.cursor_y + 1).saturating_sub(height);
+
+        let mut cells = vec![Cell::default(); width * height];
+        let copy_width = width.min(self.width);
+        for y in 0..height.min(self.height - shift) {
+            let src = (y + shift) * self.width;
+            let dst = y * width;
+            cells[dst..dst + copy_width].copy_from_slice(&self.cells[src..src + copy_width]);
+        }
+
+        self.cells = cells;
+        self.width = width;
+        self.height = height;
+        self.cursor_x = self.cursor_x.min(width - 1);
+        self.cursor_y -= shift;
+        self.damage = vec![true; height];
+    }
+
+    fn put(&mut self, ch: char) {
+        self.cells[self.cursor_y * self.width + self.cursor_x] = Cell { ch };
+        self.damage[self.cursor_y] = true;
+        self.cursor_x += 1;
+        if self.cursor_x == self.width {
+            self.cursor_x = 0;
+            self.line_feed();
+        }
+    }
+
+    fn line_feed(&mut self) {
+        if self.cursor_y + 1 < self.height {
+            self.cursor_y += 1;
+            return;
+        }
+        //scroll up by one row
+        self.cells.drain(0..self.width);
+        self.cells
+            .extend(std::iter::repeat_n(Cell::default(), self.width));
+        self.damage.iter_mut().for_each(|d| *d = true);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// ScreenModel
+
+///Events sent from the server side of a [ScreenModel](struct.ScreenModel.html) to the thread that
+///renders it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScreenEvent {
+    ///The grid has damaged rows. This is only sent when the grid goes from undamaged to damaged,
+    ///so the GUI gets woken up once per frame no matter how much output arrives in between.
+    Damaged,
+    ///The stdout of the screen has been disconnected.
+    StdoutDetached,
+}
+
+//Models are registered globally because StdoutConnector::new() only receives the screen
+//identity, so this is where the connector looks up the screen it writes into.
+static SCREENS: Mutex<Vec<ScreenModel>> = Mutex::new(Vec::new());
+
+///The shared state of a screen: The grid is written by the stdout connector (on the event loop)
+///and read by the GUI thread, which is woken up through a channel of
+///[ScreenEvents](enum.ScreenEvent.html).
+#[derive(Clone, Debug)]
+pub struct ScreenModel {
+    identity: ScreenIdentity,
+    grid: Arc<Mutex<Grid>>,
+    events: mpsc::Sender<ScreenEvent>,
+}
+
+impl ScreenModel {
+    ///Creates the model for the given screen and registers it so that
+    ///[find()](#method.find) can return it. The receiver shall be handed to the GUI thread.
+    pub fn register(
+        identity: ScreenIdentity,
+        width: u16,
+        height: u16,
+    ) -> (Self, mpsc::Receiver<ScreenEvent>) {
+        let (tx, rx) = mpsc::channel();
+        let model = Self {
+            identity,
+            grid: Arc::new(Mutex::new(Grid::new(width, height))),
+            events: tx,
+        };
+        SCREENS.lock().unwrap().push(model.clone());
+        (model, rx)
+    }
+
+    pub fn find(screen_id: ScreenID<'_>) -> Option<Self> {
+        let screens = SCREENS.lock().unwrap();
+        screens
+            .iter()
+            .find(|m| m.identity.screen_id() == screen_id)
+            .cloned()
+    }
+
+    pub fn identity(&self) -> &ScreenIdentity {
+        &self.identity
+    }
+
+    ///Locks the grid. The GUI thread holds this lock while it repaints, which blocks incoming
+    ///stdout for that time, so the lock should not be held longer than necessary.
+    pub fn grid(&self) -> MutexGuard<'_, Grid> {
+        self.grid.lock().unwrap()
+    }
+
+    pub fn write_text(&self, text: &str) {
+        let mut grid = self.grid();
+        let was_damaged = grid.has_damage();
+        grid.write_text(text);
+        if !was_damaged && grid.has_damage() {
+            //if the GUI thread is gone, there is no one to tell
+            let _ = self.events.send(ScreenEvent::Damaged);
+        }
+    }
+
+    ///Resizes the grid and publishes the new size to all clients on this screen that have
+    ///subscribed to it. This is called by the GUI thread when the window size changes.
+    pub fn resize<A, D>(&self, width: u16, height: u16, dispatch: &D)
+    where
+        A: server::Application,
+        D: server::Dispatch<A>,
+    {
+        let (width, height) = {
+            let mut grid = self.grid();
+            grid.resize(width, height);
+            (grid.width(), grid.height())
+        };
+
+        //width and height are published together, so that no client sees the new width with
+        //the old height or vice versa
+        let mut tx = server::core::PropertyTransaction::new();
+        let screen = self.identity.clone();
+        tx.publish(WIDTH_PROPERTY, &width.encode_to_vector(), move |identity| {
+            identity.stdout_screen_id() == Some(screen.screen_id())
+        });
+        let screen = self.identity.clone();
+        tx.publish(
+            HEIGHT_PROPERTY,
+            &height.encode_to_vector(),
+            move |identity| identity.stdout_screen_id() == Some(screen.screen_id()),
+        );
+        tx.commit(dispatch);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// StdoutConnector
+
+///A [TextStdoutConnector](../vt6/server/trait.TextStdoutConnector.html) that writes the received
+///text into the [ScreenModel](struct.ScreenModel.html) of its screen.
+#[derive(Debug)]
+pub struct ScreenStdoutConnector {
+    id: ScreenIdentity,
+    model: Option<ScreenModel>,
+}
+
+impl server::TextStdoutConnector for ScreenStdoutConnector {
+    fn new(id: ScreenIdentity) -> Self {
+        let model = ScreenModel::find(id.screen_id());
+        Self { id, model }
+    }
+
+    fn identity(&self) -> Option<&ScreenIdentity> {
+        Some(&self.id)
+    }
+
+    fn receive_text(&mut self, text: &str) -> Result<(), StdoutError> {
+        let model = self.model.as_ref().ok_or(StdoutError::ScreenClosed)?;
+        model.write_text(text);
+        Ok(())
+    }
+}
+
+impl Drop for ScreenStdoutConnector {
+    fn drop(&mut self) {
+        if let Some(ref model) = self.model {
+            let _ = model.events.send(ScreenEvent::StdoutDetached);
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// SizeHandler
+
+///A message handler for the read-only properties `example1.width` and `example1.height`, which
+///report the size of the grid of the screen that the client's stdout is connected to. Requests to
+///change them are ignored. When the size changes,
+///[`ScreenModel::resize()`](struct.ScreenModel.html#method.resize) publishes the new values.
+///
+///This handler must be chained after
+///[vt6::server::core::MessageHandler](../vt6/server/core/struct.MessageHandler.html).
+#[derive(Default)]
+pub struct SizeHandler<Next>(Next);
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::MessageHandler<A>
+    for SizeHandler<Next>
+{
+    fn get_supported_module_version(&self, module: &ModuleIdentifier<'_>) -> Option<u16> {
+        match module.as_str() {
+            "example1" => Some(0),
+            _ => self.0.get_supported_module_version(module),
+        }
+    }
+
+    fn get_module_deprecation(
+        &self,
+        module: &ModuleIdentifier<'_>,
+    ) -> Option<server::ModuleDeprecation> {
+        self.0.get_module_deprecation(module)
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::Handler<A>
+    for SizeHandler<Next>
+{
+    fn handle<D: server::Dispatch<A>>(
+        &self,
+        msg: &msg::Message,
+        conn: &mut server::Connection<A, D>,
+    ) -> Result<(), server::HandlerError> {
+        self.0.handle(msg, conn)
+    }
+
+    fn handle_error<D: server::Dispatch<A>>(
+        &self,
+        err: &msg::ParseError,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.handle_error(err, conn);
+    }
+
+    fn on_connect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_connect(conn);
+    }
+
+    fn on_state_change<D: server::Dispatch<A>>(
+        &self,
+        old_state: &server::ConnectionState<A>,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.on_state_change(old_state, conn);
+    }
+
+    fn on_disconnect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_disconnect(conn);
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>>
+    server::core::MessageHandlerExt<A> for SizeHandler<Next>
+{
+    fn handle_property<D: server::Dispatch<A>>(
+        &self,
+        name: &ScopedIdentifier<'_>,
+        requested_value: Option<&[u8]>,
+        conn: &mut server::Connection<A, D>,
+    ) -> Option<Vec<u8>> {
+        let is_width = match name.as_str() {
+            WIDTH_PROPERTY => true,
+            HEIGHT_PROPERTY => false,
+            _ => return self.0.handle_property(name, requested_value, conn),
+        };
+
+        let screen_id = conn.message_connector()?.identity().stdout_screen_id()?;
+        let model = ScreenModel::find(screen_id)?;
+        let grid = model.grid();
+        Some(if is_width {
+            grid.width().encode_to_vector()
+        } else {
+            grid.height().encode_to_vector()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid_text(grid: &Grid) -> Vec<String> {
+        (0..grid.height()).map(|y| grid.row_text(y)).collect()
+    }
+
+    #[test]
+    fn test_grid_write_text() {
+        let mut grid = Grid::new(5, 3);
+        assert_eq!(grid.take_damage(), vec![0, 1, 2]);
+
+        grid.write_text("ab\tchello");
+        assert_eq!(grid_text(&grid), vec!["ab  c", "hello", ""]);
+        assert_eq!((grid.cursor_x, grid.cursor_y), (0, 2));
+        assert_eq!(grid.take_damage(), vec![0, 1]);
+        assert!(!grid.has_damage());
+
+        //writing past the bottom row scrolls everything up
+        grid.write_text("x\x08y\nz");
+        assert_eq!(grid_text(&grid), vec!["hello", "y", "z"]);
+        assert_eq!((grid.cursor_x, grid.cursor_y), (1, 2));
+        assert_eq!(grid.take_damage(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_grid_resize() {
+        let mut grid = Grid::new(5, 3);
+        grid.write_text("one\ntwo\nfour");
+        assert_eq!((grid.cursor_x, grid.cursor_y), (4, 2));
+
+        //shrinking discards rows from the top to keep the cursor visible
+        grid.resize(3, 2);
+        assert_eq!(grid_text(&grid), vec!["two", "fou"]);
+        assert_eq!((grid.cursor_x, grid.cursor_y), (2, 1));
+
+        grid.take_damage();
+        grid.resize(4, 3);
+        assert_eq!(grid_text(&grid), vec!["two", "fou", ""]);
+        assert_eq!(grid.take_damage(), vec![0, 1, 2]);
+    }
+}
@@ -0,0 +1,329 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::client::{DisconnectReason, DisconnectWatcher};
+use crate::common::core::msg;
+use bytes::{Bytes, BytesMut};
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures::stream::{Stream, StreamExt};
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncRead, ReadBuf};
+
+//Messages are never longer than this. [vt6/foundation, sect. 3.1.2]
+const MAX_MESSAGE_LEN: usize = 1024;
+//How much stdin is read at once by AsyncStdinReceiver.
+const STDIN_CHUNK_LEN: usize = 4096;
+
+///Reads messages from an msgio socket, for clients using the [Tokio library](https://tokio.rs/).
+///This is only available with the `use_tokio` feature.
+///
+///This is the async counterpart of
+///[`Connection::recv_message()`](struct.Connection.html#method.recv_message), and is usually
+///obtained from [`Connection::into_async()`](struct.Connection.html#method.into_async). It is a
+///[Stream](https://docs.rs/futures/0.3/futures/stream/trait.Stream.html) that yields each message
+///received from the server as a separate buffer, which can be inspected with
+///[Message::parse()](../common/core/msg/struct.Message.html#method.parse). The stream ends when
+///the server closes the connection. When the server sends something that is not a valid message,
+///the invalid input is discarded and an error of kind `InvalidData` is yielded, after which the
+///stream continues with the next message.
+///
+///```no_run
+///# use vt6::client::{Connection, Msgio};
+///# async fn example(conn: Connection<Msgio>) -> std::io::Result<()> {
+///let (mut receiver, mut sender) = conn.into_async()?;
+///while let Some(buf) = receiver.recv_message().await? {
+///    let (msg, _) = vt6::common::core::msg::Message::parse(&buf).unwrap();
+///    //...
+///}
+///# Ok(())
+///# }
+///```
+///
+///Unlike [AsyncMessageSender](struct.AsyncMessageSender.html), this type is cancellation-safe:
+///All state is kept in the receiver itself, so when the future returned by `recv_message()` or
+///`StreamExt::next()` is dropped (e.g. because another branch of a `select!` has completed), no
+///data is lost, and the next call picks up where the previous one left off. Timeouts can be
+///implemented in the same way, or with `recv_message_timeout()`.
+///
+///When the stream ends or fails because the connection is gone, the reason can be obtained from
+///`disconnect_reason()`, or through a callback registered with `on_disconnect()`.
+#[derive(Debug)]
+pub struct AsyncMessageReceiver<R> {
+    reader: R,
+    buf: BytesMut,
+    is_eof: bool,
+    disconnect: DisconnectWatcher,
+}
+
+impl<R: AsyncRead + Unpin> AsyncMessageReceiver<R> {
+    ///Wraps the given reader, which is usually the read half of a socket that has already been
+    ///put into msgio mode.
+    pub fn new(reader: R) -> Self {
+        Self::with_received(reader, &[], DisconnectWatcher::default())
+    }
+
+    //Like new(), but `received` contains data that was received on the socket before it was
+    //wrapped, e.g. because it arrived together with the handshake. The disconnect watcher is
+    //carried over from the Connection, too.
+    pub(crate) fn with_received(reader: R, received: &[u8], disconnect: DisconnectWatcher) -> Self {
+        let mut buf = BytesMut::with_capacity(MAX_MESSAGE_LEN);
+        buf.extend_from_slice(received);
+        Self {
+            reader,
+            buf,
+            is_eof: false,
+            disconnect,
+        }
+    }
+
+    ///Like [`Connection::on_disconnect()`](struct.Connection.html#method.on_disconnect). A
+    ///callback that was registered on the Connection before `into_async()` stays registered.
+    pub fn on_disconnect<F: FnMut(DisconnectReason) + Send + 'static>(&mut self, callback: F) {
+        self.disconnect.set_callback(Box::new(callback));
+    }
+
+    ///Like [`Connection::disconnect_reason()`](struct.Connection.html#method.disconnect_reason).
+    pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+        self.disconnect.reason()
+    }
+
+    ///Waits until the next message from the server has been received. Returns `Ok(None)` when
+    ///the server has closed the connection. This is a shorthand for `StreamExt::next()` that is
+    ///easier to use with the `?` operator.
+    pub async fn recv_message(&mut self) -> io::Result<Option<Bytes>> {
+        self.next().await.transpose()
+    }
+
+    ///Like `recv_message()`, but gives up after the given timeout with an error of kind
+    ///`TimedOut`. The receiver can be used again afterwards, since no data is lost when waiting
+    ///is aborted.
+    pub async fn recv_message_timeout(&mut self, timeout: Duration) -> io::Result<Option<Bytes>> {
+        crate::common::io_timeout(timeout, self.recv_message(), "message").await
+    }
+
+    ///Returns a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    ///Unwraps the reader. Data that has already been received, but not been yielded by the
+    ///stream yet, is lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn poll_read_more(&mut self, cx: &mut Context<'_>, max_len: usize) -> Poll<io::Result<()>> {
+        let mut chunk = [0u8; STDIN_CHUNK_LEN];
+        let mut read_buf = ReadBuf::new(&mut chunk[0..max_len]);
+        match Pin::new(&mut self.reader).poll_read(cx, &mut read_buf) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(e)) => Poll::Ready(self.disconnect.check(Err(e))),
+            Poll::Ready(Ok(())) => {
+                if read_buf.filled().is_empty() {
+                    self.is_eof = true;
+                    self.disconnect.report(DisconnectReason::Closed);
+                }
+                self.buf.extend_from_slice(read_buf.filled());
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for AsyncMessageReceiver<R> {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            match msg::Message::parse(&this.buf) {
+                Ok((_, len)) => return Poll::Ready(Some(Ok(this.buf.split_to(len).freeze()))),
+                Err(e) if e.is_incomplete() => {
+                    if this.buf.len() >= MAX_MESSAGE_LEN {
+                        //a message cannot be this long, so this is not a message
+                        this.buf.clear();
+                        return Poll::Ready(Some(Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "discarded overlong message",
+                        ))));
+                    }
+                }
+                Err(e) => {
+                    //After a parse error, recover by skipping ahead to the next possible start of
+                    //a message, i.e. the next `{` sign. [vt6/foundation, sect. 3.3]
+                    let err = io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+                    let bytes_to_discard = e.resync_offset;
+                    let _ = this.buf.split_to(bytes_to_discard);
+                    return Poll::Ready(Some(Err(err)));
+                }
+            }
+
+            //an incomplete message at EOF is dropped silently, like in Connection::recv_message()
+            if this.is_eof {
+                return Poll::Ready(None);
+            }
+            if let Err(e) = futures::ready!(this.poll_read_more(cx, MAX_MESSAGE_LEN)) {
+                return Poll::Ready(Some(Err(e)));
+            }
+        }
+    }
+}
+
+///Reads input from an stdin socket, for clients using the [Tokio library](https://tokio.rs/).
+///This is only available with the `use_tokio` feature.
+///
+///This is the async counterpart of reading from a
+///[`Connection<Stdin>`](struct.Connection.html), and is usually obtained from
+///[`Connection::into_async()`](struct.Connection.html#method.into_async-1). It is a
+///[Stream](https://docs.rs/futures/0.3/futures/stream/trait.Stream.html) that yields the input
+///as it arrives, in chunks of arbitrary size. The stream ends when the server closes the
+///connection.
+///
+///Like [AsyncMessageReceiver](struct.AsyncMessageReceiver.html), this type is cancellation-safe,
+///and `recv_timeout()` can be used to wait for input for a limited time.
+#[derive(Debug)]
+pub struct AsyncStdinReceiver<R>(AsyncMessageReceiver<R>);
+
+impl<R: AsyncRead + Unpin> AsyncStdinReceiver<R> {
+    ///Wraps the given reader, which is usually a socket that has already been put into stdin
+    ///mode.
+    pub fn new(reader: R) -> Self {
+        Self::with_received(reader, &[], DisconnectWatcher::default())
+    }
+
+    pub(crate) fn with_received(reader: R, received: &[u8], disconnect: DisconnectWatcher) -> Self {
+        Self(AsyncMessageReceiver::with_received(
+            reader, received, disconnect,
+        ))
+    }
+
+    ///Like [`Connection::on_disconnect()`](struct.Connection.html#method.on_disconnect). A
+    ///callback that was registered on the Connection before `into_async()` stays registered.
+    pub fn on_disconnect<F: FnMut(DisconnectReason) + Send + 'static>(&mut self, callback: F) {
+        self.0.on_disconnect(callback)
+    }
+
+    ///Like [`Connection::disconnect_reason()`](struct.Connection.html#method.disconnect_reason).
+    pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+        self.0.disconnect_reason()
+    }
+
+    ///Waits until more input has been received. Returns `Ok(None)` when the server has closed the
+    ///connection.
+    pub async fn recv(&mut self) -> io::Result<Option<Bytes>> {
+        self.next().await.transpose()
+    }
+
+    ///Like `recv()`, but gives up after the given timeout with an error of kind `TimedOut`.
+    pub async fn recv_timeout(&mut self, timeout: Duration) -> io::Result<Option<Bytes>> {
+        crate::common::io_timeout(timeout, self.recv(), "input").await
+    }
+
+    ///Returns a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        self.0.get_ref()
+    }
+
+    ///Unwraps the reader. Input that has already been received, but not been yielded by the
+    ///stream yet, is lost.
+    pub fn into_inner(self) -> R {
+        self.0.into_inner()
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for AsyncStdinReceiver<R> {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut self.get_mut().0;
+        if this.buf.is_empty() {
+            if this.is_eof {
+                return Poll::Ready(None);
+            }
+            if let Err(e) = futures::ready!(this.poll_read_more(cx, STDIN_CHUNK_LEN)) {
+                return Poll::Ready(Some(Err(e)));
+            }
+            if this.is_eof {
+                return Poll::Ready(None);
+            }
+        }
+        Poll::Ready(Some(Ok(this.buf.split().freeze())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_message_receiver() {
+        runtime().block_on(async {
+            let (reader, mut writer) = tokio::io::duplex(64);
+            let mut receiver = AsyncMessageReceiver::with_received(
+                reader,
+                b"{1|4:have,}{2|4:h",
+                DisconnectWatcher::default(),
+            );
+
+            //data received before wrapping the socket comes first
+            let buf = receiver.recv_message().await.unwrap().unwrap();
+            assert_eq!(&buf[..], b"{1|4:have,}");
+
+            //waiting for an incomplete message can be aborted without losing data
+            let result = receiver
+                .recv_message_timeout(Duration::from_millis(10))
+                .await;
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+            writer
+                .write_all(b"ave,5:core1,}garbage{1|4:nope,}")
+                .await
+                .unwrap();
+            let buf = receiver.recv_message().await.unwrap().unwrap();
+            assert_eq!(&buf[..], b"{2|4:have,5:core1,}");
+
+            //garbage is reported and skipped
+            let err = receiver.recv_message().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            let buf = receiver.recv_message().await.unwrap().unwrap();
+            assert_eq!(&buf[..], b"{1|4:nope,}");
+
+            //timeouts and garbage do not count as a disconnect, but EOF does
+            assert_eq!(receiver.disconnect_reason(), None);
+            std::mem::drop(writer);
+            assert!(receiver.recv_message().await.unwrap().is_none());
+            assert!(receiver.next().await.is_none());
+            assert_eq!(receiver.disconnect_reason(), Some(DisconnectReason::Closed));
+        });
+    }
+
+    #[test]
+    fn test_stdin_receiver() {
+        runtime().block_on(async {
+            let (reader, mut writer) = tokio::io::duplex(64);
+            let mut receiver =
+                AsyncStdinReceiver::with_received(reader, b"hello", DisconnectWatcher::default());
+            assert_eq!(&receiver.recv().await.unwrap().unwrap()[..], b"hello");
+
+            let result = receiver.recv_timeout(Duration::from_millis(10)).await;
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+            writer.write_all(b"world").await.unwrap();
+            assert_eq!(&receiver.recv().await.unwrap().unwrap()[..], b"world");
+
+            std::mem::drop(writer);
+            assert!(receiver.recv().await.unwrap().is_none());
+        });
+    }
+}
@@ -0,0 +1,149 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg;
+use crate::common::SendQueue;
+use std::io;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+///The default for [`AsyncMessageSender::with_high_water_mark()`](struct.AsyncMessageSender.html#method.with_high_water_mark).
+pub const DEFAULT_HIGH_WATER_MARK: usize = 16384;
+
+///A send queue for messages on an msgio socket, for clients using the
+///[Tokio library](https://tokio.rs/). This is only available with the `use_tokio` feature.
+///
+///This is the async counterpart of the send queue in [Connection](struct.Connection.html): Small
+///messages are coalesced so that they can be sent with a single write. Instead of blocking, this
+///type applies backpressure: When more than the configured high-water mark of bytes is waiting to
+///be sent, `send_message()` does not return until enough of them have been written into the
+///socket. A slow server therefore slows down the sender instead of making the queue grow without
+///bound.
+///
+///The writer is usually the write half of a socket that has already been put into msgio mode.
+///
+///```no_run
+///# use vt6::common::core::ModuleIdentifier;
+///# async fn example(tokio_stream: tokio::net::UnixStream) -> std::io::Result<()> {
+///# let module = ModuleIdentifier::parse("example1").unwrap();
+///let (reader, writer) = tokio_stream.into_split();
+///let mut sender = vt6::client::AsyncMessageSender::new(writer);
+///sender.send_message(&vt6::msg::Want(module)).await?;
+///sender.flush().await?;
+///# Ok(())
+///# }
+///```
+///
+///The futures returned by `send_message()` and `flush()` are not cancellation-safe: If they are
+///dropped while writing, a part of the queued messages may be lost.
+pub struct AsyncMessageSender<W> {
+    writer: W,
+    queue: SendQueue,
+    high_water_mark: usize,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncMessageSender<W> {
+    ///Wraps the given writer. The high-water mark is set to
+    ///[DEFAULT_HIGH_WATER_MARK](constant.DEFAULT_HIGH_WATER_MARK.html).
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            queue: SendQueue::default(),
+            high_water_mark: DEFAULT_HIGH_WATER_MARK,
+        }
+    }
+
+    ///Sets how many bytes may be waiting in the send queue before `send_message()` starts writing
+    ///them into the socket. With a high-water mark of 0, every message is written immediately.
+    pub fn with_high_water_mark(self, bytes: usize) -> Self {
+        Self {
+            high_water_mark: bytes,
+            ..self
+        }
+    }
+
+    ///Puts a message into the send queue. If this makes the send queue grow above the high-water
+    ///mark, queued messages are written into the socket until it is below the high-water mark
+    ///again. Call `flush()` to make sure that all queued messages are sent.
+    pub async fn send_message<M: msg::EncodeMessage>(&mut self, msg: &M) -> io::Result<()> {
+        self.queue
+            .push_message(msg)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too long"))?;
+        while self.queue.filled_len() > self.high_water_mark {
+            self.write_next_buffer().await?;
+        }
+        Ok(())
+    }
+
+    ///Writes all queued messages into the socket, and flushes the writer.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        while !self.queue.is_empty() {
+            self.write_next_buffer().await?;
+        }
+        self.writer.flush().await
+    }
+
+    async fn write_next_buffer(&mut self) -> io::Result<()> {
+        if let Some(buf) = self.queue.pop() {
+            let result = self.writer.write_all(buf.filled()).await;
+            self.queue.recycle(buf);
+            result?;
+        }
+        Ok(())
+    }
+
+    ///Returns how many bytes of messages are waiting in the send queue.
+    pub fn queued_len(&self) -> usize {
+        self.queue.filled_len()
+    }
+
+    ///Returns a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    ///Unwraps the writer. Messages that are still queued are lost, so `flush()` should usually be
+    ///called first.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::ModuleIdentifier;
+    use crate::msg::Want;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn test_backpressure() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            //the server side only accepts 64 bytes at once into its buffer
+            let (writer, mut reader) = tokio::io::duplex(64);
+            let receiver = tokio::spawn(async move {
+                let mut received = Vec::new();
+                reader.read_to_end(&mut received).await.unwrap();
+                received
+            });
+
+            let mut sender = AsyncMessageSender::new(writer).with_high_water_mark(100);
+            let msg = Want(ModuleIdentifier::parse("core1").unwrap());
+            for _ in 0..1000 {
+                sender.send_message(&msg).await.unwrap();
+                assert!(sender.queued_len() <= 100);
+            }
+            sender.flush().await.unwrap();
+            assert_eq!(sender.queued_len(), 0);
+            std::mem::drop(sender);
+
+            let received = receiver.await.unwrap();
+            assert_eq!(received, b"{2|4:want,5:core1,}".repeat(1000));
+        });
+    }
+}
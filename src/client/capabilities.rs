@@ -0,0 +1,439 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::client::{Connection, Msgio};
+use crate::common::core::msg::DecodeMessage;
+use crate::common::core::ModuleIdentifier;
+use crate::msg::{Have, Want};
+use core::fmt;
+use core::ops::RangeInclusive;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, RwLock};
+
+///The result of [probe_capabilities()](fn.probe_capabilities.html): which modules the server
+///supports, and in which versions.
+///
+///Modules are referred to by their name without the major version, e.g. "core" instead of
+///"core1". For each probed module, the highest major version within the probed range that the
+///server supports is chosen.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    modules: BTreeMap<String, ProbedModule>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ProbedModule {
+    requested: RangeInclusive<u16>,
+    //major and minor version, if supported
+    version: Option<(u16, u16)>,
+}
+
+impl Capabilities {
+    ///Returns the major and minor version of the given module that was agreed on with the server,
+    ///or `None` if the server does not support any of the probed versions of this module, or if
+    ///this module was not probed at all.
+    pub fn version(&self, name: &str) -> Option<(u16, u16)> {
+        self.modules.get(name).and_then(|m| m.version)
+    }
+
+    ///Like [version()](#method.version), but returns a descriptive error if the module is not
+    ///supported.
+    pub fn require_module(&self, name: &str) -> Result<(u16, u16), ModuleError> {
+        match self.modules.get(name) {
+            None => Err(ModuleError::NotProbed(name.into())),
+            Some(m) => m.version.ok_or_else(|| ModuleError::Unsupported {
+                name: name.into(),
+                requested: m.requested.clone(),
+            }),
+        }
+    }
+
+    ///Iterates over all probed modules that the server supports, in alphabetical order. Each item
+    ///contains the module name and the agreed major and minor version.
+    pub fn supported_modules(&self) -> impl Iterator<Item = (&str, u16, u16)> {
+        self.modules.iter().filter_map(|(name, m)| {
+            m.version
+                .map(|(major, minor)| (name.as_str(), major, minor))
+        })
+    }
+}
+
+///The negotiation state of a connection, i.e. the [Capabilities](struct.Capabilities.html) that
+///were negotiated with the server so far.
+///
+///This is a cheap handle that can be cloned and sent to other threads. All clones refer to the
+///same state, which is updated whenever [probe_capabilities()](fn.probe_capabilities.html) runs
+///on the connection. Libraries built on top of a connection can therefore check which modules are
+///available through their own handle (obtained from
+///[`Connection::negotiation()`](struct.Connection.html#method.negotiation)), without having
+///access to the connection itself:
+///
+///```no_run
+///# fn main() -> Result<(), Box<dyn std::error::Error>> {
+///let conn = vt6::client::Connection::connect("/run/user/1000/vt6/1234")?;
+///let mut conn = conn.client_hello("secret")?;
+///let negotiation = conn.negotiation().clone();
+///std::thread::spawn(move || {
+///    if negotiation.require_module("term").is_ok() {
+///        //...
+///    }
+///});
+///vt6::client::probe_capabilities(&mut conn, &[("term", 1..=1)])?;
+///# Ok(())
+///# }
+///```
+#[derive(Clone, Debug, Default)]
+pub struct Negotiation(Arc<RwLock<Option<Arc<Capabilities>>>>);
+
+impl Negotiation {
+    ///Returns a snapshot of the capabilities that were negotiated so far, or `None` if
+    ///[probe_capabilities()](fn.probe_capabilities.html) has not completed successfully yet.
+    ///
+    ///This is cheap, and only blocks briefly while a `probe_capabilities()` call is storing its
+    ///result. The snapshot does not change when further modules are probed later on.
+    pub fn enabled_modules(&self) -> Option<Arc<Capabilities>> {
+        self.0.read().unwrap().clone()
+    }
+
+    ///Like [`Capabilities::require_module()`](struct.Capabilities.html#method.require_module),
+    ///but also reports `ModuleError::NotProbed` if no capabilities have been negotiated yet.
+    pub fn require_module(&self, name: &str) -> Result<(u16, u16), ModuleError> {
+        match *self.0.read().unwrap() {
+            Some(ref caps) => caps.require_module(name),
+            None => Err(ModuleError::NotProbed(name.into())),
+        }
+    }
+
+    fn store(&self, caps: Capabilities) -> Arc<Capabilities> {
+        let caps = Arc::new(caps);
+        *self.0.write().unwrap() = Some(caps.clone());
+        caps
+    }
+}
+
+///Error type returned by
+///[`Connection::require_module()`](struct.Connection.html#method.require_module) and
+///[`Capabilities::require_module()`](struct.Capabilities.html#method.require_module).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModuleError {
+    ///The module was not included in the call to
+    ///[probe_capabilities()](fn.probe_capabilities.html), or the capabilities were not probed at
+    ///all. This usually indicates a bug in the client.
+    NotProbed(String),
+    ///The server does not support any of the requested major versions of the module.
+    Unsupported {
+        name: String,
+        requested: RangeInclusive<u16>,
+    },
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::NotProbed(ref name) => {
+                write!(f, "support for module {} has not been probed", name)
+            }
+            Self::Unsupported {
+                ref name,
+                ref requested,
+            } => {
+                if requested.start() == requested.end() {
+                    write!(
+                        f,
+                        "terminal does not support module {}{}",
+                        name,
+                        requested.start()
+                    )
+                } else {
+                    write!(
+                        f,
+                        "terminal does not support any of the modules {}{} to {}{}",
+                        name,
+                        requested.start(),
+                        name,
+                        requested.end()
+                    )
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for ModuleError {}
+
+///Error type returned by [probe_capabilities()](fn.probe_capabilities.html).
+#[derive(Debug)]
+pub enum ProbeError {
+    ///An IO error occurred on the socket. If the server closed the connection before answering
+    ///all `want` messages, this is an error of kind `UnexpectedEof`.
+    Io(io::Error),
+    ///The server sent something other than the expected `have` messages. The human-readable
+    ///representation of the message is included.
+    UnexpectedReply(String),
+}
+
+impl From<io::Error> for ProbeError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Io(ref e) => write!(f, "IO error while probing capabilities: {}", e),
+            Self::UnexpectedReply(ref s) => write!(f, "expected have, got {}", s),
+        }
+    }
+}
+
+impl std::error::Error for ProbeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Self::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+///Finds out which versions of the given modules the server supports.
+///
+///Each module is given by its name (without major version) and the range of major versions that
+///the client can work with. A `want` message is sent for each of these major versions, all at
+///once, before waiting for the `have` replies, so the whole exchange only takes a single round
+///trip. For each module, the highest supported major version is chosen.
+///
+///The result is cached in the connection's [Negotiation](struct.Negotiation.html), where it can
+///be accessed through
+///[`Connection::capabilities()`](struct.Connection.html#method.capabilities) and
+///[`Connection::require_module()`](struct.Connection.html#method.require_module), as well as by
+///other threads that hold a clone of the Negotiation. Calling this function again adds to the
+///cached result: Modules that were already probed for the same range of major versions are not
+///probed again, so libraries can call this for the modules that they need without causing
+///redundant round trips. The returned snapshot contains all modules that were probed so far.
+///
+///This must be called while no other replies are outstanding, since any message other than the
+///expected `have` messages is reported as an error.
+///
+///```no_run
+///# fn main() -> Result<(), Box<dyn std::error::Error>> {
+///let conn = vt6::client::Connection::connect("/run/user/1000/vt6/1234")?;
+///let mut conn = conn.client_hello("secret")?;
+///vt6::client::probe_capabilities(&mut conn, &[("core", 1..=2), ("term", 1..=1)])?;
+///let (major, minor) = conn.require_module("core")?;
+///println!("using core{}.{}", major, minor);
+///# Ok(())
+///# }
+///```
+///
+///# Panics
+///
+///Panics if any of the module names, when combined with any of the requested major versions, does
+///not yield a valid module identifier.
+pub fn probe_capabilities(
+    conn: &mut Connection<Msgio>,
+    modules: &[(&str, RangeInclusive<u16>)],
+) -> Result<Arc<Capabilities>, ProbeError> {
+    let cached = conn.negotiation().enabled_modules();
+    let mut caps = cached
+        .as_ref()
+        .map(|caps| Capabilities::clone(caps))
+        .unwrap_or_default();
+    //all module identifiers that we still expect a `have` for
+    let mut pending = Vec::new();
+
+    for (name, requested) in modules {
+        if let Some(probed) = caps.modules.get(*name) {
+            if probed.requested == *requested {
+                continue;
+            }
+        }
+        for major in requested.clone() {
+            let ident = format!("{}{}", name, major);
+            let module = ModuleIdentifier::parse(&ident)
+                .filter(|m| m.name().as_str() == *name)
+                .expect("probe_capabilities() called with invalid module name");
+            conn.queue_message(&Want(module))?;
+            pending.push(ident);
+        }
+        caps.modules.insert(
+            (*name).into(),
+            ProbedModule {
+                requested: requested.clone(),
+                version: None,
+            },
+        );
+    }
+
+    while !pending.is_empty() {
+        let msg = match conn.recv_message()? {
+            Some(msg) => msg,
+            None => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        };
+        let (ident, version) = match Have::decode_message(&msg) {
+            Some(Have::ThisModule(v)) => {
+                let module = v.module();
+                (module.as_str().to_owned(), Some(v.minor_version()))
+            }
+            Some(Have::NotThisModule(m)) => (m.as_str().to_owned(), None),
+            None => return Err(ProbeError::UnexpectedReply(msg.to_string())),
+        };
+        let idx = pending
+            .iter()
+            .position(|p| *p == ident)
+            .ok_or_else(|| ProbeError::UnexpectedReply(msg.to_string()))?;
+        pending.swap_remove(idx);
+
+        if let Some(minor) = version {
+            //this unwrap() is safe since we only sent `want` for valid module identifiers
+            let module = ModuleIdentifier::parse(&ident).unwrap();
+            let probed = caps.modules.get_mut(module.name().as_str()).unwrap();
+            let major = module.major_version();
+            let is_newer = match probed.version {
+                Some((m, _)) => m < major,
+                None => true,
+            };
+            if is_newer {
+                probed.version = Some((major, minor));
+            }
+        }
+    }
+
+    match cached {
+        //nothing needed to be probed
+        Some(cached) if *cached == caps => Ok(cached),
+        _ => Ok(conn.negotiation().store(caps)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    fn connect() -> (Connection<Msgio>, UnixStream) {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        server
+            .write_all(b"{5|19:posix1.server-hello,3:foo,1:1,0:,1:1,}")
+            .unwrap();
+        let conn = Connection::from_stream(client).client_hello("abc").unwrap();
+        let mut buf = [0u8; 33];
+        server.read_exact(&mut buf).unwrap();
+        (conn, server)
+    }
+
+    #[test]
+    fn test_probe_capabilities() {
+        let (mut conn, mut server) = connect();
+        assert_eq!(
+            conn.require_module("core"),
+            Err(ModuleError::NotProbed("core".into()))
+        );
+
+        //the replies are already waiting when the probe starts, which works because all `want`
+        //messages are sent before the first reply is read
+        server
+            .write_all(b"{2|4:have,7:core1.3,}{2|4:have,7:core2.0,}")
+            .unwrap();
+        server
+            .write_all(b"{2|4:have,5:term1,}{2|4:have,4:sig2,}{2|4:have,6:sig1.0,}")
+            .unwrap();
+        let caps = probe_capabilities(
+            &mut conn,
+            &[("core", 1..=2), ("term", 1..=1), ("sig", 1..=2)],
+        )
+        .unwrap();
+        assert_eq!(caps.version("core"), Some((2, 0)));
+        assert_eq!(caps.version("term"), None);
+        assert_eq!(
+            caps.supported_modules().collect::<Vec<_>>(),
+            vec![("core", 2, 0), ("sig", 1, 0)]
+        );
+
+        let mut buf = [0u8; 93];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..],
+            &b"{2|4:want,5:core1,}{2|4:want,5:core2,}{2|4:want,5:term1,}{2|4:want,4:sig1,}{2|4:want,4:sig2,}"[..]
+        );
+
+        //the result is cached on the connection
+        assert_eq!(conn.require_module("sig"), Ok((1, 0)));
+        let err = conn.require_module("term").unwrap_err();
+        assert_eq!(err.to_string(), "terminal does not support module term1");
+        let err = conn.require_module("foo").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "support for module foo has not been probed"
+        );
+    }
+
+    #[test]
+    fn test_shared_negotiation() {
+        let (mut conn, mut server) = connect();
+        let negotiation = conn.negotiation().clone();
+        assert!(negotiation.enabled_modules().is_none());
+
+        server.write_all(b"{2|4:have,7:core1.3,}").unwrap();
+        probe_capabilities(&mut conn, &[("core", 1..=1)]).unwrap();
+        let snapshot = std::thread::spawn(move || {
+            assert_eq!(negotiation.require_module("core"), Ok((1, 3)));
+            negotiation.enabled_modules().unwrap()
+        })
+        .join()
+        .unwrap();
+
+        //modules that were already probed are not probed again, and further modules are added to
+        //the cached result without affecting earlier snapshots
+        server.write_all(b"{2|4:have,5:term1,}").unwrap();
+        let caps = probe_capabilities(&mut conn, &[("core", 1..=1), ("term", 1..=1)]).unwrap();
+        assert_eq!(
+            caps.supported_modules().collect::<Vec<_>>(),
+            vec![("core", 1, 3)]
+        );
+        assert_eq!(
+            conn.require_module("term").unwrap_err().to_string(),
+            "terminal does not support module term1"
+        );
+        assert!(snapshot.require_module("term").is_err());
+        let mut buf = [0u8; 38];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"{2|4:want,5:core1,}{2|4:want,5:term1,}");
+
+        //when everything was probed already, there is no round trip at all
+        let cached = probe_capabilities(&mut conn, &[("term", 1..=1)]).unwrap();
+        assert!(Arc::ptr_eq(&cached, &caps));
+        assert!(Arc::ptr_eq(&conn.capabilities().unwrap(), &caps));
+    }
+
+    #[test]
+    fn test_probe_capabilities_unexpected_reply() {
+        let (mut conn, mut server) = connect();
+        server.write_all(b"{2|4:have,7:core3.0,}").unwrap();
+        match probe_capabilities(&mut conn, &[("core", 1..=2)]) {
+            Err(ProbeError::UnexpectedReply(s)) => assert_eq!(s, "(have core3.0)"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(conn.capabilities().is_none());
+
+        let err = ModuleError::Unsupported {
+            name: "core".into(),
+            requested: 1..=2,
+        };
+        assert_eq!(
+            err.to_string(),
+            "terminal does not support any of the modules core1 to core2"
+        );
+
+        std::mem::drop(server);
+        assert!(matches!(
+            probe_capabilities(&mut conn, &[("core", 1..=1)]),
+            Err(ProbeError::Io(_))
+        ));
+    }
+}
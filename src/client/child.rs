@@ -0,0 +1,253 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::client::{Connection, Msgio};
+use crate::common::core::msg::DecodeMessage;
+use crate::common::core::{ClientID, ScreenID};
+use crate::msg::core::{ClientMake, ClientNew};
+use crate::msg::Nope;
+use core::fmt;
+use std::io;
+
+///Describes a child client that shall be registered with
+///[register_child()](fn.register_child.html).
+///
+///The child's client ID must be below the client ID of the connection that registers it. The
+///screens default to none; use the builder methods to attach the child's standard streams.
+///
+///```
+///# use vt6::common::core::{ClientID, ScreenID};
+///# use vt6::client::ChildClient;
+///let screen = ScreenID::parse("screen1").unwrap();
+///let child = ChildClient::new(ClientID::parse("a1").unwrap())
+///    .with_stdin(screen)
+///    .with_stdout(screen);
+///assert_eq!(child.client_id().as_str(), "a1");
+///assert_eq!(child.stderr_screen_id(), None);
+///```
+#[derive(Clone, Debug)]
+pub struct ChildClient<'a> {
+    client_id: ClientID<'a>,
+    stdin_screen_id: Option<ScreenID<'a>>,
+    stdout_screen_id: Option<ScreenID<'a>>,
+    stderr_screen_id: Option<ScreenID<'a>>,
+}
+
+impl<'a> ChildClient<'a> {
+    pub fn new(client_id: ClientID<'a>) -> Self {
+        Self {
+            client_id,
+            stdin_screen_id: None,
+            stdout_screen_id: None,
+            stderr_screen_id: None,
+        }
+    }
+
+    pub fn with_stdin(mut self, screen_id: ScreenID<'a>) -> Self {
+        self.stdin_screen_id = Some(screen_id);
+        self
+    }
+
+    pub fn with_stdout(mut self, screen_id: ScreenID<'a>) -> Self {
+        self.stdout_screen_id = Some(screen_id);
+        self
+    }
+
+    pub fn with_stderr(mut self, screen_id: ScreenID<'a>) -> Self {
+        self.stderr_screen_id = Some(screen_id);
+        self
+    }
+
+    pub fn client_id(&self) -> ClientID<'a> {
+        self.client_id
+    }
+
+    pub fn stdin_screen_id(&self) -> Option<ScreenID<'a>> {
+        self.stdin_screen_id
+    }
+
+    pub fn stdout_screen_id(&self) -> Option<ScreenID<'a>> {
+        self.stdout_screen_id
+    }
+
+    pub fn stderr_screen_id(&self) -> Option<ScreenID<'a>> {
+        self.stderr_screen_id
+    }
+}
+
+///The credentials of a child client, as returned by [register_child()](fn.register_child.html).
+///The secret shall be handed to the child process, which uses it for its handshake.
+#[derive(Clone)]
+pub struct ClientCredentials {
+    secret: String,
+}
+
+impl ClientCredentials {
+    ///Returns the secret that the child client can use to authenticate with the terminal.
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+}
+
+//Debug is implemented manually to keep the secret out of logs
+impl fmt::Debug for ClientCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientCredentials")
+            .field("secret", &format_args!("<redacted>"))
+            .finish()
+    }
+}
+
+///Error type returned by [register_child()](fn.register_child.html).
+#[derive(Debug)]
+pub enum RegisterError {
+    ///An IO error occurred on the socket. If the server closed the connection before answering,
+    ///this is an error of kind `UnexpectedEof`.
+    Io(io::Error),
+    ///The server refused to register the child client. This happens when the child's client ID
+    ///is not below the registering client's ID, or when it is already in use.
+    Rejected,
+    ///The server sent something other than `core1.client-new` or `nope`. The human-readable
+    ///representation of the message is included.
+    UnexpectedReply(String),
+}
+
+impl From<io::Error> for RegisterError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Io(ref e) => write!(f, "IO error while registering child client: {}", e),
+            Self::Rejected => write!(f, "terminal refused to register child client"),
+            Self::UnexpectedReply(ref s) => write!(f, "expected core1.client-new, got {}", s),
+        }
+    }
+}
+
+impl std::error::Error for RegisterError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Self::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+///Registers a child client with the server by sending `core1.client-make` and waiting for the
+///`core1.client-new` reply. This is what a shell does before spawning a child process, so that
+///the child can connect to the terminal with the returned secret.
+///
+///Like [probe_capabilities()](fn.probe_capabilities.html), this must be called while no other
+///replies are outstanding, since any message other than the expected reply is reported as an
+///error.
+///
+///```no_run
+///# fn main() -> Result<(), Box<dyn std::error::Error>> {
+///# use vt6::common::core::ClientID;
+///let conn = vt6::client::Connection::connect("/run/user/1000/vt6/1234")?;
+///let mut conn = conn.client_hello("secret")?;
+///let child_id = format!("{}1", conn.client_id());
+///let child = vt6::client::ChildClient::new(ClientID::parse(&child_id).unwrap());
+///let creds = vt6::client::register_child(&mut conn, &child)?;
+///println!("child secret is {}", creds.secret());
+///# Ok(())
+///# }
+///```
+pub fn register_child(
+    conn: &mut Connection<Msgio>,
+    child: &ChildClient<'_>,
+) -> Result<ClientCredentials, RegisterError> {
+    conn.send_message(&ClientMake {
+        client_id: child.client_id,
+        stdin_screen_id: child.stdin_screen_id,
+        stdout_screen_id: child.stdout_screen_id,
+        stderr_screen_id: child.stderr_screen_id,
+    })?;
+
+    let msg = match conn.recv_message()? {
+        Some(msg) => msg,
+        None => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+    };
+    if let Some(ClientNew { secret }) = ClientNew::decode_message(&msg) {
+        return Ok(ClientCredentials {
+            secret: secret.into(),
+        });
+    }
+    match Nope::decode_message(&msg) {
+        Some(Nope(mt)) if mt.as_str() == "core1.client-make" => Err(RegisterError::Rejected),
+        _ => Err(RegisterError::UnexpectedReply(
+            msg.display_redacted().to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    fn connect() -> (Connection<Msgio>, UnixStream) {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        server
+            .write_all(b"{5|19:posix1.server-hello,3:foo,1:1,0:,1:1,}")
+            .unwrap();
+        let conn = Connection::from_stream(client).client_hello("abc").unwrap();
+        let mut buf = [0u8; 33];
+        server.read_exact(&mut buf).unwrap();
+        (conn, server)
+    }
+
+    #[test]
+    fn test_register_child() {
+        let (mut conn, mut server) = connect();
+        let screen = ScreenID::parse("screen1").unwrap();
+        let child = ChildClient::new(ClientID::parse("foo1").unwrap())
+            .with_stdin(screen)
+            .with_stderr(screen);
+
+        server
+            .write_all(b"{2|16:core1.client-new,6:s3cr3t,}")
+            .unwrap();
+        let creds = register_child(&mut conn, &child).unwrap();
+        assert_eq!(creds.secret(), "s3cr3t");
+        assert_eq!(
+            format!("{:?}", creds),
+            "ClientCredentials { secret: <redacted> }"
+        );
+        let expected = b"{5|17:core1.client-make,4:foo1,7:screen1,0:,7:screen1,}";
+        let mut buf = [0u8; 55];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &expected[..]);
+
+        //refusal is reported as a typed error
+        server
+            .write_all(b"{2|4:nope,17:core1.client-make,}")
+            .unwrap();
+        assert!(matches!(
+            register_child(&mut conn, &child),
+            Err(RegisterError::Rejected)
+        ));
+        server.read_exact(&mut buf).unwrap();
+
+        server.write_all(b"{2|4:nope,9:core1.sub,}").unwrap();
+        match register_child(&mut conn, &child) {
+            Err(RegisterError::UnexpectedReply(s)) => assert_eq!(s, "(nope core1.sub)"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        server.read_exact(&mut buf).unwrap();
+
+        std::mem::drop(server);
+        assert!(matches!(
+            register_child(&mut conn, &child),
+            Err(RegisterError::Io(_))
+        ));
+    }
+}
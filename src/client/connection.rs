@@ -0,0 +1,865 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+#[cfg(feature = "use_tokio")]
+use crate::client::{AsyncMessageReceiver, AsyncMessageSender, AsyncStdinReceiver};
+use crate::client::{
+    Capabilities, DisconnectReason, DisconnectWatcher, HandlerError, MessageHandler, ModuleError,
+    Negotiation,
+};
+use crate::common::core::msg::DecodeMessage;
+use crate::common::core::{msg, ClientID, OwnedClientID, OwnedScreenID, ScreenID};
+use crate::common::io::FixedBuffer;
+use crate::common::{PaddedStdinDecoder, SendQueue};
+use crate::msg::posix::{ClientHello, PaddedStdinHello, ServerHello, StdinHello, StdoutHello};
+use core::fmt;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+
+///Marker trait for the type parameter of [struct Connection](struct.Connection.html).
+///
+///This is the client-side counterpart of
+///[`vt6::server::ConnectionState`](../server/enum.ConnectionState.html). While the server has to
+///decide at runtime which state a socket is in, the client always knows which handshake it
+///performed, so the state is tracked in the type system instead. This trait is sealed: The only
+///implementors are [Handshaking](struct.Handshaking.html), [Msgio](struct.Msgio.html),
+///[Stdin](struct.Stdin.html), [PaddedStdin](struct.PaddedStdin.html) and
+///[Stdout](struct.Stdout.html).
+pub trait ConnectionState: sealed::Sealed {
+    ///Returns the name of the state, e.g. "Msgio" for `Connection<Msgio>`. This function is useful
+    ///for formatting error messages.
+    fn type_name() -> &'static str;
+}
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for super::Handshaking {}
+    impl Sealed for super::Msgio {}
+    impl Sealed for super::Stdin {}
+    impl Sealed for super::PaddedStdin {}
+    impl Sealed for super::Stdout {}
+}
+
+///Connection state: The socket has just been opened and no handshake has been performed yet.
+#[derive(Debug)]
+pub struct Handshaking;
+
+///Connection state: The socket is in msgio mode because of a successful client-hello handshake.
+///Contains the information that the server sent in its server-hello reply.
+#[derive(Debug)]
+pub struct Msgio {
+    client_id: OwnedClientID,
+    stdin_screen_id: Option<OwnedScreenID>,
+    stdout_screen_id: Option<OwnedScreenID>,
+    stderr_screen_id: Option<OwnedScreenID>,
+    negotiation: Negotiation,
+}
+
+///Connection state: The socket is in stdin mode because of a stdin-hello handshake. The server
+///sends the standard input of the respective screen on this socket.
+#[derive(Debug)]
+pub struct Stdin;
+
+///Connection state: The socket is in padded stdin mode because of a padded-stdin-hello handshake.
+///Like in stdin mode, the server sends the standard input of the respective screen on this
+///socket, but it may add padding, which is removed again when reading from the connection.
+#[derive(Debug)]
+pub struct PaddedStdin {
+    decoder: PaddedStdinDecoder,
+}
+
+///Connection state: The socket is in stdout mode because of a stdout-hello handshake. Everything
+///written into this socket is shown on the respective screen.
+#[derive(Debug)]
+pub struct Stdout;
+
+impl ConnectionState for Handshaking {
+    fn type_name() -> &'static str {
+        "Handshaking"
+    }
+}
+
+impl ConnectionState for Msgio {
+    fn type_name() -> &'static str {
+        "Msgio"
+    }
+}
+
+impl ConnectionState for Stdin {
+    fn type_name() -> &'static str {
+        "Stdin"
+    }
+}
+
+impl ConnectionState for PaddedStdin {
+    fn type_name() -> &'static str {
+        "PaddedStdin"
+    }
+}
+
+impl ConnectionState for Stdout {
+    fn type_name() -> &'static str {
+        "Stdout"
+    }
+}
+
+///A client's connection to the server socket of a VT6 terminal.
+///
+///The type argument tracks which handshake has been performed on this connection. A fresh
+///connection is a `Connection<Handshaking>`, and the handshake methods consume it to produce a
+///connection in the respective state. Since messages can only be exchanged on msgio sockets, and
+///standard input and output can only be exchanged on stdin and stdout sockets, trying to use a
+///socket before the handshake is complete does not compile:
+///
+///```compile_fail
+///# fn main() -> std::io::Result<()> {
+///use vt6::client::Connection;
+///let mut conn = Connection::connect("/run/user/1000/vt6/1234")?;
+///conn.send_message(&vt6::msg::Want(vt6::common::core::ModuleIdentifier::parse("core1").unwrap()))?;
+///# Ok(())
+///# }
+///```
+///
+///Instead, the handshake has to come first:
+///
+///```no_run
+///# fn main() -> Result<(), Box<dyn std::error::Error>> {
+///use vt6::client::Connection;
+///let conn = Connection::connect("/run/user/1000/vt6/1234")?;
+///let mut conn = conn.client_hello("secret")?;
+///conn.send_message(&vt6::msg::Want(vt6::common::core::ModuleIdentifier::parse("core1").unwrap()))?;
+///# Ok(())
+///# }
+///```
+pub struct Connection<S: ConnectionState> {
+    stream: UnixStream,
+    rx: RecvBuffer,
+    tx: SendQueue,
+    state: S,
+    disconnect: DisconnectWatcher,
+}
+
+//When this many bytes are waiting in the send queue, queue_message() flushes automatically.
+const AUTO_FLUSH_THRESHOLD: usize = 4096;
+
+impl<S: ConnectionState> fmt::Debug for Connection<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Connection<{}>({:?})", S::type_name(), self.stream)
+    }
+}
+
+impl<S: ConnectionState> Connection<S> {
+    ///Returns a reference to the underlying socket, e.g. for polling it in an event loop.
+    pub fn stream(&self) -> &UnixStream {
+        &self.stream
+    }
+
+    ///Registers a callback that is called once the connection to the server has ended, so that
+    ///interactive programs can degrade gracefully (e.g. stop trying to set the window title)
+    ///instead of reacting to the IO errors from each individual operation.
+    ///
+    ///The disconnect is noticed during IO on this connection: when a read reports EOF, or when a
+    ///read or write fails with an error that indicates a broken connection. The callback is
+    ///called at most once. If the disconnect has already been noticed, it is called immediately.
+    ///A callback registered before the handshake is carried over into the resulting connection,
+    ///and into the receiver returned by `into_async()`. Registering another callback replaces the
+    ///previous one.
+    ///
+    ///```no_run
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///let conn = vt6::client::Connection::connect("/run/user/1000/vt6/1234")?;
+    ///let mut conn = conn.client_hello("secret")?;
+    ///conn.on_disconnect(|reason| {
+    ///    if !reason.is_clean() {
+    ///        eprintln!("lost connection to terminal: {}", reason);
+    ///    }
+    ///});
+    ///# Ok(())
+    ///# }
+    ///```
+    pub fn on_disconnect<F: FnMut(DisconnectReason) + Send + 'static>(&mut self, callback: F) {
+        self.disconnect.set_callback(Box::new(callback));
+    }
+
+    ///Returns why the connection to the server has ended, or `None` if no disconnect has been
+    ///noticed so far. See [on_disconnect()](#method.on_disconnect) for details.
+    pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+        self.disconnect.reason()
+    }
+
+    fn into_state<T: ConnectionState>(self, state: T) -> Connection<T> {
+        let (stream, rx, tx, disconnect) = self.into_parts();
+        Connection {
+            stream,
+            rx,
+            tx,
+            state,
+            disconnect,
+        }
+    }
+
+    //Since Connection implements Drop, its fields cannot be moved out directly.
+    fn into_parts(self) -> (UnixStream, RecvBuffer, SendQueue, DisconnectWatcher) {
+        let mut this = std::mem::ManuallyDrop::new(self);
+        //SAFETY: `this` is never dropped, and each of its fields is either dropped in place or
+        //moved out exactly once
+        unsafe {
+            std::ptr::drop_in_place(&mut this.state);
+            (
+                std::ptr::read(&this.stream),
+                std::ptr::read(&this.rx),
+                std::ptr::read(&this.tx),
+                std::ptr::read(&this.disconnect),
+            )
+        }
+    }
+
+    #[cfg(feature = "use_tokio")]
+    fn into_tokio_stream(self) -> io::Result<(tokio::net::UnixStream, Vec<u8>, DisconnectWatcher)> {
+        //the send queue is empty here: only msgio connections use it, and they flush it first
+        let (stream, mut rx, _, disconnect) = self.into_parts();
+        rx.buf.discard(rx.consumed);
+        let received = rx.buf.filled().to_vec();
+        stream.set_nonblocking(true)?;
+        let stream = tokio::net::UnixStream::from_std(stream)?;
+        Ok((stream, received, disconnect))
+    }
+
+    fn write_message<M: msg::EncodeMessage>(&mut self, msg: &M) -> io::Result<()> {
+        let mut buf = [0u8; 1024];
+        let len = msg
+            .encode(&mut buf)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too long"))?;
+        self.stream.write_all(&buf[0..len])
+    }
+}
+
+impl<S: ConnectionState> Drop for Connection<S> {
+    fn drop(&mut self) {
+        //Best effort: a client that exits right after queue_message() should not lose those
+        //messages. Errors are ignored since there is nobody left to report them to.
+        while let Some(buf) = self.tx.pop() {
+            if self.stream.write_all(buf.filled()).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+impl Connection<Handshaking> {
+    ///Connects to the server socket at the given path. The path is usually obtained from
+    ///[`EnvironmentRef::server_socket_path()`](struct.EnvironmentRef.html#method.server_socket_path).
+    pub fn connect<P: AsRef<std::path::Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::from_stream(UnixStream::connect(path)?))
+    }
+
+    ///Wraps a socket that is already connected to the server socket, but where no handshake has
+    ///been performed yet.
+    pub fn from_stream(stream: UnixStream) -> Self {
+        Self {
+            stream,
+            rx: RecvBuffer::new(),
+            tx: SendQueue::default(),
+            state: Handshaking,
+            disconnect: DisconnectWatcher::default(),
+        }
+    }
+
+    ///Performs a client-hello handshake to put this socket into msgio mode. The `secret` is
+    ///usually obtained from
+    ///[`EnvironmentRef::client_secret()`](struct.EnvironmentRef.html#method.client_secret).
+    ///
+    ///This blocks until the server has answered with a server-hello message.
+    pub fn client_hello(mut self, secret: &str) -> Result<Connection<Msgio>, HandshakeError> {
+        self.write_message(&ClientHello { secret })?;
+        let state = {
+            let msg = match self.rx.recv_message(&mut self.stream)? {
+                Some(msg) => msg,
+                //the server closes the socket when it does not accept the handshake
+                None => return Err(HandshakeError::Rejected),
+            };
+            match ServerHello::decode_message(&msg) {
+                Some(hello) => Msgio {
+                    client_id: OwnedClientID::from(&hello.client_id),
+                    stdin_screen_id: hello.stdin_screen_id.as_ref().map(OwnedScreenID::from),
+                    stdout_screen_id: hello.stdout_screen_id.as_ref().map(OwnedScreenID::from),
+                    stderr_screen_id: hello.stderr_screen_id.as_ref().map(OwnedScreenID::from),
+                    negotiation: Negotiation::default(),
+                },
+                None => return Err(HandshakeError::UnexpectedReply(msg.to_string())),
+            }
+        };
+        Ok(self.into_state(state))
+    }
+
+    ///Performs a stdin-hello handshake to put this socket into stdin mode.
+    ///
+    ///The server does not answer this handshake. If the secret is not accepted, the server will
+    ///close the socket, so the first read on the resulting connection will report EOF.
+    pub fn stdin_hello(mut self, secret: &str) -> io::Result<Connection<Stdin>> {
+        self.write_message(&StdinHello { secret })?;
+        Ok(self.into_state(Stdin))
+    }
+
+    ///Performs a padded-stdin-hello handshake to put this socket into padded stdin mode. Use this
+    ///instead of [stdin_hello()](#method.stdin_hello) to allow the server to hide how much
+    ///standard input it sends at once, e.g. while the user is entering a password.
+    ///
+    ///The server does not answer this handshake. If the secret is not accepted, the server will
+    ///close the socket, so the first read on the resulting connection will report EOF.
+    ///
+    ///Unlike `Connection<Stdin>`, the resulting connection cannot be converted for use with Tokio,
+    ///since the padding has to be removed while reading.
+    pub fn padded_stdin_hello(mut self, secret: &str) -> io::Result<Connection<PaddedStdin>> {
+        self.write_message(&PaddedStdinHello { secret })?;
+        Ok(self.into_state(PaddedStdin {
+            decoder: PaddedStdinDecoder::new(),
+        }))
+    }
+
+    ///Performs a stdout-hello handshake to put this socket into stdout mode.
+    ///
+    ///The server does not answer this handshake. If the secret is not accepted, the server will
+    ///close the socket, so subsequent writes on the resulting connection will fail.
+    pub fn stdout_hello(mut self, secret: &str) -> io::Result<Connection<Stdout>> {
+        self.write_message(&StdoutHello { secret })?;
+        Ok(self.into_state(Stdout))
+    }
+}
+
+impl Connection<Msgio> {
+    ///Returns this client's ID, as reported by the server during the handshake.
+    pub fn client_id(&self) -> ClientID<'_> {
+        self.state.client_id.as_ref()
+    }
+
+    ///Returns the ID of the screen that this client's stdin is connected to, if any.
+    pub fn stdin_screen_id(&self) -> Option<ScreenID<'_>> {
+        self.state.stdin_screen_id.as_ref().map(|s| s.as_ref())
+    }
+
+    ///Returns the ID of the screen that this client's stdout is connected to, if any.
+    pub fn stdout_screen_id(&self) -> Option<ScreenID<'_>> {
+        self.state.stdout_screen_id.as_ref().map(|s| s.as_ref())
+    }
+
+    ///Returns the ID of the screen that this client's stderr is connected to, if any.
+    pub fn stderr_screen_id(&self) -> Option<ScreenID<'_>> {
+        self.state.stderr_screen_id.as_ref().map(|s| s.as_ref())
+    }
+
+    ///Returns a snapshot of the capabilities that were negotiated through
+    ///[probe_capabilities()](fn.probe_capabilities.html) on this connection so far, if any. This
+    ///is a shorthand for `self.negotiation().enabled_modules()`.
+    pub fn capabilities(&self) -> Option<Arc<Capabilities>> {
+        self.state.negotiation.enabled_modules()
+    }
+
+    ///Returns the negotiation state of this connection. It can be cloned and handed to other
+    ///threads, so that they can check which modules are available without access to the
+    ///connection.
+    pub fn negotiation(&self) -> &Negotiation {
+        &self.state.negotiation
+    }
+
+    ///Returns the major and minor version of the given module that was agreed on with the server
+    ///during [probe_capabilities()](fn.probe_capabilities.html), or a descriptive error if the
+    ///server does not support the module or if it was not probed.
+    ///
+    ///```no_run
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///# let conn = vt6::client::Connection::connect("/run/user/1000/vt6/1234")?;
+    ///# let mut conn = conn.client_hello("secret")?;
+    ///vt6::client::probe_capabilities(&mut conn, &[("term", 1..=1)])?;
+    ///if let Err(e) = conn.require_module("term") {
+    ///    eprintln!("cannot continue: {}", e); //e.g. "terminal does not support module term1"
+    ///}
+    ///# Ok(())
+    ///# }
+    ///```
+    pub fn require_module(&self, name: &str) -> Result<(u16, u16), ModuleError> {
+        self.state.negotiation.require_module(name)
+    }
+
+    ///Sends a message to the server. Messages that were queued with `queue_message()` are sent
+    ///first. This is a shorthand for `queue_message()` followed by `flush()`.
+    pub fn send_message<M: msg::EncodeMessage>(&mut self, msg: &M) -> io::Result<()> {
+        self.queue_message(msg)?;
+        self.flush()
+    }
+
+    ///Puts a message into the send queue without sending it yet. Small messages are coalesced, so
+    ///that a batch of messages queued with this method can be sent with a single write when
+    ///`flush()` is called. Messages are always sent in the order in which they were queued.
+    ///
+    ///To limit memory usage, the send queue is flushed automatically once a few KiB of messages
+    ///have accumulated. The queue is also flushed at the start of `recv_message()`, since the
+    ///server would otherwise never get to answer the queued messages. Messages that are still
+    ///queued when the connection is dropped are sent on a best-effort basis: Dropping blocks until
+    ///they have been written, and errors are ignored. Call `flush()` to find out whether they were
+    ///sent.
+    ///
+    ///```no_run
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///use vt6::common::core::ModuleIdentifier;
+    ///let conn = vt6::client::Connection::connect("/run/user/1000/vt6/1234")?;
+    ///let mut conn = conn.client_hello("secret")?;
+    ///for module in &["core1", "term1", "sig1"] {
+    ///    conn.queue_message(&vt6::msg::Want(ModuleIdentifier::parse(module).unwrap()))?;
+    ///}
+    ///conn.flush()?;
+    ///# Ok(())
+    ///# }
+    ///```
+    pub fn queue_message<M: msg::EncodeMessage>(&mut self, msg: &M) -> io::Result<()> {
+        self.tx
+            .push_message(msg)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too long"))?;
+        if self.tx.filled_len() >= AUTO_FLUSH_THRESHOLD {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    ///Sends all messages in the send queue to the server. Blocks until all of them have been
+    ///written into the socket.
+    ///
+    ///If an error occurs, some of the queued messages may have been sent partially or not at all.
+    ///Since the connection is usually unusable at that point, those messages are discarded.
+    pub fn flush(&mut self) -> io::Result<()> {
+        while let Some(buf) = self.tx.pop() {
+            let result = self.stream.write_all(buf.filled());
+            self.tx.recycle(buf);
+            self.disconnect.check(result)?;
+        }
+        Ok(())
+    }
+
+    ///Returns how many bytes of messages are waiting in the send queue.
+    pub fn queued_len(&self) -> usize {
+        self.tx.filled_len()
+    }
+
+    ///Flushes the send queue, then blocks until the next message from the server has been
+    ///received. Returns `Ok(None)` when the server has closed the connection. (See
+    ///[on_disconnect()](#method.on_disconnect) for how to be notified about that without checking
+    ///each result.)
+    ///
+    ///The returned message borrows from this connection's receive buffer, and will be discarded
+    ///from it at the start of the next `recv_message()` call. When the server sends something
+    ///that is not a valid message, the invalid input is discarded and an error of kind
+    ///`InvalidData` is returned. Callers can just call `recv_message()` again to continue with
+    ///the next message.
+    pub fn recv_message(&mut self) -> io::Result<Option<msg::Message<'_>>> {
+        self.flush()?;
+        let result = self
+            .disconnect
+            .check(self.rx.recv_message(&mut self.stream));
+        if let Ok(None) = result {
+            self.disconnect.report(DisconnectReason::Closed);
+        }
+        result
+    }
+
+    ///Like `recv_message()`, but instead of returning the message, passes it to the given chain
+    ///of [message handlers](trait.MessageHandler.html). Returns `Ok(false)` when the server has
+    ///closed the connection, and `Ok(true)` when a message was handled. Errors from the handlers
+    ///are passed through, but the connection remains usable after them.
+    ///
+    ///This is useful for clients that need to react to messages which the server sends on its
+    ///own accord: Instead of matching on the message types after each `recv_message()`, they can
+    ///just call this method in a loop.
+    pub fn handle_next_message<H: MessageHandler>(&mut self) -> Result<bool, HandlerError> {
+        //the handler needs access to the connection, so the message cannot stay in the receive
+        //buffer while it is being handled
+        let mut buf = [0u8; 1024];
+        let len = match self.recv_message()? {
+            Some(_) => self.rx.consumed,
+            None => return Ok(false),
+        };
+        buf[0..len].copy_from_slice(&self.rx.buf.filled()[0..len]);
+        let (msg, _) =
+            msg::Message::parse(&buf[0..len]).expect("message failed to parse on second attempt");
+        H::default().handle(&msg, self)?;
+        Ok(true)
+    }
+
+    #[cfg(feature = "use_tokio")]
+    ///Converts this connection for use with the [Tokio library](https://tokio.rs/). This is only
+    ///available with the `use_tokio` feature, and must be called from within a Tokio runtime.
+    ///
+    ///The send queue is flushed first. Messages that have already been received, but not been
+    ///returned by `recv_message()` yet, are carried over into the receiver.
+    pub fn into_async(
+        mut self,
+    ) -> io::Result<(
+        AsyncMessageReceiver<tokio::net::unix::OwnedReadHalf>,
+        AsyncMessageSender<tokio::net::unix::OwnedWriteHalf>,
+    )> {
+        self.flush()?;
+        let (stream, received, disconnect) = self.into_tokio_stream()?;
+        let (reader, writer) = stream.into_split();
+        Ok((
+            AsyncMessageReceiver::with_received(reader, &received, disconnect),
+            AsyncMessageSender::new(writer),
+        ))
+    }
+}
+
+impl Connection<Stdin> {
+    #[cfg(feature = "use_tokio")]
+    ///Converts this connection for use with the [Tokio library](https://tokio.rs/). This is only
+    ///available with the `use_tokio` feature, and must be called from within a Tokio runtime.
+    pub fn into_async(self) -> io::Result<AsyncStdinReceiver<tokio::net::UnixStream>> {
+        let (stream, received, disconnect) = self.into_tokio_stream()?;
+        Ok(AsyncStdinReceiver::with_received(
+            stream, &received, disconnect,
+        ))
+    }
+}
+
+impl Read for Connection<Stdin> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        //if the server sent data immediately after the handshake, it might already be in our
+        //receive buffer
+        if self.rx.buf.filled_len() > 0 {
+            let len = std::cmp::min(buf.len(), self.rx.buf.filled_len());
+            buf[0..len].copy_from_slice(&self.rx.buf.filled()[0..len]);
+            self.rx.buf.discard(len);
+            return Ok(len);
+        }
+        let result = self.disconnect.check(self.stream.read(buf));
+        if let Ok(0) = result {
+            if !buf.is_empty() {
+                self.disconnect.report(DisconnectReason::Closed);
+            }
+        }
+        result
+    }
+}
+
+impl Read for Connection<PaddedStdin> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        //a read might yield nothing but chunk headers and padding, so keep reading until there is
+        //some actual data
+        loop {
+            let len = if self.rx.buf.filled_len() > 0 {
+                let len = std::cmp::min(buf.len(), self.rx.buf.filled_len());
+                buf[0..len].copy_from_slice(&self.rx.buf.filled()[0..len]);
+                self.rx.buf.discard(len);
+                len
+            } else {
+                let len = self.disconnect.check(self.stream.read(buf))?;
+                if len == 0 {
+                    self.disconnect.report(DisconnectReason::Closed);
+                    if !self.state.decoder.is_at_chunk_boundary() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "connection closed in the middle of a padded chunk",
+                        ));
+                    }
+                    return Ok(0);
+                }
+                len
+            };
+            let data_len = self.state.decoder.decode_in_place(&mut buf[0..len]);
+            if data_len > 0 {
+                return Ok(data_len);
+            }
+        }
+    }
+}
+
+impl Write for Connection<Stdout> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.disconnect.check(self.stream.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.disconnect.check(self.stream.flush())
+    }
+}
+
+///Error type returned by
+///[`Connection::client_hello()`](struct.Connection.html#method.client_hello).
+#[derive(Debug)]
+pub enum HandshakeError {
+    ///An IO error occurred on the socket.
+    Io(io::Error),
+    ///The server closed the connection instead of answering the handshake. This usually means
+    ///that the secret was not accepted.
+    Rejected,
+    ///The server answered with something other than a server-hello message. The human-readable
+    ///representation of the reply is included.
+    UnexpectedReply(String),
+}
+
+impl From<io::Error> for HandshakeError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Io(ref e) => write!(f, "IO error during handshake: {}", e),
+            Self::Rejected => write!(f, "handshake rejected by server"),
+            Self::UnexpectedReply(ref s) => write!(f, "expected server-hello, got {}", s),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Self::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// struct RecvBuffer
+
+//Receive buffer for messages. Since messages are never longer than 1024 bytes
+//[vt6/foundation, sect. 3.1.2], a fixed-size buffer suffices.
+struct RecvBuffer {
+    buf: FixedBuffer<1024>,
+    //How many bytes at the start of `self.buf` belong to the message that was last returned from
+    //recv_message(). These are discarded at the start of the next recv_message().
+    consumed: usize,
+}
+
+impl RecvBuffer {
+    fn new() -> Self {
+        Self {
+            buf: FixedBuffer::new(),
+            consumed: 0,
+        }
+    }
+
+    fn recv_message<R: Read>(&mut self, reader: &mut R) -> io::Result<Option<msg::Message<'_>>> {
+        self.buf.discard(self.consumed);
+        self.consumed = 0;
+
+        //read until we have a full message
+        //
+        //NOTE: We only parse here to find the message length. The message itself is parsed again
+        //below since we cannot return a reference into `self.buf` from within this loop.
+        let msg_len = loop {
+            match msg::Message::parse(self.buf.filled()) {
+                Ok((_, len)) => break len,
+                Err(e) if e.is_incomplete() => {
+                    if self.buf.is_full() {
+                        //a message cannot be longer than the buffer, so this is not a message
+                        self.buf.clear();
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "discarded overlong message",
+                        ));
+                    }
+                    let bytes_read = reader.read(self.buf.unfilled_mut())?;
+                    if bytes_read == 0 {
+                        return Ok(None);
+                    }
+                    self.buf.fill(bytes_read);
+                }
+                Err(e) => {
+                    //After a parse error, recover by skipping ahead to the next possible start of
+                    //a message, i.e. the next `{` sign. [vt6/foundation, sect. 3.3]
+                    let err = io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+                    let bytes_to_discard = e.resync_offset;
+                    self.buf.discard(bytes_to_discard);
+                    return Err(err);
+                }
+            }
+        };
+
+        self.consumed = msg_len;
+        let (msg, _) = msg::Message::parse(&self.buf.filled()[0..msg_len])
+            .expect("message failed to parse on second attempt");
+        Ok(Some(msg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_hello() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let conn = Connection::from_stream(client);
+
+        //answer the handshake, and also send a second message directly behind it to check that
+        //it does not get lost between the state transitions
+        server
+            .write_all(b"{5|19:posix1.server-hello,3:foo,1:1,0:,1:1,}{2|4:want,5:core1,}")
+            .unwrap();
+        let mut conn = conn.client_hello("abc").unwrap();
+        assert_eq!(conn.client_id().as_str(), "foo");
+        assert_eq!(conn.stdin_screen_id(), ScreenID::parse("1"));
+        assert_eq!(conn.stdout_screen_id(), None);
+        assert_eq!(conn.stderr_screen_id(), ScreenID::parse("1"));
+
+        let msg = conn.recv_message().unwrap().unwrap();
+        assert_eq!(format!("{}", msg), "(want core1)");
+
+        let mut buf = [0u8; 33];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"{2|19:posix1.client-hello,3:abc,}");
+
+        //garbage is reported and skipped
+        server.write_all(b"garbage{2|4:want,5:core1,}").unwrap();
+        let err = conn.recv_message().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let msg = conn.recv_message().unwrap().unwrap();
+        assert_eq!(format!("{}", msg), "(want core1)");
+
+        //the disconnect is reported once through the callback
+        let (tx, rx) = std::sync::mpsc::channel();
+        conn.on_disconnect(move |reason| tx.send(reason).unwrap());
+        assert_eq!(conn.disconnect_reason(), None);
+        std::mem::drop(server);
+        assert!(conn.recv_message().unwrap().is_none());
+        assert!(conn.recv_message().unwrap().is_none());
+        assert_eq!(conn.disconnect_reason(), Some(DisconnectReason::Closed));
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            vec![DisconnectReason::Closed]
+        );
+    }
+
+    #[test]
+    fn test_client_hello_rejected() {
+        let (client, server) = UnixStream::pair().unwrap();
+        std::mem::drop(server);
+        let conn = Connection::from_stream(client);
+        //writing the client-hello might already fail because of the closed socket
+        assert!(matches!(
+            conn.client_hello("abc"),
+            Err(HandshakeError::Rejected) | Err(HandshakeError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_padded_stdin() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = Connection::from_stream(client)
+            .padded_stdin_hello("abc")
+            .unwrap();
+        let mut buf = [0u8; 39];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"{2|25:posix1.padded-stdin-hello,3:abc,}");
+
+        //chunks that only contain padding do not produce empty reads
+        server
+            .write_all(b"\x00\x00\x00\x04\0\0\0\0\x00\x03\x00\x02abc\0\0")
+            .unwrap();
+        let mut buf = [0u8; 64];
+        let len = conn.read(&mut buf).unwrap();
+        assert_eq!(&buf[0..len], b"abc");
+
+        //a chunk that is cut off by the disconnect is reported as an error
+        server.write_all(b"\x00\x05\x00\x00de").unwrap();
+        std::mem::drop(server);
+        let len = conn.read(&mut buf).unwrap();
+        assert_eq!(&buf[0..len], b"de");
+        let err = conn.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(conn.disconnect_reason(), Some(DisconnectReason::Closed));
+    }
+
+    #[test]
+    fn test_send_queue() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        server
+            .write_all(b"{5|19:posix1.server-hello,3:foo,1:1,0:,1:1,}")
+            .unwrap();
+        let mut conn = Connection::from_stream(client).client_hello("abc").unwrap();
+        let mut buf = [0u8; 33];
+        server.read_exact(&mut buf).unwrap();
+
+        //queued messages are not sent before flush()
+        let want =
+            |name| crate::msg::Want(crate::common::core::ModuleIdentifier::parse(name).unwrap());
+        conn.queue_message(&want("core1")).unwrap();
+        conn.queue_message(&want("term1")).unwrap();
+        assert_eq!(conn.queued_len(), 38);
+        server.set_nonblocking(true).unwrap();
+        assert_eq!(
+            server.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        server.set_nonblocking(false).unwrap();
+
+        //send_message() sends the queued messages first
+        conn.send_message(&want("sig1")).unwrap();
+        assert_eq!(conn.queued_len(), 0);
+        let mut buf = [0u8; 56];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..],
+            &b"{2|4:want,5:core1,}{2|4:want,5:term1,}{2|4:want,4:sig1,}"[..]
+        );
+
+        //recv_message() flushes the queue before waiting for a reply
+        conn.queue_message(&want("core1")).unwrap();
+        server.write_all(b"{2|4:have,7:core1.0,}").unwrap();
+        let msg = conn.recv_message().unwrap().unwrap();
+        assert_eq!(format!("{}", msg), "(have core1.0)");
+        let mut buf = [0u8; 19];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"{2|4:want,5:core1,}");
+
+        //large batches are flushed automatically
+        for _ in 0..1000 {
+            conn.queue_message(&want("core1")).unwrap();
+        }
+        assert!(conn.queued_len() < AUTO_FLUSH_THRESHOLD);
+
+        //the rest is sent when the connection is dropped
+        assert!(conn.queued_len() > 0);
+        std::mem::drop(conn);
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).unwrap();
+        assert_eq!(received.len(), 1000 * 19);
+    }
+
+    #[cfg(feature = "use_tokio")]
+    #[test]
+    fn test_into_async() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        server
+            .write_all(b"{5|19:posix1.server-hello,3:foo,0:,0:,0:,}{1|4:have,}{1|4:nope,}")
+            .unwrap();
+        let mut conn = Connection::from_stream(client).client_hello("abc").unwrap();
+        let msg = conn.recv_message().unwrap().unwrap();
+        assert_eq!(format!("{}", msg), "(have)");
+        let core1 = crate::common::core::ModuleIdentifier::parse("core1").unwrap();
+        conn.queue_message(&crate::msg::Want(core1)).unwrap();
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (mut receiver, _sender) = conn.into_async().unwrap();
+            //messages that were already received are not lost...
+            let buf = receiver.recv_message().await.unwrap().unwrap();
+            assert_eq!(&buf[..], b"{1|4:nope,}");
+            //...and neither are queued messages
+            let mut buf = [0u8; 33 + 19];
+            server.read_exact(&mut buf).unwrap();
+            assert!(buf.ends_with(b"{2|4:want,5:core1,}"));
+        });
+    }
+}
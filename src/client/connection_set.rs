@@ -0,0 +1,246 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::client::{Connection, HandshakeError, Msgio, Stdin, Stdout};
+use std::io;
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+///The sockets that a client holds to a single terminal: the msgio connection, and optionally a
+///stdin and a stdout connection. Built with
+///[`ConnectionSet::builder()`](#method.builder).
+///
+///All connections in the set are opened together, and they are also torn down together: When the
+///set is dropped (or [closed](#method.close)), all of its sockets are shut down, even if copies of
+///them have been made with `try_clone()`, e.g. for polling them in another thread.
+///
+///```no_run
+///# fn main() -> Result<(), Box<dyn std::error::Error>> {
+///use std::io::Write;
+///let env = vt6::client::Environment::discover()?;
+///let env = env.parse().map_err(|e| e.to_string())?;
+///let mut conns = vt6::client::ConnectionSet::builder(env.server_socket_path(), env.client_secret())
+///    .with_stdout("stdout-secret")
+///    .connect()?;
+///println!("connected as {}", conns.msgio().client_id());
+///if let Some(stdout) = conns.stdout_mut() {
+///    stdout.write_all(b"Hello World\n")?;
+///}
+///conns.close()?;
+///# Ok(())
+///# }
+///```
+#[derive(Debug)]
+pub struct ConnectionSet {
+    msgio: Connection<Msgio>,
+    stdin: Option<Connection<Stdin>>,
+    stdout: Option<Connection<Stdout>>,
+}
+
+impl ConnectionSet {
+    ///Starts building a connection set for the server socket at the given path. The `secret` is
+    ///used for the client-hello handshake on the msgio connection. Stdin and stdout connections
+    ///are only opened when their secrets are given to the builder.
+    pub fn builder<'a, P: AsRef<Path> + ?Sized>(
+        path: &'a P,
+        secret: &'a str,
+    ) -> ConnectionSetBuilder<'a> {
+        ConnectionSetBuilder {
+            path: path.as_ref(),
+            secret,
+            stdin_secret: None,
+            stdout_secret: None,
+        }
+    }
+
+    ///Returns the msgio connection.
+    pub fn msgio(&self) -> &Connection<Msgio> {
+        &self.msgio
+    }
+
+    pub fn msgio_mut(&mut self) -> &mut Connection<Msgio> {
+        &mut self.msgio
+    }
+
+    ///Returns the stdin connection, or `None` if no stdin connection was requested.
+    pub fn stdin_mut(&mut self) -> Option<&mut Connection<Stdin>> {
+        self.stdin.as_mut()
+    }
+
+    ///Returns the stdout connection, or `None` if no stdout connection was requested.
+    pub fn stdout_mut(&mut self) -> Option<&mut Connection<Stdout>> {
+        self.stdout.as_mut()
+    }
+
+    ///Shuts down all connections in this set. Unlike dropping the set, this reports errors. All
+    ///sockets are shut down even if an error occurs on one of them; the first error is returned.
+    pub fn close(self) -> io::Result<()> {
+        //the sockets are shut down again when `self` is dropped, but that is harmless
+        self.shutdown()
+    }
+
+    fn streams(&self) -> impl Iterator<Item = &UnixStream> {
+        let stdin = self.stdin.as_ref().map(|c| c.stream());
+        let stdout = self.stdout.as_ref().map(|c| c.stream());
+        std::iter::once(self.msgio.stream())
+            .chain(stdin)
+            .chain(stdout)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        let mut result = Ok(());
+        for stream in self.streams() {
+            match stream.shutdown(Shutdown::Both) {
+                //the server has already closed this socket
+                Err(ref e) if e.kind() == io::ErrorKind::NotConnected => {}
+                Err(e) if result.is_ok() => result = Err(e),
+                _ => {}
+            }
+        }
+        result
+    }
+}
+
+impl Drop for ConnectionSet {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+///Builder for a [ConnectionSet](struct.ConnectionSet.html).
+///
+///The secrets for the stdin and stdout connections are handed out by the server together with the
+///client secret (see [vt6/posix1.0, section 2.2](https://vt6.io/std/posix/1.0/#section-2-2)).
+#[derive(Clone, Debug)]
+pub struct ConnectionSetBuilder<'a> {
+    path: &'a Path,
+    secret: &'a str,
+    stdin_secret: Option<&'a str>,
+    stdout_secret: Option<&'a str>,
+}
+
+impl<'a> ConnectionSetBuilder<'a> {
+    ///Requests a stdin connection, which will be set up with a stdin-hello handshake using the
+    ///given secret.
+    pub fn with_stdin(mut self, secret: &'a str) -> Self {
+        self.stdin_secret = Some(secret);
+        self
+    }
+
+    ///Requests a stdout connection, which will be set up with a stdout-hello handshake using the
+    ///given secret.
+    pub fn with_stdout(mut self, secret: &'a str) -> Self {
+        self.stdout_secret = Some(secret);
+        self
+    }
+
+    ///Opens all requested connections and performs their handshakes. The msgio connection is set
+    ///up first, so that a rejected client secret is reported before any other socket is opened.
+    ///
+    ///Since the server does not answer stdin-hello and stdout-hello, a rejected stdin or stdout
+    ///secret is only noticed during the first IO on the respective connection (see
+    ///[`Connection::stdin_hello()`](struct.Connection.html#method.stdin_hello)). If any step
+    ///fails, the connections that have been opened so far are closed again.
+    pub fn connect(self) -> Result<ConnectionSet, HandshakeError> {
+        let msgio = Connection::connect(self.path)?.client_hello(self.secret)?;
+        let stdin = match self.stdin_secret {
+            Some(secret) => Some(Connection::connect(self.path)?.stdin_hello(secret)?),
+            None => None,
+        };
+        let stdout = match self.stdout_secret {
+            Some(secret) => Some(Connection::connect(self.path)?.stdout_hello(secret)?),
+            None => None,
+        };
+        Ok(ConnectionSet {
+            msgio,
+            stdin,
+            stdout,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+
+    fn read_n(stream: &mut UnixStream, n: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; n];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_connection_set() {
+        let dir =
+            std::env::temp_dir().join(format!("vt6-test-connection-set-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("socket");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (mut msgio, _) = listener.accept().unwrap();
+            let hello = b"{2|19:posix1.client-hello,3:abc,}";
+            assert_eq!(read_n(&mut msgio, hello.len()), hello);
+            msgio
+                .write_all(b"{5|19:posix1.server-hello,3:foo,1:1,1:1,0:,}")
+                .unwrap();
+
+            let (mut stdin, _) = listener.accept().unwrap();
+            let hello = b"{2|18:posix1.stdin-hello,3:def,}";
+            assert_eq!(read_n(&mut stdin, hello.len()), hello);
+            let (mut stdout, _) = listener.accept().unwrap();
+            let hello = b"{2|19:posix1.stdout-hello,3:ghi,}";
+            assert_eq!(read_n(&mut stdout, hello.len()), hello);
+
+            stdin.write_all(b"input").unwrap();
+            assert_eq!(read_n(&mut stdout, 6), b"output");
+            (msgio, stdin, stdout)
+        });
+
+        let mut conns = ConnectionSet::builder(&path, "abc")
+            .with_stdin("def")
+            .with_stdout("ghi")
+            .connect()
+            .unwrap();
+        assert_eq!(conns.msgio().client_id().as_str(), "foo");
+
+        let mut buf = [0u8; 5];
+        conns.stdin_mut().unwrap().read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"input");
+        conns.stdout_mut().unwrap().write_all(b"output").unwrap();
+        let (mut msgio, mut stdin, mut stdout) = server.join().unwrap();
+
+        //closing the set ends all connections at once, even for clones of the sockets
+        let clone = conns.msgio().stream().try_clone().unwrap();
+        conns.close().unwrap();
+        for stream in [&mut msgio, &mut stdin, &mut stdout] {
+            assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        }
+        assert!((&clone).write_all(b"x").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_connection_set_without_stdio() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        server
+            .write_all(b"{5|19:posix1.server-hello,3:foo,0:,0:,0:,}")
+            .unwrap();
+        let set = ConnectionSet {
+            msgio: Connection::from_stream(client).client_hello("abc").unwrap(),
+            stdin: None,
+            stdout: None,
+        };
+        assert_eq!(set.streams().count(), 1);
+        std::mem::drop(set);
+        let hello = b"{2|19:posix1.client-hello,3:abc,}";
+        assert_eq!(read_n(&mut server, hello.len()), hello);
+        assert_eq!(server.read(&mut [0u8; 8]).unwrap(), 0);
+    }
+}
@@ -5,6 +5,7 @@
 *******************************************************************************/
 
 use crate::common::core::{ClientID, EncodeArgument};
+use core::convert::TryFrom;
 
 ///An encoding helper for client IDs, as defined by
 ///[vt6/foundation, section 2.6](https://vt6.io/std/foundation/#section-2-6).
@@ -12,13 +13,43 @@ use crate::common::core::{ClientID, EncodeArgument};
 ///See documentation on [`ClientIDSuffix`](enum.ClientIDSuffix.html) for
 ///detailed explanation. Use
 ///[`ClientIDSuffix::below()`](enum.ClientIDSuffix.html#method.below) to
-///construct instances of this type.
+///construct instances of this type, or [`parse()`](#method.parse) to decode a
+///full client ID.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub struct RelativeClientID<'a> {
     base: ClientID<'a>,
     suffix: ClientIDSuffix,
 }
 
 impl<'a> RelativeClientID<'a> {
+    ///Decodes a full client ID that was generated by
+    ///[`ClientIDSuffix::below()`](enum.ClientIDSuffix.html#method.below) with
+    ///the given base. Returns None if `full` does not start with `base`, or if
+    ///the remainder is not a valid suffix encoding.
+    ///
+    ///Only one level of the hierarchy is decoded. For example, the ID of a
+    ///grandchild does not decode relative to the grandparent's ID; decode it
+    ///relative to its parent's ID instead.
+    ///
+    ///```
+    ///# use vt6::common::core::ClientID;
+    ///# use vt6::client::core::{ClientIDSuffix, RelativeClientID};
+    ///let base = ClientID::parse("foo").unwrap();
+    ///let full = ClientID::parse("foo1z2").unwrap();
+    ///let id = RelativeClientID::parse(full, base).unwrap();
+    ///assert_eq!(id.base().as_str(), "foo");
+    ///assert_eq!(id.suffix(), ClientIDSuffix::Child(0, 62));
+    ///assert!(RelativeClientID::parse(full, ClientID::parse("bar").unwrap()).is_none());
+    ///```
+    pub fn parse(full: ClientID<'a>, base: ClientID<'_>) -> Option<Self> {
+        let full = full.as_str();
+        let rest = full.strip_prefix(base.as_str())?;
+        let suffix = ClientIDSuffix::decode(rest.as_bytes())?;
+        //cannot fail: the prefix is a non-empty substring of a valid client ID
+        let base = ClientID::parse(&full[0..(full.len() - rest.len())])?;
+        Some(suffix.below(base))
+    }
+
     ///Returns the base client ID, i.e. the bytestring that was given to
     ///[`ClientIDSuffix::below()`](enum.ClientIDSuffix.html#method.below).
     pub fn base(&'_ self) -> ClientID<'a> {
@@ -90,6 +121,26 @@ impl ClientIDSuffix {
         }
     }
 
+    //This is the inverse of encode(), see there for the encoding scheme.
+    fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.is_empty() {
+            return Some(Own);
+        }
+        if buf[0] == LOOKUP_TABLE[0] {
+            return match decode_number(&buf[1..])? {
+                (i, []) => Some(Local(i)),
+                _ => None,
+            };
+        }
+        match decode_number(buf)? {
+            (i, []) => Some(Job(i)),
+            (i, rest) => match decode_number(rest)? {
+                (j, []) => Some(Child(i, j)),
+                _ => None,
+            },
+        }
+    }
+
     //This is an implementation of EncodeArgument, but we keep it private
     //because it's never useful to encode just a client ID suffix without the
     //base.
@@ -174,10 +225,27 @@ fn encode_number(num: u32, buf: &mut [u8]) {
     }
 }
 
+//Decodes one number from the start of the buffer, and returns it together with
+//the rest of the buffer.
+fn decode_number(buf: &[u8]) -> Option<(u32, &[u8])> {
+    let z_count = buf.iter().take_while(|b| **b == LOOKUP_TABLE[61]).count();
+    let last = *buf.get(z_count)?;
+    let index = LOOKUP_TABLE.iter().position(|b| *b == last)? as u32;
+    //"0" is not a valid codeword on its own (see get_size_for_number)
+    if z_count == 0 && index == 0 {
+        return None;
+    }
+    let num = u32::try_from(z_count)
+        .ok()?
+        .checked_mul(61)?
+        .checked_add(index)?;
+    Some((num - 1, &buf[(z_count + 1)..]))
+}
+
 #[cfg(test)]
 mod tests {
-    use super::ClientIDSuffix;
     use super::ClientIDSuffix::*;
+    use super::{ClientIDSuffix, RelativeClientID};
     use crate::common::core::{ClientID, EncodeArgument};
 
     #[test]
@@ -236,6 +304,18 @@ mod tests {
                 suffix,
                 encoded
             );
+
+            let full = ClientID::parse(expected).unwrap();
+            let decoded = RelativeClientID::parse(full, base);
+            assert_eq!(decoded.map(|id| id.suffix()), Some(suffix), "{}", expected);
+        }
+
+        //IDs that were not produced by ClientIDSuffix::below(base)
+        for input in &[
+            "fo", "bar1", "foo0", "foo00", "foo0z", "foo1z", "foo111", "foo01z12",
+        ] {
+            let full = ClientID::parse(input).unwrap();
+            assert_eq!(RelativeClientID::parse(full, base), None, "{}", input);
         }
     }
 }
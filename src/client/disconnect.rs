@@ -0,0 +1,141 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use std::fmt;
+use std::io;
+
+///Why the connection to the server has ended, as far as the client can tell. This is reported by
+///[`Connection::on_disconnect()`](struct.Connection.html#method.on_disconnect) and
+///[`Connection::disconnect_reason()`](struct.Connection.html#method.disconnect_reason).
+///
+///The socket does not tell us why the server went away, so this is only a best-effort guess:
+///A terminal that crashed while the client was not reading may still look like a clean shutdown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisconnectReason {
+    ///The server closed the connection in an orderly way, i.e. the client read EOF from the
+    ///socket. This usually means that the terminal has shut down, or that it has closed this
+    ///particular connection (e.g. because another client has taken over the screen).
+    Closed,
+    ///The connection broke down with an IO error of the given kind, e.g. `ConnectionReset` if the
+    ///server went away while data was in flight, or `BrokenPipe` if the client wrote into a
+    ///socket that the server had already closed.
+    Error(io::ErrorKind),
+}
+
+impl DisconnectReason {
+    ///Returns whether the server closed the connection in an orderly way.
+    pub fn is_clean(&self) -> bool {
+        *self == Self::Closed
+    }
+
+    //Returns the reason if the given error means that the connection is gone. Other errors (e.g.
+    //`InvalidData` for garbage from the server, or `TimedOut`) leave the connection usable.
+    fn from_io_error(e: &io::Error) -> Option<Self> {
+        use io::ErrorKind::*;
+        match e.kind() {
+            UnexpectedEof => Some(Self::Closed),
+            kind @ (BrokenPipe | ConnectionAborted | ConnectionReset | NotConnected) => {
+                Some(Self::Error(kind))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Closed => write!(f, "server closed the connection"),
+            Self::Error(kind) => write!(f, "connection to server lost: {}", io::Error::from(kind)),
+        }
+    }
+}
+
+//Tracks whether a connection has been disconnected, and calls the registered callback when that
+//happens. This is shared by all the client connection types, and carried over when a Connection
+//is converted into its async counterparts.
+#[derive(Default)]
+pub(crate) struct DisconnectWatcher {
+    reason: Option<DisconnectReason>,
+    callback: Option<Box<dyn FnMut(DisconnectReason) + Send>>,
+}
+
+impl fmt::Debug for DisconnectWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DisconnectWatcher")
+            .field("reason", &self.reason)
+            .field("has_callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+impl DisconnectWatcher {
+    pub(crate) fn reason(&self) -> Option<DisconnectReason> {
+        self.reason
+    }
+
+    pub(crate) fn set_callback(&mut self, mut callback: Box<dyn FnMut(DisconnectReason) + Send>) {
+        //when we're already disconnected, the callback would never be called otherwise
+        match self.reason {
+            Some(reason) => callback(reason),
+            None => self.callback = Some(callback),
+        }
+    }
+
+    //Records the disconnect. Only the first report counts, since the errors that follow it are
+    //usually just consequences of the first one.
+    pub(crate) fn report(&mut self, reason: DisconnectReason) {
+        if self.reason.is_none() {
+            self.reason = Some(reason);
+            if let Some(mut callback) = self.callback.take() {
+                callback(reason);
+            }
+        }
+    }
+
+    //Passes the given result through, but reports a disconnect if it contains an error that
+    //indicates one.
+    pub(crate) fn check<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        if let Err(ref e) = result {
+            if let Some(reason) = DisconnectReason::from_io_error(e) {
+                self.report(reason);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_disconnect_watcher() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let mut watcher = DisconnectWatcher::default();
+        let r = reports.clone();
+        watcher.set_callback(Box::new(move |reason| r.lock().unwrap().push(reason)));
+
+        //errors that leave the connection usable are not reported
+        let result: io::Result<()> = Err(io::ErrorKind::InvalidData.into());
+        assert!(watcher.check(result).is_err());
+        assert_eq!(watcher.reason(), None);
+
+        let result: io::Result<()> = Err(io::ErrorKind::ConnectionReset.into());
+        assert!(watcher.check(result).is_err());
+        watcher.report(DisconnectReason::Closed);
+        let expected = DisconnectReason::Error(io::ErrorKind::ConnectionReset);
+        assert_eq!(watcher.reason(), Some(expected));
+        assert_eq!(*reports.lock().unwrap(), vec![expected]);
+        assert!(!expected.is_clean());
+
+        //a callback registered after the fact is called immediately
+        let r = reports.clone();
+        watcher.set_callback(Box::new(move |reason| r.lock().unwrap().push(reason)));
+        assert_eq!(*reports.lock().unwrap(), vec![expected, expected]);
+    }
+}
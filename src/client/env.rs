@@ -5,12 +5,147 @@
 *******************************************************************************/
 
 use crate::common::core::msg;
-use crate::common::core::msg::DecodeMessage;
+use crate::common::core::msg::{DecodeMessage, EncodeMessage};
 use crate::msg::posix::ParentHello;
 use core::fmt;
+use std::ffi::OsString;
+#[cfg(unix)]
 use std::fs::File;
+#[cfg(unix)]
 use std::io::Read;
+#[cfg(unix)]
 use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+
+///The environment variable that [Environment::discover()](struct.Environment.html#method.discover)
+///reads the server socket path from when file descriptor 60 is not available. On Windows, this is
+///the name of the server's named pipe, e.g. `\\.\pipe\vt6-1234`.
+pub const SERVER_SOCKET_ENV_VAR: &str = "VT6_SERVER_SOCKET";
+
+///The environment variable that [Environment::discover()](struct.Environment.html#method.discover)
+///reads the client secret from when file descriptor 60 is not available.
+pub const CLIENT_SECRET_ENV_VAR: &str = "VT6_CLIENT_SECRET";
+
+///The environment variable that tells legacy code, e.g. shell scripts, that it runs on a VT6
+///terminal, without having to look at file descriptor 60. Its value is
+///[PROTOCOL_HINT](constant.PROTOCOL_HINT.html).
+pub const PROTOCOL_ENV_VAR: &str = "VT6";
+
+///The value of [PROTOCOL_ENV_VAR](constant.PROTOCOL_ENV_VAR.html). This names the module that
+///defines how clients connect to the server socket
+///([vt6/posix1.0](https://vt6.io/std/posix/1.0/)).
+pub const PROTOCOL_HINT: &str = "posix1";
+
+///The set of environment variables that advertise a VT6 terminal to child processes which cannot
+///receive a `posix1.parent-hello` on file descriptor 60, e.g. because a shell wrapper spawns them.
+///
+///The variables are:
+///
+///* [PROTOCOL_ENV_VAR](constant.PROTOCOL_ENV_VAR.html) (`VT6`), always set to
+///  [PROTOCOL_HINT](constant.PROTOCOL_HINT.html),
+///* [SERVER_SOCKET_ENV_VAR](constant.SERVER_SOCKET_ENV_VAR.html) (`VT6_SERVER_SOCKET`), always
+///  set, and
+///* [CLIENT_SECRET_ENV_VAR](constant.CLIENT_SECRET_ENV_VAR.html) (`VT6_CLIENT_SECRET`), only set
+///  when a secret was registered for the specific child that receives it.
+///
+///When reading the variables, a missing `VT6` is tolerated for compatibility with parents that
+///only set the other two. If `VT6` is set to anything but `posix1`, all variables are ignored,
+///since they were then not meant for this protocol.
+///
+///```
+///# use std::ffi::OsString;
+///# use vt6::client::EnvVars;
+///let vars = EnvVars::new("/run/user/1000/vt6/1234");
+///let mut cmd = std::process::Command::new("sh");
+///vars.apply(&mut cmd);
+///
+///let env: Vec<_> = cmd.get_envs().collect();
+///let lookup = |name: &str| {
+///    let value = env.iter().find(|(k, _)| *k == name).and_then(|(_, v)| *v);
+///    value.map(OsString::from)
+///};
+///assert_eq!(EnvVars::from_lookup(lookup), Some(vars));
+///```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvVars {
+    server_socket_path: PathBuf,
+    client_secret: Option<String>,
+}
+
+impl EnvVars {
+    ///Creates a set of variables that advertises the server socket at the given path, but does not
+    ///include a client secret.
+    pub fn new<P: Into<PathBuf>>(server_socket_path: P) -> Self {
+        Self {
+            server_socket_path: server_socket_path.into(),
+            client_secret: None,
+        }
+    }
+
+    ///Includes the given client secret. Since client secrets can only be used once, this is only
+    ///useful for a secret that was registered for the one child process that receives it.
+    pub fn with_client_secret(mut self, secret: &str) -> Self {
+        self.client_secret = Some(secret.into());
+        self
+    }
+
+    pub fn server_socket_path(&self) -> &Path {
+        &self.server_socket_path
+    }
+
+    pub fn client_secret(&self) -> Option<&str> {
+        self.client_secret.as_deref()
+    }
+
+    ///Returns the variables as pairs of name and value, e.g. for writing them into a shell script.
+    pub fn to_pairs(&self) -> Vec<(&'static str, OsString)> {
+        let mut pairs = vec![
+            (PROTOCOL_ENV_VAR, PROTOCOL_HINT.into()),
+            (
+                SERVER_SOCKET_ENV_VAR,
+                self.server_socket_path.clone().into(),
+            ),
+        ];
+        if let Some(ref secret) = self.client_secret {
+            pairs.push((CLIENT_SECRET_ENV_VAR, secret.into()));
+        }
+        pairs
+    }
+
+    ///Sets the variables in the environment of the given command. If this set does not include a
+    ///client secret, the secret variable is removed from the command's environment, so that the
+    ///child does not inherit a secret that was meant for this process.
+    pub fn apply(&self, cmd: &mut std::process::Command) {
+        if self.client_secret.is_none() {
+            cmd.env_remove(CLIENT_SECRET_ENV_VAR);
+        }
+        cmd.envs(self.to_pairs());
+    }
+
+    ///Reads the variables from the environment of this process. Returns `None` if they do not
+    ///advertise a VT6 terminal.
+    pub fn from_env() -> Option<Self> {
+        Self::from_lookup(|name| std::env::var_os(name))
+    }
+
+    ///Like `from_env()`, but looks up variables with the given function instead of in the
+    ///environment of this process, e.g. for inspecting the environment of a different process.
+    pub fn from_lookup<F: FnMut(&str) -> Option<OsString>>(mut lookup: F) -> Option<Self> {
+        if let Some(hint) = lookup(PROTOCOL_ENV_VAR) {
+            if hint != PROTOCOL_HINT {
+                return None;
+            }
+        }
+        let path = lookup(SERVER_SOCKET_ENV_VAR).filter(|p| !p.is_empty())?;
+        let secret = lookup(CLIENT_SECRET_ENV_VAR)
+            .and_then(|s| s.into_string().ok())
+            .filter(|s| !s.is_empty());
+        Some(Self {
+            server_socket_path: path.into(),
+            client_secret: secret,
+        })
+    }
+}
 
 ///General information about the current client process.
 ///
@@ -36,8 +171,10 @@ pub struct Environment {
     buf: [u8; 1024],
     ///How many bytes of `self.buf` is filled (counting from the beginning).
     filled: usize,
-    ///Whether FD 60 exists.
+    ///Whether FD 60 exists, or the environment variables are set.
     has_vt6_terminal: bool,
+    ///Whether we got a client secret (always true for FD 60).
+    has_client_secret: bool,
 }
 
 impl Environment {
@@ -49,11 +186,37 @@ impl Environment {
     ///described in the documentation on `struct Environment`, the resulting Environment instance
     ///should be held as a singleton, either in `main()` or through `lazy_static!` or similar
     ///facilities.
+    ///
+    ///If file descriptor 60 does not exist, or on platforms that do not have file descriptors
+    ///(most notably Windows), the server socket path and client secret are taken from the
+    ///[environment variables](struct.EnvVars.html) instead. The order of precedence is:
+    ///
+    ///1. If file descriptor 60 exists, its parent-hello is used, and the environment variables are
+    ///   ignored entirely. Unlike FD 60, environment variables are inherited by child processes,
+    ///   so they may be left over from a process further up the tree.
+    ///2. Otherwise, if the environment variables advertise a VT6 terminal and include a client
+    ///   secret, they are used.
+    ///3. If the environment variables advertise a VT6 terminal, but do not include a client
+    ///   secret, `parse()` reports `MissingClientSecret`.
+    ///4. Otherwise, `parse()` reports `NoVT6Terminal`.
     pub fn discover() -> std::io::Result<Self> {
+        #[cfg(unix)]
+        {
+            let env = Self::from_parent_hello_fd()?;
+            if env.has_vt6_terminal {
+                return Ok(env);
+            }
+        }
+        Ok(Self::from_env_vars())
+    }
+
+    #[cfg(unix)]
+    fn from_parent_hello_fd() -> std::io::Result<Self> {
         let mut env = Self {
             buf: [0u8; 1024],
             filled: 0,
             has_vt6_terminal: true,
+            has_client_secret: true,
         };
 
         //SAFETY: we need to call this unsafe trait method to obtain a File handle
@@ -86,6 +249,34 @@ impl Environment {
         Ok(env)
     }
 
+    fn from_env_vars() -> Self {
+        let mut env = Self {
+            buf: [0u8; 1024],
+            filled: 0,
+            has_vt6_terminal: false,
+            has_client_secret: false,
+        };
+        let vars = match EnvVars::from_env() {
+            Some(vars) => vars,
+            None => return env,
+        };
+        env.has_vt6_terminal = true;
+        if let Some(secret) = vars.client_secret() {
+            //the values are stored as a parent-hello message, so that parse() does not need to
+            //care where they came from
+            let hello = ParentHello {
+                client_secret: secret,
+                server_socket_path: vars.server_socket_path(),
+            };
+            //if the values are too long, the empty buffer will be reported as corrupt by parse()
+            if let Ok(filled) = hello.encode(&mut env.buf) {
+                env.filled = filled;
+            }
+            env.has_client_secret = true;
+        }
+        env
+    }
+
     ///Parses the data that was read during `discover()` into an instance of `EnvironmentRef`. This
     ///operation can be repeated as many times as necessary. If `EnvironmentRef` instances are
     ///needed in multiple threads, each thread can run `parse()` on its own.
@@ -94,6 +285,9 @@ impl Environment {
         if !self.has_vt6_terminal {
             return Err(NoVT6Terminal);
         }
+        if !self.has_client_secret {
+            return Err(MissingClientSecret);
+        }
         let (m, _) = msg::Message::parse(&self.buf[0..self.filled]).map_err(CorruptParentHello)?;
         if let Some(hello) = ParentHello::decode_message(&m) {
             return Ok(EnvironmentRef { hello });
@@ -112,10 +306,23 @@ pub struct EnvironmentRef<'a> {
 }
 
 impl EnvironmentRef<'_> {
-    ///Returns the filesystem path of the terminal's server socket.
+    ///Returns the filesystem path of the terminal's server socket, or on Windows, the name of its
+    ///named pipe. (The client connection types in this crate cannot connect to named pipes.)
     pub fn server_socket_path(&self) -> &std::path::Path {
         self.hello.server_socket_path
     }
+
+    ///Returns the secret that this client can use to handshake with the terminal via
+    ///[`Connection::client_hello()`](struct.Connection.html#method.client_hello).
+    pub fn client_secret(&self) -> &str {
+        self.hello.client_secret
+    }
+
+    ///Returns the environment variables that advertise this terminal to child processes. The
+    ///client secret is not included, since it belongs to this process.
+    pub fn env_vars(&self) -> EnvVars {
+        EnvVars::new(self.server_socket_path())
+    }
 }
 
 ///Error type returned from [`Environment::parse`](struct.Environment.html).
@@ -123,6 +330,9 @@ impl EnvironmentRef<'_> {
 pub enum EnvironmentError<'a> {
     ///The client is not connected to a VT6-capable terminal.
     NoVT6Terminal,
+    ///The environment variables advertise a VT6 terminal, but do not include a client secret for
+    ///this client, so it cannot connect to the terminal.
+    MissingClientSecret,
     ///The ParentHello message received by this client during discovery was not a valid VT6 message.
     CorruptParentHello(msg::ParseError<'a>),
     ///The server socket path from the ParentHello message is not a valid path.
@@ -133,8 +343,80 @@ impl fmt::Display for EnvironmentError<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Self::NoVT6Terminal => write!(f, "not connected to a VT6-capable terminal"),
+            Self::MissingClientSecret => {
+                write!(
+                    f,
+                    "running on a VT6-capable terminal, but without a client secret"
+                )
+            }
             Self::CorruptParentHello(ref e) => write!(f, "cannot parse ParentHello: {}", e),
             Self::InvalidParentHello(ref msg) => write!(f, "invalid ParentHello: {}", msg),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discovery_from_env_vars() {
+        std::env::remove_var(SERVER_SOCKET_ENV_VAR);
+        std::env::set_var(CLIENT_SECRET_ENV_VAR, "s3cr3t");
+        let env = Environment::from_env_vars();
+        assert!(matches!(env.parse(), Err(EnvironmentError::NoVT6Terminal)));
+
+        std::env::set_var(SERVER_SOCKET_ENV_VAR, "/run/user/1000/vt6/1234");
+        let env = Environment::from_env_vars();
+        let env_ref = env.parse().unwrap();
+        assert_eq!(env_ref.client_secret(), "s3cr3t");
+        assert_eq!(
+            env_ref.server_socket_path(),
+            std::path::Path::new("/run/user/1000/vt6/1234")
+        );
+
+        //without a secret, the terminal is advertised, but cannot be connected to
+        std::env::remove_var(CLIENT_SECRET_ENV_VAR);
+        let env = Environment::from_env_vars();
+        assert!(matches!(
+            env.parse(),
+            Err(EnvironmentError::MissingClientSecret)
+        ));
+
+        //variables for a different protocol are ignored
+        std::env::set_var(CLIENT_SECRET_ENV_VAR, "s3cr3t");
+        std::env::set_var(PROTOCOL_ENV_VAR, "posix2");
+        let env = Environment::from_env_vars();
+        assert!(matches!(env.parse(), Err(EnvironmentError::NoVT6Terminal)));
+
+        std::env::remove_var(PROTOCOL_ENV_VAR);
+        std::env::remove_var(SERVER_SOCKET_ENV_VAR);
+        std::env::remove_var(CLIENT_SECRET_ENV_VAR);
+    }
+
+    #[test]
+    fn test_env_vars() {
+        let vars = EnvVars::new("/run/vt6").with_client_secret("s3cr3t");
+        let pairs = vars.to_pairs();
+        assert_eq!(
+            pairs,
+            vec![
+                ("VT6", OsString::from("posix1")),
+                ("VT6_SERVER_SOCKET", OsString::from("/run/vt6")),
+                ("VT6_CLIENT_SECRET", OsString::from("s3cr3t")),
+            ]
+        );
+        let lookup = |name: &str| {
+            let pair = pairs.iter().find(|(k, _)| *k == name);
+            pair.map(|(_, v)| v.clone())
+        };
+        assert_eq!(EnvVars::from_lookup(lookup), Some(vars));
+
+        //an empty socket path does not advertise anything
+        let lookup = |name: &str| match name {
+            "VT6_SERVER_SOCKET" => Some(OsString::new()),
+            _ => None,
+        };
+        assert_eq!(EnvVars::from_lookup(lookup), None);
+    }
+}
@@ -0,0 +1,172 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::client::{Connection, Msgio};
+use crate::common::core::msg;
+use core::fmt;
+use std::io;
+
+///Error type for the `handle()` method in [trait MessageHandler](trait.MessageHandler.html).
+///
+///This is the client-side counterpart of
+///[`vt6::server::HandlerError`](../server/enum.HandlerError.html). Since clients do not reply to
+///messages that they cannot handle, these errors are just passed on to the caller of
+///[`Connection::handle_next_message()`](struct.Connection.html#method.handle_next_message).
+#[derive(Debug)]
+pub enum HandlerError {
+    ///The message was of a type that no handler in the chain recognized.
+    UnknownMessageType,
+    ///The message type was recognized, but the message was semantically invalid.
+    InvalidMessage,
+    ///An IO error occurred on the connection, either while receiving the message or while the
+    ///handler was sending a reply.
+    Io(io::Error),
+}
+
+impl From<io::Error> for HandlerError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::UnknownMessageType => write!(f, "unknown message type"),
+            Self::InvalidMessage => write!(f, "invalid message"),
+            Self::Io(ref e) => write!(f, "IO error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for HandlerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Self::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+///The trait for handlers of messages that the server sends on its own accord, e.g. when it asks
+///the client a question.
+///
+///This is the client-side counterpart of [`vt6::server::Handler`](../server/trait.Handler.html),
+///and handlers are composed in the same way: Each handler takes care of the message types that it
+///knows, and forwards everything else to the next handler in the chain. The chain usually ends in
+///a [RejectHandler](struct.RejectHandler.html). Handlers are instantiated for every message, hence
+///the `Default` bound on this trait.
+///
+///```no_run
+///# use vt6::common::core::msg;
+///use vt6::client::{Connection, HandlerError, MessageHandler, Msgio, RejectHandler};
+///
+///#[derive(Default)]
+///struct PingHandler<Next>(Next);
+///
+///impl<Next: MessageHandler> MessageHandler for PingHandler<Next> {
+///    fn handle(&self, msg: &msg::Message, conn: &mut Connection<Msgio>) -> Result<(), HandlerError> {
+///        match msg.parsed_type().as_str() {
+///            "example1.ping" => Ok(conn.send_message(&vt6::msg::Want(
+///                vt6::common::core::ModuleIdentifier::parse("example1").unwrap(),
+///            ))?),
+///            _ => self.0.handle(msg, conn),
+///        }
+///    }
+///}
+///
+///# fn main() -> Result<(), Box<dyn std::error::Error>> {
+///let conn = Connection::connect("/run/user/1000/vt6/1234")?;
+///let mut conn = conn.client_hello("secret")?;
+///while conn.handle_next_message::<PingHandler<RejectHandler>>()? {}
+///# Ok(())
+///# }
+///```
+pub trait MessageHandler: Default {
+    ///Handle a message sent by the server. The connection is given so that the handler can send
+    ///replies. Unlike with `recv_message()`, the message does not borrow from the connection.
+    fn handle(&self, msg: &msg::Message, conn: &mut Connection<Msgio>) -> Result<(), HandlerError>;
+}
+
+///A [MessageHandler](trait.MessageHandler.html) that just rejects everything as
+///[UnknownMessageType](enum.HandlerError.html).
+///
+///This handler is usually the last in every MessageHandler chain.
+#[derive(Default)]
+pub struct RejectHandler;
+
+impl MessageHandler for RejectHandler {
+    fn handle(
+        &self,
+        _msg: &msg::Message,
+        _conn: &mut Connection<Msgio>,
+    ) -> Result<(), HandlerError> {
+        Err(HandlerError::UnknownMessageType)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    struct Pong;
+
+    impl msg::EncodeMessage for Pong {
+        fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+            msg::MessageFormatter::new(buf, "test1.pong", 0).finalize()
+        }
+    }
+
+    #[derive(Default)]
+    struct PingHandler<Next>(Next);
+
+    impl<Next: MessageHandler> MessageHandler for PingHandler<Next> {
+        fn handle(
+            &self,
+            msg: &msg::Message,
+            conn: &mut Connection<Msgio>,
+        ) -> Result<(), HandlerError> {
+            match msg.parsed_type().as_str() {
+                "test1.ping" if msg.arguments().next().is_none() => Ok(conn.send_message(&Pong)?),
+                "test1.ping" => Err(HandlerError::InvalidMessage),
+                _ => self.0.handle(msg, conn),
+            }
+        }
+    }
+
+    #[test]
+    fn test_handler_chain() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        server
+            .write_all(b"{5|19:posix1.server-hello,3:foo,0:,0:,0:,}")
+            .unwrap();
+        let mut conn = Connection::from_stream(client).client_hello("abc").unwrap();
+        let mut buf = [0u8; 33];
+        server.read_exact(&mut buf).unwrap();
+
+        type Handler = PingHandler<RejectHandler>;
+        server
+            .write_all(b"{1|10:test1.ping,}{1|10:test1.else,}{2|10:test1.ping,0:,}")
+            .unwrap();
+        assert!(conn.handle_next_message::<Handler>().unwrap());
+        let mut buf = [0u8; 18];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"{1|10:test1.pong,}");
+        assert!(matches!(
+            conn.handle_next_message::<Handler>(),
+            Err(HandlerError::UnknownMessageType)
+        ));
+        assert!(matches!(
+            conn.handle_next_message::<Handler>(),
+            Err(HandlerError::InvalidMessage)
+        ));
+
+        std::mem::drop(server);
+        assert!(!conn.handle_next_message::<Handler>().unwrap());
+    }
+}
@@ -0,0 +1,51 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::{Chunk, Reassembler, ReassemblyError};
+use crate::msg::input::Paste;
+
+///Puts the chunks of a paste back together. See [vt6::msg::input::Paste](../msg/input/struct.Paste.html)
+///for how pastes are split into `input1.paste` messages.
+///
+///```
+///# use vt6::client::PasteReassembler;
+///# use vt6::msg::input::paste_chunks;
+///let text = "a long line of pasted text\n".repeat(100);
+///let mut reassembler = PasteReassembler::new(1 << 20);
+///let mut result = None;
+///for chunk in paste_chunks(&text) {
+///    result = reassembler.push(&chunk).unwrap();
+///}
+///assert_eq!(result, Some(text));
+///```
+#[derive(Clone, Debug)]
+pub struct PasteReassembler(Reassembler);
+
+impl PasteReassembler {
+    ///Creates a reassembler that accepts pastes of up to `max_size` bytes. Longer pastes are
+    ///rejected with [ReassemblyError::TooLarge](../common/enum.ReassemblyError.html).
+    pub fn new(max_size: usize) -> Self {
+        Self(Reassembler::new(max_size))
+    }
+
+    ///Adds a chunk to the paste that is being reassembled. When the final chunk has been added,
+    ///the complete text is returned. After an error, the partially received paste is discarded.
+    pub fn push(&mut self, paste: &Paste<'_>) -> Result<Option<String>, ReassemblyError> {
+        let chunk = Chunk {
+            sequence: paste.sequence,
+            is_final: paste.is_final,
+            data: paste.text.as_bytes(),
+        };
+        let result = self.0.push(&chunk)?;
+        //unwrap() is safe because each chunk is valid UTF-8 on its own
+        Ok(result.map(|buf| String::from_utf8(buf).unwrap()))
+    }
+
+    ///Returns whether a paste is partially received.
+    pub fn is_in_progress(&self) -> bool {
+        self.0.is_in_progress()
+    }
+}
@@ -4,10 +4,57 @@
 * Refer to the file "LICENSE" for details.
 *******************************************************************************/
 
-#[cfg(feature = "use_std")]
+#[cfg(feature = "use_tokio")]
+mod async_receiver;
+#[cfg(feature = "use_tokio")]
+pub use async_receiver::*;
+#[cfg(feature = "use_tokio")]
+mod async_sender;
+#[cfg(feature = "use_tokio")]
+pub use async_sender::*;
+#[cfg(all(feature = "use_std", feature = "module_posix", unix))]
+mod capabilities;
+#[cfg(all(feature = "use_std", feature = "module_posix", unix))]
+pub use capabilities::*;
+#[cfg(all(feature = "use_std", feature = "module_posix", unix))]
+mod child;
+#[cfg(all(feature = "use_std", feature = "module_posix", unix))]
+pub use child::*;
+#[cfg(all(feature = "use_std", feature = "module_posix", unix))]
+mod connection;
+#[cfg(all(feature = "use_std", feature = "module_posix", unix))]
+pub use connection::*;
+#[cfg(all(feature = "use_std", feature = "module_posix", unix))]
+mod connection_set;
+#[cfg(all(feature = "use_std", feature = "module_posix", unix))]
+pub use connection_set::*;
+#[cfg(all(feature = "use_std", feature = "module_posix"))]
+mod disconnect;
+#[cfg(all(feature = "use_std", feature = "module_posix"))]
+pub use disconnect::*;
+#[cfg(all(feature = "use_std", feature = "module_posix"))]
 mod env;
-#[cfg(feature = "use_std")]
+#[cfg(all(feature = "use_std", feature = "module_posix"))]
 pub use env::*;
+#[cfg(all(feature = "use_std", feature = "module_posix", unix))]
+mod handler;
+#[cfg(all(feature = "use_std", feature = "module_posix", unix))]
+pub use handler::*;
+#[cfg(all(feature = "use_std", feature = "module_input"))]
+mod input;
+#[cfg(all(feature = "use_std", feature = "module_input"))]
+pub use input::*;
+#[cfg(all(feature = "use_std", feature = "module_posix", unix))]
+mod reconnect;
+#[cfg(all(feature = "use_std", feature = "module_posix", unix))]
+pub use reconnect::*;
+#[cfg(feature = "use_tokio")]
+mod subscriptions;
+#[cfg(feature = "use_tokio")]
+pub use subscriptions::*;
 
 ///Client-side implementation of the [vt6/core module](https://vt6.io/std/core/).
 pub mod core;
+#[cfg(all(feature = "use_std", feature = "module_posix", unix))]
+///Spawning of child processes that inherit the VT6 context of this client.
+pub mod process;
@@ -0,0 +1,251 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::client::core::ClientIDSuffix;
+use crate::client::{register_child, ChildClient, Connection, EnvVars, Msgio, RegisterError};
+use crate::common::core::msg::EncodeMessage;
+use crate::common::core::{ClientID, EncodeArgument, OwnedScreenID, ScreenID};
+use crate::msg::posix::ParentHello;
+use core::fmt;
+use std::ffi::OsStr;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::process;
+
+///The file descriptor on which a client process receives its `posix1.parent-hello` message, as
+///defined in [\[vt6/posix1.0, section 2.2\]](https://vt6.io/std/posix/1.0/#section-2-2).
+const PARENT_HELLO_FD: libc::c_int = 60;
+
+///A wrapper around [std::process::Command](https://doc.rust-lang.org/std/process/struct.Command.html)
+///that spawns a child process which inherits the VT6 context of this client.
+///
+///When the child is spawned, it is registered with the server as a child client through
+///`core1.client-make` (see [register_child()](../fn.register_child.html)). Its client ID is
+///formed by appending the given suffix to the client ID of the connection, and it is attached to
+///the same screens as this client, since it inherits this process's standard streams. The
+///resulting secret is sent to the child in a `posix1.parent-hello` message on file descriptor 60,
+///where the child can pick it up with
+///[Environment::discover()](../struct.Environment.html#method.discover). The child's environment
+///also [advertises the terminal](../struct.EnvVars.html) (without the secret), so that processes
+///further down the tree which cannot read FD 60 still know that they are on a VT6 terminal.
+///
+///```no_run
+///# fn main() -> Result<(), Box<dyn std::error::Error>> {
+///use vt6::client::core::ClientIDSuffix;
+///use vt6::client::process::Command;
+///
+///let conn = vt6::client::Connection::connect("/run/user/1000/vt6/1234")?;
+///let mut conn = conn.client_hello("secret")?;
+///let mut cmd = Command::new("ls", ClientIDSuffix::Child(0, 0));
+///cmd.as_std_mut().arg("-l");
+///let status = cmd.spawn(&mut conn)?.wait()?;
+///# Ok(())
+///# }
+///```
+#[derive(Debug)]
+pub struct Command {
+    inner: process::Command,
+    suffix: ClientIDSuffix,
+}
+
+impl Command {
+    ///Creates a command for running the given program. See
+    ///[ClientIDSuffix](../core/enum.ClientIDSuffix.html) for how to choose the suffix.
+    pub fn new<S: AsRef<OsStr>>(program: S, suffix: ClientIDSuffix) -> Self {
+        Self::from_std(process::Command::new(program), suffix)
+    }
+
+    ///Wraps a command that has already been configured.
+    pub fn from_std(inner: process::Command, suffix: ClientIDSuffix) -> Self {
+        Self { inner, suffix }
+    }
+
+    ///Gives access to the wrapped command, e.g. for adding arguments or setting up redirections.
+    pub fn as_std_mut(&mut self) -> &mut process::Command {
+        &mut self.inner
+    }
+
+    ///Registers the child client with the server, and spawns the child process.
+    ///
+    ///Like `register_child()`, this must be called while no other replies are outstanding on
+    ///`conn`. The child client is registered even if spawning the process fails afterwards.
+    pub fn spawn(mut self, conn: &mut Connection<Msgio>) -> Result<process::Child, SpawnError> {
+        let _hello = self.prepare(conn)?;
+        //the child has its own copy of the socket now, so our copy is dropped afterwards
+        Ok(self.inner.spawn()?)
+    }
+
+    #[cfg(feature = "use_tokio")]
+    ///Like `spawn()`, but spawns the child process as a Tokio child process. This is only
+    ///available with the `use_tokio` feature, and must be called from within a Tokio runtime.
+    pub fn spawn_async(
+        mut self,
+        conn: &mut Connection<Msgio>,
+    ) -> Result<tokio::process::Child, SpawnError> {
+        let _hello = self.prepare(conn)?;
+        Ok(tokio::process::Command::from(self.inner).spawn()?)
+    }
+
+    //Registers the child client and prepares the socket that will become FD 60 in the child. The
+    //returned socket must be kept alive until the child has been spawned.
+    fn prepare(&mut self, conn: &mut Connection<Msgio>) -> Result<UnixStream, SpawnError> {
+        let addr = conn.stream().peer_addr()?;
+        let server_socket_path = addr.as_pathname().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "server socket does not have a filesystem path",
+            )
+        })?;
+
+        let client_id = String::from_utf8(self.suffix.below(conn.client_id()).encode_to_vector())
+            .expect("client ID suffix is not valid UTF-8");
+        //the child is attached to the same screens as this client, but these need to be copied
+        //since `conn` is borrowed mutably during the registration
+        let own = |id: Option<ScreenID<'_>>| id.map(|id| OwnedScreenID::from(&id));
+        let stdin = own(conn.stdin_screen_id());
+        let stdout = own(conn.stdout_screen_id());
+        let stderr = own(conn.stderr_screen_id());
+
+        //cannot fail: a valid client ID with a valid suffix is a valid client ID
+        let mut child = ChildClient::new(ClientID::parse(&client_id).unwrap());
+        if let Some(ref id) = stdin {
+            child = child.with_stdin(id.as_ref());
+        }
+        if let Some(ref id) = stdout {
+            child = child.with_stdout(id.as_ref());
+        }
+        if let Some(ref id) = stderr {
+            child = child.with_stderr(id.as_ref());
+        }
+        let creds = register_child(conn, &child)?;
+        EnvVars::new(server_socket_path).apply(&mut self.inner);
+
+        //The parent-hello is written into a socket pair instead of a pipe, since std creates
+        //those with CLOEXEC set. The message is much smaller than the socket buffer, so this does
+        //not block even though nobody is reading yet. Once our end is closed, the child reads EOF
+        //after the message.
+        let mut buf = [0u8; 1024];
+        let len = ParentHello {
+            client_secret: creds.secret(),
+            server_socket_path,
+        }
+        .encode(&mut buf)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "server socket path too long"))?;
+        let (mut ours, theirs) = UnixStream::pair()?;
+        ours.write_all(&buf[0..len])?;
+        std::mem::drop(ours);
+
+        let fd = theirs.as_raw_fd();
+        //SAFETY: dup2() and fcntl() are async-signal-safe, so they may be called between fork()
+        //and exec()
+        unsafe {
+            self.inner.pre_exec(move || {
+                //dup2() clears CLOEXEC on the new FD, except if it is the same as the old FD
+                let result = if fd == PARENT_HELLO_FD {
+                    libc::fcntl(fd, libc::F_SETFD, 0)
+                } else {
+                    libc::dup2(fd, PARENT_HELLO_FD)
+                };
+                if result == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(theirs)
+    }
+}
+
+///Error type returned by [`Command::spawn()`](struct.Command.html#method.spawn).
+#[derive(Debug)]
+pub enum SpawnError {
+    ///The server did not register the child client.
+    Register(RegisterError),
+    ///The child process could not be spawned, or the parent-hello message could not be prepared
+    ///for it.
+    Io(io::Error),
+}
+
+impl From<RegisterError> for SpawnError {
+    fn from(e: RegisterError) -> Self {
+        Self::Register(e)
+    }
+}
+
+impl From<io::Error> for SpawnError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Register(ref e) => e.fmt(f),
+            Self::Io(ref e) => write!(f, "cannot spawn child process: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SpawnError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Self::Register(ref e) => Some(e),
+            Self::Io(ref e) => Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_spawn_with_parent_hello() {
+        let dir = std::env::temp_dir().join(format!("vt6-test-process-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("socket");
+        let listener = UnixListener::bind(&path).unwrap();
+        let client = UnixStream::connect(&path).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        server
+            .write_all(b"{5|19:posix1.server-hello,3:foo,1:1,0:,1:1,}")
+            .unwrap();
+        let mut conn = Connection::from_stream(client).client_hello("abc").unwrap();
+        let mut buf = [0u8; 33];
+        server.read_exact(&mut buf).unwrap();
+
+        server
+            .write_all(b"{2|16:core1.client-new,6:s3cr3t,}")
+            .unwrap();
+        //this needs bash instead of sh since e.g. dash only supports redirecting FDs 0-9
+        let mut cmd = Command::new("bash", ClientIDSuffix::Child(0, 0));
+        cmd.as_std_mut()
+            .args(["-c", "cat <&60"])
+            .stdout(process::Stdio::piped());
+        let output = cmd.spawn(&mut conn).unwrap().wait_with_output().unwrap();
+
+        //the child is registered with the screens of its parent
+        let expected = b"{5|17:core1.client-make,5:foo11,1:1,0:,1:1,}";
+        let mut buf = [0u8; 44];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, expected);
+
+        let path = path.to_str().unwrap();
+        let expected = format!(
+            "{{3|19:posix1.parent-hello,6:s3cr3t,{}:{},}}",
+            path.len(),
+            path
+        );
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::client::{Connection, HandshakeError, Msgio};
+use std::path::Path;
+use std::time::Duration;
+
+///Configuration for [reconnect()](fn.reconnect.html): how often and how long to wait between
+///connection attempts.
+///
+///The delay starts at the initial delay and doubles after each failed attempt, up to the maximum
+///delay.
+///
+///```
+///# use std::time::Duration;
+///let backoff = vt6::client::Backoff::default()
+///    .initial_delay(Duration::from_millis(100))
+///    .max_delay(Duration::from_millis(300))
+///    .max_attempts(5);
+///let delays: Vec<_> = backoff.delays().map(|d| d.as_millis()).collect();
+///assert_eq!(delays, vec![100, 200, 300, 300]);
+///```
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    initial_delay: Duration,
+    max_delay: Duration,
+    max_attempts: u32,
+}
+
+impl Default for Backoff {
+    ///Returns a backoff that makes 10 attempts within about 10 seconds.
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(2),
+            max_attempts: 10,
+        }
+    }
+}
+
+impl Backoff {
+    ///Sets the delay between the first and second attempt.
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    ///Sets the maximum delay between two attempts.
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    ///Sets the maximum number of attempts. Values below 1 are treated as 1.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    ///Returns the delays between each two consecutive attempts.
+    pub fn delays(&self) -> impl Iterator<Item = Duration> {
+        let max_delay = self.max_delay;
+        let count = self.max_attempts.max(1) - 1;
+        std::iter::successors(Some(self.initial_delay.min(max_delay)), move |d| {
+            Some(d.checked_mul(2).unwrap_or(max_delay).min(max_delay))
+        })
+        .take(count as usize)
+    }
+}
+
+///Re-establishes a msgio connection after the previous one was lost, e.g. because the terminal
+///was restarted.
+///
+///This connects to the server socket at the given path and performs a client-hello handshake
+///with the given secret, which is usually the same secret that was used for the original
+///connection. Whether the server accepts a secret that has been used before is up to the server
+///(see
+///[`vt6::server::Application::resume_client()`](../server/trait.Application.html#method.resume_client)).
+///
+///Failed connection attempts are retried according to the given backoff, since the server socket
+///may not be available while the terminal is restarting. If the server rejects the handshake, no
+///further attempts are made. When all attempts fail, the error from the last attempt is returned.
+///
+///The resumed connection is a new connection in every respect: Messages that were sent on the
+///old connection and not answered yet are lost, and the capabilities of the server need to be
+///probed again with [probe_capabilities()](fn.probe_capabilities.html).
+///
+///```no_run
+///# fn main() -> Result<(), Box<dyn std::error::Error>> {
+///let path = "/run/user/1000/vt6/1234";
+///let mut conn = vt6::client::Connection::connect(path)?.client_hello("secret")?;
+///while let Some(_msg) = conn.recv_message()? {
+///    //...
+///}
+///
+/////the terminal has closed the connection; try to resume it
+///conn = vt6::client::reconnect(path, "secret", &vt6::client::Backoff::default())?;
+///# Ok(())
+///# }
+///```
+pub fn reconnect<P: AsRef<Path>>(
+    path: P,
+    secret: &str,
+    backoff: &Backoff,
+) -> Result<Connection<Msgio>, HandshakeError> {
+    let path = path.as_ref();
+    let mut delays = backoff.delays();
+    loop {
+        let err = match Connection::connect(path) {
+            Ok(conn) => match conn.client_hello(secret) {
+                Ok(conn) => return Ok(conn),
+                Err(e) => e,
+            },
+            Err(e) => HandshakeError::Io(e),
+        };
+        match (err, delays.next()) {
+            (HandshakeError::Io(_), Some(delay)) => std::thread::sleep(delay),
+            (err, _) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+
+    fn socket_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("vt6-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let path = socket_path("reconnect");
+        let backoff = Backoff::default()
+            .initial_delay(Duration::from_millis(5))
+            .max_delay(Duration::from_millis(20))
+            .max_attempts(50);
+
+        //the server socket only appears after a while
+        let server_path = path.clone();
+        let server = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(30));
+            let listener = UnixListener::bind(&server_path).unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 33];
+            stream.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf[..], &b"{2|19:posix1.client-hello,3:abc,}"[..]);
+            stream
+                .write_all(b"{5|19:posix1.server-hello,3:foo,1:1,0:,1:1,}")
+                .unwrap();
+        });
+
+        let conn = reconnect(&path, "abc", &backoff).unwrap();
+        assert_eq!(conn.client_id().as_str(), "foo");
+        server.join().unwrap();
+
+        //rejected handshakes are not retried
+        std::fs::remove_file(&path).unwrap();
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 33];
+            stream.read_exact(&mut buf).unwrap();
+            std::mem::drop(stream);
+            listener
+        });
+        assert!(matches!(
+            reconnect(&path, "abc", &backoff),
+            Err(HandshakeError::Rejected)
+        ));
+        let listener = server.join().unwrap();
+        listener.set_nonblocking(true).unwrap();
+        assert!(listener.accept().is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            reconnect(&path, "abc", &backoff.max_attempts(2)),
+            Err(HandshakeError::Io(_))
+        ));
+    }
+}
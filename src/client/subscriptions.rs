@@ -0,0 +1,223 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::client::AsyncMessageSender;
+use crate::common::core::msg::DecodeMessage;
+use crate::common::core::{msg, ScopedIdentifier};
+use crate::msg::core::{Pub, Sub};
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures::stream::Stream;
+use std::collections::HashMap;
+use std::io;
+use tokio::io::AsyncWrite;
+use tokio::sync::mpsc;
+
+///Tracks the properties that a client has subscribed to, for clients using the
+///[Tokio library](https://tokio.rs/). This is only available with the `use_tokio` feature.
+///
+///Each call to `subscribe()` returns a [PropertyStream](struct.PropertyStream.html) that yields
+///the values of the property as the server publishes them, so that e.g. a TUI application can
+///bind a UI element to a terminal property. The `core1.pub` messages need to be fed into
+///`handle_message()` by whoever reads from the
+///[AsyncMessageReceiver](struct.AsyncMessageReceiver.html), since the same socket also carries
+///the replies to all other messages.
+///
+///```no_run
+///use futures::stream::StreamExt;
+///# use vt6::client::{Connection, Msgio};
+///# use vt6::common::core::ScopedIdentifier;
+///# async fn example(conn: Connection<Msgio>) -> std::io::Result<()> {
+///let (mut receiver, mut sender) = conn.into_async()?;
+///let mut subs = vt6::client::PropertySubscriptions::new();
+///let title = ScopedIdentifier::parse("core1.title").unwrap();
+///let mut titles = subs.subscribe(&mut sender, &title).await?;
+///tokio::spawn(async move {
+///    while let Some(value) = titles.next().await {
+///        println!("title is now {:?}", String::from_utf8_lossy(&value));
+///    }
+///});
+///while let Some(buf) = receiver.recv_message().await? {
+///    let (msg, _) = vt6::common::core::msg::Message::parse(&buf).unwrap();
+///    if !subs.handle_message(&msg) {
+///        //...
+///    }
+///}
+///# Ok(())
+///# }
+///```
+///
+///Subscriptions do not survive the connection. After a reconnect (see
+///[reconnect()](fn.reconnect.html)), call `resubscribe()` with the new connection's sender to
+///subscribe to all properties again. The existing streams stay valid and continue with the
+///values published on the new connection.
+#[derive(Debug, Default)]
+pub struct PropertySubscriptions {
+    properties: HashMap<String, Property>,
+}
+
+#[derive(Debug, Default)]
+struct Property {
+    value: Option<Vec<u8>>,
+    subscribers: Vec<mpsc::UnboundedSender<Vec<u8>>>,
+}
+
+impl Property {
+    fn has_subscribers(&mut self) -> bool {
+        self.subscribers.retain(|s| !s.is_closed());
+        !self.subscribers.is_empty()
+    }
+}
+
+impl PropertySubscriptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Subscribes to the given property, and returns a stream of its values.
+    ///
+    ///The `core1.sub` message is only sent (and the send queue flushed) when there is no other
+    ///live stream for this property. Otherwise, the new stream starts with the last value that
+    ///was published, if any.
+    pub async fn subscribe<W: AsyncWrite + Unpin>(
+        &mut self,
+        sender: &mut AsyncMessageSender<W>,
+        name: &ScopedIdentifier<'_>,
+    ) -> io::Result<PropertyStream> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let prop = self.properties.entry(name.as_str().into()).or_default();
+        if prop.has_subscribers() {
+            if let Some(ref value) = prop.value {
+                //cannot fail since we hold the receiver
+                let _ = tx.send(value.clone());
+            }
+            prop.subscribers.push(tx);
+            return Ok(PropertyStream(rx));
+        }
+
+        prop.value = None;
+        prop.subscribers.push(tx);
+        sender.send_message(&Sub { name: name.clone() }).await?;
+        sender.flush().await?;
+        Ok(PropertyStream(rx))
+    }
+
+    ///Returns the last value that was published for the given property, or `None` if there is no
+    ///subscription for it or the server has not published a value yet.
+    pub fn value(&self, name: &str) -> Option<&[u8]> {
+        self.properties.get(name)?.value.as_deref()
+    }
+
+    ///Processes a message received from the server. If it is a `core1.pub` message for a property
+    ///that was subscribed to, the value is passed on to all streams for that property, and `true`
+    ///is returned. Otherwise, `false` is returned and the message is left for the caller.
+    pub fn handle_message(&mut self, msg: &msg::Message) -> bool {
+        let msg = match Pub::decode_message(msg) {
+            Some(msg) => msg,
+            None => return false,
+        };
+        let prop = match self.properties.get_mut(msg.name.as_str()) {
+            Some(prop) => prop,
+            None => return false,
+        };
+        prop.value = Some(msg.value.into());
+        for s in &prop.subscribers {
+            //streams that have been dropped are cleaned up by has_subscribers()
+            let _ = s.send(msg.value.into());
+        }
+        if !prop.has_subscribers() {
+            self.properties.remove(msg.name.as_str());
+        }
+        true
+    }
+
+    ///Subscribes to all properties that still have live streams again, usually on a new
+    ///connection after the previous one was lost. Since the old values may be stale, they are
+    ///forgotten until the server publishes new ones.
+    pub async fn resubscribe<W: AsyncWrite + Unpin>(
+        &mut self,
+        sender: &mut AsyncMessageSender<W>,
+    ) -> io::Result<()> {
+        self.properties.retain(|_, prop| prop.has_subscribers());
+        for (name, prop) in self.properties.iter_mut() {
+            prop.value = None;
+            //unwrap() is safe because the names came from ScopedIdentifier instances
+            let name = ScopedIdentifier::parse(name).unwrap();
+            sender.send_message(&Sub { name }).await?;
+        }
+        sender.flush().await
+    }
+}
+
+///A stream of the values of a property. This is returned by
+///[`PropertySubscriptions::subscribe()`](struct.PropertySubscriptions.html#method.subscribe).
+///
+///The stream ends when the PropertySubscriptions instance is dropped.
+#[derive(Debug)]
+pub struct PropertyStream(mpsc::UnboundedReceiver<Vec<u8>>);
+
+impl Stream for PropertyStream {
+    type Item = Vec<u8>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().0.poll_recv(cx)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use futures::stream::StreamExt;
+    use tokio::io::AsyncReadExt;
+
+    fn parse(buf: &[u8]) -> msg::Message<'_> {
+        msg::Message::parse(buf).unwrap().0
+    }
+
+    #[test]
+    fn test_property_subscriptions() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (client, mut server) = tokio::net::UnixStream::pair().unwrap();
+            let mut sender = AsyncMessageSender::new(client);
+            let mut subs = PropertySubscriptions::new();
+            let title = ScopedIdentifier::parse("core1.title").unwrap();
+
+            let mut stream1 = subs.subscribe(&mut sender, &title).await.unwrap();
+            let mut buf = [0u8; 31];
+            server.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"{2|9:core1.sub,11:core1.title,}");
+
+            assert!(subs.handle_message(&parse(b"{3|9:core1.pub,11:core1.title,3:foo,}")));
+            assert!(!subs.handle_message(&parse(b"{3|9:core1.pub,11:core1.other,3:foo,}")));
+            assert!(!subs.handle_message(&parse(b"{2|4:want,5:core1,}")));
+            assert_eq!(subs.value("core1.title"), Some(&b"foo"[..]));
+            assert_eq!(stream1.next().await, Some(b"foo".to_vec()));
+
+            //a second subscription does not need another `core1.sub`, and starts with the
+            //current value
+            let mut stream2 = subs.subscribe(&mut sender, &title).await.unwrap();
+            assert_eq!(stream2.next().await, Some(b"foo".to_vec()));
+
+            //after a reconnect, the properties that are still subscribed are subscribed again
+            std::mem::drop(stream1);
+            let (client, mut server) = tokio::net::UnixStream::pair().unwrap();
+            let mut sender = AsyncMessageSender::new(client);
+            subs.resubscribe(&mut sender).await.unwrap();
+            server.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"{2|9:core1.sub,11:core1.title,}");
+            assert_eq!(subs.value("core1.title"), None);
+            assert!(subs.handle_message(&parse(b"{3|9:core1.pub,11:core1.title,3:bar,}")));
+            assert_eq!(stream2.next().await, Some(b"bar".to_vec()));
+
+            std::mem::drop(subs);
+            assert_eq!(stream2.next().await, None);
+        });
+    }
+}
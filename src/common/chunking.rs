@@ -0,0 +1,296 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::{msg, DecodeArgument, EncodeArgument};
+use core::fmt;
+
+///One part of a payload that is too large to fit into a single message.
+///
+///Modules that need to transfer large payloads can split them into chunks with
+///[chunks()](fn.chunks.html), send one message per chunk, and reassemble the payload on the
+///receiving side with a [Reassembler](struct.Reassembler.html). Within a message, a chunk occupies
+///three arguments: the sequence number (starting at 0), a boolean that is true for the last chunk
+///of the payload, and the chunk's part of the payload.
+///
+///```
+///# use vt6::common::core::msg::{Message, MessageFormatter};
+///# use vt6::common::{chunks, Chunk, Reassembler};
+///let payload = b"a payload that is much too long for a single message".repeat(30);
+///let max_len = Chunk::max_data_len("example1.chunk");
+///let mut reassembler = Reassembler::new(4096);
+///let mut result = None;
+///for chunk in chunks(&payload, max_len) {
+///    let mut buf = [0u8; 1024];
+///    let mut f = MessageFormatter::new(&mut buf, "example1.chunk", Chunk::NUM_ARGUMENTS);
+///    chunk.add_to(&mut f);
+///    let len = f.finalize().unwrap();
+///
+///    let (msg, _) = Message::parse(&buf[0..len]).unwrap();
+///    let chunk = Chunk::decode_arguments(&mut msg.arguments()).unwrap();
+///    result = reassembler.push(&chunk).unwrap();
+///}
+///assert_eq!(result, Some(payload));
+///```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Chunk<'a> {
+    pub sequence: u32,
+    pub is_final: bool,
+    pub data: &'a [u8],
+}
+
+impl<'a> Chunk<'a> {
+    ///The number of message arguments that a chunk occupies.
+    pub const NUM_ARGUMENTS: usize = 3;
+
+    ///Returns the maximum length of `data`, such that a message of the given type, containing
+    ///nothing but the chunk, does not exceed the maximum message length of 1024 bytes.
+    pub fn max_data_len(type_name: &str) -> usize {
+        //"{4|" + message type + sequence number (at most 10 digits) + final flag + length prefix
+        //of the data (at most 4 digits)
+        let overhead = 3 + type_name.len().get_size() + type_name.len() + 2 + 14 + 4 + 6;
+        1024usize.saturating_sub(overhead)
+    }
+
+    ///Adds the arguments for this chunk to the given message.
+    ///
+    ///# Panics
+    ///
+    ///Panics if more arguments are being added than what has been announced in
+    ///`MessageFormatter::new()`.
+    pub fn add_to(&self, f: &mut msg::MessageFormatter<'_>) {
+        f.add_argument(&self.sequence);
+        f.add_argument(&self.is_final);
+        f.add_argument(self.data);
+    }
+
+    ///Decodes a chunk from the next three arguments of a message. Returns `None` if there are not
+    ///enough arguments or if they are malformed.
+    pub fn decode_arguments(args: &mut msg::MessageIterator<'a>) -> Option<Self> {
+        Some(Self {
+            sequence: u32::decode_argument(args.next()?)?,
+            is_final: bool::decode_argument(args.next()?)?,
+            data: args.next()?,
+        })
+    }
+}
+
+///Splits a payload into chunks of at most `max_len` bytes each. An empty payload yields a single
+///empty chunk, so that the receiving side always sees a final chunk. See [Chunk](struct.Chunk.html)
+///for an example.
+///
+///# Panics
+///
+///Panics if `max_len` is 0.
+pub fn chunks(payload: &[u8], max_len: usize) -> Chunks<'_> {
+    assert!(max_len > 0, "chunks() called with max_len = 0");
+    Chunks {
+        rest: Some(payload),
+        max_len,
+        sequence: 0,
+    }
+}
+
+///Iterator returned by [chunks()](fn.chunks.html).
+#[derive(Clone, Debug)]
+pub struct Chunks<'a> {
+    rest: Option<&'a [u8]>,
+    max_len: usize,
+    sequence: u32,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = Chunk<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest?;
+        let is_final = rest.len() <= self.max_len;
+        let (data, rest) = rest.split_at(if is_final { rest.len() } else { self.max_len });
+        self.rest = if is_final { None } else { Some(rest) };
+        let chunk = Chunk {
+            sequence: self.sequence,
+            is_final,
+            data,
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+        Some(chunk)
+    }
+}
+
+///Reassembles payloads that were split into [chunks](struct.Chunk.html).
+///
+///The reassembler enforces a maximum payload size, so that a misbehaving peer cannot make the
+///receiver allocate unbounded amounts of memory. After an error, the partially received payload
+///is discarded, and the reassembler expects the first chunk of a new payload.
+#[derive(Clone, Debug)]
+pub struct Reassembler {
+    max_size: usize,
+    next_sequence: u32,
+    buf: Vec<u8>,
+}
+
+impl Reassembler {
+    ///Creates a reassembler that accepts payloads of up to `max_size` bytes.
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            next_sequence: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    ///Adds a chunk to the payload that is being reassembled. When the final chunk has been
+    ///added, the complete payload is returned, and the reassembler is ready for the next payload.
+    pub fn push(&mut self, chunk: &Chunk<'_>) -> Result<Option<Vec<u8>>, ReassemblyError> {
+        if chunk.sequence != self.next_sequence {
+            let err = ReassemblyError::OutOfSequence {
+                expected: self.next_sequence,
+                actual: chunk.sequence,
+            };
+            self.reset();
+            return Err(err);
+        }
+        if self.buf.len() + chunk.data.len() > self.max_size {
+            self.reset();
+            return Err(ReassemblyError::TooLarge(self.max_size));
+        }
+
+        self.buf.extend_from_slice(chunk.data);
+        if chunk.is_final {
+            self.next_sequence = 0;
+            Ok(Some(core::mem::take(&mut self.buf)))
+        } else {
+            self.next_sequence = self.next_sequence.wrapping_add(1);
+            Ok(None)
+        }
+    }
+
+    ///Returns whether a payload is partially received.
+    pub fn is_in_progress(&self) -> bool {
+        self.next_sequence > 0
+    }
+
+    ///Discards the partially received payload, if any.
+    pub fn reset(&mut self) {
+        self.next_sequence = 0;
+        self.buf.clear();
+    }
+}
+
+///Error type returned by [Reassembler::push()](struct.Reassembler.html#method.push).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReassemblyError {
+    ///A chunk was received with a different sequence number than expected, i.e. chunks were lost,
+    ///duplicated or reordered.
+    OutOfSequence { expected: u32, actual: u32 },
+    ///The payload exceeds the maximum size (included in the error) of the reassembler.
+    TooLarge(usize),
+}
+
+impl fmt::Display for ReassemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::OutOfSequence { expected, actual } => write!(
+                f,
+                "expected chunk with sequence number {}, got {}",
+                expected, actual
+            ),
+            Self::TooLarge(max_size) => {
+                write!(f, "payload exceeds maximum size of {} bytes", max_size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReassemblyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks() {
+        let payload = b"abcdefgh";
+        let result: Vec<_> = chunks(payload, 3).collect();
+        assert_eq!(
+            result,
+            vec![
+                Chunk {
+                    sequence: 0,
+                    is_final: false,
+                    data: b"abc"
+                },
+                Chunk {
+                    sequence: 1,
+                    is_final: false,
+                    data: b"def"
+                },
+                Chunk {
+                    sequence: 2,
+                    is_final: true,
+                    data: b"gh"
+                },
+            ]
+        );
+        assert_eq!(chunks(b"abc", 3).count(), 1);
+        assert_eq!(
+            chunks(b"", 3).collect::<Vec<_>>(),
+            vec![Chunk {
+                sequence: 0,
+                is_final: true,
+                data: b""
+            }]
+        );
+    }
+
+    #[test]
+    fn test_max_data_len() {
+        let type_name = "example1.chunk";
+        let data = [b'x'; 1024];
+        let max_len = Chunk::max_data_len(type_name);
+        let chunk = Chunk {
+            sequence: u32::MAX,
+            is_final: false,
+            data: &data[0..max_len],
+        };
+        let mut buf = [0u8; 1024];
+        let mut f = msg::MessageFormatter::new(&mut buf, type_name, Chunk::NUM_ARGUMENTS);
+        chunk.add_to(&mut f);
+        assert!(f.finalize().is_ok());
+    }
+
+    #[test]
+    fn test_reassembler() {
+        let mut r = Reassembler::new(5);
+        let chunk = |sequence, is_final, data| Chunk {
+            sequence,
+            is_final,
+            data,
+        };
+
+        assert_eq!(r.push(&chunk(0, false, b"ab")), Ok(None));
+        assert!(r.is_in_progress());
+        assert_eq!(r.push(&chunk(1, true, b"cd")), Ok(Some(b"abcd".to_vec())));
+        assert!(!r.is_in_progress());
+
+        //chunks out of sequence discard the partial payload
+        assert_eq!(r.push(&chunk(0, false, b"ab")), Ok(None));
+        let err = r.push(&chunk(2, true, b"cd")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "expected chunk with sequence number 1, got 2"
+        );
+        assert!(!r.is_in_progress());
+
+        //payloads over the size limit are rejected
+        assert_eq!(r.push(&chunk(0, false, b"abc")), Ok(None));
+        let err = r.push(&chunk(1, true, b"def")).unwrap_err();
+        assert_eq!(err, ReassemblyError::TooLarge(5));
+        assert_eq!(
+            r.push(&chunk(0, true, b"abcde")),
+            Ok(Some(b"abcde".to_vec()))
+        );
+    }
+}
@@ -15,6 +15,11 @@
 ///formats defined for basic property types in
 ///[vt6/core1.0, section 2.1](https://vt6.io/std/core/1.0/#section-2-1).
 ///
+///The identifier types from this module (e.g. [ClientID](struct.ClientID.html)
+///or [ScreenID](struct.ScreenID.html)) borrow from the argument and are decoded
+///through their respective `parse()` functions, so they can be extracted from
+///a message directly without an intermediate `&str`.
+///
 ///The generic trait implementation for `Option<T>` decodes empty inputs as
 ///`None` and anything else as `Some` (except for parse errors).
 pub trait DecodeArgument<'a>: Sized {
@@ -46,7 +51,7 @@ impl<'a> DecodeArgument<'a> for &'a str {
     }
 }
 
-#[cfg(feature = "use_std")]
+#[cfg(all(feature = "use_std", unix))]
 impl<'a> DecodeArgument<'a> for &'a std::path::Path {
     fn decode_argument(arg: &'a [u8]) -> Option<Self> {
         use std::os::unix::ffi::OsStrExt;
@@ -55,6 +60,14 @@ impl<'a> DecodeArgument<'a> for &'a std::path::Path {
     }
 }
 
+//On other platforms, paths are not arbitrary byte strings, so only UTF-8 paths can be accepted.
+#[cfg(all(feature = "use_std", not(unix)))]
+impl<'a> DecodeArgument<'a> for &'a std::path::Path {
+    fn decode_argument(arg: &'a [u8]) -> Option<Self> {
+        core::str::from_utf8(arg).ok().map(std::path::Path::new)
+    }
+}
+
 impl<'a, T: DecodeArgument<'a>> DecodeArgument<'a> for Option<T> {
     fn decode_argument(arg: &'a [u8]) -> Option<Self> {
         if arg.is_empty() {
@@ -103,7 +116,8 @@ impl_DecodeArgument_via_parse_from_string!(
     crate::common::core::MessageType<'a>,
     crate::common::core::ModuleIdentifier<'a>,
     crate::common::core::ModuleVersion<'a>,
-    crate::common::core::ScopedIdentifier<'a>
+    crate::common::core::ScopedIdentifier<'a>,
+    crate::common::core::ScreenID<'a>
 );
 
 #[cfg(test)]
@@ -152,4 +166,34 @@ mod tests {
             assert_eq!(None, usize::decode_argument(input));
         }
     }
+
+    #[test]
+    fn test_decode_identifiers() {
+        //the decoded identifiers borrow from the input instead of copying it
+        let input = b"core1.set".to_vec();
+        let ident = ScopedIdentifier::decode_argument(&input).unwrap();
+        assert_eq!(ident.as_str().as_ptr(), input.as_ptr());
+
+        assert_eq!(ClientID::decode_argument(b"a1"), ClientID::parse("a1"));
+        assert_eq!(ClientID::decode_argument(b"a-1"), None);
+        assert_eq!(
+            ScreenID::decode_argument(b"tab-1"),
+            ScreenID::parse("tab-1")
+        );
+        assert_eq!(ScreenID::decode_argument(b"tab 1"), None);
+        assert_eq!(ScreenID::decode_argument(b"\xFF"), None);
+        assert_eq!(
+            ModuleIdentifier::decode_argument(b"core1"),
+            ModuleIdentifier::parse("core1")
+        );
+        assert_eq!(ModuleIdentifier::decode_argument(b"core"), None);
+
+        //identifiers in optional arguments
+        assert_eq!(Option::<ScreenID>::decode_argument(b""), Some(None));
+        assert_eq!(
+            Option::<ScreenID>::decode_argument(b"1"),
+            Some(ScreenID::parse("1"))
+        );
+        assert_eq!(Option::<ScreenID>::decode_argument(b"/"), None);
+    }
 }
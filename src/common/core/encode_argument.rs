@@ -93,8 +93,9 @@ impl EncodeArgument for str {
 #[cfg(feature = "use_std")]
 impl EncodedArgument for std::path::Path {
     fn encoded(&self) -> &[u8] {
-        use std::os::unix::ffi::OsStrExt;
-        self.as_os_str().as_bytes()
+        //On Unix, this is the same as `OsStrExt::as_bytes()`. On Windows, this is WTF-8, which is
+        //plain UTF-8 unless the path contains unpaired surrogates.
+        self.as_os_str().as_encoded_bytes()
     }
 }
 
@@ -108,18 +109,18 @@ impl EncodeArgument for bool {
     }
 }
 
-impl<'a, T> EncodeArgument for Option<&'a T>
+impl<T> EncodeArgument for Option<&T>
 where
     T: EncodeArgument + ?Sized,
 {
     fn get_size(&self) -> usize {
         match *self {
             None => 0,
-            Some(ref val) => val.get_size(),
+            Some(val) => val.get_size(),
         }
     }
     fn encode(&self, buf: &mut [u8]) {
-        if let Some(ref val) = *self {
+        if let Some(val) = *self {
             val.encode(buf);
         }
     }
@@ -191,6 +192,7 @@ impl_EncodeArgument_for_integer!(isize, usize: u32);
 impl_EncodeArgument_for_integer!(isize, usize: u64);
 
 #[cfg(test)]
+#[allow(clippy::legacy_numeric_constants)]
 mod tests {
 
     use crate::common::core::*;
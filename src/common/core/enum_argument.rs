@@ -0,0 +1,185 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+///Defines an enum whose values are encoded as identifier strings in message arguments.
+///
+///Many arguments in VT6 messages take one of a small set of identifiers, e.g. `on`/`off` or the
+///names of signals. For an enum declared inside this macro, with an identifier string assigned to
+///each variant, the macro generates:
+///
+///* an `as_str()` method that returns the identifier for a value,
+///* an implementation of [EncodedArgument](common/core/trait.EncodedArgument.html) (and thus
+///  [EncodeArgument](common/core/trait.EncodeArgument.html)) that encodes a value as its
+///  identifier, and
+///* an implementation of [DecodeArgument](common/core/trait.DecodeArgument.html) that decodes
+///  the identifiers back into values.
+///
+///```
+///# use vt6::common::core::{DecodeArgument, EncodeArgument};
+///vt6::vt6_enum_argument! {
+///    ///The shape of the text cursor.
+///    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+///    pub enum CursorShape {
+///        Block = "block",
+///        ///A vertical bar between two cells.
+///        Bar = "bar",
+///        Underline = "underline",
+///    }
+///}
+///
+///assert_eq!(CursorShape::Bar.as_str(), "bar");
+///assert_eq!(CursorShape::Underline.encode_to_vector(), b"underline");
+///assert_eq!(CursorShape::decode_argument(b"block"), Some(CursorShape::Block));
+///assert_eq!(CursorShape::decode_argument(b"beam"), None);
+///```
+///
+///By default, decoding an unknown identifier fails, so the message containing it is rejected as
+///invalid. When a later version of a module may add identifiers that older peers should tolerate,
+///a fallback variant can be declared with `_` in place of its identifier. Unknown identifiers
+///then decode into that variant instead. Since the fallback variant does not remember the
+///identifier that it was decoded from, it is encoded as an empty argument (which is not decoded
+///into the fallback variant, but rejected as invalid).
+///
+///```
+///# use vt6::common::core::{DecodeArgument, EncodeArgument};
+///vt6::vt6_enum_argument! {
+///    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+///    pub enum CursorShape {
+///        Block = "block",
+///        Bar = "bar",
+///        ///A shape that was added in a later version of the module.
+///        Unknown = _,
+///    }
+///}
+///
+///assert_eq!(CursorShape::decode_argument(b"beam"), Some(CursorShape::Unknown));
+///assert_eq!(CursorShape::decode_argument(b""), None);
+///assert_eq!(CursorShape::Unknown.encode_to_vector(), b"");
+///```
+#[macro_export]
+macro_rules! vt6_enum_argument {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $( $(#[$vmeta:meta])* $variant:ident = $value:tt ),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $( $(#[$vmeta])* $variant, )+
+        }
+
+        impl $name {
+            ///Returns the identifier that represents this value in message arguments.
+            pub fn as_str(&self) -> &'static str {
+                match *self {
+                    $( Self::$variant => $crate::__vt6_enum_argument_str!($value), )+
+                }
+            }
+        }
+
+        impl $crate::common::core::EncodedArgument for $name {
+            fn encoded(&self) -> &[u8] {
+                self.as_str().as_bytes()
+            }
+        }
+
+        impl<'a> $crate::common::core::DecodeArgument<'a> for $name {
+            fn decode_argument(arg: &'a [u8]) -> Option<Self> {
+                $( $crate::__vt6_enum_argument_decode!(arg, Self::$variant, $value); )+
+                $( $crate::__vt6_enum_argument_fallback!(arg, Self::$variant, $value); )+
+                None
+            }
+        }
+    };
+}
+
+//The helpers below treat the identifier `_` of a fallback variant differently from string
+//literals. They are only exported because the expansion of vt6_enum_argument! refers to them.
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __vt6_enum_argument_str {
+    (_) => {
+        ""
+    };
+    ($value:literal) => {
+        $value
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __vt6_enum_argument_decode {
+    ($arg:ident, $variant:path, _) => {};
+    ($arg:ident, $variant:path, $value:literal) => {
+        if $arg == $value.as_bytes() {
+            return Some($variant);
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __vt6_enum_argument_fallback {
+    ($arg:ident, $variant:path, _) => {
+        if !$arg.is_empty() {
+            return Some($variant);
+        }
+    };
+    ($arg:ident, $variant:path, $value:literal) => {};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::core::{DecodeArgument, EncodeArgument};
+
+    crate::vt6_enum_argument! {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        enum Switch {
+            On = "on",
+            Off = "off",
+            Toggle = "toggle",
+        }
+    }
+
+    crate::vt6_enum_argument! {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        enum Color {
+            //the fallback must not shadow the variants declared after it
+            Other = _,
+            Red = "red",
+            Blue = "blue",
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for value in &[Switch::On, Switch::Off, Switch::Toggle] {
+            let encoded = value.encode_to_vector();
+            assert_eq!(encoded, value.as_str().as_bytes());
+            assert_eq!(Switch::decode_argument(&encoded), Some(*value));
+        }
+        for value in &[Color::Red, Color::Blue] {
+            let encoded = value.encode_to_vector();
+            assert_eq!(encoded, value.as_str().as_bytes());
+            assert_eq!(Color::decode_argument(&encoded), Some(*value));
+        }
+    }
+
+    #[test]
+    fn test_unknown_variant() {
+        for input in &[&b"maybe"[..], b"", b"On", b"of", b"offf", b"on "] {
+            assert_eq!(Switch::decode_argument(input), None, "{:?}", input);
+        }
+
+        assert_eq!(Color::decode_argument(b"green"), Some(Color::Other));
+        assert_eq!(Color::decode_argument(b"Red"), Some(Color::Other));
+        assert_eq!(Color::decode_argument(b""), None);
+        //the fallback variant does not survive a roundtrip
+        assert_eq!(Color::Other.encode_to_vector(), b"");
+    }
+}
@@ -0,0 +1,382 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use super::*;
+
+//The types in this file describe positions and areas on a screen, measured in character cells.
+//Each of them is encoded into a single message argument:
+//
+//    Point   "x,y"        e.g. "3,5"
+//    Size    "WxH"        e.g. "80x24"
+//    Rect    "WxH+x+y"    e.g. "80x24+3+5" (like the geometry strings of X11 programs)
+//
+//All numbers are decimal and consist of digits only (no signs, no leading zeroes).
+
+////////////////////////////////////////////////////////////////////////////////
+// Point
+
+///The position of a character cell on a screen. Columns (`x`) and rows (`y`) are counted from
+///zero, starting at the top left corner.
+///
+///In message arguments, a point is encoded as `x,y`.
+///
+///```
+///# use vt6::common::core::*;
+///let point = Point { x: 3, y: 5 };
+///assert_eq!(point.encode_to_vector(), b"3,5");
+///assert_eq!(Point::decode_argument(b"3,5"), Some(point));
+///assert_eq!(Point::decode_argument(b"3,-5"), None);
+///```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Point {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl core::fmt::Display for Point {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{},{}", self.x, self.y)
+    }
+}
+
+impl EncodeArgument for Point {
+    fn get_size(&self) -> usize {
+        self.x.get_size() + 1 + self.y.get_size()
+    }
+    fn encode(&self, buf: &mut [u8]) {
+        let s = self.x.get_size();
+        self.x.encode(&mut buf[0..s]);
+        buf[s] = b',';
+        self.y.encode(&mut buf[(s + 1)..]);
+    }
+}
+
+impl<'a> DecodeArgument<'a> for Point {
+    fn decode_argument(arg: &'a [u8]) -> Option<Self> {
+        let (x, y) = split_at_byte(arg, b',')?;
+        Some(Point {
+            x: decode_cells(x)?,
+            y: decode_cells(y)?,
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Size
+
+///The size of an area on a screen, measured in character cells. Both dimensions are always
+///non-zero.
+///
+///In message arguments, a size is encoded as `WxH`.
+///
+///```
+///# use vt6::common::core::*;
+///let size = Size::new(80, 24).unwrap();
+///assert_eq!(size.encode_to_vector(), b"80x24");
+///assert_eq!(Size::decode_argument(b"80x24"), Some(size));
+///assert_eq!(Size::decode_argument(b"80x0"), None);
+///```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Size {
+    width: u32,
+    height: u32,
+}
+
+impl core::fmt::Display for Size {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl EncodeArgument for Size {
+    fn get_size(&self) -> usize {
+        self.width.get_size() + 1 + self.height.get_size()
+    }
+    fn encode(&self, buf: &mut [u8]) {
+        let s = self.width.get_size();
+        self.width.encode(&mut buf[0..s]);
+        buf[s] = b'x';
+        self.height.encode(&mut buf[(s + 1)..]);
+    }
+}
+
+impl<'a> DecodeArgument<'a> for Size {
+    fn decode_argument(arg: &'a [u8]) -> Option<Self> {
+        let (width, height) = split_at_byte(arg, b'x')?;
+        Self::new(decode_cells(width)?, decode_cells(height)?)
+    }
+}
+
+impl Size {
+    ///Returns a size with the given dimensions, or `None` if one of them is zero.
+    pub fn new(width: u32, height: u32) -> Option<Self> {
+        if width == 0 || height == 0 {
+            return None;
+        }
+        Some(Size { width, height })
+    }
+
+    ///Returns the number of columns.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    ///Returns the number of rows.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    ///Returns the number of cells in an area of this size.
+    pub fn area(&self) -> u64 {
+        u64::from(self.width) * u64::from(self.height)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Rect
+
+///A rectangular area on a screen, consisting of the cells from `origin()` (inclusive) to `end()`
+///(exclusive). Since its size is a [Size](struct.Size.html), a rectangle is never empty.
+///
+///In message arguments, a rectangle is encoded as `WxH+x+y`, where `x,y` is the origin.
+///
+///```
+///# use vt6::common::core::*;
+///let rect = Rect::new(Point { x: 3, y: 5 }, Size::new(80, 24).unwrap()).unwrap();
+///assert_eq!(rect.end(), Point { x: 83, y: 29 });
+///assert_eq!(rect.encode_to_vector(), b"80x24+3+5");
+///assert_eq!(Rect::decode_argument(b"80x24+3+5"), Some(rect));
+///```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Rect {
+    origin: Point,
+    size: Size,
+}
+
+impl core::fmt::Display for Rect {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}+{}+{}", self.size, self.origin.x, self.origin.y)
+    }
+}
+
+impl EncodeArgument for Rect {
+    fn get_size(&self) -> usize {
+        self.size.get_size() + 1 + self.origin.x.get_size() + 1 + self.origin.y.get_size()
+    }
+    fn encode(&self, buf: &mut [u8]) {
+        let s = self.size.get_size();
+        self.size.encode(&mut buf[0..s]);
+        buf[s] = b'+';
+        let t = s + 1 + self.origin.x.get_size();
+        self.origin.x.encode(&mut buf[(s + 1)..t]);
+        buf[t] = b'+';
+        self.origin.y.encode(&mut buf[(t + 1)..]);
+    }
+}
+
+impl<'a> DecodeArgument<'a> for Rect {
+    fn decode_argument(arg: &'a [u8]) -> Option<Self> {
+        let (size, origin) = split_at_byte(arg, b'+')?;
+        let (x, y) = split_at_byte(origin, b'+')?;
+        let origin = Point {
+            x: decode_cells(x)?,
+            y: decode_cells(y)?,
+        };
+        Self::new(origin, Size::decode_argument(size)?)
+    }
+}
+
+impl Rect {
+    ///Returns a rectangle with the given origin and size, or `None` if the rectangle extends past
+    ///the largest representable coordinate (i.e. if `end()` would overflow).
+    pub fn new(origin: Point, size: Size) -> Option<Self> {
+        origin.x.checked_add(size.width)?;
+        origin.y.checked_add(size.height)?;
+        Some(Rect { origin, size })
+    }
+
+    ///Returns the top left cell of this rectangle.
+    pub fn origin(&self) -> Point {
+        self.origin
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    ///Returns the point just past the bottom right cell of this rectangle.
+    pub fn end(&self) -> Point {
+        Point {
+            x: self.origin.x + self.size.width,
+            y: self.origin.y + self.size.height,
+        }
+    }
+
+    ///Returns whether the given cell lies within this rectangle.
+    pub fn contains(&self, point: Point) -> bool {
+        let end = self.end();
+        (self.origin.x..end.x).contains(&point.x) && (self.origin.y..end.y).contains(&point.y)
+    }
+
+    ///Returns the area that is covered by both rectangles, or `None` if they do not overlap.
+    ///
+    ///```
+    ///# use vt6::common::core::*;
+    ///let screen = Rect::decode_argument(b"80x24+0+0").unwrap();
+    ///let window = Rect::decode_argument(b"20x10+70+20").unwrap();
+    ///assert_eq!(screen.intersection(&window).unwrap().to_string(), "10x4+70+20");
+    ///assert_eq!(screen.intersection(&Rect::decode_argument(b"1x1+80+0").unwrap()), None);
+    ///```
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let (end, other_end) = (self.end(), other.end());
+        let origin = Point {
+            x: self.origin.x.max(other.origin.x),
+            y: self.origin.y.max(other.origin.y),
+        };
+        let width = end.x.min(other_end.x).checked_sub(origin.x)?;
+        let height = end.y.min(other_end.y).checked_sub(origin.y)?;
+        Some(Rect {
+            origin,
+            size: Size::new(width, height)?,
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// helper functions
+
+fn split_at_byte(arg: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let idx = arg.iter().position(|&b| b == separator)?;
+    Some((&arg[0..idx], &arg[(idx + 1)..]))
+}
+
+//Unlike the generic integer decoding, this does not accept a leading plus sign, which would be
+//confusing next to the plus signs that separate the components of a Rect.
+fn decode_cells(arg: &[u8]) -> Option<u32> {
+    if !arg.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    u32::decode_argument(arg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_roundtrip<T>(val: T, encoded: &str)
+    where
+        T: EncodeArgument + for<'a> DecodeArgument<'a> + core::fmt::Display + core::fmt::Debug + Eq,
+    {
+        assert_eq!(val.encode_to_vector(), encoded.as_bytes());
+        assert_eq!(val.to_string(), encoded);
+        assert_eq!(T::decode_argument(encoded.as_bytes()), Some(val));
+    }
+
+    #[test]
+    fn test_encode_geometry() {
+        check_roundtrip(Point { x: 0, y: 0 }, "0,0");
+        check_roundtrip(Point { x: 132, y: 7 }, "132,7");
+        check_roundtrip(
+            Point {
+                x: u32::MAX,
+                y: u32::MAX,
+            },
+            "4294967295,4294967295",
+        );
+        check_roundtrip(Size::new(1, 1).unwrap(), "1x1");
+        check_roundtrip(Size::new(80, 24).unwrap(), "80x24");
+        check_roundtrip(
+            Rect::new(Point { x: 0, y: 10 }, Size::new(132, 43).unwrap()).unwrap(),
+            "132x43+0+10",
+        );
+        check_roundtrip(
+            Rect::new(
+                Point {
+                    x: u32::MAX - 1,
+                    y: 0,
+                },
+                Size::new(1, 1).unwrap(),
+            )
+            .unwrap(),
+            "1x1+4294967294+0",
+        );
+    }
+
+    #[test]
+    fn test_decode_geometry_fails() {
+        for input in &[
+            "",
+            ",",
+            "1",
+            "1,",
+            ",1",
+            "1,2,3",
+            "01,2",
+            "1,-2",
+            "+1,2",
+            "1, 2",
+            "4294967296,0",
+        ] {
+            assert_eq!(
+                Point::decode_argument(input.as_bytes()),
+                None,
+                "{:?}",
+                input
+            );
+        }
+        for input in &[
+            "", "x", "80", "80x", "x24", "0x24", "80x0", "80x24x1", "080x24", "80X24", "+80x24",
+        ] {
+            assert_eq!(Size::decode_argument(input.as_bytes()), None, "{:?}", input);
+        }
+        for input in &[
+            "",
+            "80x24",
+            "80x24+1",
+            "80x24+1+",
+            "80x24++1+2",
+            "80x24+1++2",
+            "80x24+1+2+3",
+            "0x24+1+2",
+            "80x24+-1+2",
+            "2x1+4294967294+0",
+        ] {
+            assert_eq!(Rect::decode_argument(input.as_bytes()), None, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_rect_geometry() {
+        fn point(x: u32, y: u32) -> Point {
+            Point { x, y }
+        }
+
+        assert_eq!(Size::new(0, 1), None);
+        assert_eq!(Size::new(1, 0), None);
+        let max = Size::new(u32::MAX, u32::MAX).unwrap();
+        assert_eq!(max.area(), 18446744065119617025);
+
+        let size = Size::new(2, 3).unwrap();
+        assert!(Rect::new(point(u32::MAX - 2, 0), size).is_some());
+        assert_eq!(Rect::new(point(u32::MAX - 1, 0), size), None);
+        assert_eq!(Rect::new(point(0, u32::MAX - 2), size), None);
+
+        let rect = Rect::new(point(10, 20), size).unwrap();
+        assert!(rect.contains(point(10, 20)));
+        assert!(rect.contains(point(11, 22)));
+        assert!(!rect.contains(point(12, 22)));
+        assert!(!rect.contains(point(11, 23)));
+        assert!(!rect.contains(point(9, 21)));
+
+        assert_eq!(rect.intersection(&rect), Some(rect));
+        let other = Rect::new(point(11, 0), Size::new(5, 21).unwrap()).unwrap();
+        assert_eq!(rect.intersection(&other).unwrap().to_string(), "1x1+11+20");
+        assert_eq!(other.intersection(&rect), rect.intersection(&other));
+        //rectangles that only touch each other do not overlap
+        let other = Rect::new(point(12, 20), size).unwrap();
+        assert_eq!(rect.intersection(&other), None);
+    }
+}
@@ -16,7 +16,7 @@ use super::*;
 ///
 ///Instances of this type can be created through a successful `parse()` or
 ///[`decode_argument()`](trait.DecodeArgument.html).
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ClientID<'a>(&'a str);
 
 //TODO impl Deref?
@@ -71,37 +71,217 @@ impl<'a> ClientID<'a> {
 }
 
 fn is_client_id_char(ch: char) -> bool {
-    ('A'..='Z').contains(&ch) || ('a'..='z').contains(&ch) || ('0'..='9').contains(&ch)
+    ch.is_ascii_alphanumeric()
 }
 
-//Like a ClientID, but owns the allocation backing the contained string. This type is internal for
-//now, and appears e.g. in vt6::server::ClientIdentity. It must be defined in this module to be
-//able to construct ClientID instances without re-parsing.
-#[cfg(feature = "use_std")]
-#[derive(Clone)]
-pub(crate) struct OwnedClientID(String);
-
-#[cfg(feature = "use_std")]
+///Like a [ClientID](struct.ClientID.html), but owns the allocation backing the contained string.
+///This type appears e.g. in [vt6::server::ClientIdentity](../../server/struct.ClientIdentity.html),
+///and can be used as a key in maps that are indexed by client ID. It is only available with the
+///`use_std` and `module_posix` features.
+///
+///```
+///# use vt6::common::core::*;
+///let mut clients = std::collections::BTreeMap::new();
+///clients.insert(OwnedClientID::from(&ClientID::parse("b").unwrap()), 2);
+///clients.insert(OwnedClientID::from(&ClientID::parse("a").unwrap()), 1);
+///let ids: Vec<_> = clients.keys().map(|id| id.as_ref().as_str()).collect();
+///assert_eq!(ids, vec!["a", "b"]);
+///```
+#[cfg(all(feature = "use_std", feature = "module_posix"))]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OwnedClientID(String);
+
+#[cfg(all(feature = "use_std", feature = "module_posix"))]
 impl<'a, 'b> From<&'a ClientID<'b>> for OwnedClientID {
     fn from(id: &'a ClientID<'b>) -> OwnedClientID {
         OwnedClientID(id.0.into())
     }
 }
 
-#[cfg(feature = "use_std")]
+#[cfg(all(feature = "use_std", feature = "module_posix"))]
 impl core::fmt::Debug for OwnedClientID {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         write!(f, "ClientID::parse({:?})", &self.0)
     }
 }
 
-#[cfg(feature = "use_std")]
+#[cfg(all(feature = "use_std", feature = "module_posix"))]
+impl core::fmt::Display for OwnedClientID {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(all(feature = "use_std", feature = "module_posix"))]
+impl EncodedArgument for OwnedClientID {
+    fn encoded(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+#[cfg(all(feature = "use_std", feature = "module_posix"))]
 impl OwnedClientID {
-    pub(crate) fn as_ref(&self) -> ClientID<'_> {
+    ///Returns a borrowed ClientID with the same value.
+    pub fn as_ref(&self) -> ClientID<'_> {
         ClientID(&self.0)
     }
 }
 
+//OwnedClientID is serialized as a plain string. Deserialization validates the string like
+//ClientID::parse() does, so that invalid client IDs cannot sneak in through persisted state.
+#[cfg(all(feature = "use_serde", feature = "module_posix"))]
+impl serde::Serialize for OwnedClientID {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+#[cfg(all(feature = "use_serde", feature = "module_posix"))]
+impl<'de> serde::Deserialize<'de> for OwnedClientID {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match ClientID::parse(&s) {
+            Some(id) => Ok((&id).into()),
+            None => Err(serde::de::Error::invalid_value(
+                serde::de::Unexpected::Str(&s),
+                &"a client ID",
+            )),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// ScreenID
+
+///A screen ID, as it appears e.g. in the `posix1.server-hello` message.
+///
+///Screen IDs are chosen by the terminal and treated as opaque strings by clients. To keep them
+///safe for use in file names, environment variables and log messages, a valid screen ID is
+///non-empty and consists only of ASCII letters, digits, dashes, underscores and dots.
+///
+///Instances of this type can be created through a successful `parse()` or
+///[`decode_argument()`](trait.DecodeArgument.html).
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ScreenID<'a>(&'a str);
+
+impl<'a> core::fmt::Debug for ScreenID<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "ScreenID::parse({:?})", self.0)
+    }
+}
+
+impl<'a> core::fmt::Display for ScreenID<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<'a> EncodedArgument for ScreenID<'a> {
+    fn encoded(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl<'a> ScreenID<'a> {
+    ///Converts the given input string into a ScreenID instance. Returns None if the input is not
+    ///a valid screen ID.
+    ///
+    ///```
+    ///# use vt6::common::core::*;
+    ///assert!(ScreenID::parse("screen1").is_some());
+    ///assert!(ScreenID::parse("tab-2.split_1").is_some());
+    ///assert!(ScreenID::parse("").is_none());
+    ///assert!(ScreenID::parse("screen 1").is_none());
+    ///assert!(ScreenID::parse("screen/1").is_none());
+    ///```
+    pub fn parse(input: &'a str) -> Option<Self> {
+        if input.is_empty() {
+            return None;
+        }
+        if input.chars().all(is_screen_id_char) {
+            Some(ScreenID(input))
+        } else {
+            None
+        }
+    }
+
+    ///Returns the string value of this screen ID. This is the same string that was originally
+    ///passed into parse().
+    pub fn as_str(&'_ self) -> &'a str {
+        self.0
+    }
+}
+
+fn is_screen_id_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' || ch == '.'
+}
+
+///Like a [ScreenID](struct.ScreenID.html), but owns the allocation backing the contained string.
+///This type appears e.g. in [vt6::server::ScreenIdentity](../../server/struct.ScreenIdentity.html).
+///It is only available with the `use_std` feature.
+#[cfg(feature = "use_std")]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OwnedScreenID(String);
+
+#[cfg(feature = "use_std")]
+impl<'a, 'b> From<&'a ScreenID<'b>> for OwnedScreenID {
+    fn from(id: &'a ScreenID<'b>) -> OwnedScreenID {
+        OwnedScreenID(id.0.into())
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl core::fmt::Debug for OwnedScreenID {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "ScreenID::parse({:?})", &self.0)
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl core::fmt::Display for OwnedScreenID {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl EncodedArgument for OwnedScreenID {
+    fn encoded(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl OwnedScreenID {
+    ///Returns a borrowed ScreenID with the same value.
+    pub fn as_ref(&self) -> ScreenID<'_> {
+        ScreenID(&self.0)
+    }
+}
+
+//Like OwnedClientID, OwnedScreenID is serialized as a plain string and validated on
+//deserialization.
+#[cfg(feature = "use_serde")]
+impl serde::Serialize for OwnedScreenID {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+#[cfg(feature = "use_serde")]
+impl<'de> serde::Deserialize<'de> for OwnedScreenID {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match ScreenID::parse(&s) {
+            Some(id) => Ok((&id).into()),
+            None => Err(serde::de::Error::invalid_value(
+                serde::de::Unexpected::Str(&s),
+                &"a screen ID",
+            )),
+        }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Identifier
 
@@ -186,15 +366,15 @@ fn parse_ident_or_module_ident(input: &str) -> Option<(Identifier<'_>, Option<u1
 }
 
 fn is_ident_leader(ch: char) -> bool {
-    ('A'..='Z').contains(&ch) || ('a'..='z').contains(&ch) || ch == '_'
+    ch.is_ascii_alphabetic() || ch == '_'
 }
 
 fn is_ident_char(ch: char) -> bool {
-    ('A'..='Z').contains(&ch) || ('a'..='z').contains(&ch) || ch == '_' || ch == '-'
+    ch.is_ascii_alphabetic() || ch == '_' || ch == '-'
 }
 
 fn is_digit(ch: char) -> bool {
-    ('0'..='9').contains(&ch)
+    ch.is_ascii_digit()
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -288,6 +468,161 @@ impl<'a> ModuleIdentifier<'a> {
     }
 }
 
+///Like a [ModuleIdentifier](struct.ModuleIdentifier.html), but owns the allocation backing the
+///contained string, so that it can be stored beyond the lifetime of the message that it came from,
+///e.g. in a cache of negotiated modules. It is only available with the `use_std` feature.
+///
+///```
+///# use vt6::common::core::*;
+///let owned = {
+///    let input = String::from("core3");
+///    OwnedModuleIdentifier::from(&ModuleIdentifier::parse(&input).unwrap())
+///};
+///assert_eq!(owned.name().as_str(), "core");
+///assert_eq!(owned.major_version(), 3);
+///assert_eq!(owned.as_ref(), ModuleIdentifier::parse("core3").unwrap());
+///assert_eq!(owned.with_minor_version(4).to_string(), "core3.4");
+///```
+#[cfg(feature = "use_std")]
+#[derive(Clone)]
+pub struct OwnedModuleIdentifier {
+    source: String,
+    //the length of the name, i.e. the offset where the major version starts in `source`
+    name_len: usize,
+    major_version: u16,
+}
+
+#[cfg(feature = "use_std")]
+impl<'a, 'b> From<&'a ModuleIdentifier<'b>> for OwnedModuleIdentifier {
+    fn from(id: &'a ModuleIdentifier<'b>) -> OwnedModuleIdentifier {
+        OwnedModuleIdentifier {
+            source: id.source.into(),
+            name_len: id.name.0.len(),
+            major_version: id.major_version,
+        }
+    }
+}
+
+//Equality, ordering and hashing only consider the string representation (which determines all
+//other fields), so that OwnedModuleIdentifier can implement Borrow<str> for use as a map key.
+#[cfg(feature = "use_std")]
+impl PartialEq for OwnedModuleIdentifier {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl Eq for OwnedModuleIdentifier {}
+
+#[cfg(feature = "use_std")]
+impl PartialOrd for OwnedModuleIdentifier {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl Ord for OwnedModuleIdentifier {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.source.cmp(&other.source)
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl core::hash::Hash for OwnedModuleIdentifier {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.source.hash(state)
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl core::borrow::Borrow<str> for OwnedModuleIdentifier {
+    fn borrow(&self) -> &str {
+        &self.source
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl core::fmt::Debug for OwnedModuleIdentifier {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "ModuleIdentifier::parse({:?})", &self.source)
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl core::fmt::Display for OwnedModuleIdentifier {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        self.source.fmt(f)
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl EncodedArgument for OwnedModuleIdentifier {
+    fn encoded(&self) -> &[u8] {
+        self.source.as_bytes()
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl OwnedModuleIdentifier {
+    ///Returns a borrowed ModuleIdentifier with the same value.
+    pub fn as_ref(&self) -> ModuleIdentifier<'_> {
+        ModuleIdentifier {
+            source: &self.source,
+            name: self.name(),
+            major_version: self.major_version,
+        }
+    }
+
+    ///Same as [`ModuleIdentifier::as_str()`](struct.ModuleIdentifier.html#method.as_str).
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    ///Same as [`ModuleIdentifier::name()`](struct.ModuleIdentifier.html#method.name).
+    pub fn name(&self) -> Identifier<'_> {
+        Identifier(&self.source[0..self.name_len])
+    }
+
+    ///Same as [`ModuleIdentifier::major_version()`](struct.ModuleIdentifier.html#method.major_version).
+    pub fn major_version(&self) -> u16 {
+        self.major_version
+    }
+
+    ///Same as
+    ///[`ModuleIdentifier::with_minor_version()`](struct.ModuleIdentifier.html#method.with_minor_version).
+    pub fn with_minor_version(&self, minor_version: u16) -> OwnedModuleVersion {
+        OwnedModuleVersion {
+            module: self.clone(),
+            minor_version,
+        }
+    }
+}
+
+//Like OwnedScreenID, OwnedModuleIdentifier is serialized as a plain string and validated on
+//deserialization.
+#[cfg(feature = "use_serde")]
+impl serde::Serialize for OwnedModuleIdentifier {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+#[cfg(feature = "use_serde")]
+impl<'de> serde::Deserialize<'de> for OwnedModuleIdentifier {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match ModuleIdentifier::parse(&s) {
+            Some(id) => Ok((&id).into()),
+            None => Err(serde::de::Error::invalid_value(
+                serde::de::Unexpected::Str(&s),
+                &"a module identifier",
+            )),
+        }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // ModuleVersion
 
@@ -358,6 +693,94 @@ impl<'a> ModuleVersion<'a> {
     }
 }
 
+///Like a [ModuleVersion](struct.ModuleVersion.html), but owns the allocation backing the
+///contained string. This is to ModuleVersion what
+///[OwnedModuleIdentifier](struct.OwnedModuleIdentifier.html) is to ModuleIdentifier. It is only
+///available with the `use_std` feature.
+///
+///```
+///# use vt6::common::core::*;
+///let owned = OwnedModuleVersion::from(&ModuleVersion::parse("term2.3").unwrap());
+///assert_eq!(owned.module().as_str(), "term2");
+///assert_eq!(owned.minor_version(), 3);
+///assert_eq!(owned.as_ref(), ModuleVersion::parse("term2.3").unwrap());
+///```
+#[cfg(feature = "use_std")]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OwnedModuleVersion {
+    module: OwnedModuleIdentifier,
+    minor_version: u16,
+}
+
+#[cfg(feature = "use_std")]
+impl<'a, 'b> From<&'a ModuleVersion<'b>> for OwnedModuleVersion {
+    fn from(v: &'a ModuleVersion<'b>) -> OwnedModuleVersion {
+        OwnedModuleVersion {
+            module: (&v.module).into(),
+            minor_version: v.minor_version,
+        }
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl core::fmt::Display for OwnedModuleVersion {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}.{}", self.module, self.minor_version)
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl EncodeArgument for OwnedModuleVersion {
+    fn get_size(&self) -> usize {
+        self.as_ref().get_size()
+    }
+    fn encode(&self, buf: &mut [u8]) {
+        self.as_ref().encode(buf)
+    }
+}
+
+#[cfg(feature = "use_std")]
+impl OwnedModuleVersion {
+    ///Returns a borrowed ModuleVersion with the same value.
+    pub fn as_ref(&self) -> ModuleVersion<'_> {
+        ModuleVersion {
+            module: self.module.as_ref(),
+            minor_version: self.minor_version,
+        }
+    }
+
+    ///Same as [`ModuleVersion::module()`](struct.ModuleVersion.html#method.module).
+    pub fn module(&self) -> ModuleIdentifier<'_> {
+        self.module.as_ref()
+    }
+
+    ///Same as [`ModuleVersion::minor_version()`](struct.ModuleVersion.html#method.minor_version).
+    pub fn minor_version(&self) -> u16 {
+        self.minor_version
+    }
+}
+
+#[cfg(feature = "use_serde")]
+impl serde::Serialize for OwnedModuleVersion {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "use_serde")]
+impl<'de> serde::Deserialize<'de> for OwnedModuleVersion {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match ModuleVersion::parse(&s) {
+            Some(v) => Ok((&v).into()),
+            None => Err(serde::de::Error::invalid_value(
+                serde::de::Unexpected::Str(&s),
+                &"a module version",
+            )),
+        }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // ScopedIdentifier
 
@@ -433,6 +856,23 @@ impl<'a> ScopedIdentifier<'a> {
     pub fn member(&'a self) -> Identifier<'a> {
         self.member
     }
+
+    ///Splits this scoped identifier into the module name, the major version and the member name.
+    ///This is useful for handlers that support several major versions of the same module, and
+    ///need to dispatch the same member name differently depending on the version.
+    ///
+    ///```
+    ///# use vt6::common::core::*;
+    ///let ident = ScopedIdentifier::parse("core2.set").unwrap();
+    ///assert_eq!(ident.route(), ("core", 2, "set"));
+    ///```
+    pub fn route(&self) -> (&'a str, u16, &'a str) {
+        (
+            self.module.name.as_str(),
+            self.module.major_version,
+            self.member.as_str(),
+        )
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -486,6 +926,29 @@ impl<'a> MessageType<'a> {
             Scoped(ref s) => s.as_str(),
         }
     }
+
+    ///For scoped message types, returns the module name, the major version and the member name
+    ///(see [`ScopedIdentifier::route()`](struct.ScopedIdentifier.html#method.route)). Returns
+    ///`None` for eternal message types.
+    ///
+    ///```
+    ///# use vt6::common::core::*;
+    ///# fn handle_set_v1() {}
+    ///# fn handle_set_v2() {}
+    ///let msg_type = MessageType::parse("core2.set").unwrap();
+    ///match msg_type.route() {
+    ///    Some(("core", 1, "set")) => handle_set_v1(),
+    ///    Some(("core", 2, "set")) => handle_set_v2(),
+    ///    _ => panic!("unexpected message type"),
+    ///}
+    ///assert_eq!(MessageType::parse("want").unwrap().route(), None);
+    ///```
+    pub fn route(&self) -> Option<(&'a str, u16, &'a str)> {
+        match *self {
+            Scoped(ref s) => Some(s.route()),
+            _ => None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -584,6 +1047,17 @@ mod tests {
         assert_eq!(ModuleVersion::parse(input), None);
     }
 
+    fn check_is_screen_id(input: &str) {
+        match ScreenID::parse(input) {
+            Some(id) => assert_eq!(input, format!("{}", id)),
+            None => panic!("input {} was not recognized as screen ID", input),
+        };
+    }
+
+    fn check_is_not_screen_id(input: &str) {
+        assert_eq!(ScreenID::parse(input), None);
+    }
+
     #[test]
     fn test_parsing() {
         check_is_identifier("foo");
@@ -678,4 +1152,92 @@ mod tests {
         //names that used to be eternal message types in earlier drafts, but are not anymore
         check_is_identifier("init");
     }
+
+    #[test]
+    fn test_parsing_screen_id() {
+        check_is_screen_id("screen1");
+        check_is_screen_id("1");
+        check_is_screen_id("Screen");
+        check_is_screen_id("tab-2.split_1");
+        //unlike identifiers, screen IDs may start with any of the allowed characters
+        check_is_screen_id("-screen");
+        check_is_screen_id("_screen");
+        check_is_screen_id(".screen");
+        check_is_screen_id("...");
+        //screen IDs must not be empty
+        check_is_not_screen_id("");
+        //only ASCII letters, digits, dashes, underscores and dots are allowed
+        check_is_not_screen_id("screen 1");
+        check_is_not_screen_id("screen/1");
+        check_is_not_screen_id("screen:1");
+        check_is_not_screen_id("screen\n");
+        check_is_not_screen_id("bildschirm\u{e4}");
+        check_is_not_screen_id(" ");
+    }
+
+    #[test]
+    fn test_parsing_relative_client_id() {
+        use crate::client::core::{ClientIDSuffix, RelativeClientID};
+        fn parse<'a>(full: &'a str, base: &str) -> Option<RelativeClientID<'a>> {
+            RelativeClientID::parse(
+                ClientID::parse(full).unwrap(),
+                ClientID::parse(base).unwrap(),
+            )
+        }
+
+        //accepted: the full ID is the base plus a valid suffix
+        for (full, suffix) in &[
+            ("foo", ClientIDSuffix::Own),
+            ("foo01", ClientIDSuffix::Local(0)),
+            ("fooz1", ClientIDSuffix::Job(61)),
+            ("foo2z0", ClientIDSuffix::Child(1, 60)),
+            ("foo1z2", ClientIDSuffix::Child(0, 62)),
+        ] {
+            match parse(full, "foo") {
+                Some(id) => {
+                    assert_eq!(id.base().as_str(), "foo");
+                    assert_eq!(id.suffix(), *suffix, "{}", full);
+                    assert_eq!(id.encode_to_vector(), full.as_bytes());
+                }
+                None => panic!("input {} was not recognized relative to foo", full),
+            }
+        }
+        //the base can itself have a suffix (e.g. for grandchildren)
+        assert_eq!(
+            parse("foo1z212", "foo1z2").map(|id| id.suffix()),
+            Some(ClientIDSuffix::Child(0, 1))
+        );
+
+        //rejected: the full ID does not start with the base
+        assert_eq!(parse("bar1", "foo"), None);
+        assert_eq!(parse("fo", "foo"), None);
+        assert_eq!(parse("Foo1", "foo"), None);
+        //rejected: the remainder is not a valid suffix
+        assert_eq!(parse("foo0", "foo"), None);
+        assert_eq!(parse("foo1z", "foo"), None);
+        assert_eq!(parse("foo111", "foo"), None);
+        //rejected: only one level of the hierarchy is decoded
+        assert_eq!(parse("foo1z212", "foo"), None);
+    }
+
+    #[cfg(all(feature = "use_serde", feature = "module_posix"))]
+    #[test]
+    fn test_serde_client_id() {
+        let id: OwnedClientID = serde_json::from_str(r#""a1""#).unwrap();
+        assert_eq!(id.as_ref(), ClientID::parse("a1").unwrap());
+        assert_eq!(serde_json::to_string(&id).unwrap(), r#""a1""#);
+        assert!(serde_json::from_str::<OwnedClientID>(r#""a-1""#).is_err());
+        assert!(serde_json::from_str::<OwnedClientID>(r#""""#).is_err());
+        assert!(serde_json::from_str::<OwnedClientID>("42").is_err());
+    }
+
+    #[cfg(feature = "use_serde")]
+    #[test]
+    fn test_serde_screen_id() {
+        let id: OwnedScreenID = serde_json::from_str(r#""screen1""#).unwrap();
+        assert_eq!(id.as_ref().as_str(), "screen1");
+        assert_eq!(serde_json::to_string(&id).unwrap(), r#""screen1""#);
+        assert!(serde_json::from_str::<OwnedScreenID>(r#""screen 1""#).is_err());
+        assert!(serde_json::from_str::<OwnedScreenID>(r#""""#).is_err());
+    }
 }
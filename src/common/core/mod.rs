@@ -8,6 +8,9 @@ mod decode_argument;
 pub use self::decode_argument::*;
 mod encode_argument;
 pub use self::encode_argument::*;
+mod enum_argument;
+mod geometry;
+pub use self::geometry::*;
 mod identifiers;
 pub use self::identifiers::*;
 
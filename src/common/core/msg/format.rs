@@ -5,7 +5,7 @@
 *******************************************************************************/
 
 use crate::common::core::msg::*;
-use crate::common::core::EncodeArgument;
+use crate::common::core::{EncodeArgument, MessageType};
 
 ///A formatter for VT6 messages, as defined in
 ///[vt6/foundation, section 3.1](https://vt6.io/std/foundation/#section-3-1).
@@ -21,11 +21,48 @@ pub struct MessageFormatter<'b> {
 impl<'b> MessageFormatter<'b> {
     ///Create a new MessageFormatter. The number of arguments must be given at
     ///this point already because it gets encoded first.
-    pub fn new(
+    ///
+    ///The type name is usually a string, but any argument type can be used if
+    ///the type name is not available as a contiguous string.
+    pub fn new<T: EncodeArgument + ?Sized>(
         buffer: &'b mut [u8],
-        type_name: &str,
+        type_name: &T,
         num_arguments: usize,
     ) -> MessageFormatter<'b> {
+        Self::new_at(buffer, 0, type_name, num_arguments)
+    }
+
+    ///Like [new()](#method.new), but starts writing at the given offset into the buffer instead of
+    ///at its start. This is useful for appending a message to a buffer that already contains
+    ///other messages. The offset is usually the fill level of the buffer, and `finalize()` then
+    ///returns the new fill level.
+    ///
+    ///```
+    ///# use vt6::common::core::msg::*;
+    ///let mut buf = [0u8; 64];
+    ///let mut f = MessageFormatter::new(&mut buf, "want", 1);
+    ///f.add_argument("core1");
+    ///let filled = f.finalize().unwrap();
+    ///let mut f = MessageFormatter::new_at(&mut buf, filled, "want", 1);
+    ///f.add_argument("sig1");
+    ///let filled = f.finalize().unwrap();
+    ///assert_eq!(&buf[0..filled], b"{2|4:want,5:core1,}{2|4:want,4:sig1,}");
+    ///```
+    ///
+    ///# Panics
+    ///
+    ///Panics if `offset` is beyond the end of the buffer.
+    pub fn new_at<T: EncodeArgument + ?Sized>(
+        buffer: &'b mut [u8],
+        offset: usize,
+        type_name: &T,
+        num_arguments: usize,
+    ) -> MessageFormatter<'b> {
+        assert!(
+            offset <= buffer.len(),
+            "vt6::common::core::msg::MessageFormatter::new_at() called with offset beyond end of buffer"
+        );
+
         //NOTE (majewsky): It's not strictly true that we need the number of
         //arguments at this point; we could also write the argument count in
         //finalize(). It would just involve an extra memmove() to make room for
@@ -34,8 +71,8 @@ impl<'b> MessageFormatter<'b> {
 
         let len = num_arguments + 1; // + 1 for the message type
         let mut f = MessageFormatter {
-            buffer: crop_buffer_to_max_msglen(buffer),
-            cursor: 0,
+            buffer: crop_buffer_to_max_msglen(buffer, offset),
+            cursor: offset,
             remaining_arguments: len,
         };
         f.add_char(b'{');
@@ -69,6 +106,10 @@ impl<'b> MessageFormatter<'b> {
     ///returned, the final message can be retrieved from `&buffer[0..size]`,
     ///where `buffer` is the first argument passed to `new()`.
     ///
+    ///When the formatter was created with `new_at()`, the returned size includes
+    ///the offset, i.e. it is the new fill level of the buffer, and the message
+    ///can be retrieved from `&buffer[offset..size]`.
+    ///
     ///# Panics
     ///
     ///Panics if `add_argument()` has not been called sufficiently often (as
@@ -89,7 +130,7 @@ impl<'b> MessageFormatter<'b> {
         if self.cursor < self.buffer.len() {
             self.buffer[self.cursor] = c;
         }
-        if self.cursor == usize::max_value() {
+        if self.cursor == usize::MAX {
             panic!("overflow in MessageFormatter.cursor :: usize");
         }
         self.cursor += 1;
@@ -110,12 +151,119 @@ impl<'b> MessageFormatter<'b> {
     }
 }
 
+///A variant of [MessageFormatter](struct.MessageFormatter.html) that validates its usage.
+///
+///MessageFormatter trusts its caller to supply a valid message type and exactly the announced
+///number of arguments, and panics (or, for invalid message types, produces malformed messages)
+///otherwise. This is appropriate for message types defined in this crate, but when the message
+///type or the arguments are determined at runtime, it is more useful to get an error instead.
+///CheckedMessageFormatter reports all of these problems through
+///[FormatError](enum.FormatError.html) when `finalize()` is called.
+///
+///```
+///# use vt6::common::core::msg::*;
+///let mut buf = [0u8; 1024];
+///let mut f = CheckedMessageFormatter::new(&mut buf, "core1.set", 2);
+///f.add_argument("example1.title");
+///assert_eq!(
+///    f.finalize(),
+///    Err(FormatError::ArgumentCountMismatch { expected: 2, actual: 1 })
+///);
+///
+///let f = CheckedMessageFormatter::new(&mut buf, "core1", 0);
+///assert_eq!(f.finalize(), Err(FormatError::InvalidMessageType));
+///```
+pub struct CheckedMessageFormatter<'b> {
+    inner: MessageFormatter<'b>,
+    expected_arguments: usize,
+    actual_arguments: usize,
+    is_valid_type: bool,
+}
+
+impl<'b> CheckedMessageFormatter<'b> {
+    ///Create a new CheckedMessageFormatter. The arguments are the same as for
+    ///[`MessageFormatter::new()`](struct.MessageFormatter.html#method.new).
+    pub fn new(
+        buffer: &'b mut [u8],
+        type_name: &str,
+        num_arguments: usize,
+    ) -> CheckedMessageFormatter<'b> {
+        CheckedMessageFormatter {
+            inner: MessageFormatter::new(buffer, type_name, num_arguments),
+            expected_arguments: num_arguments,
+            actual_arguments: 0,
+            is_valid_type: MessageType::parse(type_name).is_some(),
+        }
+    }
+
+    ///Adds an argument to the message that is being rendered. Unlike
+    ///[`MessageFormatter::add_argument()`](struct.MessageFormatter.html#method.add_argument),
+    ///this does not panic when more arguments are added than announced. The error is reported by
+    ///`finalize()` instead.
+    pub fn add_argument<T: EncodeArgument + ?Sized>(&mut self, arg: &T) {
+        if self.actual_arguments < self.expected_arguments {
+            self.inner.add_argument(arg);
+        }
+        self.actual_arguments += 1;
+    }
+
+    ///Finalizes the message that is being rendered. On success, returns the number of bytes that
+    ///were rendered, like
+    ///[`MessageFormatter::finalize()`](struct.MessageFormatter.html#method.finalize) does.
+    pub fn finalize(self) -> Result<usize, FormatError> {
+        if !self.is_valid_type {
+            return Err(FormatError::InvalidMessageType);
+        }
+        if self.actual_arguments != self.expected_arguments {
+            return Err(FormatError::ArgumentCountMismatch {
+                expected: self.expected_arguments,
+                actual: self.actual_arguments,
+            });
+        }
+        self.inner.finalize().map_err(FormatError::BufferTooSmall)
+    }
+}
+
+///An error type that is returned by
+///[`CheckedMessageFormatter::finalize()`](struct.CheckedMessageFormatter.html#method.finalize).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FormatError {
+    ///The message type is not valid according to
+    ///[vt6/foundation, section 2.4](https://vt6.io/std/foundation/#section-2-4).
+    InvalidMessageType,
+    ///The number of arguments added does not match the number announced in `new()`.
+    ArgumentCountMismatch { expected: usize, actual: usize },
+    ///The target buffer was too small to contain the formatted message.
+    BufferTooSmall(BufferTooSmallError),
+}
+
+impl core::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match *self {
+            FormatError::InvalidMessageType => f.write_str("invalid message type"),
+            FormatError::ArgumentCountMismatch { expected, actual } => write!(
+                f,
+                "expected {} arguments, but {} were added",
+                expected, actual
+            ),
+            FormatError::BufferTooSmall(BufferTooSmallError(missing)) => {
+                write!(f, "buffer too small by {} bytes", missing)
+            }
+        }
+    }
+}
+
+#[cfg(any(test, feature = "use_std"))]
+impl std::error::Error for FormatError {}
+
 //This ensures that we never render a message > 1024 bytes. Overlong messages are forbidden by
-//[vt6/foundation, sect. 3.1.2].
-fn crop_buffer_to_max_msglen(buf: &mut [u8]) -> &mut [u8] {
-    if buf.len() <= 1024 {
+//[vt6/foundation, sect. 3.1.2]. The message starts at `offset`, so that is where the limit counts
+//from.
+fn crop_buffer_to_max_msglen(buf: &mut [u8], offset: usize) -> &mut [u8] {
+    let max_len = offset.saturating_add(1024);
+    if buf.len() <= max_len {
         buf
     } else {
-        &mut buf[..1024]
+        &mut buf[..max_len]
     }
 }
@@ -4,7 +4,8 @@
 * Refer to the file "LICENSE" for details.
 *******************************************************************************/
 
-use crate::common::core::{DecodeArgument, MessageType};
+use crate::common::core::{DecodeArgument, EncodeArgument, MessageType};
+use core::marker::PhantomData;
 
 mod format;
 pub use format::*;
@@ -89,6 +90,19 @@ pub struct ParseError<'s> {
     pub offset: usize,
     ///The kind of parse error that was encountered.
     pub kind: ParseErrorKind,
+    ///The length of the offending token, or `None` if the end of the buffer was reached
+    ///(`UnexpectedEOF`). For most kinds of errors, the offending token is an unexpected character
+    ///at `offset`. For `DecimalNumberTooLarge`, `DecimalNumberHasLeadingZeroes` and
+    ///`InvalidMessageType`, the offending token is a number or bytestring that ends at `offset`.
+    ///Use `span()` to obtain the location of the offending token without distinguishing these
+    ///cases.
+    pub error_len: Option<usize>,
+    ///The offset at which parsing should be resumed after discarding the preceding input. This is
+    ///the position of the next possible start of a message, i.e. the next `{` sign after the
+    ///start of the buffer, or `buffer.len()` if there is none. For `UnexpectedEOF`, this is always
+    ///`buffer.len()` since nothing can follow an incomplete message.
+    ///[\[vt6/foundation, sect. 3.3\]](https://vt6.io/std/foundation/#section-3-3)
+    pub resync_offset: usize,
 }
 
 impl<'s> ParseError<'s> {
@@ -100,6 +114,29 @@ impl<'s> ParseError<'s> {
     pub fn is_incomplete(&self) -> bool {
         self.kind == ParseErrorKind::UnexpectedEOF
     }
+
+    ///Returns the range of `self.buffer` covered by the offending token, or `None` if the end of
+    ///the buffer was reached.
+    ///
+    ///```
+    ///# use vt6::common::core::msg::*;
+    ///let err = Message::parse(b"{2|4:want,5:core1#}").unwrap_err();
+    ///assert_eq!(err.kind, ParseErrorKind::ExpectedStringCloser);
+    ///assert_eq!(err.span(), Some(17..18));
+    ///
+    ///let err = Message::parse(b"{2|04:want,5:core1,}").unwrap_err();
+    ///assert_eq!(err.kind, ParseErrorKind::DecimalNumberHasLeadingZeroes);
+    ///assert_eq!(err.span(), Some(3..5));
+    ///```
+    pub fn span(&self) -> Option<core::ops::Range<usize>> {
+        let len = self.error_len?;
+        match self.kind {
+            DecimalNumberTooLarge | DecimalNumberHasLeadingZeroes | InvalidMessageType => {
+                Some((self.offset - len)..self.offset)
+            }
+            _ => Some(self.offset..(self.offset + len)),
+        }
+    }
 }
 
 impl<'s> core::fmt::Display for ParseError<'s> {
@@ -115,6 +152,144 @@ impl<'s> std::error::Error for ParseError<'s> {
     }
 }
 
+///An error type that is returned by the `try_exactlyN()` and `try_with_optional()` methods of
+///[MessageIterator](struct.MessageIterator.html). It indicates why the arguments of a message
+///could not be decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ArgumentError {
+    ///There were `actual` arguments, but `expected` were required.
+    WrongCount { expected: usize, actual: usize },
+    ///There were `actual` arguments, but between `min` and `max` were required.
+    CountOutOfRange {
+        min: usize,
+        max: usize,
+        actual: usize,
+    },
+    ///The argument with the given index could not be decoded into the requested type. The index
+    ///counts from the iterator's position, i.e. when called on `Message::arguments()`, the first
+    ///argument after the message type has index 0.
+    Undecodable { index: usize },
+}
+
+impl core::fmt::Display for ArgumentError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match *self {
+            Self::WrongCount { expected, actual } => {
+                write!(f, "expected {} arguments, got {}", expected, actual)
+            }
+            Self::CountOutOfRange { min, max, actual } => {
+                write!(f, "expected {} to {} arguments, got {}", min, max, actual)
+            }
+            Self::Undecodable { index } => write!(f, "invalid value for argument {}", index),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "use_std"))]
+impl std::error::Error for ArgumentError {}
+
+///The number of bytes of context that [OwnedParseError](struct.OwnedParseError.html) retains from
+///the input buffer.
+pub const PARSE_ERROR_CONTEXT_LEN: usize = 32;
+
+///An owned version of [ParseError](struct.ParseError.html).
+///
+///ParseError borrows the buffer that was given to the message parser, so it cannot be kept around
+///once the buffer gets reused. OwnedParseError retains only a small window of context from the
+///buffer (at most [PARSE_ERROR_CONTEXT_LEN](constant.PARSE_ERROR_CONTEXT_LEN.html) bytes around
+///the error offset), so that it can be stored or logged later.
+///
+///```
+///# use vt6::common::core::msg::*;
+///let err = OwnedParseError::from(Message::parse(b"{2|4:want,#:core1,}").unwrap_err());
+///assert_eq!(err.kind, ParseErrorKind::ExpectedDecimalNumber);
+///assert_eq!(err.offset, 10);
+///assert_eq!(err.context(), b"{2|4:want,#:core1,}");
+///assert_eq!(err.context_offset(), 0);
+///```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnedParseError {
+    ///The position within the original buffer where the error was encountered.
+    pub offset: usize,
+    ///The kind of parse error that was encountered.
+    pub kind: ParseErrorKind,
+    ///The length of the offending token. See [ParseError](struct.ParseError.html) for details.
+    pub error_len: Option<usize>,
+    ///The offset at which parsing should be resumed. See [ParseError](struct.ParseError.html) for
+    ///details.
+    pub resync_offset: usize,
+    context: [u8; PARSE_ERROR_CONTEXT_LEN],
+    context_len: usize,
+    context_offset: usize,
+}
+
+impl OwnedParseError {
+    ///Returns the part of the original buffer that was retained. This is a window of up to
+    ///[PARSE_ERROR_CONTEXT_LEN](constant.PARSE_ERROR_CONTEXT_LEN.html) bytes around the error
+    ///offset.
+    pub fn context(&self) -> &[u8] {
+        &self.context[0..self.context_len]
+    }
+
+    ///Returns the position of `self.context()` within the original buffer.
+    pub fn context_offset(&self) -> usize {
+        self.context_offset
+    }
+
+    ///Same as [`ParseError::is_incomplete()`](struct.ParseError.html#method.is_incomplete).
+    pub fn is_incomplete(&self) -> bool {
+        self.kind == ParseErrorKind::UnexpectedEOF
+    }
+}
+
+impl<'s> From<ParseError<'s>> for OwnedParseError {
+    fn from(e: ParseError<'s>) -> Self {
+        //center the context window on the error offset where possible
+        let context_offset = e
+            .offset
+            .saturating_sub(PARSE_ERROR_CONTEXT_LEN / 2)
+            .min(e.buffer.len());
+        let context_end = e.buffer.len().min(context_offset + PARSE_ERROR_CONTEXT_LEN);
+        let context_len = context_end - context_offset;
+        let mut context = [0; PARSE_ERROR_CONTEXT_LEN];
+        context[0..context_len].copy_from_slice(&e.buffer[context_offset..context_end]);
+        OwnedParseError {
+            offset: e.offset,
+            kind: e.kind,
+            error_len: e.error_len,
+            resync_offset: e.resync_offset,
+            context,
+            context_len,
+            context_offset,
+        }
+    }
+}
+
+impl core::fmt::Display for OwnedParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "Parse error at offset {}: {} near \"",
+            self.offset, self.kind
+        )?;
+        for byte in self
+            .context()
+            .iter()
+            .flat_map(|&b| core::ascii::escape_default(b))
+        {
+            (byte as char).fmt(f)?;
+        }
+        f.write_str("\"")
+    }
+}
+
+#[cfg(any(test, feature = "use_std"))]
+impl std::error::Error for OwnedParseError {
+    fn description(&self) -> &str {
+        self.kind.to_str()
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // struct Cursor
 
@@ -138,10 +313,32 @@ impl<'s> Cursor<'s> {
 
     //assorted helper methods to make the parsing functions shorter
     fn error<T>(&self, kind: ParseErrorKind) -> Result<T, ParseError<'s>> {
+        //unless we ran into EOF, the error is about the unexpected character at self.offset
+        let error_len = if kind == UnexpectedEOF { None } else { Some(1) };
+        self.error_with_len(kind, error_len)
+    }
+    fn error_with_len<T>(
+        &self,
+        kind: ParseErrorKind,
+        error_len: Option<usize>,
+    ) -> Result<T, ParseError<'s>> {
+        //the next possible start of a message is the next `{` sign after the start of the buffer
+        //(the .skip(1) ensures that we don't resync to offset 0); this scan is skipped for
+        //incomplete messages since those are usually just waiting for the next read
+        let resync_offset = if kind == UnexpectedEOF {
+            self.buffer.len()
+        } else {
+            match self.buffer.iter().skip(1).position(|&b| b == b'{') {
+                Some(offset) => offset + 1, //`+1` compensates the effect of .skip(1)
+                None => self.buffer.len(),
+            }
+        };
         Err(ParseError {
             buffer: self.buffer,
             offset: self.offset,
             kind,
+            error_len,
+            resync_offset,
         })
     }
     fn current(&self) -> Result<u8, ParseError<'s>> {
@@ -195,12 +392,12 @@ impl<'s> Cursor<'s> {
 
             //check that there are no leading zeroes
             if digit_str.len() > 1 && digit_str.as_bytes()[0] == b'0' {
-                return self.error(DecimalNumberHasLeadingZeroes);
+                return self.error_with_len(DecimalNumberHasLeadingZeroes, Some(digit_str.len()));
             }
 
             match digit_str.parse() {
                 Ok(val) => Ok(val),
-                Err(_) => self.error(DecimalNumberTooLarge),
+                Err(_) => self.error_with_len(DecimalNumberTooLarge, Some(digit_str.len())),
             }
         }
     }
@@ -232,7 +429,7 @@ impl<'s> Cursor<'s> {
 }
 
 fn isnum(c: u8) -> bool {
-    (b'0'..=b'9').contains(&c)
+    c.is_ascii_digit()
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -293,50 +490,221 @@ impl<'s> MessageIterator<'s> {
         }
     }
 
-    //This is `pub(crate)` only for now because I want to gain experience with this API first.
-    //When it goes `pub`, it will probably be on an `IteratorExt`-like trait.
-    pub(crate) fn exactly1<A>(mut self) -> Option<A>
+    ///Returns an iterator that decodes each remaining argument into a `T`. Returns `None` if any
+    ///of the remaining arguments cannot be decoded, so that the resulting iterator always yields
+    ///exactly `self.len()` items.
+    ///
+    ///This allows for decoding messages with a variable number of arguments without allocating:
+    ///The decoded message can hold the returned iterator instead of a `Vec<T>`. Leading arguments
+    ///with a different type can be taken off with `next()` first.
+    ///
+    ///```
+    ///# use vt6::common::core::msg::*;
+    ///# use vt6::common::core::ClientID;
+    /////a hypothetical `example1.clients` message, with a list of client IDs as arguments
+    ///struct Clients<'a> {
+    ///    ids: DecodedArguments<'a, ClientID<'a>>,
+    ///}
+    ///
+    ///impl<'a> DecodeMessage<'a> for Clients<'a> {
+    ///    fn decode_message<'b>(msg: &'b Message<'a>) -> Option<Self> {
+    ///        if msg.parsed_type().as_str() != "example1.clients" {
+    ///            return None;
+    ///        }
+    ///        let ids = msg.arguments().decode_all()?;
+    ///        Some(Clients { ids })
+    ///    }
+    ///}
+    ///
+    ///let (msg, _) = Message::parse(b"{3|16:example1.clients,1:a,2:ab,}").unwrap();
+    ///let clients = Clients::decode_message(&msg).unwrap();
+    ///assert_eq!(clients.ids.len(), 2);
+    ///let ids: Vec<_> = clients.ids.map(|id| id.as_str()).collect();
+    ///assert_eq!(ids, vec!["a", "ab"]);
+    ///
+    /////client IDs cannot be empty
+    ///let (msg, _) = Message::parse(b"{3|16:example1.clients,1:a,0:,}").unwrap();
+    ///assert!(Clients::decode_message(&msg).is_none());
+    ///```
+    pub fn decode_all<T: DecodeArgument<'s>>(self) -> Option<DecodedArguments<'s, T>> {
+        if self.clone().all(|arg| T::decode_argument(arg).is_some()) {
+            Some(DecodedArguments {
+                args: self,
+                item_type: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    ///Decodes the remaining arguments into a value of type `A`. Returns `None` if there is not
+    ///exactly one argument left, or if it cannot be decoded. Use
+    ///[`try_exactly1()`](#method.try_exactly1) to find out which of these was the case.
+    ///
+    ///```
+    ///# use vt6::common::core::msg::*;
+    ///let (msg, _) = Message::parse(b"{2|9:core1.sub,11:core1.title,}").unwrap();
+    ///assert_eq!(msg.arguments().exactly1::<&str>(), Some("core1.title"));
+    ///let (msg, _) = Message::parse(b"{3|9:core1.sub,11:core1.title,3:foo,}").unwrap();
+    ///assert_eq!(msg.arguments().exactly1::<&str>(), None);
+    ///```
+    pub fn exactly1<A>(self) -> Option<A>
     where
         A: DecodeArgument<'s>,
     {
-        if self.remaining_items != 1 {
-            return None;
-        }
-        Some(A::decode_argument(self.next()?)?)
+        self.try_exactly1().ok()
     }
 
-    //This is `pub(crate)` only for now because I want to gain experience with this API first.
-    //When it goes `pub`, it will probably be on an `IteratorExt`-like trait.
-    pub(crate) fn exactly2<A, B>(mut self) -> Option<(A, B)>
+    ///Like [`exactly1()`](#method.exactly1), but for two arguments.
+    pub fn exactly2<A, B>(self) -> Option<(A, B)>
     where
         A: DecodeArgument<'s>,
         B: DecodeArgument<'s>,
     {
-        if self.remaining_items != 2 {
-            return None;
-        }
-        let a = A::decode_argument(self.next()?)?;
-        let b = B::decode_argument(self.next()?)?;
-        Some((a, b))
+        self.try_exactly2().ok()
     }
 
-    //This is `pub(crate)` only for now because I want to gain experience with this API first.
-    //When it goes `pub`, it will probably be on an `IteratorExt`-like trait.
-    pub(crate) fn exactly4<A, B, C, D>(mut self) -> Option<(A, B, C, D)>
+    ///Like [`exactly1()`](#method.exactly1), but for three arguments.
+    pub fn exactly3<A, B, C>(self) -> Option<(A, B, C)>
+    where
+        A: DecodeArgument<'s>,
+        B: DecodeArgument<'s>,
+        C: DecodeArgument<'s>,
+    {
+        self.try_exactly3().ok()
+    }
+
+    ///Like [`exactly1()`](#method.exactly1), but for four arguments.
+    pub fn exactly4<A, B, C, D>(self) -> Option<(A, B, C, D)>
     where
         A: DecodeArgument<'s>,
         B: DecodeArgument<'s>,
         C: DecodeArgument<'s>,
         D: DecodeArgument<'s>,
     {
-        if self.remaining_items != 4 {
-            return None;
+        self.try_exactly4().ok()
+    }
+
+    ///Like [`exactly1()`](#method.exactly1), but reports why decoding failed.
+    ///
+    ///```
+    ///# use vt6::common::core::msg::*;
+    ///let (msg, _) = Message::parse(b"{3|9:core1.sub,11:core1.title,3:foo,}").unwrap();
+    ///assert_eq!(
+    ///    msg.arguments().try_exactly1::<&str>(),
+    ///    Err(ArgumentError::WrongCount { expected: 1, actual: 2 }),
+    ///);
+    ///let (msg, _) = Message::parse(b"{3|9:core1.set,11:core1.title,3:foo,}").unwrap();
+    ///assert_eq!(
+    ///    msg.arguments().try_exactly2::<&str, u32>(),
+    ///    Err(ArgumentError::Undecodable { index: 1 }),
+    ///);
+    ///```
+    pub fn try_exactly1<A>(mut self) -> Result<A, ArgumentError>
+    where
+        A: DecodeArgument<'s>,
+    {
+        self.expect_count(1)?;
+        self.decode_next(0)
+    }
+
+    ///Like [`exactly2()`](#method.exactly2), but reports why decoding failed.
+    pub fn try_exactly2<A, B>(mut self) -> Result<(A, B), ArgumentError>
+    where
+        A: DecodeArgument<'s>,
+        B: DecodeArgument<'s>,
+    {
+        self.expect_count(2)?;
+        Ok((self.decode_next(0)?, self.decode_next(1)?))
+    }
+
+    ///Like [`exactly3()`](#method.exactly3), but reports why decoding failed.
+    pub fn try_exactly3<A, B, C>(mut self) -> Result<(A, B, C), ArgumentError>
+    where
+        A: DecodeArgument<'s>,
+        B: DecodeArgument<'s>,
+        C: DecodeArgument<'s>,
+    {
+        self.expect_count(3)?;
+        Ok((
+            self.decode_next(0)?,
+            self.decode_next(1)?,
+            self.decode_next(2)?,
+        ))
+    }
+
+    ///Like [`exactly4()`](#method.exactly4), but reports why decoding failed.
+    pub fn try_exactly4<A, B, C, D>(mut self) -> Result<(A, B, C, D), ArgumentError>
+    where
+        A: DecodeArgument<'s>,
+        B: DecodeArgument<'s>,
+        C: DecodeArgument<'s>,
+        D: DecodeArgument<'s>,
+    {
+        self.expect_count(4)?;
+        Ok((
+            self.decode_next(0)?,
+            self.decode_next(1)?,
+            self.decode_next(2)?,
+            self.decode_next(3)?,
+        ))
+    }
+
+    ///Prepares decoding of a message whose last arguments are optional, e.g. because they were
+    ///added in a later minor version of the module. Returns an error if fewer than `required` or
+    ///more than `required + optional` arguments are left. Otherwise, the arguments are decoded one
+    ///by one with the returned [ArgumentDecoder](struct.ArgumentDecoder.html).
+    ///
+    ///```
+    ///# use vt6::common::core::msg::*;
+    /////a hypothetical `example1.resize` message, where the third argument was added later
+    ///fn decode_resize(msg: &Message) -> Result<(u32, u32, bool), ArgumentError> {
+    ///    let mut args = msg.arguments().try_with_optional(2, 1)?;
+    ///    Ok((args.required()?, args.required()?, args.optional_or_default()?))
+    ///}
+    ///
+    ///let (msg, _) = Message::parse(b"{3|15:example1.resize,2:80,2:25,}").unwrap();
+    ///assert_eq!(decode_resize(&msg), Ok((80, 25, false)));
+    ///let (msg, _) = Message::parse(b"{4|15:example1.resize,2:80,2:25,1:t,}").unwrap();
+    ///assert_eq!(decode_resize(&msg), Ok((80, 25, true)));
+    ///let (msg, _) = Message::parse(b"{2|15:example1.resize,2:80,}").unwrap();
+    ///assert_eq!(
+    ///    decode_resize(&msg),
+    ///    Err(ArgumentError::CountOutOfRange { min: 2, max: 3, actual: 1 }),
+    ///);
+    ///```
+    pub fn try_with_optional(
+        self,
+        required: usize,
+        optional: usize,
+    ) -> Result<ArgumentDecoder<'s>, ArgumentError> {
+        let (min, max) = (required, required + optional);
+        let actual = self.remaining_items;
+        if actual < min || actual > max {
+            return Err(ArgumentError::CountOutOfRange { min, max, actual });
+        }
+        Ok(ArgumentDecoder {
+            args: self,
+            index: 0,
+        })
+    }
+
+    fn expect_count(&self, expected: usize) -> Result<(), ArgumentError> {
+        if self.remaining_items == expected {
+            Ok(())
+        } else {
+            Err(ArgumentError::WrongCount {
+                expected,
+                actual: self.remaining_items,
+            })
         }
-        let a = A::decode_argument(self.next()?)?;
-        let b = B::decode_argument(self.next()?)?;
-        let c = C::decode_argument(self.next()?)?;
-        let d = D::decode_argument(self.next()?)?;
-        Some((a, b, c, d))
+    }
+
+    //The caller must have checked the argument count with expect_count() first.
+    fn decode_next<A: DecodeArgument<'s>>(&mut self, index: usize) -> Result<A, ArgumentError> {
+        self.next()
+            .and_then(A::decode_argument)
+            .ok_or(ArgumentError::Undecodable { index })
     }
 }
 
@@ -360,6 +728,119 @@ impl<'s> core::fmt::Debug for MessageIterator<'s> {
     }
 }
 
+///An iterator over the arguments of a message, which yields each argument decoded into a `T`. This
+///is returned by [`MessageIterator::decode_all()`](struct.MessageIterator.html#method.decode_all),
+///which has already checked that all arguments can be decoded.
+pub struct DecodedArguments<'s, T> {
+    args: MessageIterator<'s>,
+    item_type: PhantomData<fn() -> T>,
+}
+
+//Clone and Debug are implemented manually since deriving them would require `T: Clone` and
+//`T: Debug`, respectively.
+impl<'s, T> Clone for DecodedArguments<'s, T> {
+    fn clone(&self) -> Self {
+        DecodedArguments {
+            args: self.args.clone(),
+            item_type: PhantomData,
+        }
+    }
+}
+
+impl<'s, T> core::fmt::Debug for DecodedArguments<'s, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        self.args.fmt(f)
+    }
+}
+
+impl<'s, T: DecodeArgument<'s>> Iterator for DecodedArguments<'s, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        //decode_all() has checked that this does not fail
+        T::decode_argument(self.args.next()?)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.args.len(), Some(self.args.len()))
+    }
+}
+
+impl<'s, T: DecodeArgument<'s>> core::iter::ExactSizeIterator for DecodedArguments<'s, T> {
+    fn len(&self) -> usize {
+        self.args.len()
+    }
+}
+
+///Decodes the arguments of a message one by one, where the last arguments may be missing. This is
+///returned by
+///[`MessageIterator::try_with_optional()`](struct.MessageIterator.html#method.try_with_optional),
+///which has already checked the number of arguments.
+#[derive(Clone, Debug)]
+pub struct ArgumentDecoder<'s> {
+    args: MessageIterator<'s>,
+    //the index of the next argument, for error reporting
+    index: usize,
+}
+
+impl<'s> ArgumentDecoder<'s> {
+    ///Decodes the next argument, which must be present.
+    pub fn required<A: DecodeArgument<'s>>(&mut self) -> Result<A, ArgumentError> {
+        match self.optional()? {
+            Some(value) => Ok(value),
+            None => Err(ArgumentError::WrongCount {
+                expected: self.index + 1,
+                actual: self.index,
+            }),
+        }
+    }
+
+    ///Decodes the next argument if it is present, or returns `None` if all arguments have been
+    ///decoded already. An argument that is present, but cannot be decoded, is still an error.
+    pub fn optional<A: DecodeArgument<'s>>(&mut self) -> Result<Option<A>, ArgumentError> {
+        let arg = match self.args.next() {
+            Some(arg) => arg,
+            None => return Ok(None),
+        };
+        let index = self.index;
+        self.index += 1;
+        A::decode_argument(arg)
+            .map(Some)
+            .ok_or(ArgumentError::Undecodable { index })
+    }
+
+    ///Like [`optional()`](#method.optional), but substitutes the default value for a missing
+    ///argument.
+    pub fn optional_or_default<A>(&mut self) -> Result<A, ArgumentError>
+    where
+        A: DecodeArgument<'s> + Default,
+    {
+        Ok(self.optional()?.unwrap_or_default())
+    }
+}
+
+///An iterator over the arguments of a message together with their positions. This is returned
+///by [`Message::arguments_with_spans()`](struct.Message.html#method.arguments_with_spans).
+#[derive(Clone, Debug)]
+pub struct ArgumentSpans<'s>(MessageIterator<'s>);
+
+impl<'s> Iterator for ArgumentSpans<'s> {
+    type Item = (core::ops::Range<usize>, &'s [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let arg = self.0.next()?;
+        //the cursor is now behind the string closer (`,`) following the argument
+        let end = self.0.cursor.offset - 1;
+        Some((end - arg.len()..end, arg))
+    }
+}
+
+impl<'s> core::iter::ExactSizeIterator for ArgumentSpans<'s> {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // struct Message
 
@@ -380,6 +861,18 @@ impl<'s> core::fmt::Debug for MessageIterator<'s> {
 ///let (msg, _) = Message::parse(b"{3|9:core1.set,13:example.title,11:hello world,}").unwrap();
 ///assert_eq!(format!("{}", msg), r#"(core1.set example.title "hello world")"#);
 ///```
+///
+///That representation escapes all bytes outside of printable ASCII, which makes text in other
+///scripts unreadable. The alternate form (`{:#}`) shows arguments that are valid UTF-8 as text
+///instead, and only escapes control characters. This is intended for logs, and is not covered by
+///the specification.
+///
+///```
+///# use vt6::common::core::msg::*;
+///let (msg, _) = Message::parse(b"{3|9:core1.set,13:example.title,10:gr\xc3\xbc\xc3\x9fe\n\xc2\x85,}").unwrap();
+///assert_eq!(format!("{}", msg), r#"(core1.set example.title "gr\xc3\xbc\xc3\x9fe\n\xc2\x85")"#);
+///assert_eq!(format!("{:#}", msg), r#"(core1.set example.title "grüße\n\u{85}")"#);
+///```
 #[derive(Clone, Debug)]
 pub struct Message<'s> {
     parsed_type: MessageType<'s>,
@@ -403,11 +896,17 @@ impl<'s> Message<'s> {
         let mut iter = MessageIterator::make(cursor, count_items);
 
         //extract the first item to check if it's a message type
+        let type_offset = iter.cursor.offset;
         let msg_type = match iter.next_or_error()? {
             None => return iter.cursor.error(ExpectedMessageType),
             Some(s) => match core::str::from_utf8(s).ok().and_then(MessageType::parse) {
                 Some(mt) => mt,
-                None => return iter.cursor.error(InvalidMessageType),
+                None => {
+                    let type_len = iter.cursor.offset - type_offset;
+                    return iter
+                        .cursor
+                        .error_with_len(InvalidMessageType, Some(type_len));
+                }
             },
         };
 
@@ -422,6 +921,28 @@ impl<'s> Message<'s> {
         Ok((msg, cursor.offset))
     }
 
+    ///Parses all consecutive messages in `buffer`. This is useful when a buffer is known to
+    ///contain a batch of complete messages, e.g. when replaying a log.
+    ///
+    ///The iterator yields one item per message or per parse error. After a parse error, parsing
+    ///resumes at the error's `resync_offset`, just like a server does. An incomplete message at
+    ///the end of the buffer is reported as an `UnexpectedEOF` error, which is always the last
+    ///item. The offsets in the yielded errors refer to `buffer` as a whole.
+    ///
+    ///```
+    ///# use vt6::common::core::msg::*;
+    ///let buffer = b"{2|4:want,5:core1,}garbage{2|4:have,7:core1.0,}{2|4:nope";
+    ///let mut iter = Message::parse_all(buffer);
+    ///assert_eq!(iter.next().unwrap().unwrap().to_string(), "(want core1)");
+    ///assert_eq!(iter.next().unwrap().unwrap_err().offset, 19);
+    ///assert_eq!(iter.next().unwrap().unwrap().to_string(), "(have core1.0)");
+    ///assert!(iter.next().unwrap().unwrap_err().is_incomplete());
+    ///assert!(iter.next().is_none());
+    ///```
+    pub fn parse_all(buffer: &'s [u8]) -> MessageStream<'s> {
+        MessageStream { buffer, offset: 0 }
+    }
+
     ///Returns the parsed message type.
     ///
     ///```
@@ -453,16 +974,106 @@ impl<'s> Message<'s> {
     pub fn arguments(&self) -> MessageIterator<'s> {
         self.arguments.clone()
     }
-}
 
-impl<'s> core::fmt::Display for Message<'s> {
-    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    ///Like [`arguments()`](#method.arguments), but each argument is accompanied by its position.
+    ///The position is the range of bytes that the argument's value occupies in the buffer that
+    ///was given to [`parse()`](#method.parse), i.e. it is relative to the start of the message.
+    ///This is useful for pointing to the exact location of an invalid argument in error messages.
+    ///
+    ///```
+    ///# use vt6::common::core::msg::Message;
+    ///let buffer = b"{3|9:core1.set,13:example.title,11:hello world,}";
+    ///let (msg, _) = Message::parse(buffer).unwrap();
+    ///let mut iter = msg.arguments_with_spans();
+    ///assert_eq!(iter.next(), Some((18..31, b"example.title" as &[u8])));
+    ///let (span, arg) = iter.next().unwrap();
+    ///assert_eq!(&buffer[span], arg);
+    ///assert_eq!(iter.next(), None);
+    ///```
+    pub fn arguments_with_spans(&self) -> ArgumentSpans<'s> {
+        ArgumentSpans(self.arguments.clone())
+    }
+
+    ///Returns a wrapper whose `Display` implementation shows the same human-readable
+    ///representation as this message's own, except that arguments containing secrets are
+    ///replaced by `<redacted>`. This is what should be used when logging messages received from
+    ///clients, since handshake messages carry the secrets that authorize a connection.
+    ///
+    ///```
+    ///# use vt6::common::core::msg::Message;
+    ///let (msg, _) = Message::parse(b"{2|19:posix1.client-hello,6:abc123,}").unwrap();
+    ///assert_eq!(msg.to_string(), "(posix1.client-hello abc123)");
+    ///assert_eq!(msg.display_redacted().to_string(), "(posix1.client-hello <redacted>)");
+    ///
+    ///let (msg, _) = Message::parse(b"{3|23:posix1.stdout-mux-hello,3:abc,3:def,}").unwrap();
+    ///assert_eq!(
+    ///    msg.display_redacted().to_string(),
+    ///    "(posix1.stdout-mux-hello <redacted> <redacted>)",
+    ///);
+    ///
+    ///let (msg, _) = Message::parse(b"{2|4:want,5:core1,}").unwrap();
+    ///assert_eq!(msg.display_redacted().to_string(), "(want core1)");
+    ///```
+    pub fn display_redacted(&self) -> RedactedMessage<'_, 's> {
+        RedactedMessage(self)
+    }
+
+    ///Returns a wrapper that encodes this message for a different major version of its module,
+    ///with the same member name and arguments. Returns `None` for messages with an eternal message
+    ///type, since those do not belong to any module.
+    ///
+    ///This helps with supporting adjacent major versions of a module where a message type did not
+    ///change between versions: The message can be translated into the version that the handler
+    ///implements, instead of implementing it twice. Whether a translation is semantically valid is
+    ///up to the caller.
+    ///
+    ///```
+    ///# use vt6::common::core::msg::*;
+    ///let (msg, _) = Message::parse(b"{2|9:core1.sub,11:core1.title,}").unwrap();
+    ///let mut buf = [0u8; 64];
+    ///let len = msg.with_major_version(2).unwrap().encode(&mut buf).unwrap();
+    ///let (translated, _) = Message::parse(&buf[0..len]).unwrap();
+    ///assert_eq!(translated.to_string(), "(core2.sub core1.title)");
+    ///
+    ///let (msg, _) = Message::parse(b"{2|4:want,5:core1,}").unwrap();
+    ///assert!(msg.with_major_version(2).is_none());
+    ///```
+    pub fn with_major_version(&self, major_version: u16) -> Option<TranslatedMessage<'_, 's>> {
+        match self.parsed_type {
+            MessageType::Scoped(_) => Some(TranslatedMessage {
+                msg: self,
+                major_version,
+            }),
+            _ => None,
+        }
+    }
+
+    fn format(&self, f: &mut core::fmt::Formatter, redact: bool) -> core::fmt::Result {
         write!(f, "({}", self.parsed_type)?;
-        for arg in self.arguments.clone() {
+        for (idx, arg) in self.arguments.clone().enumerate() {
+            if redact && is_secret_argument(self.parsed_type.as_str(), idx) {
+                f.write_str(" <redacted>")?;
+                continue;
+            }
             let escaped = arg.is_empty() || arg.iter().any(|&x| char_needs_escaping(x));
             f.write_str(if escaped { " \"" } else { " " })?;
-            for byte in arg.iter().flat_map(|&b| core::ascii::escape_default(b)) {
-                (byte as char).fmt(f)?;
+            match core::str::from_utf8(arg) {
+                Ok(text) if f.alternate() => {
+                    for ch in text.chars() {
+                        if ch.is_ascii() {
+                            write_escaped_byte(f, ch as u8)?;
+                        } else if ch.is_control() {
+                            write!(f, "{}", ch.escape_unicode())?;
+                        } else {
+                            write!(f, "{}", ch)?;
+                        }
+                    }
+                }
+                _ => {
+                    for &byte in arg {
+                        write_escaped_byte(f, byte)?;
+                    }
+                }
             }
             if escaped {
                 f.write_str("\"")?;
@@ -472,14 +1083,140 @@ impl<'s> core::fmt::Display for Message<'s> {
     }
 }
 
+///Returns whether the argument with the given index contains a secret in messages of the given
+///type.
+fn is_secret_argument(msg_type: &str, idx: usize) -> bool {
+    //this needs to be kept in sync with the credential-bearing messages in vt6::msg
+    match msg_type {
+        "core1.client-new"
+        | "posix1.client-hello"
+        | "posix1.padded-stdin-hello"
+        | "posix1.parent-hello"
+        | "posix1.stdin-hello"
+        | "posix1.stdout-hello" => idx == 0,
+        "posix1.stdout-mux-hello" => true,
+        _ => false,
+    }
+}
+
+///A wrapper that displays a message with its secrets redacted. This is returned by
+///[`Message::display_redacted()`](struct.Message.html#method.display_redacted).
+pub struct RedactedMessage<'m, 's>(&'m Message<'s>);
+
+impl<'m, 's> core::fmt::Display for RedactedMessage<'m, 's> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        self.0.format(f, true)
+    }
+}
+
+///A message that is encoded for a different major version of its module. This is returned by
+///[`Message::with_major_version()`](struct.Message.html#method.with_major_version).
+pub struct TranslatedMessage<'m, 's> {
+    msg: &'m Message<'s>,
+    major_version: u16,
+}
+
+impl<'m, 's> EncodeMessage for TranslatedMessage<'m, 's> {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, BufferTooSmallError> {
+        //with_major_version() only constructs this for scoped message types
+        let (name, _, member) = self.msg.parsed_type.route().unwrap();
+        let type_name = TranslatedMessageType {
+            name,
+            major_version: self.major_version,
+            member,
+        };
+        let mut f = MessageFormatter::new(buf, &type_name, self.msg.arguments.len());
+        for arg in self.msg.arguments() {
+            f.add_argument(arg);
+        }
+        f.finalize()
+    }
+}
+
+//The type name of a TranslatedMessage, i.e. `{name}{major_version}.{member}`.
+struct TranslatedMessageType<'a> {
+    name: &'a str,
+    major_version: u16,
+    member: &'a str,
+}
+
+impl<'a> EncodeArgument for TranslatedMessageType<'a> {
+    fn get_size(&self) -> usize {
+        self.name.len() + self.major_version.get_size() + 1 + self.member.len()
+    }
+    fn encode(&self, buf: &mut [u8]) {
+        let (name_buf, rest) = buf.split_at_mut(self.name.len());
+        let (version_buf, rest) = rest.split_at_mut(self.major_version.get_size());
+        name_buf.copy_from_slice(self.name.as_bytes());
+        self.major_version.encode(version_buf);
+        rest[0] = b'.';
+        rest[1..].copy_from_slice(self.member.as_bytes());
+    }
+}
+
+///An iterator over consecutive messages in a buffer. This is returned by
+///[`Message::parse_all()`](struct.Message.html#method.parse_all).
+#[derive(Clone, Debug)]
+pub struct MessageStream<'s> {
+    buffer: &'s [u8],
+    offset: usize,
+}
+
+impl<'s> MessageStream<'s> {
+    ///Returns the position within the buffer where the next item will be parsed from. After the
+    ///iterator has been exhausted, this is the length of the buffer.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'s> Iterator for MessageStream<'s> {
+    type Item = Result<Message<'s>, ParseError<'s>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.offset;
+        if start >= self.buffer.len() {
+            return None;
+        }
+        match Message::parse(&self.buffer[start..]) {
+            Ok((msg, len)) => {
+                self.offset += len;
+                Some(Ok(msg))
+            }
+            Err(e) => {
+                //nothing can follow an incomplete message
+                self.offset = if e.is_incomplete() {
+                    self.buffer.len()
+                } else {
+                    start + e.resync_offset
+                };
+                Some(Err(ParseError {
+                    buffer: self.buffer,
+                    offset: start + e.offset,
+                    resync_offset: start + e.resync_offset,
+                    ..e
+                }))
+            }
+        }
+    }
+}
+
+impl<'s> core::fmt::Display for Message<'s> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        self.format(f, false)
+    }
+}
+
+fn write_escaped_byte(f: &mut core::fmt::Formatter, byte: u8) -> core::fmt::Result {
+    for ch in core::ascii::escape_default(byte) {
+        core::fmt::Display::fmt(&(ch as char), f)?;
+    }
+    Ok(())
+}
+
 fn char_needs_escaping(ch: u8) -> bool {
     //vt6/foundation, sect. 3.1.3:
     //> Bytestrings whose value matches the regular expression `^[A-Za-z0-9._-]*$` are represented
     //> directly by their value.
-    !((b'A'..=b'Z').contains(&ch)
-        || (b'a'..=b'z').contains(&ch)
-        || (b'0'..=b'9').contains(&ch)
-        || ch == b'.'
-        || ch == b'_'
-        || ch == b'-')
+    !(ch.is_ascii_alphanumeric() || ch == b'.' || ch == b'_' || ch == b'-')
 }
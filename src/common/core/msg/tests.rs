@@ -85,6 +85,49 @@ fn expect_parse_incomplete(input: &[u8]) {
     expect_parse_fails(input, input.len(), UnexpectedEOF);
 }
 
+#[test]
+fn test_parse_error_spans() {
+    expect_parse_error_span(b"{4|4:want,4:core,1:1,1:2,#", Some(25..26), 26);
+    expect_parse_error_span(b"#{1|4:want,}", Some(0..1), 1);
+    expect_parse_error_span(b"{1|0:,}{1|4:want,}", Some(3..6), 7);
+    expect_parse_error_span(b"{01|10:sig1.claim,}", Some(1..3), 19);
+    expect_parse_error_span(b"{1|10000000000000000000000000000:", Some(3..32), 33);
+    //incomplete messages do not have an offending token
+    expect_parse_error_span(b"{4|4:want,4:co", None, 14);
+    expect_parse_error_span(b"", None, 0);
+    //an incomplete message does not resync to a `{` inside of it
+    expect_parse_error_span(b"{2|4:want,1:{", None, 13);
+}
+
+#[test]
+fn test_owned_parse_error() {
+    //context window is centered on the error offset
+    let input = b"{2|4:want,5:core1,}xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx{2|4:want,5:core1#}";
+    let err = OwnedParseError::from(Message::parse(&input[19..]).unwrap_err());
+    assert_eq!(err.kind, ExpectedMessageOpener);
+    assert_eq!(err.context_offset(), 0);
+    assert_eq!(err.context(), &input[19..51]);
+    let err = OwnedParseError::from(Message::parse(&input[50..]).unwrap_err());
+    assert_eq!(err.offset, 17);
+    assert_eq!(err.context_offset(), 1);
+    assert_eq!(err.context(), &input[51..]);
+    assert_eq!(
+        format!("{}", err),
+        r#"Parse error at offset 17: expected string closer near "2|4:want,5:core1#}""#
+    );
+
+    //context window is truncated at the end of the buffer
+    let err = OwnedParseError::from(Message::parse(b"").unwrap_err());
+    assert!(err.is_incomplete());
+    assert_eq!(err.context(), b"");
+}
+
+fn expect_parse_error_span(input: &[u8], span: Option<core::ops::Range<usize>>, resync: usize) {
+    let err = Message::parse(input).unwrap_err();
+    assert_eq!(err.span(), span);
+    assert_eq!(err.resync_offset, resync);
+}
+
 #[test]
 fn test_message_fmt_debug_display() {
     let (msg, _) = Message::parse(b"{2|4:want,5:core1,}").unwrap();
@@ -112,6 +155,33 @@ fn test_message_fmt_debug_display() {
     );
 }
 
+#[test]
+fn test_message_display_redacted() {
+    let (msg, _) =
+        Message::parse(b"{3|19:posix1.parent-hello,6:abc123,13:/run/vt6/1234,}").unwrap();
+    assert_eq!(
+        msg.display_redacted().to_string(),
+        r#"(posix1.parent-hello <redacted> "/run/vt6/1234")"#
+    );
+    let (msg, _) = Message::parse(b"{2|16:core1.client-new,4:a b!,}").unwrap();
+    assert_eq!(
+        msg.display_redacted().to_string(),
+        "(core1.client-new <redacted>)"
+    );
+
+    //messages without secrets are displayed as usual
+    let (msg, _) = Message::parse(b"{3|9:core1.set,13:example.bytes,5:\xA0a\"a\xC3,}").unwrap();
+    assert_eq!(msg.display_redacted().to_string(), msg.to_string());
+
+    //the decoded message types do not show their secrets either
+    #[cfg(feature = "module_posix")]
+    {
+        let (msg, _) = Message::parse(b"{2|18:posix1.stdin-hello,6:abc123,}").unwrap();
+        let hello = crate::msg::posix::StdinHello::decode_message(&msg).unwrap();
+        assert_eq!(format!("{:?}", hello), "StdinHello { secret: <redacted> }");
+    }
+}
+
 #[test]
 fn test_message_formatting() {
     let mut buf = vec![0; 4096];
@@ -157,6 +227,274 @@ fn test_message_formatting() {
     assert_eq!(f.finalize(), Err(BufferTooSmallError(required_size - 1024)));
 }
 
+#[test]
+fn test_message_formatter_at_offset() {
+    let mut buf = vec![0u8; 1100];
+    let filled = make_example_message(&mut buf).unwrap();
+    assert_eq!(filled, 19);
+
+    //the returned size is the new fill level, and the existing content is preserved
+    let f = MessageFormatter::new_at(&mut buf, filled, "sig.claim", 0);
+    let filled = f.finalize().unwrap();
+    assert_eq!(
+        &buf[0..filled],
+        b"{2|4:want,5:core1,}{1|9:sig.claim,}" as &[u8]
+    );
+
+    //the 1024-byte limit applies to the appended message, not to the whole buffer
+    let mut f = MessageFormatter::new_at(&mut buf, filled, "foo.bar", 250);
+    for _ in 0..250 {
+        f.add_argument(&0);
+    }
+    assert_eq!(f.finalize(), Ok(filled + 1016));
+
+    //when the buffer is too small, the missing bytes are reported
+    let mut f = MessageFormatter::new_at(&mut buf[0..40], filled, "want", 1);
+    f.add_argument("core1");
+    assert_eq!(f.finalize(), Err(BufferTooSmallError(filled + 19 - 40)));
+    assert_eq!(
+        crate::msg::Want(crate::common::core::ModuleIdentifier::parse("core1").unwrap())
+            .encode_at(&mut buf[0..40], 21),
+        Ok(40)
+    );
+}
+
+#[test]
+fn test_checked_message_formatter() {
+    let mut buf = vec![0u8; 1024];
+
+    //happy path
+    let mut f = CheckedMessageFormatter::new(&mut buf, "want", 1);
+    f.add_argument("core1");
+    let size = f.finalize().unwrap();
+    assert_eq!(&buf[0..size], b"{2|4:want,5:core1,}" as &[u8]);
+
+    //invalid message types
+    for type_name in &["", "core1", "core1.", "core1.s et", "core1.set,"] {
+        let f = CheckedMessageFormatter::new(&mut buf, type_name, 0);
+        assert_eq!(f.finalize(), Err(FormatError::InvalidMessageType));
+    }
+
+    //mismatching argument counts
+    let f = CheckedMessageFormatter::new(&mut buf, "core1.set", 2);
+    let err = f.finalize().unwrap_err();
+    assert_eq!(err.to_string(), "expected 2 arguments, but 0 were added");
+    let mut f = CheckedMessageFormatter::new(&mut buf, "core1.sub", 1);
+    f.add_argument("example1.title");
+    f.add_argument("example1.width");
+    assert_eq!(
+        f.finalize(),
+        Err(FormatError::ArgumentCountMismatch {
+            expected: 1,
+            actual: 2
+        })
+    );
+
+    //buffer errors are reported last
+    let mut f = CheckedMessageFormatter::new(&mut buf[0..10], "want", 1);
+    f.add_argument("core1");
+    assert_eq!(
+        f.finalize(),
+        Err(FormatError::BufferTooSmall(BufferTooSmallError(9)))
+    );
+}
+
+#[test]
+fn test_argument_spans() {
+    let buffer = b"{4|12:core1.sub-ok,0:,10:sig1.claim,3:{,},}";
+    let (msg, _) = Message::parse(buffer).unwrap();
+    let spans: Vec<_> = msg.arguments_with_spans().collect();
+    assert_eq!(spans.len(), 3);
+    assert_eq!(spans[0], (21..21, b"" as &[u8]));
+    assert_eq!(spans[1], (25..35, b"sig1.claim" as &[u8]));
+    assert_eq!(spans[2], (38..41, b"{,}" as &[u8]));
+    for (span, arg) in spans {
+        assert_eq!(&buffer[span], arg);
+    }
+
+    let (msg, _) = Message::parse(b"{1|10:sig1.claim,}").unwrap();
+    assert_eq!(msg.arguments_with_spans().len(), 0);
+    assert!(msg.arguments_with_spans().next().is_none());
+}
+
+#[test]
+fn test_parse_all() {
+    let buffer = b"{2|4:want,5:core1,}{1|10:sig1.claim,}{2|4:want,5#core1,}{2|4:want,4:sig1,}";
+    let mut iter = Message::parse_all(buffer);
+    assert_eq!(iter.next().unwrap().unwrap().to_string(), "(want core1)");
+    assert_eq!(iter.next().unwrap().unwrap().to_string(), "(sig1.claim)");
+    assert_eq!(iter.offset(), 37);
+
+    //errors refer to the entire buffer, and parsing resumes at the next message
+    let err = iter.next().unwrap().unwrap_err();
+    assert_eq!(err.kind, ExpectedStringSigil);
+    assert_eq!(err.offset, 48);
+    assert_eq!(err.span(), Some(48..49));
+    assert_eq!(err.resync_offset, 56);
+    assert_eq!(iter.next().unwrap().unwrap().to_string(), "(want sig1)");
+    assert!(iter.next().is_none());
+    assert_eq!(iter.offset(), buffer.len());
+
+    //empty buffer
+    assert!(Message::parse_all(b"").next().is_none());
+}
+
+#[test]
+fn test_encoded_size() {
+    let msg = crate::msg::Want(crate::common::core::ModuleIdentifier::parse("core1").unwrap());
+    let mut buf = [0u8; 1024];
+    assert_eq!(msg.encoded_size(), msg.encode(&mut buf).unwrap());
+    assert_eq!(msg.encoded_size(), 19);
+
+    //the size is also reported for messages exceeding the hard 1024-byte limit
+    struct Overlong;
+    impl EncodeMessage for Overlong {
+        fn encode(&self, buf: &mut [u8]) -> Result<usize, BufferTooSmallError> {
+            let mut f = MessageFormatter::new(buf, "foo.bar", 500);
+            for _ in 0..500 {
+                f.add_argument(&0);
+            }
+            f.finalize()
+        }
+    }
+    assert_eq!(Overlong.encoded_size(), 16 + 4 * 500);
+}
+
+#[test]
+fn test_translated_message() {
+    let (msg, _) = Message::parse(b"{3|9:core1.set,11:core1.title,5:hello,}").unwrap();
+    let mut buf = [0u8; 1024];
+
+    //the version number may change its length
+    let translated = msg.with_major_version(10).unwrap();
+    let len = translated.encode(&mut buf).unwrap();
+    assert_eq!(&buf[0..len], b"{3|10:core10.set,11:core1.title,5:hello,}");
+    assert_eq!(translated.encoded_size(), len);
+    let (translated, _) = Message::parse(&buf[0..len]).unwrap();
+    assert_eq!(translated.parsed_type().route(), Some(("core", 10, "set")));
+
+    //translating back yields the original message
+    let mut buf2 = [0u8; 1024];
+    let len2 = translated
+        .with_major_version(1)
+        .unwrap()
+        .encode(&mut buf2)
+        .unwrap();
+    assert_eq!(&buf2[0..len2], b"{3|9:core1.set,11:core1.title,5:hello,}");
+
+    assert_eq!(
+        msg.with_major_version(2).unwrap().encode(&mut buf[0..10]),
+        Err(BufferTooSmallError(29)),
+    );
+}
+
+#[test]
+fn test_decode_all() {
+    let (msg, _) = Message::parse(b"{4|12:example1.sum,3:foo,1:1,2:23,}").unwrap();
+
+    //leading arguments of a different type are taken off first
+    let mut args = msg.arguments();
+    assert_eq!(args.next(), Some(&b"foo"[..]));
+    let numbers = args.clone().decode_all::<u32>().unwrap();
+    assert_eq!(numbers.len(), 2);
+    assert_eq!(numbers.clone().sum::<u32>(), 24);
+    assert_eq!(numbers.collect::<Vec<_>>(), vec![1, 23]);
+
+    //the arguments are validated eagerly
+    assert!(msg.arguments().decode_all::<u32>().is_none());
+
+    //an empty list is fine
+    args.by_ref().for_each(drop);
+    assert_eq!(args.decode_all::<u32>().unwrap().next(), None);
+}
+
+#[test]
+fn test_exactly_n() {
+    let (msg, _) = Message::parse(b"{5|12:example1.foo,1:1,1:2,1:3,1:x,}").unwrap();
+    let args = msg.arguments();
+    let wrong_count = |expected| ArgumentError::WrongCount {
+        expected,
+        actual: 4,
+    };
+
+    //too few arguments are rejected just like trailing arguments
+    assert_eq!(args.clone().try_exactly1::<u8>(), Err(wrong_count(1)));
+    assert_eq!(args.clone().try_exactly2::<u8, u8>(), Err(wrong_count(2)));
+    assert_eq!(
+        args.clone().try_exactly3::<u8, u8, u8>(),
+        Err(wrong_count(3))
+    );
+    assert_eq!(
+        args.clone().try_exactly4::<u8, u8, u8, u8>(),
+        Err(ArgumentError::Undecodable { index: 3 })
+    );
+    assert_eq!(args.clone().exactly4::<u8, u8, u8, u8>(), None);
+    assert_eq!(
+        args.clone().exactly4::<u8, u8, u8, &str>(),
+        Some((1, 2, 3, "x"))
+    );
+
+    //the count refers to the remaining arguments
+    let mut args = args;
+    args.next();
+    assert_eq!(args.clone().exactly3::<u8, u8, &str>(), Some((2, 3, "x")));
+    assert_eq!(
+        ArgumentError::WrongCount {
+            expected: 2,
+            actual: 3
+        }
+        .to_string(),
+        "expected 2 arguments, got 3"
+    );
+    assert_eq!(
+        args.try_exactly3::<u8, u8, u8>().unwrap_err().to_string(),
+        "invalid value for argument 2"
+    );
+}
+
+#[test]
+fn test_optional_arguments() {
+    let (msg, _) = Message::parse(b"{4|12:example1.foo,1:1,1:2,1:x,}").unwrap();
+
+    //the count must be within the given range
+    assert_eq!(
+        msg.arguments().try_with_optional(1, 1).unwrap_err(),
+        ArgumentError::CountOutOfRange {
+            min: 1,
+            max: 2,
+            actual: 3
+        }
+    );
+    assert_eq!(
+        msg.arguments()
+            .try_with_optional(4, 0)
+            .unwrap_err()
+            .to_string(),
+        "expected 4 to 4 arguments, got 3"
+    );
+
+    //missing optional arguments are None or default
+    let mut args = msg.arguments().try_with_optional(2, 3).unwrap();
+    assert_eq!(args.required::<u8>(), Ok(1));
+    assert_eq!(args.optional::<u8>(), Ok(Some(2)));
+    assert_eq!(
+        args.clone().optional::<u8>(),
+        Err(ArgumentError::Undecodable { index: 2 })
+    );
+    assert_eq!(args.optional::<&str>(), Ok(Some("x")));
+    assert_eq!(args.optional::<u8>(), Ok(None));
+    assert_eq!(args.optional_or_default::<u32>(), Ok(0));
+
+    //asking for more required arguments than declared is reported like a missing argument
+    assert_eq!(
+        args.required::<u8>(),
+        Err(ArgumentError::WrongCount {
+            expected: 4,
+            actual: 3
+        })
+    );
+}
+
 fn make_example_message(buf: &mut [u8]) -> Result<usize, BufferTooSmallError> {
     let mut f = MessageFormatter::new(buf, "want", 1);
     f.add_argument("core1");
@@ -30,4 +30,32 @@ pub trait EncodeMessage {
     ///As the signature suggests, implementations of this method commonly use a
     ///[MessageFormatter](struct.MessageFormatter.html) to do the encoding work.
     fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError>;
+
+    ///Like `encode()`, but writes the message at the given offset into the buffer, and returns the
+    ///new fill level (i.e. `offset` plus the size of the message) instead of just the message
+    ///size. This is the counterpart to
+    ///[`MessageFormatter::new_at()`](struct.MessageFormatter.html#method.new_at) for appending
+    ///messages to a buffer.
+    ///
+    ///# Panics
+    ///
+    ///Panics if `offset` is beyond the end of the buffer.
+    fn encode_at(&self, buf: &mut [u8], offset: usize) -> Result<usize, msg::BufferTooSmallError> {
+        let size = self.encode(&mut buf[offset..])?;
+        Ok(offset + size)
+    }
+
+    ///Returns the number of bytes that `encode()` will write when given a sufficiently large
+    ///buffer. Callers can use this to choose a buffer of the right size upfront. If the result
+    ///exceeds the maximum message length of 1024 bytes, `encode()` will always fail.
+    ///
+    ///The default implementation encodes into an empty buffer, which makes the
+    ///[MessageFormatter](struct.MessageFormatter.html) count the required size without writing
+    ///anything.
+    fn encoded_size(&self) -> usize {
+        match self.encode(&mut []) {
+            Ok(size) => size,
+            Err(msg::BufferTooSmallError(size)) => size,
+        }
+    }
 }
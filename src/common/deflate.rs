@@ -0,0 +1,138 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::{DecodeArgument, EncodedArgument};
+
+///The maximum size of the data contained in a [CompressedBytes](struct.CompressedBytes.html)
+///argument. Arguments that decompress into more bytes than this are rejected, to protect the
+///receiver from decompression bombs.
+pub const DECOMPRESSED_MAX_BYTES: usize = 1 << 20;
+
+///An argument containing a byte string that is transferred in compressed form.
+///
+///The `deflate1` module is an extension provided by this crate; it is not part of the VT6
+///standard. Arguments of this type are encoded as a raw deflate stream
+///([RFC 1951](https://tools.ietf.org/html/rfc1951)). This is intended for large values that
+///compress well, e.g. snapshots of the scrollback buffer. Before sending arguments of this type,
+///a client must check for support with `(want deflate1)` (see
+///[vt6::client::probe_capabilities()](../client/fn.probe_capabilities.html)), and a server must
+///check that the client has agreed on the module (see
+///[vt6::server::deflate::is_agreed()](../server/deflate/fn.is_agreed.html)).
+///
+///The data is compressed once upon construction, and decompressed once upon decoding. Decoding
+///fails if the argument is not a valid deflate stream, or if it decompresses into more than
+///[DECOMPRESSED_MAX_BYTES](constant.DECOMPRESSED_MAX_BYTES.html).
+///
+///```
+///# use vt6::common::core::{DecodeArgument, EncodeArgument};
+///# use vt6::common::CompressedBytes;
+///let value = CompressedBytes::new(b"a".repeat(1000));
+///let encoded = value.encode_to_vector();
+///assert!(encoded.len() < 100);
+///
+///let decoded = CompressedBytes::decode_argument(&encoded).unwrap();
+///assert_eq!(decoded.data(), &b"a".repeat(1000)[..]);
+///```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompressedBytes {
+    data: Vec<u8>,
+    compressed: Vec<u8>,
+}
+
+impl CompressedBytes {
+    ///Compresses the given data.
+    pub fn new(data: Vec<u8>) -> Self {
+        let compressed = miniz_oxide::deflate::compress_to_vec(&data, 6);
+        Self { data, compressed }
+    }
+
+    ///Returns the uncompressed data.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    ///Returns the uncompressed data, consuming this instance.
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl EncodedArgument for CompressedBytes {
+    fn encoded(&self) -> &[u8] {
+        &self.compressed
+    }
+}
+
+impl<'a> DecodeArgument<'a> for CompressedBytes {
+    fn decode_argument(arg: &'a [u8]) -> Option<Self> {
+        Some(Self {
+            data: decompress(arg)?,
+            compressed: arg.to_vec(),
+        })
+    }
+}
+
+//We cannot use miniz_oxide::inflate::decompress_to_vec_with_limit() since it grows its buffer in
+//steps that may overshoot the limit, so it sometimes rejects data that fits within the limit.
+fn decompress(input: &[u8]) -> Option<Vec<u8>> {
+    use miniz_oxide::inflate::core::{decompress, inflate_flags, DecompressorOxide};
+    use miniz_oxide::inflate::TINFLStatus;
+
+    let flags = inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    let initial_size = input.len().saturating_mul(2).max(64);
+    let mut buf = vec![0u8; initial_size.min(DECOMPRESSED_MAX_BYTES)];
+    let mut decomp = DecompressorOxide::default();
+    let (mut in_pos, mut out_pos) = (0, 0);
+    loop {
+        let (status, in_consumed, out_consumed) =
+            decompress(&mut decomp, &input[in_pos..], &mut buf, out_pos, flags);
+        in_pos += in_consumed;
+        out_pos += out_consumed;
+        match status {
+            TINFLStatus::Done => {
+                buf.truncate(out_pos);
+                return Some(buf);
+            }
+            TINFLStatus::HasMoreOutput if buf.len() < DECOMPRESSED_MAX_BYTES => {
+                let new_size = buf.len().saturating_mul(2).min(DECOMPRESSED_MAX_BYTES);
+                buf.resize(new_size, 0);
+            }
+            //either the data is too large, or the input is not a valid deflate stream
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::EncodeArgument;
+
+    #[test]
+    fn test_compressed_bytes() {
+        for data in &[&b""[..], &b"hello"[..], &b"hello hello hello hello"[..]] {
+            let encoded = CompressedBytes::new(data.to_vec()).encode_to_vector();
+            let decoded = CompressedBytes::decode_argument(&encoded).unwrap();
+            assert_eq!(decoded.data(), *data);
+            assert_eq!(decoded.encode_to_vector(), encoded);
+        }
+
+        //invalid deflate streams are rejected
+        assert_eq!(CompressedBytes::decode_argument(b"hello"), None);
+        let encoded = CompressedBytes::new(b"hello".to_vec()).encode_to_vector();
+        assert_eq!(
+            CompressedBytes::decode_argument(&encoded[0..encoded.len() - 1]),
+            None
+        );
+
+        //decompression bombs are rejected
+        let encoded = CompressedBytes::new(vec![0u8; DECOMPRESSED_MAX_BYTES]).encode_to_vector();
+        assert!(CompressedBytes::decode_argument(&encoded).is_some());
+        let encoded =
+            CompressedBytes::new(vec![0u8; DECOMPRESSED_MAX_BYTES + 1]).encode_to_vector();
+        assert_eq!(CompressedBytes::decode_argument(&encoded), None);
+    }
+}
@@ -0,0 +1,93 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg;
+
+///The length of the header that precedes each frame's payload.
+pub const FRAME_HEADER_LEN: usize = 2;
+
+///The maximum payload length of a single frame.
+pub const MAX_FRAME_PAYLOAD_LEN: usize = u16::MAX as usize;
+
+///Decodes the frame at the start of the given buffer.
+///
+///On stream sockets, message boundaries are normally reconstructed by the message parser. After a
+///connection has switched into framed mode (see [vt6::msg::frame](../msg/frame/index.html)), each
+///message is instead sent as a frame, consisting of a [header](constant.FRAME_HEADER_LEN.html)
+///with the payload length as a big-endian `u16`, followed by the payload itself.
+///
+///Returns the payload and the length of the whole frame (header plus payload), or `None` if the
+///buffer does not contain a complete frame yet.
+///
+///```
+///# use vt6::common::decode_frame;
+///let buf = b"\x00\x13{2|4:want,5:core1,}{2|4:";
+///let (payload, len) = decode_frame(buf).unwrap();
+///assert_eq!(payload, b"{2|4:want,5:core1,}");
+///assert_eq!(len, 21);
+///assert_eq!(decode_frame(&buf[len..]), None);
+///```
+pub fn decode_frame(buf: &[u8]) -> Option<(&[u8], usize)> {
+    if buf.len() < FRAME_HEADER_LEN {
+        return None;
+    }
+    let payload_len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    let frame_len = FRAME_HEADER_LEN + payload_len;
+    if buf.len() < frame_len {
+        return None;
+    }
+    Some((&buf[FRAME_HEADER_LEN..frame_len], frame_len))
+}
+
+///Encodes the given payload into a frame. Returns the length of the frame, which is
+///[FRAME_HEADER_LEN](constant.FRAME_HEADER_LEN.html) plus the length of the payload.
+///
+///# Panics
+///
+///Panics if the payload is longer than
+///[MAX_FRAME_PAYLOAD_LEN](constant.MAX_FRAME_PAYLOAD_LEN.html).
+pub fn encode_frame(payload: &[u8], buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+    assert!(
+        payload.len() <= MAX_FRAME_PAYLOAD_LEN,
+        "encode_frame() called with overlong payload"
+    );
+    let frame_len = FRAME_HEADER_LEN + payload.len();
+    if buf.len() < frame_len {
+        return Err(msg::BufferTooSmallError(frame_len - buf.len()));
+    }
+    buf[0..FRAME_HEADER_LEN].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+    buf[FRAME_HEADER_LEN..frame_len].copy_from_slice(payload);
+    Ok(frame_len)
+}
+
+///A wrapper for a message that encodes it as a frame, like
+///[encode_frame()](fn.encode_frame.html) does for an already encoded message.
+///
+///```
+///# use vt6::common::core::{msg::EncodeMessage, ModuleIdentifier};
+///# use vt6::common::Framed;
+///let msg = vt6::msg::Want(ModuleIdentifier::parse("core1").unwrap());
+///let mut buf = [0u8; 64];
+///let len = Framed(&msg).encode(&mut buf).unwrap();
+///assert_eq!(&buf[0..len], b"\x00\x13{2|4:want,5:core1,}");
+///```
+pub struct Framed<'m, M: msg::EncodeMessage>(pub &'m M);
+
+impl<'m, M: msg::EncodeMessage> msg::EncodeMessage for Framed<'m, M> {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        if buf.len() < FRAME_HEADER_LEN {
+            return Err(msg::BufferTooSmallError(self.encoded_size() - buf.len()));
+        }
+        //encoded messages are never longer than 1024 bytes, so the payload length always fits
+        let payload_len = self.0.encode(&mut buf[FRAME_HEADER_LEN..])?;
+        buf[0..FRAME_HEADER_LEN].copy_from_slice(&(payload_len as u16).to_be_bytes());
+        Ok(FRAME_HEADER_LEN + payload_len)
+    }
+
+    fn encoded_size(&self) -> usize {
+        FRAME_HEADER_LEN + self.0.encoded_size()
+    }
+}
@@ -0,0 +1,138 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg;
+
+///A buffer of fixed capacity, whose first part is filled with data.
+///
+///This is the buffer type that the client and server implementations in this crate use for
+///sending and receiving. Data is appended at the end of the filled part, either by writing into
+///`unfilled_mut()` (e.g. when reading from a socket) and then calling `fill()`, or with
+///`fill_from()` and `push_message()`. Processed data is removed from the start with `discard()`.
+///
+///With the `use_std` and `module_posix` features, this type also implements
+///[vt6::server::ReceiveBuffer](../../server/trait.ReceiveBuffer.html), so custom
+///[Dispatch](../../server/trait.Dispatch.html) implementations can read from their client sockets
+///directly into a FixedBuffer. Since messages are never longer than 1024 bytes
+///[vt6/foundation, sect. 3.1.2], a capacity of 1024 is enough for receiving messages.
+///
+///```
+///use vt6::common::io::FixedBuffer;
+///
+///let mut buf = FixedBuffer::<8>::new();
+///let rest = buf.fill_from(b"hello world");
+///assert_eq!((buf.filled(), rest), (&b"hello wo"[..], &b"rld"[..]));
+///assert!(buf.is_full());
+///
+///buf.discard(6);
+///buf.unfilled_mut()[0..2].copy_from_slice(b"rl");
+///buf.fill(2);
+///assert_eq!(buf.filled(), b"worl");
+///assert_eq!(buf.unfilled_len(), 4);
+///```
+#[derive(Clone)]
+pub struct FixedBuffer<const N: usize> {
+    buf: [u8; N],
+    filled: usize,
+}
+
+impl<const N: usize> Default for FixedBuffer<N> {
+    fn default() -> Self {
+        Self {
+            buf: [0; N],
+            filled: 0,
+        }
+    }
+}
+
+impl<const N: usize> core::fmt::Debug for FixedBuffer<N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("FixedBuffer")
+            .field("capacity", &N)
+            .field("filled", &self.filled())
+            .finish()
+    }
+}
+
+impl<const N: usize> FixedBuffer<N> {
+    ///Creates an empty buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Returns the filled portion of the buffer.
+    pub fn filled(&self) -> &[u8] {
+        &self.buf[0..self.filled]
+    }
+
+    ///Returns the length of the filled portion of the buffer.
+    pub fn filled_len(&self) -> usize {
+        self.filled
+    }
+
+    ///Returns the unfilled portion of the buffer, so that data can be written into it. Afterwards,
+    ///`fill()` must be called to mark the written data as filled.
+    pub fn unfilled_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[self.filled..]
+    }
+
+    ///Returns the length of the unfilled portion of the buffer.
+    pub fn unfilled_len(&self) -> usize {
+        N - self.filled
+    }
+
+    ///Returns whether there is no space left in the buffer.
+    pub fn is_full(&self) -> bool {
+        self.filled == N
+    }
+
+    ///Marks the first `len` bytes of the unfilled portion as filled, after they have been written
+    ///through `unfilled_mut()`.
+    ///
+    ///Panics if `len` is larger than `self.unfilled_len()`.
+    pub fn fill(&mut self, len: usize) {
+        assert!(
+            len <= self.unfilled_len(),
+            "cannot fill {} bytes in a buffer with only {} bytes left",
+            len,
+            self.unfilled_len()
+        );
+        self.filled += len;
+    }
+
+    ///Fills up the unfilled portion of this buffer as much as possible from `input`, and returns
+    ///the part of `input` that did not fit.
+    pub fn fill_from<'b>(&mut self, input: &'b [u8]) -> &'b [u8] {
+        let len = core::cmp::min(input.len(), self.unfilled_len());
+        self.buf[self.filled..(self.filled + len)].copy_from_slice(&input[0..len]);
+        self.filled += len;
+        &input[len..]
+    }
+
+    ///Appends the encoded message to the filled portion. The message is appended completely or
+    ///not at all; in the latter case, the buffer is unchanged.
+    pub fn push_message<M: msg::EncodeMessage>(
+        &mut self,
+        msg: &M,
+    ) -> Result<(), msg::BufferTooSmallError> {
+        self.filled = msg.encode_at(&mut self.buf, self.filled)?;
+        Ok(())
+    }
+
+    ///Discards the first `len` bytes from the filled portion, and moves the rest of it to the
+    ///start of the buffer.
+    ///
+    ///Panics if `len` is larger than `self.filled_len()`.
+    pub fn discard(&mut self, len: usize) {
+        self.buf.copy_within(len..self.filled, 0);
+        self.filled -= len;
+    }
+
+    ///Discards all data in the buffer.
+    pub fn clear(&mut self) {
+        self.filled = 0;
+    }
+}
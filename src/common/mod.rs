@@ -4,6 +4,38 @@
 * Refer to the file "LICENSE" for details.
 *******************************************************************************/
 
+#[cfg(all(feature = "use_std", feature = "module_posix"))]
+mod send_buffer;
+#[cfg(all(feature = "use_std", feature = "module_posix"))]
+pub(crate) use self::send_buffer::*;
+#[cfg(feature = "use_std")]
+mod chunking;
+#[cfg(feature = "use_std")]
+pub use self::chunking::*;
+#[cfg(feature = "module_deflate")]
+mod deflate;
+#[cfg(feature = "module_deflate")]
+pub use self::deflate::*;
+mod framing;
+pub use self::framing::*;
+#[cfg(feature = "module_posix")]
+mod padding;
+#[cfg(feature = "module_posix")]
+pub use self::padding::*;
+#[cfg(feature = "module_posix")]
+mod stdout_mux;
+#[cfg(feature = "module_posix")]
+pub use self::stdout_mux::*;
+#[cfg(feature = "use_tokio")]
+mod timeout;
+#[cfg(feature = "use_tokio")]
+pub(crate) use self::timeout::*;
+mod utf8;
+pub use self::utf8::*;
+
+///Buffer types that are shared between the client and server implementations.
+pub mod io;
+
 ///Common types and definitions for the [vt6/foundation](https://vt6.io/std/foundation/) and
 ///[vt6/core](https://vt6.io/std/core/) modules.
 pub mod core;
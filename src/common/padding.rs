@@ -0,0 +1,198 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg;
+
+///The length of the header that precedes each chunk on a padded stdin connection.
+pub const PADDED_HEADER_LEN: usize = 4;
+
+///The maximum data length of a single chunk on a padded stdin connection.
+pub const MAX_PADDED_DATA_LEN: usize = u16::MAX as usize;
+
+///Decodes the chunk header at the start of the given buffer.
+///
+///A padded stdin connection (see [PaddedStdinHello](../msg/posix/struct.PaddedStdinHello.html))
+///carries the standard input of a screen like a regular stdin connection, but its byte stream is
+///a sequence of chunks, so that the server can make its writes longer than the data that they
+///contain. Each chunk consists of a [header](constant.PADDED_HEADER_LEN.html), the data, and the
+///padding. The header contains the data length and the padding length, both as big-endian `u16`.
+///The padding consists of zero bytes and is not part of the standard input.
+///
+///Returns the data length and the padding length, or `None` if the buffer does not contain a
+///complete header yet. Clients that read the stream piecemeal should use
+///[PaddedStdinDecoder](struct.PaddedStdinDecoder.html) instead.
+///
+///```
+///# use vt6::common::decode_padded_header;
+///assert_eq!(decode_padded_header(b"\x00\x02\x00\x03hi\0\0\0"), Some((2, 3)));
+///assert_eq!(decode_padded_header(b"\x00\x02\x00"), None);
+///```
+pub fn decode_padded_header(buf: &[u8]) -> Option<(usize, usize)> {
+    if buf.len() < PADDED_HEADER_LEN {
+        return None;
+    }
+    let data_len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    let padding_len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+    Some((data_len, padding_len))
+}
+
+///Encodes a chunk for a padded stdin connection, consisting of the given data followed by
+///`padding_len` zero bytes. Returns the length of the chunk, which is
+///[PADDED_HEADER_LEN](constant.PADDED_HEADER_LEN.html) plus the length of the data plus
+///`padding_len`.
+///
+///```
+///# use vt6::common::encode_padded_chunk;
+///let mut buf = [0xFFu8; 16];
+///let len = encode_padded_chunk(b"hi", 3, &mut buf).unwrap();
+///assert_eq!(&buf[0..len], b"\x00\x02\x00\x03hi\0\0\0");
+///```
+///
+///# Panics
+///
+///Panics if the data or the padding is longer than
+///[MAX_PADDED_DATA_LEN](constant.MAX_PADDED_DATA_LEN.html).
+pub fn encode_padded_chunk(
+    data: &[u8],
+    padding_len: usize,
+    buf: &mut [u8],
+) -> Result<usize, msg::BufferTooSmallError> {
+    assert!(
+        data.len() <= MAX_PADDED_DATA_LEN,
+        "encode_padded_chunk() called with overlong data"
+    );
+    assert!(
+        padding_len <= MAX_PADDED_DATA_LEN,
+        "encode_padded_chunk() called with overlong padding"
+    );
+    let data_end = PADDED_HEADER_LEN + data.len();
+    let chunk_len = data_end + padding_len;
+    if buf.len() < chunk_len {
+        return Err(msg::BufferTooSmallError(chunk_len - buf.len()));
+    }
+    buf[0..2].copy_from_slice(&(data.len() as u16).to_be_bytes());
+    buf[2..PADDED_HEADER_LEN].copy_from_slice(&(padding_len as u16).to_be_bytes());
+    buf[PADDED_HEADER_LEN..data_end].copy_from_slice(data);
+    for b in &mut buf[data_end..chunk_len] {
+        *b = 0;
+    }
+    Ok(chunk_len)
+}
+
+///Removes the chunk headers and the padding from the byte stream of a padded stdin connection
+///(see [decode_padded_header()](fn.decode_padded_header.html) for the format), leaving only the
+///standard input.
+///
+///Since a read from the socket can end anywhere within a chunk, the decoder remembers between
+///calls how much of the current chunk is left.
+///
+///```
+///# use vt6::common::PaddedStdinDecoder;
+///let mut decoder = PaddedStdinDecoder::new();
+///let mut buf = *b"\x00\x02\x00\x03hi\0\0\0\x00\x01";
+///let len = decoder.decode_in_place(&mut buf);
+///assert_eq!(&buf[0..len], b"hi");
+///assert!(!decoder.is_at_chunk_boundary());
+///
+///let mut buf = *b"\x00\x00!";
+///let len = decoder.decode_in_place(&mut buf);
+///assert_eq!(&buf[0..len], b"!");
+///assert!(decoder.is_at_chunk_boundary());
+///```
+#[derive(Clone, Debug, Default)]
+pub struct PaddedStdinDecoder {
+    header: [u8; PADDED_HEADER_LEN],
+    header_len: usize,
+    data_left: usize,
+    padding_left: usize,
+}
+
+impl PaddedStdinDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Decodes the given part of the byte stream in place. The data contained in it is moved to
+    ///the start of the buffer, and its length is returned. The rest of the buffer is left in an
+    ///unspecified state.
+    pub fn decode_in_place(&mut self, buf: &mut [u8]) -> usize {
+        let (mut read, mut written) = (0, 0);
+        while read < buf.len() {
+            let available = buf.len() - read;
+            if self.data_left > 0 {
+                let len = core::cmp::min(self.data_left, available);
+                buf.copy_within(read..read + len, written);
+                read += len;
+                written += len;
+                self.data_left -= len;
+            } else if self.padding_left > 0 {
+                let len = core::cmp::min(self.padding_left, available);
+                read += len;
+                self.padding_left -= len;
+            } else {
+                self.header[self.header_len] = buf[read];
+                read += 1;
+                self.header_len += 1;
+                if let Some((data_len, padding_len)) =
+                    decode_padded_header(&self.header[0..self.header_len])
+                {
+                    self.header_len = 0;
+                    self.data_left = data_len;
+                    self.padding_left = padding_len;
+                }
+            }
+        }
+        written
+    }
+
+    ///Returns whether everything that was given to the decoder so far consisted of complete
+    ///chunks. When the connection is closed while this is false, the server has sent an
+    ///incomplete chunk.
+    pub fn is_at_chunk_boundary(&self) -> bool {
+        self.header_len == 0 && self.data_left == 0 && self.padding_left == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let mut buf = [0xFFu8; 32];
+        let len = encode_padded_chunk(b"hello", 7, &mut buf).unwrap();
+        assert_eq!(len, PADDED_HEADER_LEN + 12);
+        assert_eq!(decode_padded_header(&buf[0..len]), Some((5, 7)));
+        assert_eq!(&buf[PADDED_HEADER_LEN..len], b"hello\0\0\0\0\0\0\0");
+
+        assert_eq!(
+            encode_padded_chunk(b"hello", 7, &mut buf[0..10]),
+            Err(msg::BufferTooSmallError(6))
+        );
+    }
+
+    #[test]
+    fn test_decoder_with_split_chunks() {
+        let mut stream = [0u8; 64];
+        let mut len = encode_padded_chunk(b"abc", 5, &mut stream).unwrap();
+        len += encode_padded_chunk(b"", 4, &mut stream[len..]).unwrap();
+        len += encode_padded_chunk(b"defg", 0, &mut stream[len..]).unwrap();
+        let stream = &stream[0..len];
+
+        //no matter where the reads end, the decoder must yield the same data
+        for step in 1..=stream.len() {
+            let mut decoder = PaddedStdinDecoder::new();
+            let mut result = Vec::new();
+            for piece in stream.chunks(step) {
+                let mut piece = piece.to_vec();
+                let data_len = decoder.decode_in_place(&mut piece);
+                result.extend_from_slice(&piece[0..data_len]);
+            }
+            assert_eq!(result, b"abcdefg", "step = {}", step);
+            assert!(decoder.is_at_chunk_boundary(), "step = {}", step);
+        }
+    }
+}
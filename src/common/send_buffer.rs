@@ -0,0 +1,154 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg;
+use crate::common::io::FixedBuffer;
+
+//Assuming a 64-bit platform, this makes sizeof(SendBuffer) = 4080. General-purpose allocators
+//usually need 8-16 bytes per allocation for bookkeeping, so overall Box<SendBuffer> allocates just
+//enough to fit snugly into a single 4 KiB memory page.
+pub(crate) type SendBuffer = FixedBuffer<4072>;
+
+///A queue of SendBuffer instances. This implements the send buffering
+///that is shared between the server's transmitter jobs and the client's send methods: Small
+///messages are coalesced into the same buffer, so that they can be sent with a single write.
+///
+///The buffers that contain data always form a prefix of `self.bufs`. Empty buffers are kept
+///around at the end for reuse.
+#[derive(Default)]
+pub(crate) struct SendQueue {
+    //The boxes shall be allocated individually since we pass them around outside the Vec.
+    #[allow(clippy::vec_box)]
+    bufs: Vec<Box<SendBuffer>>,
+}
+
+impl SendQueue {
+    ///Enqueues an encoded message. The message is never split across multiple buffers, to
+    ///increase the chance that it is transmitted in one piece.
+    pub(crate) fn push_message<M: msg::EncodeMessage>(
+        &mut self,
+        msg: &M,
+    ) -> Result<(), msg::BufferTooSmallError> {
+        //put the message into the current send buffer (the last one in line that already contains
+        //some data) if it fits
+        let size = msg.encoded_size();
+        let filled_bufs = self.bufs.iter_mut().filter(|b| b.filled_len() > 0);
+        if let Some(send_buffer) = filled_bufs.last() {
+            if send_buffer.unfilled_len() >= size {
+                return send_buffer.push_message(msg);
+            }
+        }
+
+        //otherwise put it into the send buffer directly following that one (the first one that
+        //does not have any data in it) - if this errors out, it's because the rendered message is
+        //legitimately too long
+        self.next_empty_buffer().push_message(msg)
+    }
+
+    ///Enqueues arbitrary bytes. Unlike with `push_message()`, the input may be split across
+    ///multiple buffers, since it is possible that we get a ton of stdin at once (e.g. from a
+    ///clipboard paste) that does not fit into one send buffer at all.
+    //only used by the server for sending stdin
+    #[cfg_attr(not(feature = "use_tokio"), allow(dead_code))]
+    pub(crate) fn push_bytes(&mut self, mut input: &[u8]) {
+        //try to fit data into the current send buffer (the last one in line that already contains
+        //some data)
+        let filled_bufs = self.bufs.iter_mut().filter(|b| b.filled_len() > 0);
+        if let Some(send_buffer) = filled_bufs.last() {
+            input = send_buffer.fill_from(input);
+        }
+
+        //if that's not enough, fill the free send buffers directly following that one in order
+        while !input.is_empty() {
+            input = self.next_empty_buffer().fill_from(input);
+        }
+    }
+
+    fn next_empty_buffer(&mut self) -> &mut SendBuffer {
+        if !self.bufs.iter().any(|b| b.filled_len() == 0) {
+            //if there are no empty send buffers left, append a new one
+            self.bufs.push(Default::default());
+        }
+        self.bufs.iter_mut().find(|b| b.filled_len() == 0).unwrap()
+    }
+
+    ///Removes the next buffer containing data from the queue, so that it can be sent. Returns
+    ///`None` if there is no data to send.
+    pub(crate) fn pop(&mut self) -> Option<Box<SendBuffer>> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.bufs.remove(0))
+        }
+    }
+
+    ///Returns a buffer obtained from `pop()` after it has been sent, so that it can be reused.
+    pub(crate) fn recycle(&mut self, mut buf: Box<SendBuffer>) {
+        buf.clear();
+        self.bufs.push(buf);
+    }
+
+    ///Discards all data that is waiting to be sent, and returns how many bytes were discarded.
+    //only used by the server for enforcing its send buffer limit
+    #[cfg_attr(not(feature = "use_tokio"), allow(dead_code))]
+    pub(crate) fn clear(&mut self) -> usize {
+        let len = self.filled_len();
+        for buf in self.bufs.iter_mut() {
+            buf.clear();
+        }
+        len
+    }
+
+    ///Returns whether there is no data to send.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.bufs.iter().all(|b| b.filled_len() == 0)
+    }
+
+    ///Returns how many bytes are waiting to be sent.
+    pub(crate) fn filled_len(&self) -> usize {
+        self.bufs.iter().map(|b| b.filled_len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::ModuleIdentifier;
+    use crate::msg::Want;
+
+    #[test]
+    fn test_send_queue_coalescing() {
+        let mut q = SendQueue::default();
+        assert!(q.is_empty());
+        assert!(q.pop().is_none());
+
+        //small messages end up in the same buffer
+        let msg = Want(ModuleIdentifier::parse("core1").unwrap());
+        q.push_message(&msg).unwrap();
+        q.push_message(&msg).unwrap();
+        assert_eq!(q.filled_len(), 38);
+        let buf = q.pop().unwrap();
+        assert_eq!(buf.filled(), b"{2|4:want,5:core1,}{2|4:want,5:core1,}");
+        assert!(q.pop().is_none());
+        q.recycle(buf);
+
+        //bytes are split across buffers, and the recycled buffer is reused
+        q.push_bytes(&[b'x'; 5000]);
+        assert_eq!(q.filled_len(), 5000);
+        assert_eq!(q.bufs.len(), 2);
+
+        //messages are not split, so this one does not fit into the remaining space of the second
+        //buffer and goes into a third one
+        q.push_bytes(&[b'y'; 3140]);
+        q.push_message(&msg).unwrap();
+        let lens: Vec<_> = q.bufs.iter().map(|b| b.filled_len()).collect();
+        assert_eq!(lens, vec![4072, 4068, 19]);
+        assert_eq!(q.pop().unwrap().filled_len(), 4072);
+        assert_eq!(q.pop().unwrap().filled_len(), 4068);
+        assert_eq!(q.pop().unwrap().filled(), b"{2|4:want,5:core1,}");
+        assert!(q.is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg;
+
+///The length of the header that precedes each chunk on a multiplexed stdout connection.
+pub const MUX_HEADER_LEN: usize = 3;
+
+///The maximum number of screens that a single multiplexed stdout connection can serve.
+pub const MAX_MUX_SCREENS: usize = u8::MAX as usize + 1;
+
+///Decodes the chunk header at the start of the given buffer.
+///
+///A multiplexed stdout connection (see
+///[StdoutMuxHello](../msg/posix/struct.StdoutMuxHello.html)) carries the output of several
+///screens. Its byte stream is a sequence of chunks, each consisting of a
+///[header](constant.MUX_HEADER_LEN.html) and the payload. The header contains the index of the
+///screen (in the order in which the secrets appeared in the handshake) as a `u8`, followed by the
+///payload length as a big-endian `u16`.
+///
+///Returns the screen index and the payload length, or `None` if the buffer does not contain a
+///complete header yet. Unlike with [decode_frame()](fn.decode_frame.html), the payload does not
+///need to be complete, since stdout can be shown as it arrives.
+///
+///```
+///# use vt6::common::decode_mux_header;
+///assert_eq!(decode_mux_header(b"\x01\x00\x05hello"), Some((1, 5)));
+///assert_eq!(decode_mux_header(b"\x01\x00"), None);
+///```
+pub fn decode_mux_header(buf: &[u8]) -> Option<(usize, usize)> {
+    if buf.len() < MUX_HEADER_LEN {
+        return None;
+    }
+    let payload_len = u16::from_be_bytes([buf[1], buf[2]]) as usize;
+    Some((buf[0] as usize, payload_len))
+}
+
+///Encodes a chunk of output for the screen with the given index on a multiplexed stdout
+///connection. Returns the length of the chunk, which is
+///[MUX_HEADER_LEN](constant.MUX_HEADER_LEN.html) plus the length of the payload.
+///
+///```
+///# use vt6::common::encode_mux_chunk;
+///let mut buf = [0u8; 16];
+///let len = encode_mux_chunk(1, b"hello", &mut buf).unwrap();
+///assert_eq!(&buf[0..len], b"\x01\x00\x05hello");
+///```
+///
+///# Panics
+///
+///Panics if the screen index is not below [MAX_MUX_SCREENS](constant.MAX_MUX_SCREENS.html), or
+///if the payload is longer than `u16::MAX` bytes.
+pub fn encode_mux_chunk(
+    screen_index: usize,
+    payload: &[u8],
+    buf: &mut [u8],
+) -> Result<usize, msg::BufferTooSmallError> {
+    assert!(
+        screen_index < MAX_MUX_SCREENS,
+        "encode_mux_chunk() called with screen index out of range"
+    );
+    assert!(
+        payload.len() <= u16::MAX as usize,
+        "encode_mux_chunk() called with overlong payload"
+    );
+    let chunk_len = MUX_HEADER_LEN + payload.len();
+    if buf.len() < chunk_len {
+        return Err(msg::BufferTooSmallError(chunk_len - buf.len()));
+    }
+    buf[0] = screen_index as u8;
+    buf[1..MUX_HEADER_LEN].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+    buf[MUX_HEADER_LEN..chunk_len].copy_from_slice(payload);
+    Ok(chunk_len)
+}
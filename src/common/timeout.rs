@@ -0,0 +1,46 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use core::future::Future;
+use std::io;
+use std::time::{Duration, Instant};
+
+//All timeouts in the async parts of this crate go through these functions instead of calling
+//into tokio::time directly, so that this is the only place that needs to change if those parts
+//ever support other async runtimes.
+
+///Polls the future until it completes or until the timeout expires, whichever comes first.
+///Returns `None` on timeout. The future is dropped in that case, so callers that want to keep
+///polling it afterwards need to pass a `Pin<&mut F>`.
+pub(crate) async fn timeout<F: Future>(timeout: Duration, future: F) -> Option<F::Output> {
+    tokio::time::timeout(timeout, future).await.ok()
+}
+
+///Returns the current time as seen by the async runtime. This differs from `Instant::now()` when
+///the runtime's clock is paused or advanced, e.g. in simulation tests.
+pub(crate) fn now() -> Instant {
+    tokio::time::Instant::now().into_std()
+}
+
+///Waits until the given point in time, as measured by `now()`.
+pub(crate) async fn sleep_until(deadline: Instant) {
+    tokio::time::sleep_until(deadline.into()).await
+}
+
+///Like `timeout()`, but for futures returning `io::Result`. A timeout is reported as an error of
+///kind `TimedOut` with the message "timed out while waiting for {what}".
+pub(crate) async fn io_timeout<T, F>(duration: Duration, future: F, what: &str) -> io::Result<T>
+where
+    F: Future<Output = io::Result<T>>,
+{
+    match timeout(duration, future).await {
+        Some(result) => result,
+        None => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("timed out while waiting for {}", what),
+        )),
+    }
+}
@@ -0,0 +1,258 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+const REPLACEMENT_CHARACTER: &str = "\u{FFFD}";
+
+///Shortens a string to at most `max` bytes without splitting a character, e.g. when a property
+///value has a maximum length in bytes. If the string is short enough, it is returned unchanged.
+///
+///```
+///# use vt6::common::truncate_str_to_bytes;
+///assert_eq!(truncate_str_to_bytes("hello", 3), "hel");
+///assert_eq!(truncate_str_to_bytes("hello", 10), "hello");
+/////"\u{E4}" is two bytes long, so it cannot be cut off after its first byte
+///assert_eq!(truncate_str_to_bytes("h\u{E4}llo", 2), "h");
+///```
+pub fn truncate_str_to_bytes(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut len = max;
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    &s[0..len]
+}
+
+///Like [truncate_str_to_bytes()](fn.truncate_str_to_bytes.html), but for byte strings that are
+///expected to contain UTF-8, e.g. property values that have not been decoded yet. The cut is
+///moved backwards to the start of a UTF-8 sequence, so that a valid UTF-8 input remains valid.
+///Invalid input is cut at exactly `max` bytes if there is no sequence start within reach.
+///
+///```
+///# use vt6::common::truncate_utf8_bytes;
+///assert_eq!(truncate_utf8_bytes(b"hello", 3), b"hel");
+///assert_eq!(truncate_utf8_bytes("h\u{20AC}llo".as_bytes(), 3), b"h");
+///assert_eq!(truncate_utf8_bytes(b"\xFF\xFF\xFF", 2), b"\xFF\xFF");
+///```
+pub fn truncate_utf8_bytes(buf: &[u8], max: usize) -> &[u8] {
+    if buf.len() <= max {
+        return buf;
+    }
+    //continuation bytes look like 0b10xxxxxx, and a UTF-8 sequence has at most three of them
+    let is_continuation = |b: u8| b & 0xC0 == 0x80;
+    let mut len = max;
+    while len > 0 && max - len < 3 && is_continuation(buf[len]) {
+        len -= 1;
+    }
+    if is_continuation(buf[len]) {
+        len = max;
+    }
+    &buf[0..len]
+}
+
+///An incremental UTF-8 decoder for byte streams that arrive in chunks.
+///
+///When decoding a stream of bytes (e.g. stdout received from a client) chunk by chunk, a single
+///UTF-8 sequence may be split across two chunks. This decoder holds back incomplete sequences at
+///the end of a chunk until the next chunk arrives. Invalid sequences are replaced with U+FFFD
+///REPLACEMENT CHARACTER, in the same way as `String::from_utf8_lossy()` does it.
+///
+///The decoder does not allocate, and is therefore usable in no_std environments. Decoded text is
+///delivered to a callback instead of being collected into a `String`.
+///
+///```
+///# use vt6::common::Utf8StreamDecoder;
+///let mut decoder = Utf8StreamDecoder::new();
+///let mut result = String::new();
+///let input = "Grüße".as_bytes();
+///decoder.decode(&input[0..3], |s| result.push_str(s));
+///assert_eq!(result, "Gr");
+///decoder.decode(&input[3..], |s| result.push_str(s));
+///assert_eq!(result, "Grüße");
+///```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Utf8StreamDecoder {
+    partial: [u8; 4],
+    partial_len: usize,
+}
+
+impl Utf8StreamDecoder {
+    ///Creates a new decoder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Returns whether an incomplete UTF-8 sequence is being held back until more input arrives.
+    pub fn has_partial(&self) -> bool {
+        self.partial_len > 0
+    }
+
+    ///Decodes the next chunk of input. The decoded text is passed to `sink`, which may be called
+    ///any number of times (including not at all). An incomplete UTF-8 sequence at the end of
+    ///`input` is held back until the next call.
+    pub fn decode<F: FnMut(&str)>(&mut self, input: &[u8], mut sink: F) {
+        let mut rest = input;
+
+        //if we have a partial sequence from the previous call, complete it first by adding bytes
+        //one at a time until it either becomes valid or invalid
+        if self.partial_len > 0 {
+            loop {
+                let (&byte, remainder) = match rest.split_first() {
+                    Some(pair) => pair,
+                    None => return, //still incomplete; wait for more input
+                };
+                self.partial[self.partial_len] = byte;
+                self.partial_len += 1;
+                match core::str::from_utf8(&self.partial[0..self.partial_len]) {
+                    Ok(s) => {
+                        sink(s);
+                        self.partial_len = 0;
+                        rest = remainder;
+                        break;
+                    }
+                    Err(e) => {
+                        if e.error_len().is_some() {
+                            //the partial sequence is invalid; the byte that we just added is not
+                            //part of it and will be decoded again below
+                            sink(REPLACEMENT_CHARACTER);
+                            self.partial_len = 0;
+                            break;
+                        }
+                        rest = remainder;
+                    }
+                }
+            }
+        }
+
+        loop {
+            match core::str::from_utf8(rest) {
+                Ok(s) => {
+                    if !s.is_empty() {
+                        sink(s);
+                    }
+                    return;
+                }
+                Err(e) => {
+                    let (valid, remainder) = rest.split_at(e.valid_up_to());
+                    if !valid.is_empty() {
+                        //SAFETY: from_utf8() has just verified this part
+                        sink(unsafe { core::str::from_utf8_unchecked(valid) });
+                    }
+                    match e.error_len() {
+                        Some(len) => {
+                            sink(REPLACEMENT_CHARACTER);
+                            rest = &remainder[len..];
+                        }
+                        None => {
+                            //incomplete sequence at the end of the input (at most 3 bytes)
+                            self.partial[0..remainder.len()].copy_from_slice(remainder);
+                            self.partial_len = remainder.len();
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    ///Signals the end of the stream. If an incomplete UTF-8 sequence is being held back, a
+    ///replacement character is passed to `sink` in its place.
+    pub fn finish<F: FnMut(&str)>(&mut self, mut sink: F) {
+        if self.partial_len > 0 {
+            sink(REPLACEMENT_CHARACTER);
+            self.partial_len = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_chunks(chunks: &[&[u8]]) -> String {
+        let mut decoder = Utf8StreamDecoder::new();
+        let mut result = String::new();
+        for chunk in chunks {
+            decoder.decode(chunk, |s| result.push_str(s));
+        }
+        decoder.finish(|s| result.push_str(s));
+        result
+    }
+
+    #[test]
+    fn test_decode_valid() {
+        let input = "a\u{E4}\u{20AC}\u{1F4A9}z".as_bytes();
+        assert_eq!(decode_chunks(&[input]), "a\u{E4}\u{20AC}\u{1F4A9}z");
+        //split the input at every possible position, including within sequences
+        for idx in 0..input.len() {
+            let (a, b) = input.split_at(idx);
+            assert_eq!(decode_chunks(&[a, b]), "a\u{E4}\u{20AC}\u{1F4A9}z");
+        }
+        //feed the input byte by byte
+        let bytes: Vec<&[u8]> = input.chunks(1).collect();
+        assert_eq!(decode_chunks(&bytes), "a\u{E4}\u{20AC}\u{1F4A9}z");
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        //the results must match what from_utf8_lossy() does, regardless of chunking
+        let inputs: &[&[u8]] = &[
+            b"a\xFFb",
+            b"a\xE2\x82b",
+            b"\xE0\x80\x80",
+            b"\xF0\x9F\x92",
+            b"\xC3\xC3\xA4",
+            b"\x80\x80",
+        ];
+        for input in inputs {
+            let expected = String::from_utf8_lossy(input);
+            assert_eq!(decode_chunks(&[input]), expected);
+            for idx in 0..input.len() {
+                let (a, b) = input.split_at(idx);
+                assert_eq!(decode_chunks(&[a, b]), expected, "input = {:?}", input);
+            }
+            let bytes: Vec<&[u8]> = input.chunks(1).collect();
+            assert_eq!(decode_chunks(&bytes), expected, "input = {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_truncate() {
+        let input = "a\u{E4}\u{20AC}\u{1F4A9}z";
+        //offsets of character boundaries: 0, 1, 3, 6, 10, 11
+        let expected = [0, 1, 1, 3, 3, 3, 6, 6, 6, 6, 10, 11, 11];
+        for (max, &len) in expected.iter().enumerate() {
+            assert_eq!(truncate_str_to_bytes(input, max), &input[0..len]);
+            assert_eq!(
+                truncate_utf8_bytes(input.as_bytes(), max),
+                &input.as_bytes()[0..len],
+            );
+        }
+
+        //invalid input: a run of continuation bytes is cut where it would be cut without the
+        //UTF-8 awareness, but a sequence start within reach is still respected
+        assert_eq!(
+            truncate_utf8_bytes(b"ab\x80\x80\x80\x80\x80", 6),
+            b"ab\x80\x80\x80\x80"
+        );
+        assert_eq!(truncate_utf8_bytes(b"\x80\x80", 1), b"\x80");
+        assert_eq!(truncate_utf8_bytes(b"a\xE2\x82", 2), b"a");
+        assert_eq!(truncate_utf8_bytes(b"", 0), b"");
+    }
+
+    #[test]
+    fn test_decode_holds_back_partial() {
+        let mut decoder = Utf8StreamDecoder::new();
+        let mut result = String::new();
+        decoder.decode(b"ab\xE2\x82", |s| result.push_str(s));
+        assert_eq!(result, "ab");
+        assert!(decoder.has_partial());
+        decoder.decode(b"\xAC", |s| result.push_str(s));
+        assert_eq!(result, "ab\u{20AC}");
+        assert!(!decoder.has_partial());
+    }
+}
@@ -0,0 +1,369 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::ClientID;
+use crate::server::testing::Conversation;
+use crate::server::{self, Application};
+use core::fmt;
+
+///A suite of checks derived from the VT6 specifications, which validates that an
+///[Application](../server/trait.Application.html) and its handlers behave like a conforming
+///server.
+///
+///The checks run in memory, through a [Conversation](../server/testing/struct.Conversation.html)
+///for each check, so they can easily run as part of the test suite of a terminal implementation.
+///For each check, the suite registers a client through
+///[`Application::register_client()`](../server/trait.Application.html#tymethod.register_client),
+///and performs a `posix1.client-hello` handshake with it. The clients are unregistered again
+///when the suite is done.
+///
+///```
+///use vt6::conformance::Suite;
+///use vt6::server::testing::MockApplication;
+///
+///let app: MockApplication = MockApplication::new();
+///let report = Suite::new(app).run();
+///println!("{}", report);
+///assert!(report.is_success());
+///```
+pub struct Suite<A: Application> {
+    app: A,
+    client_id: String,
+}
+
+type Check<A> = fn(&Suite<A>, ClientID<'_>) -> Result<(), String>;
+
+impl<A: Application> Suite<A> {
+    ///Creates a suite that runs against the given application.
+    pub fn new(app: A) -> Self {
+        Self {
+            app,
+            client_id: "conformance".into(),
+        }
+    }
+
+    ///Sets the client ID below which the clients for the individual checks are registered. The
+    ///default is `conformance`, so the clients are called `conformance1`, `conformance2` and so
+    ///on. This needs to be changed if the application reserves this ID for something else.
+    pub fn with_client_id(mut self, id: ClientID<'_>) -> Self {
+        self.client_id = id.as_str().into();
+        self
+    }
+
+    ///Runs all checks and returns the results.
+    pub fn run(&self) -> Report {
+        let checks: [(&'static str, Check<A>); 8] = [
+            ("handshake", Self::check_handshake),
+            (
+                "handshake-invalid-secret",
+                Self::check_handshake_invalid_secret,
+            ),
+            ("want-core", Self::check_want_core),
+            ("want-unknown-module", Self::check_want_unknown_module),
+            ("want-consistency", Self::check_want_consistency),
+            (
+                "nope-on-invalid-message",
+                Self::check_nope_on_invalid_message,
+            ),
+            ("resync-after-garbage", Self::check_resync_after_garbage),
+            ("incomplete-message", Self::check_incomplete_message),
+        ];
+        let results = checks
+            .iter()
+            .enumerate()
+            .map(|(idx, (name, check))| {
+                let client_id = format!("{}{}", self.client_id, idx + 1);
+                //cannot fail: appending digits to a valid client ID yields a valid client ID
+                let client_id = ClientID::parse(&client_id).unwrap();
+                CheckResult {
+                    name,
+                    failure: check(self, client_id).err(),
+                }
+            })
+            .collect();
+
+        let client_id = ClientID::parse(&self.client_id).unwrap();
+        self.app
+            .unregister_clients(server::ClientSelector::AtOrBelow(client_id));
+        Report { results }
+    }
+
+    //Starts a conversation and performs the client handshake, so that the connection is in msgio
+    //state afterwards.
+    fn connect(&self, client_id: ClientID<'_>) -> Result<Conversation<A>, String> {
+        let creds = self
+            .app
+            .register_client(server::ClientIdentity::new(&client_id));
+        let mut conv = Conversation::new(self.app.clone());
+        conv.send_message(&crate::msg::posix::ClientHello {
+            secret: creds.secret(),
+        });
+        let replies = conv.replies();
+        let expected = format!("(posix1.server-hello {} ", client_id);
+        match replies.first() {
+            Some(reply) if reply.starts_with(&expected) => {}
+            _ => {
+                return Err(format!(
+                "expected posix1.server-hello for client {} after posix1.client-hello, got {:?}",
+                client_id, replies
+            ))
+            }
+        }
+        if replies.len() > 1 {
+            return Err(format!("unexpected replies after handshake: {:?}", replies));
+        }
+        match conv.connection().state().type_name() {
+            "Msgio" => Ok(conv),
+            other => Err(format!(
+                "expected connection in state Msgio after handshake, got {}",
+                other
+            )),
+        }
+    }
+
+    fn check_handshake(&self, client_id: ClientID<'_>) -> Result<(), String> {
+        self.connect(client_id).map(|_| ())
+    }
+
+    fn check_handshake_invalid_secret(&self, _client_id: ClientID<'_>) -> Result<(), String> {
+        let mut conv = Conversation::new(self.app.clone());
+        conv.send(b"{2|19:posix1.client-hello,22:not-a-valid-secret-123,}");
+        let replies = conv.replies();
+        if replies
+            .iter()
+            .any(|r| r.starts_with("(posix1.server-hello "))
+        {
+            return Err(format!(
+                "expected handshake with invalid secret to be refused, got {:?}",
+                replies
+            ));
+        }
+        match conv.connection().state().type_name() {
+            "Msgio" => Err("connection went into state Msgio with an invalid secret".into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_want_core(&self, client_id: ClientID<'_>) -> Result<(), String> {
+        let mut conv = self.connect(client_id)?;
+        let reply = single_reply(conv.send(b"{2|4:want,5:core1,}"), "(want core1)")?;
+        if !reply.starts_with("(have core1.") {
+            return Err(format!(
+                "expected (have core1.N) in reply to (want core1), got {}",
+                reply
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_want_unknown_module(&self, client_id: ClientID<'_>) -> Result<(), String> {
+        let mut conv = self.connect(client_id)?;
+        let reply = single_reply(
+            conv.send(b"{2|4:want,17:conformance-test1,}"),
+            "(want conformance-test1)",
+        )?;
+        expect_reply(&reply, "(have conformance-test1)")
+    }
+
+    fn check_want_consistency(&self, client_id: ClientID<'_>) -> Result<(), String> {
+        let mut conv = self.connect(client_id)?;
+        for module in &["core1", "posix1", "conformance-test1"] {
+            let want = format!("{{2|4:want,{}:{},}}", module.len(), module);
+            let context = format!("(want {})", module);
+            let first = single_reply(conv.send(want.as_bytes()), &context)?;
+            let second = single_reply(conv.send(want.as_bytes()), &context)?;
+            if first != second {
+                return Err(format!(
+                    "repeated {} got inconsistent replies: {} and {}",
+                    context, first, second
+                ));
+            }
+            //the reply must concern the requested module, with or without a minor version
+            let accepted = format!("(have {}.", module);
+            let refused = format!("(have {})", module);
+            if !first.starts_with(&accepted) && first != refused {
+                return Err(format!("unexpected reply to {}: {}", context, first));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_nope_on_invalid_message(&self, client_id: ClientID<'_>) -> Result<(), String> {
+        let mut conv = self.connect(client_id)?;
+        //eternal message with missing argument
+        let reply = single_reply(conv.send(b"{1|4:want,}"), "(want)")?;
+        expect_reply(&reply, "(nope want)")?;
+        //eternal message that clients must not send
+        let reply = single_reply(conv.send(b"{1|4:nope,}"), "(nope)")?;
+        expect_reply(&reply, "(nope nope)")?;
+        //core message with missing arguments
+        single_reply(conv.send(b"{2|4:want,5:core1,}"), "(want core1)")?;
+        let reply = single_reply(conv.send(b"{1|9:core1.set,}"), "(core1.set)")?;
+        expect_reply(&reply, "(nope core1.set)")
+    }
+
+    fn check_resync_after_garbage(&self, client_id: ClientID<'_>) -> Result<(), String> {
+        let mut conv = self.connect(client_id)?;
+        let expected = single_reply(conv.send(b"{2|4:want,5:core1,}"), "(want core1)")?;
+        //[vt6/foundation, sect. 3.3]: after a parse error, the next message starts at the next `{`
+        let inputs: [&[u8]; 3] = [
+            b"garbage{2|4:want,5:core1,}",
+            b"{9|broken{2|4:want,5:core1,}",
+            b"{2|4:want,5:core1,}}}{2|4:want,5:core1,}",
+        ];
+        for input in &inputs {
+            let replies = conv.send(input).replies();
+            if replies.iter().any(|r| *r != expected) || replies.is_empty() {
+                return Err(format!(
+                    "expected {} after {:?}, got {:?}",
+                    expected,
+                    String::from_utf8_lossy(input),
+                    replies
+                ));
+            }
+        }
+        match conv.connection().state().type_name() {
+            "Msgio" => Ok(()),
+            other => Err(format!(
+                "expected connection to stay in state Msgio after invalid input, got {}",
+                other
+            )),
+        }
+    }
+
+    fn check_incomplete_message(&self, client_id: ClientID<'_>) -> Result<(), String> {
+        let mut conv = self.connect(client_id)?;
+        let replies = conv.send(b"{2|4:want,5:co").replies();
+        if !replies.is_empty() {
+            return Err(format!(
+                "expected no reply to an incomplete message, got {:?}",
+                replies
+            ));
+        }
+        let reply = single_reply(conv.send(b"re1,}"), "(want core1) in two parts")?;
+        if !reply.starts_with("(have core1.") {
+            return Err(format!(
+                "expected (have core1.N) after completing (want core1), got {}",
+                reply
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn single_reply<A: Application>(
+    conv: &mut Conversation<A>,
+    context: &str,
+) -> Result<String, String> {
+    let mut replies = conv.replies();
+    if replies.len() != 1 {
+        return Err(format!(
+            "expected exactly one reply to {}, got {:?}",
+            context, replies
+        ));
+    }
+    Ok(replies.remove(0))
+}
+
+fn expect_reply(actual: &str, expected: &str) -> Result<(), String> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!("expected {}, got {}", expected, actual))
+    }
+}
+
+///The results of running a conformance [Suite](struct.Suite.html).
+///
+///The Display impl renders the report in the
+///[Test Anything Protocol](https://testanything.org/), which many CI systems can consume.
+#[derive(Clone, Debug)]
+pub struct Report {
+    results: Vec<CheckResult>,
+}
+
+///The result of a single check within a [Report](struct.Report.html).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckResult {
+    ///A short name for the check, e.g. `want-core`.
+    pub name: &'static str,
+    ///If the check failed, a description of what went wrong.
+    pub failure: Option<String>,
+}
+
+impl Report {
+    ///Returns the results of all checks, in the order in which they ran.
+    pub fn results(&self) -> &[CheckResult] {
+        &self.results
+    }
+
+    ///Returns the results of all checks that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &CheckResult> {
+        self.results.iter().filter(|r| r.failure.is_some())
+    }
+
+    ///Returns whether all checks passed.
+    pub fn is_success(&self) -> bool {
+        self.failures().next().is_none()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "1..{}", self.results.len())?;
+        for (idx, result) in self.results.iter().enumerate() {
+            match result.failure {
+                None => writeln!(f, "ok {} - {}", idx + 1, result.name)?,
+                Some(ref msg) => writeln!(f, "not ok {} - {}: {}", idx + 1, result.name, msg)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::{MockApplication, MockHandlers};
+
+    struct RejectAll;
+
+    impl MockHandlers for RejectAll {
+        type MessageHandler = server::RejectHandler;
+    }
+
+    #[test]
+    fn test_conformance_suite() {
+        let app: MockApplication = MockApplication::new();
+        let report = Suite::new(app.clone()).run();
+        assert!(report.is_success(), "{}", report);
+        assert_eq!(report.results().len(), 8);
+        assert!(report.to_string().starts_with("1..8\nok 1 - handshake\n"));
+        //the clients for the checks are cleaned up afterwards
+        assert!(app.registered_clients().is_empty());
+
+        //without the core handler, nothing beyond the handshake works
+        let app: MockApplication<RejectAll> = MockApplication::new();
+        let report = Suite::new(app)
+            .with_client_id(ClientID::parse("test").unwrap())
+            .run();
+        assert!(!report.is_success());
+        let failed: Vec<_> = report.failures().map(|r| r.name).collect();
+        assert_eq!(
+            failed,
+            vec![
+                "want-core",
+                "want-unknown-module",
+                "want-consistency",
+                "nope-on-invalid-message",
+                "incomplete-message",
+            ]
+        );
+        assert!(report.to_string().contains(
+            "not ok 3 - want-core: expected (have core1.N) in reply to (want core1), got (nope want)\n"
+        ));
+    }
+}
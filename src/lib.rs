@@ -40,14 +40,64 @@ here are some links for you to jump off from:
 * for `std::io`: <https://github.com/rust-lang/rfcs/issues/2262>
 * for Tokio: <https://github.com/tokio-rs/mio/issues/21>
 
+## Protocol modules
+
+Support for protocol modules beyond vt6/foundation and vt6/core is behind cargo
+features, all of which (except for `module_deflate`) are enabled by default:
+
+* `module_clipboard` for the `clip1` module, an extension provided by this
+  crate that gives clients access to the clipboard (see
+  [vt6::msg::clipboard](msg/clipboard/index.html))
+* `module_deflate` for the `deflate1` module, an extension provided by this
+  crate that allows large arguments to be sent in compressed form (see
+  [vt6::common::CompressedBytes](common/struct.CompressedBytes.html))
+* `module_frame` for the `frame1` module, an extension provided by this crate
+  that adds explicit framing to msgio connections (see
+  [vt6::msg::frame](msg/frame/index.html))
+* `module_input` for the `input1` module, an extension provided by this crate
+  that delivers user input to clients as key, mouse and paste events (see
+  [vt6::msg::input](msg/input/index.html))
+* `module_job` for the `job1` module, an extension provided by this crate that
+  lets shells implement job control over VT6 (see
+  [vt6::server::job](server/job/index.html))
+* `module_posix` for [vt6/posix](https://vt6.io/std/posix/) (required by
+  `vt6::server` and the client connection types, since the handshakes are
+  defined there)
+* `module_sig` for [vt6/sig](https://vt6.io/std/sig/)
+* `module_term` for [vt6/term](https://vt6.io/std/term/)
+
+Clients that only need some of these modules can disable the default features
+and enable just the ones they need, to reduce compile times and binary size.
+
+## Platform support
+
+The client connection types and `vt6::server::tokio` use Unix domain sockets,
+and are therefore only available on Unix. On Windows, only the server side is
+supported: the `use_windows_pipes` feature makes `vt6::server::tokio::Dispatch`
+listen on named pipes instead. This crate does not have a client connection type
+for named pipes. [vt6::client::Environment](client/struct.Environment.html)
+finds the server through environment variables on Windows, since there is no
+file descriptor 60 to inherit, but clients need to connect to the named pipe and
+speak the protocol on their own.
+
 */
 
 ///Implementation parts for VT6 clients.
 pub mod client;
 ///Common types and definitions that can be used both by VT6 servers and clients.
 pub mod common;
+#[cfg(all(feature = "use_std", feature = "module_posix"))]
+///A suite of checks for validating that a server implementation conforms to the VT6 specifications.
+pub mod conformance;
 ///Decoded representations of common VT6 messages.
 pub mod msg;
-#[cfg(feature = "use_std")]
+#[cfg(all(feature = "use_std", feature = "module_posix"))]
 ///Implementation parts for VT6 servers (terminals or shell wrappers proxying as a terminal).
 pub mod server;
+#[cfg(all(
+    any(test, feature = "testvectors"),
+    feature = "module_posix",
+    feature = "module_sig"
+))]
+///Canonical wire encodings of VT6 messages for checking interoperability.
+pub mod testvectors;
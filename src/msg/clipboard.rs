@@ -0,0 +1,129 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg;
+
+const SET: &str = "clip1.set";
+const GET: &str = "clip1.get";
+const CONTENT: &str = "clip1.content";
+
+///The maximum length of the text in a `clip1.set` or `clip1.content` message, in bytes. This
+///leaves enough room for the message type and the selection within the maximum message length of
+///1024 bytes.
+pub const MAX_TEXT_BYTES: usize = 960;
+
+crate::vt6_enum_argument! {
+    ///The selections that can be accessed with `clip1` messages.
+    ///
+    ///These correspond to the selections that OSC 52 can address on X11-like systems. On systems
+    ///that only have a single clipboard, the application may treat both selections as the same.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum Selection {
+        ///The regular clipboard, as used by explicit "copy" and "paste" actions.
+        Clipboard = "clipboard",
+        ///The primary selection, i.e. the text that is currently selected.
+        Primary = "primary",
+    }
+}
+
+///A `clip1.set` message.
+///
+///The `clip1` module is an extension provided by this crate; it is not part of the VT6 standard.
+///It gives clients structured access to the clipboard of the terminal's host system, like OSC 52
+///does for legacy terminals. The client checks for support with `(want clip1)`.
+///
+///This message is sent by a client to replace the contents of the given selection. The server
+///acknowledges by sending the same message back, or refuses with `nope`, e.g. because the client
+///is not allowed to write to the clipboard.
+///
+///```
+///# use vt6::common::core::msg::EncodeMessage;
+///# use vt6::msg::clipboard::{Selection, Set};
+///let msg = Set { selection: Selection::Clipboard, text: "hello" };
+///let mut buf = [0u8; 64];
+///let len = msg.encode(&mut buf).unwrap();
+///assert_eq!(&buf[0..len], b"{3|9:clip1.set,9:clipboard,5:hello,}");
+///```
+#[derive(Clone, Debug)]
+pub struct Set<'a> {
+    pub selection: Selection,
+    pub text: &'a str,
+}
+
+impl<'a> msg::DecodeMessage<'a> for Set<'a> {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != SET {
+            return None;
+        }
+        let (selection, text) = msg.arguments().exactly2()?;
+        Some(Set { selection, text })
+    }
+}
+
+impl<'a> msg::EncodeMessage for Set<'a> {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, SET, 2);
+        f.add_argument(&self.selection);
+        f.add_argument(self.text);
+        f.finalize()
+    }
+}
+
+///A `clip1.get` message.
+///
+///Sent by a client to query the contents of the given selection. The server answers with
+///`clip1.content`, or with `nope` if the client is not allowed to read from the clipboard or if the
+///contents are not available as text of at most [MAX_TEXT_BYTES](constant.MAX_TEXT_BYTES.html).
+#[derive(Clone, Debug)]
+pub struct Get {
+    pub selection: Selection,
+}
+
+impl<'a> msg::DecodeMessage<'a> for Get {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != GET {
+            return None;
+        }
+        let selection = msg.arguments().exactly1()?;
+        Some(Get { selection })
+    }
+}
+
+impl msg::EncodeMessage for Get {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, GET, 1);
+        f.add_argument(&self.selection);
+        f.finalize()
+    }
+}
+
+///A `clip1.content` message.
+///
+///Sent by the server in reply to `clip1.get`.
+#[derive(Clone, Debug)]
+pub struct Content<'a> {
+    pub selection: Selection,
+    pub text: &'a str,
+}
+
+impl<'a> msg::DecodeMessage<'a> for Content<'a> {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != CONTENT {
+            return None;
+        }
+        let (selection, text) = msg.arguments().exactly2()?;
+        Some(Content { selection, text })
+    }
+}
+
+impl<'a> msg::EncodeMessage for Content<'a> {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, CONTENT, 2);
+        f.add_argument(&self.selection);
+        f.add_argument(self.text);
+        f.finalize()
+    }
+}
@@ -4,16 +4,17 @@
 * Refer to the file "LICENSE" for details.
 *******************************************************************************/
 
-use crate::common::core::{msg, ClientID};
+use crate::common::core::{msg, ClientID, ScopedIdentifier, ScreenID};
+use core::fmt;
 
 ///A `core1.client-make` message.
 ///[\[vt6/core1, sect. X.Y\]](https://vt6.io/std/core1/#section-X-Y)
 #[derive(Clone, Debug)]
 pub struct ClientMake<'a> {
     pub client_id: ClientID<'a>,
-    pub stdin_screen_id: Option<&'a str>,
-    pub stdout_screen_id: Option<&'a str>,
-    pub stderr_screen_id: Option<&'a str>,
+    pub stdin_screen_id: Option<ScreenID<'a>>,
+    pub stdout_screen_id: Option<ScreenID<'a>>,
+    pub stderr_screen_id: Option<ScreenID<'a>>,
 }
 
 impl<'a> msg::DecodeMessage<'a> for ClientMake<'a> {
@@ -36,20 +37,29 @@ impl<'a> msg::EncodeMessage for ClientMake<'a> {
     fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
         let mut f = msg::MessageFormatter::new(buf, "core1.client-make", 4);
         f.add_argument(&self.client_id);
-        f.add_argument(&self.stdin_screen_id);
-        f.add_argument(&self.stdout_screen_id);
-        f.add_argument(&self.stderr_screen_id);
+        f.add_argument(&self.stdin_screen_id.as_ref());
+        f.add_argument(&self.stdout_screen_id.as_ref());
+        f.add_argument(&self.stderr_screen_id.as_ref());
         f.finalize()
     }
 }
 
 ///A `core1.client-new` message.
 ///[\[vt6/core1, sect. X.Y\]](https://vt6.io/std/core1/#section-X-Y)
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct ClientNew<'a> {
     pub secret: &'a str,
 }
 
+//Debug is implemented manually to keep the secret out of logs
+impl<'a> fmt::Debug for ClientNew<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientNew")
+            .field("secret", &format_args!("<redacted>"))
+            .finish()
+    }
+}
+
 impl<'a> msg::DecodeMessage<'a> for ClientNew<'a> {
     fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
         if msg.parsed_type().as_str() != "core1.client-new" {
@@ -92,3 +102,85 @@ impl<'a> msg::EncodeMessage for ClientEnd<'a> {
         f.finalize()
     }
 }
+
+///A `core1.sub` message.
+///[\[vt6/core1, sect. X.Y\]](https://vt6.io/std/core1/#section-X-Y)
+#[derive(Clone, Debug)]
+pub struct Sub<'a> {
+    pub name: ScopedIdentifier<'a>,
+}
+
+impl<'a> msg::DecodeMessage<'a> for Sub<'a> {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != "core1.sub" {
+            return None;
+        }
+        let name = msg.arguments().exactly1()?;
+        Some(Sub { name })
+    }
+}
+
+impl<'a> msg::EncodeMessage for Sub<'a> {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, "core1.sub", 1);
+        f.add_argument(&self.name);
+        f.finalize()
+    }
+}
+
+///A `core1.set` message.
+///[\[vt6/core1, sect. X.Y\]](https://vt6.io/std/core1/#section-X-Y)
+#[derive(Clone, Debug)]
+pub struct Set<'a> {
+    pub name: ScopedIdentifier<'a>,
+    pub requested_value: &'a [u8],
+}
+
+impl<'a> msg::DecodeMessage<'a> for Set<'a> {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != "core1.set" {
+            return None;
+        }
+        let (name, requested_value) = msg.arguments().exactly2()?;
+        Some(Set {
+            name,
+            requested_value,
+        })
+    }
+}
+
+impl<'a> msg::EncodeMessage for Set<'a> {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, "core1.set", 2);
+        f.add_argument(&self.name);
+        f.add_argument(self.requested_value);
+        f.finalize()
+    }
+}
+
+///A `core1.pub` message.
+///[\[vt6/core1, sect. X.Y\]](https://vt6.io/std/core1/#section-X-Y)
+#[derive(Clone, Debug)]
+pub struct Pub<'a> {
+    pub name: ScopedIdentifier<'a>,
+    pub value: &'a [u8],
+}
+
+impl<'a> msg::DecodeMessage<'a> for Pub<'a> {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != "core1.pub" {
+            return None;
+        }
+        let (name, value) = msg.arguments().exactly2()?;
+        Some(Pub { name, value })
+    }
+}
+
+impl<'a> msg::EncodeMessage for Pub<'a> {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, "core1.pub", 2);
+        f.add_argument(&self.name);
+        f.add_argument(self.value);
+        f.finalize()
+    }
+}
@@ -0,0 +1,43 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg;
+
+const ENABLE: &str = "frame1.enable";
+
+///A `frame1.enable` message.
+///
+///The `frame1` module is an extension provided by this crate; it is not part of the VT6
+///standard. It allows a client to switch its msgio connection into framed mode, where each
+///message is preceded by its length (see [vt6::common::decode_frame()](../../common/fn.decode_frame.html)
+///for the exact format). This makes message boundaries explicit on stream sockets, so that
+///neither side has to rely on resynchronizing the message parser after invalid input.
+///
+///The client checks for support with `(want frame1)` and then sends this message. The server
+///acknowledges by sending the same message back. Everything that the client sends after this
+///message, and everything that the server sends after the acknowledgement, is framed. If the
+///server does not support framing, it answers with `nope` instead, and the connection stays
+///unframed.
+#[derive(Clone, Debug)]
+pub struct Enable;
+
+impl<'a> msg::DecodeMessage<'a> for Enable {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != ENABLE {
+            return None;
+        }
+        if msg.arguments().len() != 0 {
+            return None;
+        }
+        Some(Enable)
+    }
+}
+
+impl msg::EncodeMessage for Enable {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        msg::MessageFormatter::new(buf, ENABLE, 0).finalize()
+    }
+}
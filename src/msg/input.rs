@@ -0,0 +1,420 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::{msg, DecodeArgument, EncodeArgument, EncodedArgument};
+
+const KEY: &str = "input1.key";
+const MOUSE: &str = "input1.mouse";
+const PASTE: &str = "input1.paste";
+
+///A key on the keyboard, as reported in `input1.key` messages.
+///
+///In message arguments, keys that produce text are represented by that text (which must be a
+///single character), and all other keys are represented by their lowercase name, e.g. `enter`,
+///`page-up` or `f5`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    ///A key that produces text. When Shift is held down, this is the shifted character, e.g.
+    ///`'A'` instead of `'a'`.
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    ///A function key. Valid values are 1 through 24.
+    F(u8),
+}
+
+impl Key {
+    fn name(self) -> Option<&'static str> {
+        Some(match self {
+            Key::Char(_) | Key::F(_) => return None,
+            Key::Enter => "enter",
+            Key::Tab => "tab",
+            Key::Backspace => "backspace",
+            Key::Escape => "escape",
+            Key::Up => "up",
+            Key::Down => "down",
+            Key::Left => "left",
+            Key::Right => "right",
+            Key::Home => "home",
+            Key::End => "end",
+            Key::PageUp => "page-up",
+            Key::PageDown => "page-down",
+            Key::Insert => "insert",
+            Key::Delete => "delete",
+        })
+    }
+}
+
+impl EncodeArgument for Key {
+    fn get_size(&self) -> usize {
+        match *self {
+            Key::Char(c) => c.len_utf8(),
+            Key::F(n) => 1 + n.get_size(),
+            key => key.name().unwrap().len(),
+        }
+    }
+    fn encode(&self, buf: &mut [u8]) {
+        match *self {
+            Key::Char(c) => {
+                c.encode_utf8(buf);
+            }
+            Key::F(n) => {
+                buf[0] = b'f';
+                n.encode(&mut buf[1..]);
+            }
+            key => buf.copy_from_slice(key.name().unwrap().as_bytes()),
+        }
+    }
+}
+
+impl<'a> DecodeArgument<'a> for Key {
+    fn decode_argument(arg: &'a [u8]) -> Option<Self> {
+        let s = core::str::from_utf8(arg).ok()?;
+        let mut chars = s.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Some(Key::Char(c));
+        }
+        Some(match s {
+            "enter" => Key::Enter,
+            "tab" => Key::Tab,
+            "backspace" => Key::Backspace,
+            "escape" => Key::Escape,
+            "up" => Key::Up,
+            "down" => Key::Down,
+            "left" => Key::Left,
+            "right" => Key::Right,
+            "home" => Key::Home,
+            "end" => Key::End,
+            "page-up" => Key::PageUp,
+            "page-down" => Key::PageDown,
+            "insert" => Key::Insert,
+            "delete" => Key::Delete,
+            _ => {
+                let n = u8::decode_argument(s.strip_prefix('f')?.as_bytes())?;
+                if !(1..=24).contains(&n) {
+                    return None;
+                }
+                Key::F(n)
+            }
+        })
+    }
+}
+
+///The modifier keys that were held down during a key press or mouse event.
+///
+///In message arguments, this is represented by the letters `s` (Shift), `a` (Alt) and `c`
+///(Control), in this order. When no modifiers are held down, the argument is empty.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl Modifiers {
+    fn letters(self) -> impl Iterator<Item = u8> {
+        let flags = [(self.shift, b's'), (self.alt, b'a'), (self.ctrl, b'c')];
+        IntoIterator::into_iter(flags)
+            .filter(|(is_set, _)| *is_set)
+            .map(|(_, letter)| letter)
+    }
+}
+
+impl EncodeArgument for Modifiers {
+    fn get_size(&self) -> usize {
+        self.letters().count()
+    }
+    fn encode(&self, buf: &mut [u8]) {
+        for (dst, letter) in buf.iter_mut().zip(self.letters()) {
+            *dst = letter;
+        }
+    }
+}
+
+impl<'a> DecodeArgument<'a> for Modifiers {
+    fn decode_argument(arg: &'a [u8]) -> Option<Self> {
+        let mut result = Modifiers::default();
+        let mut rest = arg;
+        for (letter, flag) in [
+            (b's', &mut result.shift),
+            (b'a', &mut result.alt),
+            (b'c', &mut result.ctrl),
+        ] {
+            if let Some((&first, tail)) = rest.split_first() {
+                if first == letter {
+                    *flag = true;
+                    rest = tail;
+                }
+            }
+        }
+        if rest.is_empty() {
+            Some(result)
+        } else {
+            None
+        }
+    }
+}
+
+///The mouse buttons that can be reported in `input1.mouse` messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+}
+
+///What happened in a mouse event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MouseEventKind {
+    Press(MouseButton),
+    Release(MouseButton),
+    ///The mouse was moved (with or without buttons held down).
+    Move,
+    ScrollUp,
+    ScrollDown,
+}
+
+impl MouseEventKind {
+    ///Returns the name of this kind of event, as used in the arguments of `input1.mouse`
+    ///messages.
+    pub fn as_str(self) -> &'static str {
+        use MouseButton::*;
+        use MouseEventKind::*;
+        match self {
+            Press(Left) => "press-left",
+            Press(Middle) => "press-middle",
+            Press(Right) => "press-right",
+            Release(Left) => "release-left",
+            Release(Middle) => "release-middle",
+            Release(Right) => "release-right",
+            Move => "move",
+            ScrollUp => "scroll-up",
+            ScrollDown => "scroll-down",
+        }
+    }
+}
+
+impl EncodedArgument for MouseEventKind {
+    fn encoded(&self) -> &[u8] {
+        self.as_str().as_bytes()
+    }
+}
+
+impl<'a> DecodeArgument<'a> for MouseEventKind {
+    fn decode_argument(arg: &'a [u8]) -> Option<Self> {
+        use MouseButton::*;
+        use MouseEventKind::*;
+        Some(match arg {
+            b"press-left" => Press(Left),
+            b"press-middle" => Press(Middle),
+            b"press-right" => Press(Right),
+            b"release-left" => Release(Left),
+            b"release-middle" => Release(Middle),
+            b"release-right" => Release(Right),
+            b"move" => Move,
+            b"scroll-up" => ScrollUp,
+            b"scroll-down" => ScrollDown,
+            _ => return None,
+        })
+    }
+}
+
+///An `input1.key` message.
+///
+///The `input1` module is an extension provided by this crate; it is not part of the VT6 standard.
+///It allows the server to deliver user input as events on the client's msgio connection instead of
+///as bytes on its stdin, so that key presses can be told apart from typed text, and so that
+///modifiers and mouse events are not lost. The client opts in with `(want input1)`.
+///
+///This message is sent by the server when a key was pressed.
+///
+///```
+///# use vt6::common::core::msg::EncodeMessage;
+///# use vt6::msg::input::{Key, KeyPress, Modifiers};
+///let msg = KeyPress {
+///    key: Key::Char('c'),
+///    modifiers: Modifiers { ctrl: true, ..Modifiers::default() },
+///};
+///let mut buf = [0u8; 64];
+///let len = msg.encode(&mut buf).unwrap();
+///assert_eq!(&buf[0..len], b"{3|10:input1.key,1:c,1:c,}");
+///```
+#[derive(Clone, Debug)]
+pub struct KeyPress {
+    pub key: Key,
+    pub modifiers: Modifiers,
+}
+
+impl<'a> msg::DecodeMessage<'a> for KeyPress {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != KEY {
+            return None;
+        }
+        let (key, modifiers) = msg.arguments().exactly2()?;
+        Some(KeyPress { key, modifiers })
+    }
+}
+
+impl msg::EncodeMessage for KeyPress {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, KEY, 2);
+        f.add_argument(&self.key);
+        f.add_argument(&self.modifiers);
+        f.finalize()
+    }
+}
+
+///An `input1.mouse` message.
+///
+///Sent by the server when the mouse was used on the screen. The position is given as zero-based
+///column and row of the cell below the mouse cursor.
+#[derive(Clone, Debug)]
+pub struct MouseEvent {
+    pub kind: MouseEventKind,
+    pub x: u16,
+    pub y: u16,
+    pub modifiers: Modifiers,
+}
+
+impl<'a> msg::DecodeMessage<'a> for MouseEvent {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != MOUSE {
+            return None;
+        }
+        let (kind, x, y, modifiers) = msg.arguments().exactly4()?;
+        Some(MouseEvent {
+            kind,
+            x,
+            y,
+            modifiers,
+        })
+    }
+}
+
+impl msg::EncodeMessage for MouseEvent {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, MOUSE, 4);
+        f.add_argument(&self.kind);
+        f.add_argument(&self.x);
+        f.add_argument(&self.y);
+        f.add_argument(&self.modifiers);
+        f.finalize()
+    }
+}
+
+///The maximum length of the text in a single `input1.paste` message, in bytes. This leaves enough
+///room for the message type and the other arguments within the maximum message length of 1024
+///bytes.
+pub const PASTE_MAX_CHUNK_BYTES: usize = 960;
+
+///An `input1.paste` message.
+///
+///Sent by the server when the user pasted text into the screen. Since pasted text can be much
+///longer than a single message, it is split into chunks (see [paste_chunks()](fn.paste_chunks.html)),
+///with one message per chunk. Like [vt6::common::Chunk](../../common/struct.Chunk.html), each
+///message carries a sequence number starting at 0 and a flag that is true for the last chunk of
+///the paste. Unlike there, chunks are only split on character boundaries, so each chunk is valid
+///UTF-8 on its own.
+///
+///Clients can put the chunks back together with
+///[vt6::client::PasteReassembler](../../client/struct.PasteReassembler.html).
+///
+///```
+///# use vt6::common::core::msg::EncodeMessage;
+///# use vt6::msg::input::Paste;
+///let msg = Paste { sequence: 0, is_final: true, text: "hello" };
+///let mut buf = [0u8; 64];
+///let len = msg.encode(&mut buf).unwrap();
+///assert_eq!(&buf[0..len], b"{4|12:input1.paste,1:0,1:t,5:hello,}");
+///```
+#[derive(Clone, Debug)]
+pub struct Paste<'a> {
+    pub sequence: u32,
+    pub is_final: bool,
+    pub text: &'a str,
+}
+
+impl<'a> msg::DecodeMessage<'a> for Paste<'a> {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != PASTE {
+            return None;
+        }
+        let (sequence, is_final, text) = msg.arguments().exactly3()?;
+        Some(Paste {
+            sequence,
+            is_final,
+            text,
+        })
+    }
+}
+
+impl<'a> msg::EncodeMessage for Paste<'a> {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, PASTE, 3);
+        f.add_argument(&self.sequence);
+        f.add_argument(&self.is_final);
+        f.add_argument(self.text);
+        f.finalize()
+    }
+}
+
+///Splits pasted text into `input1.paste` messages whose text is at most
+///[PASTE_MAX_CHUNK_BYTES](constant.PASTE_MAX_CHUNK_BYTES.html) long. Chunks are only split on
+///character boundaries. An empty text yields a single empty chunk.
+///
+///```
+///# use vt6::msg::input::paste_chunks;
+///let text = "\u{E4}".repeat(1000);
+///let chunks: Vec<_> = paste_chunks(&text).collect();
+///assert_eq!(chunks.len(), 3);
+///assert_eq!(chunks[0].text.len(), 960);
+///assert!(chunks[2].is_final);
+///assert_eq!(chunks.iter().map(|c| c.text).collect::<String>(), text);
+///```
+pub fn paste_chunks(text: &str) -> PasteChunks<'_> {
+    PasteChunks {
+        rest: Some(text),
+        sequence: 0,
+    }
+}
+
+///Iterator returned by [paste_chunks()](fn.paste_chunks.html).
+#[derive(Clone, Debug)]
+pub struct PasteChunks<'a> {
+    rest: Option<&'a str>,
+    sequence: u32,
+}
+
+impl<'a> Iterator for PasteChunks<'a> {
+    type Item = Paste<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest?;
+        let is_final = rest.len() <= PASTE_MAX_CHUNK_BYTES;
+        let text = crate::common::truncate_str_to_bytes(rest, PASTE_MAX_CHUNK_BYTES);
+        let rest = &rest[text.len()..];
+        self.rest = if is_final { None } else { Some(rest) };
+        let chunk = Paste {
+            sequence: self.sequence,
+            is_final,
+            text,
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+        Some(chunk)
+    }
+}
@@ -0,0 +1,94 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::{msg, ClientID};
+
+const FOREGROUND: &str = "job1.foreground";
+const SUSPEND: &str = "job1.suspend";
+const RESUME: &str = "job1.resume";
+
+///A `job1.foreground` message.
+///
+///Requests that the given job becomes the foreground job, i.e. that it receives user input and
+///signals generated for the terminal. A client may bring itself or any client below it into the
+///foreground, e.g. a shell may bring itself back into the foreground after a job was suspended.
+#[derive(Clone, Debug)]
+pub struct Foreground<'a> {
+    pub client_id: ClientID<'a>,
+}
+
+impl<'a> msg::DecodeMessage<'a> for Foreground<'a> {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != FOREGROUND {
+            return None;
+        }
+        let client_id = msg.arguments().exactly1()?;
+        Some(Foreground { client_id })
+    }
+}
+
+impl<'a> msg::EncodeMessage for Foreground<'a> {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, FOREGROUND, 1);
+        f.add_argument(&self.client_id);
+        f.finalize()
+    }
+}
+
+///A `job1.suspend` message.
+///
+///Requests that the given job is suspended, like SIGTSTP would do for a process group. Clients
+///may only suspend jobs strictly below themselves.
+#[derive(Clone, Debug)]
+pub struct Suspend<'a> {
+    pub client_id: ClientID<'a>,
+}
+
+impl<'a> msg::DecodeMessage<'a> for Suspend<'a> {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != SUSPEND {
+            return None;
+        }
+        let client_id = msg.arguments().exactly1()?;
+        Some(Suspend { client_id })
+    }
+}
+
+impl<'a> msg::EncodeMessage for Suspend<'a> {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, SUSPEND, 1);
+        f.add_argument(&self.client_id);
+        f.finalize()
+    }
+}
+
+///A `job1.resume` message.
+///
+///Requests that the given job continues running after it was suspended, like SIGCONT would do
+///for a process group. Clients may only resume jobs strictly below themselves. Resuming a job
+///does not bring it into the foreground; use `job1.foreground` for that.
+#[derive(Clone, Debug)]
+pub struct Resume<'a> {
+    pub client_id: ClientID<'a>,
+}
+
+impl<'a> msg::DecodeMessage<'a> for Resume<'a> {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != RESUME {
+            return None;
+        }
+        let client_id = msg.arguments().exactly1()?;
+        Some(Resume { client_id })
+    }
+}
+
+impl<'a> msg::EncodeMessage for Resume<'a> {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, RESUME, 1);
+        f.add_argument(&self.client_id);
+        f.finalize()
+    }
+}
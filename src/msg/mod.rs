@@ -6,10 +6,26 @@
 
 use crate::common::core::{msg, DecodeArgument, MessageType, ModuleIdentifier, ModuleVersion};
 
+#[cfg(feature = "module_clipboard")]
+///Message types for the `clip1` module (an extension provided by this crate).
+pub mod clipboard;
 ///Message types for the [vt6/core](https://vt6.io/std/core/) module.
 pub mod core;
+#[cfg(feature = "module_frame")]
+///Message types for the `frame1` module (an extension provided by this crate).
+pub mod frame;
+#[cfg(feature = "module_input")]
+///Message types for the `input1` module (an extension provided by this crate).
+pub mod input;
+#[cfg(feature = "module_job")]
+///Message types for the `job1` module (an extension provided by this crate).
+pub mod job;
+#[cfg(feature = "module_posix")]
 ///Message types for the [vt6/posix](https://vt6.io/std/posix/) module.
 pub mod posix;
+#[cfg(feature = "module_sig")]
+///Message types for the [vt6/sig](https://vt6.io/std/sig/) module.
+pub mod sig;
 
 ///A `want` message.
 ///[\[vt6/foundation, sect. 4.1\]](https://vt6.io/std/foundation/#section-4-1)
@@ -48,13 +64,9 @@ impl<'a> msg::DecodeMessage<'a> for Have<'a> {
             return None;
         }
         let arg: &'a [u8] = msg.arguments().exactly1()?;
-        if let Some(version) = ModuleVersion::decode_argument(arg) {
-            Some(Have::ThisModule(version))
-        } else if let Some(module) = ModuleIdentifier::decode_argument(arg) {
-            Some(Have::NotThisModule(module))
-        } else {
-            None
-        }
+        ModuleVersion::decode_argument(arg)
+            .map(Have::ThisModule)
+            .or_else(|| ModuleIdentifier::decode_argument(arg).map(Have::NotThisModule))
     }
 }
 
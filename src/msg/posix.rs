@@ -4,21 +4,34 @@
 * Refer to the file "LICENSE" for details.
 *******************************************************************************/
 
-use crate::common::core::{msg, ClientID};
+use crate::common::core::{msg, ClientID, DecodeArgument, ScreenID};
+use crate::common::MAX_MUX_SCREENS;
+use core::fmt;
 
 const CLIENT_HELLO: &str = "posix1.client-hello";
+const PADDED_STDIN_HELLO: &str = "posix1.padded-stdin-hello";
 const PARENT_HELLO: &str = "posix1.parent-hello";
 const SERVER_HELLO: &str = "posix1.server-hello";
 const STDIN_HELLO: &str = "posix1.stdin-hello";
 const STDOUT_HELLO: &str = "posix1.stdout-hello";
+const STDOUT_MUX_HELLO: &str = "posix1.stdout-mux-hello";
 
 ///A `posix1.client-hello` message.
 ///[\[vt6/foundation, sect. X.Y\]](https://vt6.io/std/foundation/#section-X-Y)
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct ClientHello<'a> {
     pub secret: &'a str,
 }
 
+//Debug is implemented manually for all hello messages to keep secrets out of logs
+impl<'a> fmt::Debug for ClientHello<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientHello")
+            .field("secret", &format_args!("<redacted>"))
+            .finish()
+    }
+}
+
 impl<'a> msg::DecodeMessage<'a> for ClientHello<'a> {
     fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
         if msg.parsed_type().as_str() != CLIENT_HELLO {
@@ -39,7 +52,7 @@ impl<'a> msg::EncodeMessage for ClientHello<'a> {
 
 ///A `posix1.parent-hello` message.
 ///[\[vt6/foundation, sect. X.Y\]](https://vt6.io/std/foundation/#section-X-Y)
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct ParentHello<'a> {
     pub client_secret: &'a str,
     #[cfg(feature = "use_std")]
@@ -48,6 +61,15 @@ pub struct ParentHello<'a> {
     pub server_socket_path: &'a [u8],
 }
 
+impl<'a> fmt::Debug for ParentHello<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ParentHello")
+            .field("client_secret", &format_args!("<redacted>"))
+            .field("server_socket_path", &self.server_socket_path)
+            .finish()
+    }
+}
+
 impl<'a> msg::DecodeMessage<'a> for ParentHello<'a> {
     fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
         if msg.parsed_type().as_str() != PARENT_HELLO {
@@ -75,9 +97,9 @@ impl<'a> msg::EncodeMessage for ParentHello<'a> {
 #[derive(Clone, Debug)]
 pub struct ServerHello<'a> {
     pub client_id: ClientID<'a>,
-    pub stdin_screen_id: Option<&'a str>,
-    pub stdout_screen_id: Option<&'a str>,
-    pub stderr_screen_id: Option<&'a str>,
+    pub stdin_screen_id: Option<ScreenID<'a>>,
+    pub stdout_screen_id: Option<ScreenID<'a>>,
+    pub stderr_screen_id: Option<ScreenID<'a>>,
 }
 
 impl<'a> msg::DecodeMessage<'a> for ServerHello<'a> {
@@ -100,20 +122,28 @@ impl<'a> msg::EncodeMessage for ServerHello<'a> {
     fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
         let mut f = msg::MessageFormatter::new(buf, SERVER_HELLO, 4);
         f.add_argument(&self.client_id);
-        f.add_argument(&self.stdin_screen_id);
-        f.add_argument(&self.stdout_screen_id);
-        f.add_argument(&self.stderr_screen_id);
+        f.add_argument(&self.stdin_screen_id.as_ref());
+        f.add_argument(&self.stdout_screen_id.as_ref());
+        f.add_argument(&self.stderr_screen_id.as_ref());
         f.finalize()
     }
 }
 
 ///A `posix1.stdin-hello` message.
 ///[\[vt6/posix1, sect. X.Y\]](https://vt6.io/std/posix1/#section-X-Y)
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct StdinHello<'a> {
     pub secret: &'a str,
 }
 
+impl<'a> fmt::Debug for StdinHello<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StdinHello")
+            .field("secret", &format_args!("<redacted>"))
+            .finish()
+    }
+}
+
 impl<'a> msg::DecodeMessage<'a> for StdinHello<'a> {
     fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
         if msg.parsed_type().as_str() != STDIN_HELLO {
@@ -132,13 +162,69 @@ impl<'a> msg::EncodeMessage for StdinHello<'a> {
     }
 }
 
+///A `posix1.padded-stdin-hello` message.
+///
+///This is a variant of `posix1.stdin-hello` for clients that want the server to hide the amount
+///of standard input that is sent in each write, e.g. while a password is being entered. After
+///the handshake, the server sends chunks as produced by
+///[encode_padded_chunk()](../../common/fn.encode_padded_chunk.html) instead of the plain bytes,
+///and the client removes the chunk headers and padding again with a
+///[PaddedStdinDecoder](../../common/struct.PaddedStdinDecoder.html). How much padding the server
+///adds is up to the server.
+///
+///```
+///# use vt6::common::core::msg::EncodeMessage;
+///# use vt6::msg::posix::PaddedStdinHello;
+///let mut buf = [0u8; 64];
+///let len = PaddedStdinHello { secret: "abc" }.encode(&mut buf).unwrap();
+///assert_eq!(&buf[0..len], b"{2|25:posix1.padded-stdin-hello,3:abc,}");
+///```
+#[derive(Clone)]
+pub struct PaddedStdinHello<'a> {
+    pub secret: &'a str,
+}
+
+impl<'a> fmt::Debug for PaddedStdinHello<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PaddedStdinHello")
+            .field("secret", &format_args!("<redacted>"))
+            .finish()
+    }
+}
+
+impl<'a> msg::DecodeMessage<'a> for PaddedStdinHello<'a> {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != PADDED_STDIN_HELLO {
+            return None;
+        }
+        let secret = msg.arguments().exactly1()?;
+        Some(PaddedStdinHello { secret })
+    }
+}
+
+impl<'a> msg::EncodeMessage for PaddedStdinHello<'a> {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, PADDED_STDIN_HELLO, 1);
+        f.add_argument(self.secret);
+        f.finalize()
+    }
+}
+
 ///A `posix1.stdout-hello` message.
 ///[\[vt6/posix1, sect. X.Y\]](https://vt6.io/std/posix1/#section-X-Y)
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct StdoutHello<'a> {
     pub secret: &'a str,
 }
 
+impl<'a> fmt::Debug for StdoutHello<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StdoutHello")
+            .field("secret", &format_args!("<redacted>"))
+            .finish()
+    }
+}
+
 impl<'a> msg::DecodeMessage<'a> for StdoutHello<'a> {
     fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
         if msg.parsed_type().as_str() != STDOUT_HELLO {
@@ -156,3 +242,71 @@ impl<'a> msg::EncodeMessage for StdoutHello<'a> {
         f.finalize()
     }
 }
+
+///A `posix1.stdout-mux-hello` message.
+///
+///This is a variant of `posix1.stdout-hello` for multiplexer clients that want to serve the
+///stdout of several screens over a single connection. It contains one stdout secret per screen
+///(at least one and at most [MAX_MUX_SCREENS](../../common/constant.MAX_MUX_SCREENS.html)). After
+///the handshake, the client sends chunks as produced by
+///[encode_mux_chunk()](../../common/fn.encode_mux_chunk.html), where the screen index refers to
+///the position of the respective secret in this message.
+///
+///Since the number of secrets is variable, this type does not implement `DecodeMessage`. Use
+///[decode_secrets()](#method.decode_secrets) instead.
+///
+///```
+///# use vt6::common::core::msg::{EncodeMessage, Message};
+///# use vt6::msg::posix::StdoutMuxHello;
+///let mut buf = [0u8; 64];
+///let len = StdoutMuxHello { secrets: &["abc", "def"] }.encode(&mut buf).unwrap();
+///assert_eq!(&buf[0..len], b"{3|23:posix1.stdout-mux-hello,3:abc,3:def,}");
+///
+///let (msg, _) = Message::parse(&buf[0..len]).unwrap();
+///let secrets: Vec<_> = StdoutMuxHello::decode_secrets(&msg).unwrap().collect();
+///assert_eq!(secrets, vec!["abc", "def"]);
+///```
+#[derive(Clone)]
+pub struct StdoutMuxHello<'a, 'b> {
+    pub secrets: &'b [&'a str],
+}
+
+impl<'a, 'b> fmt::Debug for StdoutMuxHello<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StdoutMuxHello")
+            .field(
+                "secrets",
+                &format_args!("<{} redacted>", self.secrets.len()),
+            )
+            .finish()
+    }
+}
+
+impl StdoutMuxHello<'_, '_> {
+    ///Decodes the secrets from a `posix1.stdout-mux-hello` message. Returns `None` if the message
+    ///has a different type, or if the number of secrets is out of range.
+    pub fn decode_secrets<'a>(msg: &msg::Message<'a>) -> Option<impl Iterator<Item = &'a str>> {
+        if msg.parsed_type().as_str() != STDOUT_MUX_HELLO {
+            return None;
+        }
+        let args = msg.arguments();
+        let is_valid = (1..=MAX_MUX_SCREENS).contains(&args.len())
+            && args
+                .clone()
+                .all(|arg| <&str>::decode_argument(arg).is_some());
+        if !is_valid {
+            return None;
+        }
+        Some(args.filter_map(<&str>::decode_argument))
+    }
+}
+
+impl<'a, 'b> msg::EncodeMessage for StdoutMuxHello<'a, 'b> {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, STDOUT_MUX_HELLO, self.secrets.len());
+        for secret in self.secrets {
+            f.add_argument(*secret);
+        }
+        f.finalize()
+    }
+}
@@ -0,0 +1,108 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg;
+
+const CLAIM: &str = "sig1.claim";
+const RELEASE: &str = "sig1.release";
+const DELIVER: &str = "sig1.deliver";
+
+crate::vt6_enum_argument! {
+    ///The signals that can be claimed with `sig1.claim`.
+    ///
+    ///These correspond to the signals that a TTY would generate in response to special key
+    ///combinations typed by the user.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum Signal {
+        ///Usually generated by Ctrl-C. Corresponds to SIGINT.
+        Interrupt = "interrupt",
+        ///Usually generated by Ctrl-\\. Corresponds to SIGQUIT.
+        Quit = "quit",
+        ///Usually generated by Ctrl-Z. Corresponds to SIGTSTP.
+        Suspend = "suspend",
+    }
+}
+
+///A `sig1.claim` message.
+///
+///Sent by a client to ask for the given signal to be delivered to it via `sig1.deliver` instead of
+///through the legacy mechanism. The server acknowledges the claim by sending the same message
+///back, or refuses it with `nope`.
+#[derive(Clone, Debug)]
+pub struct Claim {
+    pub signal: Signal,
+}
+
+impl<'a> msg::DecodeMessage<'a> for Claim {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != CLAIM {
+            return None;
+        }
+        let signal = msg.arguments().exactly1()?;
+        Some(Claim { signal })
+    }
+}
+
+impl msg::EncodeMessage for Claim {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, CLAIM, 1);
+        f.add_argument(&self.signal);
+        f.finalize()
+    }
+}
+
+///A `sig1.release` message.
+///
+///Sent by a client to give up a claim that was previously made with `sig1.claim`. The server
+///acknowledges by sending the same message back.
+#[derive(Clone, Debug)]
+pub struct Release {
+    pub signal: Signal,
+}
+
+impl<'a> msg::DecodeMessage<'a> for Release {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != RELEASE {
+            return None;
+        }
+        let signal = msg.arguments().exactly1()?;
+        Some(Release { signal })
+    }
+}
+
+impl msg::EncodeMessage for Release {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, RELEASE, 1);
+        f.add_argument(&self.signal);
+        f.finalize()
+    }
+}
+
+///A `sig1.deliver` message.
+///
+///Sent by the server to deliver a signal to a client that has claimed it.
+#[derive(Clone, Debug)]
+pub struct Deliver {
+    pub signal: Signal,
+}
+
+impl<'a> msg::DecodeMessage<'a> for Deliver {
+    fn decode_message<'b>(msg: &'b msg::Message<'a>) -> Option<Self> {
+        if msg.parsed_type().as_str() != DELIVER {
+            return None;
+        }
+        let signal = msg.arguments().exactly1()?;
+        Some(Deliver { signal })
+    }
+}
+
+impl msg::EncodeMessage for Deliver {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let mut f = msg::MessageFormatter::new(buf, DELIVER, 1);
+        f.add_argument(&self.signal);
+        f.finalize()
+    }
+}
@@ -4,7 +4,16 @@
 * Refer to the file "LICENSE" for details.
 *******************************************************************************/
 
+use crate::common::core::msg;
+#[cfg(feature = "module_job")]
+use crate::common::core::ClientID;
+use crate::common::Utf8StreamDecoder;
+#[cfg(feature = "module_clipboard")]
+use crate::msg::clipboard::Selection;
+#[cfg(feature = "module_sig")]
+use crate::msg::sig::Signal;
 use crate::server;
+use core::fmt;
 
 ///Connector for client sockets in msgio mode.
 ///
@@ -27,8 +36,145 @@ pub trait MessageConnector: Sized + Send + Sync {
 pub trait StdoutConnector: Sized + Send + Sync {
     fn new(id: server::ScreenIdentity) -> Self;
 
-    ///Called by the Connection whenever stdout has been received from the client.
-    fn receive(&mut self, buf: &[u8]);
+    ///Returns the identity of the screen that this connector was created for. The Connection uses
+    ///this to find the connector for a specific screen, e.g. for echoing input that passes
+    ///through a [line discipline](struct.LineDiscipline.html), and to track which screens have
+    ///their stdout attached.
+    ///
+    ///The default implementation returns `None`. Connectors that do not report their identity
+    ///still receive stdout, but cannot be looked up by screen.
+    fn identity(&self) -> Option<&server::ScreenIdentity> {
+        None
+    }
+
+    ///Called by the Connection whenever stdout has been received from the client. When an error
+    ///is returned, the Connection goes into `Teardown` state, i.e. the client's stdout socket is
+    ///closed.
+    fn receive(&mut self, buf: &[u8]) -> Result<(), StdoutError>;
+
+    ///Called by the Connection after each call to `receive()`. If this returns false, because
+    ///the application cannot keep up with displaying the received stdout, the Connection stops
+    ///reading from the client's socket (see
+    ///[`Connection::pause_reading()`](struct.Connection.html#method.pause_reading)). The client
+    ///is then blocked once the socket buffer is full. To continue reading, the application calls
+    ///[`Dispatch::resume_stdout()`](trait.Dispatch.html#method.resume_stdout) when it has caught
+    ///up.
+    ///
+    ///The default implementation always returns true.
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    ///Called by the Connection when it leaves the `Stdout` or `StdoutMux` state (usually into
+    ///`Teardown`), right before this connector is dropped. Connectors that hold back incomplete
+    ///output between calls to `receive()` can flush it here.
+    ///
+    ///The default implementation does nothing.
+    fn finish(&mut self) {}
+}
+
+///Error type for [`StdoutConnector::receive()`](trait.StdoutConnector.html#tymethod.receive).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StdoutError {
+    ///The screen cannot display output anymore, e.g. because the user has closed the window
+    ///that displayed it.
+    ScreenClosed,
+    ///Any other reason why the application does not accept any more output on this connection.
+    Other(String),
+}
+
+impl fmt::Display for StdoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::ScreenClosed => write!(f, "screen has been closed"),
+            Self::Other(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for StdoutError {}
+
+///Like [StdoutConnector](trait.StdoutConnector.html), but receives text instead of bytes.
+///
+///Implementors of this trait can be used as a StdoutConnector by wrapping them in
+///[Utf8StdoutConnector](struct.Utf8StdoutConnector.html).
+pub trait TextStdoutConnector: Sized + Send + Sync {
+    fn new(id: server::ScreenIdentity) -> Self;
+
+    ///Like [`StdoutConnector::identity()`](trait.StdoutConnector.html#method.identity).
+    fn identity(&self) -> Option<&server::ScreenIdentity> {
+        None
+    }
+
+    ///Called whenever stdout has been received from the client. Invalid UTF-8 sequences have been
+    ///replaced with U+FFFD REPLACEMENT CHARACTER. Errors are handled like in
+    ///[`StdoutConnector::receive()`](trait.StdoutConnector.html#tymethod.receive).
+    fn receive_text(&mut self, text: &str) -> Result<(), StdoutError>;
+
+    ///Like [`StdoutConnector::is_ready()`](trait.StdoutConnector.html#method.is_ready).
+    fn is_ready(&self) -> bool {
+        true
+    }
+}
+
+///A [StdoutConnector](trait.StdoutConnector.html) that decodes stdout as UTF-8 and forwards it to
+///a [TextStdoutConnector](trait.TextStdoutConnector.html).
+///
+///UTF-8 sequences that are split across multiple reads are held back until they are complete,
+///using a [Utf8StreamDecoder](../common/struct.Utf8StreamDecoder.html). When the connection goes
+///away in the middle of such a sequence, it is delivered as U+FFFD REPLACEMENT CHARACTER.
+pub struct Utf8StdoutConnector<C> {
+    inner: C,
+    decoder: Utf8StreamDecoder,
+}
+
+impl<C> Utf8StdoutConnector<C> {
+    ///Returns a reference to the wrapped connector.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    ///Returns a mutable reference to the wrapped connector.
+    pub fn inner_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+}
+
+impl<C: TextStdoutConnector> StdoutConnector for Utf8StdoutConnector<C> {
+    fn new(id: server::ScreenIdentity) -> Self {
+        Self {
+            inner: C::new(id),
+            decoder: Utf8StreamDecoder::new(),
+        }
+    }
+
+    fn identity(&self) -> Option<&server::ScreenIdentity> {
+        self.inner.identity()
+    }
+
+    fn receive(&mut self, buf: &[u8]) -> Result<(), StdoutError> {
+        let inner = &mut self.inner;
+        let mut result = Ok(());
+        self.decoder.decode(buf, |text| {
+            //after an error, the connection is torn down, so the rest of the input is irrelevant
+            if result.is_ok() {
+                result = inner.receive_text(text);
+            }
+        });
+        result
+    }
+
+    fn is_ready(&self) -> bool {
+        self.inner.is_ready()
+    }
+
+    fn finish(&mut self) {
+        let inner = &mut self.inner;
+        //the connection is going away, so an error does not change anything anymore
+        self.decoder.finish(|text| {
+            let _ = inner.receive_text(text);
+        });
+    }
 }
 
 ///Main integration point for application-specific logic.
@@ -70,12 +216,44 @@ pub trait Application: Clone + Send + Sync + 'static {
     ///errors.
     fn notify(&self, n: &server::Notification);
 
+    ///Returns whether messages sent to clients shall be passed through
+    ///[filter_outgoing()](#method.filter_outgoing). The default implementation returns false, so
+    ///that messages do not need to be encoded and parsed before they are sent. Applications that
+    ///implement `filter_outgoing()` must override this to return true.
+    fn has_outgoing_filter(&self) -> bool {
+        false
+    }
+    ///Decides what to do with a message that is about to be sent on the given connection. This is
+    ///usually implemented by running a [filter chain](trait.OutgoingFilter.html). It is only
+    ///called if [has_outgoing_filter()](#method.has_outgoing_filter) returns true.
+    ///
+    ///The default implementation lets all messages pass.
+    fn filter_outgoing<D: server::Dispatch<Self>>(
+        &self,
+        _msg: &msg::Message,
+        _conn: &server::Connection<Self, D>,
+    ) -> server::OutgoingAction {
+        server::OutgoingAction::Pass
+    }
+
     ///Register a new client with the terminal. This does not return an `Option<>` since the
     ///terminal is not allowed to refuse new clients. The handler generating this call will have
     ///made sure that the prospective client is below the requesting client, i.e. that the
     ///requesting client's ID is a prefix of `i.client_id()`, and that `i.client_id()` is not yet
     ///in use.
     fn register_client(&self, i: server::ClientIdentity) -> server::ClientCredentials;
+    ///Register several new clients at once, e.g. for all processes of a pipeline that a shell is
+    ///about to spawn. The same guarantees as for `register_client()` apply to each client. In
+    ///addition, the handler generating this call will have made sure that all clients of the
+    ///batch can be registered, and that none of them is below another one, so the whole batch can
+    ///be registered without failing halfway through. The credentials shall be returned in the same
+    ///order as the identities.
+    ///
+    ///The default implementation calls `register_client()` for each client. Applications can
+    ///override it to make the registration of the whole batch appear atomic to other threads.
+    fn register_clients(&self, ids: Vec<server::ClientIdentity>) -> Vec<server::ClientCredentials> {
+        ids.into_iter().map(|i| self.register_client(i)).collect()
+    }
     ///Unregister all clients matching this selector. Any resources associated with these clients
     ///shall be released, including credentials that were not redeemed yet. The caller will ensure
     ///that all relevant client connections are torn down.
@@ -84,20 +262,344 @@ pub trait Application: Clone + Send + Sync + 'static {
     ///including those where no client connection has been established yet.
     fn has_clients(&self, s: server::ClientSelector) -> bool;
 
+    ///Decides whether a client connection that was just accepted by the Dispatch shall be kept,
+    ///e.g. to limit connections per user or to throttle clients that reconnect too quickly. This
+    ///is called before a Connection object is created, and before anything is read from the
+    ///connection. When the connection is refused, the Application is notified about that through
+    ///[`Notification::ConnectionRefused`](enum.Notification.html#variant.ConnectionRefused).
+    ///
+    ///The Dispatch may refuse connections on its own (e.g. when its connection limit is reached),
+    ///in which case this is not called.
+    ///
+    ///The default implementation accepts all connections.
+    fn accept_connection(&self, _peer: &server::PeerInfo<'_>) -> server::AcceptDecision {
+        server::AcceptDecision::Accept
+    }
+
     ///Authorize a client's attempt to handshake for an msgio socket. Since each client ID is only
     ///supposed to map to exactly one msgio socket, implementations SHALL NOT authorize the same
     ///secret multiple times.
-    fn authorize_client(&self, secret: &str) -> Option<server::ClientIdentity>;
+    ///
+    ///To avoid leaking information about the secrets through timing, compare them with
+    ///[`ClientCredentials::verify()`](struct.ClientCredentials.html#method.verify) instead of `==`.
+    ///
+    ///`peer` contains the credentials of the process on the other end of the connection, if the
+    ///Dispatch was able to obtain them. Implementations can use them to enforce additional
+    ///policies, e.g. to only accept clients running under the same user ID as the terminal.
+    fn authorize_client(
+        &self,
+        secret: &str,
+        peer: Option<&server::PeerCredentials>,
+    ) -> Option<server::ClientIdentity>;
+    ///Called by the handshake handler when `authorize_client()` has refused a secret, to give the
+    ///application a chance to accept it as a resumed session instead.
+    ///
+    ///After a terminal restart (or when a client's msgio socket broke down), clients can reconnect
+    ///and present the secret from their original handshake again (see
+    ///[vt6::client::reconnect()](../client/fn.reconnect.html)). If the application has retained
+    ///the client's registration, e.g. by persisting it across the restart, it can return the
+    ///client's identity here to resume the session. The screens in the returned identity are bound
+    ///to the new connection, just like after a regular handshake. Implementations must make sure
+    ///that the client's previous connection (if any) is gone, since each client ID maps to at most
+    ///one msgio socket.
+    ///
+    ///The default implementation does not resume any sessions.
+    fn resume_client(
+        &self,
+        _secret: &str,
+        _peer: Option<&server::PeerCredentials>,
+    ) -> Option<server::ClientIdentity> {
+        None
+    }
     ///Returns information about the client with the given ID if it has been registered with the
     ///terminal.
     fn find_client(&self, id: crate::common::core::ClientID<'_>) -> Option<server::ClientIdentity>;
 
+    ///Decides whether the given client may send messages of the given type. This is called by the
+    ///[Connection](struct.Connection.html) for every message received in msgio mode, before the
+    ///message is handed to the handler chain. Messages that are not authorized are answered with
+    ///`nope` without being seen by any handler.
+    ///
+    ///This is the place to implement per-client policies in one place, e.g. to forbid
+    ///`core1.client-make` for untrusted clients.
+    ///
+    ///The default implementation allows all messages.
+    fn authorize_message(
+        &self,
+        _client: &server::ClientIdentity,
+        _msg_type: &crate::common::core::MessageType<'_>,
+    ) -> bool {
+        true
+    }
+
     ///Authorize a client's attempt to handshake for an stdin socket. To ensure that each screen
     ///has at most one stdin socket connected to it, implementations SHALL NOT authorize the same
-    ///secret multiple times.
+    ///secret again while the screen's stdin is attached to a connection. The attachment ends when
+    ///`detach_stdin()` is called for the screen.
     fn authorize_stdin(&self, secret: &str) -> Option<server::ScreenIdentity>;
     ///Authorize a client's attempt to handshake for an stdout socket. To ensure that each screen
     ///has at most one stdout socket connected to it, implementations SHALL NOT authorize the same
-    ///secret multiple times.
+    ///secret again while the screen's stdout is attached to a connection. The attachment ends
+    ///when `detach_stdout()` is called for the screen.
     fn authorize_stdout(&self, secret: &str) -> Option<server::ScreenIdentity>;
+    ///Called by the [Connection](struct.Connection.html) when a connection in stdin mode is torn
+    ///down, e.g. because the client closed it or because an IO error occurred. Afterwards, the
+    ///screen does not have an stdin socket anymore, so the application may authorize the screen's
+    ///stdin secret again.
+    ///
+    ///This is not called when the connection was displaced by a takeover (see
+    ///`takeover_stdin()`), since the screen stays attached to the connection that took over.
+    ///
+    ///The default implementation does nothing.
+    fn detach_stdin(&self, _screen: &server::ScreenIdentity) {}
+    ///Like `detach_stdin()`, but for stdout sockets.
+    ///
+    ///The default implementation does nothing.
+    fn detach_stdout(&self, _screen: &server::ScreenIdentity) {}
+    ///Called by the handshake handler when neither `authorize_stdin()` nor `resume_stdin()` have
+    ///accepted a secret, to give the application a chance to let the new connection take over the
+    ///screen's existing stdin socket. This is useful when the previous stdin connection has died
+    ///without the terminal noticing yet, e.g. because the client crashed before the socket was
+    ///closed.
+    ///
+    ///If a screen identity is returned, the new connection is attached to that screen, and the
+    ///connection that was previously attached to it is torn down. Implementations SHALL only
+    ///return a screen if the secret is valid for it.
+    ///
+    ///The default implementation does not allow takeovers.
+    fn takeover_stdin(&self, _secret: &str) -> Option<server::ScreenIdentity> {
+        None
+    }
+    ///Like `takeover_stdin()`, but for stdout sockets.
+    ///
+    ///The default implementation does not allow takeovers.
+    fn takeover_stdout(&self, _secret: &str) -> Option<server::ScreenIdentity> {
+        None
+    }
+    ///Like `resume_client()`, but for stdin sockets: Called when `authorize_stdin()` has refused a
+    ///secret, to re-bind a reconnecting client's stdin socket to its screen.
+    ///
+    ///The default implementation does not resume any sessions.
+    fn resume_stdin(&self, _secret: &str) -> Option<server::ScreenIdentity> {
+        None
+    }
+    ///Like `resume_client()`, but for stdout sockets: Called when `authorize_stdout()` has refused
+    ///a secret, to re-bind a reconnecting client's stdout socket to its screen.
+    ///
+    ///The default implementation does not resume any sessions.
+    fn resume_stdout(&self, _secret: &str) -> Option<server::ScreenIdentity> {
+        None
+    }
+
+    ///Returns the line discipline options for the given screen, or `None` if the application does
+    ///not support line disciplines. This is used by [vt6::server::term](term/index.html) to
+    ///answer `core1.sub` and `core1.set` messages for the respective properties.
+    ///
+    ///The default implementation returns `None`.
+    fn line_discipline_options(
+        &self,
+        _screen: &server::ScreenIdentity,
+    ) -> Option<server::LineDisciplineOptions> {
+        None
+    }
+    ///Stores new line discipline options for the given screen, after a client has requested
+    ///changing them. The application may choose to store different options than requested; the
+    ///caller will use the result of `line_discipline_options()` afterwards. The caller will also
+    ///update the line discipline on the screen's stdin connection, if any.
+    ///
+    ///The default implementation does nothing.
+    fn set_line_discipline_options(
+        &self,
+        _screen: &server::ScreenIdentity,
+        _options: server::LineDisciplineOptions,
+    ) {
+    }
+
+    #[cfg(feature = "module_term")]
+    ///Returns the title of the given screen, or `None` if the application does not support this
+    ///kind of title. This is used by [vt6::server::term::TitleHandler](term/struct.TitleHandler.html)
+    ///to answer `core1.sub` and `core1.set` messages for the respective properties.
+    ///
+    ///The default implementation returns `None`.
+    fn screen_title(
+        &self,
+        _screen: &server::ScreenIdentity,
+        _kind: server::term::TitleKind,
+    ) -> Option<String> {
+        None
+    }
+    #[cfg(feature = "module_term")]
+    ///Stores a new title for the given screen, after a client has requested changing it. The
+    ///caller has already validated the title. The application may choose to store a different
+    ///title than requested; the caller will use the result of `screen_title()` afterwards.
+    ///
+    ///The default implementation does nothing.
+    fn set_screen_title(
+        &self,
+        _screen: &server::ScreenIdentity,
+        _kind: server::term::TitleKind,
+        _title: &str,
+    ) {
+    }
+
+    #[cfg(feature = "module_sig")]
+    ///Decides whether the given client may claim the given signal with `sig1.claim`. This is
+    ///used by [vt6::server::sig::MessageHandler](sig/struct.MessageHandler.html).
+    ///
+    ///The default implementation allows claims from all clients whose stdin is connected to the
+    ///terminal, since signals are generated for the screen that the client's stdin is connected
+    ///to.
+    fn authorize_signal_claim(&self, client: &server::ClientIdentity, _signal: Signal) -> bool {
+        client.stdin_screen_id().is_some()
+    }
+    #[cfg(feature = "module_sig")]
+    ///Chooses which client receives a signal that was generated for the given screen, when
+    ///[vt6::server::sig::deliver_signal()](sig/fn.deliver_signal.html) is called. `claimants`
+    ///contains all clients whose stdin is connected to that screen and that have claimed the
+    ///signal (at least one). The result is an index into `claimants`. If `None` is returned, the
+    ///signal is treated as unclaimed.
+    ///
+    ///The default implementation chooses the client with the longest client ID. Since client IDs
+    ///of child clients are extensions of the client IDs of their parents, this is the most deeply
+    ///nested client, which is usually the one running in the foreground.
+    fn route_signal(
+        &self,
+        _screen: &server::ScreenIdentity,
+        _signal: Signal,
+        claimants: &[server::ClientIdentity],
+    ) -> Option<usize> {
+        (0..claimants.len()).max_by_key(|&idx| claimants[idx].client_id().as_str().len())
+    }
+    #[cfg(feature = "module_sig")]
+    ///Called by [vt6::server::sig::deliver_signal()](sig/fn.deliver_signal.html) when no client
+    ///receives the signal. The application should then fall back to the behavior for legacy
+    ///clients, e.g. by sending the respective control character on stdin.
+    ///
+    ///The default implementation does nothing.
+    fn signal_unclaimed(&self, _screen: &server::ScreenIdentity, _signal: Signal) {}
+
+    #[cfg(feature = "module_job")]
+    ///Performs a job control action that a client has requested through the `job1` module. The
+    ///job comprises all clients [AtOrBelow](enum.ClientSelector.html) the given client ID. This is
+    ///used by [vt6::server::job::MessageHandler](job/struct.MessageHandler.html), which has
+    ///already checked that `requester` is responsible for the job and that the job has clients.
+    ///
+    ///For `JobAction::Foreground`, the application should route user input and signals for the
+    ///requester's screen to the job. For `JobAction::Suspend` and `JobAction::Resume`, it should
+    ///stop or continue the processes behind the job's clients, e.g. by sending SIGTSTP or SIGCONT
+    ///to their process group. The result indicates whether the action was performed.
+    ///
+    ///The default implementation refuses all actions, since the crate cannot know how clients
+    ///map to processes.
+    fn control_job(
+        &self,
+        _requester: &server::ClientIdentity,
+        _action: server::job::JobAction,
+        _job: ClientID<'_>,
+    ) -> bool {
+        false
+    }
+
+    #[cfg(feature = "module_clipboard")]
+    ///Decides which kinds of clipboard access the given client is allowed. This is used by
+    ///[vt6::server::clipboard::MessageHandler](clipboard/struct.MessageHandler.html).
+    ///
+    ///The default implementation allows all clients to write, but not to read the clipboard (see
+    ///[ClipboardPolicy::WRITE_ONLY](clipboard/struct.ClipboardPolicy.html#associatedconstant.WRITE_ONLY)).
+    fn clipboard_policy(
+        &self,
+        _client: &server::ClientIdentity,
+    ) -> server::clipboard::ClipboardPolicy {
+        server::clipboard::ClipboardPolicy::WRITE_ONLY
+    }
+    #[cfg(feature = "module_clipboard")]
+    ///Returns the contents of the given selection as text, or `None` if the selection is empty or
+    ///does not contain text. This is used by
+    ///[vt6::server::clipboard::MessageHandler](clipboard/struct.MessageHandler.html) to answer
+    ///`clip1.get` messages from clients that are allowed to read the clipboard.
+    ///
+    ///The default implementation returns `None`.
+    fn clipboard_text(&self, _selection: Selection) -> Option<String> {
+        None
+    }
+    #[cfg(feature = "module_clipboard")]
+    ///Replaces the contents of the given selection, after a client that is allowed to write the
+    ///clipboard has requested it with `clip1.set`.
+    ///
+    ///The default implementation does nothing.
+    fn set_clipboard_text(&self, _selection: Selection, _text: &str) {}
+
+    ///Stores the value of a property of the given screen, so that it can be restored with
+    ///`restore_property()` after a server restart. The property handlers in this crate call this
+    ///whenever a client has changed the value of a property. The value is given in the same
+    ///encoding as in the `core1.pub` message that announces it.
+    ///
+    ///The default implementation does nothing.
+    fn persist_property(&self, _screen: &server::ScreenIdentity, _name: &str, _value: &[u8]) {}
+    ///Returns the value of a property of the given screen that was previously stored with
+    ///`persist_property()`, if any. Values that are not valid for the respective property are
+    ///ignored.
+    ///
+    ///This is called by [restore_properties()](fn.restore_properties.html), which the application
+    ///calls for each screen that it creates, e.g. right after starting up.
+    ///
+    ///The default implementation returns `None`.
+    fn restore_property(&self, _screen: &server::ScreenIdentity, _name: &str) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+///Restores the values of all persistent properties of the given screen from the values previously
+///stored with [`Application::persist_property()`](trait.Application.html#method.persist_property).
+///This covers the properties of all modules that are enabled in this build of the library (see
+///e.g. [`term::restore_properties()`](term/fn.restore_properties.html)).
+///
+///Nothing in this crate calls this function, since only the application knows when a screen comes
+///into existence. Applications that persist properties call it once for each screen that they
+///create, including the screens that they recreate right after starting up, and before handing
+///out the credentials for that screen, so that clients never see the values from before the
+///restore.
+pub fn restore_properties<A: Application>(
+    #[allow(unused_variables)] app: &A,
+    #[allow(unused_variables)] screen: &server::ScreenIdentity,
+) {
+    #[cfg(feature = "module_term")]
+    server::term::restore_properties(app, screen);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::ScreenID;
+
+    struct TextConnector(String);
+
+    impl TextStdoutConnector for TextConnector {
+        fn new(_id: server::ScreenIdentity) -> Self {
+            Self(String::new())
+        }
+
+        fn receive_text(&mut self, text: &str) -> Result<(), StdoutError> {
+            self.0.push_str(text);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_utf8_stdout_connector() {
+        let screen = server::ScreenIdentity::new(&ScreenID::parse("screen1").unwrap());
+        let mut c: Utf8StdoutConnector<TextConnector> = StdoutConnector::new(screen);
+
+        //sequences split across reads are held back until they are complete...
+        c.receive(b"a\xC3").unwrap();
+        assert_eq!(c.inner().0, "a");
+        c.receive(b"\xA4b\xE2\x82").unwrap();
+        assert_eq!(c.inner().0, "a\u{E4}b");
+
+        //...or until the connection goes away
+        c.finish();
+        assert_eq!(c.inner().0, "a\u{E4}b\u{FFFD}");
+        c.finish();
+        assert_eq!(c.inner().0, "a\u{E4}b\u{FFFD}");
+    }
 }
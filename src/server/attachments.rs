@@ -0,0 +1,153 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::server;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+///The ways in which a connection can be attached to a screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AttachmentKind {
+    ///The connection is in `Stdin` state and delivers input to the screen's client.
+    Stdin,
+    ///The connection is in `Stdout` state and receives output from the screen's client.
+    Stdout,
+}
+
+///A registry recording which connection is attached to each screen as its stdin or stdout.
+///
+///Each [Dispatch](trait.Dispatch.html) maintains one of these, which can be accessed through
+///[`Dispatch::attachments()`](trait.Dispatch.html#tymethod.attachments). It is updated by the
+///[Connection](struct.Connection.html) whenever a connection changes into or out of the `Stdin`
+///or `Stdout` state, so it can be used to route input to a screen or to show the attachment status
+///in the UI without having to query all connections.
+///
+///This type is a cheap handle to shared state, so an Application can keep a clone of it after
+///constructing the Dispatch.
+///
+///```
+///# use vt6::common::core::ScreenID;
+///# use vt6::server::{AttachmentKind, Attachments, ScreenIdentity};
+///let attachments: Attachments<u64> = Attachments::new();
+///let screen = ScreenIdentity::new(&ScreenID::parse("screen1").unwrap());
+///assert!(!attachments.is_stdin_attached(&screen));
+///assert_eq!(attachments.stdout_connection(&screen), None);
+///assert_eq!(attachments.connection(&screen, AttachmentKind::Stdout), None);
+///```
+pub struct Attachments<I>(Arc<Mutex<HashMap<(server::ScreenIdentity, AttachmentKind), I>>>);
+
+impl<I> Clone for Attachments<I> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<I> Default for Attachments<I> {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(HashMap::new())))
+    }
+}
+
+impl<I: Clone + PartialEq> Attachments<I> {
+    ///Creates an empty registry. This is usually only called by the Dispatch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Returns the ID of the connection that is attached to the given screen in the given way.
+    pub fn connection(&self, screen: &server::ScreenIdentity, kind: AttachmentKind) -> Option<I> {
+        let key = (screen.clone(), kind);
+        self.0.lock().unwrap().get(&key).cloned()
+    }
+
+    ///Returns the ID of the connection that is attached to the given screen as its stdin.
+    pub fn stdin_connection(&self, screen: &server::ScreenIdentity) -> Option<I> {
+        self.connection(screen, AttachmentKind::Stdin)
+    }
+
+    ///Returns the ID of the connection that is attached to the given screen as its stdout.
+    pub fn stdout_connection(&self, screen: &server::ScreenIdentity) -> Option<I> {
+        self.connection(screen, AttachmentKind::Stdout)
+    }
+
+    ///Returns whether a connection is attached to the given screen as its stdin.
+    pub fn is_stdin_attached(&self, screen: &server::ScreenIdentity) -> bool {
+        self.stdin_connection(screen).is_some()
+    }
+
+    ///Returns whether a connection is attached to the given screen as its stdout.
+    pub fn is_stdout_attached(&self, screen: &server::ScreenIdentity) -> bool {
+        self.stdout_connection(screen).is_some()
+    }
+
+    ///Records that the given connection is now attached to the screen. This replaces a previous
+    ///attachment of the same kind, which happens when a connection takes over a screen.
+    pub(crate) fn attach(&self, screen: &server::ScreenIdentity, kind: AttachmentKind, id: I) {
+        self.0.lock().unwrap().insert((screen.clone(), kind), id);
+    }
+
+    ///Removes the attachment of the given connection to the screen, and returns whether there was
+    ///one. If the screen has already been taken over by another connection, that attachment is
+    ///left alone.
+    pub(crate) fn detach(
+        &self,
+        screen: &server::ScreenIdentity,
+        kind: AttachmentKind,
+        id: &I,
+    ) -> bool {
+        let mut attachments = self.0.lock().unwrap();
+        let key = (screen.clone(), kind);
+        if attachments.get(&key) == Some(id) {
+            attachments.remove(&key);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::core::ScreenID;
+    use crate::server;
+    use crate::server::testing::{Conversation, MockApplication, MockDispatch};
+    use crate::server::Dispatch;
+    use crate::server::ScreenIdentity;
+
+    #[test]
+    fn test_attachments() {
+        let app: MockApplication = MockApplication::new();
+        let screen_id = ScreenID::parse("screen1").unwrap();
+        let screen = ScreenIdentity::new(&screen_id);
+        let screen_creds = app.add_screen(&screen_id);
+        let dispatch = MockDispatch::new(app);
+        let attachments = dispatch.attachments().clone();
+        assert!(!attachments.is_stdin_attached(&screen));
+
+        let mut conv1 = Conversation::with_dispatch(&dispatch);
+        conv1.send_message(&crate::msg::posix::StdoutHello {
+            secret: screen_creds.stdout_secret(),
+        });
+        assert!(!attachments.is_stdin_attached(&screen));
+        assert_eq!(attachments.stdout_connection(&screen), Some(0));
+
+        //after a takeover, the registry points to the new connection...
+        let mut conv2 = Conversation::with_dispatch(&dispatch);
+        conv2.send_message(&crate::msg::posix::StdoutHello {
+            secret: screen_creds.stdout_secret(),
+        });
+        assert_eq!(attachments.stdout_connection(&screen), Some(1));
+        //...even after the old connection has been torn down
+        conv1.expect_no_reply();
+        assert_eq!(conv1.connection().state().type_name(), "Teardown");
+        assert_eq!(attachments.stdout_connection(&screen), Some(1));
+
+        conv2
+            .connection_mut()
+            .set_state(server::ConnectionState::Teardown);
+        assert!(!attachments.is_stdout_attached(&screen));
+    }
+}
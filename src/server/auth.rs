@@ -4,8 +4,8 @@
 * Refer to the file "LICENSE" for details.
 *******************************************************************************/
 
-use crate::common::core::{ClientID, OwnedClientID};
-//TODO Once syntactical constraints on screen IDs are decided, add vt6::common::core::ScreenID. When we do, remove the `_screen_id` suffixes from method names where not necessary anymore.
+use crate::common::core::{ClientID, OwnedClientID, OwnedScreenID, ScreenID};
+use std::borrow::Cow;
 
 ///Information identifying a client.
 ///
@@ -18,12 +18,29 @@ use crate::common::core::{ClientID, OwnedClientID};
 ///
 ///The [Application](trait.Application.html) usually holds on to ClientIdentity instances for their
 ///entire respective lifetime, to track which clients are currently alive.
+///
+///With the `use_serde` feature, this type implements `Serialize` and `Deserialize`, e.g. for
+///restoring sessions after a server restart.
+///
+///Comparisons and hashing only consider the client ID, since the client ID identifies the client
+///on its own. Two instances that only differ in their screen IDs therefore compare equal, and can
+///be used interchangeably as keys in a HashMap or BTreeMap.
+///
+///```
+///# use vt6::common::core::*;
+///# use vt6::server::*;
+///let id = ClientID::parse("a1").unwrap();
+///let mut clients = std::collections::HashSet::new();
+///clients.insert(ClientIdentity::new(&id));
+///assert!(clients.contains(&ClientIdentity::new(&id).with_stdin(&ScreenID::parse("foo").unwrap())));
+///```
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "use_serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClientIdentity {
     id: OwnedClientID,
-    stdin_screen_id: Option<String>,
-    stdout_screen_id: Option<String>,
-    stderr_screen_id: Option<String>,
+    stdin_screen_id: Option<OwnedScreenID>,
+    stdout_screen_id: Option<OwnedScreenID>,
+    stderr_screen_id: Option<OwnedScreenID>,
 }
 
 impl ClientIdentity {
@@ -33,8 +50,8 @@ impl ClientIdentity {
     ///# use vt6::common::core::*;
     ///# use vt6::server::*;
     ///let identity = ClientIdentity::new(&ClientID::parse("example").unwrap())
-    ///    .with_stdin("foo")
-    ///    .with_stderr("bar");
+    ///    .with_stdin(&ScreenID::parse("foo").unwrap())
+    ///    .with_stderr(&ScreenID::parse("bar").unwrap());
     ///```
     pub fn new(id: &ClientID<'_>) -> Self {
         Self {
@@ -48,7 +65,7 @@ impl ClientIdentity {
     ///Sets the `stdin_screen_id()` property on this ClientIdentity. Chain this after `new()` if
     ///and only if the client's stdin is connected to the terminal (instead of to a different type
     ///of file descriptor).
-    pub fn with_stdin(self, screen_id: &str) -> ClientIdentity {
+    pub fn with_stdin(self, screen_id: &ScreenID<'_>) -> ClientIdentity {
         ClientIdentity {
             stdin_screen_id: Some(screen_id.into()),
             ..self
@@ -58,7 +75,7 @@ impl ClientIdentity {
     ///Sets the `stdout_screen_id()` property on this ClientIdentity. Chain this after `new()` if
     ///and only if the client's stdout is connected to the terminal (instead of to a different type
     ///of file descriptor).
-    pub fn with_stdout(self, screen_id: &str) -> ClientIdentity {
+    pub fn with_stdout(self, screen_id: &ScreenID<'_>) -> ClientIdentity {
         ClientIdentity {
             stdout_screen_id: Some(screen_id.into()),
             ..self
@@ -68,7 +85,7 @@ impl ClientIdentity {
     ///Sets the `stderr_screen_id()` property on this ClientIdentity. Chain this after `new()` if
     ///and only if the client's stderr is connected to the terminal (instead of to a different type
     ///of file descriptor).
-    pub fn with_stderr(self, screen_id: &str) -> ClientIdentity {
+    pub fn with_stderr(self, screen_id: &ScreenID<'_>) -> ClientIdentity {
         ClientIdentity {
             stderr_screen_id: Some(screen_id.into()),
             ..self
@@ -81,32 +98,69 @@ impl ClientIdentity {
     }
 
     ///Returns the ID of the screen that this client's stdin is connected to, if any.
-    pub fn stdin_screen_id(&self) -> Option<&str> {
+    pub fn stdin_screen_id(&self) -> Option<ScreenID<'_>> {
         self.stdin_screen_id.as_ref().map(|s| s.as_ref())
     }
 
     ///Returns the ID of the screen that this client's stdout is connected to, if any.
-    pub fn stdout_screen_id(&self) -> Option<&str> {
+    pub fn stdout_screen_id(&self) -> Option<ScreenID<'_>> {
         self.stdout_screen_id.as_ref().map(|s| s.as_ref())
     }
 
     ///Returns the ID of the screen that this client's stderr is connected to, if any.
-    pub fn stderr_screen_id(&self) -> Option<&str> {
+    pub fn stderr_screen_id(&self) -> Option<ScreenID<'_>> {
         self.stderr_screen_id.as_ref().map(|s| s.as_ref())
     }
 }
 
+impl PartialEq for ClientIdentity {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for ClientIdentity {}
+
+impl PartialOrd for ClientIdentity {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ClientIdentity {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
+impl std::hash::Hash for ClientIdentity {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
 ///Credentials issued for a client by the terminal.
+///
+///With the `use_serde` feature, this type implements `Serialize` and `Deserialize`. Since the
+///serialized form contains the secret, consider wrapping the credentials in
+///[Redacted](struct.Redacted.html) when serializing them for anything else than persistence.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "use_serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClientCredentials {
     secret: String,
 }
 
 impl ClientCredentials {
-    ///Generates a new ClientCredentials instance with a strongly random secret.
+    ///Generates a new ClientCredentials instance with a strongly random secret, using
+    ///[RandomSecrets](struct.RandomSecrets.html) with its default settings.
     pub fn generate() -> Self {
+        Self::generate_with(&RandomSecrets::default())
+    }
+
+    ///Generates a new ClientCredentials instance with a secret from the given provider.
+    pub fn generate_with<P: SecretProvider + ?Sized>(provider: &P) -> Self {
         Self {
-            secret: generate_secret(),
+            secret: provider.generate(),
         }
     }
 
@@ -114,6 +168,40 @@ impl ClientCredentials {
     pub fn secret(&self) -> &str {
         &self.secret
     }
+
+    ///Checks whether the given secret (usually from a client's handshake) matches this client's
+    ///secret. The comparison takes constant time, so Applications should use this in
+    ///`authorize_client()` instead of comparing the secrets with `==`.
+    ///
+    ///This uses the comparison of [RandomSecrets](struct.RandomSecrets.html). Applications that
+    ///use their own [SecretProvider](trait.SecretProvider.html) should call `verify_with()`
+    ///instead.
+    pub fn verify(&self, secret: &str) -> bool {
+        self.verify_with(&RandomSecrets::default(), secret)
+    }
+
+    ///Like `verify()`, but leaves the comparison to the given provider.
+    ///
+    ///```
+    ///# use vt6::server::{ClientCredentials, SecretProvider};
+    /////a provider whose secrets are case-insensitive
+    ///struct CaseInsensitive;
+    ///impl SecretProvider for CaseInsensitive {
+    ///    fn generate(&self) -> String {
+    ///        "Secret".into()
+    ///    }
+    ///    fn verify(&self, expected: &str, given: &str) -> bool {
+    ///        expected.eq_ignore_ascii_case(given)
+    ///    }
+    ///}
+    ///
+    ///let creds = ClientCredentials::generate_with(&CaseInsensitive);
+    ///assert!(creds.verify_with(&CaseInsensitive, "SECRET"));
+    ///assert!(!creds.verify("SECRET"));
+    ///```
+    pub fn verify_with<P: SecretProvider + ?Sized>(&self, provider: &P, secret: &str) -> bool {
+        provider.verify(&self.secret, secret)
+    }
 }
 
 ///Descriptor for a set of clients.
@@ -144,41 +232,102 @@ fn cid2bytes(cid: ClientID<'_>) -> &'_ [u8] {
     cid.as_str().as_bytes()
 }
 
+///Credentials of the process on the other end of a client connection, as reported by the
+///operating system (e.g. through `SO_PEERCRED` on Linux).
+///
+///The Dispatch captures these when accepting a connection, if the platform supports it. They are
+///available through [`Connection::peer_credentials()`](struct.Connection.html#method.peer_credentials)
+///and are passed into
+///[`Application::authorize_client()`](trait.Application.html#tymethod.authorize_client), e.g. to
+///only accept clients running under the same user as the terminal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerCredentials {
+    ///The effective user ID of the peer process.
+    pub uid: u32,
+    ///The effective group ID of the peer process.
+    pub gid: u32,
+    ///The process ID of the peer process, if the platform reports it.
+    pub pid: Option<i32>,
+}
+
+///What the Dispatch knows about a client connection that it has just accepted. This is passed into
+///[`Application::accept_connection()`](trait.Application.html#method.accept_connection).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerInfo<'a> {
+    ///The label of the listener that accepted the connection (see
+    ///[`Connection::listener()`](struct.Connection.html#method.listener)).
+    pub listener: Option<&'a str>,
+    ///The credentials of the peer process, if the Dispatch was able to obtain them.
+    pub credentials: Option<PeerCredentials>,
+    ///The number of client connections that the Dispatch holds already, not counting this one.
+    pub open_connections: usize,
+}
+
+///The result of [`Application::accept_connection()`](trait.Application.html#method.accept_connection).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AcceptDecision {
+    ///The connection is set up as usual.
+    Accept,
+    ///The connection is closed immediately, without reading from it.
+    Refuse,
+}
+
 ///Information identifying a screen.
 ///
 ///Screens are created either by the terminal itself (e.g. on startup) or in response to client
 ///messages. Either way, each screen is tracked as a ScreenIdentity instance (plus
 ///application-specific data) within the [Application](trait.Application.html).
-#[derive(Clone, Debug, PartialEq, Eq)]
+///
+///With the `use_serde` feature, this type implements `Serialize` and `Deserialize`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "use_serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ScreenIdentity {
-    id: String,
+    id: OwnedScreenID,
 }
 
 impl ScreenIdentity {
     ///Constructs a new ScreenIdentity.
-    pub fn new(id: &str) -> Self {
+    ///
+    ///```
+    ///# use vt6::common::core::*;
+    ///# use vt6::server::*;
+    ///let identity = ScreenIdentity::new(&ScreenID::parse("screen1").unwrap());
+    ///assert_eq!(identity.screen_id().as_str(), "screen1");
+    ///```
+    pub fn new(id: &ScreenID<'_>) -> Self {
         Self { id: id.into() }
     }
 
     ///Returns the ID of this screen.
-    pub fn screen_id(&self) -> &str {
-        &self.id
+    pub fn screen_id(&self) -> ScreenID<'_> {
+        self.id.as_ref()
     }
 }
 
 ///Credentials issued for a screen by the terminal.
+///
+///With the `use_serde` feature, this type implements `Serialize` and `Deserialize`. Since the
+///serialized form contains the secrets, consider wrapping the credentials in
+///[Redacted](struct.Redacted.html) when serializing them for anything else than persistence.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "use_serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ScreenCredentials {
     stdin_secret: String,
     stdout_secret: String,
 }
 
 impl ScreenCredentials {
-    ///Generates a new ClientCredentials instance with a strongly random secret.
+    ///Generates a new ScreenCredentials instance with strongly random secrets, using
+    ///[RandomSecrets](struct.RandomSecrets.html) with its default settings.
     pub fn generate() -> Self {
+        Self::generate_with(&RandomSecrets::default())
+    }
+
+    ///Generates a new ScreenCredentials instance with secrets from the given provider.
+    pub fn generate_with<P: SecretProvider + ?Sized>(provider: &P) -> Self {
         Self {
-            stdin_secret: generate_secret(),
-            stdout_secret: generate_secret(),
+            stdin_secret: provider.generate(),
+            stdout_secret: provider.generate(),
         }
     }
 
@@ -191,10 +340,304 @@ impl ScreenCredentials {
     pub fn stdout_secret(&self) -> &str {
         &self.stdout_secret
     }
+
+    ///Checks in constant time whether the given secret matches `stdin_secret()`. Like
+    ///[`ClientCredentials::verify()`](struct.ClientCredentials.html#method.verify), this uses the
+    ///comparison of [RandomSecrets](struct.RandomSecrets.html).
+    pub fn verify_stdin(&self, secret: &str) -> bool {
+        self.verify_stdin_with(&RandomSecrets::default(), secret)
+    }
+
+    ///Checks in constant time whether the given secret matches `stdout_secret()`. Like
+    ///[`ClientCredentials::verify()`](struct.ClientCredentials.html#method.verify), this uses the
+    ///comparison of [RandomSecrets](struct.RandomSecrets.html).
+    pub fn verify_stdout(&self, secret: &str) -> bool {
+        self.verify_stdout_with(&RandomSecrets::default(), secret)
+    }
+
+    ///Like `verify_stdin()`, but leaves the comparison to the given provider.
+    pub fn verify_stdin_with<P: SecretProvider + ?Sized>(
+        &self,
+        provider: &P,
+        secret: &str,
+    ) -> bool {
+        provider.verify(&self.stdin_secret, secret)
+    }
+
+    ///Like `verify_stdout()`, but leaves the comparison to the given provider.
+    pub fn verify_stdout_with<P: SecretProvider + ?Sized>(
+        &self,
+        provider: &P,
+        secret: &str,
+    ) -> bool {
+        provider.verify(&self.stdout_secret, secret)
+    }
+}
+
+///A source of secrets for [ClientCredentials](struct.ClientCredentials.html) and
+///[ScreenCredentials](struct.ScreenCredentials.html).
+///
+///The default implementation is [RandomSecrets](struct.RandomSecrets.html). Applications can
+///provide their own implementation, e.g. to derive secrets from a key management system, and
+///pass it into `ClientCredentials::generate_with()` and `ScreenCredentials::generate_with()`, as
+///well as into the respective `verify_with()` methods when checking a client's secret.
+pub trait SecretProvider {
+    ///Generates a new secret. Since secrets are sent in messages, they must not be empty.
+    fn generate(&self) -> String;
+
+    ///Checks whether the secret given by a client matches the expected secret. The default
+    ///implementation compares the secrets in constant time (except for their length, which is
+    ///not considered secret), so that timing does not reveal how much of a guess was correct.
+    fn verify(&self, expected: &str, given: &str) -> bool {
+        let (expected, given) = (expected.as_bytes(), given.as_bytes());
+        if expected.len() != given.len() {
+            return false;
+        }
+        let diff = expected
+            .iter()
+            .zip(given)
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        diff == 0
+    }
+}
+
+///The default [SecretProvider](trait.SecretProvider.html), which generates strongly random
+///secrets from the operating system's random number generator.
+///
+///By default, secrets consist of 32 characters from the URL-safe base64 alphabet, which amounts
+///to 192 bits of entropy. Both the length and the alphabet can be configured. Each character is
+///chosen uniformly from the alphabet.
+///
+///```
+///# use vt6::server::{RandomSecrets, SecretProvider};
+///let provider = RandomSecrets::default()
+///    .with_length(16)
+///    .with_alphabet("0123456789abcdef");
+///let secret = provider.generate();
+///assert_eq!(secret.len(), 16);
+///assert!(secret.chars().all(|c| c.is_ascii_hexdigit()));
+///assert!(provider.verify(&secret, &secret));
+///assert!(!provider.verify(&secret, "0000000000000000"));
+///```
+#[derive(Clone, Debug)]
+pub struct RandomSecrets {
+    length: usize,
+    alphabet: Cow<'static, str>,
+}
+
+const BASE64_URL_ALPHABET: &str =
+    "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+impl Default for RandomSecrets {
+    fn default() -> Self {
+        Self {
+            length: 32,
+            alphabet: Cow::Borrowed(BASE64_URL_ALPHABET),
+        }
+    }
+}
+
+impl RandomSecrets {
+    ///Sets the number of characters in each secret.
+    ///
+    ///Panics if the length is zero.
+    pub fn with_length(mut self, length: usize) -> Self {
+        assert!(length > 0, "secrets must not be empty");
+        self.length = length;
+        self
+    }
+
+    ///Sets the characters that secrets are made of. Since secrets are transmitted as message
+    ///arguments and usually passed to child processes through the environment, the alphabet is
+    ///restricted to printable ASCII characters.
+    ///
+    ///Panics if the alphabet is empty, contains anything other than printable ASCII characters,
+    ///or contains more than 256 characters. Also panics if any character appears more than once,
+    ///since that character would then be more likely than the others, which weakens the secrets.
+    ///
+    ///```should_panic
+    ///# use vt6::server::RandomSecrets;
+    ///let provider = RandomSecrets::default().with_alphabet("0123456789abcdefa");
+    ///```
+    pub fn with_alphabet<S: Into<Cow<'static, str>>>(mut self, alphabet: S) -> Self {
+        let alphabet = alphabet.into();
+        assert!(
+            !alphabet.is_empty()
+                && alphabet.len() <= 256
+                && alphabet.bytes().all(|b| b.is_ascii_graphic()),
+            "secret alphabet must consist of printable ASCII characters"
+        );
+        let mut seen = [false; 128];
+        for b in alphabet.bytes() {
+            assert!(
+                !std::mem::replace(&mut seen[b as usize], true),
+                "secret alphabet contains {:?} more than once",
+                b as char
+            );
+        }
+        self.alphabet = alphabet;
+        self
+    }
+}
+
+impl SecretProvider for RandomSecrets {
+    fn generate(&self) -> String {
+        let alphabet = self.alphabet.as_bytes();
+        //to avoid modulo bias, discard random bytes that fall into the incomplete last round of
+        //the alphabet
+        let limit = 256 - 256 % alphabet.len();
+        let mut secret = String::with_capacity(self.length);
+        let mut buf = [0u8; 64];
+        while secret.len() < self.length {
+            getrandom::getrandom(&mut buf).unwrap();
+            for &b in buf.iter().filter(|&&b| (b as usize) < limit) {
+                if secret.len() == self.length {
+                    break;
+                }
+                secret.push(alphabet[b as usize % alphabet.len()] as char);
+            }
+        }
+        secret
+    }
+}
+
+///A wrapper for serializing credentials without their secrets. This is only available with the
+///`use_serde` feature.
+///
+///The serialized form has the same structure as that of the wrapped credentials, but each secret
+///is replaced by the string `"<redacted>"`. This is useful when credentials shall appear in logs or
+///debugging dumps. Redacted credentials cannot be deserialized back into usable credentials.
+///
+///```
+///# use vt6::server::*;
+///let creds = ClientCredentials::generate();
+///let json = serde_json::to_string(&Redacted(&creds)).unwrap();
+///assert_eq!(json, r#"{"secret":"<redacted>"}"#);
+///
+///let creds = ScreenCredentials::generate();
+///let json = serde_json::to_string(&Redacted(&creds)).unwrap();
+///assert_eq!(json, r#"{"stdin_secret":"<redacted>","stdout_secret":"<redacted>"}"#);
+///```
+#[cfg(feature = "use_serde")]
+#[derive(Clone, Copy, Debug)]
+pub struct Redacted<'a, T>(pub &'a T);
+
+#[cfg(feature = "use_serde")]
+const REDACTED_SECRET: &str = "<redacted>";
+
+#[cfg(feature = "use_serde")]
+impl<'a> serde::Serialize for Redacted<'a, ClientCredentials> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct("ClientCredentials", 1)?;
+        s.serialize_field("secret", REDACTED_SECRET)?;
+        s.end()
+    }
 }
 
-fn generate_secret() -> String {
-    let mut buf1 = [0u8; 24];
-    getrandom::getrandom(&mut buf1).unwrap();
-    base64::encode_config(&buf1, base64::URL_SAFE)
+#[cfg(feature = "use_serde")]
+impl<'a> serde::Serialize for Redacted<'a, ScreenCredentials> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct("ScreenCredentials", 2)?;
+        s.serialize_field("stdin_secret", REDACTED_SECRET)?;
+        s.serialize_field("stdout_secret", REDACTED_SECRET)?;
+        s.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_secrets() {
+        let provider = RandomSecrets::default();
+        assert!(provider.verify("abcd", "abcd"));
+        assert!(!provider.verify("abcd", "abc"));
+        assert!(!provider.verify("abcd", "abcde"));
+        assert!(!provider.verify("abcd", "abce"));
+        assert!(!provider.verify("abcd", "Abcd"));
+
+        let creds = ClientCredentials::generate_with(&provider);
+        assert!(creds.verify_with(&provider, creds.secret()));
+        assert!(!creds.verify_with(&provider, &creds.secret()[1..]));
+    }
+
+    #[test]
+    fn test_alphabet_validation() {
+        let check = |alphabet: &'static str| {
+            std::panic::catch_unwind(|| RandomSecrets::default().with_alphabet(alphabet)).is_ok()
+        };
+        assert!(check("ab"));
+        assert!(!check(""));
+        assert!(!check("aba"));
+        assert!(!check("0123456789abcdefa"));
+        assert!(!check("a b"));
+    }
+
+    #[test]
+    fn test_generated_secrets() {
+        let provider = RandomSecrets::default();
+        let secret = provider.generate();
+        assert_eq!(secret.len(), 32);
+        assert!(secret.chars().all(|c| BASE64_URL_ALPHABET.contains(c)));
+
+        let provider = RandomSecrets::default()
+            .with_length(200)
+            .with_alphabet("xyz");
+        for _ in 0..10 {
+            let secret = provider.generate();
+            assert_eq!(secret.len(), 200);
+            assert!(secret.chars().all(|c| "xyz".contains(c)));
+        }
+
+        let creds = ScreenCredentials::generate_with(&provider);
+        assert_eq!(creds.stdin_secret().len(), 200);
+        assert_eq!(creds.stdout_secret().len(), 200);
+    }
+
+    #[cfg(feature = "use_serde")]
+    fn roundtrip<T: serde::Serialize + serde::de::DeserializeOwned>(value: &T) -> T {
+        let json = serde_json::to_string(value).unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "use_serde")]
+    fn test_serde_identities() {
+        let screen_id = ScreenID::parse("screen1").unwrap();
+        let identity = ClientIdentity::new(&ClientID::parse("a1").unwrap())
+            .with_stdin(&screen_id)
+            .with_stderr(&ScreenID::parse("screen2").unwrap());
+        let identity2 = roundtrip(&identity);
+        assert_eq!(identity2.client_id(), identity.client_id());
+        assert_eq!(identity2.stdin_screen_id(), Some(screen_id));
+        assert_eq!(identity2.stdout_screen_id(), None);
+        assert_eq!(identity2.stderr_screen_id(), ScreenID::parse("screen2"));
+
+        let identity = ScreenIdentity::new(&screen_id);
+        assert_eq!(roundtrip(&identity), identity);
+
+        //invalid IDs are rejected when deserializing
+        let json = r#"{"id":"a-1","stdin_screen_id":null,"stdout_screen_id":null,"stderr_screen_id":null}"#;
+        assert!(serde_json::from_str::<ClientIdentity>(json).is_err());
+        let json = r#"{"id":"a1","stdin_screen_id":"screen 1","stdout_screen_id":null,"stderr_screen_id":null}"#;
+        assert!(serde_json::from_str::<ClientIdentity>(json).is_err());
+        assert!(serde_json::from_str::<ScreenIdentity>(r#"{"id":"screen 1"}"#).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "use_serde")]
+    fn test_serde_credentials() {
+        let creds = ClientCredentials::generate();
+        let creds2 = roundtrip(&creds);
+        assert_eq!(creds2.secret(), creds.secret());
+        assert!(creds2.verify(creds.secret()));
+
+        let creds = ScreenCredentials::generate();
+        let creds2 = roundtrip(&creds);
+        assert_eq!(creds2.stdin_secret(), creds.stdin_secret());
+        assert_eq!(creds2.stdout_secret(), creds.stdout_secret());
+    }
 }
@@ -0,0 +1,200 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg::DecodeMessage;
+use crate::common::core::{msg, ModuleIdentifier, ScopedIdentifier};
+use crate::msg::clipboard::{Content, Get, Set, MAX_TEXT_BYTES};
+use crate::server;
+use crate::server::HandlerError::{InvalidMessage, PermissionDenied};
+use crate::server::MessageConnector;
+
+///Describes which kinds of clipboard access a client is allowed. Returned by
+///[`Application::clipboard_policy()`](../trait.Application.html#method.clipboard_policy).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ClipboardPolicy {
+    ///Whether the client may read the clipboard with `clip1.get`.
+    pub read: bool,
+    ///Whether the client may write the clipboard with `clip1.set`.
+    pub write: bool,
+}
+
+impl ClipboardPolicy {
+    ///A policy that does not allow any access.
+    pub const DENY: Self = Self {
+        read: false,
+        write: false,
+    };
+    ///A policy that allows writing, but not reading. This is the default for most terminals that
+    ///support OSC 52, since reading the clipboard may expose secrets (e.g. passwords) to programs
+    ///that should not see them.
+    pub const WRITE_ONLY: Self = Self {
+        read: false,
+        write: true,
+    };
+    ///A policy that allows reading and writing.
+    pub const READ_WRITE: Self = Self {
+        read: true,
+        write: true,
+    };
+}
+
+///A [MessageHandler](../trait.MessageHandler.html) for the `clip1` module, which gives clients
+///access to the clipboard. See [vt6::msg::clipboard](../../msg/clipboard/index.html) for the
+///message types.
+///
+///This handler processes `clip1.set` and `clip1.get` messages. Access is checked against
+///[`Application::clipboard_policy()`](../trait.Application.html#method.clipboard_policy) for the
+///client that sent the message; disallowed requests are answered with `nope`. The clipboard
+///itself is stored by the application through
+///[`Application::clipboard_text()`](../trait.Application.html#method.clipboard_text) and
+///[`Application::set_clipboard_text()`](../trait.Application.html#method.set_clipboard_text).
+#[derive(Default)]
+pub struct MessageHandler<Next>(Next);
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::MessageHandler<A>
+    for MessageHandler<Next>
+{
+    fn get_supported_module_version(&self, module: &ModuleIdentifier<'_>) -> Option<u16> {
+        match module.as_str() {
+            "clip1" => Some(0),
+            _ => self.0.get_supported_module_version(module),
+        }
+    }
+
+    fn get_module_deprecation(
+        &self,
+        module: &ModuleIdentifier<'_>,
+    ) -> Option<server::ModuleDeprecation> {
+        self.0.get_module_deprecation(module)
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::Handler<A>
+    for MessageHandler<Next>
+{
+    fn handle<D: server::Dispatch<A>>(
+        &self,
+        msg: &msg::Message,
+        conn: &mut server::Connection<A, D>,
+    ) -> Result<(), server::HandlerError> {
+        match msg.parsed_type().as_str() {
+            "clip1.set" => {
+                let msg = Set::decode_message(msg).ok_or(InvalidMessage)?;
+                let d = conn.dispatch();
+                let identity = conn.message_connector().unwrap().identity();
+                if !d.application().clipboard_policy(identity).write {
+                    return Err(PermissionDenied);
+                }
+                d.application().set_clipboard_text(msg.selection, msg.text);
+                conn.enqueue_message(&msg);
+                Ok(())
+            }
+            "clip1.get" => {
+                let msg = Get::decode_message(msg).ok_or(InvalidMessage)?;
+                let d = conn.dispatch();
+                let identity = conn.message_connector().unwrap().identity();
+                if !d.application().clipboard_policy(identity).read {
+                    return Err(PermissionDenied);
+                }
+                let text = d
+                    .application()
+                    .clipboard_text(msg.selection)
+                    .filter(|text| text.len() <= MAX_TEXT_BYTES)
+                    .ok_or(InvalidMessage)?;
+                conn.enqueue_message(&Content {
+                    selection: msg.selection,
+                    text: &text,
+                });
+                Ok(())
+            }
+            _ => self.0.handle(msg, conn),
+        }
+    }
+
+    fn handle_error<D: server::Dispatch<A>>(
+        &self,
+        err: &msg::ParseError,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.handle_error(err, conn);
+    }
+
+    fn on_connect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_connect(conn);
+    }
+
+    fn on_state_change<D: server::Dispatch<A>>(
+        &self,
+        old_state: &server::ConnectionState<A>,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.on_state_change(old_state, conn);
+    }
+
+    fn on_disconnect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_disconnect(conn);
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>>
+    server::core::MessageHandlerExt<A> for MessageHandler<Next>
+{
+    fn handle_property<D: server::Dispatch<A>>(
+        &self,
+        name: &ScopedIdentifier<'_>,
+        requested_value: Option<&[u8]>,
+        conn: &mut server::Connection<A, D>,
+    ) -> Option<Vec<u8>> {
+        self.0.handle_property(name, requested_value, conn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::ClientID;
+    use crate::server::testing::{Conversation, MockApplication, MockHandlers};
+    use crate::server::ClientIdentity;
+
+    struct ClipboardHandlers;
+
+    impl MockHandlers for ClipboardHandlers {
+        type MessageHandler = server::core::MessageHandler<MessageHandler<server::RejectHandler>>;
+    }
+
+    #[test]
+    fn test_clipboard_access() {
+        let app: MockApplication<ClipboardHandlers> = MockApplication::new();
+        let client_id = ClientID::parse("a").unwrap();
+        let creds = server::Application::register_client(&app, ClientIdentity::new(&client_id));
+        let mut conv = Conversation::new(app.clone());
+        conv.send_message(&crate::msg::posix::ClientHello {
+            secret: creds.secret(),
+        })
+        .expect(r#"(posix1.server-hello a "" "" "")"#)
+        .send(b"{2|4:want,5:clip1,}")
+        .expect("(have clip1.0)");
+
+        //by default, clients may write, but not read the clipboard
+        conv.send(b"{3|9:clip1.set,9:clipboard,5:hello,}")
+            .expect("(clip1.set clipboard hello)")
+            .send(b"{2|9:clip1.get,9:clipboard,}")
+            .expect("(nope clip1.get)");
+        let text =
+            server::Application::clipboard_text(&app, crate::msg::clipboard::Selection::Clipboard);
+        assert_eq!(text.as_deref(), Some("hello"));
+
+        //the policy is chosen per client
+        app.set_clipboard_policy(client_id, ClipboardPolicy::READ_WRITE);
+        conv.send(b"{2|9:clip1.get,9:clipboard,}")
+            .expect("(clip1.content clipboard hello)")
+            .send(b"{2|9:clip1.get,7:primary,}")
+            .expect("(nope clip1.get)");
+        app.set_clipboard_policy(client_id, ClipboardPolicy::DENY);
+        conv.send(b"{3|9:clip1.set,7:primary,5:hello,}")
+            .expect("(nope clip1.set)");
+    }
+}
@@ -4,10 +4,19 @@
 * Refer to the file "LICENSE" for details.
 *******************************************************************************/
 
-use crate::common::core::{msg, MessageType};
+use crate::common::core::{
+    msg, MessageType, ModuleIdentifier, OwnedModuleIdentifier, ScopedIdentifier,
+};
+use crate::common::io::FixedBuffer;
+#[cfg(feature = "module_frame")]
+use crate::common::{decode_frame, Framed, FRAME_HEADER_LEN};
+#[cfg(feature = "module_sig")]
+use crate::msg::sig::Signal;
 use crate::msg::{Have, Nope};
 use crate::server;
-use crate::server::{Handler, MessageHandler};
+use crate::server::{Handler, MessageHandler, OutgoingAction};
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
 
 ///State machine for a client socket.
 #[derive(Debug)]
@@ -21,8 +30,12 @@ pub enum ConnectionState<A: server::Application> {
     Stdin(server::ScreenIdentity),
     ///This socket is in stdout mode because of a successful stdout-hello message.
     Stdout(A::StdoutConnector),
+    ///This socket is in stdout mode for multiple screens because of a successful stdout-mux-hello
+    ///message.
+    StdoutMux(server::StdoutMux<A::StdoutConnector>),
     ///This socket is currently being torn down. No further IO shall be performed on the socket and
-    ///all resources relating to it shall be released.
+    ///all resources relating to it shall be released. The reason is available through
+    ///[`Connection::teardown_reason()`](struct.Connection.html#method.teardown_reason).
     Teardown,
 }
 
@@ -39,6 +52,7 @@ impl<A: server::Application> ConnectionState<A> {
             Self::Msgio(_) => "Msgio",
             Self::Stdin(_) => "Stdin",
             Self::Stdout(_) => "Stdout",
+            Self::StdoutMux(_) => "StdoutMux",
             Self::Teardown => "Teardown",
         }
     }
@@ -59,13 +73,114 @@ impl<A: server::Application> ConnectionState<A> {
     pub fn can_receive_stdin_for_screen(&self, id: &server::ScreenIdentity) -> bool {
         matches!(self, Self::Stdin(ref my_id) if my_id == id)
     }
+
+    ///Checks whether a connection in this state may go into the given state. A connection leaves
+    ///`Handshake` for one of the other states, and any connection may go into `Teardown`. Going
+    ///into `Teardown` repeatedly is allowed and has no effect.
+    pub fn can_transition_to(&self, next: &ConnectionState<A>) -> bool {
+        matches!(
+            (self, next),
+            (Self::Handshake, Self::Msgio(_))
+                | (Self::Handshake, Self::Stdin(_))
+                | (Self::Handshake, Self::Stdout(_))
+                | (Self::Handshake, Self::StdoutMux(_))
+                | (_, Self::Teardown)
+        )
+    }
+}
+
+///Why a [Connection](struct.Connection.html) went into `Teardown` state. This is recorded by
+///[`Connection::tear_down()`](struct.Connection.html#method.tear_down) and reported in
+///[`Notification::ConnectionClosed`](enum.Notification.html#variant.ConnectionClosed).
+///
+///New versions of this library can add new variants to this enum at any time. Applications should
+///always have a catch-all branch when matching on variants of this enum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TeardownReason {
+    ///The client closed the connection.
+    ClientDisconnected,
+    ///Reading from or writing to the socket failed.
+    IOError,
+    ///The client sent input that is not allowed in the connection's state, e.g. a failed
+    ///handshake or anything at all on a stdin connection.
+    ProtocolViolation,
+    ///The Application refused stdout sent by the client (or the echo of stdin).
+    StdoutRefused,
+    ///Another connection took over the screen attachment of this connection.
+    DisplacedByTakeover,
+    ///The lifetime of the client ended through a `core1.client-end` message.
+    LifetimeEnded,
+    ///The client did not read from its socket within the Dispatch's write timeout.
+    WriteTimeout,
+    ///The connection had the most data waiting to be sent when the send buffer limit of the
+    ///Dispatch was exceeded.
+    SendBufferLimitExceeded,
+    ///The Dispatch is shutting down.
+    ServerShutdown,
+    ///A panic occurred while the connection was being worked on, so its state cannot be trusted
+    ///anymore.
+    Panicked,
+    ///The connection was put into `Teardown` state with
+    ///[`set_state()`](struct.Connection.html#method.set_state) instead of `tear_down()`, so the
+    ///reason is not known.
+    Unspecified,
+}
+
+impl std::fmt::Display for TeardownReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msg = match *self {
+            Self::ClientDisconnected => "client disconnected",
+            Self::IOError => "IO error",
+            Self::ProtocolViolation => "protocol violation",
+            Self::StdoutRefused => "stdout refused",
+            Self::DisplacedByTakeover => "displaced by takeover",
+            Self::LifetimeEnded => "client lifetime ended",
+            Self::WriteTimeout => "write timeout",
+            Self::SendBufferLimitExceeded => "send buffer limit exceeded",
+            Self::ServerShutdown => "server shutdown",
+            Self::Panicked => "panic while processing connection",
+            Self::Unspecified => "unspecified reason",
+        };
+        f.write_str(msg)
+    }
+}
+
+///Error type for [`Connection::try_transition()`](struct.Connection.html#method.try_transition).
+///Contains the [type names](enum.ConnectionState.html#method.type_name) of the connection's
+///current state and of the rejected state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidTransition {
+    pub from: &'static str,
+    pub to: &'static str,
+}
+
+impl std::fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "connection cannot go from state {} into state {}",
+            self.from, self.to
+        )
+    }
 }
 
+impl std::error::Error for InvalidTransition {}
+
 ///Generic interface for a receive buffer.
 ///
 ///The actual buffer type is tied to the concrete [Dispatch](trait.Dispatch.html) and
 ///instances are created and filled by it. The Dispatch then calls `handle_incoming` on the
-///[Connection](struct.Connection.html) to process the contents of the receive buffer.
+///[Connection](struct.Connection.html) to process the contents of the receive buffer. This
+///crate implements it for [vt6::common::io::FixedBuffer](../common/io/struct.FixedBuffer.html)
+///and for `Vec<u8>`, which custom Dispatch implementations can use instead of writing their own
+///buffer type.
+///
+///`handle_incoming()` only consumes complete messages and leaves an incomplete message at the
+///start of the buffer until the rest of it has been received. The buffer must therefore be able
+///to hold at least one complete message, i.e. 1024 bytes
+///[\[vt6/foundation, sect. 3.1.2\]](https://vt6.io/std/foundation/#section-3-1-2), plus the
+///frame header for framed connections. Otherwise, a connection that sends a long message gets
+///stuck.
 pub trait ReceiveBuffer {
     ///Returns a reference to the filled part of the buffer.
     fn contents(&self) -> &[u8];
@@ -74,6 +189,24 @@ pub trait ReceiveBuffer {
     fn discard(&mut self, len: usize);
 }
 
+impl ReceiveBuffer for Vec<u8> {
+    fn contents(&self) -> &[u8] {
+        self
+    }
+    fn discard(&mut self, len: usize) {
+        self.drain(0..len);
+    }
+}
+
+impl<const N: usize> ReceiveBuffer for FixedBuffer<N> {
+    fn contents(&self) -> &[u8] {
+        self.filled()
+    }
+    fn discard(&mut self, len: usize) {
+        FixedBuffer::discard(self, len);
+    }
+}
+
 //A simple helper object containing one of the handlers associated with A, depending on which
 //connection state we're currently in. This is only used inside Connection::handle_incoming_msgio().
 //That method used to take the concrete Handler as a type argument, but if we only have a type
@@ -93,21 +226,94 @@ impl<A: server::Application> HandlerObj<A> {
     }
 }
 
+//Checks a replacement given to Connection::substitute_input().
+fn is_single_message(buf: &[u8]) -> bool {
+    matches!(msg::Message::parse(buf), Ok((_, len)) if len == buf.len())
+}
+
 ///A single client connection to the server socket.
 pub struct Connection<A: server::Application, D: server::Dispatch<A>> {
     dispatch: D,
     id: D::ConnectionID,
+    listener: Option<String>,
+    peer_credentials: Option<server::PeerCredentials>,
     state: ConnectionState<A>,
+    line_discipline: Option<server::LineDiscipline>,
+    egress_shaping: server::EgressShaping,
+    stdin_padded: bool,
+    subscriptions: HashSet<String>,
+    module_subscriptions: HashSet<String>,
+    ///Results of module negotiations: module identifier -> agreed minor version, or `None` if
+    ///refused.
+    negotiated_modules: HashMap<OwnedModuleIdentifier, Option<u16>>,
+    #[cfg(feature = "module_sig")]
+    claimed_signals: HashSet<Signal>,
+    #[cfg(feature = "module_frame")]
+    framed: bool,
+    legacy_names: bool,
+    ///Set by handlers during `handle_error()`, see substitute_input().
+    input_recovery: Option<InputRecovery>,
+    is_reading_paused: bool,
+    teardown_reason: Option<TeardownReason>,
+    ///How many errors occurred during the handshake.
+    handshake_errors: usize,
+    ///The offset of the start of the receive buffer within the stream of bytes received so far.
+    input_offset: u64,
+    ///Discarded input that has not been reported in a notification yet.
+    discarded: server::DiscardedBytes,
+    discard_notified_at: Option<Instant>,
+    stats: server::ConnectionStats,
+    recording: Option<Recording>,
+}
+
+//How a handler has dealt with the input that caused a parse error.
+enum InputRecovery {
+    Substitute(usize, Vec<u8>),
+    AwaitMore,
+}
+
+struct Recording {
+    started: Instant,
+    log: server::SessionLog,
+    ///The offset within the stream of received bytes up to which input has been recorded.
+    recorded_upto: u64,
 }
 
 impl<A: server::Application, D: server::Dispatch<A>> Connection<A, D> {
     ///Creates a new connection. This interface is usually only called by the Dispatch when
     ///accepting a client connection to the server socket.
+    ///
+    ///The connection starts out in the `Handshake` state. Before the first input is handed to
+    ///`handle_incoming()`, the Dispatch must make the connection reachable through its `id` for
+    ///`enqueue_message()` and broadcasts, and then call `handle_connect()`. See the documentation
+    ///of [trait Dispatch](trait.Dispatch.html#implementing-a-dispatch) for the full lifecycle.
     pub fn new(dispatch: D, id: D::ConnectionID) -> Self {
         Self {
             dispatch,
             id,
+            listener: None,
+            peer_credentials: None,
             state: ConnectionState::Handshake,
+            line_discipline: None,
+            egress_shaping: Default::default(),
+            stdin_padded: false,
+            subscriptions: HashSet::new(),
+            module_subscriptions: HashSet::new(),
+            negotiated_modules: HashMap::new(),
+            #[cfg(feature = "module_sig")]
+            claimed_signals: HashSet::new(),
+            #[cfg(feature = "module_frame")]
+            framed: false,
+            legacy_names: false,
+            input_recovery: None,
+            is_reading_paused: false,
+            teardown_reason: None,
+            handshake_errors: 0,
+            input_offset: 0,
+            discarded: Default::default(),
+            discard_notified_at: None,
+            stats: server::ConnectionStats::new(ConnectionState::<A>::Handshake.type_name()),
+            recording: None,
         }
     }
 
@@ -123,6 +329,61 @@ impl<A: server::Application, D: server::Dispatch<A>> Connection<A, D> {
         self.id.clone()
     }
 
+    ///Sets the label of the listener that accepted this connection. This is usually only called by
+    ///the Dispatch, when it accepts connections on multiple server sockets. Chain this after
+    ///`new()`.
+    pub fn with_listener(self, label: &str) -> Self {
+        Self {
+            listener: Some(label.into()),
+            ..self
+        }
+    }
+
+    ///Returns the label of the listener that accepted this connection, if the Dispatch has set
+    ///one. This label is also included in [Notifications](enum.Notification.html) concerning this
+    ///connection.
+    pub fn listener(&self) -> Option<&str> {
+        self.listener.as_deref()
+    }
+
+    ///Sets the credentials of the peer process. This is usually only called by the Dispatch, when
+    ///the platform allows it to obtain them for an accepted connection. Chain this after `new()`.
+    pub fn with_peer_credentials(self, creds: server::PeerCredentials) -> Self {
+        Self {
+            peer_credentials: Some(creds),
+            ..self
+        }
+    }
+
+    ///Returns the credentials of the process on the other end of this connection, if the Dispatch
+    ///has obtained them.
+    pub fn peer_credentials(&self) -> Option<&server::PeerCredentials> {
+        self.peer_credentials.as_ref()
+    }
+
+    ///Asks the Dispatch to stop reading from this connection's socket until `resume_reading()` is
+    ///called. Input that has already been read is still processed.
+    ///
+    ///This provides backpressure: While reading is paused, the client's writes fill up the socket
+    ///buffer and then block, so a client that produces output faster than the application can
+    ///display it is slowed down instead of making the server buffer unbounded amounts of data.
+    ///For stdout connections, this is usually triggered through
+    ///[`StdoutConnector::is_ready()`](trait.StdoutConnector.html#method.is_ready).
+    pub fn pause_reading(&mut self) {
+        self.is_reading_paused = true;
+    }
+
+    ///Lets the Dispatch continue reading from this connection's socket after `pause_reading()`.
+    pub fn resume_reading(&mut self) {
+        self.is_reading_paused = false;
+    }
+
+    ///Returns whether reading from this connection's socket has been paused with
+    ///`pause_reading()`. Dispatch implementations check this before reading from the socket.
+    pub fn is_reading_paused(&self) -> bool {
+        self.is_reading_paused
+    }
+
     ///Returns the current state of this connection.
     pub fn state(&self) -> &ConnectionState<A> {
         &self.state
@@ -132,8 +393,144 @@ impl<A: server::Application, D: server::Dispatch<A>> Connection<A, D> {
     ///the socket from handshake mode into msgio, stdin or stdout mode. Also, any handler wishing
     ///to dismantle the connection (e.g. because of a fatal error) can use this method to set the
     ///socket in teardown mode, which will cause the dispatch to shut down the connection.
+    ///
+    ///The [lifecycle methods](trait.Handler.html#method.on_state_change) of the Application's
+    ///handlers are called after the state has changed.
+    ///
+    ///This method does not check whether the transition makes sense. Handlers should use
+    ///[try_transition()](#method.try_transition) instead.
     pub fn set_state(&mut self, state: ConnectionState<A>) {
-        self.state = state;
+        let mut old_state = std::mem::replace(&mut self.state, state);
+        #[cfg(feature = "use_tracing")]
+        tracing::debug!(
+            from = old_state.type_name(),
+            to = self.state.type_name(),
+            "connection changed state"
+        );
+        self.stats.state_transitions.push(server::StateTransition {
+            at: Instant::now(),
+            state: self.state.type_name(),
+        });
+        self.update_attachments(&old_state);
+        //the old StdoutConnector is dropped at the end of this method
+        match old_state {
+            ConnectionState::Stdout(ref mut c) => server::StdoutConnector::finish(c),
+            ConnectionState::StdoutMux(ref mut mux) => mux.finish(),
+            _ => {}
+        }
+        let is_disconnect = matches!(self.state, ConnectionState::Teardown)
+            && !matches!(old_state, ConnectionState::Teardown);
+        if is_disconnect {
+            self.teardown_reason
+                .get_or_insert(TeardownReason::Unspecified);
+            //this is the last chance to report discarded input that was held back
+            self.notify_discarded_input();
+            if !self.subscriptions.is_empty() || !self.module_subscriptions.is_empty() {
+                self.dispatch.subscriptions().unsubscribe_all(&self.id);
+            }
+        }
+
+        A::HandshakeHandler::default().on_state_change(&old_state, self);
+        A::MessageHandler::default().on_state_change(&old_state, self);
+        if is_disconnect {
+            A::HandshakeHandler::default().on_disconnect(self);
+            A::MessageHandler::default().on_disconnect(self);
+        }
+    }
+
+    ///Puts the connection into `Teardown` state, and records why. If the connection is in
+    ///`Teardown` state already, the original reason is kept.
+    pub fn tear_down(&mut self, reason: TeardownReason) {
+        if !matches!(self.state, ConnectionState::Teardown) {
+            self.teardown_reason = Some(reason);
+            self.set_state(ConnectionState::Teardown);
+        }
+    }
+
+    ///Returns why the connection went into `Teardown` state, or `None` if it has not.
+    pub fn teardown_reason(&self) -> Option<TeardownReason> {
+        self.teardown_reason
+    }
+
+    ///Like [set_state()](#method.set_state), but rejects transitions that are not allowed by
+    ///[`ConnectionState::can_transition_to()`](enum.ConnectionState.html#method.can_transition_to),
+    ///e.g. from `Stdout` back into `Handshake`. A rejected transition leaves the state unchanged
+    ///and is reported to the Application with an `InvalidStateTransition` notification.
+    pub fn try_transition(&mut self, state: ConnectionState<A>) -> Result<(), InvalidTransition> {
+        if !self.state.can_transition_to(&state) {
+            let err = InvalidTransition {
+                from: self.state.type_name(),
+                to: state.type_name(),
+            };
+            let n = server::Notification::InvalidStateTransition {
+                listener: self.listener(),
+                from: err.from,
+                to: err.to,
+            };
+            self.dispatch.notify(&n);
+            return Err(err);
+        }
+        self.set_state(state);
+        Ok(())
+    }
+
+    //Keeps the Dispatch's attachment registry in sync with a state change, and tells the
+    //Application when a screen's stdin or stdout has been released. (When another connection has
+    //taken over the screen in the meantime, the attachment is not ours anymore, so nothing is
+    //released.)
+    fn update_attachments(&self, old_state: &ConnectionState<A>) {
+        use server::StdoutConnector;
+        fn attachments_of<A: server::Application>(
+            state: &ConnectionState<A>,
+        ) -> Vec<(&server::ScreenIdentity, server::AttachmentKind)> {
+            match *state {
+                ConnectionState::Stdin(ref screen) => vec![(screen, server::AttachmentKind::Stdin)],
+                ConnectionState::Stdout(ref c) => c
+                    .identity()
+                    .map(|screen| (screen, server::AttachmentKind::Stdout))
+                    .into_iter()
+                    .collect(),
+                ConnectionState::StdoutMux(ref mux) => mux
+                    .connectors()
+                    .iter()
+                    .filter_map(|c| c.identity())
+                    .map(|screen| (screen, server::AttachmentKind::Stdout))
+                    .collect(),
+                _ => Vec::new(),
+            }
+        }
+        let attachments = self.dispatch.attachments();
+        for (screen, kind) in attachments_of(old_state) {
+            if attachments.detach(screen, kind, &self.id) {
+                let app = self.dispatch.application();
+                match kind {
+                    server::AttachmentKind::Stdin => app.detach_stdin(screen),
+                    server::AttachmentKind::Stdout => app.detach_stdout(screen),
+                }
+            }
+        }
+        for (screen, kind) in attachments_of(&self.state) {
+            attachments.attach(screen, kind, self.id.clone());
+        }
+    }
+
+    ///Reports this connection to the [lifecycle methods](trait.Handler.html#method.on_connect) of
+    ///the Application's handlers. This interface is called by the Dispatch once after accepting
+    ///the connection, as soon as messages can be enqueued on it.
+    pub fn handle_connect(&mut self) {
+        A::HandshakeHandler::default().on_connect(self);
+        A::MessageHandler::default().on_connect(self);
+    }
+
+    ///Returns statistics for this connection.
+    pub fn stats(&self) -> &server::ConnectionStats {
+        &self.stats
+    }
+
+    ///Returns a mutable reference to the statistics for this connection. This is used by the
+    ///Dispatch to maintain the byte counts, and should not be needed elsewhere.
+    pub fn stats_mut(&mut self) -> &mut server::ConnectionStats {
+        &mut self.stats
     }
 
     ///A shorthand for extracting the MessageConnector out of `self.state()`. Returns `None` when
@@ -147,7 +544,8 @@ impl<A: server::Application, D: server::Dispatch<A>> Connection<A, D> {
     }
 
     ///A shorthand for extracting the StdoutConnector out of `self.state()`. Returns `None` when
-    ///not in stdout mode.
+    ///not in stdout mode. For connections serving multiple screens, use
+    ///[stdout_connector_for()](#method.stdout_connector_for) instead.
     pub fn stdout_connector(&mut self) -> Option<&mut A::StdoutConnector> {
         use ConnectionState::*;
         match self.state {
@@ -156,40 +554,460 @@ impl<A: server::Application, D: server::Dispatch<A>> Connection<A, D> {
         }
     }
 
+    ///Returns the StdoutConnector for the given screen if this connection receives the stdout of
+    ///that screen, either in `Stdout` or in `StdoutMux` state.
+    pub fn stdout_connector_for(
+        &mut self,
+        screen: &server::ScreenIdentity,
+    ) -> Option<&mut A::StdoutConnector> {
+        use server::StdoutConnector;
+        use ConnectionState::*;
+        match self.state {
+            Stdout(ref mut c) if c.identity() == Some(screen) => Some(c),
+            StdoutMux(ref mut mux) => mux.connector_for(screen),
+            _ => None,
+        }
+    }
+
     ///A shorthand for `self.dispatch().enqueue_message(self, msg)`. See
     ///[over here](trait.Dispatch.html#tymethod.enqueue_message) for details.
+    ///
+    ///If the connection is in framed mode, the message is wrapped in a
+    ///[frame](../common/struct.Framed.html) first. Handlers should therefore always send messages
+    ///through this method instead of calling the Dispatch directly.
+    ///
+    ///Before the message is sent, it is passed through
+    ///[`Application::filter_outgoing()`](trait.Application.html#method.filter_outgoing), which
+    ///may drop or replace it.
     pub fn enqueue_message<M: msg::EncodeMessage>(&mut self, msg: &M) {
+        if !self.dispatch().application().has_outgoing_filter() {
+            return self.enqueue_filtered_message(msg);
+        }
+        let mut buf = vec![0u8; msg.encoded_size()];
+        let len = match msg.encode(&mut buf) {
+            Ok(len) => len,
+            //leave it to the Dispatch to report messages that cannot be encoded
+            Err(_) => return self.enqueue_filtered_message(msg),
+        };
+        buf.truncate(len);
+        let action = match msg::Message::parse(&buf) {
+            Ok((parsed, _)) => self.dispatch().application().filter_outgoing(&parsed, self),
+            //not a valid message, so there is nothing to filter by
+            Err(_) => OutgoingAction::Pass,
+        };
+        match action {
+            OutgoingAction::Pass => self.enqueue_filtered_message(&server::PreEncodedMessage(buf)),
+            OutgoingAction::Drop => {}
+            OutgoingAction::Replace(buf) => {
+                self.enqueue_filtered_message(&server::PreEncodedMessage(buf))
+            }
+        }
+    }
+
+    fn enqueue_filtered_message<M: msg::EncodeMessage>(&mut self, msg: &M) {
+        #[cfg(feature = "module_frame")]
+        {
+            if self.framed {
+                self.record_outbound(&Framed(msg));
+                return self.dispatch().enqueue_message(self, &Framed(msg));
+            }
+        }
+        self.record_outbound(msg);
         self.dispatch().enqueue_message(self, msg)
     }
 
+    fn record_outbound<M: msg::EncodeMessage>(&mut self, msg: &M) {
+        if let Some(ref mut rec) = self.recording {
+            let mut buf = vec![0u8; msg.encoded_size()];
+            //messages that cannot be encoded are not sent either, so there is nothing to record
+            if let Ok(len) = msg.encode(&mut buf) {
+                rec.log.push(
+                    server::Direction::Outbound,
+                    rec.started.elapsed(),
+                    &buf[0..len],
+                );
+            }
+        }
+    }
+
+    ///Starts recording the data exchanged on this connection into a
+    ///[SessionLog](struct.SessionLog.html). Input is recorded as it is received from the client,
+    ///and messages are recorded as they are passed to
+    ///[enqueue_message()](#method.enqueue_message). Stdin is not recorded since it does not
+    ///originate from the client. If a recording is already running, it is restarted.
+    ///
+    ///To record entire sessions, handlers can call this in `on_connect()` and collect the log with
+    ///[take_recording()](#method.take_recording) in `on_disconnect()`.
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Recording {
+            started: Instant::now(),
+            log: server::SessionLog::new(),
+            recorded_upto: self.input_offset,
+        });
+    }
+
+    ///Returns the log of the recording that is currently running, if any.
+    pub fn recording(&self) -> Option<&server::SessionLog> {
+        self.recording.as_ref().map(|rec| &rec.log)
+    }
+
+    ///Stops the recording that is currently running, and returns its log.
+    pub fn take_recording(&mut self) -> Option<server::SessionLog> {
+        self.recording.take().map(|rec| rec.log)
+    }
+
     ///A shorthand for `self.dispatch().enqueue_stdin(self, buf)`. See
     ///[over here](trait.Dispatch.html#tymethod.enqueue_stdin) for details.
+    ///
+    ///If a [line discipline](struct.LineDiscipline.html) is attached to this connection, the
+    ///input is passed through it first.
     pub fn enqueue_stdin(&mut self, buf: &[u8]) {
-        self.dispatch().enqueue_stdin(self, buf)
+        match self.line_discipline {
+            None => self.dispatch().enqueue_stdin(self, buf),
+            Some(ref mut ld) => {
+                let (mut stdin, mut echo) = (Vec::new(), Vec::new());
+                ld.process(buf, &mut stdin, &mut echo);
+                self.enqueue_line_discipline_output(&stdin, echo);
+            }
+        }
+    }
+
+    ///Returns the [line discipline](struct.LineDiscipline.html) attached to this connection, if
+    ///any.
+    pub fn line_discipline(&self) -> Option<&server::LineDiscipline> {
+        self.line_discipline.as_ref()
+    }
+
+    ///Attaches a [line discipline](struct.LineDiscipline.html) with the given options to this
+    ///connection, or updates the options of the line discipline that is already attached. When
+    ///`None` is given, the line discipline is detached. Any partial line that the line discipline
+    ///was holding back is sent to the client when line buffering is switched off in this way.
+    ///
+    ///Line disciplines are only used in stdin mode. Calls in any other state are ignored.
+    pub fn set_line_discipline(&mut self, options: Option<server::LineDisciplineOptions>) {
+        if !self.state.can_receive_stdin() {
+            return;
+        }
+        let mut stdin = Vec::new();
+        match (self.line_discipline.as_mut(), options) {
+            (Some(ld), Some(options)) => ld.set_options(options, &mut stdin),
+            (Some(ld), None) => {
+                ld.set_options(Default::default(), &mut stdin);
+                self.line_discipline = None;
+            }
+            (None, Some(options)) => {
+                self.line_discipline = Some(server::LineDiscipline::new(options));
+            }
+            (None, None) => {}
+        }
+        self.enqueue_line_discipline_output(&stdin, Vec::new());
+    }
+
+    ///Returns the options for shaping the data that is sent on this connection.
+    pub fn egress_shaping(&self) -> server::EgressShaping {
+        self.egress_shaping
+    }
+
+    ///Sets the options for shaping the data that is sent on this connection, e.g. to hide the
+    ///typing rhythm while a client shows a password prompt. See
+    ///[EgressShaping](struct.EgressShaping.html) for details.
+    pub fn set_egress_shaping(&mut self, options: server::EgressShaping) {
+        self.egress_shaping = options;
+    }
+
+    ///Marks this connection as being in padded stdin mode, where the stdin sent on it is wrapped
+    ///in chunks that can carry padding (see
+    ///[PaddedStdinHello](../msg/posix/struct.PaddedStdinHello.html)). This is usually called by
+    ///the handler for the `posix1.padded-stdin-hello` handshake. There is no way to switch back.
+    pub fn enable_stdin_padding(&mut self) {
+        self.stdin_padded = true;
+    }
+
+    ///Returns whether this connection is in padded stdin mode. The Dispatch must encode all stdin
+    ///sent on such a connection into chunks as described for
+    ///[encode_padded_chunk()](../common/fn.encode_padded_chunk.html), padded as requested by
+    ///[`egress_shaping().padding_block`](struct.EgressShaping.html#structfield.padding_block).
+    pub fn is_stdin_padded(&self) -> bool {
+        self.stdin_padded
+    }
+
+    fn enqueue_line_discipline_output(&mut self, stdin: &[u8], echo: Vec<u8>) {
+        if !stdin.is_empty() {
+            self.dispatch().enqueue_stdin(self, stdin);
+        }
+        if !echo.is_empty() {
+            //the echo goes to the stdout connection for the same screen
+            let screen = match self.state {
+                ConnectionState::Stdin(ref screen) => screen.clone(),
+                _ => return,
+            };
+            self.dispatch.enqueue_broadcast(Box::new(move |conn| {
+                use server::StdoutConnector;
+                let result = match conn.stdout_connector_for(&screen) {
+                    Some(connector) => connector.receive(&echo),
+                    None => Ok(()),
+                };
+                if result.is_err() {
+                    conn.tear_down(TeardownReason::StdoutRefused);
+                }
+            }));
+        }
+    }
+
+    ///Records that the client on this connection has subscribed to the property with the given
+    ///name. This is usually called by the handler for `core1.sub` messages.
+    pub fn subscribe(&mut self, name: &ScopedIdentifier<'_>) {
+        if self.subscriptions.insert(name.as_str().into()) {
+            self.dispatch
+                .subscriptions()
+                .subscribe(name, self.id.clone());
+        }
+    }
+
+    ///Records that the client on this connection shall receive changes to all properties of the
+    ///given module, like if it had subscribed to each of them. There is no message for this in
+    ///`vt6/core`, so this is only ever called by the application, e.g. for a privileged client
+    ///that mirrors the state of a screen.
+    ///
+    ///Unlike `core1.sub`, this does not send the current property values to the client.
+    pub fn subscribe_module(&mut self, module: &ModuleIdentifier<'_>) {
+        if self.module_subscriptions.insert(module.as_str().into()) {
+            self.dispatch
+                .subscriptions()
+                .subscribe_module(module, self.id.clone());
+        }
+    }
+
+    ///Returns whether the client on this connection has subscribed to the property with the given
+    ///name, either directly or through `subscribe_module()`. Handlers use this to decide whether to
+    ///send a `core1.pub` message to this connection when the property value changes.
+    pub fn is_subscribed(&self, name: &str) -> bool {
+        self.subscriptions.contains(name)
+            || name
+                .split('.')
+                .next()
+                .is_some_and(|module| self.module_subscriptions.contains(module))
+    }
+
+    ///Answers a `want` for the given module, i.e. returns the minor version of the module that
+    ///the server supports, or `None` if the module is not supported.
+    ///
+    ///The first time that a module is negotiated on this connection, the given handler is asked
+    ///through `get_supported_module_version()`, and the answer is recorded. Later negotiations for
+    ///the same module are answered from that record, so that the client always gets the same
+    ///answer for the lifetime of the connection, as required by
+    ///[\[vt6/foundation, sect. 4.2\]](https://vt6.io/std/foundation/#section-4-2). If the handler
+    ///reports the module as deprecated, the Application is notified at this point.
+    pub fn negotiate_module<H: MessageHandler<A>>(
+        &mut self,
+        handler: &H,
+        module: &ModuleIdentifier<'_>,
+    ) -> Option<u16> {
+        if let Some(&result) = self.negotiated_modules.get(module.as_str()) {
+            return result;
+        }
+        let result = handler.get_supported_module_version(module);
+        self.negotiated_modules.insert(module.into(), result);
+        if result.is_some() {
+            if let Some(deprecation) = handler.get_module_deprecation(module) {
+                let n = server::Notification::DeprecatedModuleNegotiated {
+                    listener: self.listener(),
+                    module: module.as_str(),
+                    deprecation,
+                };
+                self.dispatch.notify(&n);
+            }
+        }
+        result
+    }
+
+    ///Returns the minor version of the given module if it was agreed on with the client on this
+    ///connection through a `want`/`have` exchange. Returns `None` if the module was refused or
+    ///has not been negotiated yet.
+    ///
+    ///Handlers can use this to check whether the client has negotiated a module before using it.
+    pub fn agreed_module_version(&self, module: &ModuleIdentifier<'_>) -> Option<u16> {
+        self.negotiated_modules
+            .get(module.as_str())
+            .copied()
+            .flatten()
+    }
+
+    ///Returns the highest major version of the module with the given name (e.g. `core`) that was
+    ///agreed on with the client on this connection. Returns `None` if no major version of that
+    ///module was agreed on.
+    ///
+    ///When the server supports several major versions of a module, handlers can use this to
+    ///choose the version for messages that the server sends on its own accord (e.g. `core1.pub`
+    ///vs. `core2.pub`), so that the client receives the message type that it asked for.
+    pub fn agreed_major_version(&self, name: &str) -> Option<u16> {
+        self.negotiated_modules
+            .iter()
+            .filter(|(module, result)| result.is_some() && module.name().as_str() == name)
+            .map(|(module, _)| module.major_version())
+            .max()
+    }
+
+    ///Returns whether the given module has been negotiated on this connection, regardless of
+    ///whether it was agreed on or refused.
+    pub fn has_negotiated_module(&self, module: &ModuleIdentifier<'_>) -> bool {
+        self.negotiated_modules.contains_key(module.as_str())
+    }
+
+    #[cfg(feature = "module_sig")]
+    ///Records that the client on this connection has claimed the given signal. This is usually
+    ///called by the handler for `sig1.claim` messages.
+    pub fn claim_signal(&mut self, signal: Signal) {
+        self.claimed_signals.insert(signal);
+    }
+
+    #[cfg(feature = "module_sig")]
+    ///Records that the client on this connection has released its claim on the given signal. This
+    ///is usually called by the handler for `sig1.release` messages.
+    pub fn release_signal(&mut self, signal: Signal) {
+        self.claimed_signals.remove(&signal);
+    }
+
+    #[cfg(feature = "module_sig")]
+    ///Returns whether the client on this connection has claimed the given signal. This is used by
+    ///[vt6::server::sig::deliver_signal()](sig/fn.deliver_signal.html).
+    pub fn has_claimed_signal(&self, signal: Signal) -> bool {
+        self.claimed_signals.contains(&signal)
+    }
+
+    #[cfg(feature = "module_frame")]
+    ///Switches this connection into framed mode. Afterwards, incoming data is decoded as frames,
+    ///and outgoing messages are encoded as frames. This is usually called by the handler for
+    ///`frame1.enable` messages, see [vt6::server::frame](frame/index.html). There is no way to
+    ///switch back.
+    pub fn enable_framing(&mut self) {
+        self.framed = true;
+    }
+
+    #[cfg(feature = "module_frame")]
+    ///Returns whether this connection is in framed mode.
+    pub fn is_framed(&self) -> bool {
+        self.framed
+    }
+
+    ///Marks this connection as belonging to a client that uses unversioned message types like
+    ///`core.set`. This is usually called by
+    ///[LegacyNamesHandler](struct.LegacyNamesHandler.html), and makes
+    ///[LegacyNamesFilter](struct.LegacyNamesFilter.html) translate replies into that form. There
+    ///is no way to switch back.
+    pub fn enable_legacy_names(&mut self) {
+        self.legacy_names = true;
+    }
+
+    ///Returns whether this connection belongs to a client that uses unversioned message types.
+    pub fn uses_legacy_names(&self) -> bool {
+        self.legacy_names
+    }
+
+    ///Replaces invalid input with a valid message. This may only be called from
+    ///[`Handler::handle_error()`](trait.Handler.html#tymethod.handle_error), by handlers that
+    ///understand the input that could not be parsed, e.g. because it is in an obsolete format.
+    ///
+    ///The first `len` bytes of `err.buffer` are then consumed as if they had contained
+    ///`replacement`, which must contain exactly one encoded message. The replacement goes through
+    ///the handler chain like any other message, and the parse error is neither counted in the
+    ///connection's stats nor reported to the Application. If the replacement is not a valid
+    ///message, or if `len` is zero, the parse error is handled as usual.
+    pub fn substitute_input(&mut self, len: usize, replacement: Vec<u8>) {
+        self.input_recovery = Some(InputRecovery::Substitute(len, replacement));
+    }
+
+    ///Like [substitute_input()](#method.substitute_input), but for handlers that need more input
+    ///to make sense of the input that could not be parsed. The input is kept, and parsed again
+    ///once more data has been received. Handlers must only call this while `err.buffer` is shorter
+    ///than the maximum message length of 1024 bytes, since the input would otherwise be kept
+    ///forever. This has no effect on connections in framed mode.
+    pub fn await_more_input(&mut self) {
+        self.input_recovery = Some(InputRecovery::AwaitMore);
     }
 
     ///Handle data sent by the client. This interface is called by the Dispatch whenever data has
     ///been read from the client socket associated with this Connection instance.
+    ///
+    ///With the `use_tracing` feature, this runs inside a `vt6.connection` span (with the connection
+    ///ID and listener label), and each message is handled inside a `vt6.message` span (with the
+    ///message type and, in msgio mode, the client ID).
     pub fn handle_incoming<B: ReceiveBuffer>(&mut self, buf: &mut B) {
+        #[cfg(feature = "use_tracing")]
+        let _span = tracing::debug_span!(
+            "vt6.connection",
+            id = ?self.id,
+            listener = self.listener(),
+        )
+        .entered();
+        if let Some(ref mut rec) = self.recording {
+            //data that was already received in an earlier call, but not consumed yet, is still at
+            //the start of the buffer and must not be recorded again
+            let skip = (rec.recorded_upto - self.input_offset) as usize;
+            let data = buf.contents();
+            if data.len() > skip {
+                rec.log.push(
+                    server::Direction::Inbound,
+                    rec.started.elapsed(),
+                    &data[skip..],
+                );
+            }
+            rec.recorded_upto = self.input_offset + data.len() as u64;
+        }
+        self.handle_incoming_step(buf)
+    }
+
+    fn handle_incoming_step<B: ReceiveBuffer>(&mut self, buf: &mut B) {
         if !buf.contents().is_empty() {
             use server::StdoutConnector;
             use ConnectionState::*;
             match self.state {
                 Handshake => self.handle_incoming_msgio::<B>(buf, HandlerObj::<A>::handshake()),
+                #[cfg(feature = "module_frame")]
+                Msgio(_) if self.framed => {
+                    self.handle_incoming_frame::<B>(buf, HandlerObj::<A>::message())
+                }
                 Msgio(_) => self.handle_incoming_msgio::<B>(buf, HandlerObj::<A>::message()),
                 Stdin(_) => {
                     //receiving anything on stdin is an error, so close the connection (we might
                     //have to relax this in the future depending on how insistent legacy clients
                     //are on being stupid; but it's always a good idea to start out strict and get
                     //more lenient over time then the other way around)
-                    self.set_state(ConnectionState::Teardown);
-                    let n = server::Notification::IncomingBytesDiscarded(buf.contents());
-                    self.dispatch.application().notify(&n);
-                    buf.discard(buf.contents().len());
+                    let len = buf.contents().len();
+                    self.discard_input(buf, len);
+                    self.tear_down(TeardownReason::ProtocolViolation);
                 }
                 Stdout(ref mut connector) => {
-                    connector.receive(buf.contents());
-                    buf.discard(buf.contents().len());
+                    let result = connector.receive(buf.contents());
+                    let is_ready = connector.is_ready();
+                    let len = buf.contents().len();
+                    self.consume_input(buf, len);
+                    if let Err(_e) = result {
+                        #[cfg(feature = "use_tracing")]
+                        tracing::debug!(error = %_e, "stdout refused by connector");
+                        self.tear_down(TeardownReason::StdoutRefused);
+                    } else if !is_ready {
+                        self.pause_reading();
+                    }
+                }
+                StdoutMux(ref mut mux) => {
+                    let result = mux.receive(buf.contents());
+                    let is_ready = mux.is_ready();
+                    match result {
+                        Ok(len) => {
+                            self.consume_input(buf, len);
+                            if !is_ready {
+                                self.pause_reading();
+                            }
+                        }
+                        Err(_e) => {
+                            #[cfg(feature = "use_tracing")]
+                            tracing::debug!(error = %_e, "stdout refused by connector");
+                            let len = buf.contents().len();
+                            self.discard_input(buf, len);
+                            self.tear_down(TeardownReason::StdoutRefused);
+                        }
+                    }
                 }
                 Teardown => {}
             }
@@ -199,70 +1017,732 @@ impl<A: server::Application, D: server::Dispatch<A>> Connection<A, D> {
     fn handle_incoming_msgio<B: ReceiveBuffer>(&mut self, buf: &mut B, handler: HandlerObj<A>) {
         match msg::Message::parse(buf.contents()) {
             Ok((msg, bytes_parsed)) => {
-                use server::HandlerError::*;
-                let handle_result = match handler {
-                    HandlerObj::HandshakeHandler(ref h) => h.handle(&msg, self),
-                    HandlerObj::MessageHandler(ref h) => h.handle(&msg, self),
-                };
-                match (handle_result, handler) {
-                    (Ok(_), _) => { /* nice */ }
-                    //during handshake, anything that's not a handshake is a fatal error
-                    (Err(_), HandlerObj::HandshakeHandler(_)) => {
-                        self.set_state(ConnectionState::Teardown);
-                    }
-                    //error handling according to [vt6/foundation, sect. 3.3.2]
-                    (Err(InvalidMessage), HandlerObj::MessageHandler(_)) => {
-                        self.enqueue_message(&Nope(msg.parsed_type()));
-                    }
-                    (Err(UnknownMessageType), HandlerObj::MessageHandler(ref h)) => {
-                        if let MessageType::Scoped(mt) = msg.parsed_type() {
-                            let module_id = mt.module();
-                            let result = h.get_supported_module_version(&module_id);
-                            let reply = match result {
-                                Some(v) => Have::ThisModule(module_id.with_minor_version(v)),
-                                None => Have::NotThisModule(module_id),
-                            };
-                            self.enqueue_message(&reply);
-                        } else {
-                            //anything else is an eternal message not understood by the handler, so
-                            //it must be semantically invalid
-                            self.enqueue_message(&Nope(msg.parsed_type()));
-                        }
-                    }
-                }
-                buf.discard(bytes_parsed);
+                self.handle_message(&msg, &handler);
+                self.consume_input(buf, bytes_parsed);
             }
             Err(e) if e.kind == msg::ParseErrorKind::UnexpectedEOF => {
                 //if we don't have a full message yet, wait until the next read
                 return;
             }
             Err(e) => {
-                match handler {
-                    HandlerObj::HandshakeHandler(h) => h.handle_error(&e, self),
-                    HandlerObj::MessageHandler(h) => h.handle_error(&e, self),
-                };
-                //during handshake, anything that's not a valid handshake is a fatal error
-                if matches!(self.state, ConnectionState::Handshake) {
-                    self.set_state(ConnectionState::Teardown);
-                }
                 //After a parse error, recover by skipping ahead to the next possible start of
                 //a message, i.e. the next `{` sign. [vt6/foundation, sect. 3.3]
-                //
-                //The .skip(1) ensures that we don't skip by 0 bytes.
-                let bytes_to_discard = match buf.contents().iter().skip(1).position(|&b| b == b'{')
-                {
-                    Some(offset) => offset + 1,   //`+1` compensates the effect of .skip(1)
-                    None => buf.contents().len(), //no `{` at all -> everything is garbage
-                };
-                let n = server::Notification::IncomingBytesDiscarded(
-                    &buf.contents()[0..bytes_to_discard],
-                );
-                self.dispatch.application().notify(&n);
-                buf.discard(bytes_to_discard);
+                let bytes_to_discard = e.resync_offset;
+                match self.handle_parse_error(e, &handler) {
+                    Some(InputRecovery::Substitute(len, replacement)) => {
+                        self.handle_substitute(&replacement, &handler);
+                        self.consume_input(buf, len);
+                    }
+                    Some(InputRecovery::AwaitMore) => return,
+                    None => {
+                        self.discard_input(buf, bytes_to_discard);
+                        if matches!(self.state, ConnectionState::Handshake) {
+                            self.handle_handshake_error();
+                        }
+                    }
+                }
             }
         }
         //handling the previous message (or error) may have changed into a different state, so
-        //tail-call back into handle_incoming() to disambiguate again
-        self.handle_incoming(buf)
+        //tail-call back into handle_incoming_step() to disambiguate again
+        self.handle_incoming_step(buf)
+    }
+
+    //Like handle_incoming_msgio(), but for connections in framed mode. Each frame must contain
+    //exactly one message. Since the frame tells us where the next message starts, invalid input
+    //never requires resynchronization: Anything in the frame that is not a valid message is just
+    //discarded.
+    #[cfg(feature = "module_frame")]
+    fn handle_incoming_frame<B: ReceiveBuffer>(&mut self, buf: &mut B, handler: HandlerObj<A>) {
+        let (payload, frame_len) = match decode_frame(buf.contents()) {
+            Some(frame) => frame,
+            //if we don't have a full frame yet, wait until the next read
+            None => return,
+        };
+        let payload_len = payload.len();
+        match msg::Message::parse(payload) {
+            Ok((msg, bytes_parsed)) => {
+                self.handle_message(&msg, &handler);
+                self.consume_input(buf, FRAME_HEADER_LEN + bytes_parsed);
+                if bytes_parsed < payload_len {
+                    self.discard_input(buf, payload_len - bytes_parsed);
+                }
+            }
+            Err(e) => {
+                //within a complete frame, an incomplete message is an error like any other
+                match self.handle_parse_error(e, &handler) {
+                    Some(InputRecovery::Substitute(_, replacement)) => {
+                        self.handle_substitute(&replacement, &handler);
+                        self.consume_input(buf, frame_len);
+                    }
+                    _ => self.discard_input(buf, frame_len),
+                }
+            }
+        }
+        self.handle_incoming_step(buf)
+    }
+
+    fn handle_message(&mut self, msg: &msg::Message, handler: &HandlerObj<A>) {
+        use server::HandlerError::*;
+        #[cfg(feature = "use_tracing")]
+        let _span = self.message_span(msg).entered();
+        self.stats.messages_handled += 1;
+        if !self.is_message_authorized(msg) {
+            self.enqueue_message(&Nope(msg.parsed_type()));
+            return;
+        }
+        let started = Instant::now();
+        let handle_result = match *handler {
+            HandlerObj::HandshakeHandler(ref h) => h.handle(msg, self),
+            HandlerObj::MessageHandler(ref h) => h.handle(msg, self),
+        };
+        self.record_handler_time(msg, started.elapsed());
+        #[cfg(feature = "use_tracing")]
+        if let Err(ref e) = handle_result {
+            tracing::debug!(error = %e, "message refused by handler");
+        }
+        match (handle_result, handler) {
+            (Ok(_), _) => { /* nice */ }
+            (Err(_), HandlerObj::HandshakeHandler(_)) => {
+                if self.handle_handshake_error() {
+                    self.enqueue_message(&Nope(msg.parsed_type()));
+                }
+            }
+            //error handling according to [vt6/foundation, sect. 3.3.2]
+            (
+                Err(InvalidMessage | PermissionDenied | TemporarilyUnavailable | ResourceExhausted),
+                HandlerObj::MessageHandler(_),
+            ) => {
+                self.enqueue_message(&Nope(msg.parsed_type()));
+            }
+            (Err(UnknownMessageType), HandlerObj::MessageHandler(ref h)) => {
+                if let MessageType::Scoped(mt) = msg.parsed_type() {
+                    let module_id = mt.module();
+                    let result = self.negotiate_module(h, &module_id);
+                    let reply = match result {
+                        Some(v) => Have::ThisModule(module_id.with_minor_version(v)),
+                        None => Have::NotThisModule(module_id),
+                    };
+                    self.enqueue_message(&reply);
+                } else {
+                    //anything else is an eternal message not understood by the handler, so
+                    //it must be semantically invalid
+                    self.enqueue_message(&Nope(msg.parsed_type()));
+                }
+            }
+        }
+    }
+
+    fn record_handler_time(&mut self, msg: &msg::Message, elapsed: std::time::Duration) {
+        self.stats.handler_time += elapsed;
+        let threshold = match self.dispatch.slow_handler_threshold() {
+            Some(threshold) if elapsed >= threshold => threshold,
+            _ => return,
+        };
+        self.stats.slow_messages += 1;
+        #[cfg(feature = "use_tracing")]
+        tracing::warn!(?elapsed, "slow message handler");
+        let msg_type = msg.parsed_type();
+        let n = server::Notification::SlowHandler {
+            listener: self.listener(),
+            msg_type: msg_type.as_str(),
+            elapsed,
+            threshold,
+        };
+        self.dispatch.notify(&n);
+    }
+
+    //During handshake, anything that's not a valid handshake is a fatal error, unless the
+    //Dispatch is configured to tolerate some errors. Returns whether the connection survives.
+    fn handle_handshake_error(&mut self) -> bool {
+        self.handshake_errors += 1;
+        let tolerance = self.dispatch.handshake_tolerance();
+        if tolerance.allows(self.handshake_errors, self.stats.uptime()) {
+            return true;
+        }
+        self.tear_down(TeardownReason::ProtocolViolation);
+        false
+    }
+
+    fn is_message_authorized(&self, msg: &msg::Message) -> bool {
+        use server::MessageConnector;
+        match self.state {
+            ConnectionState::Msgio(ref connector) => self
+                .dispatch
+                .application()
+                .authorize_message(connector.identity(), &msg.parsed_type()),
+            //handshakes are authorized by the HandshakeHandler itself
+            _ => true,
+        }
+    }
+
+    //Returns how a handler has dealt with the offending input, if at all. Substitutions are only
+    //returned if they can be handled.
+    fn handle_parse_error(
+        &mut self,
+        e: msg::ParseError,
+        handler: &HandlerObj<A>,
+    ) -> Option<InputRecovery> {
+        #[cfg(feature = "use_tracing")]
+        tracing::debug!(error = %e.kind, offset = e.offset, "could not parse message");
+        self.input_recovery = None;
+        match *handler {
+            HandlerObj::HandshakeHandler(ref h) => h.handle_error(&e, self),
+            HandlerObj::MessageHandler(ref h) => h.handle_error(&e, self),
+        };
+        match self.input_recovery.take() {
+            Some(InputRecovery::Substitute(len, replacement))
+                if len > 0 && len <= e.buffer.len() && is_single_message(&replacement) =>
+            {
+                return Some(InputRecovery::Substitute(len, replacement));
+            }
+            Some(InputRecovery::AwaitMore) => return Some(InputRecovery::AwaitMore),
+            _ => {}
+        }
+        self.stats.parse_errors += 1;
+        let n = server::Notification::IncomingParseError {
+            listener: self.listener(),
+            error: e.into(),
+        };
+        self.dispatch.notify(&n);
+        None
+    }
+
+    fn handle_substitute(&mut self, replacement: &[u8], handler: &HandlerObj<A>) {
+        //handle_parse_error() has checked that this parses
+        if let Ok((msg, _)) = msg::Message::parse(replacement) {
+            self.handle_message(&msg, handler);
+        }
+    }
+
+    #[cfg(feature = "use_tracing")]
+    fn message_span(&self, msg: &msg::Message) -> tracing::Span {
+        use server::MessageConnector;
+        let span = tracing::debug_span!(
+            "vt6.message",
+            msg_type = %msg.parsed_type(),
+            client_id = tracing::field::Empty,
+        );
+        if let ConnectionState::Msgio(ref connector) = self.state {
+            span.record(
+                "client_id",
+                tracing::field::display(connector.identity().client_id()),
+            );
+        }
+        span
+    }
+
+    fn consume_input<B: ReceiveBuffer>(&mut self, buf: &mut B, len: usize) {
+        buf.discard(len);
+        self.input_offset += len as u64;
+    }
+
+    fn discard_input<B: ReceiveBuffer>(&mut self, buf: &mut B, len: usize) {
+        self.discarded
+            .record(self.input_offset, &buf.contents()[0..len]);
+        self.consume_input(buf, len);
+
+        //rate-limit notifications about discarded input
+        let interval = self.dispatch.discard_notification_interval();
+        let is_due = match self.discard_notified_at {
+            Some(t) => t.elapsed() >= interval,
+            None => true,
+        };
+        if is_due {
+            self.notify_discarded_input();
+        }
+    }
+
+    fn notify_discarded_input(&mut self) {
+        if self.discarded.count == 0 {
+            return;
+        }
+        let discarded = std::mem::take(&mut self.discarded);
+        self.discard_notified_at = Some(Instant::now());
+        let n = server::Notification::IncomingBytesDiscarded {
+            listener: self.listener(),
+            discarded: &discarded,
+        };
+        self.dispatch.notify(&n);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::{ClientID, ScreenID};
+    use crate::server::testing::{Conversation, MockApplication, MockDispatch, MockHandlers};
+    use crate::server::{ClientIdentity, ScreenIdentity};
+
+    fn register_client<H: MockHandlers>(
+        app: &MockApplication<H>,
+        id: &str,
+    ) -> server::ClientCredentials {
+        let id = ClientID::parse(id).unwrap();
+        server::Application::register_client(app, ClientIdentity::new(&id))
+    }
+
+    fn client_hello(creds: &server::ClientCredentials) -> crate::msg::posix::ClientHello<'_> {
+        crate::msg::posix::ClientHello {
+            secret: creds.secret(),
+        }
+    }
+
+    #[test]
+    fn test_authorize_message() {
+        let app: MockApplication = MockApplication::new();
+        app.forbid_message_type("core1.sub");
+        let creds = register_client(&app, "a");
+        let mut conv = Conversation::new(app);
+        conv.send_message(&client_hello(&creds))
+            .expect(r#"(posix1.server-hello a "" "" "")"#);
+
+        //messages forbidden by Application::authorize_message() are refused before any handler
+        //sees them
+        conv.send(b"{2|9:core1.sub,14:example1.width,}")
+            .expect("(nope core1.sub)")
+            .send(b"{2|4:want,5:core1,}")
+            .expect("(have core1.0)");
+        assert!(!conv.connection().is_subscribed("example1.width"));
+    }
+
+    #[test]
+    fn test_authorize_message_scope() {
+        let app: MockApplication = MockApplication::new();
+        app.forbid_message_type("posix1.client-hello");
+        app.forbid_message_type("want");
+        let creds = register_client(&app, "a");
+        let mut conv = Conversation::new(app.clone());
+
+        //handshakes are not subject to authorize_message()
+        conv.send_message(&client_hello(&creds))
+            .expect(r#"(posix1.server-hello a "" "" "")"#);
+        assert_eq!(conv.connection().state().type_name(), "Msgio");
+
+        //eternal message types can be refused just like scoped ones, and refusing a message does
+        //not affect the connection otherwise
+        conv.send(b"{2|4:want,5:core1,}")
+            .expect("(nope want)")
+            .send(b"{2|19:posix1.client-hello,1:x,}")
+            .expect("(nope posix1.client-hello)");
+        assert_eq!(conv.connection().state().type_name(), "Msgio");
+        assert_eq!(conv.connection().teardown_reason(), None);
+        assert_eq!(conv.connection().stats().messages_handled, 3);
+    }
+
+    #[test]
+    fn test_stats() {
+        let app: MockApplication = MockApplication::new();
+        let creds = register_client(&app, "a");
+        let mut conv = Conversation::new(app);
+        let stats = conv.connection().stats().clone();
+        assert_eq!((stats.bytes_received, stats.bytes_sent), (0, 0));
+        assert_eq!(stats.handler_time, std::time::Duration::ZERO);
+
+        let hello = client_hello(&creds);
+        let reply = r#"(posix1.server-hello a "" "" "")"#;
+        conv.send_message(&hello).expect(reply);
+        let stats = conv.connection().stats().clone();
+        assert_eq!(
+            stats.bytes_received,
+            msg::EncodeMessage::encoded_size(&hello) as u64
+        );
+        assert!(stats.bytes_sent > 0);
+
+        //an incomplete message counts as received, but is not handled yet
+        let input = b"{2|4:want,5:core1,}";
+        conv.send(&input[0..5]).expect_no_reply();
+        let stats2 = conv.connection().stats().clone();
+        assert_eq!(stats2.bytes_received, stats.bytes_received + 5);
+        assert_eq!(stats2.bytes_sent, stats.bytes_sent);
+        assert_eq!(stats2.messages_handled, stats.messages_handled);
+        assert_eq!(stats2.handler_time, stats.handler_time);
+
+        conv.send(&input[5..]).expect("(have core1.0)");
+        let stats3 = conv.connection().stats().clone();
+        assert_eq!(
+            stats3.bytes_received,
+            stats.bytes_received + input.len() as u64
+        );
+        assert_eq!(
+            stats3.bytes_sent,
+            stats.bytes_sent + b"{2|4:have,7:core1.0,}".len() as u64
+        );
+        assert_eq!(stats3.messages_handled, stats.messages_handled + 1);
+        assert!(stats3.handler_time > stats.handler_time);
+    }
+
+    #[test]
+    fn test_slow_handler_notification() {
+        let app: MockApplication = MockApplication::new();
+        let dispatch = MockDispatch::new(app.clone());
+        let mut conv = Conversation::with_dispatch(&dispatch);
+        conv.send(b"{1|9:core1.foo,}").expect_no_reply();
+        assert!(app.notifications().is_empty());
+        let stats = conv.connection().stats();
+        assert_eq!((stats.messages_handled, stats.slow_messages), (1, 0));
+
+        //with a threshold of zero, every handler is too slow
+        dispatch.set_slow_handler_threshold(Some(std::time::Duration::ZERO));
+        let mut conv = Conversation::with_dispatch(&dispatch);
+        conv.send(b"{1|9:core1.foo,}").expect_no_reply();
+        let notifications = app.notifications();
+        assert_eq!(notifications.len(), 1);
+        assert!(notifications[0].starts_with("handling core1.foo message took "));
+        assert!(notifications[0].ends_with(" (threshold is 0ns)"));
+        assert_eq!(conv.connection().stats().slow_messages, 1);
+    }
+
+    #[test]
+    fn test_state_transitions() {
+        let app: MockApplication = MockApplication::new();
+        let screen_id = ScreenID::parse("screen1").unwrap();
+        let screen_creds = app.add_screen(&screen_id);
+        let dispatch = MockDispatch::new(app.clone());
+        let mut conv = Conversation::with_dispatch(&dispatch);
+        conv.send_message(&crate::msg::posix::StdinHello {
+            secret: screen_creds.stdin_secret(),
+        });
+        assert_eq!(conv.connection().state().type_name(), "Stdin");
+        assert!(app.notifications().is_empty());
+
+        //a connection cannot go back into handshake, or switch between modes...
+        let screen = ScreenIdentity::new(&screen_id);
+        for state in [ConnectionState::Handshake, ConnectionState::Stdin(screen)] {
+            let result = conv.connection_mut().try_transition(state);
+            assert_eq!(result.map_err(|e| e.from), Err("Stdin"));
+        }
+        assert_eq!(conv.connection().state().type_name(), "Stdin");
+        assert_eq!(
+            app.notifications(),
+            vec![
+                "rejected transition of client connection from state Stdin into state Handshake",
+                "rejected transition of client connection from state Stdin into state Stdin",
+            ]
+        );
+
+        //...but it can always be torn down
+        let conn = conv.connection_mut();
+        assert_eq!(conn.try_transition(ConnectionState::Teardown), Ok(()));
+        assert_eq!(conn.try_transition(ConnectionState::Teardown), Ok(()));
+        assert_eq!(
+            conn.try_transition(ConnectionState::Handshake),
+            Err(InvalidTransition {
+                from: "Teardown",
+                to: "Handshake",
+            })
+        );
+    }
+
+    #[test]
+    fn test_handshake() {
+        let app: MockApplication = MockApplication::new();
+
+        //a handshake can be split across reads, and can share a read with the messages following
+        //it
+        let creds = register_client(&app, "a");
+        let hello = format!(
+            "{{2|19:posix1.client-hello,{}:{},}}",
+            creds.secret().len(),
+            creds.secret()
+        );
+        let (part1, part2) = hello.as_bytes().split_at(20);
+        let mut conv = Conversation::new(app.clone());
+        conv.send(part1).expect_no_reply();
+        assert_eq!(conv.connection().state().type_name(), "Handshake");
+        conv.send(&[part2, b"{2|4:want,5:core1,}"].concat())
+            .expect(r#"(posix1.server-hello a "" "" "")"#)
+            .expect("(have core1.0)")
+            .expect_no_reply();
+
+        //by default, any invalid input during the handshake is fatal
+        let mut conv = Conversation::new(app.clone());
+        conv.send(b"{2|19:posix1.client-hello,5:wrong,}")
+            .expect_no_reply();
+        assert_eq!(conv.connection().state().type_name(), "Teardown");
+        assert_eq!(
+            conv.connection().teardown_reason(),
+            Some(server::TeardownReason::ProtocolViolation)
+        );
+        let mut conv = Conversation::new(app.clone());
+        conv.send(b"garbage").expect_no_reply();
+        assert_eq!(conv.connection().state().type_name(), "Teardown");
+
+        //with a tolerance, refused messages get a `nope` and the client can try again
+        let dispatch = MockDispatch::new(app.clone());
+        dispatch.set_handshake_tolerance(server::HandshakeTolerance::new(2));
+        let creds = register_client(&app, "b");
+        let mut conv = Conversation::with_dispatch(&dispatch);
+        conv.send(b"{2|19:posix1.client-hello,5:wrong,}")
+            .expect("(nope posix1.client-hello)")
+            .send(b"garbage")
+            .expect_no_reply()
+            .send_message(&client_hello(&creds))
+            .expect(r#"(posix1.server-hello b "" "" "")"#);
+        assert_eq!(conv.connection().state().type_name(), "Msgio");
+
+        //the next error after the tolerance is exhausted is fatal
+        let mut conv = Conversation::with_dispatch(&dispatch);
+        conv.send(b"{1|9:core1.foo,}garbage{1|9:core1.foo,}")
+            .expect("(nope core1.foo)")
+            .expect_no_reply();
+        assert_eq!(conv.connection().state().type_name(), "Teardown");
+
+        //after the time budget, every error is fatal
+        dispatch.set_handshake_tolerance(
+            server::HandshakeTolerance::new(2).with_time_budget(std::time::Duration::ZERO),
+        );
+        let mut conv = Conversation::with_dispatch(&dispatch);
+        conv.send(b"{1|9:core1.foo,}").expect_no_reply();
+        assert_eq!(conv.connection().state().type_name(), "Teardown");
+    }
+
+    #[test]
+    fn test_stdout_conversation() {
+        let app: MockApplication = MockApplication::new();
+        let screen_id = ScreenID::parse("screen1").unwrap();
+        let screen = ScreenIdentity::new(&screen_id);
+        let screen_creds = app.add_screen(&screen_id);
+        let mut conv = Conversation::new(app);
+        conv.send_message(&crate::msg::posix::StdoutHello {
+            secret: screen_creds.stdout_secret(),
+        })
+        .expect_no_reply();
+        assert_eq!(conv.connection().state().type_name(), "Stdout");
+
+        conv.send(b"hello").expect_no_reply();
+        assert_eq!(conv.connection().state().type_name(), "Stdout");
+
+        //when the connector cannot keep up, reading is paused until the application resumes it
+        let connector = conv.connection_mut().stdout_connector_for(&screen).unwrap();
+        connector.set_ready(false);
+        conv.send(b"world").expect_no_reply();
+        assert!(conv.connection().is_reading_paused());
+        server::Dispatch::resume_stdout(&conv.dispatch(), &screen);
+        conv.expect_no_reply();
+        assert!(!conv.connection().is_reading_paused());
+        let connector = conv.connection_mut().stdout_connector_for(&screen).unwrap();
+        assert_eq!(connector.received(), b"helloworld");
+
+        //when the connector refuses stdout, the connection is closed
+        connector.close();
+        conv.send(b"exit").expect_no_reply();
+        assert_eq!(conv.connection().state().type_name(), "Teardown");
+    }
+
+    #[test]
+    fn test_module_negotiation() {
+        let app: MockApplication = MockApplication::new();
+        let creds = register_client(&app, "a");
+        let mut conv = Conversation::new(app);
+        conv.send_message(&client_hello(&creds))
+            .expect(r#"(posix1.server-hello a "" "" "")"#);
+
+        let core1 = ModuleIdentifier::parse("core1").unwrap();
+        let foo1 = ModuleIdentifier::parse("foo1").unwrap();
+        assert!(!conv.connection().has_negotiated_module(&core1));
+
+        //repeated negotiations are answered the same way
+        conv.send(b"{2|4:want,5:core1,}{2|4:want,5:core1,}")
+            .expect("(have core1.0)")
+            .expect("(have core1.0)");
+        assert_eq!(conv.connection().agreed_module_version(&core1), Some(0));
+        assert_eq!(conv.connection().agreed_major_version("core"), Some(1));
+        assert_eq!(conv.connection().agreed_major_version("foo"), None);
+
+        //messages of unknown modules also count as a negotiation
+        conv.send(b"{1|8:foo1.bar,}")
+            .expect("(have foo1)")
+            .send(b"{2|4:want,4:foo1,}")
+            .expect("(have foo1)");
+        assert!(conv.connection().has_negotiated_module(&foo1));
+        assert_eq!(conv.connection().agreed_module_version(&foo1), None);
+    }
+
+    //Declares `core1` as deprecated.
+    #[derive(Default)]
+    struct DeprecatingHandler<Next>(Next);
+
+    impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::MessageHandler<A>
+        for DeprecatingHandler<Next>
+    {
+        fn get_supported_module_version(&self, module: &ModuleIdentifier<'_>) -> Option<u16> {
+            self.0.get_supported_module_version(module)
+        }
+
+        fn get_module_deprecation(
+            &self,
+            module: &ModuleIdentifier<'_>,
+        ) -> Option<server::ModuleDeprecation> {
+            match module.as_str() {
+                "core1" | "foo1" => Some(server::ModuleDeprecation {
+                    replacement: Some("core2"),
+                    sunset: None,
+                }),
+                _ => self.0.get_module_deprecation(module),
+            }
+        }
+    }
+
+    impl<A: server::Application, Next: server::core::MessageHandlerExt<A>>
+        server::core::MessageHandlerExt<A> for DeprecatingHandler<Next>
+    {
+        fn handle_property<D: server::Dispatch<A>>(
+            &self,
+            name: &ScopedIdentifier<'_>,
+            requested_value: Option<&[u8]>,
+            conn: &mut server::Connection<A, D>,
+        ) -> Option<Vec<u8>> {
+            self.0.handle_property(name, requested_value, conn)
+        }
+    }
+
+    impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::Handler<A>
+        for DeprecatingHandler<Next>
+    {
+        fn handle<D: server::Dispatch<A>>(
+            &self,
+            msg: &msg::Message,
+            conn: &mut server::Connection<A, D>,
+        ) -> Result<(), server::HandlerError> {
+            self.0.handle(msg, conn)
+        }
+
+        fn handle_error<D: server::Dispatch<A>>(
+            &self,
+            err: &msg::ParseError,
+            conn: &mut server::Connection<A, D>,
+        ) {
+            self.0.handle_error(err, conn);
+        }
+    }
+
+    struct DeprecatingHandlers;
+
+    impl MockHandlers for DeprecatingHandlers {
+        type MessageHandler =
+            server::core::MessageHandler<DeprecatingHandler<server::RejectHandler>>;
+    }
+
+    #[test]
+    fn test_module_deprecation() {
+        let app: MockApplication<DeprecatingHandlers> = MockApplication::new();
+        let creds = register_client(&app, "a");
+        let mut conv = Conversation::new(app.clone());
+        conv.send_message(&client_hello(&creds))
+            .expect(r#"(posix1.server-hello a "" "" "")"#);
+
+        //the core handler forwards the question to the next handler, the `have` reply is
+        //unaffected, and the Application is notified only once
+        conv.send(b"{2|4:want,5:core1,}{2|4:want,5:core1,}")
+            .expect("(have core1.0)")
+            .expect("(have core1.0)");
+        assert_eq!(
+            app.notifications(),
+            vec!["client negotiated deprecated module core1 (use core2 instead)"]
+        );
+
+        //modules that are not supported at all are not reported
+        conv.send(b"{2|4:want,4:foo1,}").expect("(have foo1)");
+        assert_eq!(app.notifications().len(), 1);
+    }
+
+    thread_local! {
+        static LIFECYCLE_EVENTS: std::cell::RefCell<Vec<String>> = Default::default();
+    }
+
+    //Records all lifecycle events in LIFECYCLE_EVENTS.
+    #[derive(Default)]
+    struct LifecycleHandler<Next>(Next);
+
+    impl<A: server::Application, Next: server::MessageHandler<A>> server::MessageHandler<A>
+        for LifecycleHandler<Next>
+    {
+        fn get_supported_module_version(&self, module: &ModuleIdentifier<'_>) -> Option<u16> {
+            self.0.get_supported_module_version(module)
+        }
+    }
+
+    impl<A: server::Application, Next: server::MessageHandler<A>> server::Handler<A>
+        for LifecycleHandler<Next>
+    {
+        fn handle<D: server::Dispatch<A>>(
+            &self,
+            msg: &msg::Message,
+            conn: &mut server::Connection<A, D>,
+        ) -> Result<(), server::HandlerError> {
+            self.0.handle(msg, conn)
+        }
+
+        fn handle_error<D: server::Dispatch<A>>(
+            &self,
+            err: &msg::ParseError,
+            conn: &mut server::Connection<A, D>,
+        ) {
+            self.0.handle_error(err, conn);
+        }
+
+        fn on_connect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+            let event = format!("connect in {}", conn.state().type_name());
+            LIFECYCLE_EVENTS.with(|events| events.borrow_mut().push(event));
+            self.0.on_connect(conn);
+        }
+
+        fn on_state_change<D: server::Dispatch<A>>(
+            &self,
+            old_state: &ConnectionState<A>,
+            conn: &mut server::Connection<A, D>,
+        ) {
+            let event = format!(
+                "change from {} to {}",
+                old_state.type_name(),
+                conn.state().type_name()
+            );
+            LIFECYCLE_EVENTS.with(|events| events.borrow_mut().push(event));
+            self.0.on_state_change(old_state, conn);
+        }
+
+        fn on_disconnect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+            LIFECYCLE_EVENTS.with(|events| events.borrow_mut().push("disconnect".into()));
+            self.0.on_disconnect(conn);
+        }
+    }
+
+    fn take_lifecycle_events() -> Vec<String> {
+        LIFECYCLE_EVENTS.with(|events| events.take())
+    }
+
+    struct LifecycleHandlers;
+
+    impl MockHandlers for LifecycleHandlers {
+        type MessageHandler = LifecycleHandler<server::core::MessageHandler<server::RejectHandler>>;
+    }
+
+    #[test]
+    fn test_lifecycle_events() {
+        let app: MockApplication<LifecycleHandlers> = MockApplication::new();
+        let creds = register_client(&app, "a");
+        take_lifecycle_events();
+        let mut conv = Conversation::new(app);
+        assert_eq!(take_lifecycle_events(), vec!["connect in Handshake"]);
+
+        conv.send_message(&client_hello(&creds))
+            .expect(r#"(posix1.server-hello a "" "" "")"#);
+        assert_eq!(
+            take_lifecycle_events(),
+            vec!["change from Handshake to Msgio"]
+        );
+
+        conv.connection_mut().set_state(ConnectionState::Teardown);
+        //a repeated teardown does not count as another disconnect
+        conv.connection_mut().set_state(ConnectionState::Teardown);
+        assert_eq!(
+            take_lifecycle_events(),
+            vec![
+                "change from Msgio to Teardown",
+                "disconnect",
+                "change from Teardown to Teardown"
+            ]
+        );
     }
 }
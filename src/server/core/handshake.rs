@@ -6,10 +6,12 @@
 
 use crate::common::core::msg;
 use crate::common::core::msg::DecodeMessage;
-use crate::msg::posix::{ClientHello, ServerHello, StdinHello, StdoutHello};
+use crate::msg::posix::{
+    ClientHello, PaddedStdinHello, ServerHello, StdinHello, StdoutHello, StdoutMuxHello,
+};
 use crate::server;
-use crate::server::HandlerError::InvalidMessage;
-use crate::server::{MessageConnector, StdoutConnector};
+use crate::server::HandlerError::{InvalidMessage, PermissionDenied};
+use crate::server::{AttachmentKind, MessageConnector, StdoutConnector};
 
 ///A [HandshakeHandler](../trait.HandshakeHandler.html) providing basic support for the client
 ///handshakes defined in [`vt6/foundation`](https://vt6.io/std/foundation/) and the platform
@@ -35,24 +37,92 @@ impl<A: server::Application, Next: server::HandshakeHandler<A>> server::Handler<
         let app = d.application();
 
         match msg.parsed_type().as_str() {
-            "posix1.stdin-hello" => {
-                let msg = StdinHello::decode_message(msg).ok_or(InvalidMessage)?;
-                let identity = app.authorize_stdin(msg.secret).ok_or(InvalidMessage)?;
-                conn.set_state(server::ConnectionState::Stdin(identity));
+            "posix1.stdin-hello" | "posix1.padded-stdin-hello" => {
+                let (secret, is_padded) = match StdinHello::decode_message(msg) {
+                    Some(msg) => (msg.secret, false),
+                    None => {
+                        let msg = PaddedStdinHello::decode_message(msg).ok_or(InvalidMessage)?;
+                        (msg.secret, true)
+                    }
+                };
+                let (identity, is_takeover) = match app
+                    .authorize_stdin(secret)
+                    .or_else(|| app.resume_stdin(secret))
+                {
+                    Some(identity) => (identity, false),
+                    None => (app.takeover_stdin(secret).ok_or(PermissionDenied)?, true),
+                };
+                let line_discipline = app.line_discipline_options(&identity);
+                if is_takeover {
+                    displace_attached_connection(conn, &identity, AttachmentKind::Stdin);
+                }
+                //this must happen before the transition, since handlers may start sending stdin
+                //as soon as the screen's stdin is attached
+                if is_padded {
+                    conn.enable_stdin_padding();
+                }
+                conn.try_transition(server::ConnectionState::Stdin(identity))
+                    .map_err(|_| InvalidMessage)?;
+                conn.set_line_discipline(line_discipline);
                 Ok(())
             }
             "posix1.stdout-hello" => {
                 let msg = StdoutHello::decode_message(msg).ok_or(InvalidMessage)?;
-                let identity = app.authorize_stdout(msg.secret).ok_or(InvalidMessage)?;
+                let (identity, is_takeover) =
+                    authorize_stdout(app, msg.secret).ok_or(PermissionDenied)?;
+                if is_takeover {
+                    displace_attached_connection(conn, &identity, AttachmentKind::Stdout);
+                }
                 let connector = A::StdoutConnector::new(identity);
-                conn.set_state(server::ConnectionState::Stdout(connector));
+                conn.try_transition(server::ConnectionState::Stdout(connector))
+                    .map_err(|_| InvalidMessage)?;
+                Ok(())
+            }
+            "posix1.stdout-mux-hello" => {
+                let secrets: Vec<_> = StdoutMuxHello::decode_secrets(msg)
+                    .ok_or(InvalidMessage)?
+                    .collect();
+                //a repeated secret would take over the screen from ourselves
+                if (1..secrets.len()).any(|i| secrets[..i].contains(&secrets[i])) {
+                    return Err(InvalidMessage);
+                }
+                let mut screens = Vec::with_capacity(secrets.len());
+                for secret in secrets {
+                    match authorize_stdout(app, secret) {
+                        Some(screen) => screens.push(screen),
+                        None => {
+                            //all or nothing: release the screens that we got so far
+                            for (identity, is_takeover) in screens {
+                                if !is_takeover {
+                                    app.detach_stdout(&identity);
+                                }
+                            }
+                            return Err(PermissionDenied);
+                        }
+                    }
+                }
+                let mut connectors = Vec::with_capacity(screens.len());
+                for (identity, is_takeover) in screens {
+                    if is_takeover {
+                        displace_attached_connection(conn, &identity, AttachmentKind::Stdout);
+                    }
+                    connectors.push(A::StdoutConnector::new(identity));
+                }
+                let mux = server::StdoutMux::new(connectors);
+                conn.try_transition(server::ConnectionState::StdoutMux(mux))
+                    .map_err(|_| InvalidMessage)?;
                 Ok(())
             }
             "posix1.client-hello" => {
                 let msg = ClientHello::decode_message(msg).ok_or(InvalidMessage)?;
-                let identity = app.authorize_client(msg.secret).ok_or(InvalidMessage)?;
+                let peer = conn.peer_credentials();
+                let identity = app
+                    .authorize_client(msg.secret, peer)
+                    .or_else(|| app.resume_client(msg.secret, peer))
+                    .ok_or(PermissionDenied)?;
                 let connector = A::MessageConnector::new(identity.clone());
-                conn.set_state(server::ConnectionState::Msgio(connector));
+                conn.try_transition(server::ConnectionState::Msgio(connector))
+                    .map_err(|_| InvalidMessage)?;
                 let reply = ServerHello {
                     client_id: identity.client_id(),
                     stdin_screen_id: identity.stdin_screen_id(),
@@ -73,4 +143,132 @@ impl<A: server::Application, Next: server::HandshakeHandler<A>> server::Handler<
     ) {
         self.0.handle_error(err, conn);
     }
+
+    fn on_connect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_connect(conn);
+    }
+
+    fn on_state_change<D: server::Dispatch<A>>(
+        &self,
+        old_state: &server::ConnectionState<A>,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.on_state_change(old_state, conn);
+    }
+
+    fn on_disconnect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_disconnect(conn);
+    }
+}
+
+///Finds the screen for a stdout secret. The second return value is true if another connection is
+///attached to that screen's stdout and shall be displaced.
+fn authorize_stdout<A: server::Application>(
+    app: &A,
+    secret: &str,
+) -> Option<(server::ScreenIdentity, bool)> {
+    match app
+        .authorize_stdout(secret)
+        .or_else(|| app.resume_stdout(secret))
+    {
+        Some(identity) => Some((identity, false)),
+        None => Some((app.takeover_stdout(secret)?, true)),
+    }
+}
+
+///Tears down the connection that is attached to the given screen in the given way, if any. This
+///is used when `conn` takes over the screen's stdin or stdout, so it must be called before `conn`
+///itself attaches to the screen.
+fn displace_attached_connection<A: server::Application, D: server::Dispatch<A>>(
+    conn: &server::Connection<A, D>,
+    screen: &server::ScreenIdentity,
+    kind: AttachmentKind,
+) {
+    let d = conn.dispatch();
+    let previous = match d.attachments().connection(screen, kind) {
+        Some(id) => id,
+        None => return,
+    };
+    d.enqueue_broadcast(Box::new(move |other| {
+        if other.id() == previous {
+            #[cfg(feature = "use_tracing")]
+            tracing::debug!("connection displaced by takeover");
+            other.tear_down(server::TeardownReason::DisplacedByTakeover);
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::core::{ClientID, ScreenID};
+    use crate::server;
+    use crate::server::testing::{Conversation, MockApplication, MockDispatch};
+    use crate::server::ClientIdentity;
+
+    #[test]
+    fn test_stdin_takeover() {
+        let app: MockApplication = MockApplication::new();
+        let screen_creds = app.add_screen(&ScreenID::parse("screen1").unwrap());
+        let dispatch = MockDispatch::new(app.clone());
+        let hello = crate::msg::posix::StdinHello {
+            secret: screen_creds.stdin_secret(),
+        };
+
+        let mut conv1 = Conversation::with_dispatch(&dispatch);
+        conv1.send_message(&hello);
+        assert_eq!(conv1.connection().state().type_name(), "Stdin");
+
+        //a second connection with the same secret displaces the first one
+        let mut conv2 = Conversation::with_dispatch(&dispatch);
+        conv2.send_message(&hello);
+        assert_eq!(conv2.connection().state().type_name(), "Stdin");
+        conv1.expect_no_reply();
+        assert_eq!(conv1.connection().state().type_name(), "Teardown");
+        assert_eq!(
+            conv1.connection().teardown_reason(),
+            Some(server::TeardownReason::DisplacedByTakeover)
+        );
+
+        //the screen is still attached to the second connection...
+        let secret = screen_creds.stdin_secret();
+        assert!(server::Application::authorize_stdin(&app, secret).is_none());
+
+        //...until that connection is torn down, too (the first reason sticks)
+        assert_eq!(conv2.connection().teardown_reason(), None);
+        conv2
+            .connection_mut()
+            .set_state(server::ConnectionState::Teardown);
+        conv2
+            .connection_mut()
+            .tear_down(server::TeardownReason::ServerShutdown);
+        assert_eq!(
+            conv2.connection().teardown_reason(),
+            Some(server::TeardownReason::Unspecified)
+        );
+        assert!(server::Application::authorize_stdin(&app, secret).is_some());
+    }
+
+    #[test]
+    fn test_resumed_session() {
+        let app: MockApplication = MockApplication::new();
+        let screen_id = ScreenID::parse("screen1").unwrap();
+        let identity = ClientIdentity::new(&ClientID::parse("a").unwrap()).with_stdin(&screen_id);
+        let creds = server::Application::register_client(&app, identity);
+        let hello = crate::msg::posix::ClientHello {
+            secret: creds.secret(),
+        };
+        Conversation::new(app.clone())
+            .send_message(&hello)
+            .expect(r#"(posix1.server-hello a screen1 "" "")"#);
+
+        //the secret can only be used again if the application resumes the session
+        let mut conv = Conversation::new(app.clone());
+        conv.send_message(&hello).expect_no_reply();
+        assert_eq!(conv.connection().state().type_name(), "Teardown");
+
+        app.allow_resume(ClientID::parse("a").unwrap());
+        Conversation::new(app)
+            .send_message(&hello)
+            .expect(r#"(posix1.server-hello a screen1 "" "")"#);
+    }
 }
@@ -5,14 +5,13 @@
 *******************************************************************************/
 
 use crate::common::core::msg::DecodeMessage;
-use crate::common::core::{msg, ModuleIdentifier, OwnedClientID};
+use crate::common::core::{msg, ModuleIdentifier, OwnedClientID, ScopedIdentifier};
 use crate::msg::core::*;
 use crate::msg::{Have, Want};
 use crate::server;
 use crate::server::HandlerError::InvalidMessage;
-use crate::server::{
-    ClientIdentity, ClientSelector, ConnectionState, MessageConnector, MessageHandler as _,
-};
+use crate::server::{ClientIdentity, ClientSelector, ConnectionState, MessageConnector};
+use std::collections::HashSet;
 
 ///Extension trait for [message handlers](../trait.MessageHandler.html).
 ///
@@ -25,8 +24,229 @@ use crate::server::{
 ///handler in the chain when they cannot give a definitive answer. The last handler in a chain will
 ///usually deny any requests not answered earlier.
 pub trait MessageHandlerExt<A: server::Application>: server::MessageHandler<A> {
-    //NOTE: This is currently empty, but I'm leaving it here because there will be messages here to
-    //handle core1.{set,sub} later.
+    ///Handles a `core1.sub` message (if `requested_value` is `None`) or a `core1.set` message (if
+    ///`requested_value` is `Some`) for the property with the given name.
+    ///
+    ///If the property is known to this handler, the current value of the property (after the
+    ///requested change was applied, if any) shall be returned in encoded form. The caller will
+    ///send it to the client in a `core1.pub` message. If the requested value is not acceptable,
+    ///the handler shall leave the property unchanged and return its current value.
+    ///
+    ///If the value of the property changed because of this call, the handler is responsible for
+    ///publishing the new value to other subscribers, e.g. with
+    ///[publish_property()](fn.publish_property.html). The client that sent the `core1.set` should
+    ///be left out there, since it receives the new value in the reply already.
+    ///
+    ///The default implementation does not know any properties and returns `None`, so the caller
+    ///rejects the message.
+    fn handle_property<D: server::Dispatch<A>>(
+        &self,
+        _name: &ScopedIdentifier<'_>,
+        _requested_value: Option<&[u8]>,
+        _conn: &mut server::Connection<A, D>,
+    ) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+///Sends a `core1.pub` message with the given property value to all clients that have subscribed
+///to this property and whose identity matches the given predicate. The messages are sent through
+///[`Dispatch::enqueue_broadcast()`](../trait.Dispatch.html#tymethod.enqueue_broadcast).
+///
+///To publish several properties that change together, use a
+///[PropertyTransaction](struct.PropertyTransaction.html) instead.
+///
+///# Panics
+///
+///Panics if `name` is not a valid scoped identifier.
+pub fn publish_property<A, D, P>(dispatch: &D, name: &str, value: &[u8], predicate: P)
+where
+    A: server::Application,
+    D: server::Dispatch<A>,
+    P: Fn(&ClientIdentity) -> bool + Send + Sync + 'static,
+{
+    let mut tx = PropertyTransaction::new();
+    tx.publish(name, value, predicate);
+    tx.commit(dispatch);
+}
+
+///A set of property changes that is published to subscribers as a whole.
+///
+///Some properties only make sense together, e.g. the width and height of a screen during a
+///resize. When their new values are published with separate calls to
+///[publish_property()](fn.publish_property.html), other broadcasts can end up between them, so a
+///client may observe the new width together with the old height. A transaction collects all
+///changes first, and [commit()](#method.commit) then sends all `core1.pub` messages for a client
+///in one go, without anything else in between.
+///
+///```no_run
+///# use vt6::common::core::EncodeArgument;
+///# use vt6::server::core::PropertyTransaction;
+///# use vt6::server::{ClientIdentity, Dispatch};
+///# fn example(dispatch: impl Dispatch<vt6::server::testing::MockApplication>) {
+///# let (width, height) = (80u32, 25u32);
+///# let is_on_screen = |_: &ClientIdentity| true;
+///let mut tx = PropertyTransaction::new();
+///tx.publish("example1.width", &width.encode_to_vector(), is_on_screen.clone());
+///tx.publish("example1.height", &height.encode_to_vector(), is_on_screen);
+///tx.commit(&dispatch);
+///# }
+///```
+#[derive(Default)]
+pub struct PropertyTransaction {
+    changes: Vec<PropertyChange>,
+}
+
+struct PropertyChange {
+    name: String,
+    value: Vec<u8>,
+    predicate: Box<dyn Fn(&ClientIdentity) -> bool + Send + Sync>,
+}
+
+impl PropertyTransaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Adds a property value to this transaction. When the transaction is committed, it will be
+    ///sent to all clients that have subscribed to this property and whose identity matches the
+    ///given predicate, like with [publish_property()](fn.publish_property.html). If the same
+    ///property was already added to this transaction, the previous value is replaced.
+    ///
+    ///# Panics
+    ///
+    ///Panics if `name` is not a valid scoped identifier.
+    pub fn publish<P>(&mut self, name: &str, value: &[u8], predicate: P) -> &mut Self
+    where
+        P: Fn(&ClientIdentity) -> bool + Send + Sync + 'static,
+    {
+        let name: String = ScopedIdentifier::parse(name)
+            .expect("PropertyTransaction::publish() called with invalid property name")
+            .as_str()
+            .into();
+        self.changes.retain(|c| c.name != name);
+        self.changes.push(PropertyChange {
+            name,
+            value: value.into(),
+            predicate: Box::new(predicate),
+        });
+        self
+    }
+
+    ///Returns whether no properties have been added to this transaction.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    ///Sends the `core1.pub` messages for all properties in this transaction through a single
+    ///[`Dispatch::enqueue_broadcast_to()`](../trait.Dispatch.html#method.enqueue_broadcast_to),
+    ///which only visits the connections that are listed as subscribers of any of these properties
+    ///in [`Dispatch::subscriptions()`](../trait.Dispatch.html#tymethod.subscriptions). Each client
+    ///receives the messages for all matching properties directly after each other, in the order in
+    ///which they were added to the transaction.
+    pub fn commit<A, D>(self, dispatch: &D)
+    where
+        A: server::Application,
+        D: server::Dispatch<A>,
+    {
+        let subscriptions = dispatch.subscriptions();
+        let mut seen = HashSet::new();
+        let targets: Vec<D::ConnectionID> = self
+            .changes
+            .iter()
+            .flat_map(|change| subscriptions.subscribers(&change.name))
+            .filter(|id| seen.insert(id.clone()))
+            .collect();
+        if targets.is_empty() {
+            return;
+        }
+        let changes = self.changes;
+        dispatch.enqueue_broadcast_to(
+            targets,
+            Box::new(move |conn| {
+                let identity = match conn.state() {
+                    ConnectionState::Msgio(ref connector) => connector.identity(),
+                    _ => return,
+                };
+                let selected: Vec<&PropertyChange> = changes
+                    .iter()
+                    .filter(|c| conn.is_subscribed(&c.name) && (c.predicate)(identity))
+                    .collect();
+                for change in selected {
+                    conn.enqueue_message(&Pub {
+                        name: ScopedIdentifier::parse(&change.name).unwrap(),
+                        value: &change.value,
+                    });
+                }
+            }),
+        );
+    }
+}
+
+///Registers the clients requested by `core1.client-make` messages (or a future batched
+///equivalent) on behalf of the client on `conn`, and returns their credentials in the same order
+///as the requests.
+///
+///The whole batch is validated before anything is registered: Each new client ID must be below the
+///requesting client's ID and must not be in use yet, and no client in the batch may be below
+///another one. If any request violates these rules, no client is registered at all and
+///`HandlerError::InvalidMessage` is returned. Otherwise, the batch is handed to
+///[`Application::register_clients()`](../trait.Application.html#method.register_clients).
+///
+///# Panics
+///
+///Panics if the connection is not in the message-passing state.
+pub fn make_clients<A, D>(
+    conn: &mut server::Connection<A, D>,
+    requests: &[ClientMake<'_>],
+) -> Result<Vec<server::ClientCredentials>, server::HandlerError>
+where
+    A: server::Application,
+    D: server::Dispatch<A>,
+{
+    let connector = conn.message_connector().unwrap();
+    let parent_id = OwnedClientID::from(&connector.identity().client_id());
+    let parent = ClientSelector::StrictlyBelow(parent_id.as_ref());
+    let d = conn.dispatch();
+    let app = d.application();
+    for (idx, req) in requests.iter().enumerate() {
+        //new client ID must be below this client's ID
+        if !parent.contains(req.client_id) {
+            return Err(InvalidMessage);
+        }
+        //client ID must not be in use yet
+        let selector = ClientSelector::AtOrBelow(req.client_id);
+        if app.has_clients(selector.clone()) {
+            return Err(InvalidMessage);
+        }
+        //clients in the same batch must not overlap either
+        let overlaps = requests[0..idx].iter().any(|other| {
+            selector.contains(other.client_id)
+                || ClientSelector::AtOrBelow(other.client_id).contains(req.client_id)
+        });
+        if overlaps {
+            return Err(InvalidMessage);
+        }
+    }
+
+    //convert ClientMake msgs into server::ClientIdentity
+    let ids = requests
+        .iter()
+        .map(|req| {
+            let mut id = ClientIdentity::new(&req.client_id);
+            if let Some(ref sid) = req.stdin_screen_id {
+                id = id.with_stdin(sid);
+            }
+            if let Some(ref sid) = req.stdout_screen_id {
+                id = id.with_stdout(sid);
+            }
+            if let Some(ref sid) = req.stderr_screen_id {
+                id = id.with_stderr(sid);
+            }
+            id
+        })
+        .collect();
+    Ok(app.register_clients(ids))
 }
 
 ///A [MessageHandler](../trait.MessageHandler.html) covering all messages defined in
@@ -48,6 +268,13 @@ impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::M
             _ => self.0.get_supported_module_version(module),
         }
     }
+
+    fn get_module_deprecation(
+        &self,
+        module: &ModuleIdentifier<'_>,
+    ) -> Option<server::ModuleDeprecation> {
+        self.0.get_module_deprecation(module)
+    }
 }
 
 impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::Handler<A>
@@ -58,11 +285,10 @@ impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::H
         msg: &msg::Message,
         conn: &mut server::Connection<A, D>,
     ) -> Result<(), server::HandlerError> {
-        //TODO handle core1.sub and core1.set (deferred until we have an actual property)
         match msg.parsed_type().as_str() {
             "want" => {
                 let Want(module_id) = Want::decode_message(msg).ok_or(InvalidMessage)?;
-                let result = self.get_supported_module_version(&module_id);
+                let result = conn.negotiate_module(self, &module_id);
                 let reply = match result {
                     Some(v) => Have::ThisModule(module_id.with_minor_version(v)),
                     None => Have::NotThisModule(module_id),
@@ -72,36 +298,10 @@ impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::H
             }
             "core1.client-make" => {
                 let msg = ClientMake::decode_message(msg).ok_or(InvalidMessage)?;
-                let connector = conn.message_connector().unwrap();
-
-                //new client ID must be below this client's ID
-                let selector = ClientSelector::StrictlyBelow(connector.identity().client_id());
-                if !selector.contains(msg.client_id) {
-                    return Err(InvalidMessage);
-                }
-                //client ID must not be in use yet
-                let d = conn.dispatch();
-                let selector = ClientSelector::AtOrBelow(msg.client_id);
-                if d.application().has_clients(selector) {
-                    return Err(InvalidMessage);
-                }
-
-                //convert ClientMake msg into server::ClientIdentity
-                let mut id = ClientIdentity::new(&msg.client_id);
-                if let Some(sid) = msg.stdin_screen_id {
-                    id = id.with_stdin(sid);
-                }
-                if let Some(sid) = msg.stdout_screen_id {
-                    id = id.with_stdout(sid);
-                }
-                if let Some(sid) = msg.stderr_screen_id {
-                    id = id.with_stderr(sid);
-                }
-
                 //register client and send secret to registrar
-                let creds = d.application().register_client(id);
+                let creds = make_clients(conn, &[msg])?;
                 let reply = ClientNew {
-                    secret: creds.secret(),
+                    secret: creds[0].secret(),
                 };
                 conn.enqueue_message(&reply);
                 Ok(())
@@ -122,13 +322,43 @@ impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::H
                     let selector = ClientSelector::AtOrBelow(owned_client_id.as_ref());
                     if let ConnectionState::Msgio(ref connector) = conn.state() {
                         if selector.contains(connector.identity().client_id()) {
-                            conn.set_state(ConnectionState::Teardown);
+                            conn.tear_down(server::TeardownReason::LifetimeEnded);
                         }
                     }
                 }));
                 Ok(())
             }
-            "posix1.stdin-hello" | "posix1.stdout-hello" | "posix1.client-hello" => {
+            "core1.sub" => {
+                //unknown properties are rejected with `nope`
+                let msg = Sub::decode_message(msg).ok_or(InvalidMessage)?;
+                let value = self
+                    .0
+                    .handle_property(&msg.name, None, conn)
+                    .ok_or(InvalidMessage)?;
+                conn.subscribe(&msg.name);
+                conn.enqueue_message(&Pub {
+                    name: msg.name,
+                    value: &value,
+                });
+                Ok(())
+            }
+            "core1.set" => {
+                let msg = Set::decode_message(msg).ok_or(InvalidMessage)?;
+                let value = self
+                    .0
+                    .handle_property(&msg.name, Some(msg.requested_value), conn)
+                    .ok_or(InvalidMessage)?;
+                conn.enqueue_message(&Pub {
+                    name: msg.name,
+                    value: &value,
+                });
+                Ok(())
+            }
+            "posix1.stdin-hello"
+            | "posix1.padded-stdin-hello"
+            | "posix1.stdout-hello"
+            | "posix1.stdout-mux-hello"
+            | "posix1.client-hello" => {
                 //these message types exist, but they are only allowed during the handshake phase
                 Err(InvalidMessage)
             }
@@ -143,4 +373,110 @@ impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::H
     ) {
         self.0.handle_error(err, conn);
     }
+
+    fn on_connect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_connect(conn);
+    }
+
+    fn on_state_change<D: server::Dispatch<A>>(
+        &self,
+        old_state: &server::ConnectionState<A>,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.on_state_change(old_state, conn);
+    }
+
+    fn on_disconnect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_disconnect(conn);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::{ClientID, ScreenID};
+    use crate::server::testing::{Conversation, MockApplication};
+
+    fn connect(app: &MockApplication) -> Conversation<MockApplication> {
+        let creds = server::Application::register_client(
+            app,
+            ClientIdentity::new(&ClientID::parse("a").unwrap()),
+        );
+        let mut conv = Conversation::new(app.clone());
+        conv.send_message(&crate::msg::posix::ClientHello {
+            secret: creds.secret(),
+        })
+        .expect(r#"(posix1.server-hello a "" "" "")"#);
+        conv
+    }
+
+    #[test]
+    fn test_make_clients() {
+        let app: MockApplication = MockApplication::new();
+        let mut conv = connect(&app);
+        server::Application::register_client(
+            &app,
+            ClientIdentity::new(&ClientID::parse("ac").unwrap()),
+        );
+
+        let screen_id = ScreenID::parse("screen1").unwrap();
+        let make = |id: &'static str| ClientMake {
+            client_id: ClientID::parse(id).unwrap(),
+            stdin_screen_id: None,
+            stdout_screen_id: Some(screen_id),
+            stderr_screen_id: None,
+        };
+
+        //if any client in the batch cannot be registered, none of them are
+        for batch in &[
+            vec![make("ab"), make("b")],   //not below the requester
+            vec![make("ab"), make("ac")],  //already in use
+            vec![make("ab"), make("abd")], //below another client in the batch
+            vec![make("abd"), make("ab")],
+        ] {
+            let result = make_clients(conv.connection_mut(), batch);
+            assert!(matches!(result, Err(InvalidMessage)));
+            assert_eq!(app.registered_clients().len(), 2);
+        }
+
+        let creds = make_clients(conv.connection_mut(), &[make("ab"), make("ad")]);
+        assert_eq!(creds.unwrap().len(), 2);
+        let clients = app.registered_clients();
+        assert_eq!(clients.len(), 4);
+        assert_eq!(clients[2].client_id().as_str(), "ab");
+        assert_eq!(clients[3].client_id().as_str(), "ad");
+        assert_eq!(clients[3].stdout_screen_id(), Some(screen_id));
+    }
+
+    #[test]
+    fn test_property_transaction() {
+        let app: MockApplication = MockApplication::new();
+        let mut conv = connect(&app);
+        for name in &["example1.width", "example1.height", "example1.depth"] {
+            conv.connection_mut()
+                .subscribe(&ScopedIdentifier::parse(name).unwrap());
+        }
+
+        //changes published in the meantime are sent before the transaction, not in between
+        let mut tx = PropertyTransaction::new();
+        tx.publish("example1.width", b"80", |_| true);
+        publish_property(&conv.dispatch(), "example1.depth", b"8", |_| true);
+        tx.publish("example1.height", b"24", |_| true)
+            .publish("example1.width", b"120", |_| true)
+            .publish("example1.title", b"", |_| true)
+            .publish("example1.depth", b"24", |i| i.stdout_screen_id().is_some());
+        assert!(!tx.is_empty());
+        tx.commit(&conv.dispatch());
+
+        //only subscribed properties with matching predicates are published, and the later width
+        //replaces the earlier one
+        assert_eq!(
+            conv.replies(),
+            vec![
+                "(core1.pub example1.depth 8)",
+                "(core1.pub example1.height 24)",
+                "(core1.pub example1.width 120)",
+            ]
+        );
+    }
 }
@@ -0,0 +1,97 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::{msg, ModuleIdentifier, ScopedIdentifier};
+use crate::server;
+
+const MODULE: &str = "deflate1";
+
+///A [MessageHandler](../trait.MessageHandler.html) for the `deflate1` module, which allows large
+///arguments to be sent in compressed form. See
+///[vt6::common::CompressedBytes](../../common/struct.CompressedBytes.html) for how this works.
+///
+///This module does not define any messages of its own, so this handler only announces support
+///for the module to clients. Handlers that want to send compressed arguments must check with
+///[is_agreed()](fn.is_agreed.html) that the client has negotiated the module first.
+#[derive(Default)]
+pub struct MessageHandler<Next>(Next);
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::MessageHandler<A>
+    for MessageHandler<Next>
+{
+    fn get_supported_module_version(&self, module: &ModuleIdentifier<'_>) -> Option<u16> {
+        match module.as_str() {
+            MODULE => Some(0),
+            _ => self.0.get_supported_module_version(module),
+        }
+    }
+
+    fn get_module_deprecation(
+        &self,
+        module: &ModuleIdentifier<'_>,
+    ) -> Option<server::ModuleDeprecation> {
+        self.0.get_module_deprecation(module)
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::Handler<A>
+    for MessageHandler<Next>
+{
+    fn handle<D: server::Dispatch<A>>(
+        &self,
+        msg: &msg::Message,
+        conn: &mut server::Connection<A, D>,
+    ) -> Result<(), server::HandlerError> {
+        self.0.handle(msg, conn)
+    }
+
+    fn handle_error<D: server::Dispatch<A>>(
+        &self,
+        err: &msg::ParseError,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.handle_error(err, conn);
+    }
+
+    fn on_connect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_connect(conn);
+    }
+
+    fn on_state_change<D: server::Dispatch<A>>(
+        &self,
+        old_state: &server::ConnectionState<A>,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.on_state_change(old_state, conn);
+    }
+
+    fn on_disconnect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_disconnect(conn);
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>>
+    server::core::MessageHandlerExt<A> for MessageHandler<Next>
+{
+    fn handle_property<D: server::Dispatch<A>>(
+        &self,
+        name: &ScopedIdentifier<'_>,
+        requested_value: Option<&[u8]>,
+        conn: &mut server::Connection<A, D>,
+    ) -> Option<Vec<u8>> {
+        self.0.handle_property(name, requested_value, conn)
+    }
+}
+
+///Returns whether the client on this connection has agreed on the `deflate1` module, i.e. whether
+///arguments of type [CompressedBytes](../../common/struct.CompressedBytes.html) may be sent to it.
+pub fn is_agreed<A: server::Application, D: server::Dispatch<A>>(
+    conn: &server::Connection<A, D>,
+) -> bool {
+    //this unwrap() is safe since MODULE is a valid module identifier
+    let module = ModuleIdentifier::parse(MODULE).unwrap();
+    conn.agreed_module_version(&module).is_some()
+}
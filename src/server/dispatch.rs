@@ -4,8 +4,9 @@
 * Refer to the file "LICENSE" for details.
 *******************************************************************************/
 
-use crate::common::core::msg;
+use crate::common::core::{msg, ClientID};
 use crate::server;
+use std::sync::{Arc, Condvar, Mutex};
 
 ///A reference to the IO job or worker thread managing the server socket.
 ///
@@ -15,14 +16,86 @@ use crate::server;
 ///[vt6::server::tokio](tokio/index.html) submodule if the "use_tokio" feature is enabled on the
 ///crate. Similar implementations for other IO libraries may be added in the future, but you can
 ///always provide your own if the ones supplied with this crate don't fit your use case.
-pub trait Dispatch<A: server::Application>: Clone + Sized {
+///
+///# Implementing a Dispatch
+///
+///A Dispatch owns the [Connection](struct.Connection.html) objects for all its client sockets,
+///and drives each of them through the following lifecycle:
+///
+///1. After accepting a client socket, create the Connection with
+///   [`Connection::new()`](struct.Connection.html#method.new), and optionally attach a listener
+///   label and the peer credentials of the socket. Register it under its ID, so that it can be
+///   found by `enqueue_message()`, `enqueue_stdin()` and broadcasts. Then call
+///   [`handle_connect()`](struct.Connection.html#method.handle_connect).
+///2. Read from the socket into a [ReceiveBuffer](trait.ReceiveBuffer.html), and pass it into
+///   [`handle_incoming()`](struct.Connection.html#method.handle_incoming), which consumes
+///   everything that it could process. Add the number of bytes read to
+///   [`stats_mut().bytes_received`](struct.ConnectionStats.html#structfield.bytes_received), and
+///   likewise for `bytes_sent` when writing.
+///3. While [`is_reading_paused()`](struct.Connection.html#method.is_reading_paused) is true, do
+///   not read from the socket, so that the client is slowed down by the socket buffer. Reading
+///   resumes after [`resume_reading()`](struct.Connection.html#method.resume_reading), which is
+///   usually called from within a broadcast.
+///4. When the client disconnects or the socket fails, call
+///   [`tear_down()`](struct.Connection.html#method.tear_down) with the respective
+///   [TeardownReason](enum.TeardownReason.html). Once the state is `Teardown` (which handlers
+///   can also cause), write out what is left in the send buffers if desired, close the socket,
+///   and drop the Connection. Dropping a Connection in any other state skips the cleanup of its
+///   attachments and subscriptions.
+///
+///Handlers run inside `handle_incoming()` and `handle_connect()` with a `&mut Connection`, and
+///call back into the Dispatch from there. Implementations therefore need to observe the following
+///rules to avoid deadlocks:
+///
+///* `enqueue_message()` and `enqueue_stdin()` receive the `&mut Connection` that the caller
+///  already holds. They must only append to the send buffer of that connection, and must not try
+///  to obtain access to any Connection (including this one) by themselves.
+///* `enqueue_broadcast()` and `enqueue_broadcast_to()` must not run the action right away, since
+///  the caller may be holding a `&mut Connection`. Actions run later, when the Dispatch can hand
+///  out each Connection in turn, e.g. after `handle_incoming()` has returned.
+///* Messages for each connection must be sent in the order in which they were enqueued (see
+///  below).
+///* `application()` may be called at any time, including from within handlers, and
+///  [`Application::notify()`](trait.Application.html#tymethod.notify) is called synchronously on
+///  whichever thread is running the Connection method in question.
+///
+///The [MockDispatch](testing/struct.MockDispatch.html) in the testing module is a small
+///implementation that runs without any IO, and can serve as a starting point.
+///
+///# Message ordering
+///
+///Messages for a connection are sent in the order in which they were enqueued, regardless of
+///whether they were enqueued directly with `enqueue_message()` or by a broadcast action. For
+///this purpose, a message that is enqueued by a broadcast action counts as enqueued at the time
+///when the broadcast was enqueued, and broadcasts are executed in the order in which they were
+///enqueued. For example, when an application thread publishes a property value with a
+///broadcast, and a handler that is running at the same time on a subscribed connection sends a
+///reply afterwards, the subscriber sees the new value before the reply, even though the
+///broadcast can only be executed once the handler has returned.
+///
+///Consequently, an implementation that executes broadcasts later than their enqueueing must hold
+///back the messages that are enqueued directly in the meantime until the preceding broadcasts
+///have been executed. There is no ordering between different connections, nor between messages
+///and stdin.
+pub trait Dispatch<A: server::Application>: Clone + Sized + 'static {
     ///The dispatch assigns a unique ID of this type to every [Connection](struct.Connection.html)
-    ///managed by it.
-    type ConnectionID: Clone + Send + Sync;
+    ///managed by it. The Debug representation of the ID appears in logs, e.g. in the tracing
+    ///spans emitted with the `use_tracing` feature. Handlers compare IDs to recognize a specific
+    ///connection within a broadcast.
+    type ConnectionID: Clone + Eq + core::hash::Hash + Send + Sync + core::fmt::Debug + 'static;
 
     ///A reference to the application core.
     fn application(&self) -> &A;
 
+    ///The registry of connections that are attached to screens as their stdin or stdout. The
+    ///Dispatch only needs to hold on to it; it is kept up to date by the
+    ///[Connection](struct.Connection.html).
+    fn attachments(&self) -> &server::Attachments<Self::ConnectionID>;
+
+    ///The registry of property subscriptions. Like with `attachments()`, the Dispatch only needs
+    ///to hold on to it, and the [Connection](struct.Connection.html) keeps it up to date.
+    fn subscriptions(&self) -> &server::Subscriptions<Self::ConnectionID>;
+
     ///Registers a broadcast action.
     ///
     ///When handling input or requests sent by a client, the respective handler only has a
@@ -39,15 +112,85 @@ pub trait Dispatch<A: server::Application>: Clone + Sized {
     ///question and we cannot get a second mutable reference to it. What the handler actually does
     ///is to enqueue a broadcast action. The dispatch takes ownership of the action and executes it
     ///as soon as all `&mut Connection` references have been returned to it.
+    #[allow(clippy::type_complexity)]
     fn enqueue_broadcast(
         &self,
         action: Box<dyn Fn(&mut server::Connection<A, Self>) + Send + Sync>,
     );
 
+    ///Like `enqueue_broadcast()`, but the action only runs on the connections with the given IDs.
+    ///This is used when the recipients are already known, e.g. when publishing a property value
+    ///to the subscribers found in `subscriptions()`. IDs of connections that do not exist
+    ///(anymore) are ignored.
+    ///
+    ///The default implementation enqueues a broadcast to all connections that checks the ID of
+    ///each connection. Implementations should override this if they can look up connections by ID
+    ///directly.
+    #[allow(clippy::type_complexity)]
+    fn enqueue_broadcast_to(
+        &self,
+        targets: Vec<Self::ConnectionID>,
+        action: Box<dyn Fn(&mut server::Connection<A, Self>) + Send + Sync>,
+    ) {
+        self.enqueue_broadcast(Box::new(move |conn| {
+            if targets.contains(&conn.id()) {
+                action(conn);
+            }
+        }));
+    }
+
+    ///Runs the given query on all connections and collects the results that are not `None`.
+    ///
+    ///This is built on top of `enqueue_broadcast()`, so the same restrictions apply: If the
+    ///dispatch cannot execute the broadcast right away (e.g. because a handler is currently
+    ///holding a `&mut Connection`), the results only become available later. The returned
+    ///[ConnectionQuery](struct.ConnectionQuery.html) can be used to check whether the results are
+    ///complete, or to wait for them.
+    ///
+    ///# Examples
+    ///
+    ///To count the clients that are currently connected in msgio mode (e.g. for a status display):
+    ///
+    ///```no_run
+    ///# use vt6::server::testing::MockApplication;
+    ///# use vt6::server::{Dispatch, MessageConnector};
+    ///# fn example(dispatch: &impl Dispatch<MockApplication>) {
+    ///let query = dispatch.query_connections(|conn| {
+    ///    conn.message_connector().map(|c| c.identity().client_id().as_str().to_owned())
+    ///});
+    ///if let Ok(client_ids) = query.try_wait() {
+    ///    println!("{} clients connected", client_ids.len());
+    ///}
+    ///# }
+    ///```
+    fn query_connections<T, F>(&self, query: F) -> ConnectionQuery<T>
+    where
+        T: Send + 'static,
+        F: Fn(&mut server::Connection<A, Self>) -> Option<T> + Send + Sync + 'static,
+    {
+        let state = Arc::new(QueryState {
+            results: Mutex::new((Vec::new(), false)),
+            complete: Condvar::new(),
+        });
+        //the dispatch drops the broadcast action once it has run on all connections, so the
+        //collector's Drop impl marks the results as complete
+        let collector = QueryCollector(state.clone());
+        self.enqueue_broadcast(Box::new(move |conn| {
+            if let Some(result) = query(conn) {
+                collector.0.results.lock().unwrap().0.push(result);
+            }
+        }));
+        ConnectionQuery(state)
+    }
+
     ///Writes a message into the send buffer of the given connection.
     ///
     ///Calls are only allowed when `conn.state()` is `Handshake` or `Msgio`. If this condition is
-    ///not met, the implementation may choose to ignore the message or to panic.
+    ///not met, or if the message cannot be encoded, the implementation shall not panic in release
+    ///builds, so that a buggy handler does not take down the entire server. Instead, the message
+    ///is dropped and a [MessageDropped](enum.Notification.html#variant.MessageDropped)
+    ///notification is reported. When debug assertions are enabled, calling this in the wrong
+    ///state panics, so that such bugs are noticed during development.
     ///
     ///You need a `&mut Connection` reference to call this, so this method can easily be called
     ///inside [handlers](trait.Handler.html). If you want to send messages while not handling a
@@ -61,8 +204,9 @@ pub trait Dispatch<A: server::Application>: Clone + Sized {
 
     ///Writes standard input into the send buffer of the given connection.
     ///
-    ///Calls are only alowed when `conn.state()` is `Stdin`. If this condition is not met, the
-    ///implementation may choose to ignore the message or to panic.
+    ///Calls are only allowed when `conn.state()` is `Stdin`. If this condition is not met, the
+    ///same rules as for `enqueue_message()` apply, except that the input is reported in a
+    ///[StdinDropped](enum.Notification.html#variant.StdinDropped) notification.
     ///
     ///You need a `&mut Connection` reference to call this, so you probably need to
     ///`enqueue_broadcast()` your request and have the dispatch get back to you when it's ready to
@@ -74,7 +218,8 @@ pub trait Dispatch<A: server::Application>: Clone + Sized {
     ///
     ///```ignore
     ///let buf: Vec<u8> = "hello stdin".into();
-    ///let screen = vt6::server::ScreenIdentity::new("example");
+    ///let screen_id = vt6::common::core::ScreenID::parse("example").unwrap();
+    ///let screen = vt6::server::ScreenIdentity::new(&screen_id);
     ///dispatch.enqueue_broadcast(Box::new(move |conn| {
     ///    if conn.state().can_receive_stdin_for_screen(&screen) {
     ///        conn.enqueue_stdin(&buf);
@@ -82,4 +227,346 @@ pub trait Dispatch<A: server::Application>: Clone + Sized {
     ///}));
     ///```
     fn enqueue_stdin(&self, conn: &mut server::Connection<A, Self>, buf: &[u8]);
+
+    ///Continues reading stdout from the client whose stdout is connected to the given screen,
+    ///after the screen's [StdoutConnector](trait.StdoutConnector.html) has paused reading by
+    ///returning false from `is_ready()`. This is a shorthand for calling
+    ///[`Connection::resume_reading()`](struct.Connection.html#method.resume_reading) on the
+    ///respective connection through a broadcast.
+    fn resume_stdout(&self, screen: &server::ScreenIdentity) {
+        let screen = screen.clone();
+        self.enqueue_broadcast(Box::new(move |conn| {
+            if conn.stdout_connector_for(&screen).is_some() {
+                conn.resume_reading();
+            }
+        }));
+    }
+
+    ///Sends a message to the client with the given ID, e.g. from inside a handler that needs to
+    ///tell another client about something. The message goes to all connections where that client
+    ///is connected in msgio mode; if there are none, it is dropped silently.
+    ///
+    ///This is built on top of `enqueue_broadcast()`, which is what makes it safe to call from
+    ///anywhere: The message is encoded right away, so the caller does not need to keep anything
+    ///alive, and it is written into the recipient's send buffer only once the dispatch can hand
+    ///out a `&mut Connection` for it. Since this method never waits for that to happen, it cannot
+    ///deadlock, even when called by a handler that is holding the `&mut Connection` of the
+    ///recipient (i.e. when a client sends a message to itself) or from within a broadcast action.
+    ///The flip side is that the message is delivered only after the current handler returns, so
+    ///replies that the handler enqueues on its own connection may arrive first.
+    ///
+    ///```no_run
+    ///# use vt6::common::core::{msg::EncodeMessage, ClientID};
+    ///# use vt6::server::{Application, Connection, Dispatch};
+    ///# fn example<A, D, M>(conn: &Connection<A, D>, msg: M)
+    ///# where A: Application, D: Dispatch<A>, M: EncodeMessage {
+    ///let d = conn.dispatch();
+    ///d.enqueue_message_for_client(ClientID::parse("a1").unwrap(), &msg);
+    ///# }
+    ///```
+    fn enqueue_message_for_client<M: msg::EncodeMessage>(&self, client_id: ClientID<'_>, msg: &M) {
+        let size = msg.encoded_size();
+        let mut buf = vec![0u8; size];
+        let len = match msg.encode(&mut buf) {
+            Ok(len) => len,
+            Err(_) => {
+                let n = server::Notification::MessageDropped {
+                    listener: None,
+                    size,
+                    reason: server::DropReason::MessageTooLong,
+                };
+                return self.notify(&n);
+            }
+        };
+        buf.truncate(len);
+        let encoded = PreEncodedMessage(buf);
+        let client_id = client_id.as_str().to_owned();
+        self.enqueue_broadcast(Box::new(move |conn| {
+            use server::MessageConnector;
+            let is_match = match conn.message_connector() {
+                Some(c) => c.identity().client_id().as_str() == client_id,
+                None => false,
+            };
+            if is_match {
+                conn.enqueue_message(&encoded);
+            }
+        }));
+    }
+
+    #[cfg(feature = "module_input")]
+    ///Delivers text that the user pasted into the given screen to the client running in it. See
+    ///[vt6::server::input::send_paste()](input/fn.send_paste.html) for details.
+    fn send_paste(&self, screen: &server::ScreenIdentity, text: &str) {
+        server::input::send_paste(self, screen, text)
+    }
+
+    ///Returns the minimum interval between two
+    ///[`Notification::IncomingBytesDiscarded`](enum.Notification.html#variant.IncomingBytesDiscarded)
+    ///for the same connection. Input that is discarded within this interval after a notification
+    ///is aggregated into the next notification, which is sent when input is discarded after the
+    ///interval has passed, or when the connection is torn down.
+    ///
+    ///The default implementation returns one second.
+    fn discard_notification_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(1)
+    }
+
+    ///Returns how much invalid input connections tolerate during the handshake before they are
+    ///torn down. See [HandshakeTolerance](struct.HandshakeTolerance.html) for details.
+    ///
+    ///The default implementation returns `HandshakeTolerance::STRICT`.
+    fn handshake_tolerance(&self) -> server::HandshakeTolerance {
+        server::HandshakeTolerance::STRICT
+    }
+
+    ///Returns how long a handler may take to handle a single message before the Application is
+    ///warned through
+    ///[`Notification::SlowHandler`](enum.Notification.html#variant.SlowHandler). Handlers run
+    ///while the Dispatch holds on to the connection, so a slow handler delays all further input
+    ///on that connection, and possibly other connections as well. Regardless of this setting, the
+    ///time spent in handlers is accumulated in
+    ///[`ConnectionStats::handler_time`](struct.ConnectionStats.html#structfield.handler_time).
+    ///
+    ///The default implementation returns `None`, i.e. no warnings are reported.
+    fn slow_handler_threshold(&self) -> Option<std::time::Duration> {
+        None
+    }
+
+    ///Returns which notifications are passed on to the Application by `notify()`.
+    ///
+    ///The default implementation returns `NotificationFilter::ALL`.
+    fn notification_filter(&self) -> server::NotificationFilter {
+        server::NotificationFilter::ALL
+    }
+
+    ///Passes the given notification on to
+    ///[`Application::notify()`](trait.Application.html#tymethod.notify), unless its kind is not
+    ///contained in `notification_filter()`. All notifications from within this crate are sent
+    ///through this method, so implementations of this trait should not override it.
+    fn notify(&self, n: &server::Notification) {
+        if self.notification_filter().accepts(n) {
+            self.application().notify(n);
+        }
+    }
+}
+
+//A message that has already been encoded, for when a message needs to be sent at a later point,
+//but the message type borrows from data that will be gone by then.
+pub(crate) struct PreEncodedMessage(pub(crate) Vec<u8>);
+
+impl msg::EncodeMessage for PreEncodedMessage {
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+        let len = self.0.len();
+        if buf.len() < len {
+            return Err(msg::BufferTooSmallError(len - buf.len()));
+        }
+        buf[0..len].copy_from_slice(&self.0);
+        Ok(len)
+    }
+
+    fn encoded_size(&self) -> usize {
+        self.0.len()
+    }
+}
+
+///How much invalid input a connection tolerates during the handshake. This is returned by
+///[`Dispatch::handshake_tolerance()`](trait.Dispatch.html#method.handshake_tolerance).
+///
+///Invalid input means parse errors as well as messages that the
+///[HandshakeHandler](trait.HandshakeHandler.html) refuses, e.g. a `posix1.client-hello` with an
+///unknown secret. When such an error is tolerated, the client gets a `nope` reply for refused
+///messages (invalid input that could not be parsed is discarded silently), and can try again.
+///Otherwise, the connection is torn down. Note that a handshake may consist of more than one
+///message, since the connection stays in the `Handshake` state until one of the handshake
+///handlers changes it.
+///
+///```
+///# use std::time::Duration;
+///# use vt6::server::HandshakeTolerance;
+/////tolerate up to three errors within the first five seconds
+///let tolerance = HandshakeTolerance::new(3).with_time_budget(Duration::from_secs(5));
+///assert!(tolerance.allows(3, Duration::from_secs(1)));
+///assert!(!tolerance.allows(4, Duration::from_secs(1)));
+///assert!(!tolerance.allows(1, Duration::from_secs(5)));
+///assert!(!HandshakeTolerance::STRICT.allows(1, Duration::from_secs(0)));
+///```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HandshakeTolerance {
+    max_errors: usize,
+    time_budget: Option<std::time::Duration>,
+}
+
+impl HandshakeTolerance {
+    ///Every error during the handshake is fatal. This is the default.
+    pub const STRICT: Self = Self {
+        max_errors: 0,
+        time_budget: None,
+    };
+
+    ///Tolerates up to `max_errors` errors during the handshake. The next error is fatal.
+    pub fn new(max_errors: usize) -> Self {
+        Self {
+            max_errors,
+            time_budget: None,
+        }
+    }
+
+    ///Makes all errors fatal once the given time has passed since the connection was accepted,
+    ///even if fewer than `max_errors` errors occurred up to then.
+    pub fn with_time_budget(mut self, budget: std::time::Duration) -> Self {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    ///Returns whether a connection survives its `error_count`-th error (counting from 1), when
+    ///this error occurs after the connection has been open for the given time.
+    pub fn allows(&self, error_count: usize, elapsed: std::time::Duration) -> bool {
+        error_count <= self.max_errors && self.time_budget.is_none_or(|b| elapsed < b)
+    }
+}
+
+impl Default for HandshakeTolerance {
+    fn default() -> Self {
+        Self::STRICT
+    }
+}
+
+///What a Dispatch does when the data waiting in the send buffers of all its connections would
+///exceed the configured limit. The Application is informed through
+///[`Notification::SendBufferLimitExceeded`](enum.Notification.html#variant.SendBufferLimitExceeded)
+///whenever this happens.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendBufferPolicy {
+    ///The new data is discarded.
+    RejectNew,
+    ///Stdin that is waiting to be sent on any connection is discarded to make room for the new
+    ///data. If that is not enough, the new data is discarded as well.
+    DropBulk,
+    ///The connection with the most data waiting to be sent is closed, and its send buffers are
+    ///discarded. If that is the connection that the new data was meant for, or if there is still
+    ///not enough room afterwards, the new data is discarded as well.
+    CloseMostBacklogged,
+}
+
+///The pending result of [`Dispatch::query_connections()`](trait.Dispatch.html#method.query_connections).
+pub struct ConnectionQuery<T>(Arc<QueryState<T>>);
+
+struct QueryState<T> {
+    //the bool is whether the results are complete
+    results: Mutex<(Vec<T>, bool)>,
+    complete: Condvar,
+}
+
+struct QueryCollector<T>(Arc<QueryState<T>>);
+
+impl<T> Drop for QueryCollector<T> {
+    fn drop(&mut self) {
+        self.0.results.lock().unwrap().1 = true;
+        self.0.complete.notify_all();
+    }
+}
+
+impl<T> ConnectionQuery<T> {
+    ///Returns whether the query has run on all connections.
+    pub fn is_complete(&self) -> bool {
+        self.0.results.lock().unwrap().1
+    }
+
+    ///Returns the results if the query has run on all connections, or gives the query back
+    ///otherwise.
+    pub fn try_wait(self) -> Result<Vec<T>, Self> {
+        let results = {
+            let mut guard = self.0.results.lock().unwrap();
+            if !guard.1 {
+                None
+            } else {
+                Some(std::mem::take(&mut guard.0))
+            }
+        };
+        results.ok_or(self)
+    }
+
+    ///Blocks until the query has run on all connections, and returns the results.
+    ///
+    ///This must not be called from within a handler or broadcast action, or from a thread that
+    ///the dispatch needs to make progress; otherwise it will deadlock.
+    pub fn wait(self) -> Vec<T> {
+        let guard = self.0.results.lock().unwrap();
+        let mut guard = self.0.complete.wait_while(guard, |r| !r.1).unwrap();
+        std::mem::take(&mut guard.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::{MessageType, ModuleIdentifier};
+    use crate::server::testing::{Conversation, MockApplication, MockDispatch};
+    use crate::server::{ClientIdentity, Dispatch};
+
+    #[test]
+    fn test_message_for_client() {
+        let app: MockApplication = MockApplication::new();
+        let dispatch = MockDispatch::new(app.clone());
+        let mut convs: Vec<_> = ["a", "b"]
+            .iter()
+            .map(|id| {
+                let identity = ClientIdentity::new(&ClientID::parse(id).unwrap());
+                let creds = server::Application::register_client(&app, identity);
+                let mut conv = Conversation::with_dispatch(&dispatch);
+                conv.send_message(&crate::msg::posix::ClientHello {
+                    secret: creds.secret(),
+                })
+                .replies();
+                conv
+            })
+            .collect();
+
+        //the message is only delivered to the recipient, and only after the sender's handler has
+        //released its connection
+        let have = crate::msg::Have::NotThisModule(ModuleIdentifier::parse("foo1").unwrap());
+        convs[0]
+            .connection()
+            .dispatch()
+            .enqueue_message_for_client(ClientID::parse("b").unwrap(), &have);
+        convs[0].expect_no_reply();
+        convs[1].expect("(have foo1)").expect_no_reply();
+
+        //messages for unknown clients are dropped
+        dispatch.enqueue_message_for_client(
+            ClientID::parse("c").unwrap(),
+            &crate::msg::Nope(MessageType::Want),
+        );
+        for conv in &mut convs {
+            conv.expect_no_reply();
+        }
+    }
+
+    #[test]
+    fn test_query_connections() {
+        let app: MockApplication = MockApplication::new();
+        let creds = server::Application::register_client(
+            &app,
+            ClientIdentity::new(&ClientID::parse("a").unwrap()),
+        );
+        let mut conv = Conversation::new(app);
+        conv.send_message(&crate::msg::posix::ClientHello {
+            secret: creds.secret(),
+        })
+        .expect(r#"(posix1.server-hello a "" "" "")"#);
+
+        //the test dispatch only runs broadcasts when the conversation advances
+        let query = conv.dispatch().query_connections(|conn| {
+            conn.message_connector().map(|c| {
+                server::MessageConnector::identity(c)
+                    .client_id()
+                    .as_str()
+                    .to_owned()
+            })
+        });
+        let query = query.try_wait().err().unwrap();
+        conv.expect_no_reply();
+        assert!(query.is_complete());
+        assert_eq!(query.wait(), vec!["a".to_owned()]);
+    }
 }
@@ -0,0 +1,152 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg::DecodeMessage;
+use crate::common::core::{msg, ModuleIdentifier, ScopedIdentifier};
+use crate::msg::frame::Enable;
+use crate::server;
+use crate::server::HandlerError::InvalidMessage;
+
+///A [MessageHandler](../trait.MessageHandler.html) for the `frame1` module, which allows clients
+///to switch their msgio connection into framed mode. See
+///[vt6::msg::frame::Enable](../../msg/frame/struct.Enable.html) for how this works.
+///
+///Framing is opt-in: Connections only ever switch into framed mode if this handler is part of the
+///application's handler chain. Once switched, the [Connection](../struct.Connection.html) takes
+///care of decoding incoming frames and encoding outgoing messages as frames.
+#[derive(Default)]
+pub struct MessageHandler<Next>(Next);
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::MessageHandler<A>
+    for MessageHandler<Next>
+{
+    fn get_supported_module_version(&self, module: &ModuleIdentifier<'_>) -> Option<u16> {
+        match module.as_str() {
+            "frame1" => Some(0),
+            _ => self.0.get_supported_module_version(module),
+        }
+    }
+
+    fn get_module_deprecation(
+        &self,
+        module: &ModuleIdentifier<'_>,
+    ) -> Option<server::ModuleDeprecation> {
+        self.0.get_module_deprecation(module)
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::Handler<A>
+    for MessageHandler<Next>
+{
+    fn handle<D: server::Dispatch<A>>(
+        &self,
+        msg: &msg::Message,
+        conn: &mut server::Connection<A, D>,
+    ) -> Result<(), server::HandlerError> {
+        match msg.parsed_type().as_str() {
+            "frame1.enable" => {
+                let msg = Enable::decode_message(msg).ok_or(InvalidMessage)?;
+                //the acknowledgement is the last unframed message (unless we're already framed)
+                conn.enqueue_message(&msg);
+                conn.enable_framing();
+                Ok(())
+            }
+            _ => self.0.handle(msg, conn),
+        }
+    }
+
+    fn handle_error<D: server::Dispatch<A>>(
+        &self,
+        err: &msg::ParseError,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.handle_error(err, conn);
+    }
+
+    fn on_connect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_connect(conn);
+    }
+
+    fn on_state_change<D: server::Dispatch<A>>(
+        &self,
+        old_state: &server::ConnectionState<A>,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.on_state_change(old_state, conn);
+    }
+
+    fn on_disconnect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_disconnect(conn);
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>>
+    server::core::MessageHandlerExt<A> for MessageHandler<Next>
+{
+    fn handle_property<D: server::Dispatch<A>>(
+        &self,
+        name: &ScopedIdentifier<'_>,
+        requested_value: Option<&[u8]>,
+        conn: &mut server::Connection<A, D>,
+    ) -> Option<Vec<u8>> {
+        self.0.handle_property(name, requested_value, conn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::ClientID;
+    use crate::server::testing::{Conversation, MockApplication, MockHandlers};
+    use crate::server::ClientIdentity;
+
+    struct FrameHandlers;
+
+    impl MockHandlers for FrameHandlers {
+        type MessageHandler = server::core::MessageHandler<MessageHandler<server::RejectHandler>>;
+    }
+
+    #[test]
+    fn test_framing() {
+        let app: MockApplication<FrameHandlers> = MockApplication::new();
+        let creds = server::Application::register_client(
+            &app,
+            ClientIdentity::new(&ClientID::parse("a").unwrap()),
+        );
+        let mut conv = Conversation::new(app);
+        conv.send_message(&crate::msg::posix::ClientHello {
+            secret: creds.secret(),
+        })
+        .expect(r#"(posix1.server-hello a "" "" "")"#)
+        .send(b"{2|4:want,6:frame1,}")
+        .expect("(have frame1.0)");
+        assert!(!conv.connection().is_framed());
+
+        //the acknowledgement is still unframed, but everything after it is framed
+        conv.send(b"{1|13:frame1.enable,}")
+            .expect("(frame1.enable)");
+        assert!(conv.connection().is_framed());
+        conv.send(b"\x00\x13{2|4:want,5:core1,}")
+            .expect_wire(b"\x00\x15{2|4:have,7:core1.0,}");
+
+        //frames can arrive in pieces
+        conv.send(b"\x00")
+            .send(b"\x13{2|4:want,")
+            .expect_no_reply()
+            .send(b"5:core1,}")
+            .expect_wire(b"\x00\x15{2|4:have,7:core1.0,}");
+
+        //invalid frames are discarded without affecting the next frame
+        conv.send(b"\x00\x0A{2|4:want,\x00\x13{2|4:want,5:core1,}")
+            .expect_wire(b"\x00\x15{2|4:have,7:core1.0,}");
+        assert_eq!(conv.connection().stats().parse_errors, 1);
+
+        //trailing garbage in a frame is discarded, but the message before it is still handled
+        conv.send(b"\x00\x16{2|4:want,5:core1,}foo")
+            .expect_wire(b"\x00\x15{2|4:have,7:core1.0,}");
+        assert_eq!(conv.connection().stats().parse_errors, 1);
+    }
+}
@@ -11,6 +11,15 @@ use crate::server;
 ///
 ///The value is used to trigger the baseline error handling behavior.
 ///[\[vt6/foundation, sect. 3.3.2\]](https://vt6.io/std/foundation/#section-3-3-2)
+///
+///The baseline error handling only knows `have` and `nope` responses, so all variants except for
+///`UnknownMessageType` result in a `nope` response. The variants still differ in what they tell
+///the Application (e.g. in logs) about why the message was refused. If a module defines its own
+///error replies, the handler shall enqueue the respective reply itself and return `Ok(())`.
+///
+///During the handshake phase, every error counts towards the
+///[HandshakeTolerance](struct.HandshakeTolerance.html) of the Dispatch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum HandlerError {
     ///The message was of an unknown type. The caller must render a `have` response to describe
     ///support for the respective module and major version.
@@ -18,8 +27,33 @@ pub enum HandlerError {
     ///The message type was recognized, but the message was semantically invalid. The caller must
     ///render a `nope` response.
     InvalidMessage,
+    ///The message was valid, but the client is not allowed to send it, e.g. because the
+    ///Application's policy forbids it, or because a secret was not accepted. The caller must
+    ///render a `nope` response.
+    PermissionDenied,
+    ///The message was valid, but cannot be processed right now, e.g. because a resource that it
+    ///refers to is held by someone else. The caller must render a `nope` response. Unlike with the
+    ///other errors, the client may succeed when it sends the same message again later.
+    TemporarilyUnavailable,
+    ///The message was valid, but processing it would exceed a limit, e.g. on the number of
+    ///clients or on the size of some data. The caller must render a `nope` response.
+    ResourceExhausted,
+}
+
+impl core::fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(match *self {
+            HandlerError::UnknownMessageType => "unknown message type",
+            HandlerError::InvalidMessage => "invalid message",
+            HandlerError::PermissionDenied => "permission denied",
+            HandlerError::TemporarilyUnavailable => "temporarily unavailable",
+            HandlerError::ResourceExhausted => "resource exhausted",
+        })
+    }
 }
 
+impl std::error::Error for HandlerError {}
+
 ///The main trait for message handlers.
 ///
 ///Handlers are used to parse and handle messages sent by the client on fresh sockets
@@ -65,6 +99,34 @@ pub trait Handler<A: server::Application>: Default {
         err: &msg::ParseError,
         conn: &mut server::Connection<A, D>,
     );
+
+    ///Called when the Dispatch has accepted a new connection, after the connection has been
+    ///registered with the Dispatch.
+    ///
+    ///The lifecycle methods `on_connect()`, `on_state_change()` and `on_disconnect()` are called
+    ///on both the [HandshakeHandler](trait.HandshakeHandler.html) and the
+    ///[MessageHandler](trait.MessageHandler.html) of the Application (in this order), regardless
+    ///of the connection's state. A handler that is included in both chains will therefore see
+    ///each event twice. Handlers that forward to a next handler must forward these calls as well.
+    ///
+    ///The default implementations do nothing. They are intended for middleware like metrics
+    ///collectors or session trackers.
+    fn on_connect<D: server::Dispatch<A>>(&self, _conn: &mut server::Connection<A, D>) {}
+
+    ///Called when the connection has changed into a different state through
+    ///[`Connection::set_state()`](struct.Connection.html#method.set_state). `old_state` is the
+    ///state that the connection was in before, e.g. to find out which client was connected when
+    ///the connection changes from `Msgio` into `Teardown`.
+    fn on_state_change<D: server::Dispatch<A>>(
+        &self,
+        _old_state: &server::ConnectionState<A>,
+        _conn: &mut server::Connection<A, D>,
+    ) {
+    }
+
+    ///Called when the connection has been set into `Teardown` state, right after
+    ///`on_state_change()`. The Dispatch will close the connection afterwards.
+    fn on_disconnect<D: server::Dispatch<A>>(&self, _conn: &mut server::Connection<A, D>) {}
 }
 
 ///Marker trait for [handlers](trait.Handler.html) that can be used on msgio sockets.
@@ -79,8 +141,69 @@ pub trait MessageHandler<A: server::Application>: Handler<A> {
     ///`have` messages are defined in
     ///[\[vt6/foundation, sect. 4.2\]](https://vt6.io/std/foundation/#section-4-2).
     fn get_supported_module_version(&self, module: &ModuleIdentifier<'_>) -> Option<u16>;
+
+    ///Returns whether clients should migrate away from the given module, even though it is
+    ///supported. This is only asked about modules for which `get_supported_module_version()`
+    ///returned `Some`. Since `have` replies cannot carry this information, the
+    ///[Connection](struct.Connection.html) reports it to the Application instead, through a
+    ///[`Notification::DeprecatedModuleNegotiated`](enum.Notification.html#variant.DeprecatedModuleNegotiated)
+    ///when a client agrees on the module.
+    ///
+    ///The default implementation returns `None`. Handlers that forward
+    ///`get_supported_module_version()` to a next handler must forward this method as well, even
+    ///for their own modules. The handlers in this crate do so, which means that a deprecation
+    ///policy for any module can be imposed by a handler near the end of the chain (e.g. right
+    ///before the [RejectHandler](struct.RejectHandler.html)):
+    ///
+    ///```no_run
+    ///# use std::marker::PhantomData;
+    ///# use vt6::common::core::ModuleIdentifier;
+    ///# use vt6::server::{Application, MessageHandler, ModuleDeprecation};
+    ///# struct MyHandler<A, Next>(Next, PhantomData<A>);
+    ///# impl<A: Application, Next: MessageHandler<A>> MyHandler<A, Next> {
+    ///fn get_module_deprecation(&self, module: &ModuleIdentifier<'_>) -> Option<ModuleDeprecation> {
+    ///    match module.as_str() {
+    ///        "example1" => Some(ModuleDeprecation {
+    ///            replacement: Some("example2"),
+    ///            sunset: Some("2021-06"),
+    ///        }),
+    ///        _ => self.0.get_module_deprecation(module),
+    ///    }
+    ///}
+    ///# }
+    ///```
+    fn get_module_deprecation(&self, _module: &ModuleIdentifier<'_>) -> Option<ModuleDeprecation> {
+        None
+    }
+}
+
+///Describes why and until when a module is deprecated. This is returned by
+///[`MessageHandler::get_module_deprecation()`](trait.MessageHandler.html#method.get_module_deprecation).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModuleDeprecation {
+    ///The module that clients should use instead, if any (e.g. `term2` for `term1`).
+    pub replacement: Option<&'static str>,
+    ///When support for the deprecated module is going to be removed, in a free-form
+    ///human-readable format (e.g. a date or a release of the terminal), if this is known.
+    pub sunset: Option<&'static str>,
+}
+
+impl core::fmt::Display for ModuleDeprecation {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match (self.replacement, self.sunset) {
+            (None, None) => Ok(()),
+            (Some(r), None) => write!(f, "use {} instead", r),
+            (None, Some(s)) => write!(f, "support ends {}", s),
+            (Some(r), Some(s)) => write!(f, "use {} instead, support ends {}", r, s),
+        }
+    }
 }
 
 ///Marker trait for [handlers](trait.Handler.html) that can be used during the client handshake
 ///phase.
+///
+///A handshake ends when a handler changes the connection into a different state. A handler may
+///also accept a message without doing so, in which case the handshake continues with the next
+///message. Errors returned by handshake handlers are fatal unless the Dispatch tolerates them (see
+///[`Dispatch::handshake_tolerance()`](trait.Dispatch.html#method.handshake_tolerance)).
 pub trait HandshakeHandler<A: server::Application>: Handler<A> {}
@@ -0,0 +1,322 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::msg::input::{Key, KeyPress, Modifiers, MouseButton, MouseEvent, MouseEventKind, Paste};
+use std::io::Write;
+
+const PASTE_START: &[u8] = b"\x1B[200~";
+const PASTE_END: &[u8] = b"\x1B[201~";
+
+///Encodes input events into the byte sequences that a traditional terminal would send.
+///
+///This is used for clients that have not negotiated the `input1` module, and thus only receive
+///input as bytes on their stdin socket. The encoding follows the conventions of xterm and its
+///descendants: Control characters for Ctrl combinations, an ESC prefix for Alt combinations,
+///CSI sequences for cursor and function keys, SGR mouse reporting (mode 1006) for mouse events,
+///and bracketed paste for pastes.
+///
+///Some events cannot be represented in this way (e.g. function keys beyond F20). These are
+///encoded as nothing at all.
+///
+///```
+///# use vt6::msg::input::{Key, KeyPress, Modifiers};
+///# use vt6::server::input::EncodeLegacyInput;
+///let ctrl = Modifiers { ctrl: true, ..Modifiers::default() };
+///let msg = KeyPress { key: Key::Char('c'), modifiers: ctrl };
+///assert_eq!(msg.legacy_bytes(), b"\x03");
+///let msg = KeyPress { key: Key::Up, modifiers: ctrl };
+///assert_eq!(msg.legacy_bytes(), b"\x1B[1;5A");
+///```
+pub trait EncodeLegacyInput {
+    ///Appends the legacy encoding of this event to the given buffer.
+    fn encode_legacy(&self, buf: &mut Vec<u8>);
+
+    ///A convenience function that returns the legacy encoding of this event in a new Vec.
+    fn legacy_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode_legacy(&mut buf);
+        buf
+    }
+}
+
+impl EncodeLegacyInput for KeyPress {
+    fn encode_legacy(&self, buf: &mut Vec<u8>) {
+        let m = self.modifiers;
+        //the modifier parameter in CSI sequences, e.g. "5" for Ctrl in "\x1B[1;5A"
+        let param = 1 + m.shift as u8 + 2 * (m.alt as u8) + 4 * (m.ctrl as u8);
+
+        let final_byte = match self.key {
+            Key::Up => b'A',
+            Key::Down => b'B',
+            Key::Right => b'C',
+            Key::Left => b'D',
+            Key::Home => b'H',
+            Key::End => b'F',
+            Key::F(n @ 1..=4) => b'P' + (n - 1),
+            _ => 0,
+        };
+        if final_byte != 0 {
+            if param > 1 {
+                let _ = write!(buf, "\x1B[1;{}{}", param, final_byte as char);
+            } else if let Key::F(_) = self.key {
+                buf.extend_from_slice(&[0x1B, b'O', final_byte]);
+            } else {
+                buf.extend_from_slice(&[0x1B, b'[', final_byte]);
+            }
+            return;
+        }
+
+        let tilde_code = match self.key {
+            Key::Insert => 2,
+            Key::Delete => 3,
+            Key::PageUp => 5,
+            Key::PageDown => 6,
+            Key::F(n @ 5..=20) => [
+                15, 17, 18, 19, 20, 21, 23, 24, 25, 26, 28, 29, 31, 32, 33, 34,
+            ][(n - 5) as usize],
+            Key::F(_) => return,
+            _ => 0,
+        };
+        if tilde_code != 0 {
+            if param > 1 {
+                let _ = write!(buf, "\x1B[{};{}~", tilde_code, param);
+            } else {
+                let _ = write!(buf, "\x1B[{}~", tilde_code);
+            }
+            return;
+        }
+
+        if m.alt {
+            buf.push(0x1B);
+        }
+        match self.key {
+            Key::Char(c) if m.ctrl => match ctrl_byte(c) {
+                Some(b) => buf.push(b),
+                None => push_char(buf, c),
+            },
+            Key::Char(c) => push_char(buf, c),
+            Key::Enter => buf.push(b'\r'),
+            Key::Tab if m.shift => buf.extend_from_slice(b"\x1B[Z"),
+            Key::Tab => buf.push(b'\t'),
+            Key::Backspace if m.ctrl => buf.push(0x08),
+            Key::Backspace => buf.push(0x7F),
+            Key::Escape => buf.push(0x1B),
+            //all other keys were handled above
+            _ => {}
+        }
+    }
+}
+
+//Returns the control character produced by Ctrl plus the given character, if any.
+fn ctrl_byte(c: char) -> Option<u8> {
+    match c {
+        ' ' | '@' => Some(0x00),
+        'a'..='z' => Some(c as u8 - b'a' + 1),
+        'A'..='Z' | '[' | '\\' | ']' | '^' | '_' => Some(c as u8 & 0x1F),
+        '?' => Some(0x7F),
+        _ => None,
+    }
+}
+
+fn push_char(buf: &mut Vec<u8>, c: char) {
+    let mut tmp = [0u8; 4];
+    buf.extend_from_slice(c.encode_utf8(&mut tmp).as_bytes());
+}
+
+impl EncodeLegacyInput for MouseEvent {
+    fn encode_legacy(&self, buf: &mut Vec<u8>) {
+        let button_code = |b: MouseButton| match b {
+            MouseButton::Left => 0,
+            MouseButton::Middle => 1,
+            MouseButton::Right => 2,
+        };
+        let (code, is_release) = match self.kind {
+            MouseEventKind::Press(b) => (button_code(b), false),
+            MouseEventKind::Release(b) => (button_code(b), true),
+            //motion flag plus "no button"
+            MouseEventKind::Move => (35, false),
+            MouseEventKind::ScrollUp => (64, false),
+            MouseEventKind::ScrollDown => (65, false),
+        };
+        let Modifiers { shift, alt, ctrl } = self.modifiers;
+        let code = code + 4 * (shift as u16) + 8 * (alt as u16) + 16 * (ctrl as u16);
+        //SGR mouse coordinates are one-based
+        let _ = write!(
+            buf,
+            "\x1B[<{};{};{}{}",
+            code,
+            u32::from(self.x) + 1,
+            u32::from(self.y) + 1,
+            if is_release { 'm' } else { 'M' }
+        );
+    }
+}
+
+impl<'a> EncodeLegacyInput for Paste<'a> {
+    ///Encodes the paste in bracketed paste mode: The first chunk of a paste is preceded by the
+    ///start marker, and the last chunk is followed by the end marker. If the pasted text contains
+    ///the end marker, it is removed, so that the pasted text cannot end the paste early and inject
+    ///keystrokes. (To make sure that the end marker is also caught when it spans two chunks,
+    ///encode the whole paste as a single chunk.) Clients that have not enabled bracketed paste
+    ///mode expect just the text itself instead.
+    fn encode_legacy(&self, buf: &mut Vec<u8>) {
+        if self.sequence == 0 {
+            buf.extend_from_slice(PASTE_START);
+        }
+        let mut rest = self.text.as_bytes();
+        while let Some(idx) = find(rest, PASTE_END) {
+            buf.extend_from_slice(&rest[0..idx]);
+            rest = &rest[(idx + PASTE_END.len())..];
+        }
+        buf.extend_from_slice(rest);
+        if self.is_final {
+            buf.extend_from_slice(PASTE_END);
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: Key, modifiers: &str) -> Vec<u8> {
+        let modifiers = Modifiers {
+            shift: modifiers.contains('s'),
+            alt: modifiers.contains('a'),
+            ctrl: modifiers.contains('c'),
+        };
+        KeyPress { key, modifiers }.legacy_bytes()
+    }
+
+    #[test]
+    fn test_legacy_keys() {
+        assert_eq!(key(Key::Char('a'), ""), b"a");
+        assert_eq!(key(Key::Char('A'), "s"), b"A");
+        assert_eq!(key(Key::Char('\u{E4}'), ""), "\u{E4}".as_bytes());
+        assert_eq!(key(Key::Char('a'), "c"), b"\x01");
+        assert_eq!(key(Key::Char('['), "c"), b"\x1B");
+        assert_eq!(key(Key::Char(' '), "c"), b"\x00");
+        assert_eq!(key(Key::Char('x'), "a"), b"\x1Bx");
+        assert_eq!(key(Key::Char('x'), "ac"), b"\x1B\x18");
+        assert_eq!(key(Key::Char('1'), "c"), b"1");
+
+        assert_eq!(key(Key::Enter, ""), b"\r");
+        assert_eq!(key(Key::Tab, ""), b"\t");
+        assert_eq!(key(Key::Tab, "s"), b"\x1B[Z");
+        assert_eq!(key(Key::Backspace, ""), b"\x7F");
+        assert_eq!(key(Key::Backspace, "a"), b"\x1B\x7F");
+        assert_eq!(key(Key::Escape, ""), b"\x1B");
+
+        assert_eq!(key(Key::Left, ""), b"\x1B[D");
+        assert_eq!(key(Key::End, "s"), b"\x1B[1;2F");
+        assert_eq!(key(Key::Up, "sac"), b"\x1B[1;8A");
+        assert_eq!(key(Key::Delete, ""), b"\x1B[3~");
+        assert_eq!(key(Key::PageDown, "c"), b"\x1B[6;5~");
+        assert_eq!(key(Key::F(1), ""), b"\x1BOP");
+        assert_eq!(key(Key::F(4), "a"), b"\x1B[1;3S");
+        assert_eq!(key(Key::F(5), ""), b"\x1B[15~");
+        assert_eq!(key(Key::F(12), ""), b"\x1B[24~");
+        assert_eq!(key(Key::F(20), "s"), b"\x1B[34;2~");
+        assert_eq!(key(Key::F(24), ""), b"");
+    }
+
+    #[test]
+    fn test_legacy_mouse_and_paste() {
+        let event = |kind, modifiers| MouseEvent {
+            kind,
+            x: 9,
+            y: 0,
+            modifiers,
+        };
+        let none = Modifiers::default();
+        let ctrl = Modifiers { ctrl: true, ..none };
+        let left = MouseButton::Left;
+        assert_eq!(
+            event(MouseEventKind::Press(left), none).legacy_bytes(),
+            b"\x1B[<0;10;1M"
+        );
+        assert_eq!(
+            event(MouseEventKind::Release(MouseButton::Right), none).legacy_bytes(),
+            b"\x1B[<2;10;1m"
+        );
+        assert_eq!(
+            event(MouseEventKind::ScrollDown, ctrl).legacy_bytes(),
+            b"\x1B[<81;10;1M"
+        );
+        assert_eq!(
+            event(MouseEventKind::Move, none).legacy_bytes(),
+            b"\x1B[<35;10;1M"
+        );
+
+        let paste = |sequence, is_final, text| Paste {
+            sequence,
+            is_final,
+            text,
+        };
+        assert_eq!(
+            paste(0, true, "hello").legacy_bytes(),
+            b"\x1B[200~hello\x1B[201~"
+        );
+        assert_eq!(paste(0, false, "hel").legacy_bytes(), b"\x1B[200~hel");
+        assert_eq!(paste(1, true, "lo").legacy_bytes(), b"lo\x1B[201~");
+        //the end marker cannot be smuggled in through the pasted text
+        assert_eq!(
+            paste(0, true, "a\x1B[201~rm -rf /\r\x1B[201~").legacy_bytes(),
+            b"\x1B[200~arm -rf /\r\x1B[201~"
+        );
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        use crate::common::core::msg::{DecodeMessage, EncodeMessage, Message};
+        let mut buf = [0u8; 256];
+
+        let msg = KeyPress {
+            key: Key::F(11),
+            modifiers: Modifiers {
+                shift: true,
+                ctrl: true,
+                ..Modifiers::default()
+            },
+        };
+        let len = msg.encode(&mut buf).unwrap();
+        let (parsed, _) = Message::parse(&buf[0..len]).unwrap();
+        assert_eq!(parsed.to_string(), "(input1.key f11 sc)");
+        let decoded = KeyPress::decode_message(&parsed).unwrap();
+        assert_eq!((decoded.key, decoded.modifiers), (msg.key, msg.modifiers));
+
+        let msg = MouseEvent {
+            kind: MouseEventKind::Press(MouseButton::Middle),
+            x: 80,
+            y: 24,
+            modifiers: Modifiers::default(),
+        };
+        let len = msg.encode(&mut buf).unwrap();
+        let (parsed, _) = Message::parse(&buf[0..len]).unwrap();
+        assert_eq!(
+            parsed.to_string(),
+            r#"(input1.mouse press-middle 80 24 "")"#
+        );
+        let decoded = MouseEvent::decode_message(&parsed).unwrap();
+        assert_eq!((decoded.kind, decoded.x, decoded.y), (msg.kind, 80, 24));
+
+        //invalid arguments are rejected
+        for input in &[
+            &b"{3|10:input1.key,2:ab,0:,}"[..],
+            b"{3|10:input1.key,3:f25,0:,}",
+            b"{3|10:input1.key,3:f05,0:,}",
+            b"{3|10:input1.key,1:a,2:cs,}",
+            b"{3|10:input1.key,1:a,2:ss,}",
+        ] {
+            let (parsed, _) = Message::parse(input).unwrap();
+            assert!(KeyPress::decode_message(&parsed).is_none(), "{}", parsed);
+        }
+    }
+}
@@ -0,0 +1,10 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+mod legacy;
+pub use legacy::*;
+mod msg;
+pub use msg::*;
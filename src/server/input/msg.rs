@@ -0,0 +1,229 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::{msg, ModuleIdentifier, ScopedIdentifier};
+use crate::msg::input::{paste_chunks, Paste};
+use crate::server;
+use crate::server::input::EncodeLegacyInput;
+use crate::server::{ClientIdentity, ConnectionState, MessageConnector, ScreenIdentity};
+use std::sync::{Arc, Mutex};
+
+const MODULE: &str = "input1";
+
+///A [MessageHandler](../trait.MessageHandler.html) for the `input1` module, which delivers user
+///input to clients as events. See [vt6::msg::input](../../msg/input/index.html) for the message
+///types.
+///
+///All messages of this module are sent by the server, so this handler only announces support for
+///the module to clients. Before sending input events to a client, check with
+///[is_agreed()](fn.is_agreed.html) that the client has negotiated the module. Clients that have
+///not done so only understand input as bytes on their stdin, which can be produced with
+///[EncodeLegacyInput](trait.EncodeLegacyInput.html).
+#[derive(Default)]
+pub struct MessageHandler<Next>(Next);
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::MessageHandler<A>
+    for MessageHandler<Next>
+{
+    fn get_supported_module_version(&self, module: &ModuleIdentifier<'_>) -> Option<u16> {
+        match module.as_str() {
+            MODULE => Some(0),
+            _ => self.0.get_supported_module_version(module),
+        }
+    }
+
+    fn get_module_deprecation(
+        &self,
+        module: &ModuleIdentifier<'_>,
+    ) -> Option<server::ModuleDeprecation> {
+        self.0.get_module_deprecation(module)
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::Handler<A>
+    for MessageHandler<Next>
+{
+    fn handle<D: server::Dispatch<A>>(
+        &self,
+        msg: &msg::Message,
+        conn: &mut server::Connection<A, D>,
+    ) -> Result<(), server::HandlerError> {
+        self.0.handle(msg, conn)
+    }
+
+    fn handle_error<D: server::Dispatch<A>>(
+        &self,
+        err: &msg::ParseError,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.handle_error(err, conn);
+    }
+
+    fn on_connect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_connect(conn);
+    }
+
+    fn on_state_change<D: server::Dispatch<A>>(
+        &self,
+        old_state: &server::ConnectionState<A>,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.on_state_change(old_state, conn);
+    }
+
+    fn on_disconnect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_disconnect(conn);
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>>
+    server::core::MessageHandlerExt<A> for MessageHandler<Next>
+{
+    fn handle_property<D: server::Dispatch<A>>(
+        &self,
+        name: &ScopedIdentifier<'_>,
+        requested_value: Option<&[u8]>,
+        conn: &mut server::Connection<A, D>,
+    ) -> Option<Vec<u8>> {
+        self.0.handle_property(name, requested_value, conn)
+    }
+}
+
+///Returns whether the client on this connection has agreed on the `input1` module, i.e. whether
+///input events may be sent to it as messages.
+pub fn is_agreed<A: server::Application, D: server::Dispatch<A>>(
+    conn: &server::Connection<A, D>,
+) -> bool {
+    //this unwrap() is safe since MODULE is a valid module identifier
+    let module = ModuleIdentifier::parse(MODULE).unwrap();
+    conn.agreed_module_version(&module).is_some()
+}
+
+///Delivers text that the user pasted into the given screen. This is also available as
+///[`Dispatch::send_paste()`](../trait.Dispatch.html#method.send_paste).
+///
+///The candidates are all clients whose stdin is connected to the given screen and that have
+///agreed on the `input1` module. The paste is sent to the one with the longest client ID (the most
+///deeply nested client, which is usually the one running in the foreground) as a series of
+///`input1.paste` messages, which the client can put back together with
+///[vt6::client::PasteReassembler](../../client/struct.PasteReassembler.html). If there are no
+///candidates, the paste is sent on the screen's stdin socket instead, in bracketed paste mode (see
+///[EncodeLegacyInput](trait.EncodeLegacyInput.html)).
+///
+///Delivery happens through [`Dispatch::enqueue_broadcast()`](../trait.Dispatch.html#tymethod.enqueue_broadcast),
+///so the paste may be sent after this function has returned.
+pub fn send_paste<A, D>(dispatch: &D, screen: &ScreenIdentity, text: &str)
+where
+    A: server::Application,
+    D: server::Dispatch<A>,
+{
+    let screen = screen.clone();
+    let recipient: Arc<Mutex<Option<ClientIdentity>>> = Arc::new(Mutex::new(None));
+
+    //first pass: find the recipient
+    let recipient_ref = recipient.clone();
+    let screen_ref = screen.clone();
+    dispatch.enqueue_broadcast(Box::new(move |conn| {
+        if !is_agreed(conn) {
+            return;
+        }
+        if let ConnectionState::Msgio(ref connector) = conn.state() {
+            let identity = connector.identity();
+            if identity.stdin_screen_id() != Some(screen_ref.screen_id()) {
+                return;
+            }
+            let mut recipient = recipient_ref.lock().unwrap();
+            let is_better = match *recipient {
+                Some(ref r) => r.client_id().as_str().len() < identity.client_id().as_str().len(),
+                None => true,
+            };
+            if is_better {
+                *recipient = Some(identity.clone());
+            }
+        }
+    }));
+
+    //second pass: deliver the paste to the recipient, or to stdin if there is none (broadcasts
+    //are executed in order, so the first pass is complete at this point)
+    let text: Arc<str> = text.into();
+    dispatch.enqueue_broadcast(Box::new(move |conn| {
+        let (is_recipient, is_fallback) = match (&*recipient.lock().unwrap(), conn.state()) {
+            (Some(r), ConnectionState::Msgio(ref connector)) => {
+                (connector.identity().client_id() == r.client_id(), false)
+            }
+            (None, state) => (false, state.can_receive_stdin_for_screen(&screen)),
+            _ => (false, false),
+        };
+        if is_recipient {
+            for chunk in paste_chunks(&text) {
+                conn.enqueue_message(&chunk);
+            }
+        } else if is_fallback {
+            //encode the whole paste at once to reliably strip embedded end markers
+            let paste = Paste {
+                sequence: 0,
+                is_final: true,
+                text: &text,
+            };
+            conn.enqueue_stdin(&paste.legacy_bytes());
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::{ClientID, ScreenID};
+    use crate::server::testing::{Conversation, MockApplication, MockHandlers};
+    use crate::server::Dispatch;
+
+    struct InputHandlers;
+
+    impl MockHandlers for InputHandlers {
+        type MessageHandler = server::core::MessageHandler<MessageHandler<server::RejectHandler>>;
+    }
+
+    #[test]
+    fn test_send_paste() {
+        let app: MockApplication<InputHandlers> = MockApplication::new();
+        let screen_id = ScreenID::parse("screen1").unwrap();
+        let screen_creds = app.add_screen(&screen_id);
+        let creds = server::Application::register_client(
+            &app,
+            ClientIdentity::new(&ClientID::parse("a").unwrap()).with_stdin(&screen_id),
+        );
+        let mut stdin_conv = Conversation::new(app);
+        stdin_conv
+            .send_message(&crate::msg::posix::StdinHello {
+                secret: screen_creds.stdin_secret(),
+            })
+            .expect_no_reply();
+        let mut msgio_conv = Conversation::with_dispatch(&stdin_conv.dispatch());
+        msgio_conv
+            .send_message(&crate::msg::posix::ClientHello {
+                secret: creds.secret(),
+            })
+            .expect(r#"(posix1.server-hello a screen1 "" "")"#);
+        let screen = ScreenIdentity::new(&screen_id);
+
+        //clients that have not agreed on input1 get the paste on stdin in bracketed paste mode
+        stdin_conv.dispatch().send_paste(&screen, "ls\n");
+        msgio_conv.expect_no_reply();
+        stdin_conv.expect_stdin(b"\x1B[200~ls\n\x1B[201~");
+
+        //otherwise the paste is sent in chunks through the msgio connection
+        msgio_conv
+            .send(b"{2|4:want,6:input1,}")
+            .expect("(have input1.0)");
+        let text = "x".repeat(1000);
+        stdin_conv.dispatch().send_paste(&screen, &text);
+        let replies = msgio_conv.replies();
+        assert_eq!(replies.len(), 2);
+        assert!(replies[0].starts_with("(input1.paste 0 f xxx"));
+        assert!(replies[1].starts_with("(input1.paste 1 t xxx"));
+        stdin_conv.expect_stdin(b"");
+    }
+}
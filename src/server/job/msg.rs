@@ -0,0 +1,217 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg::DecodeMessage;
+use crate::common::core::{msg, ClientID, ModuleIdentifier, ScopedIdentifier};
+use crate::msg::job::{Foreground, Resume, Suspend};
+use crate::server;
+use crate::server::ClientSelector::{AtOrBelow, StrictlyBelow};
+use crate::server::HandlerError::{InvalidMessage, PermissionDenied};
+use crate::server::MessageConnector;
+
+///The actions that clients can request with the messages of the `job1` module. Passed to
+///[`Application::control_job()`](../trait.Application.html#method.control_job).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum JobAction {
+    ///Requested with `job1.foreground`.
+    Foreground,
+    ///Requested with `job1.suspend`.
+    Suspend,
+    ///Requested with `job1.resume`.
+    Resume,
+}
+
+///A [MessageHandler](../trait.MessageHandler.html) for the `job1` module, which lets shells
+///implement job control over VT6. See [vt6::msg::job](../../msg/job/index.html) for the message
+///types.
+///
+///A job is identified by the client ID of its topmost client, and comprises all clients
+///[AtOrBelow](../enum.ClientSelector.html) that client ID. This handler checks that the requesting
+///client is responsible for the job: Clients may bring themselves or any job below them into the
+///foreground, but may only suspend or resume jobs strictly below them. Requests for client IDs
+///without any registered clients are rejected as invalid. The actual job control is left to
+///[`Application::control_job()`](../trait.Application.html#method.control_job), since it depends
+///on how the application tracks the processes behind its clients.
+#[derive(Default)]
+pub struct MessageHandler<Next>(Next);
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::MessageHandler<A>
+    for MessageHandler<Next>
+{
+    fn get_supported_module_version(&self, module: &ModuleIdentifier<'_>) -> Option<u16> {
+        match module.as_str() {
+            "job1" => Some(0),
+            _ => self.0.get_supported_module_version(module),
+        }
+    }
+
+    fn get_module_deprecation(
+        &self,
+        module: &ModuleIdentifier<'_>,
+    ) -> Option<server::ModuleDeprecation> {
+        self.0.get_module_deprecation(module)
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::Handler<A>
+    for MessageHandler<Next>
+{
+    fn handle<D: server::Dispatch<A>>(
+        &self,
+        msg: &msg::Message,
+        conn: &mut server::Connection<A, D>,
+    ) -> Result<(), server::HandlerError> {
+        match msg.parsed_type().as_str() {
+            "job1.foreground" => {
+                let msg = Foreground::decode_message(msg).ok_or(InvalidMessage)?;
+                control_job(conn, JobAction::Foreground, msg.client_id)?;
+                conn.enqueue_message(&msg);
+                Ok(())
+            }
+            "job1.suspend" => {
+                let msg = Suspend::decode_message(msg).ok_or(InvalidMessage)?;
+                control_job(conn, JobAction::Suspend, msg.client_id)?;
+                conn.enqueue_message(&msg);
+                Ok(())
+            }
+            "job1.resume" => {
+                let msg = Resume::decode_message(msg).ok_or(InvalidMessage)?;
+                control_job(conn, JobAction::Resume, msg.client_id)?;
+                conn.enqueue_message(&msg);
+                Ok(())
+            }
+            _ => self.0.handle(msg, conn),
+        }
+    }
+
+    fn handle_error<D: server::Dispatch<A>>(
+        &self,
+        err: &msg::ParseError,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.handle_error(err, conn);
+    }
+
+    fn on_connect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_connect(conn);
+    }
+
+    fn on_state_change<D: server::Dispatch<A>>(
+        &self,
+        old_state: &server::ConnectionState<A>,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.on_state_change(old_state, conn);
+    }
+
+    fn on_disconnect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_disconnect(conn);
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>>
+    server::core::MessageHandlerExt<A> for MessageHandler<Next>
+{
+    fn handle_property<D: server::Dispatch<A>>(
+        &self,
+        name: &ScopedIdentifier<'_>,
+        requested_value: Option<&[u8]>,
+        conn: &mut server::Connection<A, D>,
+    ) -> Option<Vec<u8>> {
+        self.0.handle_property(name, requested_value, conn)
+    }
+}
+
+fn control_job<A: server::Application, D: server::Dispatch<A>>(
+    conn: &mut server::Connection<A, D>,
+    action: JobAction,
+    job: ClientID<'_>,
+) -> Result<(), server::HandlerError> {
+    let d = conn.dispatch();
+    let identity = conn.message_connector().unwrap().identity();
+    let requester = identity.client_id();
+    let is_responsible = match action {
+        JobAction::Foreground => AtOrBelow(requester).contains(job),
+        JobAction::Suspend | JobAction::Resume => StrictlyBelow(requester).contains(job),
+    };
+    if !is_responsible {
+        return Err(PermissionDenied);
+    }
+    if !d.application().has_clients(AtOrBelow(job)) {
+        return Err(InvalidMessage);
+    }
+    if !d.application().control_job(identity, action, job) {
+        return Err(PermissionDenied);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::{Conversation, MockApplication, MockHandlers};
+    use crate::server::ClientIdentity;
+
+    struct JobHandlers;
+
+    impl MockHandlers for JobHandlers {
+        type MessageHandler = server::core::MessageHandler<MessageHandler<server::RejectHandler>>;
+    }
+
+    #[test]
+    fn test_job_control() {
+        let app: MockApplication<JobHandlers> = MockApplication::new();
+        let register = |id| {
+            let id = ClientID::parse(id).unwrap();
+            server::Application::register_client(&app, ClientIdentity::new(&id))
+        };
+        let creds = register("a");
+        register("ab");
+        register("abc");
+        let mut conv = Conversation::new(app.clone());
+        conv.send_message(&crate::msg::posix::ClientHello {
+            secret: creds.secret(),
+        })
+        .expect(r#"(posix1.server-hello a "" "" "")"#)
+        .send(b"{2|4:want,4:job1,}")
+        .expect("(have job1.0)");
+
+        //jobs below the requester can be controlled
+        conv.send(b"{2|12:job1.suspend,2:ab,}")
+            .expect("(job1.suspend ab)")
+            .send(b"{2|11:job1.resume,3:abc,}")
+            .expect("(job1.resume abc)")
+            .send(b"{2|15:job1.foreground,2:ab,}")
+            .expect("(job1.foreground ab)");
+
+        //the requester can only bring itself back into the foreground, not suspend itself
+        conv.send(b"{2|15:job1.foreground,1:a,}")
+            .expect("(job1.foreground a)")
+            .send(b"{2|12:job1.suspend,1:a,}")
+            .expect("(nope job1.suspend)");
+
+        //jobs outside of the requester's hierarchy, or without clients, are off-limits
+        conv.send(b"{2|12:job1.suspend,1:b,}")
+            .expect("(nope job1.suspend)")
+            .send(b"{2|12:job1.suspend,2:ad,}")
+            .expect("(nope job1.suspend)")
+            .send(b"{2|12:job1.suspend,0:,}")
+            .expect("(nope job1.suspend)");
+
+        use JobAction::*;
+        let actions = app.job_actions();
+        let actions: Vec<_> = actions.iter().map(|(a, id)| (*a, id.as_str())).collect();
+        assert_eq!(
+            actions,
+            vec![
+                (Suspend, "ab"),
+                (Resume, "abc"),
+                (Foreground, "ab"),
+                (Foreground, "a")
+            ]
+        );
+    }
+}
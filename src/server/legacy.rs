@@ -0,0 +1,385 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg::{self, MessageFormatter, ParseErrorKind};
+use crate::common::core::{ModuleIdentifier, ScopedIdentifier};
+use crate::server;
+use crate::server::OutgoingAction;
+
+///The major version that legacy clients implicitly refer to. Unversioned names predate the
+///versioning of modules, so they always refer to the first major version.
+const LEGACY_MAJOR_VERSION: u16 = 1;
+
+///A [Handler](trait.Handler.html) for clients that predate versioned module names, and send
+///message types like `core.set` instead of `core1.set`.
+///
+///Since messages with unversioned types are not valid messages, they never reach the handler
+///chain on their own. This handler picks them up in `handle_error()`, translates them into the
+///versioned form and [substitutes](struct.Connection.html#method.substitute_input) them for the
+///original input, so that the rest of the chain (including this handler) sees them like messages
+///from any other client. Besides the message type, the property names in `core.sub`, `core.set`
+///and `core.pub` are translated as well. Unversioned names always refer to the first major
+///version of their module.
+///
+///Once a client has sent a legacy message, its connection is
+///[marked](struct.Connection.html#method.enable_legacy_names) accordingly, and
+///[LegacyNamesFilter](struct.LegacyNamesFilter.html) translates replies back into the legacy
+///form. Both are opt-in, and should be placed at the start of their respective chains, so that
+///all other handlers and filters only ever see the versioned form:
+///
+///```no_run
+///# use vt6::common::core::{msg, ClientID};
+///# use vt6::server::testing::{MockMessageConnector, MockStdoutConnector};
+///# use vt6::server::{core, *};
+///# type MyHandler<Next> = vt6::server::term::MessageHandler<Next>;
+///# #[derive(Clone)]
+///# struct MyApplication;
+///impl Application for MyApplication {
+///    type MessageHandler = LegacyNamesHandler<core::MessageHandler<MyHandler<RejectHandler>>>;
+///    type HandshakeHandler = LegacyNamesHandler<core::HandshakeHandler<RejectHandler>>;
+///
+///    fn has_outgoing_filter(&self) -> bool {
+///        true
+///    }
+///
+///    fn filter_outgoing<D: Dispatch<Self>>(
+///        &self,
+///        msg: &msg::Message,
+///        conn: &Connection<Self, D>,
+///    ) -> OutgoingAction {
+///        LegacyNamesFilter::<PassFilter>::default().filter(msg, conn)
+///    }
+///
+///    //... other fields elided ...
+///#     type MessageConnector = MockMessageConnector;
+///#     type StdoutConnector = MockStdoutConnector;
+///#     fn notify(&self, _: &Notification) {}
+///#     fn register_client(&self, _: ClientIdentity) -> ClientCredentials { unimplemented!() }
+///#     fn unregister_clients(&self, _: ClientSelector) {}
+///#     fn has_clients(&self, _: ClientSelector) -> bool { false }
+///#     fn authorize_client(
+///#         &self,
+///#         _: &str,
+///#         _: Option<&PeerCredentials>,
+///#     ) -> Option<ClientIdentity> {
+///#         None
+///#     }
+///#     fn find_client(&self, _: ClientID<'_>) -> Option<ClientIdentity> { None }
+///#     fn authorize_stdin(&self, _: &str) -> Option<ScreenIdentity> { None }
+///#     fn authorize_stdout(&self, _: &str) -> Option<ScreenIdentity> { None }
+///}
+///```
+#[derive(Default)]
+pub struct LegacyNamesHandler<Next>(Next);
+
+impl<A: server::Application, Next: server::MessageHandler<A>> server::MessageHandler<A>
+    for LegacyNamesHandler<Next>
+{
+    fn get_supported_module_version(&self, module: &ModuleIdentifier<'_>) -> Option<u16> {
+        self.0.get_supported_module_version(module)
+    }
+
+    fn get_module_deprecation(
+        &self,
+        module: &ModuleIdentifier<'_>,
+    ) -> Option<server::ModuleDeprecation> {
+        self.0.get_module_deprecation(module)
+    }
+}
+
+impl<A: server::Application, Next: server::HandshakeHandler<A>> server::HandshakeHandler<A>
+    for LegacyNamesHandler<Next>
+{
+}
+
+impl<A: server::Application, Next: server::Handler<A>> server::Handler<A>
+    for LegacyNamesHandler<Next>
+{
+    fn handle<D: server::Dispatch<A>>(
+        &self,
+        msg: &msg::Message,
+        conn: &mut server::Connection<A, D>,
+    ) -> Result<(), server::HandlerError> {
+        self.0.handle(msg, conn)
+    }
+
+    fn handle_error<D: server::Dispatch<A>>(
+        &self,
+        err: &msg::ParseError,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        match translate_legacy_input(err) {
+            Some(Translation::Complete(len, replacement)) => {
+                conn.enable_legacy_names();
+                conn.substitute_input(len, replacement);
+            }
+            //the limit ensures that we do not wait forever for the end of something that is not
+            //a message at all
+            Some(Translation::Incomplete) if err.buffer.len() < 1024 => conn.await_more_input(),
+            _ => self.0.handle_error(err, conn),
+        }
+    }
+
+    fn on_connect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_connect(conn);
+    }
+
+    fn on_state_change<D: server::Dispatch<A>>(
+        &self,
+        old_state: &server::ConnectionState<A>,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.on_state_change(old_state, conn);
+    }
+
+    fn on_disconnect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_disconnect(conn);
+    }
+}
+
+///An [OutgoingFilter](trait.OutgoingFilter.html) that translates messages into the legacy form
+///with unversioned names, for clients that have been recognized by
+///[LegacyNamesHandler](struct.LegacyNamesHandler.html). Messages to all other clients are not
+///touched.
+///
+///Since the legacy form is not understood by other filters, this filter translates whatever the
+///rest of the chain decides to send, and should therefore be the first filter in the chain.
+///Besides message types, the property names in `core1.sub`, `core1.set` and `core1.pub`, and the
+///message type in `nope` are translated. Only names from the first major version of a module
+///are translated, since legacy clients cannot refer to any other version anyway.
+#[derive(Default)]
+pub struct LegacyNamesFilter<Next>(Next);
+
+impl<A: server::Application, Next: server::OutgoingFilter<A>> server::OutgoingFilter<A>
+    for LegacyNamesFilter<Next>
+{
+    fn filter<D: server::Dispatch<A>>(
+        &self,
+        msg: &msg::Message,
+        conn: &server::Connection<A, D>,
+    ) -> OutgoingAction {
+        let action = self.0.filter(msg, conn);
+        if !conn.uses_legacy_names() {
+            return action;
+        }
+        match action {
+            OutgoingAction::Pass => match legacy_form(msg) {
+                Some(buf) => OutgoingAction::Replace(buf),
+                None => OutgoingAction::Pass,
+            },
+            OutgoingAction::Replace(buf) => {
+                let translated = match msg::Message::parse(&buf) {
+                    Ok((replacement, _)) => legacy_form(&replacement),
+                    Err(_) => None,
+                };
+                OutgoingAction::Replace(translated.unwrap_or(buf))
+            }
+            OutgoingAction::Drop => OutgoingAction::Drop,
+        }
+    }
+}
+
+enum Translation {
+    //the translated message, and the length of the input that it replaces
+    Complete(usize, Vec<u8>),
+    //the input has a legacy message type, but the rest of the message has not arrived yet
+    Incomplete,
+}
+
+//Translates input that could not be parsed because it starts with a message in legacy form.
+fn translate_legacy_input(err: &msg::ParseError) -> Option<Translation> {
+    if err.kind != ParseErrorKind::InvalidMessageType {
+        return None;
+    }
+    //for this kind of error, the span covers the entire type argument, i.e. `{len}:{type},`
+    let span = err.span()?;
+    let token = &err.buffer[span.clone()];
+    let colon = token.iter().position(|&c| c == b':')?;
+    let msg_type = versioned_name(&token[colon + 1..token.len() - 1])?;
+    let new_token = format!("{}:{},", msg_type.len(), msg_type);
+
+    let mut buf = err.buffer[0..span.start].to_vec();
+    buf.extend_from_slice(new_token.as_bytes());
+    buf.extend_from_slice(&err.buffer[span.end..]);
+    let (msg, len) = match msg::Message::parse(&buf) {
+        Ok(result) => result,
+        Err(e) if e.is_incomplete() => return Some(Translation::Incomplete),
+        Err(_) => return None,
+    };
+    let original_len = len + span.len() - new_token.len();
+
+    let first_arg = match msg.arguments().next() {
+        Some(arg) if has_property_name(msg_type.as_str()) => versioned_name(arg),
+        _ => None,
+    };
+    let replacement = match first_arg {
+        Some(ref name) => encode_with_names(&msg, &msg_type, Some(name))?,
+        None => buf[0..len].to_vec(),
+    };
+    Some(Translation::Complete(original_len, replacement))
+}
+
+//Returns the legacy form of the given message, or `None` if it does not contain any names that
+//need to be translated.
+fn legacy_form(msg: &msg::Message) -> Option<Vec<u8>> {
+    let msg_type = msg.parsed_type();
+    let first_arg = match msg.arguments().next() {
+        Some(arg) if msg_type.as_str() == "nope" || has_property_name(msg_type.as_str()) => {
+            core::str::from_utf8(arg).ok().and_then(legacy_name)
+        }
+        _ => None,
+    };
+    let legacy_type = legacy_name(msg_type.as_str());
+    if legacy_type.is_none() && first_arg.is_none() {
+        return None;
+    }
+    let legacy_type = legacy_type.as_deref().unwrap_or_else(|| msg_type.as_str());
+    encode_with_names(msg, legacy_type, first_arg.as_deref())
+}
+
+//Returns whether the first argument of messages of this (versioned) type is a property name.
+fn has_property_name(msg_type: &str) -> bool {
+    matches!(msg_type, "core1.sub" | "core1.set" | "core1.pub")
+}
+
+//Translates a legacy name like `core.set` into `core1.set`. Returns `None` if the input is not
+//in legacy form.
+fn versioned_name(name: &[u8]) -> Option<String> {
+    let name = core::str::from_utf8(name).ok()?;
+    if ScopedIdentifier::parse(name).is_some() {
+        return None;
+    }
+    let (module, member) = name.split_once('.')?;
+    let result = format!("{}{}.{}", module, LEGACY_MAJOR_VERSION, member);
+    ScopedIdentifier::parse(&result)?;
+    Some(result)
+}
+
+//Translates a name like `core1.set` into `core.set`. Returns `None` if the input is not a scoped
+//identifier with the legacy major version.
+fn legacy_name(name: &str) -> Option<String> {
+    match ScopedIdentifier::parse(name)?.route() {
+        (module, LEGACY_MAJOR_VERSION, member) => Some(format!("{}.{}", module, member)),
+        _ => None,
+    }
+}
+
+//Encodes the given message with a different message type and, if given, a different first
+//argument.
+fn encode_with_names(
+    msg: &msg::Message,
+    msg_type: &str,
+    first_arg: Option<&str>,
+) -> Option<Vec<u8>> {
+    let mut buf = vec![0u8; 1024];
+    let args = msg.arguments();
+    let mut f = MessageFormatter::new(&mut buf, msg_type, args.len());
+    for (idx, arg) in args.enumerate() {
+        match first_arg {
+            Some(name) if idx == 0 => f.add_argument(name),
+            _ => f.add_argument(arg),
+        }
+    }
+    let len = f.finalize().ok()?;
+    buf.truncate(len);
+    Some(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::ClientID;
+    use crate::server::testing::{Conversation, MockApplication, MockHandlers};
+
+    #[test]
+    fn test_translate_legacy_input() {
+        let translate = |input: &[u8]| match msg::Message::parse(input) {
+            Ok(_) => panic!("input is not in legacy form"),
+            Err(e) => match translate_legacy_input(&e) {
+                Some(Translation::Complete(len, buf)) => {
+                    Some((len, String::from_utf8(buf).unwrap()))
+                }
+                Some(Translation::Incomplete) => Some((0, String::new())),
+                None => None,
+            },
+        };
+
+        //property names are translated as well, but only in the messages that contain them
+        assert_eq!(
+            translate(b"{3|8:core.set,10:core.title,3:foo,}{2|4:want,5:core1,}"),
+            Some((35, "{3|9:core1.set,11:core1.title,3:foo,}".into()))
+        );
+        assert_eq!(
+            translate(b"{2|9:sig.claim,9:interrupt,}"),
+            Some((28, "{2|10:sig1.claim,9:interrupt,}".into()))
+        );
+        //a message that has not been received completely
+        assert_eq!(translate(b"{2|8:core.sub,"), Some((0, String::new())));
+        //a message type that is invalid for other reasons
+        assert_eq!(translate(b"{1|3:foo,}"), None);
+        assert_eq!(translate(b"{2|8:core.sub,#}"), None);
+    }
+
+    struct LegacyHandlers;
+
+    impl MockHandlers for LegacyHandlers {
+        type MessageHandler =
+            LegacyNamesHandler<server::core::MessageHandler<server::RejectHandler>>;
+
+        fn has_outgoing_filter() -> bool {
+            true
+        }
+
+        fn filter_outgoing<D: server::Dispatch<MockApplication<Self>>>(
+            msg: &msg::Message,
+            conn: &server::Connection<MockApplication<Self>, D>,
+        ) -> OutgoingAction {
+            use server::OutgoingFilter;
+            LegacyNamesFilter::<server::PassFilter>::default().filter(msg, conn)
+        }
+    }
+
+    #[test]
+    fn test_legacy_client() {
+        let app: MockApplication<LegacyHandlers> = MockApplication::new();
+        let creds = server::Application::register_client(
+            &app,
+            server::ClientIdentity::new(&ClientID::parse("a").unwrap()),
+        );
+        let mut conv = Conversation::new(app);
+        conv.send_message(&crate::msg::posix::ClientHello {
+            secret: creds.secret(),
+        })
+        .expect(r#"(posix1.server-hello a "" "" "")"#);
+
+        //clients using versioned names are not affected
+        conv.send(b"{2|9:core1.sub,11:core1.title,}")
+            .expect("(nope core1.sub)");
+        assert!(!conv.connection().uses_legacy_names());
+
+        //once a legacy message is received, replies are translated into the legacy form
+        conv.send(b"{2|8:core.sub,10:core.title,}")
+            .expect_wire(b"{2|4:nope,8:core.sub,}");
+        assert!(conv.connection().uses_legacy_names());
+        conv.send(b"{2|8:core.sub,")
+            .expect_no_reply()
+            .send(b"10:core.title,}")
+            .expect_wire(b"{2|4:nope,8:core.sub,}");
+        assert_eq!(conv.connection().stats().parse_errors, 0);
+
+        conv.connection_mut()
+            .subscribe(&ScopedIdentifier::parse("example1.title").unwrap());
+        server::core::publish_property(&conv.dispatch(), "example1.title", b"foo", |_| true);
+        conv.expect_wire(b"{3|8:core.pub,13:example.title,3:foo,}");
+
+        //eternal message types are not translated
+        conv.send(b"{2|4:want,5:core1,}").expect("(have core1.0)");
+
+        //input that is invalid for other reasons is still reported as such
+        conv.send(b"{1|3:foo,}").expect_no_reply();
+        assert_eq!(conv.connection().stats().parse_errors, 1);
+    }
+}
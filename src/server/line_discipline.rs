@@ -0,0 +1,181 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+///Options for a [LineDiscipline](struct.LineDiscipline.html).
+///
+///The default value has all options disabled, i.e. input is passed through unchanged and
+///immediately.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LineDisciplineOptions {
+    ///Whether input is echoed back onto the screen.
+    pub echo: bool,
+    ///Whether input is held back until a full line has been entered.
+    pub line_buffered: bool,
+}
+
+///A line discipline for connections in stdin mode.
+///
+///Legacy clients expect the terminal (or rather, the kernel's TTY layer) to provide local echo and
+///line editing. When a line discipline is attached to a connection in stdin mode via
+///[`Connection::set_line_discipline()`](struct.Connection.html#method.set_line_discipline), all
+///input enqueued with `Connection::enqueue_stdin()` passes through it:
+///
+///* If `echo` is enabled, input is echoed back to the
+///  [StdoutConnector](trait.StdoutConnector.html) for the same screen.
+///* If `line_buffered` is enabled, input is held back until a newline is entered. Carriage
+///  returns are translated into newlines, and backspace (`^H`) or delete (`^?`) erase the last
+///  character of the line that has not been sent yet.
+#[derive(Clone, Debug, Default)]
+pub struct LineDiscipline {
+    options: LineDisciplineOptions,
+    pending: Vec<u8>,
+}
+
+impl LineDiscipline {
+    ///Creates a new line discipline with the given options.
+    pub fn new(options: LineDisciplineOptions) -> Self {
+        Self {
+            options,
+            pending: Vec::new(),
+        }
+    }
+
+    ///Returns the current options.
+    pub fn options(&self) -> LineDisciplineOptions {
+        self.options
+    }
+
+    ///Changes the options of this line discipline. When line buffering gets disabled, the partial
+    ///line that was held back until now is appended to `stdin`.
+    pub fn set_options(&mut self, options: LineDisciplineOptions, stdin: &mut Vec<u8>) {
+        self.options = options;
+        if !options.line_buffered {
+            stdin.append(&mut self.pending);
+        }
+    }
+
+    ///Returns the partial line that is being held back because line buffering is enabled.
+    pub fn pending(&self) -> &[u8] {
+        &self.pending
+    }
+
+    ///Processes the given input. Bytes that shall be sent to the client are appended to `stdin`,
+    ///and bytes that shall be shown on the screen are appended to `echo`.
+    ///
+    ///```
+    ///# use vt6::server::*;
+    ///let mut ld = LineDiscipline::new(LineDisciplineOptions {
+    ///    echo: true,
+    ///    line_buffered: true,
+    ///});
+    ///let (mut stdin, mut echo) = (Vec::new(), Vec::new());
+    ///ld.process(b"helo\x7Flo", &mut stdin, &mut echo);
+    ///assert_eq!(stdin, b"");
+    ///ld.process(b"\r", &mut stdin, &mut echo);
+    ///assert_eq!(stdin, b"hello\n");
+    ///assert_eq!(echo, b"helo\x08 \x08lo\n");
+    ///```
+    pub fn process(&mut self, input: &[u8], stdin: &mut Vec<u8>, echo: &mut Vec<u8>) {
+        if !self.options.line_buffered {
+            stdin.append(&mut self.pending);
+            stdin.extend_from_slice(input);
+            if self.options.echo {
+                echo.extend_from_slice(input);
+            }
+            return;
+        }
+
+        for &byte in input {
+            match byte {
+                b'\r' | b'\n' => {
+                    self.pending.push(b'\n');
+                    stdin.append(&mut self.pending);
+                    if self.options.echo {
+                        echo.push(b'\n');
+                    }
+                }
+                0x08 | 0x7F => {
+                    //erase the last character, not just the last byte
+                    let len_before = self.pending.len();
+                    while let Some(b) = self.pending.pop() {
+                        //stop once we have removed something that is not a UTF-8 continuation
+                        //byte (i.e., the start of the last character)
+                        if b & 0xC0 != 0x80 {
+                            break;
+                        }
+                    }
+                    if self.options.echo && self.pending.len() != len_before {
+                        echo.extend_from_slice(b"\x08 \x08");
+                    }
+                }
+                _ => {
+                    self.pending.push(byte);
+                    if self.options.echo {
+                        echo.push(byte);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(options: LineDisciplineOptions, inputs: &[&[u8]], stdin: &[u8], echo: &[u8]) {
+        let mut ld = LineDiscipline::new(options);
+        let (mut actual_stdin, mut actual_echo) = (Vec::new(), Vec::new());
+        for input in inputs {
+            ld.process(input, &mut actual_stdin, &mut actual_echo);
+        }
+        assert_eq!(actual_stdin, stdin);
+        assert_eq!(actual_echo, echo);
+    }
+
+    #[test]
+    fn test_line_discipline() {
+        let raw = LineDisciplineOptions::default();
+        let echo = LineDisciplineOptions {
+            echo: true,
+            line_buffered: false,
+        };
+        let cooked = LineDisciplineOptions {
+            echo: false,
+            line_buffered: true,
+        };
+
+        check(raw, &[b"foo\x7F\r", b"bar"], b"foo\x7F\rbar", b"");
+        check(
+            echo,
+            &[b"foo\x7F\r", b"bar"],
+            b"foo\x7F\rbar",
+            b"foo\x7F\rbar",
+        );
+        check(cooked, &[b"foo\x7F\r", b"bar"], b"fo\n", b"");
+        check(cooked, &[b"foo", b"\n", b"bar\nbaz"], b"foo\nbar\n", b"");
+        //erasing removes entire UTF-8 characters, but never more than what is pending
+        check(cooked, &["a\u{1F4A9}\x7F".as_bytes(), b"\n"], b"a\n", b"");
+        check(cooked, &[b"a\n\x7F\x7Fb\n"], b"a\nb\n", b"");
+    }
+
+    #[test]
+    fn test_line_discipline_switch_modes() {
+        let mut ld = LineDiscipline::new(LineDisciplineOptions {
+            echo: false,
+            line_buffered: true,
+        });
+        let (mut stdin, mut echo) = (Vec::new(), Vec::new());
+        ld.process(b"foo\nbar", &mut stdin, &mut echo);
+        assert_eq!(stdin, b"foo\n");
+        assert_eq!(ld.pending(), b"bar");
+
+        //disabling line buffering releases the partial line
+        ld.set_options(LineDisciplineOptions::default(), &mut stdin);
+        assert_eq!(stdin, b"foo\nbar");
+        assert_eq!(ld.pending(), b"");
+    }
+}
@@ -31,6 +31,8 @@ working together.
 
 mod application;
 pub use application::*;
+mod attachments;
+pub use attachments::*;
 mod auth;
 pub use auth::*;
 mod connection;
@@ -39,17 +41,61 @@ mod dispatch;
 pub use dispatch::*;
 mod handler;
 pub use handler::*;
+mod legacy;
+pub use legacy::*;
+mod line_discipline;
+pub use line_discipline::*;
 mod notification;
 pub use notification::*;
+mod outgoing;
+pub use outgoing::*;
+mod recording;
+pub use recording::*;
 mod reject;
 pub use reject::*;
+mod shaping;
+pub use shaping::*;
+mod stats;
+pub use stats::*;
+mod stdout_mux;
+pub use stdout_mux::*;
+mod subscriptions;
+pub use subscriptions::*;
 mod util;
 pub use util::*;
 
+#[cfg(feature = "module_clipboard")]
+///Handlers and types for the `clip1` module (an extension provided by this crate).
+pub mod clipboard;
 ///Handlers and types for the [vt6::core](https://vt6.io/std/core/) module. Also implements some
 ///behavior defined in [vt6::foundation](https://vt6.io/std/foundation/).
 pub mod core;
+#[cfg(feature = "module_deflate")]
+///Handlers for the `deflate1` module (an extension provided by this crate).
+pub mod deflate;
+#[cfg(feature = "module_frame")]
+///Handlers for the `frame1` module (an extension provided by this crate).
+pub mod frame;
+#[cfg(feature = "module_input")]
+///Handlers and legacy encodings for the `input1` module (an extension provided by this crate).
+pub mod input;
+#[cfg(feature = "module_job")]
+///Handlers and types for the `job1` module (an extension provided by this crate).
+pub mod job;
+#[cfg(feature = "module_sig")]
+///Handlers and types for the [vt6::sig](https://vt6.io/std/sig/) module.
+pub mod sig;
+#[cfg(feature = "module_term")]
+///Handlers and types for the [vt6::term](https://vt6.io/std/term/) module.
+pub mod term;
+///Utilities for testing handler chains without a real server socket.
+pub mod testing;
 
-#[cfg(feature = "use_tokio")]
-///An implementation of a server listener using the [Tokio library](https://tokio.rs/).
+#[cfg(all(
+    feature = "use_tokio",
+    any(unix, all(windows, feature = "use_windows_pipes"))
+))]
+///An implementation of a server listener using the [Tokio library](https://tokio.rs/). On Windows,
+///this requires the `use_windows_pipes` feature, and listens on named pipes instead of Unix
+///sockets.
 pub mod tokio;
@@ -4,6 +4,9 @@
 * Refer to the file "LICENSE" for details.
 *******************************************************************************/
 
+use crate::common::core::msg::OwnedParseError;
+use crate::server::{ModuleDeprecation, SendBufferPolicy, TeardownReason};
+
 ///A notification that originates somewhere within this module.
 ///
 ///Notifications are sent to application-level code through the notify() function on [trait
@@ -15,52 +18,1050 @@
 ///New versions of this library can add new variants to this enum at any time. Applications should
 ///always have a catch-all branch when matching on variants of this enum.
 ///
+///## Listener labels
+///
+///All variants concerning a specific client connection have a `listener` field. When the
+///Dispatch accepts connections on multiple server sockets, this contains the label of the
+///listener that accepted the connection (see
+///[`Connection::listener()`](struct.Connection.html#method.listener)).
+///
 #[derive(Debug)]
 pub enum Notification<'a> {
     ///A new client connection was accepted.
-    ConnectionOpened,
+    ConnectionOpened { listener: Option<&'a str> },
     ///A client connection encountered an IO error.
-    ConnectionIOError(Box<dyn std::error::Error>),
-    ///A client connection was closed.
-    ConnectionClosed,
-    ///The referenced bytestring is about to be discarded from a receive buffer to recover from a
-    ///parse error. This notification is always sent immediately after IncomingParseError.
-    IncomingBytesDiscarded(&'a [u8]),
+    ConnectionIOError {
+        listener: Option<&'a str>,
+        error: Box<dyn std::error::Error>,
+    },
+    ///A client connection was closed. `reason` tells why the connection was torn down.
+    ConnectionClosed {
+        listener: Option<&'a str>,
+        reason: TeardownReason,
+    },
+    ///A message sent by the client could not be parsed.
+    IncomingParseError {
+        listener: Option<&'a str>,
+        error: OwnedParseError,
+    },
+    ///Input was discarded from a receive buffer, usually to recover from a parse error. To avoid
+    ///flooding the log when a client sends large amounts of garbage, discarded input is aggregated
+    ///per connection, and this notification is sent at most once per
+    ///[`Dispatch::discard_notification_interval()`](trait.Dispatch.html#method.discard_notification_interval)
+    ///for each connection.
+    IncomingBytesDiscarded {
+        listener: Option<&'a str>,
+        discarded: &'a DiscardedBytes,
+    },
+    ///Data for the client could not be enqueued without exceeding the limit on the send buffers of
+    ///all connections combined, so the Dispatch applied its
+    ///[SendBufferPolicy](enum.SendBufferPolicy.html). `queued` is the amount of data that was
+    ///waiting to be sent before, and `requested` is the size of the new data. `accepted` is
+    ///whether the new data was enqueued after all.
+    SendBufferLimitExceeded {
+        listener: Option<&'a str>,
+        limit: usize,
+        queued: usize,
+        requested: usize,
+        policy: SendBufferPolicy,
+        accepted: bool,
+    },
+    ///Writing to the client socket did not make progress within the write timeout configured on
+    ///the Dispatch, usually because the client does not read from its socket. `queued` is the
+    ///amount of data that is still waiting to be sent. If `closed` is true, the Dispatch tears
+    ///the connection down; otherwise it keeps trying, and reports again after each timeout.
+    WriteTimeout {
+        listener: Option<&'a str>,
+        timeout: std::time::Duration,
+        queued: usize,
+        closed: bool,
+    },
+    ///A handler tried to put the connection into a state that cannot be reached from its current
+    ///state, e.g. from `Stdout` back into `Handshake`. The state was not changed. `from` and `to`
+    ///are the [type names](enum.ConnectionState.html#method.type_name) of the states. See
+    ///[`Connection::try_transition()`](struct.Connection.html#method.try_transition).
+    InvalidStateTransition {
+        listener: Option<&'a str>,
+        from: &'static str,
+        to: &'static str,
+    },
+    ///A message for the client was dropped instead of being enqueued, because sending it would
+    ///have violated the protocol. This usually indicates a bug in a handler or in the
+    ///Application. `size` is the encoded size of the message.
+    MessageDropped {
+        listener: Option<&'a str>,
+        size: usize,
+        reason: DropReason,
+    },
+    ///Standard input for the client was dropped instead of being enqueued, because the connection
+    ///was not a stdin connection. Like `MessageDropped`, this indicates a bug in the code that
+    ///enqueued the input. `size` is the number of bytes that were dropped.
+    StdinDropped {
+        listener: Option<&'a str>,
+        size: usize,
+        reason: DropReason,
+    },
+    ///Handling a message took at least as long as the
+    ///[`Dispatch::slow_handler_threshold()`](trait.Dispatch.html#method.slow_handler_threshold).
+    ///`msg_type` is the type of the message, and `elapsed` is how long its handler took.
+    SlowHandler {
+        listener: Option<&'a str>,
+        msg_type: &'a str,
+        elapsed: std::time::Duration,
+        threshold: std::time::Duration,
+    },
+    ///The data waiting in the send buffer of the connection has reached the high watermark
+    ///configured on the Dispatch, i.e. the client does not keep up with reading what is sent to
+    ///it (e.g. a pager that is paused while its stdin keeps coming in). UIs can use this to show
+    ///an "input blocked" indicator until the corresponding `SendBufferLowWatermark` arrives.
+    SendBufferHighWatermark {
+        listener: Option<&'a str>,
+        queued: usize,
+        watermark: usize,
+    },
+    ///After a `SendBufferHighWatermark`, the data waiting in the send buffer of the connection has
+    ///fallen to the low watermark configured on the Dispatch.
+    SendBufferLowWatermark {
+        listener: Option<&'a str>,
+        queued: usize,
+        watermark: usize,
+    },
+    ///A client agreed on a module that a handler reported as deprecated through
+    ///[`MessageHandler::get_module_deprecation()`](trait.MessageHandler.html#method.get_module_deprecation).
+    ///`module` is the module identifier including the major version, e.g. `term1`. This is
+    ///reported once per connection and module.
+    DeprecatedModuleNegotiated {
+        listener: Option<&'a str>,
+        module: &'a str,
+        deprecation: ModuleDeprecation,
+    },
+    ///A client connection was closed right after it was accepted, before a Connection object was
+    ///created for it. `reason` tells who decided to refuse it.
+    ConnectionRefused {
+        listener: Option<&'a str>,
+        reason: RefusalReason,
+    },
     //TODO Note to self: Before 1.0, check which variants have been obsoleted by proper APIs
     //elsewhere.
 }
 
 impl<'a> Notification<'a> {
+    ///Returns which kind of notification this is, e.g. for checking it against a
+    ///[NotificationFilter](struct.NotificationFilter.html).
+    pub fn kind(&self) -> NotificationKind {
+        match self {
+            Self::ConnectionOpened { .. } => NotificationKind::ConnectionOpened,
+            Self::ConnectionIOError { .. } => NotificationKind::ConnectionIOError,
+            Self::ConnectionClosed { .. } => NotificationKind::ConnectionClosed,
+            Self::IncomingParseError { .. } => NotificationKind::IncomingParseError,
+            Self::IncomingBytesDiscarded { .. } => NotificationKind::IncomingBytesDiscarded,
+            Self::SendBufferLimitExceeded { .. } => NotificationKind::SendBufferLimitExceeded,
+            Self::WriteTimeout { .. } => NotificationKind::WriteTimeout,
+            Self::InvalidStateTransition { .. } => NotificationKind::InvalidStateTransition,
+            Self::MessageDropped { .. } => NotificationKind::MessageDropped,
+            Self::StdinDropped { .. } => NotificationKind::StdinDropped,
+            Self::SlowHandler { .. } => NotificationKind::SlowHandler,
+            Self::SendBufferHighWatermark { .. } => NotificationKind::SendBufferHighWatermark,
+            Self::SendBufferLowWatermark { .. } => NotificationKind::SendBufferLowWatermark,
+            Self::DeprecatedModuleNegotiated { .. } => NotificationKind::DeprecatedModuleNegotiated,
+            Self::ConnectionRefused { .. } => NotificationKind::ConnectionRefused,
+        }
+    }
+
     ///Returns whether this notification is an error or an informational message.
     pub fn is_error(&self) -> bool {
         match self {
-            Self::ConnectionOpened => false,
-            Self::ConnectionIOError(_) => true,
-            Self::ConnectionClosed => false,
-            Self::IncomingBytesDiscarded(_) => false,
+            Self::ConnectionOpened { .. } => false,
+            Self::ConnectionIOError { .. } => true,
+            Self::ConnectionClosed { .. } => false,
+            Self::IncomingParseError { .. } => true,
+            Self::IncomingBytesDiscarded { .. } => false,
+            Self::SendBufferLimitExceeded { .. } => true,
+            Self::WriteTimeout { .. } => true,
+            Self::InvalidStateTransition { .. } => true,
+            Self::MessageDropped { .. } => true,
+            Self::StdinDropped { .. } => true,
+            Self::SlowHandler { .. } => false,
+            Self::SendBufferHighWatermark { .. } => false,
+            Self::SendBufferLowWatermark { .. } => false,
+            Self::DeprecatedModuleNegotiated { .. } => false,
+            Self::ConnectionRefused { .. } => false,
+        }
+    }
+
+    ///Returns the label of the listener that accepted the connection that this notification is
+    ///about, if any.
+    pub fn listener(&self) -> Option<&'a str> {
+        match *self {
+            Self::ConnectionOpened { listener } => listener,
+            Self::ConnectionIOError { listener, .. } => listener,
+            Self::ConnectionClosed { listener, .. } => listener,
+            Self::IncomingParseError { listener, .. } => listener,
+            Self::IncomingBytesDiscarded { listener, .. } => listener,
+            Self::SendBufferLimitExceeded { listener, .. } => listener,
+            Self::WriteTimeout { listener, .. } => listener,
+            Self::InvalidStateTransition { listener, .. } => listener,
+            Self::MessageDropped { listener, .. } => listener,
+            Self::StdinDropped { listener, .. } => listener,
+            Self::SlowHandler { listener, .. } => listener,
+            Self::SendBufferHighWatermark { listener, .. } => listener,
+            Self::SendBufferLowWatermark { listener, .. } => listener,
+            Self::DeprecatedModuleNegotiated { listener, .. } => listener,
+            Self::ConnectionRefused { listener, .. } => listener,
         }
     }
 }
 
 impl<'a> std::fmt::Display for Notification<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(label) = self.listener() {
+            write!(f, "[{}] ", label)?;
+        }
         match self {
-            Self::ConnectionOpened => {
+            Self::ConnectionOpened { .. } => {
                 write!(f, "client connection opened")
             }
-            Self::ConnectionIOError(e) => {
-                write!(f, "client connection encountered IO error: {}", e)
+            Self::ConnectionIOError { error, .. } => {
+                write!(f, "client connection encountered IO error: {}", error)
+            }
+            Self::ConnectionClosed { reason, .. } => {
+                write!(f, "client connection closed ({})", reason)
+            }
+            Self::IncomingParseError { error, .. } => {
+                write!(f, "client sent invalid message: {}", error)
+            }
+            Self::IncomingBytesDiscarded { discarded: d, .. } => {
+                write!(
+                    f,
+                    "discarded {} bytes of invalid input at offsets {}..={}: {:?}",
+                    d.total_bytes,
+                    d.first_offset,
+                    d.last_offset,
+                    std::string::String::from_utf8_lossy(&d.sample)
+                )?;
+                if d.total_bytes > d.sample.len() as u64 {
+                    write!(f, " (truncated)")?;
+                }
+                Ok(())
+            }
+            Self::SendBufferLimitExceeded {
+                limit,
+                queued,
+                requested,
+                policy,
+                accepted,
+                ..
+            } => {
+                write!(
+                    f,
+                    "cannot enqueue {} bytes with {} bytes already queued (limit is {} bytes), applied policy {:?}, new data was {}",
+                    requested,
+                    queued,
+                    limit,
+                    policy,
+                    if *accepted { "enqueued" } else { "discarded" }
+                )
+            }
+            Self::WriteTimeout {
+                timeout,
+                queued,
+                closed,
+                ..
+            } => {
+                write!(
+                    f,
+                    "client did not read for {:?} with {} bytes still queued",
+                    timeout, queued
+                )?;
+                if *closed {
+                    write!(f, ", closing connection")?;
+                }
+                Ok(())
+            }
+            Self::InvalidStateTransition { from, to, .. } => {
+                write!(
+                    f,
+                    "rejected transition of client connection from state {} into state {}",
+                    from, to
+                )
+            }
+            Self::MessageDropped { size, reason, .. } => {
+                write!(
+                    f,
+                    "dropped message of {} bytes for client: {}",
+                    size, reason
+                )
+            }
+            Self::StdinDropped { size, reason, .. } => {
+                write!(f, "dropped {} bytes of stdin for client: {}", size, reason)
+            }
+            Self::SlowHandler {
+                msg_type,
+                elapsed,
+                threshold,
+                ..
+            } => {
+                write!(
+                    f,
+                    "handling {} message took {:?} (threshold is {:?})",
+                    msg_type, elapsed, threshold
+                )
             }
-            Self::ConnectionClosed => {
-                write!(f, "client connection closed")
+            Self::SendBufferHighWatermark {
+                queued, watermark, ..
+            } => {
+                write!(
+                    f,
+                    "client is not keeping up with reading: {} bytes queued (high watermark is {} bytes)",
+                    queued, watermark
+                )
             }
-            Self::IncomingBytesDiscarded(buf) => {
+            Self::SendBufferLowWatermark {
+                queued, watermark, ..
+            } => {
                 write!(
                     f,
-                    "discarded invalid input: {:?}",
-                    std::string::String::from_utf8_lossy(buf)
+                    "client has caught up with reading: {} bytes queued (low watermark is {} bytes)",
+                    queued, watermark
                 )
             }
+            Self::DeprecatedModuleNegotiated {
+                module,
+                deprecation,
+                ..
+            } => {
+                write!(f, "client negotiated deprecated module {}", module)?;
+                if deprecation.replacement.is_some() || deprecation.sunset.is_some() {
+                    write!(f, " ({})", deprecation)?;
+                }
+                Ok(())
+            }
+            Self::ConnectionRefused { reason, .. } => {
+                write!(f, "client connection refused ({})", reason)
+            }
+        }
+    }
+}
+
+///An owned version of [Notification](enum.Notification.html).
+///
+///Notification borrows from the connection that it concerns, so it cannot leave the call to
+///[`Application::notify()`](trait.Application.html#tymethod.notify). OwnedNotification can be
+///stored or sent to a different thread, e.g. through a
+///[NotificationSender](struct.NotificationSender.html). Since the original error in
+///`ConnectionIOError` may not be `Send`, only its message is retained. The variants have the same
+///meaning as in Notification.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OwnedNotification {
+    ConnectionOpened {
+        listener: Option<String>,
+    },
+    ConnectionIOError {
+        listener: Option<String>,
+        error: String,
+    },
+    ConnectionClosed {
+        listener: Option<String>,
+        reason: TeardownReason,
+    },
+    IncomingParseError {
+        listener: Option<String>,
+        error: OwnedParseError,
+    },
+    IncomingBytesDiscarded {
+        listener: Option<String>,
+        discarded: DiscardedBytes,
+    },
+    SendBufferLimitExceeded {
+        listener: Option<String>,
+        limit: usize,
+        queued: usize,
+        requested: usize,
+        policy: SendBufferPolicy,
+        accepted: bool,
+    },
+    WriteTimeout {
+        listener: Option<String>,
+        timeout: std::time::Duration,
+        queued: usize,
+        closed: bool,
+    },
+    InvalidStateTransition {
+        listener: Option<String>,
+        from: &'static str,
+        to: &'static str,
+    },
+    MessageDropped {
+        listener: Option<String>,
+        size: usize,
+        reason: DropReason,
+    },
+    StdinDropped {
+        listener: Option<String>,
+        size: usize,
+        reason: DropReason,
+    },
+    SlowHandler {
+        listener: Option<String>,
+        msg_type: String,
+        elapsed: std::time::Duration,
+        threshold: std::time::Duration,
+    },
+    SendBufferHighWatermark {
+        listener: Option<String>,
+        queued: usize,
+        watermark: usize,
+    },
+    SendBufferLowWatermark {
+        listener: Option<String>,
+        queued: usize,
+        watermark: usize,
+    },
+    DeprecatedModuleNegotiated {
+        listener: Option<String>,
+        module: String,
+        deprecation: ModuleDeprecation,
+    },
+    ConnectionRefused {
+        listener: Option<String>,
+        reason: RefusalReason,
+    },
+}
+
+impl<'a, 'b> From<&'a Notification<'b>> for OwnedNotification {
+    fn from(n: &'a Notification<'b>) -> Self {
+        let listener = n.listener().map(String::from);
+        match n {
+            Notification::ConnectionOpened { .. } => Self::ConnectionOpened { listener },
+            Notification::ConnectionIOError { error, .. } => Self::ConnectionIOError {
+                listener,
+                error: error.to_string(),
+            },
+            Notification::ConnectionClosed { reason, .. } => Self::ConnectionClosed {
+                listener,
+                reason: *reason,
+            },
+            Notification::IncomingParseError { error, .. } => Self::IncomingParseError {
+                listener,
+                error: error.clone(),
+            },
+            Notification::IncomingBytesDiscarded { discarded, .. } => {
+                Self::IncomingBytesDiscarded {
+                    listener,
+                    discarded: (*discarded).clone(),
+                }
+            }
+            Notification::SendBufferLimitExceeded {
+                limit,
+                queued,
+                requested,
+                policy,
+                accepted,
+                ..
+            } => Self::SendBufferLimitExceeded {
+                listener,
+                limit: *limit,
+                queued: *queued,
+                requested: *requested,
+                policy: *policy,
+                accepted: *accepted,
+            },
+            Notification::WriteTimeout {
+                timeout,
+                queued,
+                closed,
+                ..
+            } => Self::WriteTimeout {
+                listener,
+                timeout: *timeout,
+                queued: *queued,
+                closed: *closed,
+            },
+            Notification::InvalidStateTransition { from, to, .. } => {
+                Self::InvalidStateTransition { listener, from, to }
+            }
+            Notification::MessageDropped { size, reason, .. } => Self::MessageDropped {
+                listener,
+                size: *size,
+                reason: *reason,
+            },
+            Notification::StdinDropped { size, reason, .. } => Self::StdinDropped {
+                listener,
+                size: *size,
+                reason: *reason,
+            },
+            Notification::SlowHandler {
+                msg_type,
+                elapsed,
+                threshold,
+                ..
+            } => Self::SlowHandler {
+                listener,
+                msg_type: (*msg_type).into(),
+                elapsed: *elapsed,
+                threshold: *threshold,
+            },
+            Notification::SendBufferHighWatermark {
+                queued, watermark, ..
+            } => Self::SendBufferHighWatermark {
+                listener,
+                queued: *queued,
+                watermark: *watermark,
+            },
+            Notification::SendBufferLowWatermark {
+                queued, watermark, ..
+            } => Self::SendBufferLowWatermark {
+                listener,
+                queued: *queued,
+                watermark: *watermark,
+            },
+            Notification::DeprecatedModuleNegotiated {
+                module,
+                deprecation,
+                ..
+            } => Self::DeprecatedModuleNegotiated {
+                listener,
+                module: (*module).into(),
+                deprecation: *deprecation,
+            },
+            Notification::ConnectionRefused { reason, .. } => Self::ConnectionRefused {
+                listener,
+                reason: *reason,
+            },
+        }
+    }
+}
+
+impl OwnedNotification {
+    ///Same as [`Notification::kind()`](enum.Notification.html#method.kind).
+    pub fn kind(&self) -> NotificationKind {
+        match self {
+            Self::ConnectionOpened { .. } => NotificationKind::ConnectionOpened,
+            Self::ConnectionIOError { .. } => NotificationKind::ConnectionIOError,
+            Self::ConnectionClosed { .. } => NotificationKind::ConnectionClosed,
+            Self::IncomingParseError { .. } => NotificationKind::IncomingParseError,
+            Self::IncomingBytesDiscarded { .. } => NotificationKind::IncomingBytesDiscarded,
+            Self::SendBufferLimitExceeded { .. } => NotificationKind::SendBufferLimitExceeded,
+            Self::WriteTimeout { .. } => NotificationKind::WriteTimeout,
+            Self::InvalidStateTransition { .. } => NotificationKind::InvalidStateTransition,
+            Self::MessageDropped { .. } => NotificationKind::MessageDropped,
+            Self::StdinDropped { .. } => NotificationKind::StdinDropped,
+            Self::SlowHandler { .. } => NotificationKind::SlowHandler,
+            Self::SendBufferHighWatermark { .. } => NotificationKind::SendBufferHighWatermark,
+            Self::SendBufferLowWatermark { .. } => NotificationKind::SendBufferLowWatermark,
+            Self::DeprecatedModuleNegotiated { .. } => NotificationKind::DeprecatedModuleNegotiated,
+            Self::ConnectionRefused { .. } => NotificationKind::ConnectionRefused,
         }
     }
+
+    ///Same as [`Notification::is_error()`](enum.Notification.html#method.is_error).
+    pub fn is_error(&self) -> bool {
+        match self {
+            Self::ConnectionOpened { .. } => false,
+            Self::ConnectionIOError { .. } => true,
+            Self::ConnectionClosed { .. } => false,
+            Self::IncomingParseError { .. } => true,
+            Self::IncomingBytesDiscarded { .. } => false,
+            Self::SendBufferLimitExceeded { .. } => true,
+            Self::WriteTimeout { .. } => true,
+            Self::InvalidStateTransition { .. } => true,
+            Self::MessageDropped { .. } => true,
+            Self::StdinDropped { .. } => true,
+            Self::SlowHandler { .. } => false,
+            Self::SendBufferHighWatermark { .. } => false,
+            Self::SendBufferLowWatermark { .. } => false,
+            Self::DeprecatedModuleNegotiated { .. } => false,
+            Self::ConnectionRefused { .. } => false,
+        }
+    }
+
+    ///Same as [`Notification::listener()`](enum.Notification.html#method.listener).
+    pub fn listener(&self) -> Option<&str> {
+        match self {
+            Self::ConnectionOpened { listener } => listener.as_deref(),
+            Self::ConnectionIOError { listener, .. } => listener.as_deref(),
+            Self::ConnectionClosed { listener, .. } => listener.as_deref(),
+            Self::IncomingParseError { listener, .. } => listener.as_deref(),
+            Self::IncomingBytesDiscarded { listener, .. } => listener.as_deref(),
+            Self::SendBufferLimitExceeded { listener, .. } => listener.as_deref(),
+            Self::WriteTimeout { listener, .. } => listener.as_deref(),
+            Self::InvalidStateTransition { listener, .. } => listener.as_deref(),
+            Self::MessageDropped { listener, .. } => listener.as_deref(),
+            Self::StdinDropped { listener, .. } => listener.as_deref(),
+            Self::SlowHandler { listener, .. } => listener.as_deref(),
+            Self::SendBufferHighWatermark { listener, .. } => listener.as_deref(),
+            Self::SendBufferLowWatermark { listener, .. } => listener.as_deref(),
+            Self::DeprecatedModuleNegotiated { listener, .. } => listener.as_deref(),
+            Self::ConnectionRefused { listener, .. } => listener.as_deref(),
+        }
+    }
+}
+
+impl std::fmt::Display for OwnedNotification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        //render through the borrowed form to ensure that both forms look the same
+        let listener = self.listener();
+        let n = match self {
+            Self::ConnectionOpened { .. } => Notification::ConnectionOpened { listener },
+            Self::ConnectionIOError { error, .. } => Notification::ConnectionIOError {
+                listener,
+                error: error.as_str().into(),
+            },
+            Self::ConnectionClosed { reason, .. } => Notification::ConnectionClosed {
+                listener,
+                reason: *reason,
+            },
+            Self::IncomingParseError { error, .. } => Notification::IncomingParseError {
+                listener,
+                error: error.clone(),
+            },
+            Self::IncomingBytesDiscarded { discarded, .. } => {
+                Notification::IncomingBytesDiscarded {
+                    listener,
+                    discarded,
+                }
+            }
+            Self::SendBufferLimitExceeded {
+                limit,
+                queued,
+                requested,
+                policy,
+                accepted,
+                ..
+            } => Notification::SendBufferLimitExceeded {
+                listener,
+                limit: *limit,
+                queued: *queued,
+                requested: *requested,
+                policy: *policy,
+                accepted: *accepted,
+            },
+            Self::WriteTimeout {
+                timeout,
+                queued,
+                closed,
+                ..
+            } => Notification::WriteTimeout {
+                listener,
+                timeout: *timeout,
+                queued: *queued,
+                closed: *closed,
+            },
+            Self::InvalidStateTransition { from, to, .. } => {
+                Notification::InvalidStateTransition { listener, from, to }
+            }
+            Self::MessageDropped { size, reason, .. } => Notification::MessageDropped {
+                listener,
+                size: *size,
+                reason: *reason,
+            },
+            Self::StdinDropped { size, reason, .. } => Notification::StdinDropped {
+                listener,
+                size: *size,
+                reason: *reason,
+            },
+            Self::SlowHandler {
+                msg_type,
+                elapsed,
+                threshold,
+                ..
+            } => Notification::SlowHandler {
+                listener,
+                msg_type,
+                elapsed: *elapsed,
+                threshold: *threshold,
+            },
+            Self::SendBufferHighWatermark {
+                queued, watermark, ..
+            } => Notification::SendBufferHighWatermark {
+                listener,
+                queued: *queued,
+                watermark: *watermark,
+            },
+            Self::SendBufferLowWatermark {
+                queued, watermark, ..
+            } => Notification::SendBufferLowWatermark {
+                listener,
+                queued: *queued,
+                watermark: *watermark,
+            },
+            Self::DeprecatedModuleNegotiated {
+                module,
+                deprecation,
+                ..
+            } => Notification::DeprecatedModuleNegotiated {
+                listener,
+                module,
+                deprecation: *deprecation,
+            },
+            Self::ConnectionRefused { reason, .. } => Notification::ConnectionRefused {
+                listener,
+                reason: *reason,
+            },
+        };
+        n.fmt(f)
+    }
+}
+
+///The kinds of [notifications](enum.Notification.html), i.e. the variants of Notification without
+///their fields. See there for what each kind means.
+///
+///New versions of this library can add new variants to this enum at any time, just like for
+///Notification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NotificationKind {
+    ConnectionOpened,
+    ConnectionIOError,
+    ConnectionClosed,
+    IncomingParseError,
+    IncomingBytesDiscarded,
+    SendBufferLimitExceeded,
+    WriteTimeout,
+    InvalidStateTransition,
+    MessageDropped,
+    SlowHandler,
+    SendBufferHighWatermark,
+    SendBufferLowWatermark,
+    DeprecatedModuleNegotiated,
+    ConnectionRefused,
+    StdinDropped,
+}
+
+///A set of [notification kinds](enum.NotificationKind.html). This is returned by
+///[`Dispatch::notification_filter()`](trait.Dispatch.html#method.notification_filter) to choose
+///which notifications are passed on to the Application.
+///
+///Some notifications can be very frequent (e.g. `IncomingBytesDiscarded` for a client that sends
+///garbage, or the send buffer watermarks for a pager that keeps getting paused), so applications
+///that are not interested in them can turn them off instead of having to ignore them in
+///[`Application::notify()`](trait.Application.html#tymethod.notify).
+///
+///```
+///# use vt6::server::{NotificationFilter, NotificationKind};
+///let filter = NotificationFilter::ALL
+///    .without(NotificationKind::SendBufferHighWatermark)
+///    .without(NotificationKind::SendBufferLowWatermark);
+///assert!(filter.contains(NotificationKind::ConnectionOpened));
+///assert!(!filter.contains(NotificationKind::SendBufferLowWatermark));
+///
+///let filter = NotificationFilter::NONE.with(NotificationKind::WriteTimeout);
+///assert!(filter.contains(NotificationKind::WriteTimeout));
+///assert!(!filter.contains(NotificationKind::ConnectionOpened));
+///```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NotificationFilter(u32);
+
+impl NotificationFilter {
+    ///Contains all kinds of notifications, including those that will be added in later versions
+    ///of this library. This is the default.
+    pub const ALL: Self = Self(!0);
+    ///Does not contain any kind of notification.
+    pub const NONE: Self = Self(0);
+
+    ///Returns a copy of this filter that also contains the given kind.
+    pub const fn with(self, kind: NotificationKind) -> Self {
+        Self(self.0 | Self::bit(kind))
+    }
+
+    ///Returns a copy of this filter that does not contain the given kind.
+    pub const fn without(self, kind: NotificationKind) -> Self {
+        Self(self.0 & !Self::bit(kind))
+    }
+
+    ///Returns whether this filter contains the given kind.
+    pub fn contains(&self, kind: NotificationKind) -> bool {
+        self.0 & Self::bit(kind) != 0
+    }
+
+    ///Returns whether notifications like the given one pass this filter.
+    pub fn accepts(&self, n: &Notification<'_>) -> bool {
+        self.contains(n.kind())
+    }
+
+    const fn bit(kind: NotificationKind) -> u32 {
+        1 << (kind as u32)
+    }
+}
+
+impl Default for NotificationFilter {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+///Forwards notifications into a channel, so that they can be consumed asynchronously.
+///
+///[`Application::notify()`](trait.Application.html#tymethod.notify) is called synchronously
+///on whichever thread the Dispatch is running on. Applications that want to process notifications
+///elsewhere (e.g. in the event loop of a GUI) can keep a NotificationSender in their Application
+///and forward each notification into it. Notifications are converted into
+///[OwnedNotification](enum.OwnedNotification.html) for this purpose. When the receiving side of
+///the channel has been dropped, notifications are silently discarded.
+///
+///```no_run
+///# use vt6::common::core::ClientID;
+///# use vt6::server::testing::{MockMessageConnector, MockStdoutConnector};
+///# use vt6::server::*;
+///# #[derive(Clone)]
+///# struct MyApplication {
+///#     notification_sender: NotificationSender,
+///# }
+///# impl MyApplication {
+///#     fn new(notification_sender: NotificationSender) -> Self {
+///#         Self { notification_sender }
+///#     }
+///# }
+///let (sender, receiver) = vt6::server::NotificationSender::channel();
+///let app = MyApplication::new(sender);
+///std::thread::spawn(move || {
+///    for n in receiver {
+///        println!("{}", n);
+///    }
+///});
+///
+///impl vt6::server::Application for MyApplication {
+///    fn notify(&self, n: &vt6::server::Notification) {
+///        self.notification_sender.send(n);
+///    }
+///    //... other methods and associated types elided ...
+///#     type MessageConnector = MockMessageConnector;
+///#     type StdoutConnector = MockStdoutConnector;
+///#     type MessageHandler = RejectHandler;
+///#     type HandshakeHandler = RejectHandler;
+///#     fn register_client(&self, _: ClientIdentity) -> ClientCredentials { unimplemented!() }
+///#     fn unregister_clients(&self, _: ClientSelector) {}
+///#     fn has_clients(&self, _: ClientSelector) -> bool { false }
+///#     fn authorize_client(
+///#         &self,
+///#         _: &str,
+///#         _: Option<&PeerCredentials>,
+///#     ) -> Option<ClientIdentity> {
+///#         None
+///#     }
+///#     fn find_client(&self, _: ClientID<'_>) -> Option<ClientIdentity> { None }
+///#     fn authorize_stdin(&self, _: &str) -> Option<ScreenIdentity> { None }
+///#     fn authorize_stdout(&self, _: &str) -> Option<ScreenIdentity> { None }
+///}
+///```
+#[derive(Clone, Debug)]
+pub struct NotificationSender(NotificationSenderImpl);
+
+#[derive(Clone, Debug)]
+enum NotificationSenderImpl {
+    Std(std::sync::mpsc::Sender<OwnedNotification>),
+    #[cfg(feature = "use_tokio")]
+    Tokio(tokio::sync::mpsc::UnboundedSender<OwnedNotification>),
+}
+
+impl NotificationSender {
+    ///Creates a NotificationSender that forwards into a channel from `std::sync::mpsc`.
+    pub fn channel() -> (Self, std::sync::mpsc::Receiver<OwnedNotification>) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        (Self(NotificationSenderImpl::Std(tx)), rx)
+    }
+
+    ///Creates a NotificationSender that forwards into an unbounded channel from
+    ///`tokio::sync::mpsc`. This is only available with the `use_tokio` feature.
+    #[cfg(feature = "use_tokio")]
+    pub fn tokio_channel() -> (
+        Self,
+        tokio::sync::mpsc::UnboundedReceiver<OwnedNotification>,
+    ) {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        (Self(NotificationSenderImpl::Tokio(tx)), rx)
+    }
+
+    ///Sends the given notification into the channel.
+    pub fn send(&self, n: &Notification<'_>) {
+        let n = OwnedNotification::from(n);
+        //errors only occur when the receiver has been dropped, so there is no one left to care
+        match self.0 {
+            NotificationSenderImpl::Std(ref tx) => {
+                let _ = tx.send(n);
+            }
+            #[cfg(feature = "use_tokio")]
+            NotificationSenderImpl::Tokio(ref tx) => {
+                let _ = tx.send(n);
+            }
+        }
+    }
+}
+
+///The reason why a message or standard input was dropped. This is reported in
+///[`Notification::MessageDropped`](enum.Notification.html#variant.MessageDropped) and
+///[`Notification::StdinDropped`](enum.Notification.html#variant.StdinDropped).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropReason {
+    ///The message could not be encoded, usually because it exceeds the maximum message length of
+    ///1024 bytes. [\[vt6/foundation, sect. 3.1.2\]](https://vt6.io/std/foundation/#section-3-1-2)
+    MessageTooLong,
+    ///The connection was in a state that cannot receive the dropped data. Contains the
+    ///[type name](enum.ConnectionState.html#method.type_name) of that state.
+    InvalidState(&'static str),
+}
+
+impl std::fmt::Display for DropReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::MessageTooLong => write!(f, "message too long"),
+            Self::InvalidState(state) => write!(f, "connection is in state {}", state),
+        }
+    }
+}
+
+///The reason why a client connection was refused. This is reported in
+///[`Notification::ConnectionRefused`](enum.Notification.html#variant.ConnectionRefused).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefusalReason {
+    ///The Dispatch already had as many connections as it is configured to allow at once.
+    TooManyConnections { limit: usize },
+    ///The Application refused the connection in
+    ///[`Application::accept_connection()`](trait.Application.html#method.accept_connection).
+    Application,
+}
+
+impl std::fmt::Display for RefusalReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::TooManyConnections { limit } => {
+                write!(f, "too many connections, limit is {}", limit)
+            }
+            Self::Application => write!(f, "refused by application"),
+        }
+    }
+}
+
+///The maximum number of bytes retained in `DiscardedBytes::sample`.
+pub const DISCARDED_BYTES_SAMPLE_LEN: usize = 64;
+
+///A summary of input that was discarded from a connection's receive buffer. This is reported in
+///[`Notification::IncomingBytesDiscarded`](enum.Notification.html#variant.IncomingBytesDiscarded).
+///
+///Offsets count the bytes received on the connection, starting at 0 for the first byte received.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiscardedBytes {
+    ///The number of bytes that were discarded since the previous notification.
+    pub total_bytes: u64,
+    ///The number of separate occasions on which input was discarded since the previous
+    ///notification.
+    pub count: usize,
+    ///The offset of the first discarded byte.
+    pub first_offset: u64,
+    ///The offset of the last discarded byte.
+    pub last_offset: u64,
+    ///The first few bytes that were discarded (at most
+    ///[DISCARDED_BYTES_SAMPLE_LEN](constant.DISCARDED_BYTES_SAMPLE_LEN.html)).
+    pub sample: Vec<u8>,
+}
+
+impl DiscardedBytes {
+    pub(crate) fn record(&mut self, offset: u64, buf: &[u8]) {
+        if buf.is_empty() {
+            return;
+        }
+        if self.count == 0 {
+            self.first_offset = offset;
+        }
+        self.total_bytes += buf.len() as u64;
+        self.count += 1;
+        self.last_offset = offset + buf.len() as u64 - 1;
+        let sample_len = (DISCARDED_BYTES_SAMPLE_LEN - self.sample.len()).min(buf.len());
+        self.sample.extend_from_slice(&buf[0..sample_len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discarded_bytes_aggregation() {
+        let mut d = DiscardedBytes::default();
+        d.record(10, b"foo");
+        d.record(20, b"");
+        d.record(42, &[b'x'; 100]);
+        assert_eq!(d.total_bytes, 103);
+        assert_eq!(d.count, 2);
+        assert_eq!(d.first_offset, 10);
+        assert_eq!(d.last_offset, 141);
+        assert_eq!(d.sample.len(), DISCARDED_BYTES_SAMPLE_LEN);
+        assert_eq!(&d.sample[0..4], b"foox");
+
+        let n = Notification::IncomingBytesDiscarded {
+            listener: None,
+            discarded: &d,
+        };
+        assert!(!n.is_error());
+        assert!(n
+            .to_string()
+            .starts_with("discarded 103 bytes of invalid input at offsets 10..=141: \"fooxxx"));
+
+        let n = Notification::IncomingBytesDiscarded {
+            listener: Some("screen1"),
+            discarded: &d,
+        };
+        assert_eq!(n.listener(), Some("screen1"));
+        assert!(n.to_string().starts_with("[screen1] discarded 103 bytes"));
+    }
+
+    #[test]
+    fn test_notification_sender() {
+        let (sender, receiver) = NotificationSender::channel();
+        sender.send(&Notification::ConnectionOpened {
+            listener: Some("main"),
+        });
+        sender.send(&Notification::ConnectionIOError {
+            listener: None,
+            error: "broken pipe".into(),
+        });
+
+        let n = receiver.try_recv().unwrap();
+        assert_eq!(
+            n,
+            OwnedNotification::ConnectionOpened {
+                listener: Some("main".into())
+            }
+        );
+        assert_eq!(n.to_string(), "[main] client connection opened");
+        let n = receiver.try_recv().unwrap();
+        assert!(n.is_error());
+        assert_eq!(
+            n.to_string(),
+            "client connection encountered IO error: broken pipe"
+        );
+        assert!(receiver.try_recv().is_err());
+
+        //a dropped receiver is not an error
+        std::mem::drop(receiver);
+        sender.send(&Notification::ConnectionClosed {
+            listener: None,
+            reason: TeardownReason::ClientDisconnected,
+        });
+    }
+
+    #[test]
+    fn test_notification_filter() {
+        use crate::server::testing::{Conversation, MockApplication, MockDispatch};
+        let app: MockApplication = MockApplication::new();
+        let dispatch = MockDispatch::new(app.clone());
+        let mut conv = Conversation::with_dispatch(&dispatch);
+        conv.send(b"garbage");
+        assert_eq!(app.notifications().len(), 2);
+
+        dispatch.set_notification_filter(
+            NotificationFilter::ALL.without(NotificationKind::IncomingBytesDiscarded),
+        );
+        let mut conv = Conversation::with_dispatch(&dispatch);
+        conv.send(b"garbage");
+        let notifications = app.notifications();
+        assert_eq!(notifications.len(), 3, "{:?}", notifications);
+        assert!(notifications[2].starts_with("client sent invalid message"));
+        //the connection keeps track regardless of which notifications are delivered
+        assert_eq!(conv.connection().stats().parse_errors, 1);
+
+        dispatch.set_notification_filter(NotificationFilter::NONE);
+        let mut conv = Conversation::with_dispatch(&dispatch);
+        conv.send(b"garbage");
+        assert_eq!(app.notifications().len(), 3);
+    }
 }
@@ -0,0 +1,215 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg;
+use crate::server;
+
+///The decision of an [OutgoingFilter](trait.OutgoingFilter.html) about an outgoing message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OutgoingAction {
+    ///Send the message as it is.
+    Pass,
+    ///Do not send the message at all.
+    Drop,
+    ///Send the given message instead. The buffer must contain exactly one encoded message.
+    Replace(Vec<u8>),
+}
+
+///A filter for messages that the server sends to its clients.
+///
+///Whereas [handlers](trait.Handler.html) only see the messages that clients send, filters see
+///every message that goes through
+///[`Connection::enqueue_message()`](struct.Connection.html#method.enqueue_message) (including
+///replies, broadcasts and published properties) right before it is written into the send buffer.
+///This allows servers to redact secrets, enforce policies on what certain clients may learn, or
+///rewrite messages for clients that need special treatment.
+///
+///Filtering is optional. Filters are chained in the same way as handlers, and an Application that
+///wants to filter messages runs its chain in
+///[`Application::filter_outgoing()`](trait.Application.html#method.filter_outgoing). The last
+///filter in a chain is usually [PassFilter](struct.PassFilter.html):
+///
+///```no_run
+///# use vt6::common::core::{msg, ClientID};
+///# use vt6::server::testing::{MockMessageConnector, MockStdoutConnector};
+///# use vt6::server::*;
+///# type MyRedactingFilter<Next> = LegacyNamesFilter<Next>;
+///# type MyPolicyFilter<Next> = LegacyNamesFilter<Next>;
+///# #[derive(Clone)]
+///# struct MyApplication;
+///impl Application for MyApplication {
+///    fn has_outgoing_filter(&self) -> bool {
+///        true
+///    }
+///
+///    fn filter_outgoing<D: Dispatch<Self>>(
+///        &self,
+///        msg: &msg::Message,
+///        conn: &Connection<Self, D>,
+///    ) -> OutgoingAction {
+///        MyRedactingFilter::<MyPolicyFilter<PassFilter>>::default().filter(msg, conn)
+///    }
+///
+///    //... other fields elided ...
+///#     type MessageConnector = MockMessageConnector;
+///#     type StdoutConnector = MockStdoutConnector;
+///#     type MessageHandler = RejectHandler;
+///#     type HandshakeHandler = RejectHandler;
+///#     fn notify(&self, _: &Notification) {}
+///#     fn register_client(&self, _: ClientIdentity) -> ClientCredentials { unimplemented!() }
+///#     fn unregister_clients(&self, _: ClientSelector) {}
+///#     fn has_clients(&self, _: ClientSelector) -> bool { false }
+///#     fn authorize_client(
+///#         &self,
+///#         _: &str,
+///#         _: Option<&PeerCredentials>,
+///#     ) -> Option<ClientIdentity> {
+///#         None
+///#     }
+///#     fn find_client(&self, _: ClientID<'_>) -> Option<ClientIdentity> { None }
+///#     fn authorize_stdin(&self, _: &str) -> Option<ScreenIdentity> { None }
+///#     fn authorize_stdout(&self, _: &str) -> Option<ScreenIdentity> { None }
+///}
+///```
+///
+///Like handlers, filters are stateless, and a new instance is created for every message.
+pub trait OutgoingFilter<A: server::Application>: Default {
+    ///Decides what to do with a message that is about to be sent on the given connection. The
+    ///message is seen in its original form, i.e. before it is wrapped in a frame if the
+    ///connection is in framed mode.
+    ///
+    ///Filters that let a message pass shall return whatever the next filter in the chain returns.
+    ///Filters that replace a message should hand the replacement to the rest of the chain with
+    ///[forward_replacement()](fn.forward_replacement.html).
+    fn filter<D: server::Dispatch<A>>(
+        &self,
+        msg: &msg::Message,
+        conn: &server::Connection<A, D>,
+    ) -> OutgoingAction;
+}
+
+///A helper for [OutgoingFilters](trait.OutgoingFilter.html) that replace a message: Passes the
+///replacement through `next`, and returns the combined decision. If `next` lets the replacement
+///pass, `OutgoingAction::Replace(replacement)` is returned. If the replacement is not a valid
+///message, it is dropped.
+pub fn forward_replacement<A, D, F>(
+    next: &F,
+    replacement: Vec<u8>,
+    conn: &server::Connection<A, D>,
+) -> OutgoingAction
+where
+    A: server::Application,
+    D: server::Dispatch<A>,
+    F: OutgoingFilter<A>,
+{
+    let action = match msg::Message::parse(&replacement) {
+        Ok((msg, len)) if len == replacement.len() => next.filter(&msg, conn),
+        _ => return OutgoingAction::Drop,
+    };
+    match action {
+        OutgoingAction::Pass => OutgoingAction::Replace(replacement),
+        action => action,
+    }
+}
+
+///An [OutgoingFilter](trait.OutgoingFilter.html) that lets all messages pass.
+///
+///This filter is usually the last in every filter chain.
+#[derive(Default)]
+pub struct PassFilter;
+
+impl<A: server::Application> OutgoingFilter<A> for PassFilter {
+    fn filter<D: server::Dispatch<A>>(
+        &self,
+        _msg: &msg::Message,
+        _conn: &server::Connection<A, D>,
+    ) -> OutgoingAction {
+        OutgoingAction::Pass
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::msg::EncodeMessage;
+    use crate::common::core::{ClientID, ScopedIdentifier};
+    use crate::server::testing::{Conversation, MockApplication, MockHandlers};
+
+    //Drops `example1.secret`, and publishes `example1.title` as "redacted".
+    #[derive(Default)]
+    struct RedactingFilter<Next>(Next);
+
+    impl<A: server::Application, Next: OutgoingFilter<A>> OutgoingFilter<A> for RedactingFilter<Next> {
+        fn filter<D: server::Dispatch<A>>(
+            &self,
+            msg: &msg::Message,
+            conn: &server::Connection<A, D>,
+        ) -> OutgoingAction {
+            use crate::msg::core::Pub;
+            use msg::DecodeMessage;
+            match Pub::decode_message(msg) {
+                Some(p) if p.name.as_str() == "example1.secret" => OutgoingAction::Drop,
+                Some(p) if p.name.as_str() == "example1.title" && p.value != b"redacted" => {
+                    let replacement = Pub {
+                        name: p.name,
+                        value: b"redacted",
+                    };
+                    let mut buf = vec![0u8; replacement.encoded_size()];
+                    replacement.encode(&mut buf).unwrap();
+                    forward_replacement(&self.0, buf, conn)
+                }
+                _ => self.0.filter(msg, conn),
+            }
+        }
+    }
+
+    struct RedactingHandlers;
+
+    impl MockHandlers for RedactingHandlers {
+        type MessageHandler = server::core::MessageHandler<server::RejectHandler>;
+
+        fn has_outgoing_filter() -> bool {
+            true
+        }
+
+        fn filter_outgoing<D: server::Dispatch<MockApplication<Self>>>(
+            msg: &msg::Message,
+            conn: &server::Connection<MockApplication<Self>, D>,
+        ) -> OutgoingAction {
+            RedactingFilter::<PassFilter>::default().filter(msg, conn)
+        }
+    }
+
+    #[test]
+    fn test_outgoing_filter() {
+        let app: MockApplication<RedactingHandlers> = MockApplication::new();
+        let creds = server::Application::register_client(
+            &app,
+            server::ClientIdentity::new(&ClientID::parse("a").unwrap()),
+        );
+        let mut conv = Conversation::new(app);
+        conv.send_message(&crate::msg::posix::ClientHello {
+            secret: creds.secret(),
+        })
+        .expect(r#"(posix1.server-hello a "" "" "")"#);
+        for name in &["example1.secret", "example1.title", "example1.width"] {
+            conv.connection_mut()
+                .subscribe(&ScopedIdentifier::parse(name).unwrap());
+        }
+
+        let dispatch = conv.dispatch();
+        server::core::publish_property(&dispatch, "example1.secret", b"hunter2", |_| true);
+        server::core::publish_property(&dispatch, "example1.title", b"my secret", |_| true);
+        server::core::publish_property(&dispatch, "example1.width", b"80", |_| true);
+        assert_eq!(
+            conv.replies(),
+            vec![
+                "(core1.pub example1.title redacted)",
+                "(core1.pub example1.width 80)",
+            ]
+        );
+    }
+}
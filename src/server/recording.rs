@@ -0,0 +1,240 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::Duration;
+
+const HEADER: &[u8] = b"vt6-session-log 1\n";
+
+///The direction in which data was sent on a recorded connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    ///Data sent by the client and received by the server.
+    Inbound,
+    ///A message sent by the server to the client.
+    Outbound,
+}
+
+impl Direction {
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::Inbound => b'i',
+            Self::Outbound => b'o',
+        }
+    }
+
+    fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            b'i' => Some(Self::Inbound),
+            b'o' => Some(Self::Outbound),
+            _ => None,
+        }
+    }
+}
+
+///An entry in a [SessionLog](struct.SessionLog.html).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    pub direction: Direction,
+    ///The time since the recording was started.
+    pub at: Duration,
+    ///The data in wire format. For `Inbound` entries, this is the data that was received in one
+    ///read, so it may contain several messages or only part of one. Each `Outbound` entry
+    ///contains exactly one message.
+    pub data: Vec<u8>,
+}
+
+///A recording of the data that was exchanged on a single connection.
+///
+///Recordings are made with
+///[`Connection::start_recording()`](struct.Connection.html#method.start_recording), usually by a
+///handler's `on_connect()` method, and collected with
+///[`Connection::take_recording()`](struct.Connection.html#method.take_recording), usually in
+///`on_disconnect()`. A session log can be stored with [encode()](#method.encode), and replayed
+///against a handler chain with
+///[`Conversation::replay()`](testing/struct.Conversation.html#method.replay) to turn a real-world
+///session into a regression test.
+///
+///The encoding is a header line, followed by one record per entry. Each record consists of the
+///direction (`i` or `o`), the timestamp in microseconds, and the data as a netstring, e.g.
+///`i1520:12:{1|5:hello,}` for an inbound entry with 12 bytes after 1.52 milliseconds.
+///
+///```
+///# use std::time::Duration;
+///# use vt6::server::{Direction, SessionLog};
+///let mut log = SessionLog::new();
+///log.push(Direction::Inbound, Duration::from_micros(1520), b"{1|5:hello,}");
+///let encoded = log.encode();
+///assert_eq!(encoded, b"vt6-session-log 1\ni1520:12:{1|5:hello,},");
+///assert_eq!(SessionLog::decode(&encoded), Ok(log));
+///```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SessionLog {
+    entries: Vec<LogEntry>,
+}
+
+impl SessionLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> &[LogEntry] {
+        &self.entries
+    }
+
+    pub fn push(&mut self, direction: Direction, at: Duration, data: &[u8]) {
+        self.entries.push(LogEntry {
+            direction,
+            at,
+            data: data.into(),
+        });
+    }
+
+    ///Serializes this log into the format described above.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = HEADER.to_vec();
+        for entry in &self.entries {
+            buf.push(entry.direction.to_byte());
+            buf.extend_from_slice(
+                format!("{}:{}:", entry.at.as_micros(), entry.data.len()).as_bytes(),
+            );
+            buf.extend_from_slice(&entry.data);
+            buf.push(b',');
+        }
+        buf
+    }
+
+    ///Parses a log that was serialized with [encode()](#method.encode).
+    pub fn decode(buf: &[u8]) -> Result<Self, InvalidSessionLog> {
+        if !buf.starts_with(HEADER) {
+            return Err(InvalidSessionLog { offset: 0 });
+        }
+        let mut log = Self::new();
+        let mut offset = HEADER.len();
+        while offset < buf.len() {
+            let err = InvalidSessionLog { offset };
+            let direction = Direction::from_byte(buf[offset]).ok_or(err)?;
+            let (micros, rest) = parse_number(&buf[offset + 1..]).ok_or(err)?;
+            let (len, rest) = parse_number(rest).ok_or(err)?;
+            let len = usize::try_from(len).map_err(|_| err)?;
+            if rest.len() <= len || rest[len] != b',' {
+                return Err(err);
+            }
+            log.push(direction, Duration::from_micros(micros), &rest[0..len]);
+            offset = buf.len() - rest.len() + len + 1;
+        }
+        Ok(log)
+    }
+}
+
+//Parses a decimal number followed by a colon, and returns the rest of the input after the colon.
+fn parse_number(buf: &[u8]) -> Option<(u64, &[u8])> {
+    let digits = buf.iter().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 || buf.get(digits) != Some(&b':') {
+        return None;
+    }
+    let num = std::str::from_utf8(&buf[0..digits]).ok()?.parse().ok()?;
+    Some((num, &buf[digits + 1..]))
+}
+
+///Error type for [`SessionLog::decode()`](struct.SessionLog.html#method.decode). Contains the
+///offset of the record that could not be parsed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidSessionLog {
+    pub offset: usize,
+}
+
+impl fmt::Display for InvalidSessionLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid session log at offset {}", self.offset)
+    }
+}
+
+impl std::error::Error for InvalidSessionLog {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_log_encoding() {
+        let mut log = SessionLog::new();
+        log.push(Direction::Inbound, Duration::from_micros(0), b"{1|4:w");
+        log.push(Direction::Inbound, Duration::from_micros(7), b"ant,}");
+        log.push(
+            Direction::Outbound,
+            Duration::from_millis(2),
+            b"{1|4:nope,}",
+        );
+        log.push(Direction::Inbound, Duration::from_secs(3), b"");
+        let encoded = log.encode();
+        assert_eq!(
+            String::from_utf8_lossy(&encoded),
+            "vt6-session-log 1\ni0:6:{1|4:w,i7:5:ant,},o2000:11:{1|4:nope,},i3000000:0:,"
+        );
+        assert_eq!(SessionLog::decode(&encoded), Ok(log));
+        assert_eq!(SessionLog::decode(HEADER), Ok(SessionLog::new()));
+
+        let len = HEADER.len();
+        for (input, offset) in &[
+            (&b"vt6-session-log 2\n"[..], 0),
+            (b"vt6-session-log 1\nx0:0:,", len),
+            (b"vt6-session-log 1\ni0:0:,i:0:,", len + 6),
+            (b"vt6-session-log 1\ni0:3:ab,", len),
+            (b"vt6-session-log 1\ni0:2:ab", len),
+        ] {
+            assert_eq!(
+                SessionLog::decode(input),
+                Err(InvalidSessionLog { offset: *offset })
+            );
+        }
+    }
+
+    #[test]
+    fn test_replay() {
+        use crate::common::core::ClientID;
+        use crate::server::testing::{Conversation, MockApplication};
+        use crate::server::ClientIdentity;
+        let app: MockApplication = MockApplication::new();
+        let client_id = ClientID::parse("a").unwrap();
+        let creds =
+            crate::server::Application::register_client(&app, ClientIdentity::new(&client_id));
+        let hello = format!(
+            "{{2|19:posix1.client-hello,{}:{},}}",
+            creds.secret().len(),
+            creds.secret()
+        );
+        let (part1, part2) = hello.as_bytes().split_at(20);
+
+        let mut conv = Conversation::new(app.clone());
+        conv.connection_mut().start_recording();
+        conv.send(part1)
+            .send(&[part2, b"{2|4:want,5:core1,}{2|4:want,4:foo1,}"].concat())
+            .expect(r#"(posix1.server-hello a "" "" "")"#)
+            .expect("(have core1.0)")
+            .expect("(have foo1)")
+            .send(b"{2|4:want,5:core1,}")
+            .expect("(have core1.0)");
+
+        //each read is recorded exactly once, even if it was only partially consumed
+        let log = conv.connection_mut().take_recording().unwrap();
+        let directions: Vec<_> = log.entries().iter().map(|e| e.direction).collect();
+        use Direction::*;
+        assert_eq!(
+            directions,
+            vec![Inbound, Inbound, Outbound, Outbound, Outbound, Inbound, Outbound]
+        );
+        assert_eq!(log.entries()[0].data, part1);
+        assert!(conv.connection().recording().is_none());
+
+        //the recording survives serialization and can be replayed on a fresh connection, once the
+        //client's secret may be used again
+        let log = SessionLog::decode(&log.encode()).unwrap();
+        app.allow_resume(client_id);
+        Conversation::new(app).replay(&log).expect_no_reply();
+    }
+}
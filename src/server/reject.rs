@@ -4,7 +4,7 @@
 * Refer to the file "LICENSE" for details.
 *******************************************************************************/
 
-use crate::common::core::{msg, ModuleIdentifier};
+use crate::common::core::{msg, ModuleIdentifier, ScopedIdentifier};
 use crate::server;
 
 ///A [Handler](trait.Handler.html) that just rejects everything as
@@ -40,4 +40,13 @@ impl<A: server::Application> server::Handler<A> for RejectHandler {
     }
 }
 
-impl<A: server::Application> server::core::MessageHandlerExt<A> for RejectHandler {}
+impl<A: server::Application> server::core::MessageHandlerExt<A> for RejectHandler {
+    fn handle_property<D: server::Dispatch<A>>(
+        &self,
+        _name: &ScopedIdentifier<'_>,
+        _requested_value: Option<&[u8]>,
+        _conn: &mut server::Connection<A, D>,
+    ) -> Option<Vec<u8>> {
+        None
+    }
+}
@@ -0,0 +1,150 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use std::num::NonZeroU16;
+use std::time::{Duration, Instant};
+
+///Options for shaping the data that the server sends on a single connection, so that other users
+///on a shared system cannot infer as much from the timing of that traffic. Attached to a
+///connection with
+///[`Connection::set_egress_shaping()`](struct.Connection.html#method.set_egress_shaping).
+///
+///For example, when a client shows a password prompt, each keypress of the user results in a
+///separate write of stdin, and the intervals between these writes reveal the typing rhythm. With
+///`batch_interval` set, data is held back and written only at fixed points in time, so everything
+///that was entered since the previous tick goes out at once. With `padding_block` set as well,
+///the length of each write does not reveal how many keys were pressed either.
+///
+///The default value has all options disabled, i.e. data is written as soon as it is enqueued.
+///
+///Shaping is implemented in the transmit path of the Dispatch. The
+///[tokio Dispatch](tokio/struct.Dispatch.html) honors these options; other Dispatch
+///implementations may ignore them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EgressShaping {
+    ///If set, data is only written on ticks of this interval, counted from when the connection
+    ///was accepted. A zero interval is treated like `None`.
+    pub batch_interval: Option<Duration>,
+    ///If set, each write is padded so that its length is a multiple of this many bytes.
+    ///
+    ///This only applies to connections in padded stdin mode (see
+    ///[`Connection::is_stdin_padded()`](struct.Connection.html#method.is_stdin_padded)), since
+    ///their clients can tell the padding apart from the data. On all other connections, the
+    ///client would read padding as input or as a malformed message, so this option is ignored.
+    pub padding_block: Option<NonZeroU16>,
+}
+
+impl EgressShaping {
+    ///Returns when data that becomes ready for sending at `now` may be written, for a connection
+    ///that was accepted at `start`. This is the first tick of `batch_interval` that is not
+    ///earlier than `now`.
+    ///
+    ///```
+    ///# use std::time::{Duration, Instant};
+    ///# use vt6::server::EgressShaping;
+    ///let shaping = EgressShaping {
+    ///    batch_interval: Some(Duration::from_millis(50)),
+    ///    ..Default::default()
+    ///};
+    ///let start = Instant::now();
+    ///let now = start + Duration::from_millis(120);
+    ///assert_eq!(shaping.next_tick(start, now), start + Duration::from_millis(150));
+    ///assert_eq!(EgressShaping::default().next_tick(start, now), now);
+    ///```
+    pub fn next_tick(&self, start: Instant, now: Instant) -> Instant {
+        let interval = match self.batch_interval {
+            Some(i) if !i.is_zero() => i.as_nanos(),
+            _ => return now,
+        };
+        let elapsed = now.saturating_duration_since(start).as_nanos();
+        let ticks = elapsed.div_ceil(interval);
+        //the product fits into a Duration since it is less than `elapsed + interval`
+        let offset = ticks * interval;
+        start
+            + Duration::new(
+                (offset / 1_000_000_000) as u64,
+                (offset % 1_000_000_000) as u32,
+            )
+    }
+
+    ///Wraps data for a connection in padded stdin mode into chunks as produced by
+    ///[encode_padded_chunk()](../common/fn.encode_padded_chunk.html), and appends them to `out`.
+    ///If `padding_block` is set, the last chunk carries enough padding to make the total length a
+    ///multiple of it.
+    ///
+    ///```
+    ///# use std::num::NonZeroU16;
+    ///# use vt6::server::EgressShaping;
+    ///let shaping = EgressShaping {
+    ///    padding_block: NonZeroU16::new(16),
+    ///    ..Default::default()
+    ///};
+    ///let mut out = Vec::new();
+    ///shaping.pad_stdin(b"hello", &mut out);
+    ///assert_eq!(out, b"\x00\x05\x00\x07hello\0\0\0\0\0\0\0");
+    ///```
+    pub fn pad_stdin(&self, data: &[u8], out: &mut Vec<u8>) {
+        use crate::common::{encode_padded_chunk, MAX_PADDED_DATA_LEN, PADDED_HEADER_LEN};
+        let start = out.len();
+        let chunk_count = std::cmp::max(1, data.len().div_ceil(MAX_PADDED_DATA_LEN));
+        let unpadded_len = chunk_count * PADDED_HEADER_LEN + data.len();
+        let padding_len = match self.padding_block {
+            Some(block) => {
+                let block = block.get() as usize;
+                (block - unpadded_len % block) % block
+            }
+            None => 0,
+        };
+        out.resize(start + unpadded_len + padding_len, 0);
+
+        let mut offset = start;
+        let mut chunks = data.chunks(MAX_PADDED_DATA_LEN).peekable();
+        //an empty write still needs a chunk to carry the padding
+        if chunks.peek().is_none() {
+            encode_padded_chunk(&[], padding_len, &mut out[offset..]).unwrap();
+        }
+        while let Some(chunk) = chunks.next() {
+            let padding_len = if chunks.peek().is_none() {
+                padding_len
+            } else {
+                0
+            };
+            //cannot fail since we reserved enough space above
+            offset += encode_padded_chunk(chunk, padding_len, &mut out[offset..]).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::PaddedStdinDecoder;
+
+    #[test]
+    fn test_pad_stdin() {
+        let shaping = EgressShaping {
+            padding_block: NonZeroU16::new(64),
+            ..Default::default()
+        };
+        for len in [0, 1, 59, 60, 61, 65535, 65536, 200000] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251 + 1) as u8).collect();
+            let mut out = vec![42];
+            shaping.pad_stdin(&data, &mut out);
+            assert_eq!(out[0], 42);
+            assert_eq!((out.len() - 1) % 64, 0, "len = {}", len);
+
+            let mut decoder = PaddedStdinDecoder::new();
+            let decoded_len = decoder.decode_in_place(&mut out[1..]);
+            assert_eq!(&out[1..decoded_len + 1], &data[..], "len = {}", len);
+            assert!(decoder.is_at_chunk_boundary());
+        }
+
+        //without a block size, there is no padding at all
+        let mut out = Vec::new();
+        EgressShaping::default().pad_stdin(b"abc", &mut out);
+        assert_eq!(out, b"\x00\x03\x00\x00abc");
+    }
+}
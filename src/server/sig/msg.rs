@@ -0,0 +1,320 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg::DecodeMessage;
+use crate::common::core::{msg, ModuleIdentifier, ScopedIdentifier};
+use crate::msg::sig::{Claim, Deliver, Release, Signal};
+use crate::server;
+use crate::server::HandlerError::{InvalidMessage, PermissionDenied};
+use crate::server::{ClientIdentity, ConnectionState, MessageConnector, ScreenIdentity};
+use std::sync::{Arc, Mutex};
+
+///A [MessageHandler](../trait.MessageHandler.html) for the [`vt6/sig`](https://vt6.io/std/sig/)
+///module.
+///
+///This handler processes `sig1.claim` and `sig1.release` messages. Claims are authorized through
+///[`Application::authorize_signal_claim()`](../trait.Application.html#method.authorize_signal_claim)
+///and recorded on the [Connection](../struct.Connection.html). To actually deliver a signal to
+///the client that claimed it, the application calls [deliver_signal()](fn.deliver_signal.html).
+#[derive(Default)]
+pub struct MessageHandler<Next>(Next);
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::MessageHandler<A>
+    for MessageHandler<Next>
+{
+    fn get_supported_module_version(&self, module: &ModuleIdentifier<'_>) -> Option<u16> {
+        match module.as_str() {
+            "sig1" => Some(0),
+            _ => self.0.get_supported_module_version(module),
+        }
+    }
+
+    fn get_module_deprecation(
+        &self,
+        module: &ModuleIdentifier<'_>,
+    ) -> Option<server::ModuleDeprecation> {
+        self.0.get_module_deprecation(module)
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::Handler<A>
+    for MessageHandler<Next>
+{
+    fn handle<D: server::Dispatch<A>>(
+        &self,
+        msg: &msg::Message,
+        conn: &mut server::Connection<A, D>,
+    ) -> Result<(), server::HandlerError> {
+        match msg.parsed_type().as_str() {
+            "sig1.claim" => {
+                let msg = Claim::decode_message(msg).ok_or(InvalidMessage)?;
+                let d = conn.dispatch();
+                let identity = conn.message_connector().unwrap().identity();
+                if !d.application().authorize_signal_claim(identity, msg.signal) {
+                    return Err(PermissionDenied);
+                }
+                conn.claim_signal(msg.signal);
+                conn.enqueue_message(&msg);
+                Ok(())
+            }
+            "sig1.release" => {
+                let msg = Release::decode_message(msg).ok_or(InvalidMessage)?;
+                conn.release_signal(msg.signal);
+                conn.enqueue_message(&msg);
+                Ok(())
+            }
+            _ => self.0.handle(msg, conn),
+        }
+    }
+
+    fn handle_error<D: server::Dispatch<A>>(
+        &self,
+        err: &msg::ParseError,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.handle_error(err, conn);
+    }
+
+    fn on_connect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_connect(conn);
+    }
+
+    fn on_state_change<D: server::Dispatch<A>>(
+        &self,
+        old_state: &server::ConnectionState<A>,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.on_state_change(old_state, conn);
+    }
+
+    fn on_disconnect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_disconnect(conn);
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>>
+    server::core::MessageHandlerExt<A> for MessageHandler<Next>
+{
+    fn handle_property<D: server::Dispatch<A>>(
+        &self,
+        name: &ScopedIdentifier<'_>,
+        requested_value: Option<&[u8]>,
+        conn: &mut server::Connection<A, D>,
+    ) -> Option<Vec<u8>> {
+        self.0.handle_property(name, requested_value, conn)
+    }
+}
+
+///Delivers a signal that was generated for the given screen (e.g. because the user typed Ctrl-C)
+///to a client that has claimed it.
+///
+///The candidates are all clients whose stdin is connected to the given screen and that have
+///claimed this signal with `sig1.claim`.
+///[`Application::route_signal()`](../trait.Application.html#method.route_signal) chooses one of
+///them, and a `sig1.deliver` message is sent to that client. If there are no candidates or if the
+///application does not choose any of them,
+///[`Application::signal_unclaimed()`](../trait.Application.html#method.signal_unclaimed) is called
+///instead, so that the application can fall back to the legacy behavior (e.g. sending the
+///respective control character on stdin).
+///
+///Delivery happens through [`Dispatch::enqueue_broadcast()`](../trait.Dispatch.html#tymethod.enqueue_broadcast),
+///so the Application methods mentioned above may be called after this function has returned.
+pub fn deliver_signal<A, D>(dispatch: &D, screen: &ScreenIdentity, signal: Signal)
+where
+    A: server::Application,
+    D: server::Dispatch<A>,
+{
+    let routing = Arc::new(Mutex::new(SignalRouting {
+        app: dispatch.application().clone(),
+        screen: screen.clone(),
+        signal,
+        candidates: Vec::new(),
+        recipient: None,
+    }));
+
+    //first pass: collect all candidates
+    let routing_ref = routing.clone();
+    dispatch.enqueue_broadcast(Box::new(move |conn| {
+        if !conn.has_claimed_signal(signal) {
+            return;
+        }
+        let mut routing = routing_ref.lock().unwrap();
+        if let ConnectionState::Msgio(ref connector) = conn.state() {
+            let identity = connector.identity();
+            if identity.stdin_screen_id() == Some(routing.screen.screen_id()) {
+                routing.candidates.push(identity.clone());
+            }
+        }
+    }));
+
+    //second pass: deliver the signal to the chosen recipient (broadcasts are executed in order,
+    //so the first pass is complete at this point)
+    dispatch.enqueue_broadcast(Box::new(move |conn| {
+        let mut routing = routing.lock().unwrap();
+        let is_recipient = match (routing.decide(), conn.state()) {
+            (Some(recipient), ConnectionState::Msgio(ref connector)) => {
+                connector.identity().client_id() == recipient.client_id()
+            }
+            _ => false,
+        };
+        if is_recipient {
+            conn.enqueue_message(&Deliver { signal });
+        }
+    }));
+}
+
+struct SignalRouting<A: server::Application> {
+    app: A,
+    screen: ScreenIdentity,
+    signal: Signal,
+    candidates: Vec<ClientIdentity>,
+    //`None` until decide() was called
+    recipient: Option<Option<ClientIdentity>>,
+}
+
+impl<A: server::Application> SignalRouting<A> {
+    fn decide(&mut self) -> Option<&ClientIdentity> {
+        if self.recipient.is_none() {
+            let recipient = if self.candidates.is_empty() {
+                None
+            } else {
+                self.app
+                    .route_signal(&self.screen, self.signal, &self.candidates)
+                    .and_then(|idx| self.candidates.get(idx).cloned())
+            };
+            if recipient.is_none() {
+                self.app.signal_unclaimed(&self.screen, self.signal);
+            }
+            self.recipient = Some(recipient);
+        }
+        self.recipient.as_ref().unwrap().as_ref()
+    }
+}
+
+impl<A: server::Application> Drop for SignalRouting<A> {
+    fn drop(&mut self) {
+        //if there were no connections at all, the second pass never called decide(), but we still
+        //need to report the signal as unclaimed
+        self.decide();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::{ClientID, ScreenID};
+    use crate::server::testing::{Conversation, MockApplication, MockDispatch, MockHandlers};
+
+    struct SigHandlers;
+
+    impl MockHandlers for SigHandlers {
+        type MessageHandler = server::core::MessageHandler<MessageHandler<server::RejectHandler>>;
+    }
+
+    fn connect(
+        app: &MockApplication<SigHandlers>,
+        identity: ClientIdentity,
+    ) -> Conversation<MockApplication<SigHandlers>> {
+        connect_to(&MockDispatch::new(app.clone()), identity)
+    }
+
+    fn connect_to(
+        dispatch: &MockDispatch<MockApplication<SigHandlers>>,
+        identity: ClientIdentity,
+    ) -> Conversation<MockApplication<SigHandlers>> {
+        let app = server::Dispatch::application(dispatch);
+        let creds = server::Application::register_client(app, identity);
+        let mut conv = Conversation::with_dispatch(dispatch);
+        conv.send_message(&crate::msg::posix::ClientHello {
+            secret: creds.secret(),
+        })
+        .replies();
+        conv
+    }
+
+    #[test]
+    fn test_signal_claims() {
+        let app: MockApplication<SigHandlers> = MockApplication::new();
+        app.forbid_message_type("sig1.release");
+        let screen_id = ScreenID::parse("screen1").unwrap();
+        let identity = ClientIdentity::new(&ClientID::parse("a").unwrap()).with_stdin(&screen_id);
+        let mut conv = connect(&app, identity);
+
+        //incomplete messages are held back until the rest arrives
+        conv.send(b"{2|10:sig1.claim,")
+            .expect_no_reply()
+            .send(b"9:interrupt,}")
+            .expect("(sig1.claim interrupt)");
+        assert!(conv.connection().has_claimed_signal(Signal::Interrupt));
+
+        //messages forbidden by Application::authorize_message() are refused before any handler
+        //sees them
+        conv.send(b"{2|12:sig1.release,9:interrupt,}")
+            .expect("(nope sig1.release)");
+        assert!(conv.connection().has_claimed_signal(Signal::Interrupt));
+
+        //claimed signals are delivered through the msgio connection
+        deliver_signal(
+            &conv.dispatch(),
+            &ScreenIdentity::new(&screen_id),
+            Signal::Interrupt,
+        );
+        assert_eq!(conv.replies(), vec!["(sig1.deliver interrupt)"]);
+
+        //by default, only clients that read from a screen's stdin may claim signals
+        let identity = ClientIdentity::new(&ClientID::parse("b").unwrap());
+        let mut conv = connect(&app, identity);
+        conv.send(b"{2|10:sig1.claim,9:interrupt,}")
+            .expect("(nope sig1.claim)");
+        assert!(!conv.connection().has_claimed_signal(Signal::Interrupt));
+    }
+
+    #[test]
+    fn test_signal_delivery() {
+        let app: MockApplication<SigHandlers> = MockApplication::new();
+        let dispatch = MockDispatch::new(app.clone());
+        let screen1 = ScreenID::parse("screen1").unwrap();
+        let screen2 = ScreenID::parse("screen2").unwrap();
+        let client = |id: &str, screen_id: &ScreenID| {
+            ClientIdentity::new(&ClientID::parse(id).unwrap()).with_stdin(screen_id)
+        };
+
+        //"a" and "b" claim the same signal, but read from different screens; "c" reads from the
+        //same screen as "a", but does not claim anything
+        let mut conv_a = connect_to(&dispatch, client("a", &screen1));
+        let mut conv_b = connect_to(&dispatch, client("b", &screen2));
+        let mut conv_c = connect_to(&dispatch, client("c", &screen1));
+        conv_a
+            .send(b"{2|10:sig1.claim,9:interrupt,}")
+            .expect("(sig1.claim interrupt)");
+        conv_b
+            .send(b"{2|10:sig1.claim,9:interrupt,}")
+            .expect("(sig1.claim interrupt)");
+
+        //the signal only reaches the claimant attached to the screen that it was generated for
+        deliver_signal(&dispatch, &ScreenIdentity::new(&screen1), Signal::Interrupt);
+        assert_eq!(conv_a.replies(), vec!["(sig1.deliver interrupt)"]);
+        conv_b.expect_no_reply();
+        conv_c.expect_no_reply();
+        assert_eq!(app.unclaimed_signals(), vec![]);
+
+        //signals for screens without attached claimants are not delivered to anyone, but reported
+        //to the application instead
+        let screen3 = ScreenID::parse("screen3").unwrap();
+        deliver_signal(&dispatch, &ScreenIdentity::new(&screen3), Signal::Interrupt);
+        deliver_signal(&dispatch, &ScreenIdentity::new(&screen1), Signal::Quit);
+        conv_a.expect_no_reply();
+        conv_b.expect_no_reply();
+        conv_c.expect_no_reply();
+        assert_eq!(
+            app.unclaimed_signals(),
+            vec![
+                ("screen3".to_owned(), Signal::Interrupt),
+                ("screen1".to_owned(), Signal::Quit),
+            ]
+        );
+    }
+}
@@ -0,0 +1,85 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use std::time::{Duration, Instant};
+
+///A record of a [Connection](struct.Connection.html) changing into a different state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateTransition {
+    ///When the transition happened.
+    pub at: Instant,
+    ///The name of the new state, as returned by
+    ///[`ConnectionState::type_name()`](enum.ConnectionState.html#method.type_name).
+    pub state: &'static str,
+}
+
+///Statistics for a single [Connection](struct.Connection.html), as returned by
+///[`Connection::stats()`](struct.Connection.html#method.stats).
+///
+///The message and error counts, the handler times and the state transitions are maintained by the Connection itself.
+///The byte counts and the send backlog are maintained by the [Dispatch](trait.Dispatch.html)
+///through [`Connection::stats_mut()`](struct.Connection.html#method.stats_mut), since only the
+///Dispatch knows how much data was actually read from or written into the socket.
+#[derive(Clone, Debug)]
+pub struct ConnectionStats {
+    ///When the connection was opened.
+    pub opened_at: Instant,
+    ///The number of bytes received from the client.
+    pub bytes_received: u64,
+    ///The number of bytes sent to the client.
+    pub bytes_sent: u64,
+    ///The number of messages received from the client that were handled, including those that
+    ///were answered with `nope` or `have`.
+    pub messages_handled: u64,
+    ///The number of times that input from the client could not be parsed.
+    pub parse_errors: u64,
+    ///The total time spent in handlers for messages received from the client.
+    pub handler_time: Duration,
+    ///The number of messages whose handler took at least as long as the
+    ///[`Dispatch::slow_handler_threshold()`](trait.Dispatch.html#method.slow_handler_threshold).
+    pub slow_messages: u64,
+    ///All state transitions of the connection, in chronological order. The first entry is the
+    ///initial `Handshake` state.
+    pub state_transitions: Vec<StateTransition>,
+    ///If the data waiting in the send buffer of the connection has reached the high watermark of
+    ///the Dispatch, and has not fallen to the low watermark since, this is when the high
+    ///watermark was reached. This is always `None` if the Dispatch does not use watermarks (see
+    ///[`Notification::SendBufferHighWatermark`](enum.Notification.html#variant.SendBufferHighWatermark)).
+    pub send_backlog_since: Option<Instant>,
+}
+
+impl ConnectionStats {
+    pub(crate) fn new(initial_state: &'static str) -> Self {
+        let now = Instant::now();
+        Self {
+            opened_at: now,
+            bytes_received: 0,
+            bytes_sent: 0,
+            messages_handled: 0,
+            parse_errors: 0,
+            handler_time: Duration::ZERO,
+            slow_messages: 0,
+            state_transitions: vec![StateTransition {
+                at: now,
+                state: initial_state,
+            }],
+            send_backlog_since: None,
+        }
+    }
+
+    ///Returns how long ago the connection was opened.
+    pub fn uptime(&self) -> Duration {
+        self.opened_at.elapsed()
+    }
+
+    ///Returns when the connection changed into its current state.
+    pub fn state_since(&self) -> Instant {
+        self.state_transitions
+            .last()
+            .map(|t| t.at)
+            .unwrap_or(self.opened_at)
+    }
+}
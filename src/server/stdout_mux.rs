@@ -0,0 +1,190 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::{decode_mux_header, MUX_HEADER_LEN};
+use crate::server;
+use crate::server::StdoutConnector;
+
+///The state of a multiplexed stdout connection, which serves several screens at once.
+///
+///A connection goes into the `StdoutMux` state after a successful
+///[`posix1.stdout-mux-hello`](../msg/posix/struct.StdoutMuxHello.html) handshake. It holds one
+///[StdoutConnector](trait.StdoutConnector.html) per screen, in the order in which the secrets
+///appeared in the handshake, and routes each chunk of input to the connector that the chunk's
+///header refers to (see [decode_mux_header()](../common/fn.decode_mux_header.html) for the
+///format).
+#[derive(Debug)]
+pub struct StdoutMux<C> {
+    connectors: Vec<C>,
+    //index of the connector that receives the current chunk, and how many bytes of the chunk's
+    //payload are still outstanding
+    current: Option<(usize, usize)>,
+}
+
+impl<C: StdoutConnector> StdoutMux<C> {
+    ///Creates a new instance. This is usually only called by the HandshakeHandler.
+    pub fn new(connectors: Vec<C>) -> Self {
+        Self {
+            connectors,
+            current: None,
+        }
+    }
+
+    ///Returns the connectors for all screens served by this connection.
+    pub fn connectors(&self) -> &[C] {
+        &self.connectors
+    }
+
+    ///Returns the connector for the given screen, if this connection serves it.
+    pub fn connector_for(&mut self, screen: &server::ScreenIdentity) -> Option<&mut C> {
+        self.connectors
+            .iter_mut()
+            .find(|c| c.identity() == Some(screen))
+    }
+
+    ///Calls [`StdoutConnector::finish()`](../trait.StdoutConnector.html#method.finish) on all
+    ///connectors.
+    pub fn finish(&mut self) {
+        for connector in &mut self.connectors {
+            connector.finish();
+        }
+    }
+
+    ///Returns whether all connectors are ready to receive more output. When one of them is not,
+    ///the whole connection needs to pause reading, since the next chunk might be for that screen.
+    pub fn is_ready(&self) -> bool {
+        self.connectors.iter().all(|c| c.is_ready())
+    }
+
+    ///Routes as much of the given input as possible to the respective connectors, and returns how
+    ///many bytes were consumed. Payloads are passed on as they arrive, so only an incomplete
+    ///chunk header at the end of the input is not consumed.
+    ///
+    ///Returns an error if a chunk refers to a screen that is not served by this connection, or if
+    ///a connector refuses its output.
+    pub fn receive(&mut self, buf: &[u8]) -> Result<usize, server::StdoutError> {
+        let mut offset = 0;
+        loop {
+            let (idx, remaining) = match self.current {
+                Some(current) => current,
+                None => match decode_mux_header(&buf[offset..]) {
+                    Some((idx, len)) if idx < self.connectors.len() => {
+                        offset += MUX_HEADER_LEN;
+                        (idx, len)
+                    }
+                    Some((idx, _)) => {
+                        return Err(server::StdoutError::Other(format!(
+                            "stdout chunk for unknown screen index {}",
+                            idx
+                        )))
+                    }
+                    None => return Ok(offset),
+                },
+            };
+            let len = remaining.min(buf.len() - offset);
+            if len > 0 {
+                self.connectors[idx].receive(&buf[offset..offset + len])?;
+                offset += len;
+            }
+            if len == remaining {
+                self.current = None;
+            } else {
+                //the rest of this chunk's payload has not arrived yet
+                self.current = Some((idx, remaining - len));
+                return Ok(offset);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::ScreenID;
+    use crate::server::testing::{
+        Conversation, MockApplication, MockDispatch, MockStdoutConnector,
+    };
+
+    fn connector(id: &str) -> MockStdoutConnector {
+        MockStdoutConnector::new(server::ScreenIdentity::new(&ScreenID::parse(id).unwrap()))
+    }
+
+    #[test]
+    fn test_stdout_mux() {
+        let mut mux = StdoutMux::new(vec![connector("screen1"), connector("screen2")]);
+        //an incomplete header is left in the buffer for the next read
+        assert_eq!(mux.receive(b"\x01\x00\x03foo\x00\x00\x03bar\x01"), Ok(12));
+        //payloads are delivered even when incomplete
+        assert_eq!(mux.receive(b"\x01\x00\x04qu"), Ok(5));
+        assert_eq!(mux.receive(b"ux\x00\x00\x00\x00"), Ok(5));
+        assert_eq!(mux.receive(b"\x00\x00\x01!"), Ok(4));
+        assert_eq!(mux.connectors()[0].received(), b"bar!");
+        assert_eq!(mux.connectors()[1].received(), b"fooquux");
+
+        let screen2 = server::ScreenIdentity::new(&ScreenID::parse("screen2").unwrap());
+        assert!(mux.connector_for(&screen2).is_some());
+
+        assert!(mux.receive(b"\x02\x00\x01x").is_err());
+    }
+
+    #[test]
+    fn test_stdout_mux_hello() {
+        use server::Dispatch;
+        let app: MockApplication = MockApplication::new();
+        let screen_ids = ["screen1", "screen2"].map(|id| ScreenID::parse(id).unwrap());
+        let screens = screen_ids.map(|id| server::ScreenIdentity::new(&id));
+        let creds = screen_ids.map(|id| app.add_screen(&id));
+        let dispatch = MockDispatch::new(app);
+        dispatch.set_handshake_tolerance(server::HandshakeTolerance::new(2));
+        let attachments = dispatch.attachments().clone();
+
+        let mut conv1 = Conversation::with_dispatch(&dispatch);
+        conv1.send_message(&crate::msg::posix::StdoutHello {
+            secret: creds[1].stdout_secret(),
+        });
+
+        //repeated or unknown secrets are refused
+        let mut conv2 = Conversation::with_dispatch(&dispatch);
+        let secrets = [creds[0].stdout_secret(), creds[0].stdout_secret()];
+        conv2
+            .send_message(&crate::msg::posix::StdoutMuxHello { secrets: &secrets })
+            .expect("(nope posix1.stdout-mux-hello)");
+        let secrets = [creds[0].stdout_secret(), "unknown"];
+        conv2
+            .send_message(&crate::msg::posix::StdoutMuxHello { secrets: &secrets })
+            .expect("(nope posix1.stdout-mux-hello)");
+        assert_eq!(attachments.stdout_connection(&screens[1]), Some(0));
+
+        //the multiplexer takes over screen2 from the other connection
+        let secrets = [creds[0].stdout_secret(), creds[1].stdout_secret()];
+        conv2.send_message(&crate::msg::posix::StdoutMuxHello { secrets: &secrets });
+        assert_eq!(conv2.connection().state().type_name(), "StdoutMux");
+        conv1.expect_no_reply();
+        assert_eq!(conv1.connection().state().type_name(), "Teardown");
+        assert_eq!(attachments.stdout_connection(&screens[0]), Some(1));
+        assert_eq!(attachments.stdout_connection(&screens[1]), Some(1));
+
+        //output is routed to the screen in each chunk header
+        conv2
+            .send(b"\x01\x00\x05hello\x00\x00\x02")
+            .send(b"hi")
+            .expect_no_reply();
+        match conv2.connection_mut().stdout_connector_for(&screens[0]) {
+            Some(c) => assert_eq!(c.received(), b"hi"),
+            None => panic!("no connector for screen1"),
+        }
+        match conv2.connection_mut().stdout_connector_for(&screens[1]) {
+            Some(c) => assert_eq!(c.received(), b"hello"),
+            None => panic!("no connector for screen2"),
+        }
+
+        //a chunk for an unknown screen closes the connection, which releases all screens
+        conv2.send(b"\x02\x00\x00").expect_no_reply();
+        assert_eq!(conv2.connection().state().type_name(), "Teardown");
+        assert!(!attachments.is_stdout_attached(&screens[0]));
+        assert!(!attachments.is_stdout_attached(&screens[1]));
+    }
+}
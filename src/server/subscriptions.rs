@@ -0,0 +1,245 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::{ModuleIdentifier, ScopedIdentifier};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+///A registry recording which connections have subscribed to which properties.
+///
+///Each [Dispatch](trait.Dispatch.html) maintains one of these, which can be accessed through
+///[`Dispatch::subscriptions()`](trait.Dispatch.html#tymethod.subscriptions). It is updated by the
+///[Connection](struct.Connection.html) when a client subscribes to a property, and when the
+///connection goes into `Teardown` state. When a property value changes, the subscribers can be
+///looked up by the property name without visiting all connections, which is what
+///[PropertyTransaction](core/struct.PropertyTransaction.html) does to send `core1.pub` only to the
+///connections that need it.
+///
+///Besides subscriptions to single properties, there are subscriptions to all properties of a
+///module (see [`Connection::subscribe_module()`](struct.Connection.html#method.subscribe_module)).
+///
+///This type is a cheap handle to shared state, so an Application can keep a clone of it after
+///constructing the Dispatch.
+///
+///```
+///# use vt6::server::Subscriptions;
+///let subscriptions: Subscriptions<u64> = Subscriptions::new();
+///assert!(!subscriptions.has_subscribers("term1.title"));
+///assert_eq!(subscriptions.subscribers("term1.title"), vec![]);
+///```
+pub struct Subscriptions<I>(Arc<Mutex<SubscriptionIndex<I>>>);
+
+struct SubscriptionIndex<I> {
+    //property name -> IDs of connections subscribed to it
+    by_property: HashMap<String, HashSet<I>>,
+    //module name -> IDs of connections subscribed to all properties of that module
+    by_module: HashMap<String, HashSet<I>>,
+    //connection ID -> the keys under which that connection appears in the maps above
+    by_connection: HashMap<I, ConnectionKeys>,
+}
+
+#[derive(Default)]
+struct ConnectionKeys {
+    properties: Vec<String>,
+    modules: Vec<String>,
+}
+
+impl<I> Clone for Subscriptions<I> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<I> Default for Subscriptions<I> {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(SubscriptionIndex {
+            by_property: HashMap::new(),
+            by_module: HashMap::new(),
+            by_connection: HashMap::new(),
+        })))
+    }
+}
+
+impl<I: Clone + Eq + Hash> Subscriptions<I> {
+    ///Creates an empty registry. This is usually only called by the Dispatch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Returns the IDs of all connections that are subscribed to the property with the given name,
+    ///either directly or through a subscription to its module. Each ID is reported only once, in
+    ///no particular order.
+    pub fn subscribers(&self, name: &str) -> Vec<I> {
+        let index = self.0.lock().unwrap();
+        let direct = index.by_property.get(name);
+        let mut result: Vec<I> = direct.into_iter().flatten().cloned().collect();
+        if let Some(ids) = index.by_module.get(module_name(name)) {
+            let is_direct = |id: &I| direct.is_some_and(|d| d.contains(id));
+            result.extend(ids.iter().filter(|id| !is_direct(id)).cloned());
+        }
+        result
+    }
+
+    ///Returns whether any connection is subscribed to the property with the given name.
+    pub fn has_subscribers(&self, name: &str) -> bool {
+        let index = self.0.lock().unwrap();
+        index.by_property.contains_key(name) || index.by_module.contains_key(module_name(name))
+    }
+
+    ///Records that the given connection has subscribed to the given property. This is usually only
+    ///called by the Connection.
+    pub fn subscribe(&self, name: &ScopedIdentifier<'_>, id: I) {
+        let mut index = self.0.lock().unwrap();
+        let index = &mut *index;
+        if insert(&mut index.by_property, name.as_str(), id.clone()) {
+            let keys = index.by_connection.entry(id).or_default();
+            keys.properties.push(name.as_str().into());
+        }
+    }
+
+    ///Records that the given connection has subscribed to all properties of the given module.
+    ///This is usually only called by the Connection.
+    pub fn subscribe_module(&self, module: &ModuleIdentifier<'_>, id: I) {
+        let mut index = self.0.lock().unwrap();
+        let index = &mut *index;
+        if insert(&mut index.by_module, module.as_str(), id.clone()) {
+            let keys = index.by_connection.entry(id).or_default();
+            keys.modules.push(module.as_str().into());
+        }
+    }
+
+    ///Removes all subscriptions of the given connection. This is usually only called by the
+    ///Connection.
+    pub fn unsubscribe_all(&self, id: &I) {
+        let mut index = self.0.lock().unwrap();
+        let index = &mut *index;
+        let keys = match index.by_connection.remove(id) {
+            Some(keys) => keys,
+            None => return,
+        };
+        for key in keys.properties {
+            remove(&mut index.by_property, key, id);
+        }
+        for key in keys.modules {
+            remove(&mut index.by_module, key, id);
+        }
+    }
+}
+
+//Returns false if the ID was already present under that key.
+fn insert<I: Eq + Hash>(map: &mut HashMap<String, HashSet<I>>, key: &str, id: I) -> bool {
+    match map.get_mut(key) {
+        Some(ids) => ids.insert(id),
+        None => {
+            map.insert(key.into(), std::iter::once(id).collect());
+            true
+        }
+    }
+}
+
+fn remove<I: Eq + Hash>(map: &mut HashMap<String, HashSet<I>>, key: String, id: &I) {
+    if let Entry::Occupied(mut entry) = map.entry(key) {
+        entry.get_mut().remove(id);
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+    }
+}
+
+//Property names are scoped identifiers like "term1.title", so the module is everything up to the
+//first dot.
+fn module_name(name: &str) -> &str {
+    name.split('.').next().unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut ids: Vec<u64>) -> Vec<u64> {
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn test_subscriptions() {
+        let subs: Subscriptions<u64> = Subscriptions::new();
+        let title = ScopedIdentifier::parse("term1.title").unwrap();
+        let icon_title = ScopedIdentifier::parse("term1.icon-title").unwrap();
+        let term1 = ModuleIdentifier::parse("term1").unwrap();
+
+        subs.subscribe(&title, 1);
+        subs.subscribe(&title, 2);
+        subs.subscribe(&title, 1);
+        subs.subscribe(&icon_title, 3);
+        assert_eq!(sorted(subs.subscribers("term1.title")), vec![1, 2]);
+        assert_eq!(sorted(subs.subscribers("term1.icon-title")), vec![3]);
+        assert!(!subs.has_subscribers("term1.input-echo"));
+
+        //module-wide subscriptions match all properties in the module, but not other modules
+        subs.subscribe_module(&term1, 2);
+        subs.subscribe_module(&term1, 4);
+        assert_eq!(sorted(subs.subscribers("term1.title")), vec![1, 2, 4]);
+        assert_eq!(sorted(subs.subscribers("term1.input-echo")), vec![2, 4]);
+        assert!(subs.has_subscribers("term1.input-echo"));
+        assert!(!subs.has_subscribers("term2.title"));
+
+        subs.unsubscribe_all(&2);
+        subs.unsubscribe_all(&3);
+        assert_eq!(sorted(subs.subscribers("term1.title")), vec![1, 4]);
+        assert_eq!(sorted(subs.subscribers("term1.icon-title")), vec![4]);
+        subs.unsubscribe_all(&4);
+        assert!(!subs.has_subscribers("term1.icon-title"));
+    }
+
+    #[test]
+    fn test_connection_subscriptions() {
+        use crate::common::core::ClientID;
+        use crate::server::testing::{Conversation, MockApplication, MockDispatch};
+        use crate::server::{ClientIdentity, Dispatch};
+        let app: MockApplication = MockApplication::new();
+        let dispatch = MockDispatch::new(app.clone());
+        let mut convs: Vec<_> = ["a", "b"]
+            .iter()
+            .map(|id| {
+                let identity = ClientIdentity::new(&ClientID::parse(id).unwrap());
+                let creds = crate::server::Application::register_client(&app, identity);
+                let mut conv = Conversation::with_dispatch(&dispatch);
+                conv.send_message(&crate::msg::posix::ClientHello {
+                    secret: creds.secret(),
+                })
+                .replies();
+                conv
+            })
+            .collect();
+        let module = ModuleIdentifier::parse("example1").unwrap();
+        convs[0].connection_mut().subscribe_module(&module);
+        convs[1]
+            .connection_mut()
+            .subscribe(&ScopedIdentifier::parse("example1.width").unwrap());
+        let subscriptions = dispatch.subscriptions().clone();
+        assert_eq!(
+            sorted(subscriptions.subscribers("example1.width")),
+            vec![0, 1]
+        );
+        assert!(convs[0].connection().is_subscribed("example1.height"));
+        assert!(!convs[1].connection().is_subscribed("example1.height"));
+
+        //module subscriptions receive all properties of the module
+        crate::server::core::publish_property(&dispatch, "example1.height", b"24", |_| true);
+        convs[0].expect("(core1.pub example1.height 24)");
+        convs[1].expect_no_reply();
+
+        //subscriptions end with the connection
+        convs[0]
+            .connection_mut()
+            .set_state(crate::server::ConnectionState::Teardown);
+        assert_eq!(subscriptions.subscribers("example1.width"), vec![1]);
+        assert!(!subscriptions.has_subscribers("example1.height"));
+    }
+}
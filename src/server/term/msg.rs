@@ -0,0 +1,293 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::{
+    msg, DecodeArgument, EncodeArgument, ModuleIdentifier, OwnedClientID, ScopedIdentifier,
+};
+use crate::server;
+use crate::server::{LineDisciplineOptions, MessageConnector, ScreenIdentity};
+
+const INPUT_ECHO: &str = "term1.input-echo";
+const INPUT_IMMEDIATE: &str = "term1.input-immediate";
+
+///A [MessageHandler](../trait.MessageHandler.html) for the properties of the
+///[`vt6/term`](https://vt6.io/std/term/) module.
+///
+///Currently, this covers the properties `term1.input-echo` and `term1.input-immediate`, which
+///control the [line discipline](../struct.LineDiscipline.html) of the screen that the client's
+///stdin is connected to. The property values are stored by the application through
+///[`Application::line_discipline_options()`](../trait.Application.html#method.line_discipline_options)
+///and
+///[`Application::set_line_discipline_options()`](../trait.Application.html#method.set_line_discipline_options).
+///If the application does not support line disciplines, or if the client's stdin is not connected
+///to the terminal, the properties are reported as unknown.
+///
+///This handler must be chained after [vt6::server::core::MessageHandler](../core/struct.MessageHandler.html).
+#[derive(Default)]
+pub struct MessageHandler<Next>(Next);
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::MessageHandler<A>
+    for MessageHandler<Next>
+{
+    fn get_supported_module_version(&self, module: &ModuleIdentifier<'_>) -> Option<u16> {
+        match module.as_str() {
+            "term1" => Some(0),
+            _ => self.0.get_supported_module_version(module),
+        }
+    }
+
+    fn get_module_deprecation(
+        &self,
+        module: &ModuleIdentifier<'_>,
+    ) -> Option<server::ModuleDeprecation> {
+        self.0.get_module_deprecation(module)
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::Handler<A>
+    for MessageHandler<Next>
+{
+    fn handle<D: server::Dispatch<A>>(
+        &self,
+        msg: &msg::Message,
+        conn: &mut server::Connection<A, D>,
+    ) -> Result<(), server::HandlerError> {
+        self.0.handle(msg, conn)
+    }
+
+    fn handle_error<D: server::Dispatch<A>>(
+        &self,
+        err: &msg::ParseError,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.handle_error(err, conn);
+    }
+
+    fn on_connect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_connect(conn);
+    }
+
+    fn on_state_change<D: server::Dispatch<A>>(
+        &self,
+        old_state: &server::ConnectionState<A>,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.on_state_change(old_state, conn);
+    }
+
+    fn on_disconnect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_disconnect(conn);
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>>
+    server::core::MessageHandlerExt<A> for MessageHandler<Next>
+{
+    fn handle_property<D: server::Dispatch<A>>(
+        &self,
+        name: &ScopedIdentifier<'_>,
+        requested_value: Option<&[u8]>,
+        conn: &mut server::Connection<A, D>,
+    ) -> Option<Vec<u8>> {
+        if name.as_str() != INPUT_ECHO && name.as_str() != INPUT_IMMEDIATE {
+            return self.0.handle_property(name, requested_value, conn);
+        }
+
+        //the line discipline options belong to the screen connected to the client's stdin
+        let identity = conn.message_connector()?.identity();
+        let screen = ScreenIdentity::new(&identity.stdin_screen_id()?);
+        let origin = OwnedClientID::from(&identity.client_id());
+        let d = conn.dispatch();
+        let app = d.application();
+        let old_options = app.line_discipline_options(&screen)?;
+
+        //apply requested change, if any (invalid values are ignored; we will just report the
+        //current value back to the client)
+        if let Some(value) = requested_value.and_then(bool::decode_argument) {
+            let mut options = old_options;
+            set_property_value(name.as_str(), &mut options, value);
+            if options != old_options {
+                app.set_line_discipline_options(&screen, options);
+            }
+        }
+
+        let options = app.line_discipline_options(&screen)?;
+        if options != old_options {
+            //update the line discipline on the stdin connection for this screen
+            let stdin_screen = screen.clone();
+            d.enqueue_broadcast(Box::new(move |conn| {
+                if conn.state().can_receive_stdin_for_screen(&stdin_screen) {
+                    conn.set_line_discipline(Some(options));
+                }
+            }));
+
+            //persist the new values and tell subscribers about them; the application may have
+            //changed both properties at once, so they are published together (the client that
+            //sent the core1.set gets a core1.pub for the requested property as a direct reply, so
+            //it is left out for that one)
+            let mut tx = server::core::PropertyTransaction::new();
+            for &prop_name in &[INPUT_ECHO, INPUT_IMMEDIATE] {
+                let value = property_value(prop_name, options);
+                if value != property_value(prop_name, old_options) {
+                    let screen = screen.clone();
+                    let value = value.encode_to_vector();
+                    app.persist_property(&screen, prop_name, &value);
+                    let skip_origin = prop_name == name.as_str();
+                    let origin = origin.clone();
+                    tx.publish(prop_name, &value, move |identity| {
+                        identity.stdin_screen_id() == Some(screen.screen_id())
+                            && !(skip_origin && identity.client_id() == origin.as_ref())
+                    });
+                }
+            }
+            tx.commit(&d);
+        }
+
+        Some(property_value(name.as_str(), options).encode_to_vector())
+    }
+}
+
+///Restores the values of the properties handled by [MessageHandler](struct.MessageHandler.html)
+///and [TitleHandler](struct.TitleHandler.html) for the given screen from the values previously
+///stored with [`Application::persist_property()`](../trait.Application.html#method.persist_property).
+///
+///This is called by [vt6::server::restore_properties()](../fn.restore_properties.html), which
+///restores the properties of all modules at once.
+pub fn restore_properties<A: server::Application>(app: &A, screen: &ScreenIdentity) {
+    super::title::restore_titles(app, screen);
+
+    let old_options = match app.line_discipline_options(screen) {
+        Some(options) => options,
+        None => return,
+    };
+    let mut options = old_options;
+    for &prop_name in &[INPUT_ECHO, INPUT_IMMEDIATE] {
+        let value = app.restore_property(screen, prop_name);
+        if let Some(value) = value.as_deref().and_then(bool::decode_argument) {
+            set_property_value(prop_name, &mut options, value);
+        }
+    }
+    if options != old_options {
+        app.set_line_discipline_options(screen, options);
+    }
+}
+
+fn property_value(name: &str, options: LineDisciplineOptions) -> bool {
+    match name {
+        INPUT_ECHO => options.echo,
+        _ => !options.line_buffered,
+    }
+}
+
+fn set_property_value(name: &str, options: &mut LineDisciplineOptions, value: bool) {
+    match name {
+        INPUT_ECHO => options.echo = value,
+        _ => options.line_buffered = !value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::{ClientID, ScreenID};
+    use crate::server::testing::{Conversation, MockApplication, MockDispatch, MockHandlers};
+    use crate::server::ClientIdentity;
+
+    struct TermHandlers;
+
+    impl MockHandlers for TermHandlers {
+        type MessageHandler = server::core::MessageHandler<MessageHandler<server::RejectHandler>>;
+    }
+
+    #[test]
+    fn test_set_input_echo() {
+        let app: MockApplication<TermHandlers> = MockApplication::new();
+        let screen_id = ScreenID::parse("screen1").unwrap();
+        app.add_screen(&screen_id);
+        let dispatch = MockDispatch::new(app.clone());
+        let mut convs = Vec::new();
+        for &id in &["a", "b"] {
+            let identity =
+                ClientIdentity::new(&ClientID::parse(id).unwrap()).with_stdin(&screen_id);
+            let creds = server::Application::register_client(&app, identity);
+            let mut conv = Conversation::with_dispatch(&dispatch);
+            conv.send_message(&crate::msg::posix::ClientHello {
+                secret: creds.secret(),
+            })
+            .expect(&format!(r#"(posix1.server-hello {} screen1 "" "")"#, id))
+            .send(b"{2|9:core1.sub,16:term1.input-echo,}")
+            .expect("(core1.pub term1.input-echo f)");
+            convs.push(conv);
+        }
+
+        //the client that changes the property gets the new value only once, as the reply
+        convs[0]
+            .send(b"{3|9:core1.set,16:term1.input-echo,1:t,}")
+            .expect("(core1.pub term1.input-echo t)")
+            .expect_no_reply();
+        convs[1]
+            .expect("(core1.pub term1.input-echo t)")
+            .expect_no_reply();
+        let screen = ScreenIdentity::new(&screen_id);
+        assert_eq!(
+            app.persisted_property(&screen, INPUT_ECHO),
+            Some(b"t".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_restore_after_restart() {
+        let screen_id = ScreenID::parse("screen1").unwrap();
+        let screen = ScreenIdentity::new(&screen_id);
+        let client = ClientIdentity::new(&ClientID::parse("a").unwrap()).with_stdin(&screen_id);
+
+        //each change is persisted as soon as it is made
+        let app: MockApplication<TermHandlers> = MockApplication::new();
+        app.add_screen(&screen_id);
+        let default_options = server::Application::line_discipline_options(&app, &screen);
+        let creds = server::Application::register_client(&app, client.clone());
+        Conversation::new(app.clone())
+            .send_message(&crate::msg::posix::ClientHello {
+                secret: creds.secret(),
+            })
+            .expect(r#"(posix1.server-hello a screen1 "" "")"#)
+            .send(b"{3|9:core1.set,16:term1.input-echo,1:t,}")
+            .expect("(core1.pub term1.input-echo t)")
+            .send(b"{3|9:core1.set,21:term1.input-immediate,1:f,}")
+            .expect("(core1.pub term1.input-immediate f)");
+        let changed_options = server::Application::line_discipline_options(&app, &screen);
+        assert_ne!(changed_options, default_options);
+
+        //after a restart, the screen starts out with the default values again...
+        let restarted: MockApplication<TermHandlers> = MockApplication::new();
+        restarted.add_screen(&screen_id);
+        for &name in &[INPUT_ECHO, INPUT_IMMEDIATE] {
+            let value = app.persisted_property(&screen, name).unwrap();
+            server::Application::persist_property(&restarted, &screen, name, &value);
+        }
+        assert_eq!(
+            server::Application::line_discipline_options(&restarted, &screen),
+            default_options
+        );
+
+        //...until the application restores the persisted values
+        server::restore_properties(&restarted, &screen);
+        assert_eq!(
+            server::Application::line_discipline_options(&restarted, &screen),
+            changed_options
+        );
+        let creds = server::Application::register_client(&restarted, client);
+        Conversation::new(restarted)
+            .send_message(&crate::msg::posix::ClientHello {
+                secret: creds.secret(),
+            })
+            .expect(r#"(posix1.server-hello a screen1 "" "")"#)
+            .send(b"{2|9:core1.sub,16:term1.input-echo,}")
+            .expect("(core1.pub term1.input-echo t)")
+            .send(b"{2|9:core1.sub,21:term1.input-immediate,}")
+            .expect("(core1.pub term1.input-immediate f)");
+    }
+}
@@ -0,0 +1,218 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::{
+    msg, DecodeArgument, EncodeArgument, ModuleIdentifier, OwnedClientID, ScopedIdentifier,
+};
+use crate::server;
+use crate::server::{MessageConnector, ScreenIdentity};
+
+const TITLE: &str = "term1.title";
+const ICON_TITLE: &str = "term1.icon-title";
+
+///The maximum length of a title in bytes. Longer titles are truncated.
+pub const TITLE_MAX_BYTES: usize = 255;
+
+///The kinds of titles that a screen can have. Used by [TitleHandler](struct.TitleHandler.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TitleKind {
+    ///The title of the window (or tab etc.) that displays the screen. This is exposed as the
+    ///property `term1.title`.
+    Window,
+    ///The title shown when the window that displays the screen is minimized. This is exposed as
+    ///the property `term1.icon-title`.
+    Icon,
+}
+
+impl TitleKind {
+    ///Returns the name of the property that exposes this kind of title.
+    pub fn property_name(self) -> &'static str {
+        match self {
+            TitleKind::Window => TITLE,
+            TitleKind::Icon => ICON_TITLE,
+        }
+    }
+
+    fn from_property_name(name: &str) -> Option<Self> {
+        match name {
+            TITLE => Some(TitleKind::Window),
+            ICON_TITLE => Some(TitleKind::Icon),
+            _ => None,
+        }
+    }
+}
+
+///A [MessageHandler](../trait.MessageHandler.html) for the title properties of the
+///[`vt6/term`](https://vt6.io/std/term/) module.
+///
+///This covers the properties `term1.title` and `term1.icon-title` of the screen that the client's
+///stdout is connected to. The titles are stored by the application through
+///[`Application::screen_title()`](../trait.Application.html#method.screen_title) and
+///[`Application::set_screen_title()`](../trait.Application.html#method.set_screen_title). If the
+///application does not support titles, or if the client's stdout is not connected to the
+///terminal, the properties are reported as unknown.
+///
+///Requested titles must not contain control characters; otherwise the request is ignored.
+///Titles longer than [TITLE_MAX_BYTES](constant.TITLE_MAX_BYTES.html) are truncated.
+///
+///This handler must be chained after [vt6::server::core::MessageHandler](../core/struct.MessageHandler.html).
+#[derive(Default)]
+pub struct TitleHandler<Next>(Next);
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::MessageHandler<A>
+    for TitleHandler<Next>
+{
+    fn get_supported_module_version(&self, module: &ModuleIdentifier<'_>) -> Option<u16> {
+        match module.as_str() {
+            "term1" => Some(0),
+            _ => self.0.get_supported_module_version(module),
+        }
+    }
+
+    fn get_module_deprecation(
+        &self,
+        module: &ModuleIdentifier<'_>,
+    ) -> Option<server::ModuleDeprecation> {
+        self.0.get_module_deprecation(module)
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>> server::Handler<A>
+    for TitleHandler<Next>
+{
+    fn handle<D: server::Dispatch<A>>(
+        &self,
+        msg: &msg::Message,
+        conn: &mut server::Connection<A, D>,
+    ) -> Result<(), server::HandlerError> {
+        self.0.handle(msg, conn)
+    }
+
+    fn handle_error<D: server::Dispatch<A>>(
+        &self,
+        err: &msg::ParseError,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.handle_error(err, conn);
+    }
+
+    fn on_connect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_connect(conn);
+    }
+
+    fn on_state_change<D: server::Dispatch<A>>(
+        &self,
+        old_state: &server::ConnectionState<A>,
+        conn: &mut server::Connection<A, D>,
+    ) {
+        self.0.on_state_change(old_state, conn);
+    }
+
+    fn on_disconnect<D: server::Dispatch<A>>(&self, conn: &mut server::Connection<A, D>) {
+        self.0.on_disconnect(conn);
+    }
+}
+
+impl<A: server::Application, Next: server::core::MessageHandlerExt<A>>
+    server::core::MessageHandlerExt<A> for TitleHandler<Next>
+{
+    fn handle_property<D: server::Dispatch<A>>(
+        &self,
+        name: &ScopedIdentifier<'_>,
+        requested_value: Option<&[u8]>,
+        conn: &mut server::Connection<A, D>,
+    ) -> Option<Vec<u8>> {
+        let kind = match TitleKind::from_property_name(name.as_str()) {
+            Some(kind) => kind,
+            None => return self.0.handle_property(name, requested_value, conn),
+        };
+
+        //titles belong to the screen connected to the client's stdout
+        let identity = conn.message_connector()?.identity();
+        let screen = ScreenIdentity::new(&identity.stdout_screen_id()?);
+        let origin = OwnedClientID::from(&identity.client_id());
+        let d = conn.dispatch();
+        let app = d.application();
+        let old_title = app.screen_title(&screen, kind)?;
+
+        //apply requested change, if any (invalid values are ignored; we will just report the
+        //current value back to the client)
+        if let Some(requested_title) = requested_value.and_then(validate_title) {
+            if requested_title != old_title {
+                app.set_screen_title(&screen, kind, requested_title);
+            }
+        }
+
+        let title = app.screen_title(&screen, kind)?;
+        let value = title.encode_to_vector();
+        if title != old_title {
+            //persist the new value and tell the other subscribers about it (the client that sent
+            //the core1.set gets a core1.pub as a direct reply)
+            app.persist_property(&screen, kind.property_name(), &value);
+            let screen = screen.clone();
+            server::core::publish_property(&d, kind.property_name(), &value, move |identity| {
+                identity.stdout_screen_id() == Some(screen.screen_id())
+                    && identity.client_id() != origin.as_ref()
+            });
+        }
+        Some(value)
+    }
+}
+
+///Restores the titles of the given screen from the values previously stored with
+///[`Application::persist_property()`](../trait.Application.html#method.persist_property). This is
+///called by [restore_properties()](fn.restore_properties.html).
+pub(crate) fn restore_titles<A: server::Application>(app: &A, screen: &ScreenIdentity) {
+    for &kind in &[TitleKind::Window, TitleKind::Icon] {
+        let old_title = match app.screen_title(screen, kind) {
+            Some(title) => title,
+            None => continue,
+        };
+        let value = app.restore_property(screen, kind.property_name());
+        if let Some(title) = value.as_deref().and_then(validate_title) {
+            if title != old_title {
+                app.set_screen_title(screen, kind, title);
+            }
+        }
+    }
+}
+
+///Decodes a requested title. Returns None if the title is not acceptable, or truncates it to
+///TITLE_MAX_BYTES.
+fn validate_title(value: &[u8]) -> Option<&str> {
+    let title = <&str>::decode_argument(value)?;
+    if title.chars().any(char::is_control) {
+        return None;
+    }
+    Some(crate::common::truncate_str_to_bytes(title, TITLE_MAX_BYTES))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_title() {
+        assert_eq!(validate_title(b"hello"), Some("hello"));
+        assert_eq!(validate_title(b""), Some(""));
+        assert_eq!(validate_title(b"hello\x1B]0;world\x07"), None);
+        assert_eq!(validate_title(b"hello\nworld"), None);
+        assert_eq!(validate_title(b"\xFF"), None);
+
+        let long_title = "a".repeat(300);
+        assert_eq!(
+            validate_title(long_title.as_bytes()),
+            Some(&long_title[0..255])
+        );
+        //truncation does not split UTF-8 sequences (each "\u{E4}" is two bytes long, so a cut at
+        //255 bytes would end up in the middle of one)
+        let long_title = "\u{E4}".repeat(200);
+        assert_eq!(
+            validate_title(long_title.as_bytes()),
+            Some(&long_title[0..254])
+        );
+    }
+}
@@ -0,0 +1,1179 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::{msg, ClientID, ScreenID};
+#[cfg(feature = "module_clipboard")]
+use crate::msg::clipboard::Selection;
+#[cfg(feature = "module_sig")]
+use crate::msg::sig::Signal;
+use crate::server;
+use std::collections::{BTreeMap, HashMap};
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+///A scripted conversation between a single client and a server, for testing handler chains.
+///
+///The conversation runs entirely in memory: Input is fed directly into a
+///[Connection](../struct.Connection.html) that uses the given Application and its handlers, and
+///everything that the server sends back is recorded, so that it can be checked in order with the
+///`expect...()` methods. Replies can be checked either in wire format or in the human-readable
+///format produced by the Display impl of [Message](../../common/core/msg/struct.Message.html).
+///
+///Broadcasts enqueued with
+///[`Dispatch::enqueue_broadcast()`](../trait.Dispatch.html#tymethod.enqueue_broadcast) are
+///executed on the conversation's connection after each step, so replies sent through broadcasts
+///(e.g. `core1.pub` for property changes) show up in order as well.
+///
+///```no_run
+///use vt6::server::testing::Conversation;
+///# type MyApplication = vt6::server::testing::MockApplication;
+///
+///Conversation::new(MyApplication::new())
+///    .send(b"{2|19:posix1.client-hello,6:secret,}")
+///    .expect(r#"(posix1.server-hello a screen1 "" "")"#)
+///    .send(b"{2|4:want,5:core1,}{2|4:want,4:foo1,}")
+///    .expect("(have core1.0)")
+///    .expect("(have foo1)")
+///    .expect_no_reply();
+///```
+///
+///All `expect...()` methods panic when the expectation is not met, so they can be used directly
+///inside `#[test]` functions.
+///
+///To test interactions between multiple clients, create several conversations on the same
+///[MockDispatch](struct.MockDispatch.html) with
+///[with_dispatch()](#method.with_dispatch).
+pub struct Conversation<A: server::Application> {
+    conn: server::Connection<A, MockDispatch<A>>,
+    input: Vec<u8>,
+}
+
+impl<A: server::Application> Conversation<A> {
+    ///Starts a new conversation on a new [MockDispatch](struct.MockDispatch.html). The connection
+    ///starts out in the `Handshake` state, just like a freshly accepted client connection, and
+    ///the handlers' `on_connect()` methods have already been called.
+    pub fn new(app: A) -> Self {
+        Self::with_dispatch(&MockDispatch::new(app))
+    }
+
+    ///Like `new()`, but starts the conversation on an existing dispatch. The conversation's
+    ///connection receives all broadcasts that are enqueued on the dispatch from now on.
+    pub fn with_dispatch(dispatch: &MockDispatch<A>) -> Self {
+        let id = dispatch.add_connection();
+        let mut conv = Self {
+            conn: server::Connection::new(dispatch.clone(), id),
+            input: Vec::new(),
+        };
+        conv.conn.handle_connect();
+        conv
+    }
+
+    ///Returns the dispatch. This can be used to enqueue broadcasts (e.g. to send stdin to the
+    ///client); they will be executed at the start of the next step of the conversation.
+    pub fn dispatch(&self) -> MockDispatch<A> {
+        self.conn.dispatch()
+    }
+
+    ///Returns a reference to the connection, e.g. for checking its state.
+    pub fn connection(&self) -> &server::Connection<A, MockDispatch<A>> {
+        &self.conn
+    }
+
+    ///Returns a mutable reference to the connection.
+    pub fn connection_mut(&mut self) -> &mut server::Connection<A, MockDispatch<A>> {
+        &mut self.conn
+    }
+
+    ///Feeds the given input into the connection, as if the client had sent it. The input does not
+    ///need to consist of complete messages: Incomplete messages are held back until the rest is
+    ///sent in a later step, just like a real Dispatch would do.
+    pub fn send(&mut self, input: &[u8]) -> &mut Self {
+        self.run_broadcasts();
+        self.input.extend_from_slice(input);
+        self.conn.stats_mut().bytes_received += input.len() as u64;
+        self.conn.handle_incoming(&mut self.input);
+        self.run_broadcasts();
+        self
+    }
+
+    ///Like `send()`, but takes a message instead of its wire format.
+    pub fn send_message<M: msg::EncodeMessage>(&mut self, msg: &M) -> &mut Self {
+        let mut buf = vec![0u8; msg.encoded_size()];
+        let len = msg.encode(&mut buf).unwrap();
+        self.send(&buf[0..len])
+    }
+
+    ///Checks that the next reply from the server is the given message, in the human-readable
+    ///format, e.g. `(have core1.0)`.
+    pub fn expect(&mut self, expected: &str) -> &mut Self {
+        let reply = self.next_reply();
+        let actual = match msg::Message::parse(&reply) {
+            Ok((msg, _)) => msg.to_string(),
+            Err(_) => String::from_utf8_lossy(&reply).into_owned(),
+        };
+        assert_eq!(actual, expected, "unexpected reply from server");
+        self
+    }
+
+    ///Checks that the next reply from the server is the given message, in wire format, e.g.
+    ///`{2|4:have,7:core1.0,}`.
+    pub fn expect_wire(&mut self, expected: &[u8]) -> &mut Self {
+        let reply = self.next_reply();
+        assert_eq!(
+            String::from_utf8_lossy(&reply),
+            String::from_utf8_lossy(expected),
+            "unexpected reply from server"
+        );
+        self
+    }
+
+    ///Checks that the server has not sent any replies that were not checked yet.
+    pub fn expect_no_reply(&mut self) -> &mut Self {
+        self.run_broadcasts();
+        let output = self.take_output();
+        assert!(
+            output.is_empty(),
+            "expected no reply, but server sent {:?}",
+            String::from_utf8_lossy(&output)
+        );
+        self
+    }
+
+    ///Checks that the server has sent exactly the given standard input to the client since the
+    ///last call to this method. This is only relevant when the connection is in `Stdin` state.
+    pub fn expect_stdin(&mut self, expected: &[u8]) -> &mut Self {
+        self.run_broadcasts();
+        let stdin = self
+            .conn
+            .dispatch()
+            .with_connection(self.conn.id(), |c| std::mem::take(&mut c.stdin));
+        assert_eq!(
+            String::from_utf8_lossy(&stdin),
+            String::from_utf8_lossy(expected),
+            "unexpected stdin from server"
+        );
+        self
+    }
+
+    ///Replays a [session log](../struct.SessionLog.html) that was recorded on a real connection:
+    ///Inbound entries are fed into the connection with `send()`, and outbound entries are checked
+    ///with `expect_wire()`, so this panics as soon as the handlers deviate from the recording.
+    ///
+    ///Timestamps are ignored. The application must accept the secrets that were used in the
+    ///recorded handshake, and replies that were triggered by other connections (e.g. property
+    ///publications) are only reproduced if the test triggers them as well.
+    pub fn replay(&mut self, log: &server::SessionLog) -> &mut Self {
+        for entry in log.entries() {
+            match entry.direction {
+                server::Direction::Inbound => self.send(&entry.data),
+                server::Direction::Outbound => self.expect_wire(&entry.data),
+            };
+        }
+        self
+    }
+
+    ///Returns all replies that were not checked yet in the human-readable format, and marks them
+    ///as checked. This is useful when the order of replies is not deterministic.
+    pub fn replies(&mut self) -> Vec<String> {
+        self.run_broadcasts();
+        let output = self.take_output();
+        let mut result = Vec::new();
+        let mut rest = &output[..];
+        while !rest.is_empty() {
+            match msg::Message::parse(rest) {
+                Ok((msg, len)) => {
+                    result.push(msg.to_string());
+                    rest = &rest[len..];
+                }
+                Err(_) => {
+                    result.push(String::from_utf8_lossy(rest).into_owned());
+                    break;
+                }
+            }
+        }
+        result
+    }
+
+    fn take_output(&self) -> Vec<u8> {
+        self.conn
+            .dispatch()
+            .with_connection(self.conn.id(), |c| std::mem::take(&mut c.output))
+    }
+
+    ///Removes the next message from the recorded output, and returns it in wire format.
+    fn next_reply(&mut self) -> Vec<u8> {
+        self.run_broadcasts();
+        self.conn.dispatch().with_connection(self.conn.id(), |c| {
+            if c.output.is_empty() {
+                panic!("expected another reply, but server did not send anything");
+            }
+            //since all output is produced by enqueue_message(), everything in there is
+            //well-formed
+            let len = match msg::Message::parse(&c.output) {
+                Ok((_, len)) => len,
+                Err(_) => c.output.len(),
+            };
+            c.output.drain(0..len).collect()
+        })
+    }
+
+    fn run_broadcasts(&mut self) {
+        let dispatch = self.conn.dispatch();
+        let id = self.conn.id();
+        loop {
+            let broadcasts = dispatch.with_connection(id, |c| std::mem::take(&mut c.broadcasts));
+            if broadcasts.is_empty() {
+                return;
+            }
+            for broadcast in broadcasts {
+                broadcast(&mut self.conn);
+            }
+        }
+    }
+}
+
+impl<A: server::Application> Drop for Conversation<A> {
+    fn drop(&mut self) {
+        //stop queueing broadcasts for this connection
+        self.conn.dispatch().with_connection(self.conn.id(), |c| {
+            c.is_open = false;
+            c.broadcasts.clear();
+        });
+    }
+}
+
+///The [Dispatch](../trait.Dispatch.html) used by [Conversation](struct.Conversation.html). It
+///does not do any IO, and only manages the connections of the conversations that were started on
+///it. Everything that is sent to a connection is recorded, and can be inspected with
+///[sent_messages()](#method.sent_messages). Since nothing is actually written, the `bytes_sent`
+///in the [ConnectionStats](../struct.ConnectionStats.html) of a connection count everything
+///that was enqueued on it.
+#[derive(Clone)]
+pub struct MockDispatch<A: server::Application>(Arc<InnerDispatch<A>>);
+
+//Each broadcast is shared between all connections that were open when it was enqueued.
+type Broadcast<A> = Arc<dyn Fn(&mut server::Connection<A, MockDispatch<A>>) + Send + Sync>;
+
+struct InnerDispatch<A: server::Application> {
+    app: A,
+    attachments: server::Attachments<u64>,
+    subscriptions: server::Subscriptions<u64>,
+    handshake_tolerance: Mutex<server::HandshakeTolerance>,
+    slow_handler_threshold: Mutex<Option<std::time::Duration>>,
+    notification_filter: Mutex<server::NotificationFilter>,
+    connections: Mutex<BTreeMap<u64, MockConnection<A>>>,
+}
+
+struct MockConnection<A: server::Application> {
+    is_open: bool,
+    //output that was not yet checked by the conversation
+    output: Vec<u8>,
+    stdin: Vec<u8>,
+    //all messages ever sent on this connection, in the human-readable format
+    sent: Vec<String>,
+    broadcasts: Vec<Broadcast<A>>,
+}
+
+impl<A: server::Application> MockDispatch<A> {
+    ///Creates a new dispatch without any connections.
+    pub fn new(app: A) -> Self {
+        Self(Arc::new(InnerDispatch {
+            app,
+            attachments: server::Attachments::new(),
+            subscriptions: server::Subscriptions::new(),
+            handshake_tolerance: Mutex::new(server::HandshakeTolerance::STRICT),
+            slow_handler_threshold: Mutex::new(None),
+            notification_filter: Mutex::new(server::NotificationFilter::ALL),
+            connections: Mutex::new(BTreeMap::new()),
+        }))
+    }
+
+    ///Returns all messages that were sent to the client on the given connection, in the
+    ///human-readable format, e.g. `(have core1.0)`. This includes the messages that were already
+    ///checked by the conversation.
+    ///
+    ///# Panics
+    ///
+    ///Panics if there is no connection with this ID.
+    pub fn sent_messages(&self, conn_id: u64) -> Vec<String> {
+        self.with_connection(conn_id, |c| c.sent.clone())
+    }
+
+    ///Sets the value returned by
+    ///[`Dispatch::handshake_tolerance()`](../trait.Dispatch.html#method.handshake_tolerance). The
+    ///default is `HandshakeTolerance::STRICT`.
+    pub fn set_handshake_tolerance(&self, tolerance: server::HandshakeTolerance) {
+        *self.0.handshake_tolerance.lock().unwrap() = tolerance;
+    }
+
+    ///Sets the value returned by
+    ///[`Dispatch::slow_handler_threshold()`](../trait.Dispatch.html#method.slow_handler_threshold).
+    ///The default is `None`.
+    pub fn set_slow_handler_threshold(&self, threshold: Option<std::time::Duration>) {
+        *self.0.slow_handler_threshold.lock().unwrap() = threshold;
+    }
+
+    ///Sets the value returned by
+    ///[`Dispatch::notification_filter()`](../trait.Dispatch.html#method.notification_filter).
+    ///The default is `NotificationFilter::ALL`.
+    pub fn set_notification_filter(&self, filter: server::NotificationFilter) {
+        *self.0.notification_filter.lock().unwrap() = filter;
+    }
+
+    ///Returns the IDs of all connections that were started on this dispatch, in order.
+    pub fn connection_ids(&self) -> Vec<u64> {
+        self.0.connections.lock().unwrap().keys().copied().collect()
+    }
+
+    fn add_connection(&self) -> u64 {
+        let mut connections = self.0.connections.lock().unwrap();
+        let id = connections.keys().next_back().map_or(0, |id| id + 1);
+        connections.insert(
+            id,
+            MockConnection {
+                is_open: true,
+                output: Vec::new(),
+                stdin: Vec::new(),
+                sent: Vec::new(),
+                broadcasts: Vec::new(),
+            },
+        );
+        id
+    }
+
+    fn with_connection<T, F: FnOnce(&mut MockConnection<A>) -> T>(&self, id: u64, action: F) -> T {
+        let mut connections = self.0.connections.lock().unwrap();
+        let conn = connections
+            .get_mut(&id)
+            .unwrap_or_else(|| panic!("MockDispatch has no connection with ID {}", id));
+        action(conn)
+    }
+
+    //Returns whether `conn` can receive messages, and reports a dropped message if not. This does
+    //not assert, so that tests can cover the drop path in debug builds.
+    fn check_can_receive_messages(&self, conn: &server::Connection<A, Self>, size: usize) -> bool {
+        if conn.state().can_receive_messages() {
+            return true;
+        }
+        server::Dispatch::notify(
+            self,
+            &server::Notification::MessageDropped {
+                listener: conn.listener(),
+                size,
+                reason: server::DropReason::InvalidState(conn.state().type_name()),
+            },
+        );
+        false
+    }
+
+    //Like check_can_receive_messages(), but for stdin.
+    fn check_can_receive_stdin(&self, conn: &server::Connection<A, Self>, size: usize) -> bool {
+        if conn.state().can_receive_stdin() {
+            return true;
+        }
+        server::Dispatch::notify(
+            self,
+            &server::Notification::StdinDropped {
+                listener: conn.listener(),
+                size,
+                reason: server::DropReason::InvalidState(conn.state().type_name()),
+            },
+        );
+        false
+    }
+}
+
+impl<A: server::Application> server::Dispatch<A> for MockDispatch<A> {
+    type ConnectionID = u64;
+
+    fn application(&self) -> &A {
+        &self.0.app
+    }
+
+    fn attachments(&self) -> &server::Attachments<u64> {
+        &self.0.attachments
+    }
+
+    fn subscriptions(&self) -> &server::Subscriptions<u64> {
+        &self.0.subscriptions
+    }
+
+    fn handshake_tolerance(&self) -> server::HandshakeTolerance {
+        *self.0.handshake_tolerance.lock().unwrap()
+    }
+
+    fn slow_handler_threshold(&self) -> Option<std::time::Duration> {
+        *self.0.slow_handler_threshold.lock().unwrap()
+    }
+
+    fn notification_filter(&self) -> server::NotificationFilter {
+        *self.0.notification_filter.lock().unwrap()
+    }
+
+    fn enqueue_broadcast(
+        &self,
+        action: Box<dyn Fn(&mut server::Connection<A, Self>) + Send + Sync>,
+    ) {
+        let action: Broadcast<A> = Arc::from(action);
+        let mut connections = self.0.connections.lock().unwrap();
+        for conn in connections.values_mut().filter(|c| c.is_open) {
+            conn.broadcasts.push(action.clone());
+        }
+    }
+
+    fn enqueue_message<M: msg::EncodeMessage>(
+        &self,
+        conn: &mut server::Connection<A, Self>,
+        msg: &M,
+    ) {
+        //the state is checked before taking the lock on the connections, so that a failed
+        //assertion does not poison it
+        let can_receive = self.check_can_receive_messages(conn, msg.encoded_size());
+        debug_assert!(
+            can_receive,
+            "enqueue_message() called on connection in state {}",
+            conn.state().type_name()
+        );
+        if !can_receive {
+            return;
+        }
+        let mut buf = vec![0u8; msg.encoded_size()];
+        msg.encode(&mut buf).unwrap();
+        conn.stats_mut().bytes_sent += buf.len() as u64;
+        self.with_connection(conn.id(), |c| {
+            c.sent.push(describe_message(&buf));
+            c.output.extend_from_slice(&buf);
+        });
+    }
+
+    fn enqueue_stdin(&self, conn: &mut server::Connection<A, Self>, buf: &[u8]) {
+        let can_receive = self.check_can_receive_stdin(conn, buf.len());
+        debug_assert!(
+            can_receive,
+            "enqueue_stdin() called on connection in state {}",
+            conn.state().type_name()
+        );
+        if !can_receive {
+            return;
+        }
+        conn.stats_mut().bytes_sent += buf.len() as u64;
+        self.with_connection(conn.id(), |c| c.stdin.extend_from_slice(buf));
+    }
+}
+
+//Renders an encoded message (which may be framed) in the human-readable format.
+fn describe_message(buf: &[u8]) -> String {
+    let payload = match crate::common::decode_frame(buf) {
+        Some((payload, len)) if len == buf.len() => payload,
+        _ => buf,
+    };
+    match msg::Message::parse(payload) {
+        Ok((msg, _)) => msg.to_string(),
+        Err(_) => String::from_utf8_lossy(buf).into_owned(),
+    }
+}
+
+///An [Application](../trait.Application.html) for use in tests, with an in-memory registry of
+///clients and screens.
+///
+///The message handler chain is chosen through the type argument, which implements
+///[MockHandlers](trait.MockHandlers.html); by default, only the
+///[vt6::core](../core/struct.MessageHandler.html) handler is used. The handshake handler is
+///always [vt6::server::core::HandshakeHandler](../core/struct.HandshakeHandler.html).
+///
+///Clients are registered with
+///[`Application::register_client()`](../trait.Application.html#tymethod.register_client), and
+///screens with [add_screen()](#method.add_screen). Each client secret can be used for exactly one
+///handshake. Stdin and stdout secrets can be used again once the respective connection has been
+///torn down, and a connection presenting them while the screen is still attached takes the
+///attachment over. Property values stored with `persist_property()` are kept in memory and returned by
+///`restore_property()`. Message types can be refused with
+///[forbid_message_type()](#method.forbid_message_type), sessions can be resumed once allowed
+///with [allow_resume()](#method.allow_resume), and connections from some listeners can be refused
+///with [refuse_listener()](#method.refuse_listener). Everything else uses the default implementations
+///from the Application trait.
+///
+///```
+///# use vt6::common::core::{ClientID, ScreenID};
+///# use vt6::server::{Application, ClientIdentity, ConnectionState};
+///use vt6::server::testing::{Conversation, MockApplication};
+///
+///let app: MockApplication = MockApplication::new();
+///let screen_id = ScreenID::parse("screen1").unwrap();
+///app.add_screen(&screen_id);
+///let creds = app.register_client(
+///    ClientIdentity::new(&ClientID::parse("a").unwrap()).with_stdout(&screen_id),
+///);
+///
+///let mut conv = Conversation::new(app.clone());
+///conv.send_message(&vt6::msg::posix::ClientHello { secret: creds.secret() })
+///    .expect(r#"(posix1.server-hello a "" screen1 "")"#);
+///assert_eq!(app.registered_clients().len(), 1);
+///```
+pub struct MockApplication<H = CoreHandlers> {
+    state: Arc<Mutex<MockState>>,
+    handler: PhantomData<fn() -> H>,
+}
+
+#[derive(Default)]
+struct MockState {
+    //each client has its credentials and whether they have been used for a handshake
+    clients: Vec<(server::ClientIdentity, server::ClientCredentials, bool)>,
+    screens: Vec<MockScreen>,
+    //key = (screen ID, property name)
+    properties: HashMap<(String, String), Vec<u8>>,
+    notifications: Vec<String>,
+    //message types refused by authorize_message()
+    forbidden_message_types: Vec<String>,
+    //IDs of clients that may resume their session with their original secret
+    resumable_clients: Vec<String>,
+    //labels of listeners whose connections are refused by accept_connection()
+    refused_listeners: Vec<String>,
+    #[cfg(feature = "module_clipboard")]
+    clipboard: HashMap<Selection, String>,
+    #[cfg(feature = "module_clipboard")]
+    //key = client ID
+    clipboard_policies: HashMap<String, server::clipboard::ClipboardPolicy>,
+    #[cfg(feature = "module_job")]
+    //pairs of (action, job client ID)
+    job_actions: Vec<(server::job::JobAction, String)>,
+    #[cfg(feature = "module_sig")]
+    //pairs of (screen ID, signal)
+    unclaimed_signals: Vec<(String, Signal)>,
+}
+
+struct MockScreen {
+    identity: server::ScreenIdentity,
+    credentials: server::ScreenCredentials,
+    is_stdin_attached: bool,
+    is_stdout_attached: bool,
+    line_discipline: server::LineDisciplineOptions,
+}
+
+impl<H> Clone for MockApplication<H> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            handler: PhantomData,
+        }
+    }
+}
+
+impl<H> Default for MockApplication<H> {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MockState::default())),
+            handler: PhantomData,
+        }
+    }
+}
+
+impl<H> MockApplication<H> {
+    ///Creates an application without any clients or screens.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Adds a screen and returns the credentials for attaching stdin and stdout to it.
+    pub fn add_screen(&self, id: &ScreenID<'_>) -> server::ScreenCredentials {
+        let credentials = server::ScreenCredentials::generate();
+        self.state.lock().unwrap().screens.push(MockScreen {
+            identity: server::ScreenIdentity::new(id),
+            credentials: credentials.clone(),
+            is_stdin_attached: false,
+            is_stdout_attached: false,
+            line_discipline: Default::default(),
+        });
+        credentials
+    }
+
+    ///Returns all registered clients, in order of registration.
+    pub fn registered_clients(&self) -> Vec<server::ClientIdentity> {
+        let state = self.state.lock().unwrap();
+        state.clients.iter().map(|(i, _, _)| i.clone()).collect()
+    }
+
+    ///Returns whether a client with the given ID has completed its handshake.
+    pub fn is_client_authorized(&self, id: ClientID<'_>) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .clients
+            .iter()
+            .any(|(i, _, is_authorized)| *is_authorized && i.client_id() == id)
+    }
+
+    ///Makes `authorize_message()` refuse all messages of the given type, e.g. `"sig1.release"`.
+    pub fn forbid_message_type(&self, msg_type: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.forbidden_message_types.push(msg_type.to_owned());
+    }
+
+    ///Allows the client with the given ID to resume its session through `resume_client()`, by
+    ///presenting the secret from its original handshake again.
+    pub fn allow_resume(&self, id: ClientID<'_>) {
+        let mut state = self.state.lock().unwrap();
+        state.resumable_clients.push(id.as_str().to_owned());
+    }
+
+    ///Makes `accept_connection()` refuse all connections that arrive through the listener with
+    ///the given label.
+    pub fn refuse_listener(&self, label: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.refused_listeners.push(label.to_owned());
+    }
+
+    ///Returns all notifications that were received through `notify()`, in their Display format.
+    pub fn notifications(&self) -> Vec<String> {
+        self.state.lock().unwrap().notifications.clone()
+    }
+
+    ///Returns the value of a property that was stored with `persist_property()`.
+    pub fn persisted_property(
+        &self,
+        screen: &server::ScreenIdentity,
+        name: &str,
+    ) -> Option<Vec<u8>> {
+        let key = (screen.screen_id().as_str().to_owned(), name.to_owned());
+        self.state.lock().unwrap().properties.get(&key).cloned()
+    }
+
+    #[cfg(feature = "module_job")]
+    ///Returns all job control actions that were performed through `control_job()`, in order,
+    ///together with the client ID identifying the respective job. The mock performs all actions
+    ///that it is asked for.
+    pub fn job_actions(&self) -> Vec<(server::job::JobAction, String)> {
+        self.state.lock().unwrap().job_actions.clone()
+    }
+
+    #[cfg(feature = "module_sig")]
+    ///Returns all signals that were reported through `signal_unclaimed()`, in order, together
+    ///with the ID of the screen that they were generated for.
+    pub fn unclaimed_signals(&self) -> Vec<(String, Signal)> {
+        self.state.lock().unwrap().unclaimed_signals.clone()
+    }
+
+    #[cfg(feature = "module_clipboard")]
+    ///Sets the policy that `clipboard_policy()` reports for the client with the given ID. Clients
+    ///without an explicit policy get the default policy of the Application trait.
+    pub fn set_clipboard_policy(
+        &self,
+        id: ClientID<'_>,
+        policy: server::clipboard::ClipboardPolicy,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .clipboard_policies
+            .insert(id.as_str().to_owned(), policy);
+    }
+}
+
+impl<H: MockHandlers> server::Application for MockApplication<H> {
+    type MessageConnector = MockMessageConnector;
+    type StdoutConnector = MockStdoutConnector;
+    type MessageHandler = H::MessageHandler;
+    type HandshakeHandler = server::core::HandshakeHandler<server::RejectHandler>;
+
+    fn notify(&self, n: &server::Notification) {
+        self.state.lock().unwrap().notifications.push(n.to_string());
+    }
+
+    fn has_outgoing_filter(&self) -> bool {
+        H::has_outgoing_filter()
+    }
+
+    fn filter_outgoing<D: server::Dispatch<Self>>(
+        &self,
+        msg: &msg::Message,
+        conn: &server::Connection<Self, D>,
+    ) -> server::OutgoingAction {
+        H::filter_outgoing(msg, conn)
+    }
+
+    fn register_client(&self, i: server::ClientIdentity) -> server::ClientCredentials {
+        let creds = server::ClientCredentials::generate();
+        let mut state = self.state.lock().unwrap();
+        state.clients.push((i, creds.clone(), false));
+        creds
+    }
+
+    fn register_clients(&self, ids: Vec<server::ClientIdentity>) -> Vec<server::ClientCredentials> {
+        //hold the lock for the whole batch, so that other threads see all clients or none
+        let mut state = self.state.lock().unwrap();
+        ids.into_iter()
+            .map(|i| {
+                let creds = server::ClientCredentials::generate();
+                state.clients.push((i, creds.clone(), false));
+                creds
+            })
+            .collect()
+    }
+
+    fn unregister_clients(&self, s: server::ClientSelector) {
+        let mut state = self.state.lock().unwrap();
+        state.clients.retain(|(i, _, _)| !s.contains(i.client_id()));
+    }
+
+    fn has_clients(&self, s: server::ClientSelector) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .clients
+            .iter()
+            .any(|(i, _, _)| s.contains(i.client_id()))
+    }
+
+    fn accept_connection(&self, peer: &server::PeerInfo<'_>) -> server::AcceptDecision {
+        let state = self.state.lock().unwrap();
+        let is_refused = |label: &str| state.refused_listeners.iter().any(|l| l == label);
+        if peer.listener.is_some_and(is_refused) {
+            server::AcceptDecision::Refuse
+        } else {
+            server::AcceptDecision::Accept
+        }
+    }
+
+    fn authorize_client(
+        &self,
+        secret: &str,
+        _peer: Option<&server::PeerCredentials>,
+    ) -> Option<server::ClientIdentity> {
+        let mut state = self.state.lock().unwrap();
+        let (i, _, is_authorized) = state
+            .clients
+            .iter_mut()
+            .find(|(_, creds, is_authorized)| !*is_authorized && creds.verify(secret))?;
+        *is_authorized = true;
+        Some(i.clone())
+    }
+
+    fn resume_client(
+        &self,
+        secret: &str,
+        _peer: Option<&server::PeerCredentials>,
+    ) -> Option<server::ClientIdentity> {
+        let state = self.state.lock().unwrap();
+        let is_resumable = |i: &server::ClientIdentity| {
+            let id = i.client_id();
+            state.resumable_clients.iter().any(|r| r == id.as_str())
+        };
+        state
+            .clients
+            .iter()
+            .find(|(i, creds, is_authorized)| {
+                *is_authorized && is_resumable(i) && creds.verify(secret)
+            })
+            .map(|(i, _, _)| i.clone())
+    }
+
+    fn authorize_message(
+        &self,
+        _client: &server::ClientIdentity,
+        msg_type: &crate::common::core::MessageType<'_>,
+    ) -> bool {
+        let state = self.state.lock().unwrap();
+        !state
+            .forbidden_message_types
+            .iter()
+            .any(|t| t == msg_type.as_str())
+    }
+
+    fn find_client(&self, id: ClientID<'_>) -> Option<server::ClientIdentity> {
+        let state = self.state.lock().unwrap();
+        state
+            .clients
+            .iter()
+            .find(|(i, _, _)| i.client_id() == id)
+            .map(|(i, _, _)| i.clone())
+    }
+
+    fn authorize_stdin(&self, secret: &str) -> Option<server::ScreenIdentity> {
+        let mut state = self.state.lock().unwrap();
+        let screen = state
+            .screens
+            .iter_mut()
+            .find(|s| !s.is_stdin_attached && s.credentials.verify_stdin(secret))?;
+        screen.is_stdin_attached = true;
+        Some(screen.identity.clone())
+    }
+
+    fn authorize_stdout(&self, secret: &str) -> Option<server::ScreenIdentity> {
+        let mut state = self.state.lock().unwrap();
+        let screen = state
+            .screens
+            .iter_mut()
+            .find(|s| !s.is_stdout_attached && s.credentials.verify_stdout(secret))?;
+        screen.is_stdout_attached = true;
+        Some(screen.identity.clone())
+    }
+
+    fn detach_stdin(&self, screen: &server::ScreenIdentity) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(s) = state.screens.iter_mut().find(|s| s.identity == *screen) {
+            s.is_stdin_attached = false;
+        }
+    }
+
+    fn detach_stdout(&self, screen: &server::ScreenIdentity) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(s) = state.screens.iter_mut().find(|s| s.identity == *screen) {
+            s.is_stdout_attached = false;
+        }
+    }
+
+    fn takeover_stdin(&self, secret: &str) -> Option<server::ScreenIdentity> {
+        let state = self.state.lock().unwrap();
+        state
+            .screens
+            .iter()
+            .find(|s| s.is_stdin_attached && s.credentials.verify_stdin(secret))
+            .map(|s| s.identity.clone())
+    }
+
+    fn takeover_stdout(&self, secret: &str) -> Option<server::ScreenIdentity> {
+        let state = self.state.lock().unwrap();
+        state
+            .screens
+            .iter()
+            .find(|s| s.is_stdout_attached && s.credentials.verify_stdout(secret))
+            .map(|s| s.identity.clone())
+    }
+
+    fn line_discipline_options(
+        &self,
+        screen: &server::ScreenIdentity,
+    ) -> Option<server::LineDisciplineOptions> {
+        let state = self.state.lock().unwrap();
+        let s = state.screens.iter().find(|s| s.identity == *screen)?;
+        Some(s.line_discipline)
+    }
+
+    fn set_line_discipline_options(
+        &self,
+        screen: &server::ScreenIdentity,
+        options: server::LineDisciplineOptions,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(s) = state.screens.iter_mut().find(|s| s.identity == *screen) {
+            s.line_discipline = options;
+        }
+    }
+
+    fn persist_property(&self, screen: &server::ScreenIdentity, name: &str, value: &[u8]) {
+        let key = (screen.screen_id().as_str().to_owned(), name.to_owned());
+        self.state
+            .lock()
+            .unwrap()
+            .properties
+            .insert(key, value.to_vec());
+    }
+
+    fn restore_property(&self, screen: &server::ScreenIdentity, name: &str) -> Option<Vec<u8>> {
+        self.persisted_property(screen, name)
+    }
+
+    #[cfg(feature = "module_sig")]
+    fn signal_unclaimed(&self, screen: &server::ScreenIdentity, signal: Signal) {
+        let mut state = self.state.lock().unwrap();
+        let screen_id = screen.screen_id().as_str().to_owned();
+        state.unclaimed_signals.push((screen_id, signal));
+    }
+
+    #[cfg(feature = "module_job")]
+    fn control_job(
+        &self,
+        _requester: &server::ClientIdentity,
+        action: server::job::JobAction,
+        job: ClientID<'_>,
+    ) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.job_actions.push((action, job.as_str().to_owned()));
+        true
+    }
+
+    #[cfg(feature = "module_clipboard")]
+    fn clipboard_policy(
+        &self,
+        client: &server::ClientIdentity,
+    ) -> server::clipboard::ClipboardPolicy {
+        let state = self.state.lock().unwrap();
+        let key = client.client_id().as_str();
+        match state.clipboard_policies.get(key) {
+            Some(policy) => *policy,
+            None => server::clipboard::ClipboardPolicy::WRITE_ONLY,
+        }
+    }
+
+    #[cfg(feature = "module_clipboard")]
+    fn clipboard_text(&self, selection: Selection) -> Option<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .clipboard
+            .get(&selection)
+            .cloned()
+    }
+
+    #[cfg(feature = "module_clipboard")]
+    fn set_clipboard_text(&self, selection: Selection, text: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.clipboard.insert(selection, text.to_owned());
+    }
+}
+
+///Selects the message handler chain of a [MockApplication](struct.MockApplication.html).
+///
+///This is implemented on a marker type instead of on the handler chain itself, since the handler
+///chain needs to know the application type, and vice versa.
+///
+///```no_run
+///# use vt6::server::testing::{MockApplication, MockHandlers};
+///# type MyHandler<Next> = vt6::server::term::MessageHandler<Next>;
+///struct MyHandlers;
+///
+///impl MockHandlers for MyHandlers {
+///    type MessageHandler = vt6::server::core::MessageHandler<MyHandler<vt6::server::RejectHandler>>;
+///}
+///
+///let app: MockApplication<MyHandlers> = MockApplication::new();
+///```
+pub trait MockHandlers: Sized + 'static {
+    type MessageHandler: server::MessageHandler<MockApplication<Self>>;
+
+    ///Used for [`Application::has_outgoing_filter()`](../trait.Application.html#method.has_outgoing_filter).
+    ///The default implementation returns false.
+    fn has_outgoing_filter() -> bool {
+        false
+    }
+    ///Used for [`Application::filter_outgoing()`](../trait.Application.html#method.filter_outgoing).
+    ///The default implementation lets all messages pass.
+    fn filter_outgoing<D: server::Dispatch<MockApplication<Self>>>(
+        _msg: &msg::Message,
+        _conn: &server::Connection<MockApplication<Self>, D>,
+    ) -> server::OutgoingAction {
+        server::OutgoingAction::Pass
+    }
+}
+
+///The default [MockHandlers](trait.MockHandlers.html): only the
+///[vt6::core](../core/struct.MessageHandler.html) handler.
+pub struct CoreHandlers;
+
+impl MockHandlers for CoreHandlers {
+    type MessageHandler = server::core::MessageHandler<server::RejectHandler>;
+}
+
+///The [MessageConnector](../trait.MessageConnector.html) used by
+///[MockApplication](struct.MockApplication.html).
+#[derive(Clone, Debug)]
+pub struct MockMessageConnector(server::ClientIdentity);
+
+impl server::MessageConnector for MockMessageConnector {
+    fn new(id: server::ClientIdentity) -> Self {
+        Self(id)
+    }
+
+    fn identity(&self) -> &server::ClientIdentity {
+        &self.0
+    }
+}
+
+///The [StdoutConnector](../trait.StdoutConnector.html) used by
+///[MockApplication](struct.MockApplication.html). It records everything that it receives.
+#[derive(Clone, Debug)]
+pub struct MockStdoutConnector {
+    identity: server::ScreenIdentity,
+    received: Vec<u8>,
+    is_ready: bool,
+    is_closed: bool,
+}
+
+impl MockStdoutConnector {
+    ///Returns everything that the client has written to stdout so far.
+    pub fn received(&self) -> &[u8] {
+        &self.received
+    }
+
+    ///Sets the value returned by `is_ready()`, to simulate a screen that cannot keep up with
+    ///rendering. The default is `true`.
+    pub fn set_ready(&mut self, is_ready: bool) {
+        self.is_ready = is_ready;
+    }
+
+    ///Simulates that the user closed the screen: All further calls to `receive()` fail with
+    ///`StdoutError::ScreenClosed`.
+    pub fn close(&mut self) {
+        self.is_closed = true;
+    }
+}
+
+impl server::StdoutConnector for MockStdoutConnector {
+    fn new(identity: server::ScreenIdentity) -> Self {
+        Self {
+            identity,
+            received: Vec::new(),
+            is_ready: true,
+            is_closed: false,
+        }
+    }
+
+    fn identity(&self) -> Option<&server::ScreenIdentity> {
+        Some(&self.identity)
+    }
+
+    fn receive(&mut self, buf: &[u8]) -> Result<(), server::StdoutError> {
+        if self.is_closed {
+            return Err(server::StdoutError::ScreenClosed);
+        }
+        self.received.extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn is_ready(&self) -> bool {
+        self.is_ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::ScopedIdentifier;
+    use crate::server::{ClientIdentity, ScreenIdentity};
+
+    #[test]
+    fn test_msgio_conversation() {
+        let app: MockApplication = MockApplication::new();
+        let creds = server::Application::register_client(
+            &app,
+            ClientIdentity::new(&ClientID::parse("a").unwrap()),
+        );
+        let mut conv = Conversation::new(app);
+        conv.send_message(&crate::msg::posix::ClientHello {
+            secret: creds.secret(),
+        })
+        .expect(r#"(posix1.server-hello a "" "" "")"#)
+        .send(b"{2|4:want,5:core1,}{2|4:want,4:foo1,}")
+        .expect("(have core1.0)")
+        .expect_wire(b"{2|4:have,4:foo1,}")
+        .expect_no_reply();
+
+        //incomplete messages are held back until the rest arrives
+        conv.send(b"{2|4:want,")
+            .expect_no_reply()
+            .send(b"5:core1,}")
+            .expect("(have core1.0)");
+
+        //replies sent through broadcasts appear as well
+        conv.connection_mut()
+            .subscribe(&ScopedIdentifier::parse("example1.width").unwrap());
+        server::core::publish_property(&conv.dispatch(), "example1.width", b"80", |_| true);
+        assert_eq!(conv.replies(), vec!["(core1.pub example1.width 80)"]);
+    }
+
+    #[test]
+    fn test_mock_application() {
+        let app: MockApplication = MockApplication::new();
+        let screen_id = ScreenID::parse("screen1").unwrap();
+        let screen_creds = app.add_screen(&screen_id);
+        let creds = server::Application::register_client(
+            &app,
+            ClientIdentity::new(&ClientID::parse("a").unwrap()),
+        );
+        let dispatch = MockDispatch::new(app.clone());
+
+        let mut conv1 = Conversation::with_dispatch(&dispatch);
+        conv1
+            .send_message(&crate::msg::posix::ClientHello {
+                secret: creds.secret(),
+            })
+            .expect(r#"(posix1.server-hello a "" "" "")"#);
+        assert!(app.is_client_authorized(ClientID::parse("a").unwrap()));
+
+        //secrets can only be used once
+        let mut conv2 = Conversation::with_dispatch(&dispatch);
+        conv2
+            .send_message(&crate::msg::posix::ClientHello {
+                secret: creds.secret(),
+            })
+            .expect_no_reply();
+        assert_eq!(conv2.connection().state().type_name(), "Teardown");
+
+        //clients made by other clients are registered with the application
+        conv1.send_message(&crate::msg::core::ClientMake {
+            client_id: ClientID::parse("ab").unwrap(),
+            stdin_screen_id: None,
+            stdout_screen_id: Some(screen_id),
+            stderr_screen_id: None,
+        });
+        assert_eq!(conv1.replies().len(), 1);
+        let clients = app.registered_clients();
+        assert_eq!(clients.len(), 2);
+        assert_eq!(clients[1].stdout_screen_id(), Some(screen_id));
+
+        let mut conv3 = Conversation::with_dispatch(&dispatch);
+        conv3.send_message(&crate::msg::posix::StdoutHello {
+            secret: screen_creds.stdout_secret(),
+        });
+        match conv3.connection().state() {
+            server::ConnectionState::Stdout(c) => assert_eq!(c.received(), b""),
+            s => panic!("unexpected state {}", s.type_name()),
+        }
+
+        //published properties reach all subscribed connections
+        conv1
+            .connection_mut()
+            .subscribe(&ScopedIdentifier::parse("example1.width").unwrap());
+        server::core::publish_property(&dispatch, "example1.width", b"80", |_| true);
+        conv1.expect("(core1.pub example1.width 80)");
+
+        let sent = dispatch.sent_messages(conv1.connection().id());
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[2], "(core1.pub example1.width 80)");
+        assert!(dispatch.sent_messages(conv2.connection().id()).is_empty());
+        assert_eq!(dispatch.connection_ids(), vec![0, 1, 2]);
+        assert!(app.notifications().is_empty());
+    }
+
+    #[test]
+    fn test_stdin_conversation() {
+        let app: MockApplication = MockApplication::new();
+        let screen_id = ScreenID::parse("screen1").unwrap();
+        let screen_creds = app.add_screen(&screen_id);
+        let mut conv = Conversation::new(app);
+        conv.send_message(&crate::msg::posix::StdinHello {
+            secret: screen_creds.stdin_secret(),
+        })
+        .expect_no_reply();
+        assert_eq!(conv.connection().state().type_name(), "Stdin");
+
+        let screen = ScreenIdentity::new(&screen_id);
+        server::Dispatch::enqueue_broadcast(
+            &conv.dispatch(),
+            Box::new(move |conn| {
+                if conn.state().can_receive_stdin_for_screen(&screen) {
+                    conn.enqueue_stdin(b"hello");
+                }
+            }),
+        );
+        conv.expect_stdin(b"hello").expect_stdin(b"");
+    }
+
+    #[test]
+    fn test_dropped_in_invalid_state() {
+        let app: MockApplication = MockApplication::new();
+        let mut conv = Conversation::new(app.clone());
+        let dispatch = conv.dispatch();
+
+        //enqueue_message() and enqueue_stdin() panic on this in debug builds, so the drop path is
+        //checked directly
+        let conn = conv.connection_mut();
+        assert!(!dispatch.check_can_receive_stdin(conn, 5));
+        assert!(dispatch.check_can_receive_messages(conn, 19));
+        assert_eq!(
+            app.notifications(),
+            vec!["dropped 5 bytes of stdin for client: connection is in state Handshake"]
+        );
+        conv.expect_stdin(b"").expect_no_reply();
+    }
+}
@@ -5,60 +5,232 @@
 *******************************************************************************/
 
 use crate::common::core::msg;
+use crate::common::{SendBuffer, SendQueue};
 use crate::server;
 use crate::server::tokio as my;
-use futures::future::{AbortHandle, AbortRegistration, Abortable, Aborted};
+use futures::future::{AbortHandle, AbortRegistration, Abortable, Aborted, Either};
+use futures::stream::{FuturesUnordered, StreamExt};
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex, RwLock, RwLockWriteGuard};
-use tokio::sync::Notify;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
 
 struct ConnectionPoolEntry<A: server::Application> {
     conn: server::Connection<A, Dispatch<A>>,
     rx_abort: AbortHandle,
     tx_abort: AbortHandle,
+    //Signaled when Connection::is_reading_paused() changes, to wake up the rx job.
+    rx_pause_changed: Arc<Notify>,
+    rx_paused: bool,
+    //Mirrors Connection::egress_shaping() for the tx job, so that it can look at the current
+    //setting without taking the connection lock.
+    tx_shaping: watch::Sender<server::EgressShaping>,
+    //Set when the connection is removed from the pool, for threads that have obtained the entry
+    //before that and are still waiting for its lock.
+    closed: bool,
 }
 
 struct ConnectionPool<A: server::Application> {
-    conns: HashMap<u64, ConnectionPoolEntry<A>>,
+    conns: HashMap<u64, Arc<Mutex<ConnectionPoolEntry<A>>>>,
     next_connection_id: u64,
 }
 
+struct Listener {
+    path: std::path::PathBuf,
+    label: Option<Arc<str>>,
+    //`None` once run_listener() has taken the socket to accept connections on it
+    socket: Option<ListenerSocket>,
+}
+
+enum ListenerSocket {
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+    //Named pipe instances can only be created from within the Tokio runtime, so the first
+    //instance is only created once run_listener() starts accepting on this listener.
+    #[cfg(all(windows, feature = "use_windows_pipes"))]
+    NamedPipe,
+}
+
+impl Listener {
+    #[cfg(unix)]
+    fn bind(path: std::path::PathBuf, label: Option<Arc<str>>) -> std::io::Result<Self> {
+        //We bind through std instead of tokio because this may be called outside of the Tokio
+        //runtime. The socket will be registered with the runtime by run_listener().
+        let socket = std::os::unix::net::UnixListener::bind(&path)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            path,
+            label,
+            socket: Some(ListenerSocket::Unix(socket)),
+        })
+    }
+
+    #[cfg(all(windows, feature = "use_windows_pipes"))]
+    fn bind(path: std::path::PathBuf, label: Option<Arc<str>>) -> std::io::Result<Self> {
+        Ok(Self {
+            path,
+            label,
+            socket: Some(ListenerSocket::NamedPipe),
+        })
+    }
+
+    #[cfg(unix)]
+    fn remove(self) -> std::io::Result<()> {
+        std::mem::drop(self.socket);
+        std::fs::remove_file(&self.path)
+    }
+
+    #[cfg(all(windows, feature = "use_windows_pipes"))]
+    fn remove(self) -> std::io::Result<()> {
+        //named pipes go away on their own when their last instance is closed
+        Ok(())
+    }
+}
+
+///The transmit side of a connection. Outgoing data is sorted into two priority classes: Messages
+///are control data that is sent ahead of any bulk data (i.e. stdin), so that short protocol
+///replies cannot get stuck behind a large paste or similar. Within each class, data is sent in
+///the order in which it was enqueued.
+#[derive(Default)]
 struct TxConnector {
-    //The boxes shall be allocated individually since we pass them around outside the Vec.
-    #[allow(clippy::vec_box)]
-    bufs: Vec<Box<my::SendBuffer>>,
+    control: SendQueue,
+    bulk: SendQueue,
+    //whether the buffer that is currently being sent was taken from `self.bulk`, so that it can be
+    //recycled into the queue that it came from
+    sending_bulk: bool,
+    //whether the high watermark has been reached, and the low watermark has not been reached since
+    backlogged: bool,
     notify: Arc<Notify>,
 }
 
+impl TxConnector {
+    fn recycle(&mut self, buf: Box<SendBuffer>) {
+        if self.sending_bulk {
+            self.bulk.recycle(buf);
+        } else {
+            self.control.recycle(buf);
+        }
+    }
+
+    fn filled_len(&self) -> usize {
+        self.control.filled_len() + self.bulk.filled_len()
+    }
+
+    fn pop(&mut self) -> Option<Box<SendBuffer>> {
+        if let Some(buf) = self.control.pop() {
+            self.sending_bulk = false;
+            return Some(buf);
+        }
+        let buf = self.bulk.pop()?;
+        self.sending_bulk = true;
+        Some(buf)
+    }
+}
+
 pub(crate) struct InnerDispatch<A: server::Application> {
-    //NOTE: The `self.pool` lock is semantically dominant over the `self.tx` lock. To prevent
-    //deadlocks, the implementation must guarantee that `self.tx` will only ever be locked
-    //when `self.pool` is already locked (both for read locks and for write locks). Across
+    //NOTE: Each connection has a lock of its own (the Mutex around its ConnectionPoolEntry), so that
+    //independent connections can be worked on at the same time. The `self.pool` lock only protects
+    //the set of connections, and is never held while waiting for a connection lock. To prevent
+    //deadlocks, the implementation must guarantee that each thread holds at most one connection
+    //lock at a time, and that no connection lock is taken while holding the `self.tx` lock. Across
     //functions, this is usually guaranteed by passing refs to Connection instances around (which
-    //can only be obtained by holding the `self.pool` lock).
-    path: std::path::PathBuf,
+    //can only be obtained through `self.with_connection()`).
     pub(crate) app: A,
+    attachments: server::Attachments<u64>,
+    subscriptions: server::Subscriptions<u64>,
+    discard_notification_interval: Duration,
+    handshake_tolerance: server::HandshakeTolerance,
+    slow_handler_threshold: Option<Duration>,
+    notification_filter: server::NotificationFilter,
+    pub(crate) blocking_handlers: bool,
+    send_buffer_limit: Option<(usize, server::SendBufferPolicy)>,
+    //high and low watermark
+    send_buffer_watermarks: Option<(usize, usize)>,
+    pub(crate) write_timeout: Option<(Duration, WriteTimeoutAction)>,
+    max_connections: Option<usize>,
+    //The amount of data waiting in the send queues of all connections. This is only modified while
+    //holding the `self.tx` write lock.
+    send_buffer_usage: AtomicUsize,
+    listeners: Mutex<Vec<Listener>>,
+    //Signaled by add_listener() to make run_listener() pick up the new listener.
+    listeners_changed: Notify,
     abort: Mutex<Option<AbortHandle>>,
+    //The receiver/transmitter jobs of all connections, so that run_listener() can wait for them
+    //to finish during shutdown.
+    jobs: Mutex<Vec<JoinHandle<()>>>,
+    //Set to true once the accept loop has ended. Receivers stop reading when they observe this,
+    //and transmitters exit after sending everything that was enqueued up to that point.
+    shutdown: watch::Sender<bool>,
     pool: RwLock<ConnectionPool<A>>,
     tx: RwLock<HashMap<u64, TxConnector>>,
-    //This #[allow] is here because factoring out `type Broadcast<A>` or something like that does
-    //nothing good except shortening this one line at the expense of introducing another type name.
+    bc_queue: Mutex<Vec<Broadcast<A>>>,
+    //Set while some thread is executing broadcasts in do_maintenance(). Only one thread does this
+    //at a time, so that broadcasts are executed in the order in which they were enqueued.
+    bc_running: AtomicBool,
+    //The ID of the connection that a broadcast action is currently running on, or NO_CONNECTION.
+    bc_target: AtomicU64,
+    //How many connection locks are currently held by with_connection() or by broadcasts.
+    conns_in_use: AtomicUsize,
+}
+
+//Connection IDs are counted up from zero, so this one is never used.
+const NO_CONNECTION: u64 = u64::MAX;
+
+//Counts a connection lock in `InnerDispatch::conns_in_use` for as long as it exists.
+struct ConnectionInUse<'a>(&'a AtomicUsize);
+
+impl<'a> ConnectionInUse<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter)
+    }
+}
+
+impl Drop for ConnectionInUse<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct Broadcast<A: server::Application> {
+    //If set, the action only runs on the connections with these IDs.
+    targets: Option<Vec<u64>>,
     #[allow(clippy::type_complexity)]
-    bc_queue: Mutex<Vec<Box<dyn Fn(&mut server::Connection<A, Dispatch<A>>) + Send + Sync>>>,
+    action: Box<dyn Fn(&mut server::Connection<A, Dispatch<A>>) + Send + Sync>,
 }
 
 impl<A: server::Application> InnerDispatch<A> {
-    fn new(path: std::path::PathBuf, app: A) -> Arc<Self> {
+    fn new(builder: DispatchBuilder<A>, listeners: Vec<Listener>) -> Arc<Self> {
         Arc::new(InnerDispatch {
-            path,
-            app,
+            app: builder.app,
+            attachments: server::Attachments::new(),
+            subscriptions: server::Subscriptions::new(),
+            discard_notification_interval: builder.discard_notification_interval,
+            handshake_tolerance: builder.handshake_tolerance,
+            slow_handler_threshold: builder.slow_handler_threshold,
+            notification_filter: builder.notification_filter,
+            blocking_handlers: builder.blocking_handlers,
+            send_buffer_limit: builder.send_buffer_limit,
+            send_buffer_watermarks: builder.send_buffer_watermarks,
+            write_timeout: builder.write_timeout,
+            max_connections: builder.max_connections,
+            send_buffer_usage: AtomicUsize::new(0),
+            listeners: Mutex::new(listeners),
+            listeners_changed: Notify::new(),
             abort: Mutex::new(None),
+            jobs: Mutex::new(Vec::new()),
+            shutdown: watch::channel(false).0,
             pool: RwLock::new(ConnectionPool {
                 conns: HashMap::new(),
                 next_connection_id: 0,
             }),
             tx: RwLock::new(HashMap::new()),
             bc_queue: Mutex::new(Vec::new()),
+            bc_running: AtomicBool::new(false),
+            bc_target: AtomicU64::new(NO_CONNECTION),
+            conns_in_use: AtomicUsize::new(0),
         })
     }
 
@@ -66,197 +238,835 @@ impl<A: server::Application> InnerDispatch<A> {
         Dispatch(self.clone())
     }
 
+    pub(crate) fn shutdown_signal(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+
+    //Like `server::Dispatch::notify()`, for notifications that the Dispatch itself sends.
+    fn notify(&self, n: &server::Notification) {
+        if self.notification_filter.accepts(n) {
+            self.app.notify(n);
+        }
+    }
+
+    ///Takes the sockets of all listeners that run_listener() has not started accepting on yet.
+    #[allow(clippy::type_complexity)]
+    fn take_new_listeners(&self) -> Vec<(ListenerSocket, std::path::PathBuf, Option<Arc<str>>)> {
+        let mut listeners = self.listeners.lock().unwrap();
+        listeners
+            .iter_mut()
+            .filter_map(|l| Some((l.socket.take()?, l.path.clone(), l.label.clone())))
+            .collect()
+    }
+
+    async fn accept_connections(
+        self: &Arc<Self>,
+        socket: ListenerSocket,
+        #[allow(unused_variables)] path: std::path::PathBuf,
+        label: Option<Arc<str>>,
+    ) -> std::io::Result<()> {
+        match socket {
+            #[cfg(unix)]
+            ListenerSocket::Unix(socket) => {
+                let listener = tokio::net::UnixListener::from_std(socket)?;
+                loop {
+                    let (stream, _addr) = listener.accept().await?;
+                    //not all platforms support querying the peer's credentials, so failure is not
+                    //fatal
+                    let peer = stream.peer_cred().ok().map(|c| server::PeerCredentials {
+                        uid: c.uid(),
+                        gid: c.gid(),
+                        pid: c.pid(),
+                    });
+                    if !self.accept_connection(label.as_deref(), peer) {
+                        //dropping the stream closes the connection
+                        continue;
+                    }
+                    let (stream_reader, stream_writer) = stream.into_split();
+                    self.start_connection(stream_reader, stream_writer, label.as_deref(), peer);
+                }
+            }
+            #[cfg(all(windows, feature = "use_windows_pipes"))]
+            ListenerSocket::NamedPipe => {
+                use tokio::net::windows::named_pipe::ServerOptions;
+                let mut server = ServerOptions::new()
+                    .first_pipe_instance(true)
+                    .create(&path)?;
+                loop {
+                    server.connect().await?;
+                    //the next instance must exist before we hand this one off, so that there is no
+                    //window in which clients cannot connect
+                    let next = ServerOptions::new().create(&path)?;
+                    let stream = std::mem::replace(&mut server, next);
+                    if !self.accept_connection(label.as_deref(), None) {
+                        continue;
+                    }
+                    let (stream_reader, stream_writer) = tokio::io::split(stream);
+                    self.start_connection(stream_reader, stream_writer, label.as_deref(), None);
+                }
+            }
+        }
+    }
+
+    ///Decides whether a client connection that was just accepted shall be kept, according to the
+    ///connection limit and the Application. Refusals are reported to the Application.
+    fn accept_connection(
+        &self,
+        label: Option<&str>,
+        peer: Option<server::PeerCredentials>,
+    ) -> bool {
+        let open_connections = self.pool.read().unwrap().conns.len();
+        let reason = match self.max_connections {
+            Some(limit) if open_connections >= limit => {
+                server::RefusalReason::TooManyConnections { limit }
+            }
+            _ => {
+                let info = server::PeerInfo {
+                    listener: label,
+                    credentials: peer,
+                    open_connections,
+                };
+                match self.app.accept_connection(&info) {
+                    server::AcceptDecision::Accept => return true,
+                    server::AcceptDecision::Refuse => server::RefusalReason::Application,
+                }
+            }
+        };
+        #[cfg(feature = "use_tracing")]
+        tracing::debug!(listener = label, %reason, "refused connection");
+        let n = server::Notification::ConnectionRefused {
+            listener: label,
+            reason,
+        };
+        self.notify(&n);
+        false
+    }
+
+    ///Sets up the Connection object and the receiver/transmitter jobs for a freshly accepted
+    ///client connection, and returns the connection ID.
+    fn start_connection<R, W>(
+        self: &Arc<Self>,
+        stream_reader: R,
+        stream_writer: W,
+        label: Option<&str>,
+        peer: Option<server::PeerCredentials>,
+    ) -> u64
+    where
+        R: tokio::io::AsyncRead + Unpin + Send + 'static,
+        W: tokio::io::AsyncWrite + Unpin + Send + 'static,
+    {
+        let (conn_id, rx_abort, tx_abort, rx_pause_changed, tx_notify, tx_shaping) =
+            self.create_connection_object(label, peer);
+        #[cfg(feature = "use_tracing")]
+        tracing::debug!(id = conn_id, listener = label, "accepted connection");
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            //forget about jobs for connections that have already been torn down
+            jobs.retain(|job| !job.is_finished());
+            jobs.push(my::spawn_receiver(
+                self.clone(),
+                rx_abort,
+                conn_id,
+                stream_reader,
+                rx_pause_changed,
+            ));
+            jobs.push(my::spawn_transmitter(
+                self.clone(),
+                tx_abort,
+                conn_id,
+                stream_writer,
+                tx_notify,
+                tx_shaping,
+            ));
+        }
+        let n = server::Notification::ConnectionOpened { listener: label };
+        self.notify(&n);
+        self.with_connection(conn_id, |conn| conn.handle_connect());
+        conn_id
+    }
+
     fn create_connection_object(
         self: &Arc<Self>,
-    ) -> (u64, AbortRegistration, AbortRegistration, Arc<Notify>) {
+        label: Option<&str>,
+        peer: Option<server::PeerCredentials>,
+    ) -> (
+        u64,
+        AbortRegistration,
+        AbortRegistration,
+        Arc<Notify>,
+        Arc<Notify>,
+        watch::Receiver<server::EgressShaping>,
+    ) {
         let (rx_ah, rx_ar) = AbortHandle::new_pair();
         let (tx_ah, tx_ar) = AbortHandle::new_pair();
 
         let mut pool = self.pool.write().unwrap();
         let conn_id = pool.next_connection_id;
         pool.next_connection_id += 1;
-        let conn = server::Connection::new(self.dispatch(), conn_id);
-        pool.conns.insert(
-            conn_id,
-            ConnectionPoolEntry {
-                conn,
-                rx_abort: rx_ah,
-                tx_abort: tx_ah,
-            },
-        );
+        let mut conn = server::Connection::new(self.dispatch(), conn_id);
+        if let Some(label) = label {
+            conn = conn.with_listener(label);
+        }
+        if let Some(peer) = peer {
+            conn = conn.with_peer_credentials(peer);
+        }
+        let rx_pause_changed = Arc::new(Notify::new());
+        let (tx_shaping, tx_shaping_rx) = watch::channel(conn.egress_shaping());
+        let entry = ConnectionPoolEntry {
+            conn,
+            rx_abort: rx_ah,
+            tx_abort: tx_ah,
+            rx_pause_changed: rx_pause_changed.clone(),
+            rx_paused: false,
+            tx_shaping,
+            closed: false,
+        };
+        pool.conns.insert(conn_id, Arc::new(Mutex::new(entry)));
         std::mem::drop(pool); //release the write lock
 
         let tx_notify = Arc::new(Notify::new());
         let tx_connector = TxConnector {
             notify: tx_notify.clone(),
-            bufs: Vec::new(),
+            ..TxConnector::default()
         };
         self.tx.write().unwrap().insert(conn_id, tx_connector);
 
-        (conn_id, rx_ar, tx_ar, tx_notify)
+        (
+            conn_id,
+            rx_ar,
+            tx_ar,
+            rx_pause_changed,
+            tx_notify,
+            tx_shaping_rx,
+        )
     }
 
-    //This #[allow] is here because when I try fixing the lint, it turns into a compile error that
-    //I don't understand. TODO: Check if this is a bug in Clippy or rustc.
-    #[allow(clippy::needless_lifetimes)]
-    pub(crate) fn connection_mut<'a>(self: &'a Arc<Self>, conn_id: u64) -> ConnectionRefMut<'a, A> {
-        ConnectionRefMut {
-            inner: self,
-            guard: self.pool.write().unwrap(),
-            conn_id,
+    ///Returns whether the receiver job shall not read from the given connection right now. This is
+    ///called by the rx job.
+    pub(crate) fn is_reading_paused(&self, conn_id: u64) -> bool {
+        let is_paused = self.visit_connection(conn_id, |conn| conn.is_reading_paused());
+        self.do_maintenance();
+        is_paused.unwrap_or(false)
+    }
+
+    fn entry(&self, conn_id: u64) -> Option<Arc<Mutex<ConnectionPoolEntry<A>>>> {
+        self.pool.read().unwrap().conns.get(&conn_id).cloned()
+    }
+
+    ///Runs the given action on the connection with the given ID while holding its lock, and returns
+    ///the action's result, or `None` if the connection does not exist (anymore). Afterwards, this
+    ///executes the broadcasts that were waiting for the connection lock to be released.
+    pub(crate) fn with_connection<R>(
+        self: &Arc<Self>,
+        conn_id: u64,
+        action: impl FnOnce(&mut server::Connection<A, Dispatch<A>>) -> R,
+    ) -> Option<R> {
+        let result = self.visit_connection(conn_id, action);
+        self.do_maintenance();
+        result
+    }
+
+    fn visit_connection<R>(
+        &self,
+        conn_id: u64,
+        action: impl FnOnce(&mut server::Connection<A, Dispatch<A>>) -> R,
+    ) -> Option<R> {
+        let entry = self.entry(conn_id)?;
+        let mut entry = match entry.lock() {
+            Ok(entry) => entry,
+            //If an earlier action panicked while holding the lock (e.g. on a failed debug
+            //assertion in enqueue_message()), the connection may be in an inconsistent state, so
+            //we do not run any more actions on it, and tear it down instead.
+            Err(poisoned) => {
+                let mut entry = poisoned.into_inner();
+                if !entry.closed {
+                    let _in_use = ConnectionInUse::new(&self.conns_in_use);
+                    entry.conn.tear_down(server::TeardownReason::Panicked);
+                    self.do_maintenance_on_conn(conn_id, &mut entry);
+                }
+                return None;
+            }
+        };
+        //the connection may have been torn down while we were waiting for the lock
+        if entry.closed {
+            return None;
         }
+        //the counter is decremented by the guard, so that it stays correct if `action` panics
+        let in_use = ConnectionInUse::new(&self.conns_in_use);
+        let result = action(&mut entry.conn);
+        self.do_maintenance_on_conn(conn_id, &mut entry);
+        std::mem::drop(entry); //release the connection lock
+        std::mem::drop(in_use);
+        Some(result)
+    }
+
+    ///Reports an IO error on the given connection and tears the connection down. This is called by
+    ///the rx/tx jobs.
+    pub(crate) fn handle_io_error(self: &Arc<Self>, conn_id: u64, error: std::io::Error) {
+        let mut error = Some(error);
+        self.with_connection(conn_id, |conn| {
+            let n = server::Notification::ConnectionIOError {
+                listener: conn.listener(),
+                error: error.take().unwrap().into(),
+            };
+            self.notify(&n);
+            conn.tear_down(server::TeardownReason::IOError);
+        });
+        //if the connection is already gone, the error is still worth reporting
+        if let Some(error) = error {
+            let n = server::Notification::ConnectionIOError {
+                listener: None,
+                error: error.into(),
+            };
+            self.notify(&n);
+        }
+    }
+
+    ///Reports that writing to the given connection has timed out, and tears the connection down
+    ///if configured to do so. `in_flight` is the size of the send buffer that the tx job is
+    ///trying to write. Returns whether the tx job shall keep trying. This is called by the tx job.
+    pub(crate) fn handle_write_timeout(
+        self: &Arc<Self>,
+        conn_id: u64,
+        timeout: Duration,
+        action: WriteTimeoutAction,
+        in_flight: usize,
+    ) -> bool {
+        self.with_connection(conn_id, |conn| {
+            let queued = match self.tx.read().unwrap().get(&conn_id) {
+                Some(connector) => connector.filled_len(),
+                None => 0,
+            };
+            let closed = action == WriteTimeoutAction::Teardown;
+            #[cfg(feature = "use_tracing")]
+            tracing::debug!(id = conn_id, queued, closed, "write timed out");
+            let n = server::Notification::WriteTimeout {
+                listener: conn.listener(),
+                timeout,
+                queued: queued + in_flight,
+                closed,
+            };
+            self.notify(&n);
+            if closed {
+                conn.tear_down(server::TeardownReason::WriteTimeout);
+            }
+            !closed
+        })
+        .unwrap_or(false)
     }
 
     pub(crate) fn swap_send_buffer(
         self: &Arc<Self>,
         conn: &mut server::Connection<A, Dispatch<A>>,
-        buf: Option<Box<my::SendBuffer>>,
-    ) -> Option<Box<my::SendBuffer>> {
-        //This function is called by the tx job to obtain more data to send. `connector.bufs` is
-        //well-ordered, so index 0 contains the next send buffer in line. As an optimization, we
-        //allow the tx job to give us the previous buffer back, and we recycle it by putting it at
-        //the back of our send buffer queue.
+        buf: Option<Box<SendBuffer>>,
+    ) -> Option<Box<SendBuffer>> {
+        //This function is called by the tx job to obtain more data to send. As an optimization,
+        //we allow the tx job to give us the previous buffer back, and we recycle it by putting it
+        //at the back of the send buffer queue that it came from.
 
         let mut tx = self.tx.write().unwrap();
         let connector = tx.get_mut(&conn.id())?;
 
-        if let Some(mut buf) = buf {
-            buf.clear();
-            connector.bufs.push(buf);
+        if let Some(buf) = buf {
+            //the previous buffer has been sent completely
+            conn.stats_mut().bytes_sent += buf.filled_len() as u64;
+            connector.recycle(buf);
         }
 
-        if connector.bufs.iter().all(|b| b.filled_len() == 0) {
-            //we don't have any data to send right now
-            None
+        //returns None if we don't have any data to send right now
+        let buf = connector.pop();
+        if let Some(ref buf) = buf {
+            self.send_buffer_usage
+                .fetch_sub(buf.filled_len(), Ordering::SeqCst);
+        }
+        self.check_watermarks(connector, conn);
+        buf
+    }
+
+    ///Reports when the data waiting in the send buffer of a connection crosses one of the
+    ///watermarks. This is called whenever data is added to or taken out of the send buffer.
+    fn check_watermarks(
+        &self,
+        connector: &mut TxConnector,
+        conn: &mut server::Connection<A, Dispatch<A>>,
+    ) {
+        let (high, low) = match self.send_buffer_watermarks {
+            Some(w) => w,
+            None => return,
+        };
+        let queued = connector.filled_len();
+        let n = if !connector.backlogged && queued >= high {
+            connector.backlogged = true;
+            conn.stats_mut().send_backlog_since = Some(std::time::Instant::now());
+            server::Notification::SendBufferHighWatermark {
+                listener: conn.listener(),
+                queued,
+                watermark: high,
+            }
+        } else if connector.backlogged && queued <= low {
+            connector.backlogged = false;
+            conn.stats_mut().send_backlog_since = None;
+            server::Notification::SendBufferLowWatermark {
+                listener: conn.listener(),
+                queued,
+                watermark: low,
+            }
         } else {
-            Some(connector.bufs.remove(0))
+            return;
+        };
+        self.notify(&n);
+    }
+
+    ///Checks whether `size` more bytes can be enqueued for the given connection without exceeding
+    ///the send buffer limit, and applies the send buffer policy if not. Returns whether the data
+    ///shall be enqueued. If so, it has already been accounted for in `self.send_buffer_usage`.
+    fn reserve_send_buffer(
+        &self,
+        tx: &mut HashMap<u64, TxConnector>,
+        conn: &server::Connection<A, Dispatch<A>>,
+        size: usize,
+    ) -> bool {
+        let queued = self.send_buffer_usage.load(Ordering::SeqCst);
+        let (limit, policy) = match self.send_buffer_limit {
+            Some((limit, policy)) if queued + size > limit => (limit, policy),
+            _ => {
+                self.send_buffer_usage.fetch_add(size, Ordering::SeqCst);
+                return true;
+            }
+        };
+
+        let mut freed = 0;
+        let mut is_victim = false;
+        match policy {
+            server::SendBufferPolicy::RejectNew => {}
+            server::SendBufferPolicy::DropBulk => {
+                freed = tx.values_mut().map(|c| c.bulk.clear()).sum();
+            }
+            server::SendBufferPolicy::CloseMostBacklogged => {
+                let victim = tx
+                    .iter_mut()
+                    .max_by_key(|(_, c)| c.filled_len())
+                    .filter(|(_, c)| c.filled_len() > 0);
+                if let Some((&victim_id, connector)) = victim {
+                    freed = connector.control.clear() + connector.bulk.clear();
+                    is_victim = victim_id == conn.id();
+                    //we cannot get a mutable ref to the victim from here, so it gets torn down
+                    //when the broadcasts are executed
+                    self.bc_queue.lock().unwrap().push(Broadcast {
+                        targets: Some(vec![victim_id]),
+                        action: Box::new(|c| {
+                            c.tear_down(server::TeardownReason::SendBufferLimitExceeded)
+                        }),
+                    });
+                }
+            }
+        }
+        self.send_buffer_usage.fetch_sub(freed, Ordering::SeqCst);
+
+        let accepted = !is_victim && queued - freed + size <= limit;
+        if accepted {
+            self.send_buffer_usage.fetch_add(size, Ordering::SeqCst);
         }
+        let n = server::Notification::SendBufferLimitExceeded {
+            listener: conn.listener(),
+            limit,
+            queued,
+            requested: size,
+            policy,
+            accepted,
+        };
+        self.notify(&n);
+        accepted
     }
 
-    fn do_maintenance_on_conn(
-        self: &Arc<Self>,
-        pool: &mut RwLockWriteGuard<'_, ConnectionPool<A>>,
-        conn_id: u64,
+    ///Returns whether `conn` can receive messages in its current state. If not, a message of the
+    ///given size is reported as dropped. Unlike enqueue_message(), this does not assert, so tests
+    ///can cover the drop path in debug builds as well.
+    fn check_can_receive_messages(
+        &self,
+        conn: &server::Connection<A, Dispatch<A>>,
+        size: usize,
+    ) -> bool {
+        if conn.state().can_receive_messages() {
+            return true;
+        }
+        let reason = server::DropReason::InvalidState(conn.state().type_name());
+        self.report_dropped_message(conn, size, reason);
+        false
+    }
+
+    ///Like check_can_receive_messages(), but for stdin.
+    fn check_can_receive_stdin(
+        &self,
+        conn: &server::Connection<A, Dispatch<A>>,
+        size: usize,
+    ) -> bool {
+        if conn.state().can_receive_stdin() {
+            return true;
+        }
+        #[cfg(feature = "use_tracing")]
+        tracing::debug!(id = conn.id(), size, "dropped stdin");
+        let n = server::Notification::StdinDropped {
+            listener: conn.listener(),
+            size,
+            reason: server::DropReason::InvalidState(conn.state().type_name()),
+        };
+        self.notify(&n);
+        false
+    }
+
+    fn report_dropped_message(
+        &self,
+        conn: &server::Connection<A, Dispatch<A>>,
+        size: usize,
+        reason: server::DropReason,
     ) {
-        //This function is called whenever we are about to drop a
-        //ConnectionRefMut obtained from `self.connection_mut(conn_id)`. Since
-        //the caller had a mutable reference to the connection with the given
-        //ID, the connection state may have changed. Depending on the new state,
-        //we may need to perform maintenance tasks on this connection.
+        #[cfg(feature = "use_tracing")]
+        tracing::debug!(id = conn.id(), size, %reason, "dropped message");
+        let n = server::Notification::MessageDropped {
+            listener: conn.listener(),
+            size,
+            reason,
+        };
+        self.notify(&n);
+    }
+
+    fn do_maintenance_on_conn(&self, conn_id: u64, entry: &mut ConnectionPoolEntry<A>) {
+        //This function is called whenever we are about to release the lock on a connection. Since
+        //the caller had a mutable reference to the connection, the connection state may have
+        //changed. Depending on the new state, we may need to perform maintenance tasks on this
+        //connection.
+
+        //if reading was paused or resumed, tell the rx job
+        let is_paused = entry.conn.is_reading_paused();
+        if entry.rx_paused != is_paused {
+            entry.rx_paused = is_paused;
+            entry.rx_pause_changed.notify_waiters();
+        }
+
+        //if the egress shaping options were changed, tell the tx job
+        let shaping = entry.conn.egress_shaping();
+        if *entry.tx_shaping.borrow() != shaping {
+            entry.tx_shaping.send_replace(shaping);
+        }
 
         //if the connection has been set to state Teardown, abort the rx/tx jobs
         //(this will close the client connection as the respective halfs of the
-        //UnixSocket instance get dropped)
-        if let Some(conn_ref) = pool.conns.get(&conn_id) {
-            if matches!(conn_ref.conn.state(), server::ConnectionState::Teardown) {
-                conn_ref.rx_abort.abort();
-                conn_ref.tx_abort.abort();
-                pool.conns.remove(&conn_id);
-                self.tx.write().unwrap().remove(&conn_id);
-                let n = server::Notification::ConnectionClosed;
-                self.app.notify(&n);
+        //client socket get dropped)
+        if matches!(entry.conn.state(), server::ConnectionState::Teardown) {
+            entry.rx_abort.abort();
+            entry.tx_abort.abort();
+            if let Some(connector) = self.tx.write().unwrap().remove(&conn_id) {
+                self.send_buffer_usage
+                    .fetch_sub(connector.filled_len(), Ordering::SeqCst);
             }
+            entry.closed = true;
+            self.pool.write().unwrap().conns.remove(&conn_id);
+            #[cfg(feature = "use_tracing")]
+            tracing::debug!(id = conn_id, "closed connection");
+            let n = server::Notification::ConnectionClosed {
+                listener: entry.conn.listener(),
+                reason: entry
+                    .conn
+                    .teardown_reason()
+                    .unwrap_or(server::TeardownReason::Unspecified),
+            };
+            self.notify(&n);
         }
     }
 
-    fn do_maintenance(self: &Arc<Self>, pool: &mut RwLockWriteGuard<'_, ConnectionPool<A>>) {
-        //This function is called whenever we are about to drop a `self.pool.write()` lock. We use
-        //this opportunity to execute broadcasts that we could not execute until now because we had
-        //given mutable references to someone else.
-        let mut there_were_broadcasts = false;
+    fn do_maintenance(&self) {
+        //This function is called whenever we have released a connection lock. We use this
+        //opportunity to execute broadcasts that we could not execute until now because we were
+        //holding a connection lock (and a broadcast needs to take the lock of each connection that
+        //it visits). If another thread is executing broadcasts right now, it will also pick up the
+        //ones that are waiting in the queue, so we leave them to it.
+        while !self.bc_queue.lock().unwrap().is_empty() {
+            if self.bc_running.swap(true, Ordering::SeqCst) {
+                return;
+            }
+            self.run_broadcasts();
+            self.bc_running.store(false, Ordering::SeqCst);
+            //if another thread enqueued a broadcast after run_broadcasts() found the queue empty,
+            //it may have left it to us, so we need to check again
+        }
+    }
+
+    fn run_broadcasts(&self) {
         loop {
             use std::ops::DerefMut;
-            let broadcasts = std::mem::replace(self.bc_queue.lock().unwrap().deref_mut(), vec![]);
+            let broadcasts = std::mem::take(self.bc_queue.lock().unwrap().deref_mut());
             if broadcasts.is_empty() {
-                break;
+                return;
             }
-            there_were_broadcasts = true;
             for broadcast in broadcasts {
-                for ref mut conn_entry in pool.conns.values_mut() {
-                    broadcast(&mut conn_entry.conn);
+                let Broadcast { targets, action } = broadcast;
+                let targets = match targets {
+                    Some(targets) => targets,
+                    None => self.pool.read().unwrap().conns.keys().copied().collect(),
+                };
+                for conn_id in targets {
+                    self.visit_connection(conn_id, |conn| {
+                        self.bc_target.store(conn_id, Ordering::SeqCst);
+                        action(conn);
+                        self.bc_target.store(NO_CONNECTION, Ordering::SeqCst);
+                    });
                 }
             }
         }
-
-        //run do_maintenance_on_conn() for all connections to detect state changes (we could not do
-        //this in the previous loop because we cannot pass `pool` to do_maintenance_on_conn() while
-        //iterating over `&mut pool.conns`
-        if there_were_broadcasts {
-            let all_conn_ids: Vec<_> = pool
-                .conns
-                .iter_mut()
-                .map(|(_, entry)| entry.conn.id())
-                .collect();
-            for conn_id in all_conn_ids {
-                self.do_maintenance_on_conn(pool, conn_id);
-            }
-        }
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
-// connection smart pointers
-//
-// We pass these around instead of bare `&Connection` and `&mut Connection`
-// because we want to do some housekeeping whenever the `inner.pool` lock is
-// about to release. Currently we only do this for the write lock, but we may do
-// it for the read lock as well in the future.
-
-pub(crate) struct ConnectionRefMut<'a, A: server::Application> {
-    inner: &'a Arc<InnerDispatch<A>>,
-    guard: RwLockWriteGuard<'a, ConnectionPool<A>>,
-    conn_id: u64,
+// public API
+
+///What the Dispatch does when writing to a connection times out. See
+///[`DispatchBuilder::write_timeout()`](struct.DispatchBuilder.html#method.write_timeout).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteTimeoutAction {
+    ///Only notify the Application, and keep waiting for the client.
+    Report,
+    ///Notify the Application and tear the connection down.
+    Teardown,
 }
 
-impl<'a, A: server::Application> ConnectionRefMut<'a, A> {
-    pub(crate) fn alive(&mut self) -> Option<&mut server::Connection<A, Dispatch<A>>> {
-        self.guard
-            .conns
-            .get_mut(&self.conn_id)
-            .map(|conn_ref| &mut conn_ref.conn)
-    }
+///A builder for [Dispatch](struct.Dispatch.html). Use `Dispatch::builder()` to obtain one.
+pub struct DispatchBuilder<A: server::Application> {
+    path: std::path::PathBuf,
+    app: A,
+    discard_notification_interval: Duration,
+    handshake_tolerance: server::HandshakeTolerance,
+    slow_handler_threshold: Option<Duration>,
+    notification_filter: server::NotificationFilter,
+    blocking_handlers: bool,
+    send_buffer_limit: Option<(usize, server::SendBufferPolicy)>,
+    send_buffer_watermarks: Option<(usize, usize)>,
+    write_timeout: Option<(Duration, WriteTimeoutAction)>,
+    max_connections: Option<usize>,
 }
 
-impl<'a, A: server::Application> Drop for ConnectionRefMut<'a, A> {
-    fn drop(&mut self) {
-        self.inner
-            .do_maintenance_on_conn(&mut self.guard, self.conn_id);
-        self.inner.do_maintenance(&mut self.guard);
+impl<A: server::Application> DispatchBuilder<A> {
+    ///Sets the value returned by
+    ///[`Dispatch::discard_notification_interval()`](../trait.Dispatch.html#method.discard_notification_interval).
+    ///The default is one second.
+    pub fn discard_notification_interval(mut self, interval: Duration) -> Self {
+        self.discard_notification_interval = interval;
+        self
     }
-}
 
-////////////////////////////////////////////////////////////////////////////////
-// public API
+    ///Sets the value returned by
+    ///[`Dispatch::handshake_tolerance()`](../trait.Dispatch.html#method.handshake_tolerance).
+    ///The default is `HandshakeTolerance::STRICT`.
+    pub fn handshake_tolerance(mut self, tolerance: server::HandshakeTolerance) -> Self {
+        self.handshake_tolerance = tolerance;
+        self
+    }
+
+    ///Sets the value returned by
+    ///[`Dispatch::slow_handler_threshold()`](../trait.Dispatch.html#method.slow_handler_threshold).
+    ///The default is to not report slow handlers.
+    pub fn slow_handler_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_handler_threshold = Some(threshold);
+        self
+    }
+
+    ///Sets the value returned by
+    ///[`Dispatch::notification_filter()`](../trait.Dispatch.html#method.notification_filter).
+    ///The default is `NotificationFilter::ALL`.
+    pub fn notification_filter(mut self, filter: server::NotificationFilter) -> Self {
+        self.notification_filter = filter;
+        self
+    }
+
+    ///When enabled, the receiver tasks run handlers through `tokio::task::block_in_place()`, so
+    ///that handlers which block for a long time (e.g. because the Application does blocking IO)
+    ///do not stall the other tasks on the same worker thread. This only has an effect on the
+    ///multi-threaded runtime; on the current-thread runtime, handlers always run inline. The
+    ///default is to run handlers inline.
+    pub fn blocking_handlers(mut self, enabled: bool) -> Self {
+        self.blocking_handlers = enabled;
+        self
+    }
+
+    ///Limits the amount of data waiting in the send buffers of all connections combined to the
+    ///given number of bytes. When enqueueing a message or stdin would exceed the limit, the given
+    ///policy is applied. The default is to not impose any limit. The current amount can be
+    ///observed with
+    ///[`Dispatch::send_buffer_usage()`](struct.Dispatch.html#method.send_buffer_usage).
+    pub fn send_buffer_limit(mut self, limit: usize, policy: server::SendBufferPolicy) -> Self {
+        self.send_buffer_limit = Some((limit, policy));
+        self
+    }
+
+    ///Reports when the data waiting in the send buffer of a single connection reaches the given
+    ///high watermark (in bytes), e.g. because a pager is paused while stdin keeps coming in, and
+    ///when it has fallen back to the given low watermark afterwards. The Application is notified
+    ///through
+    ///[`Notification::SendBufferHighWatermark`](../enum.Notification.html#variant.SendBufferHighWatermark)
+    ///and `SendBufferLowWatermark`, and the current state is also visible in
+    ///[`ConnectionStats::send_backlog_since`](../struct.ConnectionStats.html#structfield.send_backlog_since).
+    ///The default is to not report anything.
+    ///
+    ///The gap between the watermarks keeps the notifications from flapping when the amount of
+    ///waiting data hovers around a single threshold. Panics if `low` is not smaller than `high`.
+    pub fn send_buffer_watermarks(mut self, high: usize, low: usize) -> Self {
+        assert!(
+            low < high,
+            "low watermark must be smaller than high watermark"
+        );
+        self.send_buffer_watermarks = Some((high, low));
+        self
+    }
+
+    ///Detects connections whose client does not read from its socket: When writing to a
+    ///connection does not make progress within the given timeout, the Application is notified
+    ///through
+    ///[`Notification::WriteTimeout`](../enum.Notification.html#variant.WriteTimeout), and the
+    ///given action is taken. The default is to wait indefinitely.
+    pub fn write_timeout(mut self, timeout: Duration, action: WriteTimeoutAction) -> Self {
+        self.write_timeout = Some((timeout, action));
+        self
+    }
+
+    ///Limits how many client connections the Dispatch holds at once, across all listeners. When
+    ///the limit is reached, further connections are closed right after being accepted, and the
+    ///Application is notified through
+    ///[`Notification::ConnectionRefused`](../enum.Notification.html#variant.ConnectionRefused).
+    ///The default is to accept any number of connections. Within the limit, the Application can
+    ///still refuse connections in
+    ///[`Application::accept_connection()`](../trait.Application.html#method.accept_connection).
+    pub fn max_connections(mut self, limit: usize) -> Self {
+        self.max_connections = Some(limit);
+        self
+    }
+
+    ///Creates the Dispatch. This binds the server socket, so it fails if the socket cannot be
+    ///created.
+    pub fn build(mut self) -> std::io::Result<Dispatch<A>> {
+        let listener = Listener::bind(std::mem::take(&mut self.path), None)?;
+        Ok(Dispatch(InnerDispatch::new(self, vec![listener])))
+    }
+
+    #[cfg(feature = "simulation")]
+    ///Creates the Dispatch in simulation mode, where clients are connected through
+    ///[Simulation](struct.Simulation.html) instead of a server socket. The path given to
+    ///`Dispatch::builder()` is not used. This is only available with the `simulation` feature.
+    pub fn build_simulation(self) -> my::Simulation<A> {
+        my::Simulation::new(Dispatch(InnerDispatch::new(self, Vec::new())))
+    }
+}
 
 ///An implementation of [trait Dispatch](../trait.Dispatch.html) using the
 ///[Tokio library](https://tokio.rs/).
+///
+///On Unix, the server sockets are Unix domain sockets at the given filesystem paths. On Windows
+///(with the `use_windows_pipes` feature), the paths are instead taken as names of named pipes,
+///e.g. `\\.\pipe\vt6-1234`. Named pipes are only created once `run_listener()` starts, so
+///errors like a name that is already in use are reported from there instead of from `build()` or
+///`add_listener()`. Peer credentials are not available for named pipes. This crate only
+///implements the server side of named pipes; the client connection types in `vt6::client` are
+///Unix-only.
 #[derive(Clone)]
 pub struct Dispatch<A: server::Application>(Arc<InnerDispatch<A>>);
 
 impl<A: server::Application> Dispatch<A> {
-    ///Creates a new instance. The server socket will be opened at the given path.
+    ///Creates a new instance with default options. The server socket will be opened at the given
+    ///path. This is a shorthand for `Dispatch::builder(path, app).build()`.
     pub fn new(path: impl Into<std::path::PathBuf>, app: A) -> std::io::Result<Self> {
-        Ok(Dispatch(InnerDispatch::new(path.into(), app)))
+        Self::builder(path, app).build()
+    }
+
+    ///Returns a builder for a new instance, which allows for setting options that `new()` sets to
+    ///their defaults. The server socket will be opened at the given path.
+    ///
+    ///```no_run
+    ///# fn main() -> std::io::Result<()> {
+    ///# let app: vt6::server::testing::MockApplication = Default::default();
+    ///# let socket_path = "/run/user/1000/vt6/1234";
+    ///let dispatch = vt6::server::tokio::Dispatch::builder(socket_path, app)
+    ///    .discard_notification_interval(std::time::Duration::from_secs(10))
+    ///    .build()?;
+    ///# Ok(())
+    ///# }
+    ///```
+    pub fn builder(path: impl Into<std::path::PathBuf>, app: A) -> DispatchBuilder<A> {
+        DispatchBuilder {
+            path: path.into(),
+            app,
+            discard_notification_interval: Duration::from_secs(1),
+            handshake_tolerance: server::HandshakeTolerance::STRICT,
+            slow_handler_threshold: None,
+            notification_filter: server::NotificationFilter::ALL,
+            blocking_handlers: false,
+            send_buffer_limit: None,
+            send_buffer_watermarks: None,
+            write_timeout: None,
+            max_connections: None,
+        }
+    }
+
+    ///Adds another server socket at the given path, and binds it immediately. Connections
+    ///accepted on this socket share the connection pool and the Application with all other
+    ///connections of this Dispatch, but they carry the given label (see
+    ///[`Connection::listener()`](../struct.Connection.html#method.listener)), which also appears
+    ///in [Notifications](../enum.Notification.html) about them.
+    ///
+    ///This can be called both before and while `self.run_listener()` is running. The server
+    ///socket will be cleaned up together with the one given to `Dispatch::new()`.
+    ///
+    ///```no_run
+    ///# async fn example() -> std::io::Result<()> {
+    ///# let app: vt6::server::testing::MockApplication = Default::default();
+    ///# let session_socket_path = "/run/user/1000/vt6/1234";
+    ///# let screen_socket_path = "/run/user/1000/vt6/1234-screen1";
+    ///let dispatch = vt6::server::tokio::Dispatch::new(session_socket_path, app)?;
+    ///dispatch.add_listener(screen_socket_path, "screen1")?;
+    ///dispatch.run_listener().await
+    ///# }
+    ///```
+    pub fn add_listener(
+        &self,
+        path: impl Into<std::path::PathBuf>,
+        label: &str,
+    ) -> std::io::Result<()> {
+        if *self.0.shutdown.borrow() {
+            return Err(std::io::Error::other(
+                "cannot add listener to Dispatch that is shutting down",
+            ));
+        }
+        let listener = Listener::bind(path.into(), Some(label.into()))?;
+        self.0.listeners.lock().unwrap().push(listener);
+        self.0.listeners_changed.notify_one();
+        Ok(())
     }
 
     ///Runs the dispatch's event loop. Returns `Ok(())` when `self.shutdown()` was called, or `Err`
     ///on unexpected IO errors.
     pub async fn run_listener(&self) -> std::io::Result<()> {
-        let listener = tokio::net::UnixListener::bind(&self.0.path)?;
-
         //set up an AbortHandle that shutdown() can use to intercept our loop
         let (ah, ar) = AbortHandle::new_pair();
         *(self.0.abort.lock().unwrap()) = Some(ah);
 
-        //run the listener.accept() loop until IO error or abortion via shutdown()
+        //run the listener.accept() loops of all listeners (including those added by
+        //add_listener() while we are running) until IO error or abortion via shutdown()
         let accept_future = async {
+            let mut accept_loops = FuturesUnordered::new();
             loop {
-                let (stream, _addr) = listener.accept().await?;
-                let (stream_reader, stream_writer) = stream.into_split();
-                let (conn_id, rx_abort, tx_abort, tx_notify) = self.0.create_connection_object();
-                my::spawn_receiver(self.0.clone(), rx_abort, conn_id, stream_reader);
-                my::spawn_transmitter(self.0.clone(), tx_abort, conn_id, stream_writer, tx_notify);
-                self.0.app.notify(&server::Notification::ConnectionOpened);
+                for (socket, path, label) in self.0.take_new_listeners() {
+                    accept_loops.push(self.0.accept_connections(socket, path, label));
+                }
+                let listeners_changed = self.0.listeners_changed.notified();
+                if accept_loops.is_empty() {
+                    listeners_changed.await;
+                    continue;
+                }
+                futures::pin_mut!(listeners_changed);
+                let accept_loop = accept_loops.next();
+                if let Either::Left((Some(Err(e)), _)) =
+                    futures::future::select(accept_loop, listeners_changed).await
+                {
+                    return Err(e);
+                }
             }
         };
         match Abortable::new(accept_future, ar).await {
@@ -265,25 +1075,101 @@ impl<A: server::Application> Dispatch<A> {
             Err(Aborted) => {}
         };
 
-        //tell all receiver/transmitter jobs to quit it
-        for conn in self.0.pool.write().unwrap().conns.values() {
-            conn.rx_abort.abort();
-            conn.tx_abort.abort();
+        //tell all receiver/transmitter jobs to quit it, and wait for them to do so (we do not
+        //abort them since that could lose messages that were already enqueued, or cut off a
+        //message in the middle of being written)
+        self.0.shutdown.send_replace(true);
+        let jobs = std::mem::take(&mut *self.0.jobs.lock().unwrap());
+        for job in jobs {
+            //a JoinError can only occur if the job panicked, in which case there is nothing left
+            //for us to clean up
+            let _ = job.await;
+        }
+
+        //tear down all remaining connections
+        let conn_ids: Vec<u64> = self.0.pool.read().unwrap().conns.keys().copied().collect();
+        for conn_id in conn_ids {
+            self.0.with_connection(conn_id, |conn| {
+                conn.tear_down(server::TeardownReason::ServerShutdown)
+            });
+        }
+
+        //clean up the server sockets
+        let listeners = std::mem::take(&mut *self.0.listeners.lock().unwrap());
+        let mut result = Ok(());
+        for listener in listeners {
+            result = result.and(listener.remove());
         }
+        result
+    }
+
+    ///Returns a snapshot of the [statistics](../struct.ConnectionStats.html) of all current
+    ///connections, along with their connection IDs and the names of their current states.
+    ///
+    ///This is intended for debugging displays. Since each connection has to be locked to take its
+    ///snapshot, calling this frequently can slow down the dispatch.
+    pub fn connection_stats(&self) -> Vec<(u64, &'static str, server::ConnectionStats)> {
+        let conn_ids: Vec<u64> = self.0.pool.read().unwrap().conns.keys().copied().collect();
+        let mut result: Vec<_> = conn_ids
+            .into_iter()
+            .filter_map(|id| {
+                self.0.visit_connection(id, |conn| {
+                    (id, conn.state().type_name(), conn.stats().clone())
+                })
+            })
+            .collect();
+        self.0.do_maintenance();
+        result.sort_by_key(|(id, _, _)| *id);
+        result
+    }
 
-        //clean up the server socket
-        std::mem::drop(listener);
-        std::fs::remove_file(&self.0.path)
+    ///Returns how many bytes are waiting in the send buffers of all connections combined. This
+    ///does not include data that is currently being written to a socket.
+    pub fn send_buffer_usage(&self) -> usize {
+        self.0.send_buffer_usage.load(Ordering::SeqCst)
     }
 
     ///Ask the event loop to shutdown. After this call, the `self.run_listener()` future will
     ///resolve to `Ok(())` once all client connections and the server socket have been dismantled.
+    ///
+    ///The shutdown is orderly: No new connections are accepted and no further input is read from
+    ///clients, but all messages and stdin that were enqueued before the shutdown are still sent.
+    ///If a client stops reading from its socket, `self.run_listener()` will therefore wait until
+    ///that client disconnects.
     pub fn shutdown(&self) {
         use std::ops::Deref;
         if let Some(ref handle) = self.0.abort.lock().unwrap().deref() {
             handle.abort();
         }
     }
+
+    //Connects a client through the given stream halves, as if it had been accepted on a server
+    //socket without peer credentials.
+    #[cfg(feature = "simulation")]
+    pub(crate) fn connect_stream<R, W>(&self, reader: R, writer: W, label: Option<&str>) -> u64
+    where
+        R: tokio::io::AsyncRead + Unpin + Send + 'static,
+        W: tokio::io::AsyncWrite + Unpin + Send + 'static,
+    {
+        self.0.start_connection(reader, writer, label, None)
+    }
+
+    fn enqueue(&self, broadcast: Broadcast<A>) {
+        self.0.bc_queue.lock().unwrap().push(broadcast);
+
+        //if possible, execute the broadcast right now
+        //
+        //This part is important because, if we didn't have it, and there is nothing currently
+        //being received or transmitted, the broadcast would just needlessly sit in the queue until
+        //the next time a client sends data to us. But we must not take a connection lock while
+        //the current thread holds one. `conns_in_use` counts the connection locks held by all
+        //threads, so it cannot tell whether the lock is ours. If it is nonzero, we leave the
+        //broadcast to the other lock holders: with_connection() calls do_maintenance() after
+        //releasing its lock, and do_maintenance() checks the queue again before it returns.
+        if self.0.conns_in_use.load(Ordering::SeqCst) == 0 {
+            self.0.do_maintenance();
+        }
+    }
 }
 
 impl<A: server::Application> server::Dispatch<A> for Dispatch<A> {
@@ -293,21 +1179,49 @@ impl<A: server::Application> server::Dispatch<A> for Dispatch<A> {
         &self.0.app
     }
 
+    fn attachments(&self) -> &server::Attachments<u64> {
+        &self.0.attachments
+    }
+
+    fn subscriptions(&self) -> &server::Subscriptions<u64> {
+        &self.0.subscriptions
+    }
+
+    fn discard_notification_interval(&self) -> Duration {
+        self.0.discard_notification_interval
+    }
+
+    fn handshake_tolerance(&self) -> server::HandshakeTolerance {
+        self.0.handshake_tolerance
+    }
+
+    fn slow_handler_threshold(&self) -> Option<Duration> {
+        self.0.slow_handler_threshold
+    }
+
+    fn notification_filter(&self) -> server::NotificationFilter {
+        self.0.notification_filter
+    }
+
     fn enqueue_broadcast(
         &self,
         action: Box<dyn Fn(&mut server::Connection<A, Self>) + Send + Sync>,
     ) {
-        //put the broadcast in the queue
-        self.0.bc_queue.lock().unwrap().push(action);
+        self.enqueue(Broadcast {
+            targets: None,
+            action,
+        });
+    }
 
-        //if possible, execute the broadcast right now
-        //
-        //This part is important because, if we didn't have it, and there is nothing currently
-        //being received or transmitted, the broadcast would just needlessly sit in the queue until
-        //the next time a client sends data to us.
-        if let Ok(mut pool_lock) = self.0.pool.try_write() {
-            self.0.do_maintenance(&mut pool_lock);
-        }
+    fn enqueue_broadcast_to(
+        &self,
+        targets: Vec<u64>,
+        action: Box<dyn Fn(&mut server::Connection<A, Self>) + Send + Sync>,
+    ) {
+        self.enqueue(Broadcast {
+            targets: Some(targets),
+            action,
+        });
     }
 
     fn enqueue_message<M: msg::EncodeMessage>(
@@ -315,89 +1229,905 @@ impl<A: server::Application> server::Dispatch<A> for Dispatch<A> {
         conn: &mut server::Connection<A, Self>,
         msg: &M,
     ) {
-        if !conn.state().can_receive_messages() {
-            panic!(
-                "enqueue_message() called on connection in state {}",
-                conn.state().type_name()
-            );
+        let size = msg.encoded_size();
+        //Calling this in the wrong state is a bug in the caller. We want to catch it during
+        //development, but in production, one buggy handler should not take down the server. The
+        //state is checked (and the drop reported) before any of our own locks are taken, so a
+        //failed assertion does not poison them.
+        let can_receive = self.0.check_can_receive_messages(conn, size);
+        debug_assert!(
+            can_receive,
+            "enqueue_message() called on connection in state {}",
+            conn.state().type_name()
+        );
+        if !can_receive {
+            return;
+        }
+
+        //To keep the messages for each connection in FIFO order, a message that is not sent by a
+        //broadcast must not overtake the broadcasts that are still waiting to be executed (e.g.
+        //because they were enqueued while the caller was holding the lock on `conn`, or because
+        //another thread is executing them right now). So it waits in line behind them, and is
+        //sent when they are executed. (We do not check whether the broadcasts concern this
+        //connection at all, since the queue is going to be worked off soon anyway.)
+        if self.0.bc_target.load(Ordering::SeqCst) != conn.id() {
+            let mut bc_queue = self.0.bc_queue.lock().unwrap();
+            if !bc_queue.is_empty() || self.0.bc_running.load(Ordering::SeqCst) {
+                let mut buf = vec![0u8; size];
+                match msg.encode(&mut buf) {
+                    Ok(len) => buf.truncate(len),
+                    Err(_) => {
+                        std::mem::drop(bc_queue);
+                        let reason = server::DropReason::MessageTooLong;
+                        self.0.report_dropped_message(conn, size, reason);
+                        return;
+                    }
+                }
+                let deferred = server::PreEncodedMessage(buf);
+                bc_queue.push(Broadcast {
+                    targets: Some(vec![conn.id()]),
+                    action: Box::new(move |conn| conn.dispatch().enqueue_message(conn, &deferred)),
+                });
+                return;
+            }
         }
 
         //NOTE: The mutability of `conn` is only used to enforce that the current thread holds the
-        //`self.0.pool` write lock, cf. comment on declaration of `struct InnerDispatch`.
+        //lock on `conn`, cf. comment on declaration of `struct InnerDispatch`.
         let mut tx = self.0.tx.write().unwrap();
-        let connector = match tx.get_mut(&conn.id()) {
-            Some(c) => c,
-            //`None` should not happen, since the `inner.pool` and `inner.tx` entries are deleted
-            //the same time, but if it's missing, we're in teardown anyway
-            None => return,
-        };
+        //a missing entry should not happen, since the `inner.pool` and `inner.tx` entries are
+        //deleted the same time, but if it's missing, we're in teardown anyway
+        if !tx.contains_key(&conn.id()) {
+            return;
+        }
+        if !self.0.reserve_send_buffer(&mut tx, conn, size) {
+            return;
+        }
+        let connector = tx.get_mut(&conn.id()).unwrap();
 
-        //try to fit the message into the current send buffer (the last one in line that already
-        //contains some data)
-        let mut enqueued = false;
-        let filled_bufs = connector.bufs.iter_mut().filter(|b| b.filled_len() > 0);
-        if let Some(send_buffer) = filled_bufs.last() {
-            enqueued = send_buffer.fill_if_ok(|buf| msg.encode(buf)).is_ok();
-        }
-
-        //if it doesn't work, try to fit the message into the send buffer directly following that
-        //one (the first one that does not have any data in it)
-        if !enqueued {
-            let send_buffer = match connector.bufs.iter_mut().find(|b| b.filled_len() == 0) {
-                Some(b) => b,
-                None => {
-                    connector.bufs.push(Default::default());
-                    connector.bufs.last_mut().unwrap()
-                }
-            };
-            //if the fill_if_ok() errors out this time, it's because the rendered message is
-            //legimitately too long, so it's a good time to panic
-            send_buffer.fill_if_ok(|buf| msg.encode(buf)).unwrap();
+        //if this errors out, it's because the rendered message is legitimately too long, so we
+        //give the reserved space back and drop it (this is a bug in the caller, but not one that
+        //is worth taking down the entire server for)
+        if connector.control.push_message(msg).is_err() {
+            std::mem::drop(tx);
+            self.0.send_buffer_usage.fetch_sub(size, Ordering::SeqCst);
+            self.0
+                .report_dropped_message(conn, size, server::DropReason::MessageTooLong);
+            return;
         }
 
+        self.0.check_watermarks(connector, conn);
         //wake up the transmitter job if necessary
         connector.notify.notify_one();
     }
 
-    fn enqueue_stdin(&self, conn: &mut server::Connection<A, Self>, mut input: &[u8]) {
-        if !conn.state().can_receive_stdin() {
-            panic!(
-                "enqueue_stdin() called on connection in state {}",
-                conn.state().type_name()
-            );
+    fn enqueue_stdin(&self, conn: &mut server::Connection<A, Self>, input: &[u8]) {
+        //same as in enqueue_message()
+        let can_receive = self.0.check_can_receive_stdin(conn, input.len());
+        debug_assert!(
+            can_receive,
+            "enqueue_stdin() called on connection in state {}",
+            conn.state().type_name()
+        );
+        if !can_receive {
+            return;
         }
 
         //NOTE: The mutability of `conn` is only used to enforce that the current thread holds the
-        //`self.0.pool` write lock, cf. comment on declaration of `struct InnerDispatch`.
+        //lock on `conn`, cf. comment on declaration of `struct InnerDispatch`.
         let mut tx = self.0.tx.write().unwrap();
-        let connector = match tx.get_mut(&conn.id()) {
-            Some(c) => c,
-            //`None` should not happen, since the `inner.pool` and `inner.tx` entries are deleted
-            //the same time, but if it's missing, we're in teardown anyway
-            None => return,
-        };
+        //a missing entry should not happen, since the `inner.pool` and `inner.tx` entries are
+        //deleted the same time, but if it's missing, we're in teardown anyway
+        if !tx.contains_key(&conn.id()) {
+            return;
+        }
+        if !self.0.reserve_send_buffer(&mut tx, conn, input.len()) {
+            return;
+        }
+        let connector = tx.get_mut(&conn.id()).unwrap();
+
+        connector.bulk.push_bytes(input);
+
+        self.0.check_watermarks(connector, conn);
+        //wake up the transmitter job if necessary
+        connector.notify.notify_one();
+    }
+}
 
-        //try to fit data into the current send buffer (the last one in line that already contains
-        //some data)
-        let filled_bufs = connector.bufs.iter_mut().filter(|b| b.filled_len() > 0);
-        if let Some(send_buffer) = filled_bufs.last() {
-            input = send_buffer.fill_until_full(input);
-        }
-
-        //if that's not enough, fill the free send buffers directly following that one in order
-        while !input.is_empty() {
-            let send_buffer = match connector.bufs.iter_mut().find(|b| b.filled_len() == 0) {
-                Some(b) => b,
-                None => {
-                    //if there are no empty send buffers left, append a new one
-                    connector.bufs.push(Default::default());
-                    connector.bufs.last_mut().unwrap()
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::common::core::{ClientID, ModuleIdentifier, ScreenID};
+    #[cfg(feature = "module_sig")]
+    use crate::msg::sig::{Deliver, Signal};
+    use crate::server::testing::MockApplication;
+    use crate::server::Dispatch as _;
+    use tokio::io::AsyncReadExt;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    //Creates a connection without a client socket. We do not spawn the transmitter jobs, so
+    //everything that is enqueued on the connection stays in the send buffers.
+    fn add_connection(dispatch: &Dispatch<MockApplication>) -> u64 {
+        dispatch.0.create_connection_object(None, None).0
+    }
+
+    fn socket_path(name: &str) -> std::path::PathBuf {
+        let file_name = format!("vt6-test-{}-{}.sock", std::process::id(), name);
+        let path = std::env::temp_dir().join(file_name);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    #[cfg(feature = "module_sig")]
+    fn test_shutdown_flushes_enqueued_messages() {
+        let path = socket_path("shutdown");
+        runtime().block_on(async {
+            let app: MockApplication = MockApplication::new();
+            let dispatch = Dispatch::new(&path, app).unwrap();
+            let listener = {
+                let dispatch = dispatch.clone();
+                tokio::spawn(async move { dispatch.run_listener().await })
+            };
+            while !path.exists() {
+                tokio::task::yield_now().await;
+            }
+            let mut client = tokio::net::UnixStream::connect(&path).await.unwrap();
+            while dispatch.connection_stats().is_empty() {
+                tokio::task::yield_now().await;
+            }
+
+            //enqueue much more than fits into the socket buffer, so that the transmitter is still
+            //busy when the shutdown begins
+            let msg = Deliver {
+                signal: Signal::Interrupt,
+            };
+            let mut expected = Vec::new();
+            let mut encoded = [0u8; 64];
+            let len = msg::EncodeMessage::encode(&msg, &mut encoded).unwrap();
+            for _ in 0..50000 {
+                expected.extend_from_slice(&encoded[0..len]);
+            }
+            dispatch.enqueue_broadcast(Box::new(move |conn| {
+                for _ in 0..50000 {
+                    conn.enqueue_message(&msg);
                 }
+            }));
+            dispatch.shutdown();
+
+            //the client receives everything, followed by EOF
+            let mut received = Vec::new();
+            client.read_to_end(&mut received).await.unwrap();
+            assert_eq!(received.len(), expected.len());
+            assert!(received == expected);
+
+            listener.await.unwrap().unwrap();
+            assert!(dispatch.connection_stats().is_empty());
+            assert!(!path.exists());
+        });
+    }
+
+    #[test]
+    fn test_query_connections() {
+        let path = socket_path("query");
+        runtime().block_on(async {
+            let app: MockApplication = MockApplication::new();
+            let dispatch = Dispatch::new(&path, app).unwrap();
+            let listener = {
+                let dispatch = dispatch.clone();
+                tokio::spawn(async move { dispatch.run_listener().await })
             };
-            input = send_buffer.fill_until_full(input);
+            while !path.exists() {
+                tokio::task::yield_now().await;
+            }
+            let _client1 = tokio::net::UnixStream::connect(&path).await.unwrap();
+            let _client2 = tokio::net::UnixStream::connect(&path).await.unwrap();
+            while dispatch.connection_stats().len() < 2 {
+                tokio::task::yield_now().await;
+            }
+
+            //with no other job holding the connection pool, the query runs synchronously
+            let query = dispatch.query_connections(|conn| Some(conn.state().type_name()));
+            assert!(query.is_complete());
+            let results = query.try_wait().ok().unwrap();
+            assert_eq!(results, vec!["Handshake", "Handshake"]);
+
+            //connections for which the closure returns None are skipped
+            let query = dispatch.query_connections(|conn| {
+                Some(conn.state().type_name()).filter(|&name| name == "Msgio")
+            });
+            assert!(query.wait().is_empty());
+
+            dispatch.shutdown();
+            listener.await.unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_pause_reading() {
+        let path = socket_path("pause");
+        runtime().block_on(async {
+            let app: MockApplication = MockApplication::new();
+            let dispatch = Dispatch::new(&path, app).unwrap();
+            let listener = {
+                let dispatch = dispatch.clone();
+                tokio::spawn(async move { dispatch.run_listener().await })
+            };
+            while !path.exists() {
+                tokio::task::yield_now().await;
+            }
+            let mut client = tokio::net::UnixStream::connect(&path).await.unwrap();
+            while dispatch.connection_stats().is_empty() {
+                tokio::task::yield_now().await;
+            }
+            let bytes_received = || dispatch.connection_stats()[0].2.bytes_received;
+
+            //while reading is paused, input stays in the socket
+            use tokio::io::AsyncWriteExt;
+            dispatch.enqueue_broadcast(Box::new(|conn| conn.pause_reading()));
+            client.write_all(b"{1|").await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(bytes_received(), 0);
+
+            //after resuming, it is read
+            dispatch.enqueue_broadcast(Box::new(|conn| conn.resume_reading()));
+            while bytes_received() == 0 {
+                tokio::task::yield_now().await;
+            }
+            assert_eq!(bytes_received(), 3);
+
+            dispatch.shutdown();
+            listener.await.unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_tx_priorities() {
+        let mut tx = TxConnector::default();
+        let msg = crate::msg::Want(ModuleIdentifier::parse("core1").unwrap());
+
+        //messages overtake bulk data that was enqueued earlier
+        tx.bulk.push_bytes(&[b'x'; 5000]);
+        tx.control.push_message(&msg).unwrap();
+        let buf = tx.pop().unwrap();
+        assert_eq!(buf.filled(), b"{2|4:want,5:core1,}");
+        tx.recycle(buf);
+
+        //bulk data is sent in order, and a message enqueued in between goes first
+        let buf = tx.pop().unwrap();
+        assert!(buf.filled().iter().all(|&b| b == b'x'));
+        let first_len = buf.filled_len();
+        tx.control.push_message(&msg).unwrap();
+        tx.recycle(buf);
+        let buf = tx.pop().unwrap();
+        assert_eq!(buf.filled(), b"{2|4:want,5:core1,}");
+        tx.recycle(buf);
+        let buf = tx.pop().unwrap();
+        assert_eq!(first_len + buf.filled_len(), 5000);
+        tx.recycle(buf);
+        assert!(tx.pop().is_none());
+    }
+
+    #[test]
+    fn test_send_buffer_limit() {
+        use server::SendBufferPolicy::*;
+        let msg = crate::msg::Want(ModuleIdentifier::parse("core1").unwrap());
+        let screen =
+            server::ScreenIdentity::new(&crate::common::core::ScreenID::parse("s").unwrap());
+
+        for (idx, &policy) in [RejectNew, DropBulk, CloseMostBacklogged]
+            .iter()
+            .enumerate()
+        {
+            let app: MockApplication = MockApplication::new();
+            let path = socket_path(&format!("sendbuf{}", idx));
+            let dispatch = Dispatch::builder(&path, app.clone())
+                .send_buffer_limit(50, policy)
+                .build()
+                .unwrap();
+            let stdin_id = add_connection(&dispatch);
+            let msgio_id = add_connection(&dispatch);
+            dispatch.0.with_connection(stdin_id, |conn| {
+                conn.set_state(server::ConnectionState::Stdin(screen.clone()));
+                conn.enqueue_stdin(&[b'x'; 40]);
+            });
+            assert_eq!(dispatch.send_buffer_usage(), 40);
+
+            //this message (19 bytes) exceeds the limit
+            dispatch
+                .0
+                .with_connection(msgio_id, |conn| conn.enqueue_message(&msg));
+            let notifications = app.notifications();
+            let expected_accepted = policy != RejectNew;
+            assert_eq!(
+                notifications[0],
+                format!(
+                    "cannot enqueue 19 bytes with 40 bytes already queued (limit is 50 bytes), applied policy {:?}, new data was {}",
+                    policy,
+                    if expected_accepted { "enqueued" } else { "discarded" },
+                )
+            );
+            let stats = dispatch.connection_stats();
+            match policy {
+                RejectNew => {
+                    assert_eq!(dispatch.send_buffer_usage(), 40);
+                    assert_eq!(stats.len(), 2);
+                }
+                DropBulk => {
+                    assert_eq!(dispatch.send_buffer_usage(), 19);
+                    assert_eq!(stats.len(), 2);
+                }
+                CloseMostBacklogged => {
+                    assert_eq!(dispatch.send_buffer_usage(), 19);
+                    assert_eq!(
+                        notifications[1],
+                        "client connection closed (send buffer limit exceeded)"
+                    );
+                    assert_eq!(stats.len(), 1);
+                    assert_eq!(stats[0].0, msgio_id);
+                }
+            }
+
+            //when the data is too large by itself, dropping stdin does not help
+            dispatch.0.with_connection(stdin_id, |conn| {
+                conn.enqueue_stdin(&[b'x'; 60]);
+                assert!(app.notifications().last().unwrap().ends_with("discarded"));
+            });
         }
+    }
 
-        //wake up the transmitter job if necessary
-        connector.notify.notify_one();
+    #[test]
+    fn test_send_buffer_watermarks() {
+        let screen =
+            server::ScreenIdentity::new(&crate::common::core::ScreenID::parse("s").unwrap());
+        let app: MockApplication = MockApplication::new();
+        let dispatch = Dispatch::builder(socket_path("watermarks"), app.clone())
+            .send_buffer_watermarks(100, 20)
+            .build()
+            .unwrap();
+        let id = add_connection(&dispatch);
+        dispatch.0.with_connection(id, |conn| {
+            conn.set_state(server::ConnectionState::Stdin(screen));
+
+            conn.enqueue_stdin(&[b'x'; 60]);
+            assert!(app.notifications().is_empty());
+            conn.enqueue_stdin(&[b'x'; 60]);
+            conn.enqueue_stdin(&[b'x'; 60]);
+            assert!(conn.stats().send_backlog_since.is_some());
+            assert_eq!(
+                app.notifications(),
+                vec!["client is not keeping up with reading: 120 bytes queued (high watermark is 100 bytes)"]
+            );
+
+            //the state only flips back once everything down to the low watermark has been sent
+            let mut buf = None;
+            loop {
+                buf = dispatch.0.swap_send_buffer(conn, buf);
+                if buf.is_none() {
+                    break;
+                }
+            }
+            assert!(conn.stats().send_backlog_since.is_none());
+            assert_eq!(
+                app.notifications()[1..],
+                ["client has caught up with reading: 0 bytes queued (low watermark is 20 bytes)"]
+            );
+        });
+    }
+
+    #[test]
+    fn test_dropped_messages() {
+        struct Overlong;
+        impl msg::EncodeMessage for Overlong {
+            fn encode(&self, buf: &mut [u8]) -> Result<usize, msg::BufferTooSmallError> {
+                let mut f = msg::MessageFormatter::new(buf, "foo.bar", 500);
+                for _ in 0..500 {
+                    f.add_argument(&0);
+                }
+                f.finalize()
+            }
+        }
+
+        let app: MockApplication = MockApplication::new();
+        let dispatch = Dispatch::new(socket_path("dropped"), app.clone()).unwrap();
+        let id = add_connection(&dispatch);
+        dispatch
+            .0
+            .with_connection(id, |conn| conn.enqueue_message(&Overlong));
+        let mut expected = vec!["dropped message of 2016 bytes for client: message too long"];
+
+        //enqueueing in the wrong state is dropped (in debug builds, enqueue_message() and
+        //enqueue_stdin() additionally panic, see below)
+        let screen_id = crate::common::core::ScreenID::parse("s").unwrap();
+        let screen = server::ScreenIdentity::new(&screen_id);
+        dispatch.0.with_connection(id, |conn| {
+            assert!(!dispatch.0.check_can_receive_stdin(conn, 5));
+            assert!(dispatch.0.check_can_receive_messages(conn, 19));
+            conn.set_state(server::ConnectionState::Stdin(screen));
+            assert!(!dispatch.0.check_can_receive_messages(conn, 19));
+            assert!(dispatch.0.check_can_receive_stdin(conn, 5));
+        });
+        expected.push("dropped 5 bytes of stdin for client: connection is in state Handshake");
+        expected.push("dropped message of 19 bytes for client: connection is in state Stdin");
+
+        //nothing was enqueued, but the connection survives
+        assert_eq!(dispatch.send_buffer_usage(), 0);
+        assert_eq!(dispatch.connection_stats().len(), 1);
+        assert_eq!(app.notifications(), expected);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_enqueue_in_invalid_state() {
+        let app: MockApplication = MockApplication::new();
+        let dispatch = Dispatch::new(socket_path("invalid"), app.clone()).unwrap();
+        let id1 = add_connection(&dispatch);
+        let id2 = add_connection(&dispatch);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            dispatch
+                .0
+                .with_connection(id1, |conn| conn.enqueue_stdin(b"hello"));
+        }));
+        let err = result.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<String>().map(String::as_str),
+            Some("enqueue_stdin() called on connection in state Handshake")
+        );
+        //the drop was reported before the assertion failed
+        assert_eq!(
+            app.notifications(),
+            vec!["dropped 5 bytes of stdin for client: connection is in state Handshake"]
+        );
+
+        //the connection that panicked is torn down, but the rest of the dispatch continues to
+        //work
+        assert_eq!(dispatch.0.conns_in_use.load(Ordering::SeqCst), 0);
+        assert!(dispatch.0.with_connection(id1, |_| ()).is_none());
+        let msg = crate::msg::Want(ModuleIdentifier::parse("core1").unwrap());
+        dispatch.enqueue_broadcast_to(vec![id2], Box::new(move |conn| conn.enqueue_message(&msg)));
+        assert_eq!(dispatch.send_buffer_usage(), 19);
+    }
+
+    #[test]
+    fn test_teardown_after_panic() {
+        let app: MockApplication = MockApplication::new();
+        let screen = ScreenID::parse("1").unwrap();
+        app.add_screen(&screen);
+        let identity = server::ScreenIdentity::new(&screen);
+        let dispatch = Dispatch::builder(socket_path("panic"), app.clone())
+            .max_connections(1)
+            .build()
+            .unwrap();
+        let id = add_connection(&dispatch);
+        let state = server::ConnectionState::Stdin(identity.clone());
+        dispatch.0.with_connection(id, |conn| conn.set_state(state));
+        assert_eq!(
+            dispatch
+                .attachments()
+                .connection(&identity, server::AttachmentKind::Stdin),
+            Some(id)
+        );
+        //the connection limit is exhausted
+        assert!(!dispatch.0.accept_connection(None, None));
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            dispatch.0.with_connection(id, |_| panic!("oops"));
+        }));
+        assert!(result.is_err());
+
+        //the next visit finds the poisoned lock and tears the connection down
+        assert!(dispatch.0.with_connection(id, |_| ()).is_none());
+        assert!(dispatch.0.entry(id).is_none());
+        assert!(!dispatch.0.tx.read().unwrap().contains_key(&id));
+        assert_eq!(
+            dispatch
+                .attachments()
+                .connection(&identity, server::AttachmentKind::Stdin),
+            None
+        );
+        assert_eq!(
+            app.notifications().last().map(String::as_str),
+            Some("client connection closed (panic while processing connection)")
+        );
+        assert_eq!(dispatch.0.conns_in_use.load(Ordering::SeqCst), 0);
+        //the connection does not count towards the limit anymore
+        assert!(dispatch.0.accept_connection(None, None));
+    }
+
+    #[test]
+    fn test_teardown_reasons() {
+        let path = socket_path("teardown");
+        runtime().block_on(async {
+            use tokio::io::AsyncWriteExt;
+            let app: MockApplication = MockApplication::new();
+            let dispatch = Dispatch::new(&path, app.clone()).unwrap();
+            let listener = {
+                let dispatch = dispatch.clone();
+                tokio::spawn(async move { dispatch.run_listener().await })
+            };
+            while !path.exists() {
+                tokio::task::yield_now().await;
+            }
+            let connect = || async {
+                let count = dispatch.connection_stats().len();
+                let client = tokio::net::UnixStream::connect(&path).await.unwrap();
+                while dispatch.connection_stats().len() == count {
+                    tokio::task::yield_now().await;
+                }
+                client
+            };
+            let closed = || {
+                app.notifications()
+                    .into_iter()
+                    .filter(|n| n.starts_with("client connection closed"))
+                    .collect::<Vec<_>>()
+            };
+            //returns the n-th ConnectionClosed notification, once it has been sent
+            let wait_for_close = |n: usize| async move {
+                while closed().len() < n {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                closed().remove(n - 1)
+            };
+
+            //the client disconnects
+            let client = connect().await;
+            drop(client);
+            assert_eq!(
+                wait_for_close(1).await,
+                "client connection closed (client disconnected)"
+            );
+
+            //the socket fails
+            let _client = connect().await;
+            let id = dispatch.connection_stats()[0].0;
+            let error = std::io::Error::other("broken socket");
+            dispatch.0.handle_io_error(id, error);
+            assert_eq!(
+                wait_for_close(2).await,
+                "client connection closed (IO error)"
+            );
+
+            //the handshake handler refuses a message that is not a handshake
+            let mut client = connect().await;
+            client.write_all(b"{2|4:want,5:core1,}").await.unwrap();
+            assert_eq!(
+                wait_for_close(3).await,
+                "client connection closed (protocol violation)"
+            );
+            let mut received = Vec::new();
+            client.read_to_end(&mut received).await.unwrap();
+
+            //the core1 handler tears down the connection of a client whose lifetime was ended by
+            //its parent
+            let hello = |id: &str| {
+                let identity = server::ClientIdentity::new(&ClientID::parse(id).unwrap());
+                let creds = server::Application::register_client(&app, identity);
+                let hello = crate::msg::posix::ClientHello {
+                    secret: creds.secret(),
+                };
+                let mut buf = vec![0u8; 256];
+                let len = msg::EncodeMessage::encode(&hello, &mut buf).unwrap();
+                buf.truncate(len);
+                buf
+            };
+            let (parent_hello, child_hello) = (hello("b"), hello("b1"));
+            let mut parent = connect().await;
+            parent.write_all(&parent_hello).await.unwrap();
+            let mut child = connect().await;
+            child.write_all(&child_hello).await.unwrap();
+            while dispatch
+                .connection_stats()
+                .iter()
+                .any(|(_, state, _)| *state != "Msgio")
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            parent
+                .write_all(b"{2|16:core1.client-end,2:b1,}")
+                .await
+                .unwrap();
+            assert_eq!(
+                wait_for_close(4).await,
+                "client connection closed (client lifetime ended)"
+            );
+            let mut received = Vec::new();
+            child.read_to_end(&mut received).await.unwrap();
+
+            dispatch.shutdown();
+            listener.await.unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_message_ordering() {
+        fn want(name: &'static str) -> crate::msg::Want<'static> {
+            crate::msg::Want(ModuleIdentifier::parse(name).unwrap())
+        }
+
+        let app: MockApplication = MockApplication::new();
+        let dispatch = Dispatch::new(socket_path("ordering"), app).unwrap();
+        let id = add_connection(&dispatch);
+        let sent = || {
+            let mut tx = dispatch.0.tx.write().unwrap();
+            let connector = tx.get_mut(&id).unwrap();
+            let mut result = Vec::new();
+            while let Some(buf) = connector.pop() {
+                result.extend_from_slice(buf.filled());
+                connector.recycle(buf);
+            }
+            String::from_utf8(result).unwrap()
+        };
+
+        //while a handler is running, broadcasts cannot be executed, but messages enqueued by the
+        //handler afterwards still do not overtake them
+        dispatch.0.with_connection(id, |conn| {
+            dispatch.enqueue_broadcast(Box::new(|conn| conn.enqueue_message(&want("aaa1"))));
+            dispatch.enqueue_broadcast_to(
+                vec![id],
+                Box::new(|conn| conn.enqueue_message(&want("bbb1"))),
+            );
+            conn.enqueue_message(&want("ccc1"));
+        });
+        assert_eq!(
+            sent(),
+            "{2|4:want,4:aaa1,}{2|4:want,4:bbb1,}{2|4:want,4:ccc1,}"
+        );
+
+        //messages sent by a broadcast go out right away, and broadcasts enqueued by a broadcast
+        //are executed afterwards
+        let d = dispatch.clone();
+        dispatch.enqueue_broadcast(Box::new(move |conn| {
+            conn.enqueue_message(&want("aaa1"));
+            d.enqueue_broadcast(Box::new(|conn| conn.enqueue_message(&want("ccc1"))));
+            conn.enqueue_message(&want("bbb1"));
+        }));
+        assert_eq!(
+            sent(),
+            "{2|4:want,4:aaa1,}{2|4:want,4:bbb1,}{2|4:want,4:ccc1,}"
+        );
+
+        //when nothing is waiting in the queue, messages are sent right away
+        dispatch.0.with_connection(id, |conn| {
+            conn.enqueue_message(&want("aaa1"));
+            assert_eq!(sent(), "{2|4:want,4:aaa1,}");
+        });
+    }
+
+    #[test]
+    fn test_independent_connections() {
+        let app: MockApplication = MockApplication::new();
+        let dispatch = Dispatch::new(socket_path("independent"), app).unwrap();
+        let id1 = add_connection(&dispatch);
+        let id2 = add_connection(&dispatch);
+
+        //while one thread is working on a connection, another thread can work on a different one
+        let (tx, rx) = std::sync::mpsc::channel();
+        let d = dispatch.clone();
+        let worker = std::thread::spawn(move || {
+            d.0.with_connection(id2, |_| tx.send(()).unwrap());
+        });
+        dispatch.0.with_connection(id1, |_| {
+            rx.recv_timeout(Duration::from_secs(5))
+                .expect("connections were not worked on independently");
+        });
+        worker.join().unwrap();
+
+        //a broadcast that is enqueued by another thread in the meantime is executed once the
+        //connection is released
+        dispatch.0.with_connection(id1, |_| {
+            let d = dispatch.clone();
+            std::thread::spawn(move || {
+                d.enqueue_broadcast(Box::new(|conn| {
+                    conn.enqueue_message(&crate::msg::Want(
+                        ModuleIdentifier::parse("core1").unwrap(),
+                    ))
+                }));
+            })
+            .join()
+            .unwrap();
+            assert_eq!(dispatch.send_buffer_usage(), 0);
+        });
+        assert_eq!(dispatch.send_buffer_usage(), 38);
+    }
+
+    #[test]
+    fn test_write_timeout() {
+        let path = socket_path("writetimeout");
+        runtime().block_on(async {
+            let app: MockApplication = MockApplication::new();
+            let dispatch = Dispatch::builder(&path, app.clone())
+                .write_timeout(Duration::from_millis(50), WriteTimeoutAction::Teardown)
+                .build()
+                .unwrap();
+            let listener = {
+                let dispatch = dispatch.clone();
+                tokio::spawn(async move { dispatch.run_listener().await })
+            };
+            while !path.exists() {
+                tokio::task::yield_now().await;
+            }
+            //this client never reads
+            let _client = tokio::net::UnixStream::connect(&path).await.unwrap();
+            while dispatch.connection_stats().is_empty() {
+                tokio::task::yield_now().await;
+            }
+
+            //enqueue much more than fits into the socket buffer
+            dispatch.enqueue_broadcast(Box::new(|conn| {
+                let msg = crate::msg::Want(ModuleIdentifier::parse("core1").unwrap());
+                for _ in 0..100000 {
+                    conn.enqueue_message(&msg);
+                }
+            }));
+            while !dispatch.connection_stats().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            let notifications = app.notifications();
+            let timeout = notifications
+                .iter()
+                .find(|n| n.starts_with("client did not read for 50ms with "))
+                .unwrap();
+            assert!(timeout.ends_with(" bytes still queued, closing connection"));
+
+            dispatch.shutdown();
+            listener.await.unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_multiple_listeners() {
+        let paths: Vec<_> = ["main", "early", "late"]
+            .iter()
+            .map(|name| socket_path(name))
+            .collect();
+        runtime().block_on(async {
+            let app: MockApplication = MockApplication::new();
+            let dispatch = Dispatch::new(&paths[0], app.clone()).unwrap();
+            dispatch.add_listener(&paths[1], "early").unwrap();
+            let listener = {
+                let dispatch = dispatch.clone();
+                tokio::spawn(async move { dispatch.run_listener().await })
+            };
+            dispatch.add_listener(&paths[2], "late").unwrap();
+
+            //connect to each listener once
+            let mut clients = Vec::new();
+            for path in &paths {
+                clients.push(tokio::net::UnixStream::connect(path).await.unwrap());
+            }
+            while dispatch.connection_stats().len() < 3 {
+                tokio::task::yield_now().await;
+            }
+
+            //all connections are in the same pool, and know which listener they came from
+            let labels = Arc::new(Mutex::new(Vec::new()));
+            let labels_ref = labels.clone();
+            dispatch.enqueue_broadcast(Box::new(move |conn| {
+                let label = conn.listener().map(String::from);
+                labels_ref.lock().unwrap().push(label);
+            }));
+            let mut labels = labels.lock().unwrap().clone();
+            labels.sort();
+            assert_eq!(
+                labels,
+                vec![None, Some("early".to_string()), Some("late".to_string())]
+            );
+            assert!(app
+                .notifications()
+                .contains(&"[late] client connection opened".to_string()));
+
+            //shutdown cleans up all server sockets
+            dispatch.shutdown();
+            listener.await.unwrap().unwrap();
+            for path in &paths {
+                assert!(!path.exists());
+            }
+            assert!(dispatch.add_listener(&paths[1], "too late").is_err());
+        });
+    }
+
+    #[test]
+    fn test_accept_policy() {
+        let paths = [socket_path("accept"), socket_path("accept-refused")];
+        runtime().block_on(async {
+            let app: MockApplication = MockApplication::new();
+            let dispatch = Dispatch::builder(&paths[0], app.clone())
+                .max_connections(2)
+                .build()
+                .unwrap();
+            app.refuse_listener("refused");
+            dispatch.add_listener(&paths[1], "refused").unwrap();
+            let listener = {
+                let dispatch = dispatch.clone();
+                tokio::spawn(async move { dispatch.run_listener().await })
+            };
+
+            //refused connections are closed without being read from
+            async fn expect_refused(path: &std::path::Path) {
+                let mut stream = tokio::net::UnixStream::connect(path).await.unwrap();
+                let mut buf = [0u8; 1];
+                assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+            }
+            expect_refused(&paths[1]).await;
+
+            let mut clients = Vec::new();
+            for _ in 0..2 {
+                clients.push(tokio::net::UnixStream::connect(&paths[0]).await.unwrap());
+            }
+            while dispatch.connection_stats().len() < 2 {
+                tokio::task::yield_now().await;
+            }
+            expect_refused(&paths[0]).await;
+            assert_eq!(dispatch.connection_stats().len(), 2);
+
+            //once a connection is closed, there is room for another one
+            std::mem::drop(clients.pop());
+            while dispatch.connection_stats().len() > 1 {
+                tokio::task::yield_now().await;
+            }
+            clients.push(tokio::net::UnixStream::connect(&paths[0]).await.unwrap());
+            while dispatch.connection_stats().len() < 2 {
+                tokio::task::yield_now().await;
+            }
+
+            let notifications = app.notifications();
+            let refusals: Vec<_> = notifications
+                .iter()
+                .filter(|n| n.contains("refused"))
+                .collect();
+            assert_eq!(
+                refusals,
+                vec![
+                    "[refused] client connection refused (refused by application)",
+                    "client connection refused (too many connections, limit is 2)",
+                ]
+            );
+
+            dispatch.shutdown();
+            listener.await.unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_peer_credentials() {
+        let path = socket_path("peercred");
+        runtime().block_on(async {
+            let app: MockApplication = MockApplication::new();
+            let dispatch = Dispatch::new(&path, app).unwrap();
+            let listener = {
+                let dispatch = dispatch.clone();
+                tokio::spawn(async move { dispatch.run_listener().await })
+            };
+            while !path.exists() {
+                tokio::task::yield_now().await;
+            }
+            let _client = tokio::net::UnixStream::connect(&path).await.unwrap();
+            while dispatch.connection_stats().is_empty() {
+                tokio::task::yield_now().await;
+            }
+
+            //the client is this very process
+            let creds = Arc::new(Mutex::new(None));
+            let creds_ref = creds.clone();
+            dispatch.enqueue_broadcast(Box::new(move |conn| {
+                *creds_ref.lock().unwrap() = conn.peer_credentials().copied();
+            }));
+            let creds = creds.lock().unwrap().expect("no peer credentials captured");
+            assert_eq!(creds.uid, unsafe { libc::getuid() });
+            assert_eq!(creds.gid, unsafe { libc::getgid() });
+            if let Some(pid) = creds.pid {
+                assert_eq!(pid as u32, std::process::id());
+            }
+
+            dispatch.shutdown();
+            listener.await.unwrap().unwrap();
+        });
     }
 }
@@ -8,5 +8,9 @@ mod dispatch;
 pub use dispatch::*;
 mod receiver;
 pub(crate) use receiver::*;
+#[cfg(feature = "simulation")]
+mod simulation;
+#[cfg(feature = "simulation")]
+pub use simulation::*;
 mod transmitter;
 pub(crate) use transmitter::*;
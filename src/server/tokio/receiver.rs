@@ -6,9 +6,11 @@
 
 use crate::server;
 use crate::server::tokio as my;
-use futures::future::{AbortRegistration, Abortable};
+use futures::future::{AbortRegistration, Abortable, Either};
 use std::sync::Arc;
 use tokio::io::AsyncReadExt;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
 
 impl server::ReceiveBuffer for bytes::BytesMut {
     fn contents(&self) -> &[u8] {
@@ -25,42 +27,88 @@ impl server::ReceiveBuffer for bytes::BytesMut {
     }
 }
 
-pub(crate) fn spawn_receiver<A: server::Application>(
+pub(crate) fn spawn_receiver<A, R>(
     dispatch: Arc<my::InnerDispatch<A>>,
     abort_reg: AbortRegistration,
     conn_id: u64,
-    mut reader: tokio::net::unix::OwnedReadHalf,
-) {
+    mut reader: R,
+    pause_changed: Arc<Notify>,
+) -> JoinHandle<()>
+where
+    A: server::Application,
+    R: tokio::io::AsyncRead + Unpin + Send + 'static,
+{
+    let mut shutdown = dispatch.shutdown_signal();
     let job = async move {
         let mut buf = bytes::BytesMut::with_capacity(1024);
         loop {
-            //attempt to fill the buffer
-            let bytes_read = match reader.read_buf(&mut buf).await {
+            //the Notified future is created before checking whether reading is paused, so that a
+            //change cannot slip through in between
+            let pause_changed = pause_changed.notified();
+            let stop = shutdown.wait_for(|&is_shutdown| is_shutdown);
+            futures::pin_mut!(pause_changed, stop);
+
+            //while reading is paused, leave the input in the socket buffer to apply backpressure
+            //on the client
+            if dispatch.is_reading_paused(conn_id) {
+                match futures::future::select(pause_changed, stop).await {
+                    Either::Left(_) => continue,
+                    Either::Right(_) => return,
+                }
+            }
+
+            //attempt to fill the buffer, unless the dispatch is shutting down or reading gets
+            //paused (read_buf() is cancellation-safe, so no input is lost by racing it against
+            //these signals)
+            let read = reader.read_buf(&mut buf);
+            futures::pin_mut!(read);
+            let interrupt = futures::future::select(stop, pause_changed);
+            let result = match futures::future::select(read, interrupt).await {
+                Either::Left((result, _)) => result,
+                Either::Right((Either::Left(_), _)) => return,
+                Either::Right((Either::Right(_), _)) => continue,
+            };
+            let bytes_read = match result {
                 Err(e) => {
-                    let n = server::Notification::ConnectionIOError(e.into());
-                    dispatch.app.notify(&n);
-                    if let Some(conn) = dispatch.connection_mut(conn_id).alive() {
-                        conn.set_state(server::ConnectionState::Teardown);
-                    }
+                    dispatch.handle_io_error(conn_id, e);
                     return;
                 }
                 Ok(bytes_read) => bytes_read,
             };
 
-            if buf.len() > 0 {
-                if let Some(conn) = dispatch.connection_mut(conn_id).alive() {
-                    conn.handle_incoming(&mut buf);
+            if !buf.is_empty() {
+                let mut handle = || {
+                    dispatch.with_connection(conn_id, |conn| {
+                        conn.stats_mut().bytes_received += bytes_read as u64;
+                        conn.handle_incoming(&mut buf);
+                    })
+                };
+                if dispatch.blocking_handlers && is_multi_thread_runtime() {
+                    tokio::task::block_in_place(handle);
+                } else {
+                    handle();
                 }
             }
 
             if bytes_read == 0 {
                 //EOF is reached, i.e. the client has disconnected
-                if let Some(conn) = dispatch.connection_mut(conn_id).alive() {
-                    conn.set_state(server::ConnectionState::Teardown);
-                }
+                dispatch.with_connection(conn_id, |conn| {
+                    conn.tear_down(server::TeardownReason::ClientDisconnected)
+                });
                 return;
             }
         }
     };
-    tokio::spawn(Abortable::new(job, abort_reg));
+    #[cfg(feature = "use_tracing")]
+    let job =
+        tracing::Instrument::instrument(job, tracing::debug_span!("vt6.receiver", id = conn_id));
+    tokio::spawn(async move {
+        //an abort only happens when the connection is torn down, so there is nothing to clean up
+        let _ = Abortable::new(job, abort_reg).await;
+    })
+}
+
+//block_in_place() panics on the current-thread runtime.
+fn is_multi_thread_runtime() -> bool {
+    tokio::runtime::Handle::current().runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread
 }
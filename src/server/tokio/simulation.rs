@@ -0,0 +1,458 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::server;
+use crate::server::tokio::Dispatch;
+use futures::future::Either;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{mpsc, watch};
+
+///How many bytes can be in flight in each direction of a simulated connection, similar to the
+///buffer size of a socket. When a client stops reading, the Dispatch can write this much before
+///its writes stall.
+pub const SIMULATED_BUFFER_SIZE: usize = 4096;
+
+///A [tokio Dispatch](struct.Dispatch.html) running in a deterministic simulation. This is only
+///available with the `simulation` feature, and is obtained from
+///[`DispatchBuilder::build_simulation()`](struct.DispatchBuilder.html#method.build_simulation).
+///
+///The Dispatch runs unchanged on a single-threaded Tokio runtime of its own, but there is no real
+///IO: Clients are connected through in-memory streams, all input is injected with `send()`, and
+///everything that the Dispatch writes is collected for `take_received()`. The runtime's clock is
+///paused, so time only passes when the simulation is told to `advance()` it, and timeouts fire at
+///exactly the same point in every run. The same sequence of calls on a Simulation therefore
+///always results in the same interleaving of events inside the Dispatch, which makes it possible
+///to reproduce and test race conditions that occur only rarely with real sockets.
+///
+///Nothing happens between calls: `send()` and similar methods only queue up their effects, which
+///take place once `run_until_idle()` or `advance()` is called.
+///
+///```no_run
+///# use std::time::Duration;
+///# use vt6::server::tokio::{Dispatch, WriteTimeoutAction};
+///# let app: vt6::server::testing::MockApplication = Default::default();
+///let mut sim = Dispatch::builder("unused", app)
+///    .write_timeout(Duration::from_secs(1), WriteTimeoutAction::Teardown)
+///    .build_simulation();
+///let conn_id = sim.connect(None);
+///sim.send(conn_id, b"{2|19:posix1.client-hello,6:s3cr3t,}");
+///sim.run_until_idle();
+///assert!(sim.take_received(conn_id).starts_with(b"{5|19:posix1.server-hello,"));
+///```
+///
+///Only time that is measured through Tokio is simulated. Timestamps that the
+///[Connection](../struct.Connection.html) takes through `std::time::Instant` (e.g. in
+///recordings, or for measuring handler execution time) still come from the real clock.
+pub struct Simulation<A: server::Application> {
+    runtime: tokio::runtime::Runtime,
+    dispatch: Dispatch<A>,
+    listener: tokio::task::JoinHandle<std::io::Result<()>>,
+    clients: HashMap<u64, SimulatedClient>,
+}
+
+struct SimulatedClient {
+    //`None` once the client has hung up
+    input: Option<mpsc::UnboundedSender<Vec<u8>>>,
+    output: Arc<Mutex<ClientOutput>>,
+    reading: watch::Sender<bool>,
+}
+
+#[derive(Default)]
+struct ClientOutput {
+    received: Vec<u8>,
+    //whether the client has seen EOF, i.e. the Dispatch closed the connection
+    closed: bool,
+}
+
+impl<A: server::Application> Simulation<A> {
+    pub(crate) fn new(dispatch: Dispatch<A>) -> Self {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .expect("cannot build Tokio runtime for simulation");
+        let d = dispatch.clone();
+        let listener = runtime.spawn(async move { d.run_listener().await });
+        let mut sim = Self {
+            runtime,
+            dispatch,
+            listener,
+            clients: HashMap::new(),
+        };
+        //let run_listener() get going, so that shutdown() can reach it
+        sim.run_until_idle();
+        sim
+    }
+
+    ///Returns the Dispatch that is being simulated, e.g. for enqueueing broadcasts.
+    pub fn dispatch(&self) -> &Dispatch<A> {
+        &self.dispatch
+    }
+
+    ///Connects a new client, as if it had been accepted on a server socket with the given label
+    ///(see [`Dispatch::add_listener()`](struct.Dispatch.html#method.add_listener)). Returns the
+    ///connection ID, which identifies the client in all other methods.
+    pub fn connect(&mut self, label: Option<&str>) -> u64 {
+        let _guard = self.runtime.enter();
+        let (client_stream, server_stream) = tokio::io::duplex(SIMULATED_BUFFER_SIZE);
+        let (server_reader, server_writer) = tokio::io::split(server_stream);
+        let conn_id = self
+            .dispatch
+            .connect_stream(server_reader, server_writer, label);
+
+        let (mut client_reader, mut client_writer) = tokio::io::split(client_stream);
+        let (input, mut input_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        tokio::spawn(async move {
+            while let Some(buf) = input_rx.recv().await {
+                if client_writer.write_all(&buf).await.is_err() {
+                    return;
+                }
+            }
+            //the client hung up
+            let _ = client_writer.shutdown().await;
+        });
+
+        let output = Arc::new(Mutex::new(ClientOutput::default()));
+        let (reading, mut reading_rx) = watch::channel(true);
+        let out = output.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; SIMULATED_BUFFER_SIZE];
+            loop {
+                if reading_rx.wait_for(|&r| r).await.is_err() {
+                    return;
+                }
+                let n = {
+                    let read = client_reader.read(&mut buf);
+                    let paused = reading_rx.wait_for(|&r| !r);
+                    futures::pin_mut!(read, paused);
+                    match futures::future::select(read, paused).await {
+                        Either::Left((result, _)) => result.unwrap_or(0),
+                        //read() is cancellation-safe, so no data is lost by dropping it
+                        Either::Right((Ok(_), _)) => continue,
+                        Either::Right((Err(_), _)) => return,
+                    }
+                };
+                let mut out = out.lock().unwrap();
+                if n == 0 {
+                    out.closed = true;
+                    return;
+                }
+                out.received.extend_from_slice(&buf[0..n]);
+            }
+        });
+
+        self.clients.insert(
+            conn_id,
+            SimulatedClient {
+                input: Some(input),
+                output,
+                reading,
+            },
+        );
+        conn_id
+    }
+
+    fn client(&self, conn_id: u64) -> &SimulatedClient {
+        self.clients
+            .get(&conn_id)
+            .unwrap_or_else(|| panic!("no simulated client with connection ID {}", conn_id))
+    }
+
+    ///Queues data to be sent by the given client. Nothing is sent after `hang_up()`.
+    ///
+    ///Panics if there is no client with that connection ID.
+    pub fn send(&mut self, conn_id: u64, data: &[u8]) {
+        if let Some(ref input) = self.client(conn_id).input {
+            //if the writer task is gone, the Dispatch has closed the connection, so the data would
+            //be lost anyway
+            let _ = input.send(data.to_vec());
+        }
+    }
+
+    ///Makes the given client close its end of the connection once everything it has sent so far
+    ///has been written.
+    ///
+    ///Panics if there is no client with that connection ID.
+    pub fn hang_up(&mut self, conn_id: u64) {
+        self.client(conn_id);
+        self.clients.get_mut(&conn_id).unwrap().input = None;
+    }
+
+    ///Makes the given client stop or resume reading from its connection. While it is not reading,
+    ///the Dispatch can only write up to [SIMULATED_BUFFER_SIZE](constant.SIMULATED_BUFFER_SIZE.html)
+    ///bytes before its writes stall, e.g. to trigger write timeouts.
+    ///
+    ///Panics if there is no client with that connection ID.
+    pub fn set_reading(&mut self, conn_id: u64, reading: bool) {
+        self.client(conn_id).reading.send_replace(reading);
+    }
+
+    ///Returns everything that the given client has read since the last call.
+    ///
+    ///Panics if there is no client with that connection ID.
+    pub fn take_received(&mut self, conn_id: u64) -> Vec<u8> {
+        std::mem::take(&mut self.client(conn_id).output.lock().unwrap().received)
+    }
+
+    ///Returns whether the given client has observed the Dispatch closing the connection. This
+    ///only happens while the client is reading.
+    ///
+    ///Panics if there is no client with that connection ID.
+    pub fn is_closed(&self, conn_id: u64) -> bool {
+        self.client(conn_id).output.lock().unwrap().closed
+    }
+
+    ///Runs the Dispatch and the clients until there is nothing left for them to do without time
+    ///passing.
+    ///
+    ///Since the runtime only moves its paused clock forward when all tasks are idle, this is done
+    ///by waiting for the shortest possible timer. The clock therefore moves forward by up to one
+    ///millisecond (the resolution of Tokio's timers) in the process.
+    pub fn run_until_idle(&mut self) {
+        self.runtime
+            .block_on(async { tokio::time::sleep(Duration::from_nanos(1)).await });
+    }
+
+    ///Returns the current time on the simulated clock. This is the time that the Dispatch sees,
+    ///e.g. when scheduling timeouts.
+    pub fn now(&self) -> std::time::Instant {
+        let _guard = self.runtime.enter();
+        crate::common::now()
+    }
+
+    ///Moves the clock forward by the given duration. Timeouts that expire in the meantime fire in
+    ///order, and the Dispatch and the clients run until they are idle after each of them.
+    pub fn advance(&mut self, duration: Duration) {
+        self.runtime
+            .block_on(async { tokio::time::sleep(duration).await });
+        self.run_until_idle();
+    }
+
+    ///Shuts the Dispatch down like
+    ///[`Dispatch::shutdown()`](struct.Dispatch.html#method.shutdown), and returns the result of
+    ///`run_listener()`. Clients that are not reading are disconnected, since the shutdown would
+    ///otherwise wait for them forever.
+    pub fn shutdown(self) -> std::io::Result<()> {
+        self.dispatch.shutdown();
+        for client in self.clients.values() {
+            client.reading.send_replace(true);
+        }
+        let listener = self.listener;
+        self.runtime.block_on(async move {
+            //a JoinError can only occur if run_listener() panicked
+            listener.await.expect("run_listener() panicked")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::core::{ClientID, ScreenID};
+    use crate::common::PaddedStdinDecoder;
+    use crate::server::testing::MockApplication;
+    use crate::server::tokio::WriteTimeoutAction;
+    use std::num::NonZeroU16;
+
+    //Runs a scenario where a client stops reading while the server is replying, and returns the
+    //notifications that the Application received.
+    fn run_write_timeout_scenario() -> Vec<String> {
+        let app: MockApplication = MockApplication::new();
+        let creds = server::Application::register_client(
+            &app,
+            server::ClientIdentity::new(&ClientID::parse("a").unwrap()),
+        );
+        let mut sim = Dispatch::builder("", app.clone())
+            .write_timeout(Duration::from_secs(1), WriteTimeoutAction::Teardown)
+            .build_simulation();
+
+        let conn_id = sim.connect(Some("main"));
+        let hello = format!(
+            "{{2|19:posix1.client-hello,{}:{},}}",
+            creds.secret().len(),
+            creds.secret()
+        );
+        sim.send(conn_id, hello.as_bytes());
+        sim.run_until_idle();
+        assert_eq!(
+            sim.take_received(conn_id),
+            b"{5|19:posix1.server-hello,1:a,0:,0:,0:,}"
+        );
+
+        //each `want` produces a 21-byte `have`, so this does not fit into the client's buffer
+        sim.set_reading(conn_id, false);
+        for _ in 0..300 {
+            sim.send(conn_id, b"{2|4:want,5:core1,}");
+        }
+        sim.advance(Duration::from_millis(900));
+        assert!(!app
+            .notifications()
+            .iter()
+            .any(|n| n.contains("did not read")));
+        sim.advance(Duration::from_millis(200));
+        assert!(!sim.is_closed(conn_id));
+
+        //once the client reads again, it gets what fit into the buffer, and then EOF
+        sim.set_reading(conn_id, true);
+        sim.run_until_idle();
+        assert!(sim.is_closed(conn_id));
+        assert_eq!(sim.take_received(conn_id).len(), SIMULATED_BUFFER_SIZE);
+
+        sim.shutdown().unwrap();
+        app.notifications()
+    }
+
+    #[test]
+    fn test_simulation() {
+        let notifications = run_write_timeout_scenario();
+        assert!(
+            notifications.iter().any(|n| n.contains("did not read")),
+            "{:?}",
+            notifications
+        );
+        //the same scenario always plays out in the same way
+        assert_eq!(run_write_timeout_scenario(), notifications);
+    }
+
+    #[test]
+    fn test_egress_shaping() {
+        let app: MockApplication = MockApplication::new();
+        let creds = server::Application::register_client(
+            &app,
+            server::ClientIdentity::new(&ClientID::parse("a").unwrap()),
+        );
+        let mut sim = Dispatch::builder("", app).build_simulation();
+        let conn_id = sim.connect(None);
+        let hello = format!(
+            "{{2|19:posix1.client-hello,{}:{},}}",
+            creds.secret().len(),
+            creds.secret()
+        );
+        sim.send(conn_id, hello.as_bytes());
+        sim.run_until_idle();
+        assert!(sim
+            .take_received(conn_id)
+            .starts_with(b"{5|19:posix1.server-hello,"));
+
+        use server::Dispatch as _;
+        sim.dispatch().enqueue_broadcast(Box::new(|conn| {
+            conn.set_egress_shaping(server::EgressShaping {
+                batch_interval: Some(Duration::from_millis(100)),
+                ..Default::default()
+            })
+        }));
+        sim.run_until_idle();
+
+        //replies are held back until the next tick, and then written together
+        sim.send(conn_id, b"{2|4:want,5:core1,}");
+        sim.advance(Duration::from_millis(40));
+        assert_eq!(sim.take_received(conn_id), b"");
+        sim.send(conn_id, b"{2|4:want,5:core1,}");
+        sim.advance(Duration::from_millis(40));
+        assert_eq!(sim.take_received(conn_id), b"");
+        sim.advance(Duration::from_millis(40));
+        assert_eq!(
+            sim.take_received(conn_id),
+            b"{2|4:have,7:core1.0,}{2|4:have,7:core1.0,}".to_vec()
+        );
+
+        sim.shutdown().unwrap();
+    }
+
+    //Connects a padded stdin client for a new screen, and sets the given shaping options on it.
+    fn connect_padded_stdin(shaping: server::EgressShaping) -> (Simulation<MockApplication>, u64) {
+        let app: MockApplication = MockApplication::new();
+        let creds = app.add_screen(&ScreenID::parse("1").unwrap());
+        let mut sim = Dispatch::builder("", app).build_simulation();
+        let conn_id = sim.connect(None);
+        let secret = creds.stdin_secret();
+        let hello = format!(
+            "{{2|25:posix1.padded-stdin-hello,{}:{},}}",
+            secret.len(),
+            secret
+        );
+        sim.send(conn_id, hello.as_bytes());
+        sim.run_until_idle();
+
+        use server::Dispatch as _;
+        sim.dispatch().enqueue_broadcast(Box::new(move |conn| {
+            assert!(conn.is_stdin_padded());
+            conn.set_egress_shaping(shaping);
+        }));
+        sim.run_until_idle();
+        (sim, conn_id)
+    }
+
+    fn send_stdin(sim: &Simulation<MockApplication>, data: &'static [u8]) {
+        use server::Dispatch as _;
+        sim.dispatch().enqueue_broadcast(Box::new(move |conn| {
+            if conn.state().can_receive_stdin() {
+                conn.enqueue_stdin(data);
+            }
+        }));
+    }
+
+    #[test]
+    fn test_egress_shaping_ticks() {
+        let interval = Duration::from_millis(50);
+        let (mut sim, conn_id) = connect_padded_stdin(server::EgressShaping {
+            batch_interval: Some(interval),
+            padding_block: NonZeroU16::new(32),
+        });
+
+        //type one key every 7ms, and observe the connection in steps of 1ms
+        let mut arrivals = Vec::new();
+        let mut received = Vec::new();
+        for step in 0..300 {
+            if step % 7 == 0 {
+                send_stdin(&sim, b"x");
+            }
+            sim.advance(Duration::from_millis(1));
+            let buf = sim.take_received(conn_id);
+            if !buf.is_empty() {
+                //each write is padded to the block size
+                assert_eq!(buf.len() % 32, 0, "{:?}", buf);
+                arrivals.push(sim.now());
+                received.extend_from_slice(&buf);
+            }
+        }
+
+        //writes only happen on ticks, so they are exactly one interval apart even though the
+        //keypresses are not aligned with the ticks
+        assert!(arrivals.len() >= 5, "{:?}", arrivals);
+        for pair in arrivals.windows(2) {
+            assert_eq!(pair[1] - pair[0], interval);
+        }
+
+        //the client gets the keys that were typed, except for those after the last tick
+        let mut decoder = PaddedStdinDecoder::new();
+        let len = decoder.decode_in_place(&mut received);
+        assert!(decoder.is_at_chunk_boundary());
+        assert!(received[..len].iter().all(|&b| b == b'x'));
+        let typed = (0..300).filter(|step| step % 7 == 0).count();
+        assert!(len <= typed && typed - len < 8, "{} of {} keys", len, typed);
+
+        sim.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_stdin_padding_without_batching() {
+        let (mut sim, conn_id) = connect_padded_stdin(server::EgressShaping {
+            batch_interval: None,
+            padding_block: NonZeroU16::new(16),
+        });
+        send_stdin(&sim, b"hello");
+        sim.run_until_idle();
+        assert_eq!(
+            sim.take_received(conn_id),
+            b"\x00\x05\x00\x07hello\0\0\0\0\0\0\0".to_vec()
+        );
+        sim.shutdown().unwrap();
+    }
+}
@@ -6,110 +6,102 @@
 
 use crate::server;
 use crate::server::tokio as my;
-use futures::future::{AbortRegistration, Abortable};
+use futures::future::{AbortRegistration, Abortable, Either};
 use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
-use tokio::sync::Notify;
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
 
-pub(crate) struct SendBuffer {
-    //Assuming a 64-bit platform, this makes sizeof(SendBuffer) = 4080. General-purpose allocators
-    //usually need 8-16 bytes per allocation for bookkeeping, so overall Box<SendBuffer> allocates
-    //just enough to fit snugly into a single 4 KiB memory page.
-    buf: [u8; 4072],
-    filled: usize,
-}
-
-impl Default for SendBuffer {
-    fn default() -> Self {
-        Self {
-            buf: [0; 4072],
-            filled: 0,
-        }
-    }
-}
-
-impl SendBuffer {
-    ///Executes `action` on the unfilled portion and if successful, marks the parts that were
-    ///written as filled. This is used for enqueuing messages: Messages are only enqueued
-    ///completely or not at all, to increase the chance that they are transmitted in one piece.
-    pub(crate) fn fill_if_ok<E, F>(&mut self, action: F) -> Result<(), E>
-    where
-        F: FnOnce(&mut [u8]) -> Result<usize, E>,
-    {
-        match action(&mut self.buf[self.filled..]) {
-            Err(e) => Err(e),
-            Ok(more_filled) => {
-                self.filled = self.filled.saturating_add(more_filled);
-                if self.filled >= self.buf.len() {
-                    self.filled = self.buf.len();
-                }
-                Ok(())
-            }
-        }
-    }
-
-    ///Fills up the unfilled portion of this buffer as much as possible from `input`, and returns
-    ///the part of `input` that did not fit. This is used for enqueuing stdin: It is possible that
-    ///we get a ton of stdin at once (e.g. from a clipboard paste) that does not fit into one send
-    ///buffer at all.
-    pub(crate) fn fill_until_full<'a, 'b>(&'a mut self, input: &'b [u8]) -> &'b [u8] {
-        //how much can we copy?
-        let len = std::cmp::min(
-            input.len(),                  //how much input bytes we havej
-            self.buf.len() - self.filled, //how many bytes we have unfilled
-        );
-        if len > 0 {
-            self.buf[self.filled..(self.filled + len)].copy_from_slice(&input[0..len]);
-            self.filled += len; //no overflow check necessary here
-        }
-        &input[len..]
-    }
-
-    pub(crate) fn filled(&self) -> &[u8] {
-        &self.buf[0..self.filled]
-    }
-
-    pub(crate) fn filled_len(&self) -> usize {
-        self.filled
-    }
-
-    pub(crate) fn clear(&mut self) {
-        self.filled = 0;
-    }
-}
-
-pub(crate) fn spawn_transmitter<A: server::Application>(
+pub(crate) fn spawn_transmitter<A, W>(
     dispatch: Arc<my::InnerDispatch<A>>,
     abort_reg: AbortRegistration,
     conn_id: u64,
-    mut writer: tokio::net::unix::OwnedWriteHalf,
+    mut writer: W,
     tx_notify: Arc<Notify>,
-) {
+    shaping: watch::Receiver<server::EgressShaping>,
+) -> JoinHandle<()>
+where
+    A: server::Application,
+    W: tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
     let mut buf = None;
+    //for connections in padded stdin mode: the send buffer after encoding it into padded chunks
+    let mut padded = Vec::new();
+    let mut shutdown = dispatch.shutdown_signal();
+    let start = crate::common::now();
     let job = async move {
         loop {
-            //wait for data to become available
-            tx_notify.notified().await;
+            //wait for data to become available, or for the dispatch to shut down (in the latter
+            //case, we still send all data that is waiting, and only exit after that)
+            let notified = tx_notify.notified();
+            let stop = shutdown.wait_for(|&is_shutdown| is_shutdown);
+            futures::pin_mut!(notified, stop);
+            let is_shutdown = match futures::future::select(notified, stop).await {
+                Either::Left(_) => false,
+                Either::Right(_) => true,
+            };
+
+            //with egress shaping, hold the data back until the next tick, so that the timing of
+            //our writes does not reveal when the data was enqueued
+            let now = crate::common::now();
+            let tick = shaping.borrow().next_tick(start, now);
+            if tick > now {
+                crate::common::sleep_until(tick).await;
+            }
 
             loop {
                 //get the next send buffer
-                buf = match dispatch.connection_mut(conn_id).alive() {
+                //if the connection is alive, return the old send buffer and get a new one
+                let swapped = dispatch.with_connection(conn_id, |conn| {
+                    let padding = conn.is_stdin_padded().then(|| conn.egress_shaping());
+                    (dispatch.swap_send_buffer(conn, buf), padding)
+                });
+                let padding = match swapped {
                     //the connection is being torn down
                     None => return,
-                    //the connection is alive -> return the old send buffer and get a new one
-                    Some(conn) => dispatch.swap_send_buffer(conn, buf),
+                    Some((next_buf, padding)) => {
+                        buf = next_buf;
+                        padding
+                    }
                 };
                 match buf {
                     //no data waiting anymore -> go back to sleep
+                    None if is_shutdown => return,
                     None => break,
-                    //write the entire send buffer into the socket
+                    //write the entire send buffer into the socket (write_all() takes care of short
+                    //writes and of retrying after EINTR)
                     Some(ref buf) => {
-                        if let Err(e) = writer.write_all(buf.filled()).await {
-                            let n = server::Notification::ConnectionIOError(e.into());
-                            dispatch.app.notify(&n);
-                            if let Some(conn) = dispatch.connection_mut(conn_id).alive() {
-                                conn.set_state(server::ConnectionState::Teardown);
+                        let data = match padding {
+                            Some(shaping) => {
+                                padded.clear();
+                                shaping.pad_stdin(buf.filled(), &mut padded);
+                                &padded[..]
+                            }
+                            None => buf.filled(),
+                        };
+                        let write = writer.write_all(data);
+                        futures::pin_mut!(write);
+                        let result = loop {
+                            let (timeout, action) = match dispatch.write_timeout {
+                                Some(t) => t,
+                                None => break write.as_mut().await,
+                            };
+                            //after a timeout, we keep polling the same write, so no data is lost
+                            //or duplicated if we keep trying
+                            match crate::common::timeout(timeout, write.as_mut()).await {
+                                Some(result) => break result,
+                                None => {
+                                    let in_flight = buf.filled_len();
+                                    if !dispatch
+                                        .handle_write_timeout(conn_id, timeout, action, in_flight)
+                                    {
+                                        return;
+                                    }
+                                }
                             }
+                        };
+                        if let Err(e) = result {
+                            dispatch.handle_io_error(conn_id, e);
                             return;
                         }
                     }
@@ -117,5 +109,11 @@ pub(crate) fn spawn_transmitter<A: server::Application>(
             }
         }
     };
-    tokio::spawn(Abortable::new(job, abort_reg));
+    #[cfg(feature = "use_tracing")]
+    let job =
+        tracing::Instrument::instrument(job, tracing::debug_span!("vt6.transmitter", id = conn_id));
+    tokio::spawn(async move {
+        //an abort only happens when the connection is torn down, so there is nothing to clean up
+        let _ = Abortable::new(job, abort_reg).await;
+    })
 }
@@ -0,0 +1,204 @@
+/*******************************************************************************
+* Copyright 2020 Stefan Majewsky <majewsky@gmx.net>
+* SPDX-License-Identifier: Apache-2.0
+* Refer to the file "LICENSE" for details.
+*******************************************************************************/
+
+use crate::common::core::msg::{self, DecodeMessage, EncodeMessage};
+use crate::msg::*;
+
+///A message in its canonical wire encoding, together with its decoded form.
+///
+///Independent implementations of VT6 can use the vectors in [ALL](constant.ALL.html) to check that
+///they produce and accept the same bytes as this crate. Within this crate,
+///[verify()](fn.verify.html) checks that the message types still agree with the vectors.
+///
+///This module is only available with the `testvectors` feature.
+#[derive(Clone, Copy, Debug)]
+pub struct TestVector {
+    ///The canonical wire encoding of the message.
+    pub wire: &'static [u8],
+    ///The decoded form of the message, in the same notation that the `Display` implementation of
+    ///[Message](../common/core/msg/struct.Message.html) uses, e.g. `(want core1)`.
+    pub decoded: &'static str,
+}
+
+///All test vectors. There is at least one vector for each message type in
+///[vt6::msg](../msg/index.html).
+pub const ALL: &[TestVector] = &[
+    //foundation
+    TestVector {
+        wire: b"{2|4:want,5:core1,}",
+        decoded: "(want core1)",
+    },
+    TestVector {
+        wire: b"{2|4:have,7:core1.0,}",
+        decoded: "(have core1.0)",
+    },
+    TestVector {
+        wire: b"{2|4:have,4:sig1,}",
+        decoded: "(have sig1)",
+    },
+    TestVector {
+        wire: b"{2|4:nope,4:want,}",
+        decoded: "(nope want)",
+    },
+    TestVector {
+        wire: b"{2|4:nope,9:core1.sub,}",
+        decoded: "(nope core1.sub)",
+    },
+    //vt6/core
+    TestVector {
+        wire: b"{5|17:core1.client-make,2:a1,7:screen1,7:screen1,0:,}",
+        decoded: "(core1.client-make a1 screen1 screen1 \"\")",
+    },
+    TestVector {
+        wire: b"{2|16:core1.client-new,15:aBcD-eFgH_1234=,}",
+        decoded: "(core1.client-new \"aBcD-eFgH_1234=\")",
+    },
+    TestVector {
+        wire: b"{2|16:core1.client-end,2:a1,}",
+        decoded: "(core1.client-end a1)",
+    },
+    TestVector {
+        wire: b"{2|9:core1.sub,11:term1.title,}",
+        decoded: "(core1.sub term1.title)",
+    },
+    TestVector {
+        wire: b"{3|9:core1.set,11:term1.title,11:hello world,}",
+        decoded: "(core1.set term1.title \"hello world\")",
+    },
+    TestVector {
+        wire: b"{3|9:core1.pub,16:term1.input-echo,1:t,}",
+        decoded: "(core1.pub term1.input-echo t)",
+    },
+    //vt6/posix
+    TestVector {
+        wire: b"{2|19:posix1.client-hello,15:aBcD-eFgH_1234=,}",
+        decoded: "(posix1.client-hello \"aBcD-eFgH_1234=\")",
+    },
+    TestVector {
+        wire: b"{3|19:posix1.parent-hello,15:aBcD-eFgH_1234=,15:/run/vt6/socket,}",
+        decoded: "(posix1.parent-hello \"aBcD-eFgH_1234=\" \"/run/vt6/socket\")",
+    },
+    TestVector {
+        wire: b"{5|19:posix1.server-hello,2:a1,7:screen1,7:screen1,0:,}",
+        decoded: "(posix1.server-hello a1 screen1 screen1 \"\")",
+    },
+    TestVector {
+        wire: b"{2|18:posix1.stdin-hello,15:aBcD-eFgH_1234=,}",
+        decoded: "(posix1.stdin-hello \"aBcD-eFgH_1234=\")",
+    },
+    TestVector {
+        wire: b"{2|19:posix1.stdout-hello,15:aBcD-eFgH_1234=,}",
+        decoded: "(posix1.stdout-hello \"aBcD-eFgH_1234=\")",
+    },
+    //vt6/sig
+    TestVector {
+        wire: b"{2|10:sig1.claim,9:interrupt,}",
+        decoded: "(sig1.claim interrupt)",
+    },
+    TestVector {
+        wire: b"{2|12:sig1.release,4:quit,}",
+        decoded: "(sig1.release quit)",
+    },
+    TestVector {
+        wire: b"{2|12:sig1.deliver,7:suspend,}",
+        decoded: "(sig1.deliver suspend)",
+    },
+];
+
+///Checks that this crate agrees with the given test vector: The wire encoding must parse into
+///exactly one message whose `Display` matches the decoded form, the respective type from
+///[vt6::msg](../msg/index.html) must accept the message, and encoding the decoded message must
+///reproduce the wire encoding exactly. Returns a description of the first mismatch, if any.
+pub fn verify(vector: &TestVector) -> Result<(), String> {
+    let (message, len) = msg::Message::parse(vector.wire)
+        .map_err(|e| format!("could not parse {:?}: {}", vector.decoded, e))?;
+    if len != vector.wire.len() {
+        return Err(format!(
+            "{:?} has {} trailing bytes",
+            vector.decoded,
+            vector.wire.len() - len
+        ));
+    }
+    let decoded = message.to_string();
+    if decoded != vector.decoded {
+        return Err(format!("expected {:?}, got {:?}", vector.decoded, decoded));
+    }
+
+    let reencoded = match message.parsed_type().as_str() {
+        "want" => reencode::<Want>(&message),
+        "have" => reencode::<Have>(&message),
+        "nope" => reencode::<Nope>(&message),
+        "core1.client-make" => reencode::<core::ClientMake>(&message),
+        "core1.client-new" => reencode::<core::ClientNew>(&message),
+        "core1.client-end" => reencode::<core::ClientEnd>(&message),
+        "core1.sub" => reencode::<core::Sub>(&message),
+        "core1.set" => reencode::<core::Set>(&message),
+        "core1.pub" => reencode::<core::Pub>(&message),
+        "posix1.client-hello" => reencode::<posix::ClientHello>(&message),
+        "posix1.parent-hello" => reencode::<posix::ParentHello>(&message),
+        "posix1.server-hello" => reencode::<posix::ServerHello>(&message),
+        "posix1.stdin-hello" => reencode::<posix::StdinHello>(&message),
+        "posix1.stdout-hello" => reencode::<posix::StdoutHello>(&message),
+        "sig1.claim" => reencode::<sig::Claim>(&message),
+        "sig1.release" => reencode::<sig::Release>(&message),
+        "sig1.deliver" => reencode::<sig::Deliver>(&message),
+        other => return Err(format!("no message type for {:?}", other)),
+    };
+    match reencoded {
+        None => Err(format!("could not decode {:?}", vector.decoded)),
+        Some(ref wire) if wire.as_slice() != vector.wire => Err(format!(
+            "{:?} was reencoded as {:?}",
+            vector.decoded,
+            String::from_utf8_lossy(wire)
+        )),
+        Some(_) => Ok(()),
+    }
+}
+
+fn reencode<'a, M: DecodeMessage<'a> + EncodeMessage>(
+    message: &msg::Message<'a>,
+) -> Option<Vec<u8>> {
+    let decoded = M::decode_message(message)?;
+    let mut buf = [0u8; 1024];
+    let len = decoded.encode(&mut buf).ok()?;
+    Some(buf[0..len].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_vectors() {
+        for vector in ALL {
+            if let Err(e) = verify(vector) {
+                panic!("{}", e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_detects_mismatches() {
+        //non-canonical encoding of "(have core1.0)"
+        let vector = TestVector {
+            wire: b"{2|4:have,7:core1.0,}{",
+            decoded: "(have core1.0)",
+        };
+        assert!(verify(&vector).is_err());
+        //wrong decoded form
+        let vector = TestVector {
+            wire: b"{2|4:want,5:core1,}",
+            decoded: "(want core2)",
+        };
+        assert!(verify(&vector).is_err());
+        //not accepted by the message type
+        let vector = TestVector {
+            wire: b"{2|10:sig1.claim,5:hello,}",
+            decoded: "(sig1.claim hello)",
+        };
+        assert!(verify(&vector).is_err());
+    }
+}